@@ -6,9 +6,14 @@ fn main() {
     // We only support native unwinding on some platforms
     let target_arch = env::var("CARGO_CFG_TARGET_ARCH").unwrap();
     match target_arch.as_str() {
-        "x86_64" | "arm" | "aarch64" => {}
+        "x86_64" | "arm" | "aarch64" | "riscv64" | "loongarch64" => {}
         _ => return,
     };
+    // libunwind names its per-arch libraries after the architecture family, not the target arch
+    let libunwind_arch = match target_arch.as_str() {
+        "riscv64" => "riscv",
+        arch => arch,
+    };
     let target = env::var("TARGET").unwrap();
 
     match env::var("CARGO_CFG_TARGET_OS").unwrap().as_ref() {
@@ -33,8 +38,11 @@ fn main() {
                     )
                     .unwrap();
                     std::fs::copy(
-                        format!("/usr/local/musl/{}/lib/libunwind-{}.a", target, target_arch),
-                        format!("{}/libunwind-{}.a", out_dir, target_arch),
+                        format!(
+                            "/usr/local/musl/{}/lib/libunwind-{}.a",
+                            target, libunwind_arch
+                        ),
+                        format!("{}/libunwind-{}.a", out_dir, libunwind_arch),
                     )
                     .unwrap();
                     std::fs::copy(
@@ -45,16 +53,27 @@ fn main() {
                     println!("cargo:rustc-link-search=native={}", out_dir);
                     println!("cargo:rustc-link-lib=static=unwind-remoteprocess");
                     println!("cargo:rustc-link-lib=static=unwind-ptrace");
-                    println!("cargo:rustc-link-lib=static=unwind-{}", target_arch);
+                    println!("cargo:rustc-link-lib=static=unwind-{}", libunwind_arch);
                     println!("cargo:rustc-link-lib=static=z");
                 } else {
                     println!("cargo:rustc-link-lib=dylib=lzma");
                     println!("cargo:rustc-link-lib=dylib=unwind");
                     println!("cargo:rustc-link-lib=dylib=unwind-ptrace");
-                    println!("cargo:rustc-link-lib=dylib=unwind-{}", target_arch);
+                    println!("cargo:rustc-link-lib=dylib=unwind-{}", libunwind_arch);
                 }
             }
         }
+        // libunwind is installed from ports/packages into /usr/local
+        "freebsd"
+            if env::var("CARGO_FEATURE_UNWIND").is_ok()
+                && matches!(target_arch.as_str(), "x86_64" | "aarch64") =>
+        {
+            println!("cargo:rustc-cfg=use_libunwind");
+            println!("cargo:rustc-link-search=native=/usr/local/lib");
+            println!("cargo:rustc-link-lib=dylib=unwind");
+            println!("cargo:rustc-link-lib=dylib=unwind-ptrace");
+            println!("cargo:rustc-link-lib=dylib=unwind-{}", libunwind_arch);
+        }
         _ => {}
     }
 }
@@ -0,0 +1,230 @@
+//! Async versions of the process api, for use from tokio based agents.
+//!
+//! The underlying OS calls are all blocking, so each call here is run on tokio's blocking
+//! thread pool rather than on the async executor itself.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::warn;
+use tokio::sync::mpsc;
+use tokio::task;
+
+use crate::{Error, Pid, Process, ProcessMemory, Tid};
+
+/// Events reported by [`AsyncProcess::events`]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ProcessEvent {
+    ThreadStarted(Tid),
+    ThreadExited(Tid),
+    Exited,
+}
+
+pub struct AsyncProcess {
+    pid: Pid,
+    process: Arc<Process>,
+}
+
+impl AsyncProcess {
+    pub async fn new(pid: Pid) -> Result<AsyncProcess, Error> {
+        let process = blocking(move || Process::new(pid)).await?;
+        Ok(AsyncProcess::from_process(process))
+    }
+
+    pub fn from_process(process: Process) -> AsyncProcess {
+        AsyncProcess {
+            pid: process.pid,
+            process: Arc::new(process),
+        }
+    }
+
+    pub fn pid(&self) -> Pid {
+        self.pid
+    }
+
+    /// Copies a series of bytes from the process
+    pub async fn copy(&self, addr: usize, length: usize) -> Result<Vec<u8>, Error> {
+        let process = self.process.clone();
+        blocking(move || process.copy(addr, length)).await
+    }
+
+    /// Copies a structure from the process
+    pub async fn copy_struct<T: Copy + Send + 'static>(&self, addr: usize) -> Result<T, Error> {
+        let process = self.process.clone();
+        blocking(move || process.copy_struct(addr)).await
+    }
+
+    /// Returns the thread ids of the threads in the process
+    pub async fn thread_ids(&self) -> Result<Vec<Tid>, Error> {
+        let process = self.process.clone();
+        blocking(move || thread_ids(&process)).await
+    }
+
+    /// Waits for the process to exit, checking on it every `poll_interval`
+    pub async fn wait_for_exit(&self, poll_interval: Duration) -> Result<(), Error> {
+        loop {
+            let process = self.process.clone();
+            if blocking(move || has_exited(&process)).await? {
+                return Ok(());
+            }
+            tokio::time::sleep(poll_interval).await;
+        }
+    }
+
+    /// Returns a stream of thread start/exit events for the process, found by polling the
+    /// thread list every `poll_interval`. The stream ends after the process exits, when the
+    /// thread list can't be read, or when the receiver is dropped. This has to be called from
+    /// inside a tokio runtime.
+    pub fn events(&self, poll_interval: Duration) -> mpsc::Receiver<ProcessEvent> {
+        let (sender, receiver) = mpsc::channel(64);
+        let process = self.process.clone();
+        tokio::spawn(async move {
+            let mut known: Option<HashSet<Tid>> = None;
+            loop {
+                let current = {
+                    let process = process.clone();
+                    blocking(move || {
+                        if has_exited(&process)? {
+                            return Ok(None);
+                        }
+                        Ok(Some(thread_ids(&process)?))
+                    })
+                    .await
+                };
+
+                let current: HashSet<Tid> = match current {
+                    Ok(Some(tids)) => tids.into_iter().collect(),
+                    Ok(None) => {
+                        let _ = sender.send(ProcessEvent::Exited).await;
+                        return;
+                    }
+                    // the process can exit between checking on it and listing its threads
+                    Err(e) if is_exit_error(&e) => {
+                        let _ = sender.send(ProcessEvent::Exited).await;
+                        return;
+                    }
+                    Err(e) => {
+                        warn!(
+                            "failed to list the threads of process {}: {}",
+                            process.pid, e
+                        );
+                        return;
+                    }
+                };
+
+                // only report threads that have changed since the first poll
+                if let Some(known) = known.as_ref() {
+                    let started = current
+                        .difference(known)
+                        .map(|tid| ProcessEvent::ThreadStarted(*tid));
+                    let exited = known
+                        .difference(&current)
+                        .map(|tid| ProcessEvent::ThreadExited(*tid));
+                    for event in started.chain(exited) {
+                        if sender.send(event).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+                known = Some(current);
+
+                if sender.is_closed() {
+                    return;
+                }
+                tokio::time::sleep(poll_interval).await;
+            }
+        });
+        receiver
+    }
+}
+
+async fn blocking<F, T>(f: F) -> Result<T, Error>
+where
+    F: FnOnce() -> Result<T, Error> + Send + 'static,
+    T: Send + 'static,
+{
+    task::spawn_blocking(f)
+        .await
+        .map_err(|e| Error::Other(format!("blocking task failed: {}", e)))?
+}
+
+fn thread_ids(process: &Process) -> Result<Vec<Tid>, Error> {
+    process
+        .threads()?
+        .iter()
+        .map(|thread| thread.id())
+        .collect()
+}
+
+fn has_exited(process: &Process) -> Result<bool, Error> {
+    // the threads of a zombie are still listed until its parent reaps it
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    match process.is_zombie() {
+        Ok(true) => return Ok(true),
+        Ok(false) => {}
+        Err(e) if is_exit_error(&e) => return Ok(true),
+        Err(e) => return Err(e),
+    }
+
+    // every platform fails to list threads (or lists none) once the process is gone
+    match process.threads() {
+        Ok(threads) => Ok(threads.is_empty()),
+        Err(e) if is_exit_error(&e) => Ok(true),
+        Err(e) => Err(e),
+    }
+}
+
+/// True for the errors that mean the process is gone, rather than that we failed to
+/// look at it
+fn is_exit_error(err: &Error) -> bool {
+    match err {
+        Error::ProcessExited { .. } | Error::NoSuchProcess { .. } => true,
+        #[cfg(unix)]
+        Error::IOError(e) => e.raw_os_error() == Some(libc::ESRCH),
+        _ => false,
+    }
+}
+
+#[cfg(all(test, any(target_os = "linux", target_os = "android")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_async_copy() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        let value: u64 = 0x1234_5678;
+        let addr = &value as *const u64 as usize;
+        let copied: u64 = runtime.block_on(async {
+            let process = AsyncProcess::new(std::process::id() as Pid).await.unwrap();
+            process.copy_struct(addr).await.unwrap()
+        });
+        assert_eq!(copied, value);
+    }
+
+    #[test]
+    fn test_has_exited_unreaped_child() {
+        let mut child = std::process::Command::new("sleep")
+            .arg("0.1")
+            .spawn()
+            .unwrap();
+        let process = Process::new(child.id() as Pid).unwrap();
+        assert!(!has_exited(&process).unwrap());
+
+        // the child stays a zombie until it is waited on below
+        let start = std::time::Instant::now();
+        while !has_exited(&process).unwrap() {
+            assert!(
+                start.elapsed() < Duration::from_secs(10),
+                "child never exited"
+            );
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert!(process.is_zombie().unwrap());
+        child.wait().unwrap();
+    }
+}
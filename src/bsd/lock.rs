@@ -0,0 +1,56 @@
+use libc::{pid_t, waitpid, WIFSTOPPED};
+use log::error;
+
+use std::io::Error as IoError;
+
+use super::ptrace;
+use super::{get_threads, Error, LockContainer, Thread, Tid};
+
+pub struct ProcessLock {
+    pid: pid_t,
+    threads: Vec<Thread>,
+}
+
+impl ProcessLock {
+    pub fn new(pid: pid_t, container: &LockContainer) -> Result<Self, Error> {
+        ptrace::attach(pid)?;
+        let mut wait_status = 0;
+
+        let stopped = unsafe {
+            waitpid(pid, &mut wait_status as *mut _, 0);
+            WIFSTOPPED(wait_status)
+        };
+
+        let mut lock = ProcessLock {
+            pid,
+            threads: Vec::new(),
+        };
+
+        if !stopped {
+            return Err(Error::IOError(IoError::last_os_error()));
+        }
+
+        // PT_ATTACH stops every thread in the process, so this list can't change
+        // while we hold the lock
+        lock.threads = get_threads(pid, container)?;
+        Ok(lock)
+    }
+
+    /// The threads that were suspended by this lock
+    pub fn threads(&self) -> &[Thread] {
+        &self.threads
+    }
+
+    /// The thread ids of the threads that were suspended by this lock
+    pub fn tids(&self) -> Vec<Tid> {
+        self.threads.iter().map(|thread| thread.tid).collect()
+    }
+}
+
+impl Drop for ProcessLock {
+    fn drop(&mut self) {
+        if let Err(e) = ptrace::detach(self.pid) {
+            error!("Failed to detach from process {} : {}", self.pid, e);
+        }
+    }
+}
@@ -0,0 +1,191 @@
+//! OpenBSD and NetBSD support. Both systems expose processes through sysctl and ptrace
+//! interfaces that are close enough to share an implementation, with the differences
+//! contained in the sysctl module.
+mod lock;
+mod ptrace;
+mod sysctl;
+mod threads;
+
+use libc::pid_t;
+
+use std::sync::{Arc, Mutex, Weak};
+
+use super::{Error, ProcessMemory, ReadOptions, TargetArch};
+use crate::bsd::lock::ProcessLock;
+
+pub use self::threads::ThreadIter;
+
+pub type Pid = pid_t;
+pub type Tid = pid_t;
+
+pub struct Process {
+    pub pid: Pid,
+    lock: LockContainer,
+    read_options: ReadOptions,
+}
+
+pub struct Thread {
+    pub tid: Tid,
+    pid: pid_t,
+    active: bool,
+    lock: LockContainer,
+}
+
+type LockContainer = Arc<Mutex<Weak<ProcessLock>>>;
+
+fn process_lock(pid: Pid, container: &LockContainer) -> Result<Arc<ProcessLock>, Error> {
+    let mut mutex_lock = container.lock().unwrap();
+    if let Some(ref lock) = Weak::upgrade(&mutex_lock) {
+        return Ok(Arc::clone(lock));
+    }
+
+    let lock = Arc::new(ProcessLock::new(pid, container)?);
+    *mutex_lock = Arc::downgrade(&lock);
+
+    Ok(lock)
+}
+
+fn get_threads(pid: Pid, lock: &LockContainer) -> Result<Vec<Thread>, Error> {
+    let threads = sysctl::threads(pid).map_err(|e| Error::from_os_error(pid, e))?;
+    let result = threads.iter().map(|th| Thread {
+        tid: th.tid,
+        active: th.active,
+        pid,
+        lock: Arc::clone(lock),
+    });
+
+    Ok(result.collect())
+}
+
+impl Process {
+    pub fn new(pid: Pid) -> Result<Process, Error> {
+        Ok(Process {
+            pid,
+            lock: Arc::new(Mutex::new(Weak::new())),
+            read_options: ReadOptions::default(),
+        })
+    }
+
+    /// Returns a new handle to the same process that can be kept independently of this one.
+    /// Both handles share the same lock, since only one tracer can be attached at a time.
+    pub fn try_clone(&self) -> Result<Process, Error> {
+        Ok(Process {
+            pid: self.pid,
+            lock: Arc::clone(&self.lock),
+            read_options: self.read_options,
+        })
+    }
+
+    /// Returns how reads of memory from this process are split up and retried
+    pub fn read_options(&self) -> ReadOptions {
+        self.read_options
+    }
+
+    /// Sets how reads of memory from this process are split up and retried
+    pub fn set_read_options(&mut self, options: ReadOptions) {
+        self.read_options = options;
+    }
+
+    pub fn exe(&self) -> Result<String, Error> {
+        let filename = sysctl::exe(self.pid).map_err(|e| Error::from_os_error(self.pid, e))?;
+        if filename.is_empty() {
+            return Err(Error::Other("Failed to get process executable name".into()));
+        }
+        Ok(filename)
+    }
+
+    /// Returns the architecture, pointer size and endianness of the process, from the ELF
+    /// header of its executable
+    pub fn arch(&self) -> Result<TargetArch, Error> {
+        crate::read_elf_arch(self.pid, &self.exe()?)
+    }
+
+    /// True if pointers in the process are 64 bits wide
+    pub fn is_64_bit(&self) -> Result<bool, Error> {
+        Ok(self.arch()?.is_64_bit())
+    }
+
+    pub fn cwd(&self) -> Result<String, Error> {
+        sysctl::cwd(self.pid).map_err(|e| Error::from_os_error(self.pid, e))
+    }
+
+    pub fn cmdline(&self) -> Result<Vec<String>, Error> {
+        sysctl::cmdline(self.pid).map_err(|e| Error::from_os_error(self.pid, e))
+    }
+
+    pub fn threads(&self) -> Result<Vec<Thread>, Error> {
+        get_threads(self.pid, &self.lock)
+    }
+
+    /// Returns the ids of the threads in the process
+    pub fn thread_ids(&self) -> Result<Vec<Tid>, Error> {
+        let threads = sysctl::threads(self.pid).map_err(|e| Error::from_os_error(self.pid, e))?;
+        Ok(threads.iter().map(|th| th.tid).collect())
+    }
+
+    pub fn lock(&self) -> Result<Arc<ProcessLock>, Error> {
+        process_lock(self.pid, &self.lock)
+    }
+
+    /// Locks the process, and returns a lock containing a consistent snapshot of the threads
+    /// in the process. PT_ATTACH stops the whole process, so no new threads can be created
+    /// while the lock is held and the thread list gathered by the lock is already stable.
+    pub fn lock_and_snapshot(&self) -> Result<Arc<ProcessLock>, Error> {
+        self.lock()
+    }
+
+    pub fn child_processes(&self) -> Result<Vec<(Pid, Pid)>, Error> {
+        let processes = sysctl::processes()?;
+        Ok(crate::filter_child_pids(self.pid, &processes))
+    }
+}
+
+impl Thread {
+    pub fn id(&self) -> Result<Tid, Error> {
+        Ok(self.tid)
+    }
+
+    /// Returns a new handle to the same thread
+    pub fn try_clone(&self) -> Result<Thread, Error> {
+        Ok(Thread {
+            tid: self.tid,
+            pid: self.pid,
+            active: self.active,
+            lock: Arc::clone(&self.lock),
+        })
+    }
+
+    pub fn active(&self) -> Result<bool, Error> {
+        Ok(self.active)
+    }
+
+    pub fn lock(&self) -> Result<Arc<ProcessLock>, Error> {
+        process_lock(self.pid, &self.lock)
+    }
+}
+
+impl ProcessMemory for Process {
+    /// Reads memory with PT_IO, which requires the process to be traced. If the process
+    /// isn't already locked, it is stopped for the duration of the read.
+    fn read(&self, addr: usize, buf: &mut [u8]) -> Result<(), Error> {
+        let _lock = self.lock()?;
+        self.read_options.read_chunked(addr, buf, |addr, buf| {
+            let mut offset = 0;
+            while offset < buf.len() {
+                let count = ptrace::read(self.pid, addr + offset, &mut buf[offset..])
+                    .map_err(|e| Error::from_os_error(self.pid, e))
+                    .map_err(|e| Error::from_read_error(self.pid, addr, buf.len(), e))?;
+                if count == 0 {
+                    return Err(Error::InvalidAddress {
+                        pid: self.pid,
+                        addr,
+                        len: buf.len(),
+                        source: std::io::Error::from_raw_os_error(libc::EFAULT),
+                    });
+                }
+                offset += count;
+            }
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,62 @@
+use libc::{c_int, c_void, pid_t, size_t};
+
+use std::io::Error;
+use std::ptr;
+
+const PT_ATTACH: c_int = 9;
+const PT_DETACH: c_int = 10;
+const PT_IO: c_int = 11;
+const PIOD_READ_D: c_int = 1;
+
+macro_rules! ptrace {
+    ($request:ident, $pid:expr, $addr:expr, $data:expr) => {
+        unsafe {
+            let ret = ptrace($request, $pid, $addr, $data);
+
+            if ret < 0 {
+                return Err(Error::last_os_error());
+            }
+
+            ret
+        }
+    };
+}
+
+#[repr(C)]
+struct ptrace_io_desc {
+    piod_op: c_int,
+    piod_offs: *mut c_void,
+    piod_addr: *mut c_void,
+    piod_len: size_t,
+}
+
+extern "C" {
+    fn ptrace(request: c_int, pid: pid_t, addr: *mut c_void, data: c_int) -> c_int;
+}
+
+pub fn attach(pid: pid_t) -> Result<(), Error> {
+    ptrace!(PT_ATTACH, pid, ptr::null_mut(), 0);
+
+    Ok(())
+}
+
+pub fn detach(pid: pid_t) -> Result<(), Error> {
+    // an address of 1 means to resume execution where the process stopped
+    ptrace!(PT_DETACH, pid, 1 as *mut c_void, 0);
+
+    Ok(())
+}
+
+/// Reads memory from a process we are attached to. Returns the number of bytes read,
+/// which can be less than requested if the end of a mapping was reached.
+pub fn read(pid: pid_t, addr: usize, buf: &mut [u8]) -> Result<usize, Error> {
+    let mut desc = ptrace_io_desc {
+        piod_op: PIOD_READ_D,
+        piod_offs: addr as *mut c_void,
+        piod_addr: buf.as_mut_ptr() as *mut c_void,
+        piod_len: buf.len(),
+    };
+    ptrace!(PT_IO, pid, &mut desc as *mut _ as *mut c_void, 0);
+
+    Ok(desc.piod_len)
+}
@@ -0,0 +1,340 @@
+use libc::{c_char, c_int, c_uint, c_void, pid_t};
+
+use std::collections::HashMap;
+use std::io::Error;
+use std::time::Duration;
+
+use crate::ThreadState;
+
+/// Information about a single thread in a process
+pub struct ThreadInfo {
+    pub tid: pid_t,
+    pub active: bool,
+    pub name: Option<String>,
+    pub state: ThreadState,
+    pub cpu_time: Duration,
+    /// The CPU the thread last ran on
+    pub cpu: Option<usize>,
+}
+
+/// Calls sysctl, returning the raw bytes of the result
+fn sysctl(mib: &[c_int]) -> Result<Vec<u8>, Error> {
+    let mut size = sysctl_len(mib)?;
+    let mut buffer = vec![0_u8; size];
+    let ret = unsafe {
+        libc::sysctl(
+            mib.as_ptr(),
+            mib.len() as c_uint,
+            buffer.as_mut_ptr() as *mut c_void,
+            &mut size,
+            std::ptr::null_mut(),
+            0,
+        )
+    };
+    if ret < 0 {
+        return Err(Error::last_os_error());
+    }
+    buffer.truncate(size);
+    Ok(buffer)
+}
+
+/// Calls a sysctl that returns an array of T. The last element of `mib` is set to the
+/// number of elements to return.
+fn sysctl_vec<T: Copy>(mib: &mut [c_int]) -> Result<Vec<T>, Error> {
+    let size = std::mem::size_of::<T>();
+    let last = mib.len() - 1;
+
+    // the first call gets the current count, leave some slack in case this grows
+    mib[last] = 0;
+    let count = sysctl_len(mib)? / size + 8;
+    mib[last] = count as c_int;
+
+    let buffer = sysctl(mib)?;
+    Ok(buffer
+        .chunks_exact(size)
+        .map(|chunk| unsafe { std::ptr::read_unaligned(chunk.as_ptr() as *const T) })
+        .collect())
+}
+
+fn sysctl_len(mib: &[c_int]) -> Result<usize, Error> {
+    let mut size = 0;
+    let ret = unsafe {
+        libc::sysctl(
+            mib.as_ptr(),
+            mib.len() as c_uint,
+            std::ptr::null_mut(),
+            &mut size,
+            std::ptr::null_mut(),
+            0,
+        )
+    };
+    if ret < 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(size)
+}
+
+/// Converts a fixed size, nul terminated name
+fn name(name: &[c_char]) -> Option<String> {
+    let name: Vec<u8> = name
+        .iter()
+        .take_while(|c| **c != 0)
+        .map(|c| *c as u8)
+        .collect();
+    if name.is_empty() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&name).into_owned())
+}
+
+/// Converts the cpu id of a thread, which is all ones if it hasn't run
+fn cpu(cpu: u64) -> Option<usize> {
+    if cpu == u64::MAX {
+        None
+    } else {
+        Some(cpu as usize)
+    }
+}
+
+/// Splits a buffer of nul terminated strings
+fn split_strings(buffer: &[u8]) -> Vec<String> {
+    buffer
+        .split(|b| *b == 0)
+        .filter(|s| !s.is_empty())
+        .map(|s| String::from_utf8_lossy(s).into_owned())
+        .collect()
+}
+
+#[cfg(target_os = "netbsd")]
+mod os {
+    use super::*;
+
+    extern "C" {
+        fn sched_getaffinity_np(
+            pid: pid_t,
+            lid: libc::lwpid_t,
+            size: libc::size_t,
+            set: *mut libc::cpuset_t,
+        ) -> c_int;
+    }
+
+    const KERN_PROC_PATHNAME: c_int = 5;
+    const KERN_PROC_CWD: c_int = 6;
+    const LSIDL: i32 = 1;
+    const LSRUN: i32 = 2;
+    const LSSLEEP: i32 = 3;
+    const LSSTOP: i32 = 4;
+    const LSZOMB: i32 = 5;
+    const LSDEAD: i32 = 6;
+    const LSONPROC: i32 = 7;
+    const LSSUSPENDED: i32 = 8;
+
+    pub fn threads(pid: pid_t) -> Result<Vec<ThreadInfo>, Error> {
+        let mut mib = [
+            libc::CTL_KERN,
+            libc::KERN_LWP,
+            pid,
+            std::mem::size_of::<libc::kinfo_lwp>() as c_int,
+            0,
+        ];
+        let lwps: Vec<libc::kinfo_lwp> = sysctl_vec(&mut mib)?;
+        Ok(lwps
+            .iter()
+            .map(|lwp| ThreadInfo {
+                tid: lwp.l_lid,
+                active: lwp.l_stat as i32 == LSRUN || lwp.l_stat as i32 == LSONPROC,
+                name: name(&lwp.l_name),
+                state: match lwp.l_stat as i32 {
+                    LSIDL | LSRUN | LSONPROC => ThreadState::Running,
+                    LSSLEEP => ThreadState::Sleeping,
+                    LSSTOP | LSSUSPENDED => ThreadState::Stopped,
+                    LSZOMB | LSDEAD => ThreadState::Zombie,
+                    _ => ThreadState::Unknown,
+                },
+                cpu_time: Duration::new(lwp.l_rtime_sec as u64, lwp.l_rtime_usec * 1000),
+                cpu: cpu(lwp.l_cpuid),
+            })
+            .collect())
+    }
+
+    /// Returns the CPUs a thread is allowed to run on
+    pub fn affinity(pid: pid_t, tid: pid_t) -> Result<Vec<usize>, Error> {
+        unsafe {
+            let set = libc::_cpuset_create();
+            if set.is_null() {
+                return Err(Error::last_os_error());
+            }
+            let size = libc::_cpuset_size(set);
+            let ret = sched_getaffinity_np(pid, tid, size, set);
+            let cpus = (0..size * 8)
+                .filter(|cpu| libc::_cpuset_isset(*cpu as libc::cpuid_t, set) > 0)
+                .collect();
+            libc::_cpuset_destroy(set);
+            if ret != 0 {
+                return Err(Error::last_os_error());
+            }
+            Ok(cpus)
+        }
+    }
+
+    pub fn processes() -> Result<HashMap<pid_t, pid_t>, Error> {
+        let mut mib = [
+            libc::CTL_KERN,
+            libc::KERN_PROC2,
+            libc::KERN_PROC_ALL,
+            0,
+            std::mem::size_of::<libc::kinfo_proc2>() as c_int,
+            0,
+        ];
+        let procs: Vec<libc::kinfo_proc2> = sysctl_vec(&mut mib)?;
+        Ok(procs.iter().map(|p| (p.p_pid, p.p_ppid)).collect())
+    }
+
+    pub fn exe(pid: pid_t) -> Result<String, Error> {
+        let mib = [
+            libc::CTL_KERN,
+            libc::KERN_PROC_ARGS,
+            pid,
+            KERN_PROC_PATHNAME,
+        ];
+        Ok(split_strings(&sysctl(&mib)?).pop().unwrap_or_default())
+    }
+
+    pub fn cwd(pid: pid_t) -> Result<String, Error> {
+        let mib = [libc::CTL_KERN, libc::KERN_PROC_ARGS, pid, KERN_PROC_CWD];
+        Ok(split_strings(&sysctl(&mib)?).pop().unwrap_or_default())
+    }
+
+    pub fn cmdline(pid: pid_t) -> Result<Vec<String>, Error> {
+        let mib = [
+            libc::CTL_KERN,
+            libc::KERN_PROC_ARGS,
+            pid,
+            libc::KERN_PROC_ARGV,
+        ];
+        Ok(split_strings(&sysctl(&mib)?))
+    }
+}
+
+#[cfg(target_os = "openbsd")]
+mod os {
+    use super::*;
+
+    const SIDL: i8 = 1;
+    const SRUN: i8 = 2;
+    const SSLEEP: i8 = 3;
+    const SSTOP: i8 = 4;
+    const SZOMB: i8 = 5;
+    const SDEAD: i8 = 6;
+    const SONPROC: i8 = 7;
+
+    fn procs(op: c_int, arg: c_int) -> Result<Vec<libc::kinfo_proc>, Error> {
+        let mut mib = [
+            libc::CTL_KERN,
+            libc::KERN_PROC,
+            op,
+            arg,
+            std::mem::size_of::<libc::kinfo_proc>() as c_int,
+            0,
+        ];
+        sysctl_vec(&mut mib)
+    }
+
+    pub fn threads(pid: pid_t) -> Result<Vec<ThreadInfo>, Error> {
+        let procs = procs(libc::KERN_PROC_PID | libc::KERN_PROC_SHOW_THREADS, pid)?;
+        // the process itself is included with a tid of -1
+        Ok(procs
+            .iter()
+            .filter(|p| p.p_tid != -1)
+            .map(|p| ThreadInfo {
+                tid: p.p_tid,
+                active: p.p_stat == SRUN || p.p_stat == SONPROC,
+                name: name(&p.p_name),
+                state: match p.p_stat {
+                    SIDL | SRUN | SONPROC => ThreadState::Running,
+                    SSLEEP => ThreadState::Sleeping,
+                    SSTOP => ThreadState::Stopped,
+                    SZOMB | SDEAD => ThreadState::Zombie,
+                    _ => ThreadState::Unknown,
+                },
+                cpu_time: Duration::new(p.p_rtime_sec as u64, p.p_rtime_usec * 1000),
+                cpu: cpu(p.p_cpuid),
+            })
+            .collect())
+    }
+
+    /// Returns the CPUs a thread is allowed to run on. OpenBSD has no way to pin threads to
+    /// CPUs, so this is every online CPU.
+    pub fn affinity(_pid: pid_t, _tid: pid_t) -> Result<Vec<usize>, Error> {
+        let cpus = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_ONLN) };
+        if cpus < 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok((0..cpus as usize).collect())
+    }
+
+    pub fn processes() -> Result<HashMap<pid_t, pid_t>, Error> {
+        let procs = procs(libc::KERN_PROC_ALL, 0)?;
+        Ok(procs.iter().map(|p| (p.p_pid, p.p_ppid)).collect())
+    }
+
+    pub fn exe(pid: pid_t) -> Result<String, Error> {
+        // OpenBSD doesn't track the path of the executable, argv[0] is the best we can do
+        Ok(cmdline(pid)?.into_iter().next().unwrap_or_default())
+    }
+
+    pub fn cwd(pid: pid_t) -> Result<String, Error> {
+        let mib = [libc::CTL_KERN, libc::KERN_PROC_CWD, pid];
+        Ok(split_strings(&sysctl(&mib)?).pop().unwrap_or_default())
+    }
+
+    pub fn cmdline(pid: pid_t) -> Result<Vec<String>, Error> {
+        let mib = [
+            libc::CTL_KERN,
+            libc::KERN_PROC_ARGS,
+            pid,
+            libc::KERN_PROC_ARGV,
+        ];
+        // the result is a null terminated array of pointers to the arguments, followed by
+        // the arguments themselves. The pointers are relative to the buffer passed in, so
+        // skip past the array and read the strings directly.
+        let buffer = sysctl(&mib)?;
+        let pointer_size = std::mem::size_of::<usize>();
+        let mut offset = 0;
+        while offset + pointer_size <= buffer.len() {
+            let done = buffer[offset..offset + pointer_size]
+                .iter()
+                .all(|b| *b == 0);
+            offset += pointer_size;
+            if done {
+                break;
+            }
+        }
+        Ok(split_strings(&buffer[offset..]))
+    }
+}
+
+pub use self::os::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_strings() {
+        assert_eq!(
+            split_strings(b"/bin/sh\0-c\0echo hi\0\0"),
+            vec!["/bin/sh", "-c", "echo hi"]
+        );
+    }
+
+    #[test]
+    fn test_name() {
+        let mut buffer = [0 as c_char; 20];
+        assert_eq!(name(&buffer), None);
+        for (i, c) in b"worker".iter().enumerate() {
+            buffer[i] = *c as c_char;
+        }
+        assert_eq!(name(&buffer).as_deref(), Some("worker"));
+    }
+}
@@ -0,0 +1,55 @@
+//! Details of the threads of a process. `threads_iter` lists the threads from sysctl, which
+//! returns every thread in one call, converting each as the iterator reaches it.
+use super::{sysctl, Process, Thread};
+use crate::{Error, ThreadInfo};
+
+/// An iterator over the threads of a process, returned by `Process::threads_iter`
+pub struct ThreadIter {
+    threads: std::vec::IntoIter<sysctl::ThreadInfo>,
+}
+
+impl Process {
+    /// Returns an iterator over the threads of the process, with the name, state and CPU
+    /// time of each
+    pub fn threads_iter(&self) -> Result<ThreadIter, Error> {
+        let threads = sysctl::threads(self.pid).map_err(|e| Error::from_os_error(self.pid, e))?;
+        Ok(ThreadIter {
+            threads: threads.into_iter(),
+        })
+    }
+}
+
+impl Iterator for ThreadIter {
+    type Item = Result<ThreadInfo, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let thread = self.threads.next()?;
+        Some(Ok(ThreadInfo {
+            tid: thread.tid,
+            name: thread.name,
+            state: thread.state,
+            cpu_time: thread.cpu_time,
+        }))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.threads.size_hint()
+    }
+}
+
+impl Thread {
+    /// Returns the CPUs the thread is allowed to run on
+    pub fn affinity(&self) -> Result<Vec<usize>, Error> {
+        sysctl::affinity(self.pid, self.tid).map_err(|e| Error::from_os_error(self.pid, e))
+    }
+
+    /// Returns the CPU the thread last ran on
+    pub fn last_cpu(&self) -> Result<Option<usize>, Error> {
+        let threads = sysctl::threads(self.pid).map_err(|e| Error::from_os_error(self.pid, e))?;
+        let thread = threads
+            .iter()
+            .find(|thread| thread.tid == self.tid)
+            .ok_or(Error::NoSuchProcess { pid: self.tid })?;
+        Ok(thread.cpu)
+    }
+}
@@ -0,0 +1,234 @@
+//! Demangling of the function names returned by symbolication. Function names are reported
+//! as they are stored in the binary, which for Rust and C++ code means mangled. The
+//! demanglers are behind the default `demangle` feature, and without it nothing is demangled.
+#[cfg(any(use_libunwind, all(target_os = "windows", feature = "unwind")))]
+use crate::StackFrame;
+
+/// Controls how function names are demangled during symbolication. Nothing is demangled by
+/// default, so that names are returned exactly as they appear in the binary.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DemangleOptions {
+    /// Demangle Rust symbols, in both the legacy and v0 mangling schemes
+    pub rust: bool,
+    /// Drop the hash suffix from demangled Rust names (`foo::bar::h0123456789abcdef`
+    /// becomes `foo::bar`), which otherwise changes every time the crate is rebuilt
+    pub strip_rust_hash: bool,
+    /// Demangle C++ symbols using the Itanium ABI (`_Z...`), as used by gcc and clang
+    pub cpp: bool,
+    /// Undecorate MSVC symbols (`?foo@@YAXH@Z`). dbghelp normally does this itself while
+    /// looking up symbols, and setting this makes the symbolicator return the decorated
+    /// names instead, so they can be undecorated without holding dbghelp's global lock.
+    /// Only supported on Windows.
+    pub msvc: bool,
+    /// Leave the parameter types off demangled C++ and MSVC names
+    pub cpp_no_params: bool,
+    /// Collapse the template arguments of demangled C++ and MSVC names to `<...>`, so that
+    /// `std::vector<int, std::allocator<int> >::push_back` becomes `std::vector<...>::push_back`
+    pub fold_cpp_templates: bool,
+}
+
+impl DemangleOptions {
+    /// Returns options that demangle everything this crate knows how to demangle
+    pub fn all() -> DemangleOptions {
+        DemangleOptions {
+            rust: true,
+            strip_rust_hash: true,
+            cpp: true,
+            msvc: cfg!(windows),
+            ..Default::default()
+        }
+    }
+
+    /// True if these options could change any names
+    pub fn enabled(&self) -> bool {
+        self.rust || self.cpp || self.msvc
+    }
+
+    /// Demangles a symbol name, returning None if it isn't mangled in a scheme these options
+    /// demangle
+    #[cfg(feature = "demangle")]
+    pub fn demangle(&self, name: &str) -> Option<String> {
+        if self.rust {
+            if let Ok(demangled) = rustc_demangle::try_demangle(name) {
+                return Some(if self.strip_rust_hash {
+                    format!("{:#}", demangled)
+                } else {
+                    demangled.to_string()
+                });
+            }
+        }
+        if self.cpp && name.starts_with("_Z") {
+            let mut options = cpp_demangle::DemangleOptions::new();
+            if self.cpp_no_params {
+                options = options.no_params();
+            }
+            let symbol = cpp_demangle::Symbol::new(name).ok()?;
+            let demangled = symbol.demangle_with_options(&options).ok()?;
+            return Some(self.fold(demangled));
+        }
+        #[cfg(windows)]
+        if self.msvc && name.starts_with('?') {
+            let flags = if self.cpp_no_params {
+                msvc_demangler::DemangleFlags::NAME_ONLY
+            } else {
+                msvc_demangler::DemangleFlags::llvm()
+            };
+            let demangled = msvc_demangler::demangle(name, flags).ok()?;
+            return Some(self.fold(demangled));
+        }
+        None
+    }
+
+    /// Demangles a symbol name. Without the `demangle` feature there are no demanglers, so
+    /// this always returns None
+    #[cfg(not(feature = "demangle"))]
+    pub fn demangle(&self, _name: &str) -> Option<String> {
+        None
+    }
+
+    #[cfg(feature = "demangle")]
+    fn fold(&self, demangled: String) -> String {
+        if self.fold_cpp_templates {
+            fold_templates(&demangled)
+        } else {
+            demangled
+        }
+    }
+
+    /// Returns a copy of a stack frame with the function name demangled
+    #[cfg(any(use_libunwind, all(target_os = "windows", feature = "unwind")))]
+    pub(crate) fn frame(&self, frame: &StackFrame) -> StackFrame {
+        let mut frame = frame.clone();
+        if let Some(demangled) = frame.function.as_deref().and_then(|f| self.demangle(f)) {
+            frame.function = Some(demangled);
+        }
+        frame
+    }
+}
+
+/// Replaces the contents of each outermost template argument list with `...`. The angle
+/// brackets of operators like `operator<<` aren't template arguments, and are left alone.
+#[cfg(feature = "demangle")]
+fn fold_templates(name: &str) -> String {
+    let mut folded = String::with_capacity(name.len());
+    let mut depth = 0;
+    let mut rest = name;
+    while let Some(c) = rest.chars().next() {
+        if let Some(operator) = rest.strip_prefix("operator") {
+            let len = operator.len() - operator.trim_start_matches(['<', '>', '=']).len();
+            let token = &rest[..8 + len];
+            if depth == 0 {
+                folded.push_str(token);
+            }
+            rest = &rest[token.len()..];
+            continue;
+        }
+        match c {
+            '<' => {
+                if depth == 0 {
+                    folded.push_str("<...");
+                }
+                depth += 1;
+            }
+            '>' if depth > 0 => {
+                depth -= 1;
+                if depth == 0 {
+                    folded.push('>');
+                }
+            }
+            _ if depth == 0 => folded.push(c),
+            _ => {}
+        }
+        rest = &rest[c.len_utf8()..];
+    }
+    folded
+}
+
+#[cfg(all(test, feature = "demangle"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_demangle_rust() {
+        let legacy = "_ZN4core3ptr13drop_in_place17h0123456789abcdefE";
+        let v0 = "_RNvCs1234_7mycrate3foo";
+
+        assert_eq!(DemangleOptions::default().demangle(legacy), None);
+
+        let options = DemangleOptions {
+            rust: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            options.demangle(legacy).as_deref(),
+            Some("core::ptr::drop_in_place::h0123456789abcdef")
+        );
+        assert_eq!(options.demangle("main"), None);
+
+        let options = DemangleOptions::all();
+        assert_eq!(
+            options.demangle(legacy).as_deref(),
+            Some("core::ptr::drop_in_place")
+        );
+        assert_eq!(options.demangle(v0).as_deref(), Some("mycrate::foo"));
+    }
+
+    #[test]
+    fn test_demangle_cpp() {
+        let name = "_ZNSt6vectorIiSaIiEE9push_backERKi";
+        assert_eq!(DemangleOptions::default().demangle(name), None);
+
+        let options = DemangleOptions::all();
+        assert_eq!(
+            options.demangle(name).as_deref(),
+            Some("std::vector<int, std::allocator<int> >::push_back(int const&)")
+        );
+        assert_eq!(options.demangle("main"), None);
+
+        let options = DemangleOptions {
+            cpp: true,
+            cpp_no_params: true,
+            fold_cpp_templates: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            options.demangle(name).as_deref(),
+            Some("std::vector<...>::push_back")
+        );
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_demangle_msvc() {
+        let name = "?push_back@?$vector@HV?$allocator@H@std@@@std@@QEAAXAEBH@Z";
+        assert_eq!(DemangleOptions::default().demangle(name), None);
+
+        let options = DemangleOptions {
+            msvc: true,
+            cpp_no_params: true,
+            fold_cpp_templates: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            options.demangle(name).as_deref(),
+            Some("std::vector<...>::push_back")
+        );
+    }
+
+    #[test]
+    fn test_fold_templates() {
+        assert_eq!(
+            fold_templates("foo<bar<int> >::baz(std::map<int, int>)"),
+            "foo<...>::baz(std::map<...>)"
+        );
+        assert_eq!(
+            fold_templates("operator<<(std::ostream&, Foo<int> const&)"),
+            "operator<<(std::ostream&, Foo<...> const&)"
+        );
+        assert_eq!(fold_templates("Foo<int>::operator<"), "Foo<...>::operator<");
+        assert_eq!(
+            fold_templates("Foo<int>::operator->()"),
+            "Foo<...>::operator->()"
+        );
+    }
+}
@@ -0,0 +1,29 @@
+//! Downloads debug info over HTTP, for the debuginfod and symbol server clients
+use std::path::Path;
+use std::time::Duration;
+
+use log::info;
+
+use crate::Error;
+
+/// Downloads to a temporary file first, so that an interrupted download is never mistaken
+/// for a cached file
+pub(crate) fn download(url: &str, path: &Path, timeout: Duration) -> Result<(), Error> {
+    let response = ureq::get(url)
+        .timeout(timeout)
+        .call()
+        .map_err(|e| Error::Other(format!("request for {} failed: {}", url, e)))?;
+    info!("downloading {}", url);
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let temp = path.with_extension(format!("tmp{}", std::process::id()));
+    let mut file = std::fs::File::create(&temp)?;
+    if let Err(e) = std::io::copy(&mut response.into_reader(), &mut file) {
+        let _ = std::fs::remove_file(&temp);
+        return Err(e.into());
+    }
+    std::fs::rename(&temp, path)?;
+    Ok(())
+}
@@ -0,0 +1,368 @@
+//! Symbolication from Breakpad .sym files, the text format that crash reporting pipelines
+//! produce with dump_syms. These hold function names, line tables and inlining info for a
+//! module, keyed by a module id derived from its build-id - so they can stand in for the
+//! native debug info entirely.
+//!
+//! Addresses in a .sym file are relative to the start of the module's lowest segment.
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::{Error, StackFrame};
+
+#[derive(Debug, Clone)]
+struct Line {
+    address: u64,
+    size: u64,
+    line: u64,
+    file: u32,
+}
+
+#[derive(Debug, Clone)]
+struct Inline {
+    depth: u32,
+    call_line: u64,
+    call_file: u32,
+    origin: u32,
+    ranges: Vec<(u64, u64)>,
+}
+
+impl Inline {
+    fn contains(&self, address: u64) -> bool {
+        self.ranges
+            .iter()
+            .any(|&(start, size)| address >= start && address < start.saturating_add(size))
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Function {
+    address: u64,
+    size: u64,
+    name: String,
+    lines: Vec<Line>,
+    inlines: Vec<Inline>,
+}
+
+impl Function {
+    fn line(&self, address: u64) -> Option<&Line> {
+        let index = self.lines.partition_point(|line| line.address <= address);
+        let line = self.lines.get(index.checked_sub(1)?)?;
+        if address < line.address.saturating_add(line.size) {
+            Some(line)
+        } else {
+            None
+        }
+    }
+}
+
+/// The symbols from a Breakpad .sym file
+#[derive(Debug, Clone, Default)]
+pub struct BreakpadSymbols {
+    module_id: String,
+    name: String,
+    files: HashMap<u32, String>,
+    origins: HashMap<u32, String>,
+    /// sorted by address
+    functions: Vec<Function>,
+    publics: Vec<(u64, String)>,
+}
+
+impl BreakpadSymbols {
+    /// Parses the contents of a .sym file. STACK records aren't needed for symbolication, and
+    /// are skipped along with any other records this doesn't know about.
+    pub fn parse(contents: &str) -> Result<BreakpadSymbols, Error> {
+        let mut symbols = BreakpadSymbols::default();
+        let invalid = |line: &str| Error::Other(format!("invalid breakpad record '{}'", line));
+
+        for line in contents.lines() {
+            let (record, rest) = line.split_once(' ').unwrap_or((line, ""));
+            match record {
+                "MODULE" => {
+                    // MODULE os arch id name
+                    let mut parts = rest.splitn(4, ' ');
+                    let (_os, _arch) = (parts.next(), parts.next());
+                    symbols.module_id = parts.next().ok_or_else(|| invalid(line))?.to_owned();
+                    symbols.name = parts.next().unwrap_or_default().to_owned();
+                }
+                "FILE" => {
+                    let (index, name) = rest.split_once(' ').ok_or_else(|| invalid(line))?;
+                    let index = index.parse().map_err(|_| invalid(line))?;
+                    symbols.files.insert(index, name.to_owned());
+                }
+                "INLINE_ORIGIN" => {
+                    let (index, name) = rest.split_once(' ').ok_or_else(|| invalid(line))?;
+                    let index = index.parse().map_err(|_| invalid(line))?;
+                    symbols.origins.insert(index, name.to_owned());
+                }
+                "FUNC" => {
+                    // FUNC [m] address size param_size name
+                    let rest = rest.strip_prefix("m ").unwrap_or(rest);
+                    let mut parts = rest.splitn(4, ' ');
+                    let address = parse_hex(parts.next()).ok_or_else(|| invalid(line))?;
+                    let size = parse_hex(parts.next()).ok_or_else(|| invalid(line))?;
+                    let _param_size = parts.next();
+                    symbols.functions.push(Function {
+                        address,
+                        size,
+                        name: parts.next().unwrap_or_default().to_owned(),
+                        lines: Vec::new(),
+                        inlines: Vec::new(),
+                    });
+                }
+                "INLINE" => {
+                    // INLINE depth call_line call_file origin [address size]+
+                    let values: Vec<&str> = rest.split(' ').collect();
+                    if values.len() < 6 || !values[4..].chunks_exact(2).remainder().is_empty() {
+                        return Err(invalid(line));
+                    }
+                    let number = |i: usize| values[i].parse().map_err(|_| invalid(line));
+                    let inline = Inline {
+                        depth: number(0)? as u32,
+                        call_line: number(1)?,
+                        call_file: number(2)? as u32,
+                        origin: number(3)? as u32,
+                        ranges: values[4..]
+                            .chunks(2)
+                            .map(|range| {
+                                Some((parse_hex(Some(range[0]))?, parse_hex(Some(range[1]))?))
+                            })
+                            .collect::<Option<_>>()
+                            .ok_or_else(|| invalid(line))?,
+                    };
+                    if let Some(function) = symbols.functions.last_mut() {
+                        function.inlines.push(inline);
+                    }
+                }
+                "PUBLIC" => {
+                    // PUBLIC [m] address param_size name
+                    let rest = rest.strip_prefix("m ").unwrap_or(rest);
+                    let mut parts = rest.splitn(3, ' ');
+                    let address = parse_hex(parts.next()).ok_or_else(|| invalid(line))?;
+                    let _param_size = parts.next();
+                    let name = parts.next().unwrap_or_default().to_owned();
+                    symbols.publics.push((address, name));
+                }
+                _ if record.starts_with(|c: char| c.is_ascii_hexdigit()) => {
+                    // a line record for the preceding FUNC: address size line file
+                    let mut parts = line.split(' ');
+                    let address = parse_hex(parts.next()).ok_or_else(|| invalid(line))?;
+                    let size = parse_hex(parts.next()).ok_or_else(|| invalid(line))?;
+                    let line_number = parts.next().and_then(|l| l.parse().ok());
+                    let file = parts.next().and_then(|f| f.parse().ok());
+                    let (line_number, file) = line_number.zip(file).ok_or_else(|| invalid(line))?;
+                    if let Some(function) = symbols.functions.last_mut() {
+                        function.lines.push(Line {
+                            address,
+                            size,
+                            line: line_number,
+                            file,
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        symbols.functions.sort_by_key(|f| f.address);
+        for function in &mut symbols.functions {
+            function.lines.sort_by_key(|l| l.address);
+            function.inlines.sort_by_key(|i| i.depth);
+        }
+        symbols.publics.sort_by_key(|p| p.0);
+        Ok(symbols)
+    }
+
+    pub fn load(path: &Path) -> Result<BreakpadSymbols, Error> {
+        BreakpadSymbols::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// The id of the module these symbols are for, from the MODULE record
+    pub fn module_id(&self) -> &str {
+        &self.module_id
+    }
+
+    /// The name of the module these symbols are for, from the MODULE record
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the address of a function or public symbol, relative to the start of the
+    /// module
+    pub fn find_symbol(&self, name: &str) -> Option<u64> {
+        self.functions
+            .iter()
+            .find(|f| f.name == name)
+            .map(|f| f.address)
+            .or_else(|| self.publics.iter().find(|p| p.1 == name).map(|p| p.0))
+    }
+
+    fn function(&self, address: u64) -> Option<&Function> {
+        let index = self.functions.partition_point(|f| f.address <= address);
+        let function = self.functions.get(index.checked_sub(1)?)?;
+        if address < function.address.saturating_add(function.size) {
+            Some(function)
+        } else {
+            None
+        }
+    }
+
+    /// Symbolicates an address relative to the start of the module, calling the callback
+    /// with the frames for any inlined functions first. `addr` and `module` are only used to
+    /// fill in the returned frames.
+    pub fn symbolicate(
+        &self,
+        address: u64,
+        addr: u64,
+        module: &str,
+        line_info: bool,
+        callback: &mut dyn FnMut(&StackFrame),
+    ) {
+        let frame = |function: Option<&str>, file: Option<u32>, line: Option<u64>| StackFrame {
+            line: if line_info { line } else { None },
+            column: None,
+            filename: if line_info {
+                file.and_then(|f| self.files.get(&f).cloned())
+            } else {
+                None
+            },
+            function: function.map(str::to_owned),
+            module: module.to_owned(),
+            addr,
+            module_offset: Some(address),
+            inlined: false,
+        };
+
+        let function = match self.function(address) {
+            Some(function) => function,
+            None => {
+                // fall back to the PUBLIC records, which only have names
+                let index = self.publics.partition_point(|p| p.0 <= address);
+                let name = index.checked_sub(1).map(|i| self.publics[i].1.as_str());
+                callback(&frame(name, None, None));
+                return;
+            }
+        };
+
+        // the functions inlined at this address, outermost first. Each one's call site is in
+        // the function that it was inlined into.
+        let inlines: Vec<&Inline> = function
+            .inlines
+            .iter()
+            .filter(|inline| inline.contains(address))
+            .collect();
+
+        let line = function.line(address);
+        let mut file = line.map(|l| l.file);
+        let mut line = line.map(|l| l.line);
+        for inline in inlines.iter().rev() {
+            let name = self.origins.get(&inline.origin).map(String::as_str);
+            let mut inlined = frame(name, file, line);
+            inlined.inlined = true;
+            callback(&inlined);
+            file = Some(inline.call_file);
+            line = Some(inline.call_line);
+        }
+        callback(&frame(Some(&function.name), file, line));
+    }
+}
+
+/// Returns the Breakpad module id for an ELF build-id. This is the first 16 bytes of the
+/// build-id formatted as a GUID, followed by an age of 0.
+pub fn breakpad_id(build_id: &[u8]) -> String {
+    let mut guid = [0_u8; 16];
+    let len = build_id.len().min(16);
+    guid[..len].copy_from_slice(&build_id[..len]);
+    let data1 = u32::from_le_bytes([guid[0], guid[1], guid[2], guid[3]]);
+    let data2 = u16::from_le_bytes([guid[4], guid[5]]);
+    let data3 = u16::from_le_bytes([guid[6], guid[7]]);
+    let data4: String = guid[8..].iter().map(|b| format!("{:02X}", b)).collect();
+    format!("{:08X}{:04X}{:04X}{}0", data1, data2, data3, data4)
+}
+
+/// The path of the .sym file for a module in a symbol directory laid out the way
+/// symupload and the crash reporting servers expect: `<name>/<id>/<name>.sym`
+pub fn breakpad_path(directory: &Path, name: &str, id: &str) -> PathBuf {
+    directory.join(name).join(id).join(format!("{}.sym", name))
+}
+
+fn parse_hex(value: Option<&str>) -> Option<u64> {
+    u64::from_str_radix(value?, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SYMBOLS: &str = "MODULE Linux x86_64 0123456789ABCDEF0123456789ABCDEF0 libfoo.so
+INFO CODE_ID 67452301AB89EFCD0123456789ABCDEF
+FILE 0 /src/foo.c
+FILE 1 /src/bar.h
+INLINE_ORIGIN 0 bar
+INLINE_ORIGIN 1 baz
+FUNC 1000 40 0 foo
+INLINE 0 12 0 0 1010 20
+INLINE 1 30 1 1 1018 8
+1000 10 10 0
+1010 8 31 1
+1018 8 40 1
+1020 20 14 0
+PUBLIC 2000 0 qux
+STACK CFI INIT 1000 40 .cfa: $rsp 8 + .ra: .cfa -8 + ^
+";
+
+    fn frames(symbols: &BreakpadSymbols, address: u64) -> Vec<StackFrame> {
+        let mut frames = Vec::new();
+        symbols.symbolicate(address, address, "libfoo.so", true, &mut |f| {
+            frames.push(f.clone())
+        });
+        frames
+    }
+
+    #[test]
+    fn test_parse() {
+        let symbols = BreakpadSymbols::parse(SYMBOLS).unwrap();
+        assert_eq!(symbols.module_id(), "0123456789ABCDEF0123456789ABCDEF0");
+        assert_eq!(symbols.name(), "libfoo.so");
+
+        let f = frames(&symbols, 0x1004);
+        assert_eq!(f.len(), 1);
+        assert_eq!(f[0].function.as_deref(), Some("foo"));
+        assert_eq!(f[0].filename.as_deref(), Some("/src/foo.c"));
+        assert_eq!(f[0].line, Some(10));
+
+        assert_eq!(frames(&symbols, 0x2010)[0].function.as_deref(), Some("qux"));
+    }
+
+    #[test]
+    fn test_find_symbol() {
+        let symbols = BreakpadSymbols::parse(SYMBOLS).unwrap();
+        assert_eq!(symbols.find_symbol("foo"), Some(0x1000));
+        assert_eq!(symbols.find_symbol("qux"), Some(0x2000));
+        assert_eq!(symbols.find_symbol("baz"), None);
+    }
+
+    #[test]
+    fn test_inlines() {
+        let symbols = BreakpadSymbols::parse(SYMBOLS).unwrap();
+        let f = frames(&symbols, 0x101c);
+        let names: Vec<_> = f.iter().map(|f| f.function.as_deref().unwrap()).collect();
+        assert_eq!(names, ["baz", "bar", "foo"]);
+        let lines: Vec<_> = f.iter().map(|f| f.line.unwrap()).collect();
+        assert_eq!(lines, [40, 30, 12]);
+        let inlined: Vec<_> = f.iter().map(|f| f.inlined).collect();
+        assert_eq!(inlined, [true, true, false]);
+    }
+
+    #[test]
+    fn test_breakpad_id() {
+        let build_id = [
+            0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef, 0x01, 0x23, 0x45, 0x67, 0x89, 0xab,
+            0xcd, 0xef, 0x11, 0x22, 0x33, 0x44,
+        ];
+        assert_eq!(breakpad_id(&build_id), "67452301AB89EFCD0123456789ABCDEF0");
+        assert_eq!(
+            breakpad_path(Path::new("/symbols"), "libfoo.so", "ABC0"),
+            PathBuf::from("/symbols/libfoo.so/ABC0/libfoo.so.sym")
+        );
+    }
+}
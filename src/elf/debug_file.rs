@@ -0,0 +1,144 @@
+//! Finds the separate debug info files that distributions ship stripped binaries with
+//! (the -dbgsym / -debuginfo packages), using the same search rules as gdb: first by
+//! build-id under /usr/lib/debug/.build-id, and then by the name and checksum stored in the
+//! binary's .gnu_debuglink section.
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use log::debug;
+use memmap2::Mmap;
+use object::Object;
+
+const DEBUG_DIRECTORY: &str = "/usr/lib/debug";
+
+/// Returns the separate debug info file for a binary, if one is installed. `path` is where
+/// the binary can be opened from, and `filename` the path to it inside the target process's
+/// mount namespace (which is where debuglink paths are relative to). `resolve` turns a path
+/// in the target's mount namespace into one we can open, if the file exists there.
+pub fn find_debug_file(
+    object: &object::File,
+    path: &Path,
+    filename: &Path,
+    resolve: &dyn Fn(&Path) -> Option<PathBuf>,
+) -> Option<PathBuf> {
+    if let Some(build_id) = object.build_id().ok().flatten() {
+        if let Some(debug_file) = build_id_path(Path::new(DEBUG_DIRECTORY), build_id) {
+            if debug_file.exists() {
+                return Some(debug_file);
+            }
+        }
+    }
+
+    let (name, crc) = object.gnu_debuglink().ok().flatten()?;
+    let name = Path::new(std::str::from_utf8(name).ok()?);
+    let candidates: Vec<PathBuf> = if filename.is_absolute() {
+        debuglink_candidates(filename, name)
+            .iter()
+            .filter_map(|candidate| resolve(candidate))
+            .collect()
+    } else {
+        // libraries mapped by name only (as on android) have nothing to resolve against,
+        // other than the directory we found them in
+        debuglink_candidates(path, name)
+    };
+    candidates.into_iter().find(|candidate| {
+        let matches = file_crc(candidate) == Some(crc);
+        if candidate.exists() && !matches {
+            debug!("ignoring {}: checksum mismatch", candidate.display());
+        }
+        matches
+    })
+}
+
+/// The path of a debug file named by build-id, which is split after the first byte
+fn build_id_path(directory: &Path, build_id: &[u8]) -> Option<PathBuf> {
+    let (first, rest) = build_id.split_first()?;
+    let rest: String = rest.iter().map(|b| format!("{:02x}", b)).collect();
+    Some(
+        directory
+            .join(".build-id")
+            .join(format!("{:02x}", first))
+            .join(format!("{}.debug", rest)),
+    )
+}
+
+/// The places gdb looks for a debuglink file: next to the binary, in a .debug directory
+/// next to the binary, and mirrored under the global debug directory
+fn debuglink_candidates(filename: &Path, name: &Path) -> Vec<PathBuf> {
+    let directory = match filename.parent() {
+        Some(directory) => directory,
+        None => return Vec::new(),
+    };
+    let mut candidates = Vec::new();
+    // a debuglink naming the binary itself would always match its own checksum
+    if directory.join(name) != filename {
+        candidates.push(directory.join(name));
+    }
+    candidates.push(directory.join(".debug").join(name));
+    let relative = directory.strip_prefix("/").unwrap_or(directory);
+    candidates.push(Path::new(DEBUG_DIRECTORY).join(relative).join(name));
+    candidates
+}
+
+fn file_crc(path: &Path) -> Option<u32> {
+    let file = File::open(path).ok()?;
+    let map = unsafe { Mmap::map(&file).ok()? };
+    Some(crc32(&map))
+}
+
+/// The CRC-32 used by .gnu_debuglink (the same polynomial as zlib)
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0_u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn test_build_id_path() {
+        assert_eq!(
+            build_id_path(Path::new("/usr/lib/debug"), &[0xab, 0xcd, 0xef]),
+            Some(PathBuf::from("/usr/lib/debug/.build-id/ab/cdef.debug"))
+        );
+        assert_eq!(build_id_path(Path::new("/usr/lib/debug"), &[]), None);
+    }
+
+    #[test]
+    fn test_debuglink_candidates() {
+        let candidates = debuglink_candidates(
+            Path::new("/usr/lib/libfoo.so"),
+            Path::new("libfoo.so.debug"),
+        );
+        assert_eq!(
+            candidates,
+            vec![
+                PathBuf::from("/usr/lib/libfoo.so.debug"),
+                PathBuf::from("/usr/lib/.debug/libfoo.so.debug"),
+                PathBuf::from("/usr/lib/debug/usr/lib/libfoo.so.debug"),
+            ]
+        );
+
+        // a debuglink can't name the binary itself
+        let candidates =
+            debuglink_candidates(Path::new("/usr/lib/libfoo.so"), Path::new("libfoo.so"));
+        assert!(!candidates.contains(&PathBuf::from("/usr/lib/libfoo.so")));
+    }
+}
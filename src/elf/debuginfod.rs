@@ -0,0 +1,125 @@
+//! Fetches debug info for stripped binaries from debuginfod servers, using the same
+//! environment variables and cache layout as the elfutils client - so debug info that gdb or
+//! perf has already downloaded is reused rather than fetched again.
+use std::path::PathBuf;
+use std::time::Duration;
+
+use log::debug;
+
+use crate::download::download;
+
+/// A client for the debuginfod servers listed in DEBUGINFOD_URLS
+#[derive(Debug, Clone)]
+pub struct Debuginfod {
+    urls: Vec<String>,
+    cache_dir: PathBuf,
+    timeout: Duration,
+}
+
+impl Debuginfod {
+    pub fn new(urls: Vec<String>, cache_dir: PathBuf) -> Debuginfod {
+        Debuginfod {
+            urls,
+            cache_dir,
+            timeout: Duration::from_secs(90),
+        }
+    }
+
+    /// Creates a client from the DEBUGINFOD_URLS environment variable, returning None if it
+    /// isn't set. The cache directory can be overridden with DEBUGINFOD_CACHE_PATH, and the
+    /// timeout in seconds with DEBUGINFOD_TIMEOUT.
+    pub fn from_env() -> Option<Debuginfod> {
+        let urls: Vec<String> = std::env::var("DEBUGINFOD_URLS")
+            .ok()?
+            .split_whitespace()
+            .map(str::to_owned)
+            .collect();
+        if urls.is_empty() {
+            return None;
+        }
+        let mut client = Debuginfod::new(urls, default_cache_dir()?);
+        if let Some(timeout) = std::env::var("DEBUGINFOD_TIMEOUT")
+            .ok()
+            .and_then(|t| t.parse().ok())
+        {
+            client.timeout = Duration::from_secs(timeout);
+        }
+        Some(client)
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Returns the path to the debug info for the binary with this build-id, downloading it
+    /// if it isn't already cached
+    pub fn debuginfo(&self, build_id: &[u8]) -> Option<PathBuf> {
+        self.fetch(build_id, "debuginfo")
+    }
+
+    /// Returns the path to the executable or shared library with this build-id, downloading
+    /// it if it isn't already cached
+    pub fn executable(&self, build_id: &[u8]) -> Option<PathBuf> {
+        self.fetch(build_id, "executable")
+    }
+
+    fn fetch(&self, build_id: &[u8], kind: &str) -> Option<PathBuf> {
+        let build_id = hex(build_id);
+        let cached = self.cache_path(&build_id, kind);
+        if cached.exists() {
+            return Some(cached);
+        }
+        for url in &self.urls {
+            let url = format!(
+                "{}/buildid/{}/{}",
+                url.trim_end_matches('/'),
+                build_id,
+                kind
+            );
+            match download(&url, &cached, self.timeout) {
+                Ok(()) => return Some(cached),
+                Err(e) => debug!("failed to download {}: {:?}", url, e),
+            }
+        }
+        None
+    }
+
+    fn cache_path(&self, build_id: &str, kind: &str) -> PathBuf {
+        self.cache_dir.join(build_id).join(kind)
+    }
+}
+
+fn default_cache_dir() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os("DEBUGINFOD_CACHE_PATH") {
+        return Some(PathBuf::from(path));
+    }
+    let cache = match std::env::var_os("XDG_CACHE_HOME") {
+        Some(cache) => PathBuf::from(cache),
+        None => PathBuf::from(std::env::var_os("HOME")?).join(".cache"),
+    };
+    Some(cache.join("debuginfod_client"))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cached() {
+        let cache_dir = std::env::temp_dir().join(format!("debuginfod-{}", std::process::id()));
+        let client = Debuginfod::new(vec!["http://localhost:1".to_owned()], cache_dir.clone());
+        let build_id = [0xab, 0xcd, 0x01];
+
+        let path = cache_dir.join("abcd01").join("debuginfo");
+        assert_eq!(client.cache_path(&hex(&build_id), "debuginfo"), path);
+
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, b"debug info").unwrap();
+        assert_eq!(client.debuginfo(&build_id), Some(path));
+        std::fs::remove_dir_all(&cache_dir).unwrap();
+    }
+}
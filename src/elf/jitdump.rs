@@ -0,0 +1,317 @@
+//! Symbols and line numbers for JIT compiled code, read from the jit-<pid>.dump files in
+//! the perf JITDUMP format. These are written by V8 (--perf-prof), the JVM (through a JVMTI
+//! agent) and .NET, and unlike perf maps include source line information for each function.
+//!
+//! Runtimes mmap the dump file as executable so that perf can find it, which means it shows
+//! up in /proc/pid/maps and can be located the same way.
+use std::collections::HashMap;
+use std::path::Path;
+#[cfg(use_libunwind)]
+use std::path::PathBuf;
+
+use crate::Error;
+#[cfg(use_libunwind)]
+use crate::Process;
+
+const MAGIC: u32 = 0x4A69_5444;
+const HEADER_SIZE: usize = 40;
+const RECORD_HEADER_SIZE: usize = 16;
+
+const JIT_CODE_LOAD: u32 = 0;
+const JIT_CODE_MOVE: u32 = 1;
+const JIT_CODE_DEBUG_INFO: u32 = 2;
+const JIT_CODE_CLOSE: u32 = 3;
+
+/// A source line for a range of instructions in a JIT compiled function
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct JitLine {
+    pub address: u64,
+    pub line: u64,
+    pub filename: String,
+}
+
+/// A function compiled by the JIT
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JitFunction {
+    pub address: u64,
+    pub size: u64,
+    pub name: String,
+    /// line table for the function sorted by address, empty if the runtime didn't write one
+    pub lines: Vec<JitLine>,
+    code_index: u64,
+}
+
+impl JitFunction {
+    pub fn contains(&self, addr: u64) -> bool {
+        addr >= self.address && addr < self.address.saturating_add(self.size)
+    }
+
+    /// Returns the source line for an address inside this function
+    pub fn line(&self, addr: u64) -> Option<&JitLine> {
+        let index = self.lines.partition_point(|line| line.address <= addr);
+        self.lines.get(index.checked_sub(1)?)
+    }
+}
+
+/// The functions in a JITDUMP file, sorted by address
+#[derive(Debug, Default)]
+pub struct JitDump {
+    functions: Vec<JitFunction>,
+}
+
+impl JitDump {
+    /// Parses a JITDUMP file. The runtime appends to the file as it compiles code, so a
+    /// truncated record at the end of the file is ignored rather than treated as an error.
+    pub fn parse(data: &[u8]) -> Result<JitDump, Error> {
+        let mut reader = Reader {
+            data,
+            offset: 0,
+            swap: false,
+        };
+        match reader.u32() {
+            Some(MAGIC) => {}
+            Some(magic) if magic.swap_bytes() == MAGIC => reader.swap = true,
+            _ => return Err(Error::Other("not a jitdump file".to_owned())),
+        }
+        let _version = reader.u32();
+        let header_size = reader.u32().unwrap_or(0) as usize;
+        reader.offset = header_size.max(HEADER_SIZE);
+
+        let mut functions: Vec<JitFunction> = Vec::new();
+        let mut by_index: HashMap<u64, usize> = HashMap::new();
+        let mut debug_info: HashMap<u64, Vec<JitLine>> = HashMap::new();
+
+        while let Some((id, record)) = reader.record() {
+            match id {
+                JIT_CODE_LOAD => {
+                    // skip over the pid, tid and vma fields
+                    let mut record = record.skip(16);
+                    let (address, size, code_index, name) =
+                        match (record.u64(), record.u64(), record.u64(), record.c_str()) {
+                            (Some(a), Some(b), Some(c), Some(d)) => (a, b, c, d),
+                            _ => break,
+                        };
+                    let function = JitFunction {
+                        address,
+                        size,
+                        name,
+                        lines: debug_info.remove(&address).unwrap_or_default(),
+                        code_index,
+                    };
+                    match by_index.get(&code_index) {
+                        Some(&i) => functions[i] = function,
+                        None => {
+                            by_index.insert(code_index, functions.len());
+                            functions.push(function);
+                        }
+                    }
+                }
+                JIT_CODE_MOVE => {
+                    // skip over the pid, tid and vma fields
+                    let mut record = record.skip(16);
+                    let (_old_address, new_address, _size, code_index) =
+                        match (record.u64(), record.u64(), record.u64(), record.u64()) {
+                            (Some(a), Some(b), Some(c), Some(d)) => (a, b, c, d),
+                            _ => break,
+                        };
+                    if let Some(&i) = by_index.get(&code_index) {
+                        functions[i].address = new_address;
+                    }
+                }
+                JIT_CODE_DEBUG_INFO => {
+                    let mut record = record;
+                    let (address, count) = match (record.u64(), record.u64()) {
+                        (Some(a), Some(b)) => (a, b),
+                        _ => break,
+                    };
+                    let mut lines = Vec::new();
+                    for _ in 0..count {
+                        let (line_address, line, _discriminator, filename) =
+                            match (record.u64(), record.u32(), record.u32(), record.c_str()) {
+                                (Some(a), Some(b), Some(c), Some(d)) => (a, b, c, d),
+                                _ => break,
+                            };
+                        lines.push(JitLine {
+                            address: line_address,
+                            line: line as u64,
+                            filename,
+                        });
+                    }
+                    lines.sort_by_key(|line| line.address);
+                    debug_info.insert(address, lines);
+                }
+                JIT_CODE_CLOSE => break,
+                _ => {}
+            }
+        }
+
+        functions.sort_by_key(|function| function.address);
+        Ok(JitDump { functions })
+    }
+
+    pub fn load(path: &Path) -> Result<JitDump, Error> {
+        JitDump::parse(&std::fs::read(path)?)
+    }
+
+    /// Returns the JIT compiled function containing an address
+    pub fn find(&self, addr: u64) -> Option<&JitFunction> {
+        let index = self.functions.partition_point(|f| f.address <= addr);
+        let function = self.functions.get(index.checked_sub(1)?)?;
+        if function.contains(addr) {
+            Some(function)
+        } else {
+            None
+        }
+    }
+
+    pub fn functions(&self) -> &[JitFunction] {
+        &self.functions
+    }
+}
+
+/// Finds the jitdump file a process is writing to, by looking for the mapping of it
+#[cfg(use_libunwind)]
+pub fn jitdump_path(process: &Process) -> Option<PathBuf> {
+    let maps = proc_maps::get_process_maps(process.pid).ok()?;
+    maps.iter().find_map(|m| {
+        let filename = m.filename()?;
+        let name = filename.file_name()?.to_str()?;
+        if name.starts_with("jit-") && name.ends_with(".dump") {
+            process.resolve_path(filename, m.start(), m.start() + m.size())
+        } else {
+            None
+        }
+    })
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+    swap: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let bytes = self.data.get(self.offset..self.offset + N)?;
+        self.offset += N;
+        bytes.try_into().ok()
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        let value = u32::from_ne_bytes(self.bytes()?);
+        Some(if self.swap { value.swap_bytes() } else { value })
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        let value = u64::from_ne_bytes(self.bytes()?);
+        Some(if self.swap { value.swap_bytes() } else { value })
+    }
+
+    fn skip(mut self, count: usize) -> Self {
+        self.offset += count;
+        self
+    }
+
+    fn c_str(&mut self) -> Option<String> {
+        let remaining = self.data.get(self.offset..)?;
+        let end = remaining.iter().position(|b| *b == 0)?;
+        self.offset += end + 1;
+        Some(String::from_utf8_lossy(&remaining[..end]).into_owned())
+    }
+
+    /// Returns the id of the next record, and a reader over the body of it
+    fn record(&mut self) -> Option<(u32, Reader<'a>)> {
+        let start = self.offset;
+        let id = self.u32()?;
+        let size = self.u32()? as usize;
+        if size < RECORD_HEADER_SIZE || start + size > self.data.len() {
+            return None;
+        }
+        let body = Reader {
+            data: &self.data[start + RECORD_HEADER_SIZE..start + size],
+            offset: 0,
+            swap: self.swap,
+        };
+        self.offset = start + size;
+        Some((id, body))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(id: u32, body: &[u8]) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&id.to_ne_bytes());
+        data.extend_from_slice(&((body.len() + RECORD_HEADER_SIZE) as u32).to_ne_bytes());
+        data.extend_from_slice(&0_u64.to_ne_bytes());
+        data.extend_from_slice(body);
+        data
+    }
+
+    fn code_load(address: u64, size: u64, index: u64, name: &str) -> Vec<u8> {
+        let mut body = Vec::new();
+        for value in [1_u64, address, address, size, index] {
+            body.extend_from_slice(&value.to_ne_bytes());
+        }
+        body.extend_from_slice(name.as_bytes());
+        body.push(0);
+        record(JIT_CODE_LOAD, &body)
+    }
+
+    fn jitdump() -> Vec<u8> {
+        let mut data = Vec::new();
+        for value in [MAGIC, 1, HEADER_SIZE as u32, 62, 0, 1234] {
+            data.extend_from_slice(&value.to_ne_bytes());
+        }
+        data.extend_from_slice(&[0; 16]);
+
+        let mut debug = Vec::new();
+        for value in [0x1000_u64, 2] {
+            debug.extend_from_slice(&value.to_ne_bytes());
+        }
+        for (address, line) in [(0x1000_u64, 10_u32), (0x1010, 12)] {
+            debug.extend_from_slice(&address.to_ne_bytes());
+            debug.extend_from_slice(&line.to_ne_bytes());
+            debug.extend_from_slice(&0_u32.to_ne_bytes());
+            debug.extend_from_slice(b"index.js\0");
+        }
+        data.extend(record(JIT_CODE_DEBUG_INFO, &debug));
+        data.extend(code_load(0x1000, 0x40, 1, "foo"));
+        data.extend(code_load(0x2000, 0x20, 2, "bar"));
+
+        let mut moved = Vec::new();
+        for value in [1_u64, 0x2000, 0x2000, 0x3000, 0x20, 2] {
+            moved.extend_from_slice(&value.to_ne_bytes());
+        }
+        data.extend(record(JIT_CODE_MOVE, &moved));
+        data
+    }
+
+    #[test]
+    fn test_parse_jitdump() {
+        let mut data = jitdump();
+        // a partially written record at the end should be ignored
+        data.extend_from_slice(&code_load(0x4000, 0x10, 3, "baz")[..20]);
+
+        let dump = JitDump::parse(&data).unwrap();
+        assert_eq!(dump.functions().len(), 2);
+
+        let foo = dump.find(0x1018).unwrap();
+        assert_eq!(foo.name, "foo");
+        let line = foo.line(0x1018).unwrap();
+        assert_eq!((line.line, line.filename.as_str()), (12, "index.js"));
+        assert_eq!(foo.line(0x1008).unwrap().line, 10);
+
+        assert_eq!(dump.find(0x3010).unwrap().name, "bar");
+        assert!(dump.find(0x2010).is_none());
+        assert!(dump.find(0x4000).is_none());
+    }
+
+    #[test]
+    fn test_invalid_jitdump() {
+        assert!(JitDump::parse(b"not a jitdump").is_err());
+    }
+}
@@ -0,0 +1,130 @@
+/* Hand-written subset of the libunwind-riscv.h bindings used by this crate, laid out to match rust-bindgen output */
+
+pub type size_t = ::std::os::raw::c_ulong;
+pub type unw_word_t = u64;
+pub type unw_tdep_fpreg_t = u64;
+pub const unw_error_t_UNW_ESUCCESS: unw_error_t = 0;
+pub const unw_error_t_UNW_EUNSPEC: unw_error_t = 1;
+pub const unw_error_t_UNW_ENOMEM: unw_error_t = 2;
+pub const unw_error_t_UNW_EBADREG: unw_error_t = 3;
+pub const unw_error_t_UNW_EREADONLYREG: unw_error_t = 4;
+pub const unw_error_t_UNW_ESTOPUNWIND: unw_error_t = 5;
+pub const unw_error_t_UNW_EINVALIDIP: unw_error_t = 6;
+pub const unw_error_t_UNW_EBADFRAME: unw_error_t = 7;
+pub const unw_error_t_UNW_EINVAL: unw_error_t = 8;
+pub const unw_error_t_UNW_EBADVERSION: unw_error_t = 9;
+pub const unw_error_t_UNW_ENOINFO: unw_error_t = 10;
+pub type unw_error_t = ::std::os::raw::c_uint;
+pub const unw_frame_regnum_t_UNW_REG_IP: unw_frame_regnum_t = 64;
+pub const unw_frame_regnum_t_UNW_REG_SP: unw_frame_regnum_t = 2;
+pub type unw_frame_regnum_t = ::std::os::raw::c_uint;
+pub const unw_caching_policy_t_UNW_CACHE_NONE: unw_caching_policy_t = 0;
+pub const unw_caching_policy_t_UNW_CACHE_GLOBAL: unw_caching_policy_t = 1;
+pub const unw_caching_policy_t_UNW_CACHE_PER_THREAD: unw_caching_policy_t = 2;
+pub type unw_caching_policy_t = ::std::os::raw::c_uint;
+pub type unw_regnum_t = ::std::os::raw::c_int;
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct unw_cursor {
+    pub opaque: [unw_word_t; 4096usize],
+}
+pub type unw_cursor_t = unw_cursor;
+pub type unw_fpreg_t = unw_tdep_fpreg_t;
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct unw_addr_space {
+    _unused: [u8; 0],
+}
+pub type unw_addr_space_t = *mut unw_addr_space;
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct unw_proc_info {
+    pub start_ip: unw_word_t,
+    pub end_ip: unw_word_t,
+    pub lsda: unw_word_t,
+    pub handler: unw_word_t,
+    pub gp: unw_word_t,
+    pub flags: unw_word_t,
+    pub format: ::std::os::raw::c_int,
+    pub unwind_info_size: ::std::os::raw::c_int,
+    pub unwind_info: *mut ::std::os::raw::c_void,
+    pub extra: unw_tdep_proc_info_t,
+}
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct unw_tdep_proc_info_t {
+    pub unused: ::std::os::raw::c_char,
+}
+pub type unw_proc_info_t = unw_proc_info;
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct unw_accessors {
+    pub find_proc_info: ::std::option::Option<
+        unsafe extern "C" fn(
+            arg1: unw_addr_space_t,
+            arg2: unw_word_t,
+            arg3: *mut unw_proc_info_t,
+            arg4: ::std::os::raw::c_int,
+            arg5: *mut ::std::os::raw::c_void,
+        ) -> ::std::os::raw::c_int,
+    >,
+    pub put_unwind_info: ::std::option::Option<
+        unsafe extern "C" fn(
+            arg1: unw_addr_space_t,
+            arg2: *mut unw_proc_info_t,
+            arg3: *mut ::std::os::raw::c_void,
+        ),
+    >,
+    pub get_dyn_info_list_addr: ::std::option::Option<
+        unsafe extern "C" fn(
+            arg1: unw_addr_space_t,
+            arg2: *mut unw_word_t,
+            arg3: *mut ::std::os::raw::c_void,
+        ) -> ::std::os::raw::c_int,
+    >,
+    pub access_mem: ::std::option::Option<
+        unsafe extern "C" fn(
+            arg1: unw_addr_space_t,
+            arg2: unw_word_t,
+            arg3: *mut unw_word_t,
+            arg4: ::std::os::raw::c_int,
+            arg5: *mut ::std::os::raw::c_void,
+        ) -> ::std::os::raw::c_int,
+    >,
+    pub access_reg: ::std::option::Option<
+        unsafe extern "C" fn(
+            arg1: unw_addr_space_t,
+            arg2: unw_regnum_t,
+            arg3: *mut unw_word_t,
+            arg4: ::std::os::raw::c_int,
+            arg5: *mut ::std::os::raw::c_void,
+        ) -> ::std::os::raw::c_int,
+    >,
+    pub access_fpreg: ::std::option::Option<
+        unsafe extern "C" fn(
+            arg1: unw_addr_space_t,
+            arg2: unw_regnum_t,
+            arg3: *mut unw_fpreg_t,
+            arg4: ::std::os::raw::c_int,
+            arg5: *mut ::std::os::raw::c_void,
+        ) -> ::std::os::raw::c_int,
+    >,
+    pub resume: ::std::option::Option<
+        unsafe extern "C" fn(
+            arg1: unw_addr_space_t,
+            arg2: *mut unw_cursor_t,
+            arg3: *mut ::std::os::raw::c_void,
+        ) -> ::std::os::raw::c_int,
+    >,
+    pub get_proc_name: ::std::option::Option<
+        unsafe extern "C" fn(
+            arg1: unw_addr_space_t,
+            arg2: unw_word_t,
+            arg3: *mut ::std::os::raw::c_char,
+            arg4: size_t,
+            arg5: *mut unw_word_t,
+            arg6: *mut ::std::os::raw::c_void,
+        ) -> ::std::os::raw::c_int,
+    >,
+}
+pub type unw_accessors_t = unw_accessors;
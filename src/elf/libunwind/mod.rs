@@ -1,9 +1,12 @@
 use libc::{c_char, c_int, c_void, pid_t, size_t};
 use std;
+use std::sync::Arc;
 
 #[cfg_attr(target_arch = "x86_64", path = "bindings_x86_64.rs")]
 #[cfg_attr(target_arch = "arm", path = "bindings_arm.rs")]
 #[cfg_attr(target_arch = "aarch64", path = "bindings_aarch64.rs")]
+#[cfg_attr(target_arch = "riscv64", path = "bindings_riscv64.rs")]
+#[cfg_attr(target_arch = "loongarch64", path = "bindings_loongarch64.rs")]
 mod bindings;
 
 use self::bindings::{
@@ -30,7 +33,9 @@ pub enum Error {
 type Result<T> = std::result::Result<T, crate::Error>;
 
 pub struct Unwinder {
+    /// null when unwinding with frame pointers, which doesn't use libunwind
     pub addr_space: unw_addr_space_t,
+    frame_pointer: Option<Arc<crate::Process>>,
 }
 
 impl Unwinder {
@@ -39,11 +44,37 @@ impl Unwinder {
             let addr_space = create_addr_space(&_UPT_accessors as *const _ as *mut _, 0);
             // enabling caching provides a modest speedup - but is still much slower than the gimli unwinding
             set_caching_policy(addr_space, unw_caching_policy_t_UNW_CACHE_PER_THREAD);
-            Ok(Unwinder { addr_space })
+            Ok(Unwinder {
+                addr_space,
+                frame_pointer: None,
+            })
+        }
+    }
+
+    /// Creates an unwinder for the threads of a process. With `UnwindMode::FramePointer` the
+    /// cursors this returns only follow frame pointers, without using libunwind at all.
+    pub fn with_mode(process: &crate::Process, mode: crate::UnwindMode) -> Result<Unwinder> {
+        if mode == crate::UnwindMode::FramePointer {
+            // every cursor reads the stack through this one handle to the process
+            return Ok(Unwinder {
+                addr_space: std::ptr::null_mut(),
+                frame_pointer: Some(Arc::new(process.try_clone()?)),
+            });
         }
+        Unwinder::new()
     }
 
     pub fn cursor(&self, thread: &crate::Thread) -> Result<Cursor> {
+        if let Some(process) = self.frame_pointer.as_ref() {
+            let (ip, fp) = thread.frame_registers()?;
+            return Ok(Cursor {
+                cursor: unsafe { std::mem::zeroed() },
+                upt: std::ptr::null_mut(),
+                initial_frame: true,
+                frame_pointer: Some(crate::FramePointerCursor::new(process.clone(), ip, fp)),
+            });
+        }
+
         unsafe {
             let upt = _UPT_create(thread.id()? as _);
             let mut cursor = std::mem::MaybeUninit::uninit();
@@ -55,6 +86,7 @@ impl Unwinder {
                 cursor: cursor.assume_init(),
                 upt,
                 initial_frame: true,
+                frame_pointer: None,
             })
         }
     }
@@ -62,8 +94,10 @@ impl Unwinder {
 
 impl Drop for Unwinder {
     fn drop(&mut self) {
-        unsafe {
-            destroy_addr_space(self.addr_space);
+        if !self.addr_space.is_null() {
+            unsafe {
+                destroy_addr_space(self.addr_space);
+            }
         }
     }
 }
@@ -72,10 +106,21 @@ pub struct Cursor {
     cursor: unw_cursor,
     upt: *mut c_void,
     initial_frame: bool,
+    frame_pointer: Option<crate::FramePointerCursor<Arc<crate::Process>>>,
+}
+
+fn frame_pointer_error() -> crate::Error {
+    crate::Error::UnwindError {
+        message: "only the instruction pointer is available when unwinding with frame pointers"
+            .to_owned(),
+    }
 }
 
 impl Cursor {
     pub unsafe fn register(&self, register: i32) -> Result<u64> {
+        if self.frame_pointer.is_some() {
+            return Err(frame_pointer_error());
+        }
         let mut value = 0;
         let cursor = &self.cursor as *const _ as *mut _;
 
@@ -101,6 +146,9 @@ impl Cursor {
     }
 
     pub fn ip(&self) -> Result<u64> {
+        if let Some(frame_pointer) = self.frame_pointer.as_ref() {
+            return Ok(frame_pointer.ip());
+        }
         unsafe { self.register(unw_frame_regnum_t_UNW_REG_IP as i32) }
     }
 
@@ -109,6 +157,9 @@ impl Cursor {
     }
 
     pub fn proc_name(&self) -> Result<String> {
+        if self.frame_pointer.is_some() {
+            return Err(frame_pointer_error());
+        }
         unsafe {
             let mut name = vec![0_u8 as c_char; 128];
             let cursor = &self.cursor as *const _ as *mut _;
@@ -135,10 +186,38 @@ impl Cursor {
     }
 }
 
+impl crate::StackUnwinder for Unwinder {
+    type Cursor<'a> = Cursor;
+
+    fn cursor(&self, thread: &crate::Thread) -> Result<Cursor> {
+        Unwinder::cursor(self, thread)
+    }
+}
+
+/// Registers are numbered the same way in libunwind and DWARF on the architectures libunwind
+/// is used on
+impl crate::StackCursor for Cursor {
+    fn ip(&self) -> Result<u64> {
+        Cursor::ip(self)
+    }
+
+    fn sp(&self) -> Result<u64> {
+        Cursor::sp(self)
+    }
+
+    fn register(&self, register: u16) -> Result<u64> {
+        unsafe { Cursor::register(self, register as i32) }
+    }
+}
+
 impl Iterator for Cursor {
     type Item = Result<u64>;
 
     fn next(&mut self) -> Option<Result<u64>> {
+        if let Some(frame_pointer) = self.frame_pointer.as_mut() {
+            return frame_pointer.next();
+        }
+
         // we need to return the initial stack frame, so only call unw_step if
         // this isn't the first frame
         if !self.initial_frame {
@@ -165,8 +244,10 @@ impl Iterator for Cursor {
 
 impl Drop for Cursor {
     fn drop(&mut self) {
-        unsafe {
-            _UPT_destroy(self.upt);
+        if !self.upt.is_null() {
+            unsafe {
+                _UPT_destroy(self.upt);
+            }
         }
     }
 }
@@ -274,6 +355,54 @@ extern "C" {
     fn set_caching_policy(spc: unw_addr_space_t, policy: unw_caching_policy_t) -> c_int;
 }
 
+#[cfg(target_arch = "riscv64")]
+extern "C" {
+    #[link_name = "_Uriscv_create_addr_space"]
+    #[allow(improper_ctypes)]
+    fn create_addr_space(acc: *mut unw_accessors_t, byteorder: c_int) -> unw_addr_space_t;
+    #[link_name = "_Uriscv_destroy_addr_space"]
+    fn destroy_addr_space(addr: unw_addr_space_t) -> c_void;
+    #[link_name = "_Uriscv_init_remote"]
+    fn init_remote(cursor: *mut unw_cursor_t, addr: unw_addr_space_t, ptr: *mut c_void) -> c_int;
+    #[link_name = "_Uriscv_get_reg"]
+    fn get_reg(cursor: *mut unw_cursor_t, reg: unw_regnum_t, val: *mut unw_word_t) -> c_int;
+    #[link_name = "_Uriscv_step"]
+    fn step(cursor: *mut unw_cursor_t) -> c_int;
+    #[link_name = "_Uriscv_get_proc_name"]
+    fn get_proc_name(
+        cursor: *mut unw_cursor,
+        buffer: *mut c_char,
+        len: size_t,
+        offset: *mut unw_word_t,
+    ) -> c_int;
+    #[link_name = "_Uriscv_set_caching_policy"]
+    fn set_caching_policy(spc: unw_addr_space_t, policy: unw_caching_policy_t) -> c_int;
+}
+
+#[cfg(target_arch = "loongarch64")]
+extern "C" {
+    #[link_name = "_Uloongarch64_create_addr_space"]
+    #[allow(improper_ctypes)]
+    fn create_addr_space(acc: *mut unw_accessors_t, byteorder: c_int) -> unw_addr_space_t;
+    #[link_name = "_Uloongarch64_destroy_addr_space"]
+    fn destroy_addr_space(addr: unw_addr_space_t) -> c_void;
+    #[link_name = "_Uloongarch64_init_remote"]
+    fn init_remote(cursor: *mut unw_cursor_t, addr: unw_addr_space_t, ptr: *mut c_void) -> c_int;
+    #[link_name = "_Uloongarch64_get_reg"]
+    fn get_reg(cursor: *mut unw_cursor_t, reg: unw_regnum_t, val: *mut unw_word_t) -> c_int;
+    #[link_name = "_Uloongarch64_step"]
+    fn step(cursor: *mut unw_cursor_t) -> c_int;
+    #[link_name = "_Uloongarch64_get_proc_name"]
+    fn get_proc_name(
+        cursor: *mut unw_cursor,
+        buffer: *mut c_char,
+        len: size_t,
+        offset: *mut unw_word_t,
+    ) -> c_int;
+    #[link_name = "_Uloongarch64_set_caching_policy"]
+    fn set_caching_policy(spc: unw_addr_space_t, policy: unw_caching_policy_t) -> c_int;
+}
+
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match *self {
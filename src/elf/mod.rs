@@ -0,0 +1,31 @@
+//! Symbolication of the ELF binaries loaded into a process, along with stack unwinding
+//! through libunwind's ptrace accessors. This is shared by linux and freebsd, with anything
+//! that differs between them (like how to open a file mapped into another process) going
+//! through the `Process` of each platform.
+#[cfg(use_libunwind)]
+mod breakpad;
+#[cfg(use_libunwind)]
+mod debug_file;
+#[cfg(all(use_libunwind, feature = "debuginfod"))]
+mod debuginfod;
+mod jitdump;
+#[cfg(use_libunwind)]
+pub mod libunwind;
+mod perf_map;
+#[cfg(use_libunwind)]
+mod symbol_cache;
+#[cfg(use_libunwind)]
+mod symbolication;
+
+#[cfg(use_libunwind)]
+pub use self::breakpad::BreakpadSymbols;
+#[cfg(all(use_libunwind, feature = "debuginfod"))]
+pub use self::debuginfod::Debuginfod;
+pub use self::jitdump::{JitDump, JitFunction, JitLine};
+#[cfg(use_libunwind)]
+pub use self::libunwind::Unwinder;
+pub use self::perf_map::{PerfMap, PerfMapEntry};
+#[cfg(use_libunwind)]
+pub use self::symbol_cache::set_symbol_cache_directory;
+#[cfg(use_libunwind)]
+pub use self::symbolication::*;
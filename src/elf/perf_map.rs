@@ -0,0 +1,171 @@
+//! Symbols for JIT compiled code, read from the /tmp/perf-<pid>.map files that runtimes like
+//! V8 (--perf-basic-prof), the JVM (perf-map-agent) and .NET (DOTNET_PerfMapEnabled) write.
+//!
+//! Each line of a perf map has the form `START SIZE name`, with START and SIZE in hex. Code
+//! can be recompiled at the same address, so later entries take precedence over earlier ones.
+use std::path::PathBuf;
+
+use crate::{Error, Pid};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PerfMapEntry {
+    pub address: u64,
+    pub size: u64,
+    pub name: String,
+}
+
+impl PerfMapEntry {
+    pub fn contains(&self, addr: u64) -> bool {
+        addr >= self.address && addr < self.address.saturating_add(self.size)
+    }
+}
+
+/// The JIT symbols from a perf map file, sorted by address
+#[derive(Debug, Default)]
+pub struct PerfMap {
+    entries: Vec<PerfMapEntry>,
+}
+
+impl PerfMap {
+    /// Parses the contents of a perf map file, skipping any malformed lines
+    pub fn parse(contents: &str) -> PerfMap {
+        let mut entries: Vec<PerfMapEntry> = contents
+            .lines()
+            .filter_map(|line| {
+                let mut parts = line.trim().splitn(3, ' ');
+                let address = parse_hex(parts.next()?)?;
+                let size = parse_hex(parts.next()?)?;
+                let name = parts.next()?.trim();
+                Some(PerfMapEntry {
+                    address,
+                    size,
+                    name: name.to_owned(),
+                })
+            })
+            .collect();
+
+        // the sort is stable, so when code is recompiled at the same address the newest entry
+        // ends up last - and is the one that dedup_by keeps
+        entries.sort_by_key(|entry| entry.address);
+        entries.dedup_by(|later, earlier| {
+            if later.address == earlier.address {
+                std::mem::swap(later, earlier);
+                true
+            } else {
+                false
+            }
+        });
+        PerfMap { entries }
+    }
+
+    /// Loads the perf map for a process, returning None if it hasn't written one
+    pub fn load(pid: Pid) -> Result<Option<PerfMap>, Error> {
+        let path = match perf_map_path(pid) {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        let contents = std::fs::read(path)?;
+        Ok(Some(PerfMap::parse(&String::from_utf8_lossy(&contents))))
+    }
+
+    /// Returns the JIT symbol containing an address
+    pub fn find(&self, addr: u64) -> Option<&PerfMapEntry> {
+        let index = self.entries.partition_point(|entry| entry.address <= addr);
+        let entry = self.entries.get(index.checked_sub(1)?)?;
+        if entry.contains(addr) {
+            Some(entry)
+        } else {
+            None
+        }
+    }
+
+    pub fn entries(&self) -> &[PerfMapEntry] {
+        &self.entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// Returns the path to the perf map of a process if it exists. Processes in a container
+/// write the map to /tmp inside their own mount namespace, named after their pid inside
+/// their pid namespace - so look for it through /proc/pid/root using that pid.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn perf_map_path(pid: Pid) -> Option<PathBuf> {
+    let nspid = namespace_pid(pid).unwrap_or(pid);
+    let candidates = [
+        PathBuf::from(format!("/proc/{}/root/tmp/perf-{}.map", pid, nspid)),
+        PathBuf::from(format!("/tmp/perf-{}.map", pid)),
+    ];
+    candidates.into_iter().find(|path| path.exists())
+}
+
+/// Returns the path to the perf map of a process if it exists. FreeBSD doesn't have an
+/// equivalent of /proc/pid/root, so this only looks in /tmp.
+#[cfg(target_os = "freebsd")]
+pub fn perf_map_path(pid: Pid) -> Option<PathBuf> {
+    let path = PathBuf::from(format!("/tmp/perf-{}.map", pid));
+    path.exists().then_some(path)
+}
+
+/// Returns the pid of a process inside the innermost pid namespace it belongs to
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn namespace_pid(pid: Pid) -> Option<Pid> {
+    let status = std::fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+    parse_namespace_pid(&status)
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn parse_namespace_pid(status: &str) -> Option<Pid> {
+    let line = status.lines().find(|line| line.starts_with("NSpid:"))?;
+    line["NSpid:".len()..]
+        .split_whitespace()
+        .last()?
+        .parse()
+        .ok()
+}
+
+fn parse_hex(value: &str) -> Option<u64> {
+    let value = value.strip_prefix("0x").unwrap_or(value);
+    u64::from_str_radix(value, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_perf_map() {
+        let map = PerfMap::parse(
+            "3a7f2c0 40 LazyCompile:~foo /app/index.js:10\n\
+             0x3a7f300 20 Builtin:ArgumentsAdaptorTrampoline\n\
+             not a valid line\n\
+             3a7f2c0 60 LazyCompile:*foo /app/index.js:10\n",
+        );
+        assert_eq!(map.len(), 2);
+        assert_eq!(
+            map.find(0x3a7f2d0).map(|e| e.name.as_str()),
+            Some("LazyCompile:*foo /app/index.js:10")
+        );
+        assert_eq!(
+            map.find(0x3a7f300).map(|e| e.name.as_str()),
+            Some("Builtin:ArgumentsAdaptorTrampoline")
+        );
+        assert!(map.find(0x3a7f320).is_none());
+        assert!(map.find(0x1000).is_none());
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[test]
+    fn test_parse_namespace_pid() {
+        let status = "Name:\tnode\nTgid:\t12345\nNSpid:\t12345\t7\n";
+        assert_eq!(parse_namespace_pid(status), Some(7));
+        assert_eq!(parse_namespace_pid("Name:\tnode\n"), None);
+    }
+}
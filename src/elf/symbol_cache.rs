@@ -0,0 +1,166 @@
+//! Persists the symbol tables read out of each binary to a directory on disk, keyed by
+//! build-id. Short lived tools that attach to a process, take a stack trace and exit
+//! otherwise pay the cost of reading and sorting every symbol in every mapped library on
+//! each run.
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+use log::{debug, warn};
+
+const MAGIC: &[u8; 8] = b"RPSYMBS1";
+
+lazy_static! {
+    static ref DIRECTORY: Mutex<Option<PathBuf>> = Mutex::new(None);
+}
+
+/// (address, size, name) for each symbol, sorted by address
+pub type SymbolTable = Vec<(u64, u64, String)>;
+
+/// The symbol and dynamic symbol tables of a binary
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct CachedSymbols {
+    pub symbols: SymbolTable,
+    pub dynamic_symbols: SymbolTable,
+}
+
+impl CachedSymbols {
+    fn write<W: Write>(&self, mut w: W) -> std::io::Result<()> {
+        w.write_all(MAGIC)?;
+        for table in [&self.symbols, &self.dynamic_symbols] {
+            w.write_all(&(table.len() as u64).to_le_bytes())?;
+            for (address, size, name) in table {
+                w.write_all(&address.to_le_bytes())?;
+                w.write_all(&size.to_le_bytes())?;
+                w.write_all(&(name.len() as u64).to_le_bytes())?;
+                w.write_all(name.as_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    fn read<R: Read>(mut r: R) -> std::io::Result<CachedSymbols> {
+        let mut magic = [0_u8; 8];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "not a symbol cache file",
+            ));
+        }
+        let read_u64 = |r: &mut R| -> std::io::Result<u64> {
+            let mut bytes = [0_u8; 8];
+            r.read_exact(&mut bytes)?;
+            Ok(u64::from_le_bytes(bytes))
+        };
+        let read_table = |r: &mut R| -> std::io::Result<SymbolTable> {
+            let count = read_u64(r)?;
+            let mut table = Vec::new();
+            for _ in 0..count {
+                let address = read_u64(r)?;
+                let size = read_u64(r)?;
+                let len = read_u64(r)?;
+                let mut name = Vec::new();
+                r.take(len).read_to_end(&mut name)?;
+                if name.len() as u64 != len {
+                    return Err(std::io::ErrorKind::UnexpectedEof.into());
+                }
+                let name = String::from_utf8(name)
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+                table.push((address, size, name));
+            }
+            Ok(table)
+        };
+        let symbols = read_table(&mut r)?;
+        let dynamic_symbols = read_table(&mut r)?;
+        Ok(CachedSymbols {
+            symbols,
+            dynamic_symbols,
+        })
+    }
+}
+
+fn path(directory: &Path, build_id: &[u8]) -> PathBuf {
+    let name: String = build_id.iter().map(|b| format!("{:02x}", b)).collect();
+    directory.join(format!("{}.symbols", name))
+}
+
+/// Writes to a temporary file first, so that concurrent readers never see a partial file
+fn write_file(path: &Path, symbols: &CachedSymbols) -> std::io::Result<()> {
+    let temp = path.with_extension(format!("tmp{}", std::process::id()));
+    let mut file = std::io::BufWriter::new(std::fs::File::create(&temp)?);
+    symbols.write(&mut file)?;
+    file.flush()?;
+    drop(file);
+    std::fs::rename(&temp, path)
+}
+
+/// Returns the symbols stored for a binary with the given build-id, if the on-disk cache is
+/// enabled and has them
+pub fn get(build_id: &[u8]) -> Option<CachedSymbols> {
+    let path = path(DIRECTORY.lock().unwrap().as_ref()?, build_id);
+    let file = std::fs::File::open(&path).ok()?;
+    match CachedSymbols::read(std::io::BufReader::new(file)) {
+        Ok(symbols) => {
+            debug!("loaded symbols from {}", path.display());
+            Some(symbols)
+        }
+        Err(e) => {
+            warn!("failed to read symbol cache {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+/// Stores the symbols for a binary, if the on-disk cache is enabled
+pub fn insert(build_id: &[u8], symbols: &CachedSymbols) {
+    let path = match DIRECTORY.lock().unwrap().as_ref() {
+        Some(directory) => path(directory, build_id),
+        None => return,
+    };
+    if let Err(e) = write_file(&path, symbols) {
+        warn!("failed to write symbol cache {}: {}", path.display(), e);
+    }
+}
+
+/// Sets a directory to persist symbol tables in, so that they can be reused across runs.
+/// Passing None disables the cache.
+pub fn set_symbol_cache_directory(directory: Option<PathBuf>) -> std::io::Result<()> {
+    if let Some(directory) = &directory {
+        std::fs::create_dir_all(directory)?;
+    }
+    *DIRECTORY.lock().unwrap() = directory;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serialize() {
+        let symbols = CachedSymbols {
+            symbols: vec![
+                (0x1000, 0x20, "foo".to_owned()),
+                (0x1020, 8, "bär".to_owned()),
+            ],
+            dynamic_symbols: vec![(0x2000, 0, "malloc".to_owned())],
+        };
+        let mut buffer = Vec::new();
+        symbols.write(&mut buffer).unwrap();
+        assert_eq!(CachedSymbols::read(buffer.as_slice()).unwrap(), symbols);
+
+        buffer.truncate(buffer.len() - 1);
+        assert!(CachedSymbols::read(buffer.as_slice()).is_err());
+        assert!(CachedSymbols::read(&b"RPUNWND1"[..]).is_err());
+    }
+
+    #[test]
+    fn test_path() {
+        assert_eq!(
+            path(Path::new("/tmp/cache"), &[0x12, 0xab]),
+            PathBuf::from("/tmp/cache/12ab.symbols")
+        );
+    }
+}
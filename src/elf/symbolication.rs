@@ -0,0 +1,898 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use log::{debug, error, info, trace, warn};
+use memmap2::Mmap;
+
+use crate::{
+    AddressRegion, DemangleOptions, Error, Frames, Module, ModuleId, Pid, Process, Protection,
+    StackFrame, UnknownAddressHandler,
+};
+use addr2line::Loader;
+use goblin;
+use goblin::elf::program_header::*;
+use object::{self, Object, ObjectSegment, ObjectSymbol};
+
+use super::breakpad::{breakpad_id, breakpad_path, BreakpadSymbols};
+use super::debug_file::find_debug_file;
+#[cfg(feature = "debuginfod")]
+use super::debuginfod::Debuginfod;
+use super::jitdump::{jitdump_path, JitDump};
+use super::perf_map::{perf_map_path, PerfMap};
+use super::symbol_cache::{self, CachedSymbols};
+use crate::ProcessMemory;
+
+pub struct Symbolicator {
+    binaries: BTreeMap<u64, BinaryInfo>,
+    /// the process to load binaries from, or None if created from a fixed list of modules
+    process: Option<Process>,
+    /// symbols for JIT compiled code, along with the size of the file they were loaded from
+    perf_map: RefCell<Option<(u64, PerfMap)>>,
+    jitdump: RefCell<Option<(u64, JitDump)>>,
+    jitdump_path: Option<PathBuf>,
+    demangle: DemangleOptions,
+    breakpad_directory: Option<PathBuf>,
+    #[cfg(feature = "debuginfod")]
+    debuginfod: Option<Debuginfod>,
+    unknown_address: Option<UnknownAddressHandler>,
+    /// the mappings of the process, loaded when the unknown address handler needs them
+    maps: RefCell<Vec<proc_maps::MapRange>>,
+}
+
+impl Symbolicator {
+    pub fn new(pid: Pid) -> Result<Symbolicator, Error> {
+        let process = Process::new(pid)?;
+        let mut ret = Symbolicator {
+            binaries: BTreeMap::new(),
+            process: Some(process),
+            perf_map: RefCell::new(None),
+            jitdump: RefCell::new(None),
+            jitdump_path: None,
+            demangle: DemangleOptions::default(),
+            breakpad_directory: None,
+            #[cfg(feature = "debuginfod")]
+            debuginfod: Debuginfod::from_env(),
+            unknown_address: None,
+            maps: RefCell::new(Vec::new()),
+        };
+        ret.reload()?;
+        Ok(ret)
+    }
+
+    /// Creates a symbolicator for a target that isn't a local process, like a core dump or
+    /// a remote agent, from the list of modules it has loaded. The filename of each module
+    /// needs to be a path to a copy of the binary that can be opened locally.
+    pub fn with_modules(modules: &[Module]) -> Symbolicator {
+        let binaries = modules
+            .iter()
+            .map(|module| {
+                let build_id = match &module.id {
+                    Some(ModuleId::BuildId(build_id)) => Some(build_id.clone()),
+                    _ => None,
+                };
+                let binary = BinaryInfo {
+                    offset: module.bias,
+                    address: module.address,
+                    size: module.size,
+                    filename: module.filename.clone(),
+                    path: Some(PathBuf::from(&module.filename)),
+                    build_id,
+                    symbols: RefCell::new(None),
+                };
+                (module.address + module.size, binary)
+            })
+            .collect();
+        Symbolicator {
+            binaries,
+            process: None,
+            perf_map: RefCell::new(None),
+            jitdump: RefCell::new(None),
+            jitdump_path: None,
+            demangle: DemangleOptions::default(),
+            breakpad_directory: None,
+            #[cfg(feature = "debuginfod")]
+            debuginfod: Debuginfod::from_env(),
+            unknown_address: None,
+            maps: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Reloads the list of binaries in the process. Symbolicators created with
+    /// `with_modules` have nothing to reload.
+    pub fn reload(&mut self) -> Result<(), Error> {
+        let process = match self.process.as_ref() {
+            Some(process) => process,
+            None => return Ok(()),
+        };
+        info!("reloading process binaries");
+        self.jitdump_path = jitdump_path(process);
+        self.maps.get_mut().clear();
+
+        // Get shared libraries from virtual memory mapped files
+        let maps = &proc_maps::get_process_maps(process.pid)?;
+        let shared_maps = maps
+            .iter()
+            .filter(|m| m.is_exec() && !m.is_write() && m.is_read());
+
+        // Open them up and parse etc
+        for m in shared_maps {
+            // Get the filename if it exists from the map
+            let filename = match m.filename() {
+                Some(f) => f,
+                None => continue,
+            };
+
+            // TODO: probably also want to check if the filename/size is the same
+            let address_key = (m.start() + m.size()) as u64;
+            if self.binaries.contains_key(&address_key) {
+                debug!("skipping {}", filename.display());
+                continue;
+            }
+            info!("loading debug info from {}", filename.display());
+
+            // Memory-map the file, special casing [vdso] regions
+            let file;
+            let mmapped_file;
+            let vdso_data;
+
+            // the process might be running in a different mount namespace, so let the
+            // platform find a path we can open the file from (through /proc/pid/root on linux)
+            let path = process.resolve_path(filename, m.start(), m.start() + m.size());
+
+            let buffer = if let Some(path) = path.as_ref() {
+                file = File::open(path)?;
+                mmapped_file = unsafe { Mmap::map(&file)? };
+                &mmapped_file[..]
+            } else if filename != std::path::PathBuf::from("[vsyscall]") {
+                // if the filename doesn't exist, its' almost certainly the vdso section
+                // read from the the target processes memory
+                vdso_data = process.copy(m.start(), m.size())?;
+                &vdso_data
+            } else {
+                // vsyscall region, can be ignored, but lets not keep on trying to do this
+                info!("skipping {} region", filename.display());
+
+                // insert a stub for [vsyscall] so that we don't continually try to load it etc
+                self.binaries.insert(
+                    address_key,
+                    BinaryInfo {
+                        offset: 0,
+                        address: m.start() as u64,
+                        size: m.size() as u64,
+                        filename: filename.display().to_string(),
+                        path: None,
+                        build_id: None,
+                        symbols: RefCell::new(None),
+                    },
+                );
+                continue;
+            };
+
+            debug!(
+                "loading file {} 0x{:X} 0x{:X}",
+                filename.display(),
+                m.start(),
+                buffer.len()
+            );
+            match goblin::Object::parse(&buffer) {
+                Ok(goblin::Object::Elf(elf)) => {
+                    trace!("filename {} elf {:#?}", filename.display(), elf);
+
+                    let program_header = elf
+                        .program_headers
+                        .iter()
+                        .find(|ref header| header.p_type == PT_LOAD && header.p_flags & PF_X != 0);
+
+                    let obj_base = match program_header {
+                        Some(hdr) => {
+                            // Don't panic if v_addr/start is messed up
+                            // (https://github.com/benfred/py-spy/issues/183)
+                            if hdr.p_vaddr > m.start() as u64 {
+                                warn!(
+                                    "Failed to load {} for symbols: v_addr {} is past start {}",
+                                    filename.display(),
+                                    hdr.p_vaddr,
+                                    m.start()
+                                );
+                                continue;
+                            }
+                            m.start() as u64 - hdr.p_vaddr + hdr.p_vaddr % hdr.p_align
+                        }
+                        None => {
+                            warn!(
+                                "Failed to find executable PT_LOAD header in {}",
+                                filename.display()
+                            );
+                            continue;
+                        }
+                    };
+
+                    // the map key is the end address of this filename, which lets us do a relatively efficient range
+                    // based lookup of the binary
+                    self.binaries.insert(
+                        address_key,
+                        BinaryInfo {
+                            offset: obj_base,
+                            address: m.start() as u64,
+                            size: m.size() as u64,
+                            filename: filename.display().to_string(),
+                            path,
+                            build_id: build_id(buffer),
+                            symbols: RefCell::new(None),
+                        },
+                    );
+                }
+                Ok(_) => {
+                    warn!("unknown binary type for {}", filename.display());
+                    continue;
+                }
+                Err(e) => {
+                    warn!("Failed to parse {}: {:?}", filename.display(), e);
+                    continue;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets how function names are demangled in the frames returned by symbolicate
+    pub fn set_demangle_options(&mut self, options: DemangleOptions) {
+        self.demangle = options;
+    }
+
+    /// Sets a directory of Breakpad .sym files to symbolicate from, laid out as
+    /// `<name>/<id>/<name>.sym`. Binaries that have a .sym file in this directory are
+    /// symbolicated from it instead of from their own symbols and debug info.
+    pub fn set_breakpad_directory(&mut self, directory: Option<PathBuf>) {
+        self.breakpad_directory = directory;
+        for binary in self.binaries.values() {
+            binary.symbols.borrow_mut().take();
+        }
+    }
+
+    /// Sets the debuginfod client used to fetch debug info for stripped binaries. This
+    /// defaults to a client for the servers in DEBUGINFOD_URLS, if that is set.
+    #[cfg(feature = "debuginfod")]
+    pub fn set_debuginfod(&mut self, debuginfod: Option<Debuginfod>) {
+        self.debuginfod = debuginfod;
+    }
+
+    /// Sets a handler for the addresses that aren't in any binary or in the JIT symbols the
+    /// process writes out, which can supply the frames for them instead of symbolication
+    /// failing with `Error::NoBinaryForAddress`
+    pub fn set_unknown_address_handler(&mut self, handler: Option<UnknownAddressHandler>) {
+        self.unknown_address = handler;
+    }
+
+    pub fn symbolicate(
+        &self,
+        addr: u64,
+        line_info: bool,
+        callback: &mut dyn FnMut(&StackFrame),
+    ) -> Result<(), Error> {
+        if !self.demangle.enabled() {
+            return self.lookup(addr, line_info, callback);
+        }
+        self.lookup(addr, line_info, &mut |frame| {
+            callback(&self.demangle.frame(frame))
+        })
+    }
+
+    /// Symbolicates many addresses at once, returning the frames for each in the same order
+    /// as the addresses. The addresses are resolved in sorted order so that the binary
+    /// containing them is only looked up once per run of addresses that it contains.
+    pub fn symbolicate_many(&self, addrs: &[u64], line_info: bool) -> Vec<Frames> {
+        let mut order: Vec<usize> = (0..addrs.len()).collect();
+        order.sort_unstable_by_key(|&i| addrs[i]);
+
+        let mut results: Vec<Option<Frames>> = addrs.iter().map(|_| None).collect();
+        let mut binary: Option<&BinaryInfo> = None;
+        let mut previous: Option<usize> = None;
+        for i in order {
+            let addr = addrs[i];
+            // repeated addresses (like the callers shared by every stack) only need resolving once
+            if let Some(Some(Ok(frames))) =
+                previous.filter(|&p| addrs[p] == addr).map(|p| &results[p])
+            {
+                results[i] = Some(Ok(frames.clone()));
+                continue;
+            }
+            if !binary.is_some_and(|binary| binary.contains(addr)) {
+                binary = self.get_binary(addr);
+            }
+            let mut frames = Vec::new();
+            let result = self.lookup_binary(binary, addr, line_info, &mut |frame| {
+                if self.demangle.enabled() {
+                    frames.push(self.demangle.frame(frame));
+                } else {
+                    frames.push(frame.clone());
+                }
+            });
+            results[i] = Some(result.map(|()| frames));
+            previous = Some(i);
+        }
+        results.into_iter().flatten().collect()
+    }
+
+    fn lookup(
+        &self,
+        addr: u64,
+        line_info: bool,
+        callback: &mut dyn FnMut(&StackFrame),
+    ) -> Result<(), Error> {
+        self.lookup_binary(self.get_binary(addr), addr, line_info, callback)
+    }
+
+    fn lookup_binary(
+        &self,
+        binary: Option<&BinaryInfo>,
+        addr: u64,
+        line_info: bool,
+        callback: &mut dyn FnMut(&StackFrame),
+    ) -> Result<(), Error> {
+        let binary = match binary {
+            Some(binary) => binary,
+            None => {
+                // addresses in anonymous memory are often JIT compiled code
+                if let Some(frame) = self.jitdump_frame(addr) {
+                    callback(&frame);
+                    return Ok(());
+                }
+                if let Some(frame) = self.perf_map_frame(addr) {
+                    callback(&frame);
+                    return Ok(());
+                }
+                if let Some(handler) = self.unknown_address.as_ref() {
+                    if let Some(frames) = handler(addr, self.address_region(addr).as_ref()) {
+                        for frame in frames.iter() {
+                            callback(frame);
+                        }
+                        return Ok(());
+                    }
+                }
+                return Err(Error::NoBinaryForAddress(addr));
+            }
+        };
+        if let Some(path) = binary.path.as_ref() {
+            let symbols = self.symbols(binary, path);
+            match symbols.as_ref() {
+                Some(Ok(Symbols::Native(symbols))) => {
+                    symbols.symbolicate(addr, line_info, callback)
+                }
+                Some(Ok(Symbols::Breakpad(symbols, base))) => {
+                    symbols.symbolicate(addr - base, addr, &binary.filename, line_info, callback);
+                    Ok(())
+                }
+                _ => {
+                    // we probably failed to load the symbols (maybe goblin v0.15 dependency causing error
+                    // in gimli/object crate). Rather than fail add a stub
+                    callback(&StackFrame {
+                        line: None,
+                        column: None,
+                        addr,
+                        function: None,
+                        filename: None,
+                        module: binary.filename.clone(),
+                        module_offset: Some(addr.wrapping_sub(binary.offset)),
+                        inlined: false,
+                    });
+                    Ok(())
+                }
+            }
+        } else {
+            // TODO: allow symbolication code to access vdso data
+            callback(&StackFrame {
+                line: None,
+                column: None,
+                addr,
+                function: None,
+                filename: None,
+                module: binary.filename.clone(),
+                module_offset: Some(addr.wrapping_sub(binary.offset)),
+                inlined: false,
+            });
+            Ok(())
+        }
+    }
+
+    /// Looks up an address in the jitdump file the process is writing, which unlike the perf
+    /// map can also have line numbers
+    fn jitdump_frame(&self, addr: u64) -> Option<StackFrame> {
+        let path = self.jitdump_path.as_ref()?;
+        let len = std::fs::metadata(path).ok()?.len();
+        let mut jitdump = self.jitdump.borrow_mut();
+        if jitdump.as_ref().map(|(loaded, _)| *loaded) != Some(len) {
+            info!("loading JIT symbols from {}", path.display());
+            match JitDump::load(path) {
+                Ok(dump) => *jitdump = Some((len, dump)),
+                Err(e) => {
+                    warn!("Failed to load {}: {}", path.display(), e);
+                    return None;
+                }
+            }
+        }
+        let function = jitdump.as_ref()?.1.find(addr)?;
+        let line = function.line(addr);
+        Some(StackFrame {
+            line: line.map(|line| line.line),
+            column: None,
+            addr,
+            function: Some(function.name.clone()),
+            filename: line.map(|line| line.filename.clone()),
+            module: path.display().to_string(),
+            module_offset: None,
+            inlined: false,
+        })
+    }
+
+    /// Looks up an address in the perf map written by the JIT runtime of the process. JIT
+    /// runtimes append to the map as they compile code, so this reloads it whenever it grows
+    fn perf_map_frame(&self, addr: u64) -> Option<StackFrame> {
+        let path = perf_map_path(self.process.as_ref()?.pid)?;
+        let len = std::fs::metadata(&path).ok()?.len();
+        let mut perf_map = self.perf_map.borrow_mut();
+        if perf_map.as_ref().map(|(loaded, _)| *loaded) != Some(len) {
+            info!("loading JIT symbols from {}", path.display());
+            let contents = std::fs::read(&path).ok()?;
+            *perf_map = Some((len, PerfMap::parse(&String::from_utf8_lossy(&contents))));
+        }
+        let entry = perf_map.as_ref()?.1.find(addr)?;
+        Some(StackFrame {
+            line: None,
+            column: None,
+            addr,
+            function: Some(entry.name.clone()),
+            filename: None,
+            module: path.display().to_string(),
+            module_offset: None,
+            inlined: false,
+        })
+    }
+
+    /// Returns the mapping that contains an address. JIT runtimes map new code as they run,
+    /// so the mappings are read again when the address isn't in the ones already loaded.
+    fn address_region(&self, addr: u64) -> Option<AddressRegion> {
+        let find = |maps: &[proc_maps::MapRange]| {
+            let map = maps.iter().find(|map| {
+                map.start() as u64 <= addr && addr < (map.start() + map.size()) as u64
+            })?;
+            Some(AddressRegion {
+                start: map.start() as u64,
+                size: map.size() as u64,
+                protection: Protection {
+                    read: map.is_read(),
+                    write: map.is_write(),
+                    execute: map.is_exec(),
+                },
+                filename: map.filename().map(|f| f.display().to_string()),
+            })
+        };
+        let mut maps = self.maps.borrow_mut();
+        if let Some(region) = find(&maps) {
+            return Some(region);
+        }
+        *maps = proc_maps::get_process_maps(self.process.as_ref()?.pid).ok()?;
+        find(&maps)
+    }
+
+    /// Returns the binaries loaded by the process, along with their build-ids
+    pub fn modules(&self) -> Vec<Module> {
+        self.binaries
+            .values()
+            .map(|binary| Module {
+                filename: binary.filename.clone(),
+                address: binary.address,
+                size: binary.size,
+                bias: binary.offset,
+                id: binary.build_id.clone().map(ModuleId::BuildId),
+            })
+            .collect()
+    }
+
+    /// Returns the address of a symbol in the process, from the symbol tables (or Breakpad
+    /// symbols) of the first loaded binary that defines it. The address includes the load
+    /// bias of the binary, so can be read from directly.
+    pub fn find_symbol(&self, name: &str) -> Option<u64> {
+        self.binaries.values().find_map(|binary| {
+            let symbols = self.symbols(binary, binary.path.as_ref()?);
+            match symbols.as_ref()? {
+                Ok(Symbols::Native(symbols)) => symbols.find_symbol(name),
+                Ok(Symbols::Breakpad(symbols, base)) => Some(symbols.find_symbol(name)? + base),
+                Err(_) => None,
+            }
+        })
+    }
+
+    /// Returns the symbols for a binary, loading them the first time they're needed
+    fn symbols<'a>(
+        &self,
+        binary: &'a BinaryInfo,
+        path: &Path,
+    ) -> std::cell::RefMut<'a, Option<Result<Symbols, Error>>> {
+        let mut symbols = binary.symbols.borrow_mut();
+        if symbols.is_none() {
+            info!("loading symbols from {}", binary.filename);
+            *symbols = Some(self.load_symbols(binary, path));
+        }
+        symbols
+    }
+
+    fn load_symbols(&self, binary: &BinaryInfo, path: &Path) -> Result<Symbols, Error> {
+        if let Some(symbols) = self.breakpad_symbols(binary, path) {
+            return Ok(symbols);
+        }
+        let debug_file = self.debug_file(path, Path::new(&binary.filename));
+        Ok(Symbols::Native(Box::new(SymbolData::with_debug_file(
+            path,
+            debug_file.as_deref(),
+            &binary.filename,
+            binary.offset,
+        )?)))
+    }
+
+    /// Loads the Breakpad symbols for a binary, if there is a .sym file for it
+    fn breakpad_symbols(&self, binary: &BinaryInfo, path: &Path) -> Option<Symbols> {
+        let directory = self.breakpad_directory.as_ref()?;
+        let file = File::open(path).ok()?;
+        let map = unsafe { Mmap::map(&file).ok()? };
+        let object = object::File::parse(&*map).ok()?;
+        let id = breakpad_id(object.build_id().ok()??);
+        let name = Path::new(&binary.filename).file_name()?.to_str()?;
+        let sym_path = breakpad_path(directory, name, &id);
+        if !sym_path.exists() {
+            return None;
+        }
+        match BreakpadSymbols::load(&sym_path) {
+            Ok(symbols) => {
+                info!("using breakpad symbols from {}", sym_path.display());
+                // .sym addresses are relative to the lowest segment
+                let base = object.segments().map(|s| s.address()).min().unwrap_or(0);
+                Some(Symbols::Breakpad(symbols, binary.offset + base))
+            }
+            Err(e) => {
+                warn!("failed to load {}: {:?}", sym_path.display(), e);
+                None
+            }
+        }
+    }
+
+    /// Returns a separate file holding the debug info for a binary, if the binary itself
+    /// has been stripped of it
+    fn debug_file(&self, path: &Path, filename: &Path) -> Option<PathBuf> {
+        let file = File::open(path).ok()?;
+        let map = unsafe { Mmap::map(&file).ok()? };
+        let object = object::File::parse(&*map).ok()?;
+        if object.section_by_name(".debug_info").is_some() {
+            return None;
+        }
+
+        // the binary may have been opened through /proc/pid/map_files, so look for the files
+        // it links to relative to where it's mapped from in the process instead. These files
+        // aren't mapped themselves, so there's no address range to resolve them by
+        let resolve = |candidate: &Path| match self.process.as_ref() {
+            Some(process) => process.resolve_path(candidate, 0, 0),
+            None => Some(candidate.to_path_buf()),
+        };
+        if let Some(debug_file) = find_debug_file(&object, path, filename, &resolve) {
+            info!(
+                "using debug info for {} from {}",
+                path.display(),
+                debug_file.display()
+            );
+            return Some(debug_file);
+        }
+
+        #[cfg(feature = "debuginfod")]
+        if let Some(debuginfod) = self.debuginfod.as_ref() {
+            if let Some(build_id) = object.build_id().ok().flatten() {
+                if let Some(debug_file) = debuginfod.debuginfo(build_id) {
+                    info!(
+                        "using debug info for {} from {}",
+                        path.display(),
+                        debug_file.display()
+                    );
+                    return Some(debug_file);
+                }
+            }
+        }
+        None
+    }
+
+    fn get_binary(&self, addr: u64) -> Option<&BinaryInfo> {
+        match self.binaries.range(addr..).next() {
+            Some((_, binary)) if binary.contains(addr) => Some(&binary),
+            Some(_) => None,
+            _ => None,
+        }
+    }
+}
+
+impl crate::SymbolResolver for Symbolicator {
+    fn reload(&mut self) -> Result<(), Error> {
+        Symbolicator::reload(self)
+    }
+
+    fn symbolicate(
+        &self,
+        addr: u64,
+        line_info: bool,
+        callback: &mut dyn FnMut(&StackFrame),
+    ) -> Result<(), Error> {
+        Symbolicator::symbolicate(self, addr, line_info, callback)
+    }
+
+    fn symbolicate_many(&self, addrs: &[u64], line_info: bool) -> Vec<Frames> {
+        Symbolicator::symbolicate_many(self, addrs, line_info)
+    }
+
+    fn modules(&self) -> Vec<Module> {
+        Symbolicator::modules(self)
+    }
+}
+
+pub struct SymbolData {
+    // Contains symbol info for a single binary
+    address_loader: Loader,
+    offset: u64,
+    symbols: Vec<(u64, u64, String)>,
+    dynamic_symbols: Vec<(u64, u64, String)>,
+    filename: String,
+}
+
+impl SymbolData {
+    pub fn new(filename: &str, offset: u64) -> Result<SymbolData, Error> {
+        SymbolData::with_path(Path::new(filename), filename, offset)
+    }
+
+    /// Loads symbols from the file at `path`, reporting frames from it as coming
+    /// from `filename`. These differ when the file had to be opened through
+    /// /proc/pid/root, since the target process is running in another namespace.
+    pub fn with_path(path: &Path, filename: &str, offset: u64) -> Result<SymbolData, Error> {
+        SymbolData::with_debug_file(path, None, filename, offset)
+    }
+
+    /// Loads symbols from the file at `path`, taking the debug info from `debug_file` instead
+    /// if the binary has been stripped and its debug info shipped separately
+    pub fn with_debug_file(
+        path: &Path,
+        debug_file: Option<&Path>,
+        filename: &str,
+        offset: u64,
+    ) -> Result<SymbolData, Error> {
+        info!("opening {} for symbols", path.display());
+
+        let file = File::open(path)?;
+        let map = unsafe { Mmap::map(&file)? };
+        let file = match object::File::parse(&*map) {
+            Ok(f) => f,
+            Err(e) => {
+                error!(
+                    "failed to parse file for symbolication {}: {:?}",
+                    filename, e
+                );
+                // return Err(gimli::Error::OffsetOutOfBounds.into());
+                return Err(Error::Other(
+                    "Failed to parse file for symbolication".to_string(),
+                ));
+            }
+        };
+
+        let address_loader = Loader::new(debug_file.unwrap_or(path)).map_err(|e| {
+            Error::Other(format!(
+                "Failed to get symbol context for {}: {:?}",
+                filename, e
+            ))
+        })?;
+
+        let build_id = file.build_id().ok().flatten();
+        let cached = match build_id {
+            Some(build_id) => symbol_cache::get(build_id),
+            None => None,
+        };
+        let CachedSymbols {
+            symbols,
+            dynamic_symbols,
+        } = match cached {
+            Some(cached) => cached,
+            None => {
+                let cached = read_symbol_tables(&file, debug_file);
+                if let Some(build_id) = build_id {
+                    symbol_cache::insert(build_id, &cached);
+                }
+                cached
+            }
+        };
+        Ok(SymbolData {
+            address_loader,
+            offset,
+            dynamic_symbols,
+            symbols,
+            filename: filename.to_owned(),
+        })
+    }
+
+    pub fn symbolicate(
+        &self,
+        addr: u64,
+        line_info: bool,
+        callback: &mut dyn FnMut(&StackFrame),
+    ) -> Result<(), Error> {
+        // get the address before relocations
+        let offset = addr - self.offset;
+
+        // if we are being asked for line information, sue gimli addr2line to look up the debug info
+        // (this is slow, and not necessary all the time which is why we are skipping)
+        if line_info {
+            // if we have debugging info, get the appropriate stack frames for the address
+            let mut frames = self
+                .address_loader
+                .find_frames(offset)
+                .map_err(symbolication_error(addr))?;
+
+            // addr2line returns a frame for each function inlined at this address, innermost
+            // first, followed by the function they were all inlined into
+            let mut resolved = Vec::new();
+            while let Some(frame) = frames.next().map_err(symbolication_error(addr))? {
+                let mut ret = StackFrame {
+                    line: None,
+                    column: None,
+                    filename: None,
+                    function: None,
+                    addr,
+                    module: self.filename.clone(),
+                    module_offset: Some(offset),
+                    inlined: true,
+                };
+                if let Some(func) = frame.function {
+                    ret.function = Some(
+                        func.raw_name()
+                            .map_err(symbolication_error(addr))?
+                            .to_string(),
+                    );
+                }
+                if let Some(loc) = frame.location {
+                    ret.line = loc.line.map(|x| x as u64);
+                    ret.column = loc.column.map(|x| x as u64);
+                    if let Some(file) = loc.file.as_ref() {
+                        ret.filename = Some(file.to_string());
+                    }
+                }
+                resolved.push(ret);
+            }
+
+            if let Some(outer) = resolved.last_mut() {
+                outer.inlined = false;
+                if outer.function.is_none() {
+                    outer.function = self.symbol_name(offset);
+                }
+                for frame in &resolved {
+                    callback(frame);
+                }
+                return Ok(());
+            }
+        }
+
+        // otherwise try getting the function name from the symbols
+        callback(&StackFrame {
+            line: None,
+            column: None,
+            filename: None,
+            function: self.symbol_name(offset),
+            addr,
+            module: self.filename.clone(),
+            module_offset: Some(offset),
+            inlined: false,
+        });
+        Ok(())
+    }
+
+    /// Returns the address in the process of a symbol defined by this binary
+    pub fn find_symbol(&self, name: &str) -> Option<u64> {
+        self.symbols
+            .iter()
+            .chain(self.dynamic_symbols.iter())
+            // undefined symbols (imports from other binaries) have an address of 0
+            .find(|sym| sym.2 == name && sym.0 != 0)
+            .map(|sym| sym.0 + self.offset)
+    }
+
+    /// Returns the name of the symbol containing an offset into the binary
+    fn symbol_name(&self, offset: u64) -> Option<String> {
+        if self.symbols.len() > 0 {
+            let symbol = match self.symbols.binary_search_by(|sym| sym.0.cmp(&offset)) {
+                Ok(i) => &self.symbols[i],
+                Err(i) => &self.symbols[if i > 0 { i - 1 } else { 0 }],
+            };
+            if offset >= symbol.0 && offset < (symbol.0 + symbol.1) {
+                return Some(symbol.2.clone());
+            }
+        }
+
+        if self.dynamic_symbols.len() > 0 {
+            let symbol = match self
+                .dynamic_symbols
+                .binary_search_by(|sym| sym.0.cmp(&offset))
+            {
+                Ok(i) => &self.dynamic_symbols[i],
+                Err(i) => &self.dynamic_symbols[if i > 0 { i - 1 } else { 0 }],
+            };
+            if offset >= symbol.0 && offset < (symbol.0 + symbol.1) {
+                return Some(symbol.2.clone());
+            }
+        }
+        None
+    }
+}
+
+/// Reads the build-id note of an ELF binary
+fn build_id(data: &[u8]) -> Option<Vec<u8>> {
+    let object = object::File::parse(data).ok()?;
+    Some(object.build_id().ok()??.to_vec())
+}
+
+/// Reads the symbol table of a separate debug info file
+fn load_symbols(path: &Path) -> Result<Vec<(u64, u64, String)>, Error> {
+    let file = File::open(path)?;
+    let map = unsafe { Mmap::map(&file)? };
+    let file = object::File::parse(&*map)
+        .map_err(|e| Error::Other(format!("Failed to parse {}: {}", path.display(), e)))?;
+    Ok(file
+        .symbols()
+        .filter_map(|sym| Some((sym.address(), sym.size(), sym.name().ok()?.to_string())))
+        .collect())
+}
+
+/// Reads and sorts the symbol tables of a binary
+fn read_symbol_tables(file: &object::File, debug_file: Option<&Path>) -> CachedSymbols {
+    let mut symbols = Vec::new();
+    for sym in file.symbols() {
+        if let Ok(name) = sym.name() {
+            symbols.push((sym.address(), sym.size(), name.to_string()));
+        }
+    }
+    // stripped binaries have no symbol table, but their separate debug info still does
+    if symbols.is_empty() {
+        if let Some(debug_file) = debug_file {
+            symbols = load_symbols(debug_file).unwrap_or_default();
+        }
+    }
+    symbols.sort_unstable_by(|a, b| a.cmp(&b));
+
+    let mut dynamic_symbols = Vec::new();
+    for sym in file.dynamic_symbols() {
+        if let Ok(name) = sym.name() {
+            dynamic_symbols.push((sym.address(), sym.size(), name.to_string()));
+        }
+    }
+    dynamic_symbols.sort_unstable_by(|a, b| a.cmp(&b));
+    CachedSymbols {
+        symbols,
+        dynamic_symbols,
+    }
+}
+
+// Contains info for a binary on how to unwind/symbolicate a stack trace
+struct BinaryInfo {
+    address: u64,
+    size: u64,
+    offset: u64,
+    filename: String,
+    // the path to open the binary from, which is None for the vdso
+    path: Option<PathBuf>,
+    build_id: Option<Vec<u8>>,
+    symbols: RefCell<Option<Result<Symbols, Error>>>,
+}
+
+enum Symbols {
+    Native(Box<SymbolData>),
+    /// Breakpad symbols, along with the address their relative addresses start from
+    Breakpad(BreakpadSymbols, u64),
+}
+
+impl BinaryInfo {
+    pub fn contains(&self, addr: u64) -> bool {
+        addr >= self.address && addr < (self.address + self.size)
+    }
+}
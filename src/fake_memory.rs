@@ -0,0 +1,52 @@
+//! Fake process memory for unit tests, so that code reading from another process (like the
+//! unwinders) can be run against a stack built by hand
+use crate::{Error, ProcessMemory};
+
+/// A run of bytes mapped at an address. Reading anything outside of it fails, the same way
+/// reading unmapped memory in a process does.
+pub(crate) struct FakeMemory {
+    address: usize,
+    data: Vec<u8>,
+}
+
+impl FakeMemory {
+    pub fn new(address: usize, data: Vec<u8>) -> FakeMemory {
+        FakeMemory { address, data }
+    }
+
+    /// Memory holding a run of words in native byte order, like a stack
+    pub fn from_words<W: Word>(address: usize, words: &[W]) -> FakeMemory {
+        let mut data = Vec::new();
+        for word in words {
+            word.append_to(&mut data);
+        }
+        FakeMemory::new(address, data)
+    }
+}
+
+impl ProcessMemory for FakeMemory {
+    fn read(&self, addr: usize, buf: &mut [u8]) -> Result<(), Error> {
+        let data = addr
+            .checked_sub(self.address)
+            .and_then(|offset| self.data.get(offset..offset.checked_add(buf.len())?))
+            .ok_or_else(|| Error::Other(format!("address {:#x} isn't mapped", addr)))?;
+        buf.copy_from_slice(data);
+        Ok(())
+    }
+}
+
+pub(crate) trait Word {
+    fn append_to(&self, data: &mut Vec<u8>);
+}
+
+macro_rules! word {
+    ($($t:ty),*) => {
+        $(impl Word for $t {
+            fn append_to(&self, data: &mut Vec<u8>) {
+                data.extend_from_slice(&self.to_ne_bytes());
+            }
+        })*
+    };
+}
+
+word!(u32, u64, usize);
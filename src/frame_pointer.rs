@@ -0,0 +1,109 @@
+//! A stack unwinder that only follows the chain of frame pointers. This doesn't need any
+//! unwind tables, which makes it several times faster than DWARF based unwinding, but it
+//! only produces correct stacks for code compiled with `-fno-omit-frame-pointer`.
+use crate::{Error, ProcessMemory};
+
+/// How to unwind the stacks of threads in a process
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum UnwindMode {
+    /// Use the unwind tables in each binary (.eh_frame, .debug_frame etc)
+    #[default]
+    Dwarf,
+    /// Only follow frame pointers
+    FramePointer,
+}
+
+// Each frame record holds the caller's frame pointer followed by the return address. On
+// riscv and loongarch the frame pointer points just past the record instead of at it.
+#[cfg(any(target_arch = "riscv64", target_arch = "loongarch64"))]
+const RECORD_OFFSET: u64 = 16;
+#[cfg(not(any(target_arch = "riscv64", target_arch = "loongarch64")))]
+const RECORD_OFFSET: u64 = 0;
+
+/// Iterates over the return addresses on a stack by following frame pointers, starting
+/// from the instruction pointer and frame pointer of a stopped thread.
+pub struct FramePointerCursor<P> {
+    memory: P,
+    ip: u64,
+    fp: u64,
+    initial_frame: bool,
+}
+
+impl<P: ProcessMemory> FramePointerCursor<P> {
+    pub fn new(memory: P, ip: u64, fp: u64) -> FramePointerCursor<P> {
+        FramePointerCursor {
+            memory,
+            ip,
+            fp,
+            initial_frame: true,
+        }
+    }
+
+    /// The instruction pointer of the current frame
+    pub fn ip(&self) -> u64 {
+        self.ip
+    }
+
+    /// The frame pointer of the current frame
+    pub fn fp(&self) -> u64 {
+        self.fp
+    }
+}
+
+impl<P: ProcessMemory> Iterator for FramePointerCursor<P> {
+    type Item = Result<u64, Error>;
+
+    fn next(&mut self) -> Option<Result<u64, Error>> {
+        if self.initial_frame {
+            self.initial_frame = false;
+            return if self.ip == 0 {
+                None
+            } else {
+                Some(Ok(self.ip))
+            };
+        }
+
+        // frame records are always pointer aligned, anything else means the chain is broken
+        let alignment = std::mem::size_of::<usize>() as u64 - 1;
+        let address = match self.fp.checked_sub(RECORD_OFFSET) {
+            Some(address) if self.fp != 0 && self.fp & alignment == 0 => address,
+            _ => return None,
+        };
+        let record: [usize; 2] = match self.memory.copy_struct(address as usize) {
+            Ok(record) => record,
+            Err(e) => return Some(Err(e)),
+        };
+        let (fp, ip) = (record[0] as u64, record[1] as u64);
+
+        // the stack grows down, so the frame pointers should be increasing. The outermost
+        // frame has a null frame pointer, but still has a valid return address
+        if ip == 0 || (fp != 0 && fp <= self.fp) {
+            return None;
+        }
+        self.fp = fp;
+        self.ip = ip;
+        Some(Ok(ip))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake_memory::FakeMemory;
+
+    #[test]
+    fn test_frame_pointer_cursor() {
+        let word = std::mem::size_of::<usize>();
+        let base = 0x1000 + RECORD_OFFSET as usize;
+        // two frame records, with the outermost frame having a null frame pointer
+        let mut stack = vec![0; RECORD_OFFSET as usize / word];
+        stack.extend_from_slice(&[base + 2 * word, 0x2222, 0, 0x3333]);
+        let stack = FakeMemory::from_words(0x1000, &stack);
+
+        let frames: Vec<u64> = FramePointerCursor::new(&stack, 0x1111, base as u64)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(frames, vec![0x1111, 0x2222, 0x3333]);
+    }
+}
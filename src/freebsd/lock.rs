@@ -4,15 +4,15 @@ use log::error;
 use std::io::Error as IoError;
 
 use super::ptrace;
-use super::Error;
+use super::{get_threads, Error, LockContainer, Thread, Tid};
 
-#[derive(Debug)]
 pub struct ProcessLock {
     pid: pid_t,
+    threads: Vec<Thread>,
 }
 
 impl ProcessLock {
-    pub fn new(pid: pid_t) -> Result<Self, Error> {
+    pub fn new(pid: pid_t, container: &LockContainer) -> Result<Self, Error> {
         ptrace::attach(pid)?;
         let mut wait_status = 0;
 
@@ -21,11 +21,29 @@ impl ProcessLock {
             WIFSTOPPED(wait_status)
         };
 
+        let mut lock = ProcessLock {
+            pid,
+            threads: Vec::new(),
+        };
+
         if !stopped {
             return Err(Error::IOError(IoError::last_os_error()));
         }
 
-        Ok(ProcessLock { pid })
+        // PT_ATTACH stops every thread in the process, so this list can't change
+        // while we hold the lock
+        lock.threads = get_threads(pid, container)?;
+        Ok(lock)
+    }
+
+    /// The threads that were suspended by this lock
+    pub fn threads(&self) -> &[Thread] {
+        &self.threads
+    }
+
+    /// The thread ids of the threads that were suspended by this lock
+    pub fn tids(&self) -> Vec<Tid> {
+        self.threads.iter().map(|thread| thread.tid).collect()
     }
 }
 
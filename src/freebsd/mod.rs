@@ -2,51 +2,125 @@ mod kinfo_proc;
 mod lock;
 mod procstat;
 mod ptrace;
+mod syscall_tracer;
+mod threads;
 
 use libc::{lwpid_t, pid_t};
 use read_process_memory::{CopyAddress, ProcessHandle};
 
 use std::convert::TryInto;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex, Weak};
 
-use super::{Error, ProcessMemory};
+use super::{
+    Error, OpenFile, ProcessMemory, ReadOptions, Resource, ResourceLimit, TargetArch, UnwindMode,
+};
+#[cfg(use_libunwind)]
+use super::{Symbolicator, Unwinder};
 use crate::freebsd::lock::ProcessLock;
 
+pub use self::syscall_tracer::SyscallTracer;
+pub use self::threads::ThreadIter;
+
+// from sys/proc.h
+const P_TRACED: libc::c_long = 0x00800;
+
 pub type Pid = pid_t;
 pub type Tid = lwpid_t;
 
 pub struct Process {
     pub pid: Pid,
-    lock: Arc<Mutex<Weak<ProcessLock>>>,
+    lock: LockContainer,
+    unwind_mode: UnwindMode,
+    read_options: ReadOptions,
 }
 
 pub struct Thread {
     pub tid: lwpid_t,
     pid: pid_t,
     active: bool,
-    lock: Arc<Mutex<Weak<ProcessLock>>>,
+    lock: LockContainer,
 }
 
-fn process_lock(pid: Pid, container: &Mutex<Weak<ProcessLock>>) -> Result<Arc<ProcessLock>, Error> {
+type LockContainer = Arc<Mutex<Weak<ProcessLock>>>;
+
+fn process_lock(pid: Pid, container: &LockContainer) -> Result<Arc<ProcessLock>, Error> {
     let mut mutex_lock = container.lock().unwrap();
     if let Some(ref lock) = Weak::upgrade(&mutex_lock) {
         return Ok(Arc::clone(lock));
     }
 
-    let lock = Arc::new(ProcessLock::new(pid)?);
+    let lock = Arc::new(ProcessLock::new(pid, container)?);
     *mutex_lock = Arc::downgrade(&lock);
 
     Ok(lock)
 }
 
+fn get_threads(pid: Pid, lock: &LockContainer) -> Result<Vec<Thread>, Error> {
+    let threads = procstat::threads_info(pid)?;
+    let result = threads.iter().map(|th| Thread {
+        tid: th.ki_tid,
+        active: th.ki_stat == 2,
+        pid,
+        lock: Arc::clone(lock),
+    });
+
+    Ok(result.collect())
+}
+
 impl Process {
     pub fn new(pid: Pid) -> Result<Process, Error> {
         Ok(Process {
             pid,
             lock: Arc::new(Mutex::new(Weak::new())),
+            unwind_mode: UnwindMode::default(),
+            read_options: ReadOptions::default(),
+        })
+    }
+
+    /// Returns a new handle to the same process that can be kept independently of this one.
+    /// Both handles share the same lock, since only one tracer can be attached at a time.
+    pub fn try_clone(&self) -> Result<Process, Error> {
+        Ok(Process {
+            pid: self.pid,
+            lock: Arc::clone(&self.lock),
+            unwind_mode: self.unwind_mode,
+            read_options: self.read_options,
         })
     }
 
+    /// Returns how reads of memory from this process are split up and retried
+    pub fn read_options(&self) -> ReadOptions {
+        self.read_options
+    }
+
+    /// Sets how reads of memory from this process are split up and retried
+    pub fn set_read_options(&mut self, options: ReadOptions) {
+        self.read_options = options;
+    }
+
+    /// Returns how stacks in this process are unwound
+    pub fn unwind_mode(&self) -> UnwindMode {
+        self.unwind_mode
+    }
+
+    /// Sets how stacks in this process are unwound. `UnwindMode::FramePointer` is much faster
+    /// than the default, but only works when everything was compiled with frame pointers.
+    pub fn set_unwind_mode(&mut self, mode: UnwindMode) {
+        self.unwind_mode = mode;
+    }
+
+    /// Returns a cursor that unwinds a locked thread by following frame pointers. This doesn't
+    /// need the `unwind` feature.
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    pub fn frame_pointer_cursor(
+        &self,
+        thread: &Thread,
+    ) -> Result<crate::FramePointerCursor<&Self>, Error> {
+        let (ip, fp) = thread.frame_registers()?;
+        Ok(crate::FramePointerCursor::new(self, ip, fp))
+    }
+
     pub fn exe(&self) -> Result<String, Error> {
         let filename = procstat::exe(self.pid)?;
         if filename.is_empty() {
@@ -55,26 +129,99 @@ impl Process {
         Ok(filename)
     }
 
+    /// Returns the architecture, pointer size and endianness of the process, from the ELF
+    /// header of its executable
+    pub fn arch(&self) -> Result<TargetArch, Error> {
+        crate::read_elf_arch(self.pid, &self.exe()?)
+    }
+
+    /// True if pointers in the process are 64 bits wide
+    pub fn is_64_bit(&self) -> Result<bool, Error> {
+        Ok(self.arch()?.is_64_bit())
+    }
+
     pub fn cwd(&self) -> Result<String, Error> {
         Ok(procstat::cwd(self.pid)?)
     }
 
+    /// Returns the file descriptors the process has open that refer to a path, ordered by
+    /// descriptor
+    pub fn open_files(&self) -> Result<Vec<OpenFile>, Error> {
+        let mut files: Vec<OpenFile> = procstat::open_files(self.pid)?
+            .into_iter()
+            .map(|(fd, path)| OpenFile {
+                fd: fd as u64,
+                path,
+            })
+            .collect();
+        files.sort_by_key(|file| file.fd);
+        Ok(files)
+    }
+
+    /// Returns the resource limits of the process. The number of files the process currently
+    /// has open, to compare against `Resource::OpenFiles`, can be found with `open_files`.
+    pub fn rlimits(&self) -> Result<Vec<ResourceLimit>, Error> {
+        let resources = [
+            (libc::RLIMIT_CPU, Resource::CpuTime),
+            (libc::RLIMIT_FSIZE, Resource::FileSize),
+            (libc::RLIMIT_DATA, Resource::DataSize),
+            (libc::RLIMIT_STACK, Resource::StackSize),
+            (libc::RLIMIT_CORE, Resource::CoreFileSize),
+            (libc::RLIMIT_RSS, Resource::ResidentSet),
+            (libc::RLIMIT_MEMLOCK, Resource::LockedMemory),
+            (libc::RLIMIT_NPROC, Resource::Processes),
+            (libc::RLIMIT_NOFILE, Resource::OpenFiles),
+            (libc::RLIMIT_AS, Resource::AddressSpace),
+        ];
+        let value = |value: libc::rlim_t| {
+            if value == libc::RLIM_INFINITY {
+                None
+            } else {
+                Some(value as u64)
+            }
+        };
+        resources
+            .iter()
+            .map(|&(id, resource)| {
+                let limit = procstat::rlimit(self.pid, id as libc::c_int)
+                    .map_err(|e| Error::from_os_error(self.pid, e))?;
+                Ok(ResourceLimit {
+                    resource,
+                    soft: value(limit.rlim_cur),
+                    hard: value(limit.rlim_max),
+                })
+            })
+            .collect()
+    }
+
+    /// True if the process is being traced by a debugger, or by this process holding a lock
+    /// on it
+    pub fn is_being_debugged(&self) -> Result<bool, Error> {
+        let info = procstat::threads_info(self.pid)?;
+        Ok(info.iter().any(|thread| thread.ki_flag & P_TRACED != 0))
+    }
+
     pub fn threads(&self) -> Result<Vec<Thread>, Error> {
-        let threads = procstat::threads_info(self.pid)?;
-        let result = threads.iter().map(|th| Thread {
-            tid: th.ki_tid,
-            active: th.ki_stat == 2,
-            pid: self.pid,
-            lock: Arc::clone(&self.lock),
-        });
+        get_threads(self.pid, &self.lock)
+    }
 
-        Ok(result.collect())
+    /// Returns the ids of the threads in the process from the kinfo_proc of each thread
+    pub fn thread_ids(&self) -> Result<Vec<Tid>, Error> {
+        let threads = procstat::threads_info(self.pid)?;
+        Ok(threads.iter().map(|th| th.ki_tid).collect())
     }
 
     pub fn lock(&self) -> Result<Arc<ProcessLock>, Error> {
         process_lock(self.pid, &self.lock)
     }
 
+    /// Locks the process, and returns a lock containing a consistent snapshot of the threads
+    /// in the process. PT_ATTACH stops the whole process, so no new threads can be created
+    /// while the lock is held and the thread list gathered by the lock is already stable.
+    pub fn lock_and_snapshot(&self) -> Result<Arc<ProcessLock>, Error> {
+        self.lock()
+    }
+
     pub fn cmdline(&self) -> Result<Vec<String>, Error> {
         unsafe {
             let mib: [i32; 4] = [
@@ -115,8 +262,24 @@ impl Process {
         Ok(crate::filter_child_pids(self.pid, &processes))
     }
 
-    pub fn unwinder(&self) -> Result<(), Error> {
-        unimplemented!("No unwinding yet!")
+    /// Returns a path that can be used to open a file mapped into this process. FreeBSD
+    /// doesn't have an equivalent of /proc/pid/root, so this is only the plain path.
+    pub fn resolve_path(&self, filename: &Path, _start: usize, _end: usize) -> Option<PathBuf> {
+        if filename.exists() {
+            Some(filename.to_path_buf())
+        } else {
+            None
+        }
+    }
+
+    #[cfg(use_libunwind)]
+    pub fn unwinder(&self) -> Result<Unwinder, Error> {
+        Unwinder::with_mode(self, self.unwind_mode)
+    }
+
+    #[cfg(use_libunwind)]
+    pub fn symbolicator(&self) -> Result<Symbolicator, Error> {
+        Ok(Symbolicator::new(self.pid)?)
     }
 }
 
@@ -125,10 +288,27 @@ impl Thread {
         Ok(self.tid)
     }
 
+    /// Returns a new handle to the same thread
+    pub fn try_clone(&self) -> Result<Thread, Error> {
+        Ok(Thread {
+            tid: self.tid,
+            pid: self.pid,
+            active: self.active,
+            lock: Arc::clone(&self.lock),
+        })
+    }
+
     pub fn active(&self) -> Result<bool, Error> {
         Ok(self.active)
     }
 
+    /// Returns the instruction pointer and frame pointer of this thread. The process needs
+    /// to be locked for this to succeed.
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    pub fn frame_registers(&self) -> Result<(u64, u64), Error> {
+        Ok(ptrace::frame_registers(self.tid)?)
+    }
+
     pub fn lock(&self) -> Result<Arc<ProcessLock>, Error> {
         process_lock(self.pid, &self.lock)
     }
@@ -137,7 +317,9 @@ impl Thread {
 impl ProcessMemory for Process {
     fn read(&self, addr: usize, buf: &mut [u8]) -> Result<(), Error> {
         let handle: ProcessHandle = self.pid.try_into()?;
-        Ok(handle.copy_address(addr, buf)?)
+        self.read_options
+            .read_chunked(addr, buf, |addr, buf| Ok(handle.copy_address(addr, buf)?))
+            .map_err(|e| Error::from_read_error(self.pid, addr, buf.len(), e))
     }
 }
 
@@ -244,6 +426,28 @@ mod tests {
             .expect("test failed!");
     }
 
+    #[cfg(use_libunwind)]
+    #[test]
+    fn test_unwind() {
+        trace_perl_program(PERL_PROGRAM)
+            .and_then(|(process, _p)| {
+                let unwinder = process.unwinder()?;
+                let symbolicator = process.symbolicator()?;
+                let _lock = process.lock()?;
+
+                for thread in process.threads()? {
+                    let mut frames = 0;
+                    for ip in unwinder.cursor(&thread)? {
+                        symbolicator.symbolicate(ip?, true, &mut |_| {})?;
+                        frames += 1;
+                    }
+                    assert!(frames > 0);
+                }
+                Ok(())
+            })
+            .expect("test failed!");
+    }
+
     #[test]
     fn test_exe() {
         trace_perl_program(PERL_PROGRAM)
@@ -11,6 +11,7 @@ const PS_FST_UFLAG_CDIR: c_int = 0x0002;
 const KERN_PROC_PID: c_int = 1;
 const KERN_PROC_PROC: c_int = 8;
 const KERN_PROC_INC_THREAD: c_int = 0x10;
+const KERN_PROC_RLIMIT: c_int = 37;
 
 #[link(name = "procstat")]
 extern "C" {
@@ -148,6 +149,61 @@ pub fn cwd(pid: pid_t) -> Result<String, Error> {
     }
 }
 
+/// Returns the descriptors and paths of the files a process has open
+pub fn open_files(pid: pid_t) -> Result<Vec<(c_int, String)>, Error> {
+    procstat_call(
+        KERN_PROC_PID | KERN_PROC_INC_THREAD,
+        pid,
+        0 as c_int,
+        &|prstat, kinfo, _| {
+            let mut files = Vec::new();
+            let mut filestat = unsafe { (*procstat_getfiles(prstat, kinfo, 0)).next };
+            while !filestat.is_null() {
+                unsafe {
+                    let ref derefered = *filestat;
+                    // the cwd, root and text vnodes are listed with negative descriptors
+                    if derefered.fs_fd >= 0 && !derefered.fs_path.is_null() {
+                        let bytes = CStr::from_ptr(derefered.fs_path).to_bytes();
+                        files.push((derefered.fs_fd, String::from_utf8_lossy(bytes).into_owned()));
+                    }
+                    filestat = (derefered.next).next;
+                }
+            }
+            files
+        },
+    )
+}
+
+/// Returns the soft and hard limits of a resource of a process, like RLIMIT_NOFILE
+pub fn rlimit(pid: pid_t, resource: c_int) -> Result<libc::rlimit, Error> {
+    let mib: [c_int; 5] = [
+        libc::CTL_KERN,
+        libc::KERN_PROC,
+        KERN_PROC_RLIMIT,
+        pid,
+        resource,
+    ];
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    let mut size = std::mem::size_of::<libc::rlimit>();
+    let ret = unsafe {
+        libc::sysctl(
+            mib.as_ptr(),
+            mib.len() as u32,
+            &mut limit as *mut _ as *mut c_void,
+            &mut size,
+            std::ptr::null(),
+            0,
+        )
+    };
+    if ret < 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(limit)
+}
+
 pub fn processes() -> Result<std::collections::HashMap<pid_t, pid_t>, Error> {
     procstat_call(KERN_PROC_PROC, 0, 0, &|_, kinfo, count| {
         let mut ret = std::collections::HashMap::new();
@@ -1,5 +1,6 @@
 use libc::{c_int, c_void, lwpid_t, pid_t};
-use libc::{PT_ATTACH, PT_DETACH};
+use libc::{PT_ATTACH, PT_CONTINUE, PT_DETACH, PT_GETREGS};
+use libc::{PT_GET_SC_ARGS, PT_GET_SC_RET, PT_LWPINFO, PT_SYSCALL};
 
 use std::io::Error;
 use std::ptr;
@@ -33,3 +34,81 @@ pub fn detach(tid: lwpid_t) -> Result<(), Error> {
 
     Ok(())
 }
+
+/// Returns the instruction pointer and frame pointer of a stopped thread
+#[cfg(target_arch = "x86_64")]
+pub fn frame_registers(tid: lwpid_t) -> Result<(u64, u64), Error> {
+    let mut regs: libc::reg = unsafe { std::mem::zeroed() };
+    ptrace!(PT_GETREGS, tid, &mut regs as *mut _ as *const c_void, 0);
+
+    Ok((regs.r_rip as u64, regs.r_rbp as u64))
+}
+
+/// Returns the instruction pointer and frame pointer of a stopped thread
+#[cfg(target_arch = "aarch64")]
+pub fn frame_registers(tid: lwpid_t) -> Result<(u64, u64), Error> {
+    let mut regs: libc::reg = unsafe { std::mem::zeroed() };
+    ptrace!(PT_GETREGS, tid, &mut regs as *mut _ as *const c_void, 0);
+
+    Ok((regs.elr as u64, regs.x[29] as u64))
+}
+
+/// Resumes a stopped process, delivering `signal` to it (or nothing, if it's 0). An address
+/// of 1 carries on from where the process stopped, rather than jumping to the address.
+pub fn cont(pid: pid_t, signal: c_int) -> Result<(), Error> {
+    ptrace!(PT_CONTINUE, pid, 1 as *const c_void, signal);
+
+    Ok(())
+}
+
+/// Resumes a stopped process until a thread enters or returns from a syscall
+pub fn syscall(pid: pid_t, signal: c_int) -> Result<(), Error> {
+    ptrace!(PT_SYSCALL, pid, 1 as *const c_void, signal);
+
+    Ok(())
+}
+
+/// Returns information on the thread that caused a process to stop
+pub fn lwpinfo(pid: pid_t) -> Result<libc::ptrace_lwpinfo, Error> {
+    let mut info: libc::ptrace_lwpinfo = unsafe { std::mem::zeroed() };
+    let size = std::mem::size_of::<libc::ptrace_lwpinfo>() as c_int;
+    ptrace!(PT_LWPINFO, pid, &mut info as *mut _ as *const c_void, size);
+
+    Ok(info)
+}
+
+/// Returns the arguments of the syscall a thread stopped on entering
+pub fn syscall_args(tid: lwpid_t) -> Result<[u64; 6], Error> {
+    let mut args: [libc::register_t; 8] = [0; 8];
+    let size = std::mem::size_of_val(&args) as c_int;
+    ptrace!(
+        PT_GET_SC_ARGS,
+        tid,
+        args.as_mut_ptr() as *const c_void,
+        size
+    );
+
+    let mut values = [0; 6];
+    for (value, arg) in values.iter_mut().zip(args) {
+        *value = arg as u64;
+    }
+    Ok(values)
+}
+
+/// Returns the return value of the syscall a thread stopped on returning from, which is a
+/// negated errno if it failed
+pub fn syscall_ret(tid: lwpid_t) -> Result<i64, Error> {
+    let mut ret: libc::ptrace_sc_ret = unsafe { std::mem::zeroed() };
+    let size = std::mem::size_of::<libc::ptrace_sc_ret>() as c_int;
+    ptrace!(
+        PT_GET_SC_RET,
+        tid,
+        &mut ret as *mut _ as *const c_void,
+        size
+    );
+
+    if ret.sr_error != 0 {
+        return Ok(-(ret.sr_error as i64));
+    }
+    Ok(ret.sr_retval[0] as i64)
+}
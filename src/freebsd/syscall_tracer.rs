@@ -0,0 +1,153 @@
+//! Traces the syscalls made by a process with PT_SYSCALL.
+//!
+//! Unlike linux, ptrace on FreeBSD stops and resumes whole processes: PT_LWPINFO says which
+//! thread stopped it, and that thread is the one at a syscall entry or exit.
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use libc::{waitpid, WIFEXITED, WIFSIGNALED, WIFSTOPPED, WNOHANG, WSTOPSIG};
+use log::{debug, info, warn};
+
+use super::{ptrace, Pid, Tid};
+use crate::{Error, SyscallEvent};
+
+/// Reports the syscalls entered and returned from by the threads of a process.
+///
+/// The process is attached to with ptrace until this is dropped, and stops briefly on each
+/// syscall entry and exit - which slows down syscall heavy programs considerably.
+pub struct SyscallTracer {
+    pub pid: Pid,
+    /// the syscall each thread is inside of, so that exits can report it
+    syscalls: HashMap<Tid, u64>,
+    exited: bool,
+}
+
+impl SyscallTracer {
+    /// Attaches to a process and starts tracing its syscalls
+    pub fn attach(pid: Pid) -> Result<SyscallTracer, Error> {
+        ptrace::attach(pid)?;
+        let mut status = 0;
+        let stopped = unsafe {
+            waitpid(pid, &mut status, 0);
+            WIFSTOPPED(status)
+        };
+        if !stopped {
+            return Err(Error::IOError(std::io::Error::last_os_error()));
+        }
+        ptrace::syscall(pid, 0)?;
+        info!("tracing syscalls of {}", pid);
+        Ok(SyscallTracer {
+            pid,
+            syscalls: HashMap::new(),
+            exited: false,
+        })
+    }
+
+    /// Waits up to `timeout` for the next syscall entry or exit, returning None if there
+    /// wasn't one
+    pub fn wait(&mut self, timeout: Duration) -> Result<Option<SyscallEvent>, Error> {
+        let start = Instant::now();
+        while !self.exited {
+            let mut status = 0;
+            let ret = unsafe { waitpid(self.pid, &mut status, WNOHANG) };
+            if ret < 0 {
+                return Err(Error::IOError(std::io::Error::last_os_error()));
+            }
+            if ret == self.pid {
+                if let Some(event) = self.handle_status(status)? {
+                    return Ok(Some(event));
+                }
+                continue;
+            }
+            if start.elapsed() >= timeout {
+                return Ok(None);
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        Err(Error::ProcessExited { pid: self.pid })
+    }
+
+    /// Handles a change in state of the process, resuming it and returning the syscall
+    /// event it reported if any
+    fn handle_status(&mut self, status: libc::c_int) -> Result<Option<SyscallEvent>, Error> {
+        if WIFEXITED(status) || WIFSIGNALED(status) {
+            self.exited = true;
+            return Ok(None);
+        }
+        if !WIFSTOPPED(status) {
+            return Ok(None);
+        }
+        let info = ptrace::lwpinfo(self.pid)?;
+        let tid = info.pl_lwpid;
+        let event = if info.pl_flags & libc::PL_FLAG_SCE != 0 {
+            let number = info.pl_syscall_code as u64;
+            self.syscalls.insert(tid, number);
+            Some(SyscallEvent::Enter {
+                tid,
+                number,
+                args: ptrace::syscall_args(tid)?,
+            })
+        } else if info.pl_flags & libc::PL_FLAG_SCX != 0 {
+            // threads that were inside a syscall when we attached exit without entering it
+            match self.syscalls.remove(&tid) {
+                Some(number) => Some(SyscallEvent::Exit {
+                    tid,
+                    number,
+                    ret: ptrace::syscall_ret(tid)?,
+                }),
+                None => None,
+            }
+        } else {
+            // signals weren't sent by us, so pass them on
+            let signal = WSTOPSIG(status);
+            debug!("passing signal {} on to {}", signal, self.pid);
+            ptrace::syscall(self.pid, signal)?;
+            return Ok(None);
+        };
+        ptrace::syscall(self.pid, 0)?;
+        Ok(event)
+    }
+}
+
+impl Drop for SyscallTracer {
+    fn drop(&mut self) {
+        if self.exited {
+            return;
+        }
+        // the process has to be stopped to detach. PT_CONTINUE stops tracing syscalls, so
+        // after SIGSTOP is sent the only stop left to see is for the signal itself
+        unsafe { libc::kill(self.pid, libc::SIGSTOP) };
+        loop {
+            let mut status = 0;
+            if unsafe { waitpid(self.pid, &mut status, 0) } < 0 {
+                warn!(
+                    "failed to wait for {}: {}",
+                    self.pid,
+                    std::io::Error::last_os_error()
+                );
+                return;
+            }
+            if WIFEXITED(status) || WIFSIGNALED(status) {
+                return;
+            }
+            let signal = WSTOPSIG(status);
+            let info = ptrace::lwpinfo(self.pid);
+            let at_syscall = match &info {
+                Ok(info) => info.pl_flags & (libc::PL_FLAG_SCE | libc::PL_FLAG_SCX) != 0,
+                Err(_) => false,
+            };
+            if !at_syscall && signal == libc::SIGSTOP {
+                break;
+            }
+            let signal = if at_syscall { 0 } else { signal };
+            if let Err(e) = ptrace::cont(self.pid, signal) {
+                warn!("failed to resume {}: {}", self.pid, e);
+                return;
+            }
+        }
+        if let Err(e) = ptrace::detach(self.pid) {
+            warn!("failed to detach from {}: {}", self.pid, e);
+        }
+        debug!("stopped tracing syscalls of {}", self.pid);
+    }
+}
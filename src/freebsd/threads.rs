@@ -0,0 +1,110 @@
+//! Details of the threads of a process. `threads_iter` lists the threads from libprocstat,
+//! which returns a kinfo_proc for every thread in one call, converting each as the iterator
+//! reaches it.
+use std::time::Duration;
+
+use super::kinfo_proc::kinfo_proc;
+use super::{procstat, Pid, Process, Thread};
+use crate::{Error, ThreadInfo, ThreadState};
+
+// from sys/proc.h
+const SIDL: i8 = 1;
+const SRUN: i8 = 2;
+const SSLEEP: i8 = 3;
+const SSTOP: i8 = 4;
+const SZOMB: i8 = 5;
+const SWAIT: i8 = 6;
+const SLOCK: i8 = 7;
+
+/// An iterator over the threads of a process, returned by `Process::threads_iter`
+pub struct ThreadIter {
+    threads: std::vec::IntoIter<kinfo_proc>,
+}
+
+impl Process {
+    /// Returns an iterator over the threads of the process, with the name, state and CPU
+    /// time of each
+    pub fn threads_iter(&self) -> Result<ThreadIter, Error> {
+        let threads =
+            procstat::threads_info(self.pid).map_err(|e| Error::from_os_error(self.pid, e))?;
+        Ok(ThreadIter {
+            threads: threads.into_iter(),
+        })
+    }
+}
+
+impl Iterator for ThreadIter {
+    type Item = Result<ThreadInfo, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let thread = self.threads.next()?;
+        let state = match thread.ki_stat as i8 {
+            SRUN | SIDL => ThreadState::Running,
+            SSLEEP | SWAIT | SLOCK => ThreadState::Sleeping,
+            SSTOP => ThreadState::Stopped,
+            SZOMB => ThreadState::Zombie,
+            _ => ThreadState::Unknown,
+        };
+        let name = unsafe { std::ffi::CStr::from_ptr(thread.ki_tdname.as_ptr()) }
+            .to_string_lossy()
+            .into_owned();
+        Some(Ok(ThreadInfo {
+            tid: thread.ki_tid,
+            name: if name.is_empty() { None } else { Some(name) },
+            state,
+            // ki_runtime is in microseconds
+            cpu_time: Duration::from_micros(thread.ki_runtime),
+        }))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.threads.size_hint()
+    }
+}
+
+impl Process {
+    /// Returns the CPUs the process is allowed to run on
+    pub fn affinity(&self) -> Result<Vec<usize>, Error> {
+        get_affinity(libc::CPU_WHICH_PID, self.pid as libc::id_t, self.pid)
+    }
+}
+
+impl Thread {
+    /// Returns the CPUs the thread is allowed to run on
+    pub fn affinity(&self) -> Result<Vec<usize>, Error> {
+        get_affinity(libc::CPU_WHICH_TID, self.tid as libc::id_t, self.pid)
+    }
+
+    /// Returns the CPU the thread last ran on
+    pub fn last_cpu(&self) -> Result<Option<usize>, Error> {
+        let threads =
+            procstat::threads_info(self.pid).map_err(|e| Error::from_os_error(self.pid, e))?;
+        let thread = threads
+            .iter()
+            .find(|thread| thread.ki_tid == self.tid)
+            .ok_or(Error::NoSuchProcess { pid: self.tid })?;
+        // NOCPU is -1
+        Ok(usize::try_from(thread.ki_lastcpu).ok())
+    }
+}
+
+/// Returns the CPUs in the affinity of a process or thread
+fn get_affinity(which: libc::cpuwhich_t, id: libc::id_t, pid: Pid) -> Result<Vec<usize>, Error> {
+    let mut cpus: libc::cpuset_t = unsafe { std::mem::zeroed() };
+    let ret = unsafe {
+        libc::cpuset_getaffinity(
+            libc::CPU_LEVEL_WHICH,
+            which,
+            id,
+            std::mem::size_of::<libc::cpuset_t>(),
+            &mut cpus,
+        )
+    };
+    if ret != 0 {
+        return Err(Error::from_os_error(pid, std::io::Error::last_os_error()));
+    }
+    let size = std::mem::size_of::<libc::cpuset_t>() * 8;
+    Ok((0..size)
+        .filter(|cpu| unsafe { libc::CPU_ISSET(*cpu, &cpus) })
+        .collect())
+}
@@ -0,0 +1,509 @@
+//! A client for the GDB remote serial protocol, for reading the memory and registers of
+//! targets that can't be attached to directly: programs running under gdbserver, virtual
+//! machines exposing qemu's gdbstub, and embedded devices behind a debug probe.
+//!
+//! The target only answers requests while it's halted, so memory and registers should be
+//! read while holding the lock returned by `GdbRemote::lock`, the same as with a local
+//! process.
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Mutex;
+
+use log::{debug, warn};
+
+use crate::{Error, FramePointerCursor, Pid, ProcessMemory};
+
+// how many times to resend a packet that the stub says arrived corrupted
+const MAX_RETRIES: usize = 3;
+
+/// A bidirectional byte stream to a gdb stub, like a TCP or unix socket
+pub trait GdbConnection: Read + Write + Send {}
+impl<T: Read + Write + Send> GdbConnection for T {}
+
+/// A target being debugged through a gdb stub
+pub struct GdbRemote {
+    state: Mutex<State>,
+    /// The process id reported by the stub, or 0 for targets that don't have processes
+    pub pid: Pid,
+    multiprocess: bool,
+    // the largest packet the stub accepts, which limits how much memory is read at once
+    packet_size: usize,
+}
+
+struct State {
+    stream: BufReader<Box<dyn GdbConnection>>,
+    ack: bool,
+    running: bool,
+}
+
+impl GdbRemote {
+    /// Connects to a stub listening on a TCP port, like `gdbserver :1234 ./program` or
+    /// `qemu-system-x86_64 -s` does
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> Result<GdbRemote, Error> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        GdbRemote::new(Box::new(stream))
+    }
+
+    /// Talks to a stub over an existing connection. The target is halted once this returns.
+    pub fn new(connection: Box<dyn GdbConnection>) -> Result<GdbRemote, Error> {
+        let mut state = State {
+            stream: BufReader::new(connection),
+            ack: true,
+            running: false,
+        };
+        state.stream.get_mut().write_all(b"+")?;
+
+        let supported = state.request(b"qSupported:multiprocess+;swbreak+;hwbreak+")?;
+        let supported = String::from_utf8_lossy(&supported).into_owned();
+        let mut packet_size = 400;
+        let mut multiprocess = false;
+        for feature in supported.split(';') {
+            if let Some(size) = feature.strip_prefix("PacketSize=") {
+                packet_size = usize::from_str_radix(size, 16).unwrap_or(packet_size);
+            } else if feature == "multiprocess+" {
+                multiprocess = true;
+            } else if feature == "QStartNoAckMode+" && state.request(b"QStartNoAckMode")? == b"OK" {
+                state.ack = false;
+            }
+        }
+
+        let stop = state.request(b"?")?;
+        if matches!(stop.first(), Some(b'W') | Some(b'X')) {
+            return Err(Error::Other("gdb target has exited".to_owned()));
+        }
+
+        // 'QCp<pid>.<tid>' with the multiprocess extension, and 'QC<tid>' without it
+        let current = state.request(b"qC")?;
+        let pid = match current.strip_prefix(b"QCp") {
+            Some(id) => parse_thread_id(id).map(|(pid, _)| pid).unwrap_or(0),
+            None => 0,
+        };
+        debug!(
+            "connected to gdb stub (pid {}, packet size {}, multiprocess {})",
+            pid, packet_size, multiprocess
+        );
+
+        Ok(GdbRemote {
+            state: Mutex::new(state),
+            pid: pid as Pid,
+            multiprocess,
+            packet_size,
+        })
+    }
+
+    /// Lists the threads of the target. For targets without threads, like a bare metal
+    /// device, this is a single thread per cpu core.
+    pub fn threads(&self) -> Result<Vec<u64>, Error> {
+        let mut state = self.state.lock().unwrap();
+        let mut threads = Vec::new();
+        let mut reply = state.request(b"qfThreadInfo")?;
+        while let Some(ids) = reply.strip_prefix(b"m") {
+            for id in ids.split(|c| *c == b',') {
+                let (_, tid) = parse_thread_id(id.strip_prefix(b"p").unwrap_or(id))
+                    .ok_or_else(|| protocol_error("thread id", id))?;
+                threads.push(tid);
+            }
+            reply = state.request(b"qsThreadInfo")?;
+        }
+        Ok(threads)
+    }
+
+    /// Returns the registers of a thread as the stub sends them: in the order of gdb's
+    /// register numbering for the target architecture, and in target byte order. Registers
+    /// that the stub doesn't have a value for are returned as zero.
+    pub fn registers(&self, thread: u64) -> Result<Vec<u8>, Error> {
+        let mut state = self.state.lock().unwrap();
+        self.select_thread(&mut state, thread)?;
+        let reply = state.request(b"g")?;
+        decode_hex(&reply).ok_or_else(|| protocol_error("register", &reply))
+    }
+
+    /// Returns a single register of a thread, by its gdb register number. The target is
+    /// assumed to be little endian.
+    pub fn register(&self, thread: u64, register: usize) -> Result<u64, Error> {
+        let mut state = self.state.lock().unwrap();
+        self.select_thread(&mut state, thread)?;
+        let reply = state.request(format!("p{:x}", register).as_bytes())?;
+        let bytes = decode_hex(&reply).ok_or_else(|| protocol_error("register", &reply))?;
+        Ok(bytes
+            .iter()
+            .take(8)
+            .rev()
+            .fold(0, |value, byte| (value << 8) | *byte as u64))
+    }
+
+    /// Returns a cursor over the stack of a thread, found by following frame pointers.
+    /// This requires the target to have the same architecture as the current machine.
+    #[cfg(any(
+        target_arch = "x86_64",
+        target_arch = "aarch64",
+        target_arch = "riscv64"
+    ))]
+    pub fn cursor(&self, thread: u64) -> Result<FramePointerCursor<&GdbRemote>, Error> {
+        // rip and rbp, pc and x29, or pc and s0
+        #[cfg(target_arch = "x86_64")]
+        let (ip, fp) = (16, 6);
+        #[cfg(target_arch = "aarch64")]
+        let (ip, fp) = (32, 29);
+        #[cfg(target_arch = "riscv64")]
+        let (ip, fp) = (32, 8);
+        let ip = self.register(thread, ip)?;
+        let fp = self.register(thread, fp)?;
+        Ok(FramePointerCursor::new(self, ip, fp))
+    }
+
+    /// Halts the target until the returned lock is dropped
+    pub fn lock(&self) -> Result<GdbLock<'_>, Error> {
+        let mut state = self.state.lock().unwrap();
+        let resume = state.running;
+        if state.running {
+            state.interrupt()?;
+        }
+        Ok(GdbLock {
+            remote: self,
+            resume,
+        })
+    }
+
+    /// Lets a halted target run. Targets are halted when first connected to, so this needs
+    /// calling once before sampling a target that should keep running.
+    pub fn resume(&self) -> Result<(), Error> {
+        self.state.lock().unwrap().resume()
+    }
+
+    fn select_thread(&self, state: &mut State, thread: u64) -> Result<(), Error> {
+        let packet = if self.multiprocess {
+            format!("Hgp{:x}.{:x}", self.pid, thread)
+        } else {
+            format!("Hg{:x}", thread)
+        };
+        let reply = state.request(packet.as_bytes())?;
+        if reply != b"OK" {
+            return Err(protocol_error("thread selection", &reply));
+        }
+        Ok(())
+    }
+}
+
+impl ProcessMemory for GdbRemote {
+    fn read(&self, addr: usize, buf: &mut [u8]) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        // each byte is sent back as two hex digits, inside of '$' and '#xx'
+        let chunk_size = std::cmp::max(self.packet_size.saturating_sub(4) / 2, 1);
+        for (i, chunk) in buf.chunks_mut(chunk_size).enumerate() {
+            let address = addr + i * chunk_size;
+            let reply = state.request(format!("m{:x},{:x}", address, chunk.len()).as_bytes())?;
+            match decode_hex(&reply) {
+                Some(bytes) if bytes.len() == chunk.len() => chunk.copy_from_slice(&bytes),
+                _ => {
+                    return Err(Error::InvalidAddress {
+                        pid: self.pid,
+                        addr: address,
+                        len: chunk.len(),
+                        source: std::io::Error::other(String::from_utf8_lossy(&reply)),
+                    })
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Keeps a gdb target halted, resuming it when dropped if it was running before
+pub struct GdbLock<'a> {
+    remote: &'a GdbRemote,
+    resume: bool,
+}
+
+impl Drop for GdbLock<'_> {
+    fn drop(&mut self) {
+        if self.resume {
+            if let Err(e) = self.remote.state.lock().unwrap().resume() {
+                warn!("failed to resume gdb target: {}", e);
+            }
+        }
+    }
+}
+
+impl State {
+    /// Sends a packet and returns the reply
+    fn request(&mut self, packet: &[u8]) -> Result<Vec<u8>, Error> {
+        if self.running {
+            return Err(Error::Other(
+                "gdb target is running, and needs to be locked first".to_owned(),
+            ));
+        }
+        self.send(packet)?;
+        self.receive()
+    }
+
+    fn send(&mut self, packet: &[u8]) -> Result<(), Error> {
+        let checksum = packet.iter().fold(0_u8, |sum, b| sum.wrapping_add(*b));
+        let mut framed = Vec::with_capacity(packet.len() + 4);
+        framed.push(b'$');
+        framed.extend_from_slice(packet);
+        framed.extend_from_slice(format!("#{:02x}", checksum).as_bytes());
+
+        for _ in 0..MAX_RETRIES {
+            self.stream.get_mut().write_all(&framed)?;
+            if !self.ack {
+                return Ok(());
+            }
+            match self.read_byte()? {
+                b'+' => return Ok(()),
+                b'-' => continue,
+                other => return Err(protocol_error("acknowledgement", &[other])),
+            }
+        }
+        Err(Error::Other("gdb stub rejected packet".to_owned()))
+    }
+
+    fn receive(&mut self) -> Result<Vec<u8>, Error> {
+        loop {
+            // anything before the start of the packet is a stray acknowledgement
+            while self.read_byte()? != b'$' {}
+            let mut data = Vec::new();
+            self.stream.read_until(b'#', &mut data)?;
+            if data.pop() != Some(b'#') {
+                return Err(Error::Other("gdb stub closed the connection".to_owned()));
+            }
+            let mut checksum = [0_u8; 2];
+            self.stream.read_exact(&mut checksum)?;
+
+            let expected = decode_hex(&checksum).and_then(|c| c.first().copied());
+            let actual = data.iter().fold(0_u8, |sum, b| sum.wrapping_add(*b));
+            if !self.ack {
+                return Ok(decode_packet(&data));
+            }
+            if expected == Some(actual) {
+                self.stream.get_mut().write_all(b"+")?;
+                return Ok(decode_packet(&data));
+            }
+            self.stream.get_mut().write_all(b"-")?;
+        }
+    }
+
+    fn read_byte(&mut self) -> Result<u8, Error> {
+        let mut byte = [0_u8];
+        self.stream.read_exact(&mut byte)?;
+        Ok(byte[0])
+    }
+
+    /// Interrupts a running target, and waits for it to report that it has stopped
+    fn interrupt(&mut self) -> Result<(), Error> {
+        self.stream.get_mut().write_all(&[0x03])?;
+        loop {
+            let reply = self.receive()?;
+            match reply.first() {
+                Some(b'T') | Some(b'S') => break,
+                Some(b'W') | Some(b'X') => {
+                    return Err(Error::Other("gdb target has exited".to_owned()))
+                }
+                // console output from the target while it was running
+                _ => continue,
+            }
+        }
+        self.running = false;
+        Ok(())
+    }
+
+    fn resume(&mut self) -> Result<(), Error> {
+        if !self.running {
+            // there's no reply until the target stops again
+            self.send(b"c")?;
+            self.running = true;
+        }
+        Ok(())
+    }
+}
+
+/// Expands the escaped bytes and run length encoding in a packet from the stub
+fn decode_packet(data: &[u8]) -> Vec<u8> {
+    let mut decoded = Vec::with_capacity(data.len());
+    let mut bytes = data.iter();
+    while let Some(byte) = bytes.next() {
+        match byte {
+            b'}' => {
+                if let Some(escaped) = bytes.next() {
+                    decoded.push(escaped ^ 0x20);
+                }
+            }
+            // '*' repeats the previous byte, with the count encoded as a printable character
+            b'*' => {
+                if let (Some(count), Some(last)) = (bytes.next(), decoded.last().copied()) {
+                    let count = count.saturating_sub(29) as usize;
+                    decoded.extend(std::iter::repeat_n(last, count));
+                }
+            }
+            _ => decoded.push(*byte),
+        }
+    }
+    decoded
+}
+
+/// Decodes hex encoded bytes, treating the 'xx' of unavailable registers as zero
+fn decode_hex(hex: &[u8]) -> Option<Vec<u8>> {
+    let pairs = hex.chunks_exact(2);
+    if !pairs.remainder().is_empty() {
+        return None;
+    }
+    pairs
+        .map(|pair| match pair {
+            b"xx" => Some(0),
+            pair => u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok(),
+        })
+        .collect()
+}
+
+/// Parses a thread id like `1a` or `<pid>.<tid>`, returning the pid (0 if there is none)
+/// and the thread id
+fn parse_thread_id(id: &[u8]) -> Option<(u64, u64)> {
+    let id = std::str::from_utf8(id).ok()?;
+    let parse = |s: &str| u64::from_str_radix(s, 16).ok();
+    match id.split_once('.') {
+        Some((pid, tid)) => Some((parse(pid)?, parse(tid)?)),
+        None => Some((0, parse(id)?)),
+    }
+}
+
+fn protocol_error(what: &str, reply: &[u8]) -> Error {
+    Error::Other(format!(
+        "unexpected {} reply from gdb stub: {}",
+        what,
+        String::from_utf8_lossy(reply)
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    /// A gdb stub that answers from a small amount of memory starting at 0x1000
+    struct Stub {
+        output: VecDeque<u8>,
+        input: Vec<u8>,
+        ack: bool,
+        memory: Vec<u8>,
+        thread_queries: usize,
+    }
+
+    impl Stub {
+        fn reply(&mut self, packet: &str) -> String {
+            match packet {
+                p if p.starts_with("qSupported") => "PacketSize=10;QStartNoAckMode+".to_owned(),
+                "QStartNoAckMode" => "OK".to_owned(),
+                "?" => "S05".to_owned(),
+                "qC" => "QC1".to_owned(),
+                "qfThreadInfo" => "m1,2".to_owned(),
+                "qsThreadInfo" => {
+                    self.thread_queries += 1;
+                    if self.thread_queries == 1 { "mf" } else { "l" }.to_owned()
+                }
+                p if p.starts_with("Hg") => "OK".to_owned(),
+                // rax is 0x1122, and everything else zero (run length encoded)
+                "g" => "22110*(".to_owned(),
+                "p10" => "efbe000000000000".to_owned(),
+                p if p.starts_with('m') => {
+                    let (addr, len) = p[1..].split_once(',').unwrap();
+                    let addr = usize::from_str_radix(addr, 16).unwrap();
+                    let len = usize::from_str_radix(len, 16).unwrap();
+                    match addr
+                        .checked_sub(0x1000)
+                        .and_then(|start| self.memory.get(start..start + len))
+                    {
+                        Some(bytes) => bytes.iter().map(|b| format!("{:02x}", b)).collect(),
+                        None => "E14".to_owned(),
+                    }
+                }
+                _ => String::new(),
+            }
+        }
+    }
+
+    impl Write for Stub {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.input.extend_from_slice(buf);
+            while let Some(start) = self.input.iter().position(|b| *b == b'$') {
+                let end = match self.input.iter().position(|b| *b == b'#') {
+                    Some(end) if end + 2 < self.input.len() => end,
+                    _ => break,
+                };
+                let packet = String::from_utf8(self.input[start + 1..end].to_vec()).unwrap();
+                self.input.drain(..end + 3);
+                if self.ack {
+                    self.output.push_back(b'+');
+                }
+                let reply = self.reply(&packet);
+                if packet == "QStartNoAckMode" {
+                    self.ack = false;
+                }
+                let checksum = reply.bytes().fold(0_u8, |sum, b| sum.wrapping_add(b));
+                self.output
+                    .extend(format!("${}#{:02x}", reply, checksum).bytes());
+            }
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Read for Stub {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let len = std::cmp::min(buf.len(), self.output.len());
+            for (i, byte) in self.output.drain(..len).enumerate() {
+                buf[i] = byte;
+            }
+            Ok(len)
+        }
+    }
+
+    fn connect() -> GdbRemote {
+        let stub = Stub {
+            output: VecDeque::new(),
+            input: Vec::new(),
+            ack: true,
+            memory: (0..64).collect(),
+            thread_queries: 0,
+        };
+        GdbRemote::new(Box::new(stub)).unwrap()
+    }
+
+    #[test]
+    fn test_decode_packet() {
+        assert_eq!(decode_packet(b"0* "), b"0000");
+        assert_eq!(decode_packet(b"a}\x03b"), b"a#b");
+    }
+
+    #[test]
+    fn test_parse_thread_id() {
+        assert_eq!(parse_thread_id(b"1a"), Some((0, 0x1a)));
+        assert_eq!(parse_thread_id(b"10.2"), Some((0x10, 2)));
+        assert_eq!(parse_thread_id(b"zz"), None);
+    }
+
+    #[test]
+    fn test_memory() {
+        let remote = connect();
+        // the stub's packet size only allows reading 6 bytes at a time
+        let bytes = remote.copy(0x1002, 20).unwrap();
+        assert_eq!(bytes, (2..22).collect::<Vec<u8>>());
+        assert!(matches!(
+            remote.copy(0x2000, 4),
+            Err(Error::InvalidAddress { addr: 0x2000, .. })
+        ));
+    }
+
+    #[test]
+    fn test_threads_and_registers() {
+        let remote = connect();
+        assert_eq!(remote.threads().unwrap(), vec![1, 2, 0xf]);
+        let registers = remote.registers(1).unwrap();
+        assert_eq!(registers.len(), 8);
+        assert_eq!(&registers[..2], &[0x22, 0x11]);
+        assert_eq!(remote.register(1, 16).unwrap(), 0xbeef);
+    }
+}
@@ -0,0 +1,271 @@
+//! illumos and Solaris support, built on the binary files exposed by /proc
+mod procfs;
+mod threads;
+
+use libc::pid_t;
+use log::error;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::os::unix::fs::FileExt;
+use std::sync::{Arc, Mutex, Weak};
+
+use super::{Error, ProcessMemory, ReadOptions, TargetArch};
+
+pub use self::threads::ThreadIter;
+
+pub type Pid = pid_t;
+pub type Tid = libc::c_int;
+
+const PR_MODEL_ILP32: libc::c_char = 1;
+
+pub struct Process {
+    pub pid: Pid,
+    address_space: File,
+    lock: LockContainer,
+    read_options: ReadOptions,
+}
+
+pub struct Thread {
+    pub tid: Tid,
+    pid: Pid,
+    lock: LockContainer,
+}
+
+type LockContainer = Arc<Mutex<Weak<ProcessLock>>>;
+
+/// Stops every lwp in the process through /proc/pid/ctl, setting the process running
+/// again when dropped
+pub struct ProcessLock {
+    pid: Pid,
+    ctl: File,
+    threads: Vec<Thread>,
+}
+
+impl ProcessLock {
+    fn new(pid: Pid, container: &LockContainer) -> Result<ProcessLock, Error> {
+        let ctl = procfs::stop(pid).map_err(|e| Error::from_os_error(pid, e))?;
+        let mut lock = ProcessLock {
+            pid,
+            ctl,
+            threads: Vec::new(),
+        };
+        // the whole process is stopped, so the list of lwps can't change while locked
+        lock.threads = get_threads(pid, container)?;
+        Ok(lock)
+    }
+
+    /// The threads that were suspended by this lock
+    pub fn threads(&self) -> &[Thread] {
+        &self.threads
+    }
+
+    /// The thread ids of the threads that were suspended by this lock
+    pub fn tids(&self) -> Vec<Tid> {
+        self.threads.iter().map(|thread| thread.tid).collect()
+    }
+}
+
+impl Drop for ProcessLock {
+    fn drop(&mut self) {
+        if let Err(e) = procfs::run(&mut self.ctl) {
+            error!("Failed to resume process {} : {}", self.pid, e);
+        }
+    }
+}
+
+fn process_lock(pid: Pid, container: &LockContainer) -> Result<Arc<ProcessLock>, Error> {
+    let mut mutex_lock = container.lock().unwrap();
+    if let Some(ref lock) = Weak::upgrade(&mutex_lock) {
+        return Ok(Arc::clone(lock));
+    }
+
+    let lock = Arc::new(ProcessLock::new(pid, container)?);
+    *mutex_lock = Arc::downgrade(&lock);
+
+    Ok(lock)
+}
+
+fn get_threads(pid: Pid, lock: &LockContainer) -> Result<Vec<Thread>, Error> {
+    let lwps = procfs::lwps(pid).map_err(|e| Error::from_os_error(pid, e))?;
+    Ok(lwps
+        .into_iter()
+        .map(|tid| Thread {
+            tid,
+            pid,
+            lock: Arc::clone(lock),
+        })
+        .collect())
+}
+
+impl Process {
+    pub fn new(pid: Pid) -> Result<Process, Error> {
+        let address_space =
+            File::open(format!("/proc/{}/as", pid)).map_err(|e| Error::from_os_error(pid, e))?;
+        Ok(Process {
+            pid,
+            address_space,
+            lock: Arc::new(Mutex::new(Weak::new())),
+            read_options: ReadOptions::default(),
+        })
+    }
+
+    /// Returns a new handle to the same process that can be kept independently of this one,
+    /// duplicating the descriptor for /proc/pid/as. Both handles share the same lock, since
+    /// the process can only be stopped through /proc once at a time.
+    pub fn try_clone(&self) -> Result<Process, Error> {
+        Ok(Process {
+            pid: self.pid,
+            address_space: self
+                .address_space
+                .try_clone()
+                .map_err(|e| Error::from_os_error(self.pid, e))?,
+            lock: Arc::clone(&self.lock),
+            read_options: self.read_options,
+        })
+    }
+
+    /// Returns how reads of memory from this process are split up and retried
+    pub fn read_options(&self) -> ReadOptions {
+        self.read_options
+    }
+
+    /// Sets how reads of memory from this process are split up and retried
+    pub fn set_read_options(&mut self, options: ReadOptions) {
+        self.read_options = options;
+    }
+
+    pub fn exe(&self) -> Result<String, Error> {
+        let path = std::fs::read_link(format!("/proc/{}/path/a.out", self.pid))
+            .map_err(|e| Error::from_os_error(self.pid, e))?;
+        Ok(path.to_string_lossy().to_string())
+    }
+
+    /// Returns the architecture, pointer size and endianness of the process, from the ELF
+    /// header of its executable
+    pub fn arch(&self) -> Result<TargetArch, Error> {
+        crate::read_elf_arch(self.pid, &format!("/proc/{}/object/a.out", self.pid))
+    }
+
+    /// True if pointers in the process are 64 bits wide
+    pub fn is_64_bit(&self) -> Result<bool, Error> {
+        Ok(self.arch()?.is_64_bit())
+    }
+
+    pub fn cwd(&self) -> Result<String, Error> {
+        let path = std::fs::read_link(format!("/proc/{}/path/cwd", self.pid))
+            .map_err(|e| Error::from_os_error(self.pid, e))?;
+        Ok(path.to_string_lossy().to_string())
+    }
+
+    pub fn cmdline(&self) -> Result<Vec<String>, Error> {
+        let info = procfs::psinfo(self.pid).map_err(|e| Error::from_os_error(self.pid, e))?;
+
+        // pr_psargs is truncated to 80 characters, so read the full argv from the process
+        let pointer_size = if info.pr_dmodel == PR_MODEL_ILP32 {
+            4
+        } else {
+            8
+        };
+        let pointers = self.copy(info.pr_argv, info.pr_argc as usize * pointer_size)?;
+
+        let mut ret = Vec::with_capacity(info.pr_argc as usize);
+        for pointer in pointers.chunks_exact(pointer_size) {
+            let mut bytes = [0_u8; 8];
+            bytes[..pointer_size].copy_from_slice(pointer);
+            ret.push(self.read_c_str(u64::from_le_bytes(bytes) as usize)?);
+        }
+        Ok(ret)
+    }
+
+    fn read_c_str(&self, addr: usize) -> Result<String, Error> {
+        let mut ret = Vec::new();
+        let mut chunk = [0_u8; 256];
+        loop {
+            let count = self
+                .address_space
+                .read_at(&mut chunk, (addr + ret.len()) as u64)
+                .map_err(|e| Error::from_os_error(self.pid, e))?;
+            if count == 0 {
+                break;
+            }
+            if let Some(end) = chunk[..count].iter().position(|b| *b == 0) {
+                ret.extend_from_slice(&chunk[..end]);
+                break;
+            }
+            ret.extend_from_slice(&chunk[..count]);
+        }
+        Ok(String::from_utf8_lossy(&ret).into_owned())
+    }
+
+    pub fn threads(&self) -> Result<Vec<Thread>, Error> {
+        get_threads(self.pid, &self.lock)
+    }
+
+    /// Returns the ids of the lwps in the process from /proc/pid/lwp
+    pub fn thread_ids(&self) -> Result<Vec<Tid>, Error> {
+        procfs::lwps(self.pid).map_err(|e| Error::from_os_error(self.pid, e))
+    }
+
+    pub fn lock(&self) -> Result<Arc<ProcessLock>, Error> {
+        process_lock(self.pid, &self.lock)
+    }
+
+    /// Locks the process, and returns a lock containing a consistent snapshot of the threads
+    /// in the process. PCSTOP stops every lwp in the process, so the thread list gathered by
+    /// the lock is already stable.
+    pub fn lock_and_snapshot(&self) -> Result<Arc<ProcessLock>, Error> {
+        self.lock()
+    }
+
+    pub fn child_processes(&self) -> Result<Vec<(Pid, Pid)>, Error> {
+        let mut processes = HashMap::new();
+        for entry in std::fs::read_dir("/proc")? {
+            let pid = match entry?.file_name().to_str().and_then(|s| s.parse().ok()) {
+                Some(pid) => pid,
+                None => continue,
+            };
+            // processes can exit while we're iterating, so ignore errors here
+            if let Ok(info) = procfs::psinfo(pid) {
+                processes.insert(info.pr_pid, info.pr_ppid);
+            }
+        }
+        Ok(crate::filter_child_pids(self.pid, &processes))
+    }
+}
+
+impl Thread {
+    pub fn id(&self) -> Result<Tid, Error> {
+        Ok(self.tid)
+    }
+
+    /// Returns a new handle to the same thread
+    pub fn try_clone(&self) -> Result<Thread, Error> {
+        Ok(Thread {
+            tid: self.tid,
+            pid: self.pid,
+            lock: Arc::clone(&self.lock),
+        })
+    }
+
+    /// True if the lwp is running or runnable
+    pub fn active(&self) -> Result<bool, Error> {
+        let info =
+            procfs::lwpsinfo(self.pid, self.tid).map_err(|e| Error::from_os_error(self.pid, e))?;
+        Ok(info.pr_sname == b'O' as libc::c_char || info.pr_sname == b'R' as libc::c_char)
+    }
+
+    pub fn lock(&self) -> Result<Arc<ProcessLock>, Error> {
+        process_lock(self.pid, &self.lock)
+    }
+}
+
+impl ProcessMemory for Process {
+    fn read(&self, addr: usize, buf: &mut [u8]) -> Result<(), Error> {
+        self.read_options
+            .read_chunked(addr, buf, |addr, buf| {
+                Ok(self.address_space.read_exact_at(buf, addr as u64)?)
+            })
+            .map_err(|e| Error::from_read_error(self.pid, addr, buf.len(), e))
+    }
+}
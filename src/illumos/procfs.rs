@@ -0,0 +1,145 @@
+//! Readers for the binary structures exposed by /proc on illumos and Solaris, see proc(5)
+use libc::{c_char, c_int, c_long, c_short, c_uint, c_ushort, pid_t, size_t, uintptr_t};
+
+use std::fs::File;
+use std::io::{Error, Read, Write};
+
+const PRFNSZ: usize = 16;
+const PRARGSZ: usize = 80;
+const PRCLSZ: usize = 8;
+
+/// Control messages written to /proc/pid/ctl
+const PCSTOP: c_long = 1;
+const PCRUN: c_long = 5;
+
+#[allow(dead_code)]
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct timestruc_t {
+    pub tv_sec: libc::time_t,
+    pub tv_nsec: c_long,
+}
+
+/// The leading fields of psinfo_t. The kernel may return more data than this, which is ignored.
+#[allow(dead_code)]
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct psinfo {
+    pub pr_flag: c_int,
+    pub pr_nlwp: c_int,
+    pub pr_pid: pid_t,
+    pub pr_ppid: pid_t,
+    pub pr_pgid: pid_t,
+    pub pr_sid: pid_t,
+    pub pr_uid: c_uint,
+    pub pr_euid: c_uint,
+    pub pr_gid: c_uint,
+    pub pr_egid: c_uint,
+    pub pr_addr: uintptr_t,
+    pub pr_size: size_t,
+    pub pr_rssize: size_t,
+    pub pr_pad1: size_t,
+    pub pr_ttydev: libc::dev_t,
+    pub pr_pctcpu: c_ushort,
+    pub pr_pctmem: c_ushort,
+    pr_start: timestruc_t,
+    pr_time: timestruc_t,
+    pr_ctime: timestruc_t,
+    pub pr_fname: [c_char; PRFNSZ],
+    pub pr_psargs: [c_char; PRARGSZ],
+    pub pr_wstat: c_int,
+    pub pr_argc: c_int,
+    pub pr_argv: uintptr_t,
+    pub pr_envp: uintptr_t,
+    pub pr_dmodel: c_char,
+}
+
+/// The leading fields of lwpsinfo_t
+#[allow(dead_code)]
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct lwpsinfo {
+    pub pr_flag: c_int,
+    pub pr_lwpid: c_int,
+    pub pr_addr: uintptr_t,
+    pub pr_wchan: uintptr_t,
+    pub pr_stype: c_char,
+    pub pr_state: c_char,
+    pub pr_sname: c_char,
+    pub pr_nice: c_char,
+    pub pr_syscall: c_short,
+    pub pr_oldpri: c_char,
+    pub pr_cpu: c_char,
+    pub pr_pri: c_int,
+    pub pr_pctcpu: c_ushort,
+    pub pr_pad: c_ushort,
+    pub pr_start: timestruc_t,
+    /// The CPU time used by the lwp
+    pub pr_time: timestruc_t,
+    pub pr_clname: [c_char; PRCLSZ],
+    pub pr_oldname: [c_char; PRFNSZ],
+    /// The processor the lwp last ran on
+    pub pr_onpro: c_int,
+    /// The processor the lwp is bound to, or PBIND_NONE
+    pub pr_bindpro: c_int,
+}
+
+/// Reads the start of a binary /proc file into a T
+fn read_struct<T: Copy>(path: &str) -> Result<T, Error> {
+    let mut buffer = Vec::new();
+    File::open(path)?.read_to_end(&mut buffer)?;
+    if buffer.len() < std::mem::size_of::<T>() {
+        return Err(Error::new(
+            std::io::ErrorKind::UnexpectedEof,
+            format!("{} is too short", path),
+        ));
+    }
+    Ok(unsafe { std::ptr::read_unaligned(buffer.as_ptr() as *const T) })
+}
+
+pub fn psinfo(pid: pid_t) -> Result<psinfo, Error> {
+    read_struct(&format!("/proc/{}/psinfo", pid))
+}
+
+pub fn lwpsinfo(pid: pid_t, lwpid: c_int) -> Result<lwpsinfo, Error> {
+    read_struct(&format!("/proc/{}/lwp/{}/lwpsinfo", pid, lwpid))
+}
+
+/// Returns the name of an lwp, which isn't part of lwpsinfo_t on every version
+pub fn lwpname(pid: pid_t, lwpid: c_int) -> Option<String> {
+    let name = std::fs::read(format!("/proc/{}/lwp/{}/lwpname", pid, lwpid)).ok()?;
+    let end = name.iter().position(|b| *b == 0).unwrap_or(name.len());
+    if end == 0 {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&name[..end]).into_owned())
+}
+
+/// Returns the ids of all the lwps (threads) in a process
+pub fn lwps(pid: pid_t) -> Result<Vec<c_int>, Error> {
+    let mut ret = Vec::new();
+    for entry in std::fs::read_dir(format!("/proc/{}/lwp", pid))? {
+        if let Some(lwpid) = entry?.file_name().to_str().and_then(|s| s.parse().ok()) {
+            ret.push(lwpid);
+        }
+    }
+    Ok(ret)
+}
+
+/// Opens the control file of a process, and directs it to stop. The process stays stopped
+/// until `run` is called.
+pub fn stop(pid: pid_t) -> Result<File, Error> {
+    let mut ctl = std::fs::OpenOptions::new()
+        .write(true)
+        .open(format!("/proc/{}/ctl", pid))?;
+    ctl.write_all(&PCSTOP.to_ne_bytes())?;
+    Ok(ctl)
+}
+
+/// Sets a process stopped with `stop` running again
+pub fn run(ctl: &mut File) -> Result<(), Error> {
+    let mut message = Vec::with_capacity(2 * std::mem::size_of::<c_long>());
+    message.extend_from_slice(&PCRUN.to_ne_bytes());
+    message.extend_from_slice(&(0 as c_long).to_ne_bytes());
+    ctl.write_all(&message)
+}
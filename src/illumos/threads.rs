@@ -0,0 +1,88 @@
+//! Details of the threads of a process. `threads_iter` lists the lwps lazily, reading the
+//! lwpsinfo of each as the iterator reaches it.
+use std::time::Duration;
+
+use super::{procfs, Pid, Process, Thread, Tid};
+use crate::{Error, ThreadInfo, ThreadState};
+
+const PBIND_NONE: libc::c_int = -1;
+
+/// An iterator over the threads of a process, returned by `Process::threads_iter`
+pub struct ThreadIter {
+    pid: Pid,
+    lwps: std::vec::IntoIter<Tid>,
+}
+
+impl Process {
+    /// Returns an iterator over the threads of the process, with the name, state and CPU
+    /// time of each. Threads that exit while iterating are skipped.
+    pub fn threads_iter(&self) -> Result<ThreadIter, Error> {
+        let lwps = procfs::lwps(self.pid).map_err(|e| Error::from_os_error(self.pid, e))?;
+        Ok(ThreadIter {
+            pid: self.pid,
+            lwps: lwps.into_iter(),
+        })
+    }
+}
+
+impl Iterator for ThreadIter {
+    type Item = Result<ThreadInfo, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let tid = self.lwps.next()?;
+            let info = match procfs::lwpsinfo(self.pid, tid) {
+                Ok(info) => info,
+                // the lwp exited after the directory was read
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Some(Err(Error::from_os_error(self.pid, e))),
+            };
+            let state = match info.pr_sname as u8 {
+                b'O' | b'R' => ThreadState::Running,
+                b'S' | b'W' => ThreadState::Sleeping,
+                b'T' => ThreadState::Stopped,
+                b'Z' => ThreadState::Zombie,
+                _ => ThreadState::Unknown,
+            };
+            let cpu_time = Duration::new(
+                info.pr_time.tv_sec.max(0) as u64,
+                info.pr_time.tv_nsec.max(0) as u32,
+            );
+            return Some(Ok(ThreadInfo {
+                tid,
+                name: procfs::lwpname(self.pid, tid),
+                state,
+                cpu_time,
+            }));
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.lwps.len()))
+    }
+}
+
+impl Thread {
+    /// Returns the CPUs the thread is allowed to run on. This is the processor the lwp is
+    /// bound to, or every online CPU if it isn't bound - processor sets aren't taken into
+    /// account.
+    pub fn affinity(&self) -> Result<Vec<usize>, Error> {
+        let info =
+            procfs::lwpsinfo(self.pid, self.tid).map_err(|e| Error::from_os_error(self.pid, e))?;
+        if info.pr_bindpro != PBIND_NONE {
+            return Ok(vec![info.pr_bindpro as usize]);
+        }
+        let cpus = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_ONLN) };
+        if cpus < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok((0..cpus as usize).collect())
+    }
+
+    /// Returns the CPU the thread last ran on
+    pub fn last_cpu(&self) -> Result<Option<usize>, Error> {
+        let info =
+            procfs::lwpsinfo(self.pid, self.tid).map_err(|e| Error::from_os_error(self.pid, e))?;
+        Ok(usize::try_from(info.pr_onpro).ok())
+    }
+}
@@ -18,6 +18,10 @@
 //! * the OSX stack unwinding code is very unstable and shouldn't be relied on
 //! * Getting the cwd on windows returns incorrect results
 //!
+//! `Process` is `Send` and `Sync` on every platform, so one can be shared between threads
+//! with an `Arc` and read from concurrently. Locking a process or thread still stops it for
+//! every user of the `Process` until the lock is dropped.
+//!
 //! # Example
 //!
 //! ```rust,no_run
@@ -56,9 +60,9 @@ mod osx;
 #[cfg(target_os = "macos")]
 pub use osx::*;
 
-#[cfg(target_os = "linux")]
+#[cfg(any(target_os = "linux", target_os = "android"))]
 mod linux;
-#[cfg(target_os = "linux")]
+#[cfg(any(target_os = "linux", target_os = "android"))]
 pub use linux::*;
 
 #[cfg(target_os = "freebsd")]
@@ -66,26 +70,144 @@ mod freebsd;
 #[cfg(target_os = "freebsd")]
 pub use freebsd::*;
 
+// the elf symbolication and libunwind unwinding shared by linux and freebsd
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+mod elf;
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+pub use elf::*;
+
+#[cfg(any(target_os = "openbsd", target_os = "netbsd"))]
+mod bsd;
+#[cfg(any(target_os = "openbsd", target_os = "netbsd"))]
+pub use bsd::*;
+
+#[cfg(any(target_os = "illumos", target_os = "solaris"))]
+mod illumos;
+#[cfg(any(target_os = "illumos", target_os = "solaris"))]
+pub use illumos::*;
+
 #[cfg(target_os = "windows")]
 mod windows;
 #[cfg(target_os = "windows")]
 pub use windows::*;
 
+mod frame_pointer;
+pub use frame_pointer::{FramePointerCursor, UnwindMode};
+
+#[cfg(test)]
+mod fake_memory;
+
+mod demangle;
+pub use demangle::DemangleOptions;
+
+mod snapshot;
+pub use snapshot::{MemoryChange, MemorySnapshot};
+
+mod priority;
+pub use priority::Priority;
+
+mod terminate;
+pub use terminate::Termination;
+
+mod unwind;
+pub use unwind::{StackCursor, StackUnwinder};
+
+mod symbol_resolver;
+pub use symbol_resolver::{SymbolResolver, SymbolResolverChain};
+
+#[cfg(any(feature = "debuginfod", feature = "symbol-server"))]
+mod download;
+
+#[cfg(feature = "gdb-remote")]
+mod gdb_remote;
+#[cfg(feature = "gdb-remote")]
+pub use gdb_remote::{GdbConnection, GdbLock, GdbRemote};
+
+#[cfg(feature = "pprof")]
+mod pprof;
+#[cfg(feature = "pprof")]
+pub use pprof::PprofBuilder;
+
+#[cfg(feature = "remote")]
+mod remote;
+#[cfg(feature = "remote")]
+pub use remote::{serve, serve_tcp, RemoteConnection, RemoteLock, RemoteProcess};
+
+#[cfg(feature = "async")]
+mod async_process;
+#[cfg(feature = "async")]
+pub use async_process::{AsyncProcess, ProcessEvent};
+
 #[derive(Debug)]
 pub enum Error {
+    /// The OS denied access to the process
+    PermissionDenied {
+        pid: Pid,
+        source: std::io::Error,
+    },
+    /// The process doesn't exist
+    NoSuchProcess {
+        pid: Pid,
+    },
+    /// The process exited while we were accessing it
+    ProcessExited {
+        pid: Pid,
+    },
+    /// The memory range isn't mapped (or readable) in the process
+    InvalidAddress {
+        pid: Pid,
+        addr: usize,
+        len: usize,
+        source: std::io::Error,
+    },
+    /// Failed to unwind the stack of a thread
+    UnwindError {
+        message: String,
+    },
+    /// Failed to resolve the symbols for an address
+    SymbolicationError {
+        addr: u64,
+        message: String,
+    },
     NoBinaryForAddress(u64),
     GoblinError(::goblin::error::Error),
     IOError(std::io::Error),
     Other(String),
     #[cfg(use_libunwind)]
-    LibunwindError(linux::libunwind::Error),
-    #[cfg(target_os = "linux")]
+    LibunwindError(libunwind::Error),
+    #[cfg(any(target_os = "linux", target_os = "android"))]
     NixError(nix::Error),
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    PtraceRestricted(linux::PtraceRestriction),
+    #[cfg(target_os = "macos")]
+    AttachFailed(osx::AttachFailure),
+    /// The process is a protected process, whose memory can't be read
+    #[cfg(windows)]
+    ProtectedProcess(windows::ProtectedProcess),
 }
 
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match *self {
+            Error::PermissionDenied { pid, ref source } => {
+                write!(f, "Permission denied for process {}: {}", pid, source)
+            }
+            Error::NoSuchProcess { pid } => write!(f, "No such process {}", pid),
+            Error::ProcessExited { pid } => write!(f, "Process {} has exited", pid),
+            Error::InvalidAddress {
+                pid,
+                addr,
+                len,
+                ref source,
+            } => write!(
+                f,
+                "Failed to read {} bytes at 0x{:016x} from process {}: {}",
+                len, addr, pid, source
+            ),
+            Error::UnwindError { ref message } => write!(f, "Failed to unwind: {}", message),
+            Error::SymbolicationError { addr, ref message } => {
+                write!(f, "Failed to symbolicate 0x{:016x}: {}", addr, message)
+            }
             Error::NoBinaryForAddress(addr) => {
                 write!(
                     f,
@@ -98,8 +220,14 @@ impl std::fmt::Display for Error {
             Error::Other(ref e) => write!(f, "{}", e),
             #[cfg(use_libunwind)]
             Error::LibunwindError(ref e) => e.fmt(f),
-            #[cfg(target_os = "linux")]
+            #[cfg(any(target_os = "linux", target_os = "android"))]
             Error::NixError(ref e) => e.fmt(f),
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            Error::PtraceRestricted(ref e) => e.fmt(f),
+            #[cfg(target_os = "macos")]
+            Error::AttachFailed(ref e) => e.fmt(f),
+            #[cfg(windows)]
+            Error::ProtectedProcess(ref e) => e.fmt(f),
         }
     }
 }
@@ -107,17 +235,81 @@ impl std::fmt::Display for Error {
 impl std::error::Error for Error {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match *self {
+            Error::PermissionDenied { ref source, .. } => Some(source),
+            Error::InvalidAddress { ref source, .. } => Some(source),
             Error::GoblinError(ref e) => Some(e),
             Error::IOError(ref e) => Some(e),
             #[cfg(use_libunwind)]
             Error::LibunwindError(ref e) => Some(e),
-            #[cfg(target_os = "linux")]
+            #[cfg(any(target_os = "linux", target_os = "android"))]
             Error::NixError(ref e) => Some(e),
             _ => None,
         }
     }
 }
 
+impl Error {
+    /// Converts an OS error from accessing a process into a structured error, where
+    /// the cause can be determined
+    pub(crate) fn from_os_error(pid: Pid, err: std::io::Error) -> Error {
+        match os_error_kind(&err) {
+            OsErrorKind::PermissionDenied => Error::PermissionDenied { pid, source: err },
+            OsErrorKind::NoSuchProcess => Error::NoSuchProcess { pid },
+            _ => Error::IOError(err),
+        }
+    }
+
+    /// Converts an error from reading memory in a process into a structured error
+    pub(crate) fn from_read_error(pid: Pid, addr: usize, len: usize, err: Error) -> Error {
+        let err = match err {
+            Error::IOError(err) => err,
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            Error::NixError(errno) => std::io::Error::from_raw_os_error(errno as i32),
+            err => return err,
+        };
+        match os_error_kind(&err) {
+            OsErrorKind::PermissionDenied => Error::PermissionDenied { pid, source: err },
+            OsErrorKind::NoSuchProcess => Error::ProcessExited { pid },
+            OsErrorKind::InvalidAddress => Error::InvalidAddress {
+                pid,
+                addr,
+                len,
+                source: err,
+            },
+            OsErrorKind::Other => Error::IOError(err),
+        }
+    }
+}
+
+enum OsErrorKind {
+    PermissionDenied,
+    NoSuchProcess,
+    InvalidAddress,
+    Other,
+}
+
+#[cfg(unix)]
+fn os_error_kind(err: &std::io::Error) -> OsErrorKind {
+    match err.raw_os_error() {
+        Some(libc::EPERM) | Some(libc::EACCES) => OsErrorKind::PermissionDenied,
+        Some(libc::ESRCH) | Some(libc::ENOENT) => OsErrorKind::NoSuchProcess,
+        Some(libc::EFAULT) | Some(libc::EIO) => OsErrorKind::InvalidAddress,
+        _ => OsErrorKind::Other,
+    }
+}
+
+#[cfg(windows)]
+fn os_error_kind(err: &std::io::Error) -> OsErrorKind {
+    // ERROR_ACCESS_DENIED, ERROR_INVALID_PARAMETER (returned by OpenProcess for an
+    // unknown pid), ERROR_PARTIAL_COPY and ERROR_NOACCESS
+    match err.raw_os_error() {
+        Some(5) => OsErrorKind::PermissionDenied,
+        Some(87) => OsErrorKind::NoSuchProcess,
+        Some(299) | Some(998) => OsErrorKind::InvalidAddress,
+        _ => OsErrorKind::Other,
+    }
+}
+
 impl From<goblin::error::Error> for Error {
     fn from(err: goblin::error::Error) -> Error {
         Error::GoblinError(err)
@@ -130,7 +322,7 @@ impl From<std::io::Error> for Error {
     }
 }
 
-#[cfg(target_os = "linux")]
+#[cfg(any(target_os = "linux", target_os = "android"))]
 impl From<nix::Error> for Error {
     fn from(err: nix::Error) -> Error {
         Error::NixError(err)
@@ -138,25 +330,319 @@ impl From<nix::Error> for Error {
 }
 
 #[cfg(use_libunwind)]
-impl From<linux::libunwind::Error> for Error {
-    fn from(err: linux::libunwind::Error) -> Error {
+impl From<libunwind::Error> for Error {
+    fn from(err: libunwind::Error) -> Error {
         Error::LibunwindError(err)
     }
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct StackFrame {
     pub line: Option<u64>,
+    /// The column within the line, when the debug info records one. Like the filename and
+    /// line this is only looked up when symbolicating with line info, since that is slow.
+    pub column: Option<u64>,
     pub filename: Option<String>,
     pub function: Option<String>,
     pub module: String,
     pub addr: u64,
+    /// The address relative to the module it's in, as the module's own symbols and debug
+    /// info see it - so unlike `addr` this is the same in every process that loads the module,
+    /// wherever it gets loaded. None for frames that aren't in a module, like JIT compiled code.
+    pub module_offset: Option<u64>,
+    /// True if this function was inlined into the frame that follows it. Symbolicating a
+    /// single address can return several frames when the compiler has inlined functions,
+    /// innermost first - and only the last of these is a real frame on the stack.
+    pub inlined: bool,
+}
+
+/// What uniquely identifies a binary, and so the debug info built alongside it
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ModuleId {
+    /// The GNU build-id note of an ELF binary
+    BuildId(Vec<u8>),
+    /// The LC_UUID load command of a Mach-O binary
+    Uuid([u8; 16]),
+    /// The GUID and age of the PDB that a PE binary was linked with, along with the PDB's
+    /// file name
+    Pdb {
+        guid: [u8; 16],
+        age: u32,
+        name: String,
+    },
+}
+
+/// A binary loaded by a process
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Module {
+    pub filename: String,
+    pub address: u64,
+    pub size: u64,
+    /// The amount to subtract from an address in this module to get the address in the
+    /// binary itself - the virtual address in an ELF or Mach-O file, or the RVA in a PE file
+    pub bias: u64,
+    /// None if the binary wasn't built with an identifier, or couldn't be read
+    pub id: Option<ModuleId>,
+}
+
+/// An address in a process along with the module that contains it, as returned by
+/// `Process::normalize_address`
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NormalizedAddress {
+    pub module: Module,
+    /// The address relative to the module, the same as `StackFrame::module_offset`
+    pub offset: u64,
+}
+
+/// A file descriptor, or on Windows a file handle, that a process has open
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OpenFile {
+    pub fd: u64,
+    /// The path of the file. On linux this is the target of the /proc/pid/fd link, which
+    /// for sockets, pipes and other descriptors without a path is something like
+    /// `socket:[1234]`.
+    pub path: String,
+}
+
+/// The instruction set a process was compiled for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Architecture {
+    X86,
+    X86_64,
+    Arm,
+    Aarch64,
+    RiscV,
+    LoongArch,
+    Unknown,
+}
+
+/// The order that the bytes of a value are stored in memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Endianness {
+    Little,
+    Big,
+}
+
+/// The architecture of a process returned by `Process::arch`. This can differ from the
+/// architecture of the current process, like for 32-bit processes on a 64-bit host, or
+/// processes translated by Rosetta on OSX.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TargetArch {
+    pub architecture: Architecture,
+    /// The size of a pointer in the process, in bytes
+    pub pointer_size: usize,
+    pub endianness: Endianness,
+}
+
+impl TargetArch {
+    pub fn is_64_bit(&self) -> bool {
+        self.pointer_size == 8
+    }
+}
+
+/// The I/O a process has done over its lifetime, returned by `Process::io_counters`. What's
+/// counted differs by platform: on linux and windows this is all I/O (including to pipes,
+/// sockets and the page cache), while on OSX only reads and writes to disk are counted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct IoCounters {
+    pub read_bytes: u64,
+    pub write_bytes: u64,
+    /// The number of read operations, or None where this isn't tracked (OSX)
+    pub read_ops: Option<u64>,
+    /// The number of write operations, or None where this isn't tracked (OSX)
+    pub write_ops: Option<u64>,
+}
+
+/// The transport protocol of a socket returned by `Process::connections`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Protocol {
+    Tcp,
+    Udp,
+}
+
+/// The state of a TCP socket
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TcpState {
+    Closed,
+    Listen,
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    TimeWait,
+}
+
+/// A TCP or UDP socket owned by a process
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Connection {
+    pub protocol: Protocol,
+    pub local: std::net::SocketAddr,
+    /// The address of the peer, or None for listening and unconnected sockets
+    pub remote: Option<std::net::SocketAddr>,
+    /// The state of TCP sockets, or None for UDP
+    pub state: Option<TcpState>,
 }
 
+/// What a thread is doing, according to the scheduler
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ThreadState {
+    /// Running on a CPU, or ready to run
+    Running,
+    /// Waiting for something, and can be woken by a signal
+    Sleeping,
+    /// Waiting for something that can't be interrupted, like disk IO
+    Uninterruptible,
+    /// Stopped by a signal or a debugger, or suspended
+    Stopped,
+    /// Exited, but not yet cleaned up
+    Zombie,
+    Unknown,
+}
+
+/// A thread returned by `Process::threads_iter`
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ThreadInfo {
+    pub tid: Tid,
+    /// The name of the thread, if it has been given one
+    pub name: Option<String>,
+    pub state: ThreadState,
+    /// The CPU time used by the thread, in both user and kernel mode
+    pub cpu_time: std::time::Duration,
+}
+
+/// The access allowed to memory allocated in a target process with `Process::alloc`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Protection {
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+}
+
+impl Protection {
+    pub const READ_WRITE: Protection = Protection {
+        read: true,
+        write: true,
+        execute: false,
+    };
+    pub const READ_EXECUTE: Protection = Protection {
+        read: true,
+        write: false,
+        execute: true,
+    };
+    pub const READ_WRITE_EXECUTE: Protection = Protection {
+        read: true,
+        write: true,
+        execute: true,
+    };
+}
+
+/// A thread of a traced process entering or returning from a syscall, as reported by a
+/// `SyscallTracer`
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SyscallEvent {
+    /// The thread entered a syscall. Syscalls take at most six arguments, and any past the
+    /// ones the syscall uses are meaningless.
+    Enter {
+        tid: Tid,
+        number: u64,
+        args: [u64; 6],
+    },
+    /// The thread returned from a syscall. Failed syscalls return a negated errno.
+    Exit { tid: Tid, number: u64, ret: i64 },
+}
+
+/// A resource whose use by a process is limited, as set with setrlimit
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Resource {
+    CpuTime,
+    FileSize,
+    DataSize,
+    StackSize,
+    CoreFileSize,
+    ResidentSet,
+    Processes,
+    OpenFiles,
+    LockedMemory,
+    AddressSpace,
+    FileLocks,
+    PendingSignals,
+    MessageQueueSize,
+    NicePriority,
+    RealtimePriority,
+    RealtimeTimeout,
+}
+
+/// A resource limit of a process, returned by `Process::rlimits`
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ResourceLimit {
+    pub resource: Resource,
+    /// The limit that is enforced, or None if the resource is unlimited
+    pub soft: Option<u64>,
+    /// The most the soft limit can be raised to, or None if the resource is unlimited
+    pub hard: Option<u64>,
+}
+
+/// The frames that a single address symbolicates to, innermost inlined function first
+pub type Frames = Result<Vec<StackFrame>, Error>;
+
+/// The mapping that contains an address, as passed to the unknown address handler of a
+/// `Symbolicator`
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AddressRegion {
+    pub start: u64,
+    pub size: u64,
+    pub protection: Protection,
+    /// The file the region is mapped from, or the name the OS gives anonymous regions
+    /// like `[heap]`
+    pub filename: Option<String>,
+}
+
+/// Called by a `Symbolicator` with the addresses that aren't in any module it knows about,
+/// along with the region that contains them if the address is mapped. This can return the
+/// frames for JIT compiled or interpreted code, or None to leave the address unresolved.
+pub type UnknownAddressHandler =
+    Box<dyn Fn(u64, Option<&AddressRegion>) -> Option<Vec<StackFrame>> + Send>;
+
 impl std::fmt::Display for StackFrame {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         let function = self.function.as_ref().map(String::as_str).unwrap_or("?");
-        if let Some(filename) = self.filename.as_ref() {
+        if let (Some(filename), Some(column)) = (self.filename.as_ref(), self.column) {
+            write!(
+                f,
+                "0x{:016x} {} ({}:{}:{})",
+                self.addr,
+                function,
+                filename,
+                self.line.unwrap_or(0),
+                column
+            )
+        } else if let Some(filename) = self.filename.as_ref() {
             write!(
                 f,
                 "0x{:016x} {} ({}:{})",
@@ -171,6 +657,12 @@ impl std::fmt::Display for StackFrame {
     }
 }
 
+/// Reads memory from a process, or from anything standing in for one like a core dump.
+///
+/// The trait is object safe, so code can be written against `&dyn ProcessMemory` or
+/// `Arc<dyn ProcessMemory + Send + Sync>`. The generic helpers like `copy_struct` can't be
+/// called on a bare `dyn ProcessMemory`, but can on boxes and `Arc`s of one, and references
+/// to one implement the trait themselves so can be passed to generic code.
 pub trait ProcessMemory {
     /// Copies memory from another process into an already allocated
     /// byte buffer
@@ -185,28 +677,142 @@ pub trait ProcessMemory {
     }
 
     /// Copies a structure from another process
-    fn copy_struct<T: Copy>(&self, addr: usize) -> Result<T, Error> {
+    fn copy_struct<T: Copy>(&self, addr: usize) -> Result<T, Error>
+    where
+        Self: Sized,
+    {
         let mut data = vec![0; std::mem::size_of::<T>()];
         self.read(addr, &mut data)?;
         Ok(unsafe { std::ptr::read(data.as_ptr() as *const _) })
     }
 
     /// Given a pointer that points to a struct in another process, returns the struct
-    fn copy_pointer<T: Copy>(&self, ptr: *const T) -> Result<T, Error> {
+    fn copy_pointer<T: Copy>(&self, ptr: *const T) -> Result<T, Error>
+    where
+        Self: Sized,
+    {
         self.copy_struct(ptr as usize)
     }
 
     /// Copies a series of bytes from another process into a vector of
     /// structures of type T.
-    fn copy_vec<T: Copy>(&self, addr: usize, length: usize) -> Result<Vec<T>, Error> {
+    fn copy_vec<T: Copy>(&self, addr: usize, length: usize) -> Result<Vec<T>, Error>
+    where
+        Self: Sized,
+    {
         let mut vec = self.copy(addr, length * std::mem::size_of::<T>())?;
         let capacity = vec.capacity() as usize / std::mem::size_of::<T>() as usize;
         let ptr = vec.as_mut_ptr() as *mut T;
         std::mem::forget(vec);
         unsafe { Ok(Vec::from_raw_parts(ptr, capacity, capacity)) }
     }
+
+    /// Copies as much of the memory at `addr` into `buf` as can be read, stopping at the
+    /// first page that isn't readable, and returns the number of bytes copied. Failing to
+    /// read the first page returns the error instead.
+    fn read_partial(&self, addr: usize, buf: &mut [u8]) -> Result<usize, Error> {
+        if self.read(addr, buf).is_ok() {
+            return Ok(buf.len());
+        }
+        // retry a page at a time to find where the readable memory ends
+        let mut copied = 0;
+        while copied < buf.len() {
+            let page_end = ((addr + copied) | (PAGE_SIZE - 1)).saturating_add(1);
+            let end = (page_end - addr).min(buf.len());
+            if let Err(e) = self.read(addr + copied, &mut buf[copied..end]) {
+                if copied == 0 {
+                    return Err(e);
+                }
+                break;
+            }
+            copied = end;
+        }
+        Ok(copied)
+    }
+}
+
+impl<T: ProcessMemory + ?Sized> ProcessMemory for &T {
+    fn read(&self, addr: usize, buf: &mut [u8]) -> Result<(), Error> {
+        (**self).read(addr, buf)
+    }
+
+    fn read_partial(&self, addr: usize, buf: &mut [u8]) -> Result<usize, Error> {
+        (**self).read_partial(addr, buf)
+    }
+}
+
+impl<T: ProcessMemory + ?Sized> ProcessMemory for Box<T> {
+    fn read(&self, addr: usize, buf: &mut [u8]) -> Result<(), Error> {
+        (**self).read(addr, buf)
+    }
+
+    fn read_partial(&self, addr: usize, buf: &mut [u8]) -> Result<usize, Error> {
+        (**self).read_partial(addr, buf)
+    }
+}
+
+impl<T: ProcessMemory + ?Sized> ProcessMemory for std::sync::Arc<T> {
+    fn read(&self, addr: usize, buf: &mut [u8]) -> Result<(), Error> {
+        (**self).read(addr, buf)
+    }
+
+    fn read_partial(&self, addr: usize, buf: &mut [u8]) -> Result<usize, Error> {
+        (**self).read_partial(addr, buf)
+    }
+}
+
+/// How reads of memory from a process are split up and retried, set with
+/// `Process::set_read_options`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ReadOptions {
+    /// The most bytes copied from the process by a single call to the OS. Larger reads are
+    /// split into chunks of this size.
+    pub max_chunk_size: usize,
+    /// How many more times a chunk is read after it fails, before the whole read fails
+    pub retries: u32,
+}
+
+impl Default for ReadOptions {
+    fn default() -> ReadOptions {
+        ReadOptions {
+            max_chunk_size: 1 << 20,
+            retries: 0,
+        }
+    }
 }
 
+impl ReadOptions {
+    /// Fills `buf` from `addr` a chunk at a time, calling `read` for each chunk
+    pub(crate) fn read_chunked<F>(
+        &self,
+        addr: usize,
+        buf: &mut [u8],
+        mut read: F,
+    ) -> Result<(), Error>
+    where
+        F: FnMut(usize, &mut [u8]) -> Result<(), Error>,
+    {
+        let chunk_size = self.max_chunk_size.max(1);
+        for (i, chunk) in buf.chunks_mut(chunk_size).enumerate() {
+            let chunk_addr = addr + i * chunk_size;
+            let mut attempts = 0;
+            loop {
+                match read(chunk_addr, chunk) {
+                    Ok(()) => break,
+                    Err(_) if attempts < self.retries => attempts += 1,
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The smallest page size of the supported platforms. Memory is mapped in whole pages, so
+/// a read that doesn't cross a multiple of this either succeeds or fails entirely.
+const PAGE_SIZE: usize = 4096;
+
 #[doc(hidden)]
 /// Mock for using ProcessMemory on the local process.
 pub struct LocalProcess;
@@ -219,7 +825,141 @@ impl ProcessMemory for LocalProcess {
     }
 }
 
-#[cfg(any(target_os = "linux", target_os = "windows", target_os = "freebsd"))]
+/// The registers and stack of a thread, copied by `Thread::copy_stack` while the thread was
+/// suspended so that it can be unwound after it has been resumed. Reads from the copy are
+/// served from the stack, and fail for any other address.
+#[derive(Clone)]
+pub struct StackCopy<R> {
+    pub tid: Tid,
+    pub registers: R,
+    /// The stack pointer of the thread, which is the address of the first byte of `stack`
+    pub sp: u64,
+    /// The memory from the stack pointer up to the base of the stack, truncated to the
+    /// maximum size that was requested
+    pub stack: Vec<u8>,
+}
+
+impl<R> ProcessMemory for StackCopy<R> {
+    fn read(&self, addr: usize, buf: &mut [u8]) -> Result<(), Error> {
+        let offset = (addr as u64).wrapping_sub(self.sp) as usize;
+        match self.stack.get(offset..offset.saturating_add(buf.len())) {
+            Some(data) if (addr as u64) >= self.sp => {
+                buf.copy_from_slice(data);
+                Ok(())
+            }
+            _ => Err(Error::Other(format!(
+                "address {:#x} isn't in the copied stack of thread {}",
+                addr, self.tid
+            ))),
+        }
+    }
+}
+
+/// Returns the number of CPUs that are online, which processes can be scheduled on
+#[cfg(unix)]
+pub fn online_cpus() -> Result<usize, Error> {
+    let cpus = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_ONLN) };
+    if cpus < 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(cpus as usize)
+}
+
+/// Returns the number of CPUs that are online, across every processor group
+#[cfg(windows)]
+pub fn online_cpus() -> Result<usize, Error> {
+    // ALL_PROCESSOR_GROUPS
+    let cpus = unsafe { winapi::um::winbase::GetActiveProcessorCount(0xffff) };
+    if cpus == 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(cpus as usize)
+}
+
+/// Returns the architecture of an ELF binary from the start of its header
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd",
+    target_os = "illumos",
+    target_os = "solaris"
+))]
+fn elf_arch(header: &[u8]) -> Result<TargetArch, Error> {
+    if header.len() < 20 || &header[..4] != b"\x7fELF" {
+        return Err(Error::Other("not an ELF binary".into()));
+    }
+    let pointer_size = match header[4] {
+        1 => 4,
+        2 => 8,
+        class => return Err(Error::Other(format!("unknown ELF class {}", class))),
+    };
+    let endianness = match header[5] {
+        1 => Endianness::Little,
+        2 => Endianness::Big,
+        data => return Err(Error::Other(format!("unknown ELF data encoding {}", data))),
+    };
+    let machine = match endianness {
+        Endianness::Little => u16::from_le_bytes([header[18], header[19]]),
+        Endianness::Big => u16::from_be_bytes([header[18], header[19]]),
+    };
+    let architecture = match machine {
+        3 => Architecture::X86,
+        62 => Architecture::X86_64,
+        40 => Architecture::Arm,
+        183 => Architecture::Aarch64,
+        243 => Architecture::RiscV,
+        258 => Architecture::LoongArch,
+        _ => Architecture::Unknown,
+    };
+    Ok(TargetArch {
+        architecture,
+        pointer_size,
+        endianness,
+    })
+}
+
+/// Returns the architecture of the ELF binary at `path`
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd",
+    target_os = "illumos",
+    target_os = "solaris"
+))]
+fn read_elf_arch(pid: Pid, path: &str) -> Result<TargetArch, Error> {
+    use std::io::Read;
+    let mut header = [0; 20];
+    std::fs::File::open(path)
+        .and_then(|mut file| file.read_exact(&mut header))
+        .map_err(|e| Error::from_os_error(pid, e))?;
+    elf_arch(&header)
+}
+
+/// Attaches to a process spawned by this one. A child isn't reaped until it's waited on, so
+/// its pid can't be reused by another process while the `Child` is alive.
+#[cfg(unix)]
+impl TryFrom<&std::process::Child> for Process {
+    type Error = Error;
+
+    fn try_from(child: &std::process::Child) -> Result<Process, Error> {
+        Process::new(child.id() as Pid)
+    }
+}
+
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "windows",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd",
+    target_os = "illumos",
+    target_os = "solaris"
+))]
 #[doc(hidden)]
 /// Filters pids to own include descendations of target_pid
 fn filter_child_pids(
@@ -266,6 +1006,33 @@ pub mod tests {
         assert_eq!(original.y, copy.y);
     }
 
+    #[cfg(unix)]
+    #[test]
+    fn test_from_read_error() {
+        let err = |errno| Error::IOError(std::io::Error::from_raw_os_error(errno));
+        assert!(matches!(
+            Error::from_read_error(1, 0x1000, 8, err(libc::EFAULT)),
+            Error::InvalidAddress {
+                pid: 1,
+                addr: 0x1000,
+                len: 8,
+                ..
+            }
+        ));
+        assert!(matches!(
+            Error::from_read_error(1, 0x1000, 8, err(libc::EPERM)),
+            Error::PermissionDenied { pid: 1, .. }
+        ));
+        assert!(matches!(
+            Error::from_read_error(1, 0x1000, 8, err(libc::ESRCH)),
+            Error::ProcessExited { pid: 1 }
+        ));
+        assert!(matches!(
+            Error::from_read_error(1, 0x1000, 8, Error::Other("other".into())),
+            Error::Other(_)
+        ));
+    }
+
     #[test]
     fn test_copy_struct() {
         let original = Point { x: 10, y: 20 };
@@ -275,4 +1042,256 @@ pub mod tests {
         assert_eq!(original.x, copy.x);
         assert_eq!(original.y, copy.y);
     }
+
+    /// Memory that is only readable from `start` to `end`
+    struct Mapped {
+        start: usize,
+        end: usize,
+    }
+
+    impl ProcessMemory for Mapped {
+        fn read(&self, addr: usize, buf: &mut [u8]) -> Result<(), Error> {
+            if addr < self.start || addr + buf.len() > self.end {
+                return Err(Error::Other(format!("invalid address {:#x}", addr)));
+            }
+            for (i, byte) in buf.iter_mut().enumerate() {
+                *byte = (addr + i) as u8;
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_read_partial() {
+        let memory = Mapped {
+            start: 0x1000,
+            end: 0x4000,
+        };
+        let mut buf = vec![0; 0x300];
+        assert_eq!(memory.read_partial(0x1100, &mut buf).unwrap(), 0x300);
+        assert_eq!(memory.read_partial(0x3e00, &mut buf).unwrap(), 0x200);
+        assert_eq!(buf[..4], [0, 1, 2, 3]);
+        assert!(memory.read_partial(0x4000, &mut buf).is_err());
+        assert!(memory.read_partial(0xf00, &mut buf).is_err());
+
+        let mut buf = vec![0; 0x3000];
+        assert_eq!(memory.read_partial(0x1800, &mut buf).unwrap(), 0x2800);
+        assert_eq!(memory.read_partial(0x1000, &mut buf).unwrap(), 0x3000);
+    }
+
+    #[test]
+    fn test_read_chunked() {
+        let options = ReadOptions {
+            max_chunk_size: 0x100,
+            retries: 0,
+        };
+        let mut chunks = Vec::new();
+        let mut buf = vec![0; 0x250];
+        options
+            .read_chunked(0x1000, &mut buf, |addr, buf| {
+                chunks.push((addr, buf.len()));
+                buf.fill(1);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(chunks, [(0x1000, 0x100), (0x1100, 0x100), (0x1200, 0x50)]);
+        assert!(buf.iter().all(|byte| *byte == 1));
+
+        // a chunk that fails is retried, up to the limit
+        let read = |failures: u32| {
+            let mut remaining = failures;
+            move |_: usize, _: &mut [u8]| {
+                if remaining > 0 {
+                    remaining -= 1;
+                    return Err(Error::Other("transient".into()));
+                }
+                Ok(())
+            }
+        };
+        let options = ReadOptions {
+            max_chunk_size: 0x1000,
+            retries: 2,
+        };
+        assert!(options.read_chunked(0x1000, &mut buf, read(2)).is_ok());
+        assert!(options.read_chunked(0x1000, &mut buf, read(3)).is_err());
+    }
+
+    #[test]
+    fn test_dyn_process_memory() {
+        let original = Point { x: 15, y: 25 };
+        let memory: std::sync::Arc<dyn ProcessMemory + Send + Sync> =
+            std::sync::Arc::new(LocalProcess);
+        let copy: Point = memory
+            .copy_struct(&original as *const Point as usize)
+            .unwrap();
+        assert_eq!((copy.x, copy.y), (15, 25));
+
+        // references to a trait object can be passed to generic code
+        fn copy_point<P: ProcessMemory>(memory: P, point: &Point) -> Point {
+            memory.copy_pointer(point).unwrap()
+        }
+        let memory: &dyn ProcessMemory = &LocalProcess;
+        let copy = copy_point(memory, &original);
+        assert_eq!((copy.x, copy.y), (15, 25));
+    }
+
+    #[test]
+    fn test_process_is_send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Process>();
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos", target_os = "windows"))]
+    #[test]
+    fn test_try_clone() {
+        let value: u64 = 0xfeedface;
+        let clone = {
+            let process = Process::new(std::process::id() as Pid).unwrap();
+            process.try_clone().unwrap()
+        };
+        let copy: u64 = clone.copy_struct(&value as *const u64 as usize).unwrap();
+        assert_eq!(copy, value);
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    #[test]
+    fn test_process_from_child() {
+        let mut child = std::process::Command::new("sleep")
+            .arg("10")
+            .spawn()
+            .unwrap();
+        let process = Process::try_from(&child).unwrap();
+        assert_eq!(process.pid, child.id() as Pid);
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos", target_os = "windows"))]
+    #[test]
+    fn test_process_affinity() {
+        let process = Process::new(std::process::id() as Pid).unwrap();
+        let online = online_cpus().unwrap();
+        let affinity = process.affinity().unwrap();
+        assert!(!affinity.is_empty());
+        assert!(affinity.len() <= online);
+    }
+
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    #[test]
+    fn test_stack_unwinder() {
+        // written against the traits, so it works with any backend
+        fn first_frame<U: StackUnwinder>(unwinder: &U, thread: &Thread) -> (u64, u64) {
+            let mut cursor = unwinder.cursor(thread).unwrap();
+            let ip = cursor.next().unwrap().unwrap();
+            assert_eq!(cursor.ip().unwrap(), ip);
+            (ip, cursor.sp().unwrap())
+        }
+
+        let mut child = std::process::Command::new("sleep")
+            .arg("10")
+            .spawn()
+            .unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(100));
+
+        let process = Process::new(child.id() as Pid).unwrap();
+        let thread = Thread::new(child.id() as Pid).unwrap();
+        let unwinder = process.dwarf_unwinder().unwrap();
+        {
+            let _lock = thread.lock().unwrap();
+            let registers = thread.registers().unwrap();
+            let (ip, sp) = first_frame(&unwinder, &thread);
+            assert_eq!(ip, registers.rip);
+            assert_eq!(sp, registers.rsp);
+        }
+
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+
+    #[cfg(all(use_libunwind, target_os = "linux"))]
+    #[test]
+    fn test_unknown_address_handler() {
+        let process = Process::new(std::process::id() as Pid).unwrap();
+        let mut symbolicator = process.symbolicator().unwrap();
+        // heap memory isn't in any binary, like JIT compiled code
+        let buffer = vec![0u8; 4096];
+        let addr = buffer.as_ptr() as u64;
+        assert!(matches!(
+            symbolicator.symbolicate(addr, false, &mut |_| {}),
+            Err(Error::NoBinaryForAddress(_))
+        ));
+
+        symbolicator.set_unknown_address_handler(Some(Box::new(|addr, region| {
+            let region = region?;
+            Some(vec![StackFrame {
+                line: None,
+                column: None,
+                filename: None,
+                function: Some("jit".to_owned()),
+                module: String::new(),
+                addr,
+                module_offset: Some(addr - region.start),
+                inlined: false,
+            }])
+        })));
+        let mut frames = Vec::new();
+        symbolicator
+            .symbolicate(addr, false, &mut |frame| frames.push(frame.clone()))
+            .unwrap();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].function.as_deref(), Some("jit"));
+        assert!(frames[0].module_offset.unwrap() <= addr);
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[test]
+    fn test_elf_arch() {
+        let mut header = [0u8; 20];
+        header[..4].copy_from_slice(b"\x7fELF");
+        header[4] = 2;
+        header[5] = 1;
+        header[18] = 183;
+        let arch = elf_arch(&header).unwrap();
+        assert_eq!(arch.architecture, Architecture::Aarch64);
+        assert_eq!(arch.endianness, Endianness::Little);
+        assert!(arch.is_64_bit());
+
+        header[4] = 1;
+        header[5] = 2;
+        header[18] = 0;
+        header[19] = 40;
+        let arch = elf_arch(&header).unwrap();
+        assert_eq!(arch.architecture, Architecture::Arm);
+        assert_eq!(arch.endianness, Endianness::Big);
+        assert_eq!(arch.pointer_size, 4);
+
+        assert!(elf_arch(b"MZ").is_err());
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos", target_os = "windows"))]
+    #[test]
+    fn test_process_arch() {
+        let process = Process::new(std::process::id() as Pid).unwrap();
+        let arch = process.arch().unwrap();
+        assert_eq!(arch.pointer_size, std::mem::size_of::<usize>());
+        assert_eq!(
+            process.is_64_bit().unwrap(),
+            cfg!(target_pointer_width = "64")
+        );
+    }
+
+    #[test]
+    fn test_stack_copy_read() {
+        let copy = StackCopy {
+            tid: 1,
+            registers: (),
+            sp: 0x1000,
+            stack: (0..16).collect(),
+        };
+        assert_eq!(copy.copy(0x1000, 4).unwrap(), vec![0, 1, 2, 3]);
+        assert_eq!(copy.copy(0x100c, 4).unwrap(), vec![12, 13, 14, 15]);
+        assert!(copy.copy(0x100d, 4).is_err());
+        assert!(copy.copy(0xffc, 4).is_err());
+        assert!(copy.copy(0, 4).is_err());
+    }
 }
@@ -0,0 +1,95 @@
+//! Allocates memory in a target process, by making it call mmap and munmap
+use super::{Process, Thread};
+use crate::{Error, Protection};
+
+impl Process {
+    /// Maps `len` bytes of zeroed memory into the process, returning its address. The whole
+    /// process is locked while the main thread makes the mmap syscall, so this fails if the
+    /// process is already locked.
+    pub fn alloc(&self, len: usize, prot: Protection) -> Result<u64, Error> {
+        let mut flags = libc::PROT_NONE;
+        if prot.read {
+            flags |= libc::PROT_READ;
+        }
+        if prot.write {
+            flags |= libc::PROT_WRITE;
+        }
+        if prot.execute {
+            flags |= libc::PROT_EXEC;
+        }
+        let args = [
+            0,
+            len as u64,
+            flags as u64,
+            (libc::MAP_PRIVATE | libc::MAP_ANONYMOUS) as u64,
+            -1_i64 as u64,
+            0,
+        ];
+        let addr = self.remote_syscall(libc::SYS_mmap as u64, &args)? as u64;
+        self.allocations.lock().unwrap().insert(addr, len);
+        Ok(addr)
+    }
+
+    /// Unmaps memory returned by `alloc` on this `Process`.
+    pub fn free(&self, addr: u64) -> Result<(), Error> {
+        // the kernel merges neighbouring mappings with the same protection, so the length
+        // can't be found from /proc/pid/maps
+        let len = self
+            .allocations
+            .lock()
+            .unwrap()
+            .remove(&addr)
+            .ok_or_else(|| Error::Other(format!("no memory allocated at {:#x}", addr)))?;
+        if let Err(e) = self.remote_syscall(libc::SYS_munmap as u64, &[addr, len as u64]) {
+            self.allocations.lock().unwrap().insert(addr, len);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn remote_syscall(&self, number: u64, args: &[u64]) -> Result<i64, Error> {
+        // every thread is stopped, since the syscall patches the code the main thread is at
+        let _lock = self.lock()?;
+        let ret = Thread::new(self.pid)?.syscall(number, args)?;
+        // errors are returned as -4095 to -1, where anything else is a valid result
+        if (-4095..0).contains(&ret) {
+            return Err(Error::IOError(std::io::Error::from_raw_os_error(
+                -ret as i32,
+            )));
+        }
+        Ok(ret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ProcessMemory;
+
+    #[test]
+    fn test_alloc_free() {
+        let mut child = std::process::Command::new("sleep")
+            .arg("0.5")
+            .spawn()
+            .unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        let process = Process::new(child.id() as i32).unwrap();
+
+        let addr = process.alloc(8192, Protection::READ_WRITE).unwrap();
+        let memory: [u8; 16] = process.copy_struct(addr as usize).unwrap();
+        assert_eq!(memory, [0; 16]);
+        let map = proc_maps::get_process_maps(process.pid)
+            .unwrap()
+            .into_iter()
+            .find(|map| map.start() as u64 <= addr && addr < (map.start() + map.size()) as u64)
+            .unwrap();
+        assert!(map.is_read() && map.is_write() && !map.is_exec());
+
+        process.free(addr).unwrap();
+        assert!(process.copy_struct::<u8>(addr as usize).is_err());
+        assert!(process.free(addr).is_err());
+
+        // the child should carry on sleeping where it left off, and then exit normally
+        assert!(child.wait().unwrap().success());
+    }
+}
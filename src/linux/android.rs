@@ -0,0 +1,281 @@
+//! Android specific support: figuring out how we are able to attach to an app, enumerating
+//! modules through the bionic linker, and finding libraries in APEX modules.
+use std::ffi::CString;
+use std::path::{Path, PathBuf};
+
+use super::{Pid, Process};
+use crate::{Error, ProcessMemory};
+
+const AT_PHDR: usize = 3;
+const AT_PHNUM: usize = 5;
+const PT_PHDR: u32 = 6;
+const PT_DYNAMIC: u32 = 2;
+const DT_NULL: usize = 0;
+const DT_DEBUG: usize = 21;
+
+/// Directories that shared libraries are loaded from, in the order the linker searches them.
+/// Since Android 10 most runtime libraries (libc, libart etc) live in APEX modules instead
+/// of /system/lib.
+#[cfg(target_pointer_width = "64")]
+const LIBRARY_PATHS: &[&str] = &[
+    "/apex/com.android.runtime/lib64/bionic",
+    "/apex/com.android.runtime/lib64",
+    "/apex/com.android.art/lib64",
+    "/apex/com.android.i18n/lib64",
+    "/apex/com.android.conscrypt/lib64",
+    "/system/lib64",
+    "/vendor/lib64",
+    "/odm/lib64",
+    "/product/lib64",
+];
+#[cfg(target_pointer_width = "32")]
+const LIBRARY_PATHS: &[&str] = &[
+    "/apex/com.android.runtime/lib/bionic",
+    "/apex/com.android.runtime/lib",
+    "/apex/com.android.art/lib",
+    "/apex/com.android.i18n/lib",
+    "/apex/com.android.conscrypt/lib",
+    "/system/lib",
+    "/vendor/lib",
+    "/odm/lib",
+    "/product/lib",
+];
+
+/// How the current process is able to attach to an Android process
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AttachMode {
+    /// Running as root, either through `adb root` on a userdebug build or on a rooted device
+    Root,
+    /// Running as the same uid as the target, which is what `run-as <package>` does for
+    /// debuggable apps
+    RunAs,
+    /// Neither of the above, attaching will fail
+    Unprivileged,
+}
+
+impl std::fmt::Display for AttachMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            AttachMode::Root => write!(f, "running as root"),
+            AttachMode::RunAs => write!(f, "running as the app uid"),
+            AttachMode::Unprivileged => write!(
+                f,
+                "not allowed to attach. Run through `adb shell run-as <package>` for debuggable \
+                 apps, or use `adb root` on a userdebug build"
+            ),
+        }
+    }
+}
+
+/// Figures out whether we can attach to a process, and how
+pub fn attach_mode(pid: Pid) -> Result<AttachMode, Error> {
+    if unsafe { libc::geteuid() } == 0 {
+        return Ok(AttachMode::Root);
+    }
+    let metadata =
+        std::fs::metadata(format!("/proc/{}", pid)).map_err(|e| Error::from_os_error(pid, e))?;
+    if std::os::unix::fs::MetadataExt::uid(&metadata) == unsafe { libc::getuid() } {
+        Ok(AttachMode::RunAs)
+    } else {
+        Ok(AttachMode::Unprivileged)
+    }
+}
+
+/// Returns the value of a system property, like `ro.debuggable`
+pub fn system_property(name: &str) -> Option<String> {
+    let name = CString::new(name).ok()?;
+    let mut value = vec![0_u8; libc::PROP_VALUE_MAX as usize];
+    let len = unsafe {
+        libc::__system_property_get(name.as_ptr(), value.as_mut_ptr() as *mut libc::c_char)
+    };
+    if len <= 0 {
+        return None;
+    }
+    value.truncate(len as usize);
+    Some(String::from_utf8_lossy(&value).into_owned())
+}
+
+/// True if this is a userdebug or eng build, where `adb root` is available
+pub fn is_debuggable_build() -> bool {
+    system_property("ro.debuggable").as_deref() == Some("1")
+}
+
+/// Returns the package name of an app process. Zygote renames app processes to their
+/// package name, with a ':name' suffix for processes declared with android:process.
+pub fn package_name(process: &Process) -> Result<String, Error> {
+    let cmdline = process.cmdline()?;
+    let name = cmdline.first().map(|s| s.as_str()).unwrap_or_default();
+    Ok(name.split(':').next().unwrap_or_default().to_owned())
+}
+
+/// Searches the linker paths (including APEX modules) for a library by name
+pub fn find_library(name: &Path) -> Option<PathBuf> {
+    LIBRARY_PATHS
+        .iter()
+        .map(|dir| Path::new(dir).join(name))
+        .find(|path| path.exists())
+}
+
+/// A module loaded by the dynamic linker
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LinkedModule {
+    /// The difference between the addresses in the module and where it is loaded
+    pub load_bias: usize,
+    /// The name the linker has for the module. Libraries loaded directly from an apk have
+    /// names like `/data/app/.../base.apk!/lib/arm64-v8a/libfoo.so`
+    pub name: String,
+    /// Address of the module's dynamic section
+    pub dynamic: usize,
+}
+
+/// Enumerates modules by walking the bionic linker's `r_debug` link_map list. Unlike
+/// /proc/pid/maps, this has the real names of libraries that are mapped directly out of
+/// an apk.
+pub fn linked_modules(process: &Process) -> Result<Vec<LinkedModule>, Error> {
+    let r_debug = match r_debug_address(process)? {
+        Some(r_debug) => r_debug,
+        None => return Ok(Vec::new()),
+    };
+    let word = std::mem::size_of::<usize>();
+
+    // struct r_debug { int r_version; struct link_map* r_map; ... }
+    let mut link_map: usize = process.copy_struct(r_debug + word)?;
+    let mut ret = Vec::new();
+    while link_map != 0 {
+        // struct link_map { l_addr, l_name, l_ld, l_next, l_prev }
+        let entry: [usize; 5] = process.copy_struct(link_map)?;
+        ret.push(LinkedModule {
+            load_bias: entry[0],
+            name: read_c_str(process, entry[1])?,
+            dynamic: entry[2],
+        });
+        link_map = entry[3];
+        if ret.len() > 65536 {
+            return Err(Error::Other("link_map list is too long".to_owned()));
+        }
+    }
+    Ok(ret)
+}
+
+/// Finds r_debug through the DT_DEBUG entry in the dynamic section of the executable
+fn r_debug_address(process: &Process) -> Result<Option<usize>, Error> {
+    let auxv = std::fs::read(format!("/proc/{}/auxv", process.pid))
+        .map_err(|e| Error::from_os_error(process.pid, e))?;
+    let auxv = parse_auxv(&auxv);
+    let (phdr, phnum) = match (auxv_value(&auxv, AT_PHDR), auxv_value(&auxv, AT_PHNUM)) {
+        (Some(phdr), Some(phnum)) => (phdr, phnum),
+        _ => return Ok(None),
+    };
+
+    let headers: Vec<goblin::elf::ProgramHeader> = (0..phnum)
+        .map(|i| read_program_header(process, phdr + i * program_header_size()))
+        .collect::<Result<_, _>>()?;
+
+    let bias = match headers.iter().find(|h| h.p_type == PT_PHDR) {
+        Some(h) => phdr.wrapping_sub(h.p_vaddr as usize),
+        None => return Ok(None),
+    };
+    let dynamic = match headers.iter().find(|h| h.p_type == PT_DYNAMIC) {
+        Some(h) => bias.wrapping_add(h.p_vaddr as usize),
+        None => return Ok(None),
+    };
+
+    let mut address = dynamic;
+    loop {
+        let entry: [usize; 2] = process.copy_struct(address)?;
+        match entry[0] {
+            DT_NULL => return Ok(None),
+            DT_DEBUG if entry[1] != 0 => return Ok(Some(entry[1])),
+            _ => address += std::mem::size_of::<[usize; 2]>(),
+        }
+    }
+}
+
+#[cfg(target_pointer_width = "64")]
+fn program_header_size() -> usize {
+    goblin::elf64::program_header::SIZEOF_PHDR
+}
+
+#[cfg(target_pointer_width = "32")]
+fn program_header_size() -> usize {
+    goblin::elf32::program_header::SIZEOF_PHDR
+}
+
+#[cfg(target_pointer_width = "64")]
+fn read_program_header(
+    process: &Process,
+    addr: usize,
+) -> Result<goblin::elf::ProgramHeader, Error> {
+    let header: goblin::elf64::program_header::ProgramHeader = process.copy_struct(addr)?;
+    Ok(header.into())
+}
+
+#[cfg(target_pointer_width = "32")]
+fn read_program_header(
+    process: &Process,
+    addr: usize,
+) -> Result<goblin::elf::ProgramHeader, Error> {
+    let header: goblin::elf32::program_header::ProgramHeader = process.copy_struct(addr)?;
+    Ok(header.into())
+}
+
+fn parse_auxv(data: &[u8]) -> Vec<(usize, usize)> {
+    let word = std::mem::size_of::<usize>();
+    data.chunks_exact(word * 2)
+        .map(|pair| {
+            let mut key = [0_u8; std::mem::size_of::<usize>()];
+            let mut value = [0_u8; std::mem::size_of::<usize>()];
+            key.copy_from_slice(&pair[..word]);
+            value.copy_from_slice(&pair[word..]);
+            (usize::from_ne_bytes(key), usize::from_ne_bytes(value))
+        })
+        .collect()
+}
+
+fn auxv_value(auxv: &[(usize, usize)], key: usize) -> Option<usize> {
+    auxv.iter().find(|(k, _)| *k == key).map(|(_, v)| *v)
+}
+
+fn read_c_str(process: &Process, addr: usize) -> Result<String, Error> {
+    let mut ret = Vec::new();
+    loop {
+        let chunk: [u8; 64] = process.copy_struct(addr + ret.len())?;
+        match chunk.iter().position(|b| *b == 0) {
+            Some(end) => {
+                ret.extend_from_slice(&chunk[..end]);
+                return Ok(String::from_utf8_lossy(&ret).into_owned());
+            }
+            None => ret.extend_from_slice(&chunk),
+        }
+        if ret.len() > 4096 {
+            return Err(Error::Other(format!("unterminated string at {:#x}", addr)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_auxv() {
+        let mut data = Vec::new();
+        for value in [AT_PHDR, 0x1040, AT_PHNUM, 9, 0, 0] {
+            data.extend_from_slice(&value.to_ne_bytes());
+        }
+        let auxv = parse_auxv(&data);
+        assert_eq!(auxv_value(&auxv, AT_PHDR), Some(0x1040));
+        assert_eq!(auxv_value(&auxv, AT_PHNUM), Some(9));
+        assert_eq!(auxv_value(&auxv, 7), None);
+    }
+
+    #[test]
+    fn test_linked_modules() {
+        // the test binary itself is dynamically linked, so should at least see libc
+        let process = Process::new(std::process::id() as Pid).unwrap();
+        let modules = linked_modules(&process).unwrap();
+        assert!(modules.iter().any(|m| m.name.contains("libc.so")));
+    }
+}
@@ -0,0 +1,444 @@
+//! Stack unwinding for 32-bit arm processes using the .ARM.exidx / .ARM.extab tables
+//! described in the 'Exception Handling ABI for the ARM Architecture' (EHABI).
+//!
+//! Most 32-bit arm binaries don't have an .eh_frame section, but nearly all of them have
+//! an .ARM.exidx section. Frames without an exidx entry fall back to following frame pointers.
+use std::collections::HashMap;
+use std::fs::File;
+
+use goblin::elf::program_header::PT_LOAD;
+use log::{debug, warn};
+use memmap2::Mmap;
+
+#[cfg(target_arch = "arm")]
+use super::regset::get_regset;
+use super::{Process, Thread};
+use crate::{Error, ProcessMemory};
+
+const PT_ARM_EXIDX: u32 = 0x7000_0001;
+const EXIDX_CANTUNWIND: u32 = 1;
+
+const SP: usize = 13;
+const LR: usize = 14;
+const PC: usize = 15;
+
+/// Decodes a 31-bit place-relative offset, as used in the exidx tables
+fn prel31(place: u32, word: u32) -> u32 {
+    let offset = ((word << 1) as i32) >> 1;
+    place.wrapping_add(offset as u32)
+}
+
+/// The .ARM.exidx table of a single module loaded in the target process
+#[derive(Debug, Clone)]
+pub struct ExidxTable {
+    /// (function start address, unwind entry) pairs with runtime addresses, sorted by address
+    entries: Vec<(u32, ExidxEntry)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ExidxEntry {
+    CantUnwind,
+    /// compact unwind instructions stored directly in the index table
+    Inline(u32),
+    /// address of the .ARM.extab entry holding the unwind instructions
+    Table(u32),
+}
+
+impl ExidxTable {
+    /// Parses an exidx table, given its contents and the address it is loaded at
+    pub fn parse(data: &[u8], address: u32) -> ExidxTable {
+        let mut entries = Vec::with_capacity(data.len() / 8);
+        for (i, chunk) in data.chunks_exact(8).enumerate() {
+            let place = address.wrapping_add(i as u32 * 8);
+            let function = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+            let data = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]);
+
+            let entry = if data == EXIDX_CANTUNWIND {
+                ExidxEntry::CantUnwind
+            } else if data & 0x8000_0000 != 0 {
+                ExidxEntry::Inline(data)
+            } else {
+                ExidxEntry::Table(prel31(place + 4, data))
+            };
+            entries.push((prel31(place, function), entry));
+        }
+        entries.sort_by_key(|(addr, _)| *addr);
+        ExidxTable { entries }
+    }
+
+    /// Loads the exidx table for the module mapped at `map`, reading the table itself
+    /// from the memory of the target process
+    fn load(process: &Process, map: &proc_maps::MapRange) -> Result<Option<ExidxTable>, Error> {
+        let filename = match map.filename() {
+            Some(filename) => filename,
+            None => return Ok(None),
+        };
+        let path = match process.resolve_path(filename, map.start(), map.start() + map.size()) {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        let file = File::open(path)?;
+        let buffer = unsafe { Mmap::map(&file)? };
+        let elf = goblin::elf::Elf::parse(&buffer)?;
+
+        let exidx = match elf
+            .program_headers
+            .iter()
+            .find(|h| h.p_type == PT_ARM_EXIDX)
+        {
+            Some(exidx) => exidx,
+            None => return Ok(None),
+        };
+
+        // figure out the load bias from the segment containing this mapping
+        let offset = map.offset as u64;
+        let load = match elf.program_headers.iter().find(|h| {
+            h.p_type == PT_LOAD && h.p_offset <= offset && offset < h.p_offset + h.p_filesz
+        }) {
+            Some(load) => load,
+            None => return Ok(None),
+        };
+        let bias = (map.start() as u64)
+            .wrapping_sub(offset)
+            .wrapping_sub(load.p_vaddr.wrapping_sub(load.p_offset));
+
+        let address = exidx.p_vaddr.wrapping_add(bias) as usize;
+        let data = process.copy(address, exidx.p_memsz as usize)?;
+        Ok(Some(ExidxTable::parse(&data, address as u32)))
+    }
+
+    fn find(&self, pc: u32) -> Option<ExidxEntry> {
+        let index = match self.entries.binary_search_by_key(&pc, |(addr, _)| *addr) {
+            Ok(index) => index,
+            Err(0) => return None,
+            Err(index) => index - 1,
+        };
+        Some(self.entries[index].1)
+    }
+}
+
+/// Collects the unwind instruction bytes for an exidx entry
+fn instructions<M: ProcessMemory>(memory: &M, entry: ExidxEntry) -> Result<Vec<u8>, Error> {
+    let (first, address) = match entry {
+        ExidxEntry::CantUnwind => return Ok(Vec::new()),
+        ExidxEntry::Inline(word) => (word, None),
+        ExidxEntry::Table(address) => {
+            let word: u32 = memory.copy_struct(address as usize)?;
+            if word & 0x8000_0000 != 0 {
+                (word, Some(address + 4))
+            } else {
+                // generic model: a prel31 offset to the personality routine. For the gcc/clang
+                // personality routines this is followed by data in the same format as the
+                // compact model with personality index 1
+                let word: u32 = memory.copy_struct(address as usize + 4)?;
+                let initial = [(word >> 16) as u8, (word >> 8) as u8, word as u8];
+                return extended_instructions(memory, word >> 24, &initial, address + 8);
+            }
+        }
+    };
+
+    match (first >> 24) & 0x0f {
+        0 => Ok(vec![(first >> 16) as u8, (first >> 8) as u8, first as u8]),
+        1 | 2 => match address {
+            Some(address) => {
+                let initial = [(first >> 8) as u8, first as u8];
+                extended_instructions(memory, (first >> 16) & 0xff, &initial, address)
+            }
+            None => Err(Error::UnwindError {
+                message: "long exidx entries can't be inlined".to_owned(),
+            }),
+        },
+        index => Err(Error::UnwindError {
+            message: format!("unsupported exidx personality index {}", index),
+        }),
+    }
+}
+
+/// Appends `count` words of instructions stored at `address` to the `initial` instructions
+fn extended_instructions<M: ProcessMemory>(
+    memory: &M,
+    count: u32,
+    initial: &[u8],
+    address: u32,
+) -> Result<Vec<u8>, Error> {
+    let mut ret = initial.to_vec();
+    for i in 0..count {
+        let word: u32 = memory.copy_struct((address + i * 4) as usize)?;
+        ret.extend_from_slice(&word.to_be_bytes());
+    }
+    Ok(ret)
+}
+
+/// Executes EHABI unwind instructions against `regs`, leaving the registers of the caller.
+/// Returns false if the instructions say that this frame can't be unwound.
+fn execute<M: ProcessMemory>(
+    memory: &M,
+    instructions: &[u8],
+    regs: &mut [u32; 16],
+) -> Result<bool, Error> {
+    let pop = |regs: &mut [u32; 16], mask: u32, first: usize| -> Result<(), Error> {
+        let mut vsp = regs[SP];
+        let mut sp = None;
+        for i in 0..16 {
+            if mask & (1 << i) != 0 {
+                let value: u32 = memory.copy_struct(vsp as usize)?;
+                vsp += 4;
+                if first + i == SP {
+                    sp = Some(value);
+                } else {
+                    regs[first + i] = value;
+                }
+            }
+        }
+        regs[SP] = sp.unwrap_or(vsp);
+        Ok(())
+    };
+
+    let mut set_pc = false;
+    let mut bytes = instructions.iter().copied();
+    while let Some(op) = bytes.next() {
+        match op {
+            0x00..=0x3f => regs[SP] = regs[SP].wrapping_add(((op as u32 & 0x3f) << 2) + 4),
+            0x40..=0x7f => regs[SP] = regs[SP].wrapping_sub(((op as u32 & 0x3f) << 2) + 4),
+            0x80..=0x8f => {
+                let mask = ((op as u32 & 0x0f) << 8) | bytes.next().unwrap_or(0) as u32;
+                if mask == 0 {
+                    return Ok(false);
+                }
+                pop(regs, mask, 4)?;
+                set_pc |= mask & (1 << (PC - 4)) != 0;
+            }
+            0x90..=0x9f if op != 0x9d && op != 0x9f => regs[SP] = regs[op as usize & 0x0f],
+            0xa0..=0xaf => {
+                let mut mask = (1 << ((op & 0x07) + 1)) - 1;
+                if op & 0x08 != 0 {
+                    mask |= 1 << (LR - 4);
+                }
+                pop(regs, mask, 4)?;
+            }
+            0xb0 => break,
+            0xb1 => {
+                let mask = bytes.next().unwrap_or(0) as u32;
+                if mask == 0 || mask & 0xf0 != 0 {
+                    return Err(invalid_instruction(op));
+                }
+                pop(regs, mask, 0)?;
+            }
+            0xb2 => {
+                let mut value = 0_u32;
+                let mut shift = 0;
+                for byte in bytes.by_ref() {
+                    value |= ((byte & 0x7f) as u32) << shift;
+                    shift += 7;
+                    if byte & 0x80 == 0 {
+                        break;
+                    }
+                }
+                regs[SP] = regs[SP].wrapping_add(0x204 + (value << 2));
+            }
+            // vfp registers saved with FSTMFDX have an extra padding word
+            0xb3 => {
+                let count = (bytes.next().unwrap_or(0) & 0x0f) as u32 + 1;
+                regs[SP] = regs[SP].wrapping_add(count * 8 + 4);
+            }
+            0xb8..=0xbf => regs[SP] = regs[SP].wrapping_add((op as u32 & 0x07) * 8 + 12),
+            0xc0..=0xc5 => regs[SP] = regs[SP].wrapping_add((op as u32 & 0x07) * 8 + 8),
+            0xc6 | 0xc8 | 0xc9 => {
+                let count = (bytes.next().unwrap_or(0) & 0x0f) as u32 + 1;
+                regs[SP] = regs[SP].wrapping_add(count * 8);
+            }
+            0xc7 => {
+                let mask = bytes.next().unwrap_or(0) & 0x0f;
+                regs[SP] = regs[SP].wrapping_add(mask.count_ones() * 4);
+            }
+            0xd0..=0xd7 => regs[SP] = regs[SP].wrapping_add((op as u32 & 0x07) * 8 + 8),
+            _ => return Err(invalid_instruction(op)),
+        }
+    }
+
+    if !set_pc {
+        regs[PC] = regs[LR];
+    }
+    Ok(true)
+}
+
+fn invalid_instruction(op: u8) -> Error {
+    Error::UnwindError {
+        message: format!("invalid arm unwind instruction {:#04x}", op),
+    }
+}
+
+/// Unwinds the stack of a 32-bit arm thread using .ARM.exidx tables, falling back to
+/// following frame pointers for code without unwind tables. On aarch64 hosts this handles
+/// 32-bit compat processes. The thread needs to be locked while iterating.
+pub struct ExidxCursor<'a> {
+    process: &'a Process,
+    maps: Vec<proc_maps::MapRange>,
+    tables: HashMap<usize, Option<ExidxTable>>,
+    regs: [u32; 16],
+    thumb: bool,
+    initial_frame: bool,
+}
+
+impl<'a> ExidxCursor<'a> {
+    pub fn new(process: &'a Process, thread: &Thread) -> Result<ExidxCursor<'a>, Error> {
+        let registers = thread_registers(thread)?;
+        let mut regs = [0; 16];
+        regs.copy_from_slice(&registers[..16]);
+        Ok(ExidxCursor {
+            process,
+            maps: proc_maps::get_process_maps(process.pid)?,
+            tables: HashMap::new(),
+            regs,
+            thumb: registers[16] & (1 << 5) != 0,
+            initial_frame: true,
+        })
+    }
+
+    fn table(&mut self, pc: u32) -> Option<&ExidxTable> {
+        let map = self.maps.iter().find(|m| {
+            m.is_exec() && m.start() <= pc as usize && (pc as usize) < m.start() + m.size()
+        })?;
+        let process = self.process;
+        self.tables
+            .entry(map.start())
+            .or_insert_with(|| match ExidxTable::load(process, map) {
+                Ok(table) => table,
+                Err(e) => {
+                    warn!("failed to load exidx table for {:?}: {}", map.filename(), e);
+                    None
+                }
+            })
+            .as_ref()
+    }
+
+    fn step(&mut self) -> Result<bool, Error> {
+        let pc = self.regs[PC];
+        // return addresses point to the instruction after the call, so look up pc - 1 to
+        // handle calls at the very end of a function
+        let lookup = if self.initial_frame { pc } else { pc - 1 } & !1;
+        let entry = self.table(lookup).and_then(|table| table.find(lookup));
+        if let Some(entry) = entry {
+            if entry == ExidxEntry::CantUnwind {
+                return Ok(false);
+            }
+            let instructions = instructions(self.process, entry)?;
+            if !execute(self.process, &instructions, &mut self.regs)? {
+                return Ok(false);
+            }
+        } else {
+            debug!("no exidx entry for {:#x}, following frame pointers", pc);
+            let fp = if self.thumb {
+                self.regs[7]
+            } else {
+                self.regs[11]
+            };
+            if fp == 0 {
+                return Ok(false);
+            }
+            let record: [u32; 2] = self.process.copy_struct(fp as usize)?;
+            if record[0] != 0 && record[0] <= fp {
+                return Ok(false);
+            }
+            self.regs[SP] = fp + 8;
+            if self.thumb {
+                self.regs[7] = record[0];
+            } else {
+                self.regs[11] = record[0];
+            }
+            self.regs[PC] = record[1];
+        }
+        self.thumb = self.regs[PC] & 1 != 0;
+        Ok(self.regs[PC] != 0 && self.regs[PC] != pc)
+    }
+}
+
+impl<'a> Iterator for ExidxCursor<'a> {
+    type Item = Result<u64, Error>;
+
+    fn next(&mut self) -> Option<Result<u64, Error>> {
+        if self.initial_frame {
+            let pc = self.regs[PC];
+            match self.step() {
+                Ok(true) => {}
+                Ok(false) => self.regs[PC] = 0,
+                Err(e) => {
+                    self.regs[PC] = 0;
+                    warn!("failed to unwind frame at {:#x}: {}", pc, e);
+                }
+            }
+            self.initial_frame = false;
+            return Some(Ok(pc as u64));
+        }
+
+        let pc = self.regs[PC];
+        if pc == 0 {
+            return None;
+        }
+        match self.step() {
+            Ok(true) => {}
+            Ok(false) => self.regs[PC] = 0,
+            Err(e) => {
+                self.regs[PC] = 0;
+                return Some(Err(e));
+            }
+        }
+        Some(Ok((pc & !1) as u64))
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+fn thread_registers(thread: &Thread) -> Result<[u32; 18], Error> {
+    Ok(thread.compat_registers()?.regs)
+}
+
+#[cfg(target_arch = "arm")]
+fn thread_registers(thread: &Thread) -> Result<[u32; 18], Error> {
+    get_regset(thread.tid, libc::NT_PRSTATUS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake_memory::FakeMemory;
+
+    #[test]
+    fn test_prel31() {
+        assert_eq!(prel31(0x1000, 0x10), 0x1010);
+        assert_eq!(prel31(0x1000, 0x7fff_fff0), 0x0ff0);
+    }
+
+    #[test]
+    fn test_parse_table() {
+        let mut data = Vec::new();
+        // function at +0x100 that can't be unwound, then one at +0x200 with inline instructions
+        data.extend_from_slice(&0x100_u32.to_le_bytes());
+        data.extend_from_slice(&EXIDX_CANTUNWIND.to_le_bytes());
+        data.extend_from_slice(&0x1f8_u32.to_le_bytes());
+        data.extend_from_slice(&0x80a8b0b0_u32.to_le_bytes());
+
+        let table = ExidxTable::parse(&data, 0x4000);
+        assert_eq!(table.find(0x4000), None);
+        assert_eq!(table.find(0x4150), Some(ExidxEntry::CantUnwind));
+        assert_eq!(table.find(0x4250), Some(ExidxEntry::Inline(0x80a8b0b0)));
+    }
+
+    #[test]
+    fn test_execute() {
+        // push {r4, lr} ; sub sp, sp, #8
+        let stack = FakeMemory::from_words(0x1000, &[0_u32, 0, 0x44, 0x8123]);
+        let mut regs = [0; 16];
+        regs[SP] = 0x1000;
+
+        let instructions = instructions(&stack, ExidxEntry::Inline(0x8001a8b0)).unwrap();
+        assert_eq!(instructions, vec![0x01, 0xa8, 0xb0]);
+        assert!(execute(&stack, &instructions, &mut regs).unwrap());
+        assert_eq!(regs[4], 0x44);
+        assert_eq!(regs[LR], 0x8123);
+        assert_eq!(regs[PC], 0x8123);
+        assert_eq!(regs[SP], 0x1010);
+
+        // refuse to unwind
+        assert!(!execute(&stack, &[0x80, 0x00], &mut regs).unwrap());
+    }
+}
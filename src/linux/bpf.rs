@@ -0,0 +1,473 @@
+//! Collects the user mode stacks of a process in the kernel, with a small BPF program
+//! attached to a perf event on each of its threads.
+//!
+//! The program records each sampled stack with bpf_get_stackid, and counts how many times
+//! each thread was sampled in it - so the target is never stopped, and nothing is copied
+//! out of the kernel until the counts are read. The kernel walks user stacks by following
+//! frame pointers, so code built without them will have truncated stacks.
+//!
+//! Loading the program needs CAP_BPF and CAP_PERFMON (or root), but doesn't need ptrace
+//! access to the target.
+use std::collections::{HashMap, HashSet};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+use log::{debug, info};
+
+use super::perf::{perf_event_open, PerfClock, PerfEventAttr};
+use super::{Pid, Process, Tid};
+use crate::Error;
+#[cfg(use_libunwind)]
+use crate::Frames;
+
+const BPF_MAP_CREATE: libc::c_int = 0;
+const BPF_MAP_LOOKUP_ELEM: libc::c_int = 1;
+const BPF_MAP_DELETE_ELEM: libc::c_int = 3;
+const BPF_MAP_GET_NEXT_KEY: libc::c_int = 4;
+const BPF_PROG_LOAD: libc::c_int = 5;
+
+const BPF_MAP_TYPE_HASH: u32 = 1;
+const BPF_MAP_TYPE_STACK_TRACE: u32 = 7;
+const BPF_PROG_TYPE_PERF_EVENT: u32 = 7;
+
+const PERF_EVENT_IOC_SET_BPF: libc::c_ulong = 0x4004_2408;
+
+/// The deepest stack the kernel records, which is the default of
+/// kernel.perf_event_max_stack
+const MAX_DEPTH: usize = 127;
+/// How many distinct stacks can be recorded between reads
+const MAX_STACKS: u32 = 16384;
+
+const VERIFIER_LOG_SIZE: usize = 65536;
+
+#[repr(C)]
+#[allow(dead_code)]
+struct MapCreateAttr {
+    map_type: u32,
+    key_size: u32,
+    value_size: u32,
+    max_entries: u32,
+    map_flags: u32,
+}
+
+#[repr(C)]
+#[allow(dead_code)]
+struct MapElemAttr {
+    map_fd: u32,
+    _pad: u32,
+    key: u64,
+    value: u64,
+    flags: u64,
+}
+
+#[repr(C)]
+#[allow(dead_code)]
+struct ProgLoadAttr {
+    prog_type: u32,
+    insn_cnt: u32,
+    insns: u64,
+    license: u64,
+    log_level: u32,
+    log_size: u32,
+    log_buf: u64,
+    kern_version: u32,
+    prog_flags: u32,
+}
+
+/// `struct bpf_insn`, with the destination register in the low bits of `regs`
+#[repr(C)]
+#[derive(Clone, Copy)]
+#[allow(dead_code)]
+struct Insn {
+    code: u8,
+    regs: u8,
+    off: i16,
+    imm: i32,
+}
+
+const fn insn(code: u8, dst: u8, src: u8, off: i16, imm: i32) -> Insn {
+    Insn {
+        code,
+        regs: dst | (src << 4),
+        off,
+        imm,
+    }
+}
+
+/// Loads the file descriptor of a map into a register, which takes two instructions
+fn load_map(dst: u8, map: &OwnedFd) -> [Insn; 2] {
+    const BPF_PSEUDO_MAP_FD: u8 = 1;
+    [
+        insn(0x18, dst, BPF_PSEUDO_MAP_FD, 0, map.as_raw_fd()),
+        insn(0, 0, 0, 0, 0),
+    ]
+}
+
+/// The program run on every sample. In C this is:
+///
+/// ```c
+/// struct key { u32 stack_id; u32 tid; };
+///
+/// int sample(struct bpf_perf_event_data *ctx) {
+///     long stack_id = bpf_get_stackid(ctx, &stacks, BPF_F_USER_STACK);
+///     if (stack_id < 0)
+///         return 0;
+///     struct key key = { stack_id, bpf_get_current_pid_tgid() };
+///     u64 *count = bpf_map_lookup_elem(&counts, &key);
+///     if (count) {
+///         __sync_fetch_and_add(count, 1);
+///     } else {
+///         u64 one = 1;
+///         bpf_map_update_elem(&counts, &key, &one, BPF_ANY);
+///     }
+///     return 0;
+/// }
+/// ```
+fn program(stacks: &OwnedFd, counts: &OwnedFd) -> Vec<Insn> {
+    const BPF_FUNC_MAP_LOOKUP_ELEM: i32 = 1;
+    const BPF_FUNC_MAP_UPDATE_ELEM: i32 = 2;
+    const BPF_FUNC_GET_CURRENT_PID_TGID: i32 = 14;
+    const BPF_FUNC_GET_STACKID: i32 = 27;
+    const BPF_F_USER_STACK: i32 = 1 << 8;
+
+    let mut insns = vec![insn(0xbf, 6, 1, 0, 0)]; // r6 = ctx
+    insns.extend(load_map(2, stacks));
+    insns.extend([
+        insn(0xbf, 1, 6, 0, 0),                // r1 = ctx
+        insn(0xb7, 3, 0, 0, BPF_F_USER_STACK), // r3 = flags
+        insn(0x85, 0, 0, 0, BPF_FUNC_GET_STACKID),
+        insn(0xc5, 0, 0, 23, 0), // if r0 s< 0 goto exit
+        insn(0xbf, 7, 0, 0, 0),  // r7 = stack id
+        insn(0x85, 0, 0, 0, BPF_FUNC_GET_CURRENT_PID_TGID),
+        insn(0x63, 10, 7, -8, 0), // key.stack_id = r7
+        insn(0x63, 10, 0, -4, 0), // key.tid = the low half of r0
+    ]);
+    insns.extend(load_map(1, counts));
+    insns.extend([
+        insn(0xbf, 2, 10, 0, 0), // r2 = &key
+        insn(0x07, 2, 0, 0, -8),
+        insn(0x85, 0, 0, 0, BPF_FUNC_MAP_LOOKUP_ELEM),
+        insn(0x15, 0, 0, 3, 0), // if r0 == NULL goto insert
+        insn(0xb7, 1, 0, 0, 1),
+        insn(0xdb, 0, 1, 0, 0),  // *count += 1
+        insn(0x05, 0, 0, 10, 0), // goto exit
+        insn(0xb7, 1, 0, 0, 1),  // insert:
+        insn(0x7b, 10, 1, -16, 0),
+    ]);
+    insns.extend(load_map(1, counts));
+    insns.extend([
+        insn(0xbf, 2, 10, 0, 0), // r2 = &key
+        insn(0x07, 2, 0, 0, -8),
+        insn(0xbf, 3, 10, 0, 0), // r3 = &one
+        insn(0x07, 3, 0, 0, -16),
+        insn(0xb7, 4, 0, 0, 0), // BPF_ANY
+        insn(0x85, 0, 0, 0, BPF_FUNC_MAP_UPDATE_ELEM),
+        insn(0xb7, 0, 0, 0, 0), // exit:
+        insn(0x95, 0, 0, 0, 0),
+    ]);
+    insns
+}
+
+fn bpf<T>(cmd: libc::c_int, attr: &mut T) -> std::io::Result<libc::c_int> {
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_bpf,
+            cmd,
+            attr as *mut T,
+            std::mem::size_of::<T>() as libc::c_uint,
+        )
+    };
+    if ret < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(ret as libc::c_int)
+}
+
+fn create_map(
+    map_type: u32,
+    key_size: u32,
+    value_size: u32,
+    max_entries: u32,
+) -> std::io::Result<OwnedFd> {
+    let mut attr = MapCreateAttr {
+        map_type,
+        key_size,
+        value_size,
+        max_entries,
+        map_flags: 0,
+    };
+    let fd = bpf(BPF_MAP_CREATE, &mut attr)?;
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+fn map_elem(cmd: libc::c_int, map: &OwnedFd, key: *const u8, value: *mut u8) -> bool {
+    let mut attr = MapElemAttr {
+        map_fd: map.as_raw_fd() as u32,
+        _pad: 0,
+        key: key as u64,
+        value: value as u64,
+        flags: 0,
+    };
+    bpf(cmd, &mut attr).is_ok()
+}
+
+fn load_program(insns: &[Insn]) -> Result<OwnedFd, Error> {
+    let license = b"GPL\0";
+    let mut attr = ProgLoadAttr {
+        prog_type: BPF_PROG_TYPE_PERF_EVENT,
+        insn_cnt: insns.len() as u32,
+        insns: insns.as_ptr() as u64,
+        license: license.as_ptr() as u64,
+        log_level: 0,
+        log_size: 0,
+        log_buf: 0,
+        kern_version: 0,
+        prog_flags: 0,
+    };
+    match bpf(BPF_PROG_LOAD, &mut attr) {
+        Ok(fd) => Ok(unsafe { OwnedFd::from_raw_fd(fd) }),
+        Err(e)
+            if e.raw_os_error() == Some(libc::EINVAL) || e.raw_os_error() == Some(libc::EACCES) =>
+        {
+            // load it again with the verifier log, to say why it was rejected
+            let mut log = vec![0_u8; VERIFIER_LOG_SIZE];
+            attr.log_level = 1;
+            attr.log_size = log.len() as u32;
+            attr.log_buf = log.as_mut_ptr() as u64;
+            let _ = bpf(BPF_PROG_LOAD, &mut attr);
+            let len = log.iter().position(|&b| b == 0).unwrap_or(log.len());
+            Err(Error::Other(format!(
+                "failed to load bpf program: {}\n{}",
+                e,
+                String::from_utf8_lossy(&log[..len])
+            )))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// A user mode stack recorded by a `BpfStackSampler`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BpfStack {
+    pub tid: Tid,
+    /// The instruction pointer of each frame, innermost first
+    pub addrs: Vec<u64>,
+    /// How many times the thread was sampled with this stack
+    pub count: u64,
+}
+
+impl BpfStack {
+    /// Symbolicates the frames of the stack, returning the frames for each address in order
+    #[cfg(use_libunwind)]
+    pub fn symbolicate(&self, symbolicator: &crate::Symbolicator, line_info: bool) -> Vec<Frames> {
+        symbolicator.symbolicate_many(&self.addrs, line_info)
+    }
+}
+
+/// Samples the stacks of the threads of a process in the kernel. Threads started after the
+/// sampler was opened are only sampled once `refresh` has been called.
+pub struct BpfStackSampler {
+    pub pid: Pid,
+    clock: PerfClock,
+    frequency: u64,
+    stacks: OwnedFd,
+    counts: OwnedFd,
+    program: OwnedFd,
+    events: HashMap<Tid, OwnedFd>,
+}
+
+impl BpfStackSampler {
+    /// Loads the sampling program, and starts sampling every thread in the process
+    /// `frequency` times a second
+    pub fn open(pid: Pid, clock: PerfClock, frequency: u64) -> Result<BpfStackSampler, Error> {
+        let stacks = create_map(
+            BPF_MAP_TYPE_STACK_TRACE,
+            4,
+            (MAX_DEPTH * 8) as u32,
+            MAX_STACKS,
+        )
+        .map_err(|e| Error::from_os_error(pid, e))?;
+        let counts = create_map(BPF_MAP_TYPE_HASH, 8, 8, MAX_STACKS)
+            .map_err(|e| Error::from_os_error(pid, e))?;
+        let program = load_program(&program(&stacks, &counts))?;
+
+        let mut sampler = BpfStackSampler {
+            pid,
+            clock,
+            frequency,
+            stacks,
+            counts,
+            program,
+            events: HashMap::new(),
+        };
+        sampler.refresh()?;
+        info!(
+            "sampling {} threads of {} with bpf",
+            sampler.events.len(),
+            pid
+        );
+        Ok(sampler)
+    }
+
+    /// Starts sampling threads created since the sampler was opened or last refreshed, and
+    /// stops tracking threads that have exited
+    pub fn refresh(&mut self) -> Result<(), Error> {
+        let tids = Process::new(self.pid)?.thread_ids()?;
+        self.events.retain(|tid, _| tids.contains(tid));
+        for tid in tids {
+            if self.events.contains_key(&tid) {
+                continue;
+            }
+            let attr = PerfEventAttr::sampling(self.clock, self.frequency);
+            let event = match perf_event_open(tid, &attr) {
+                Ok(fd) => unsafe { OwnedFd::from_raw_fd(fd) },
+                // the thread exited before the event could be opened
+                Err(Error::NoSuchProcess { .. }) => {
+                    debug!("thread {} exited", tid);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            let ret = unsafe {
+                libc::ioctl(
+                    event.as_raw_fd(),
+                    PERF_EVENT_IOC_SET_BPF as _,
+                    self.program.as_raw_fd(),
+                )
+            };
+            if ret < 0 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+            self.events.insert(tid, event);
+        }
+        Ok(())
+    }
+
+    /// The number of threads being sampled
+    pub fn thread_count(&self) -> usize {
+        self.events.len()
+    }
+
+    /// Returns the stacks recorded since the last call, with how many times each thread was
+    /// sampled in each. Samples taken while the stacks are being read can be lost.
+    pub fn stacks(&mut self) -> Result<Vec<BpfStack>, Error> {
+        let mut keys: Vec<[u8; 8]> = Vec::new();
+        let mut next = [0_u8; 8];
+        loop {
+            let previous = keys.last().map_or(std::ptr::null(), |key| key.as_ptr());
+            if !map_elem(
+                BPF_MAP_GET_NEXT_KEY,
+                &self.counts,
+                previous,
+                next.as_mut_ptr(),
+            ) {
+                break;
+            }
+            keys.push(next);
+        }
+
+        let mut ret = Vec::with_capacity(keys.len());
+        let mut stack_ids = HashSet::new();
+        for key in keys {
+            let mut count = [0_u8; 8];
+            let found = map_elem(
+                BPF_MAP_LOOKUP_ELEM,
+                &self.counts,
+                key.as_ptr(),
+                count.as_mut_ptr(),
+            );
+            map_elem(
+                BPF_MAP_DELETE_ELEM,
+                &self.counts,
+                key.as_ptr(),
+                std::ptr::null_mut(),
+            );
+            if !found {
+                continue;
+            }
+
+            let (stack_id, tid) = parse_key(&key);
+            let mut frames = [0_u8; MAX_DEPTH * 8];
+            if !map_elem(
+                BPF_MAP_LOOKUP_ELEM,
+                &self.stacks,
+                stack_id.to_ne_bytes().as_ptr(),
+                frames.as_mut_ptr(),
+            ) {
+                debug!("stack {} of thread {} is missing", stack_id, tid);
+                continue;
+            }
+            stack_ids.insert(stack_id);
+            ret.push(BpfStack {
+                tid,
+                addrs: parse_stack(&frames),
+                count: u64::from_ne_bytes(count),
+            });
+        }
+
+        for stack_id in stack_ids {
+            map_elem(
+                BPF_MAP_DELETE_ELEM,
+                &self.stacks,
+                stack_id.to_ne_bytes().as_ptr(),
+                std::ptr::null_mut(),
+            );
+        }
+        Ok(ret)
+    }
+}
+
+/// Splits a key of the counts map into the stack id and thread id
+fn parse_key(key: &[u8; 8]) -> (u32, Tid) {
+    let stack_id = u32::from_ne_bytes(key[0..4].try_into().unwrap());
+    let tid = u32::from_ne_bytes(key[4..8].try_into().unwrap()) as Tid;
+    (stack_id, tid)
+}
+
+/// Reads the addresses of a stack from the stack map, which are zero after the last frame
+fn parse_stack(frames: &[u8]) -> Vec<u64> {
+    frames
+        .chunks_exact(8)
+        .map(|addr| u64::from_ne_bytes(addr.try_into().unwrap()))
+        .take_while(|&addr| addr != 0)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_stack() {
+        let mut key = [0_u8; 8];
+        key[0..4].copy_from_slice(&17_u32.to_ne_bytes());
+        key[4..8].copy_from_slice(&1234_u32.to_ne_bytes());
+        assert_eq!(parse_key(&key), (17, 1234));
+
+        let mut frames = vec![0_u8; MAX_DEPTH * 8];
+        for (i, addr) in [0x401000_u64, 0x402000, 0x7f00_0000_1000]
+            .iter()
+            .enumerate()
+        {
+            frames[i * 8..i * 8 + 8].copy_from_slice(&addr.to_ne_bytes());
+        }
+        assert_eq!(
+            parse_stack(&frames),
+            vec![0x401000, 0x402000, 0x7f00_0000_1000]
+        );
+        assert!(parse_stack(&[0; 16]).is_empty());
+    }
+
+    #[test]
+    fn test_program_jumps() {
+        // the jumps to exit have to land on the `r0 = 0` before the exit instruction
+        let fd = OwnedFd::from(std::fs::File::open("/proc/self/stat").unwrap());
+        let insns = program(&fd, &fd);
+        let exit = insns.len() - 2;
+        for (i, insn) in insns.iter().enumerate() {
+            if insn.code == 0xc5 || insn.code == 0x05 {
+                assert_eq!(i as i64 + 1 + insn.off as i64, exit as i64);
+            }
+        }
+        assert_eq!(insns[exit].code, 0xb7);
+    }
+}
@@ -0,0 +1,103 @@
+use lazy_static::lazy_static;
+
+use crate::Error;
+
+/// A single entry from /proc/pid/cgroup
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CGroup {
+    /// The hierarchy id, which is always 0 for cgroup v2
+    pub hierarchy_id: u32,
+    /// The controllers bound to the hierarchy (empty for cgroup v2)
+    pub controllers: Vec<String>,
+    /// The path of the cgroup, relative to the mount point of the hierarchy
+    pub path: String,
+}
+
+impl CGroup {
+    pub fn is_v2(&self) -> bool {
+        self.hierarchy_id == 0 && self.controllers.is_empty()
+    }
+}
+
+pub fn parse_cgroups(contents: &str) -> Result<Vec<CGroup>, Error> {
+    let mut ret = Vec::new();
+    for line in contents.lines().filter(|line| !line.is_empty()) {
+        let mut fields = line.splitn(3, ':');
+        let (id, controllers, path) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(id), Some(controllers), Some(path)) => (id, controllers, path),
+            _ => {
+                return Err(Error::Other(format!(
+                    "Failed to parse cgroup line '{}'",
+                    line
+                )))
+            }
+        };
+        let hierarchy_id = id
+            .parse()
+            .map_err(|_| Error::Other(format!("Failed to parse cgroup line '{}'", line)))?;
+        ret.push(CGroup {
+            hierarchy_id,
+            controllers: controllers
+                .split(',')
+                .filter(|c| !c.is_empty())
+                .map(|c| c.to_owned())
+                .collect(),
+            path: path.to_owned(),
+        });
+    }
+    Ok(ret)
+}
+
+/// Returns the container id from a set of cgroups, if there is one. Container runtimes
+/// name cgroups after the container id, which is a 64 character hex string (for instance
+/// '/docker/<id>', '/kubepods/burstable/pod<uid>/<id>' or '/system.slice/docker-<id>.scope')
+pub fn container_id(cgroups: &[CGroup]) -> Option<String> {
+    lazy_static! {
+        static ref RE: regex::Regex =
+            regex::Regex::new(r"(?:^|[^0-9a-f])([0-9a-f]{64})(?:$|[^0-9a-f])").unwrap();
+    }
+    cgroups.iter().find_map(|cgroup| {
+        cgroup
+            .path
+            .rsplit('/')
+            .find_map(|segment| Some(RE.captures(segment)?.get(1)?.as_str().to_owned()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: &str = "3ba8c5c1f2a0e2b7a1f7a5d6e9b8c7d6e5f4a3b2c1d0e9f8a7b6c5d4e3f2a1b0";
+
+    #[test]
+    fn test_parse_cgroups() {
+        let cgroups = parse_cgroups("12:cpu,cpuacct:/user.slice\n0::/init.scope\n").unwrap();
+        assert_eq!(cgroups.len(), 2);
+        assert_eq!(cgroups[0].hierarchy_id, 12);
+        assert_eq!(cgroups[0].controllers, vec!["cpu", "cpuacct"]);
+        assert_eq!(cgroups[0].path, "/user.slice");
+        assert!(!cgroups[0].is_v2());
+        assert!(cgroups[1].is_v2());
+        assert_eq!(cgroups[1].path, "/init.scope");
+
+        assert!(parse_cgroups("garbage").is_err());
+    }
+
+    #[test]
+    fn test_container_id() {
+        for path in [
+            format!("0::/docker/{}", ID),
+            format!("0::/system.slice/docker-{}.scope", ID),
+            format!("0::/kubepods/burstable/pod1234/{}", ID),
+            format!("0::/kubepods.slice/cri-containerd-{}.scope", ID),
+        ] {
+            let cgroups = parse_cgroups(&path).unwrap();
+            assert_eq!(container_id(&cgroups), Some(ID.to_owned()));
+        }
+
+        let cgroups = parse_cgroups("0::/user.slice/user-1000.slice/session-2.scope").unwrap();
+        assert_eq!(container_id(&cgroups), None);
+    }
+}
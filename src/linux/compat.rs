@@ -0,0 +1,157 @@
+//! Support for 32-bit (i386 / armhf) processes running on a 64-bit (x86_64 / aarch64) host
+use std::fs::File;
+use std::io::Read;
+
+use super::regset::read_regset;
+use super::{Pid, Process, Thread};
+use crate::{Error, ProcessMemory};
+
+const ELFCLASS32: u8 = 1;
+
+/// True if the process is a 32-bit compat process on a 64-bit host
+pub fn is_compat(pid: Pid) -> Result<bool, Error> {
+    // byte 4 of the elf header (EI_CLASS) has the pointer width of the binary
+    let mut header = [0_u8; 5];
+    File::open(format!("/proc/{}/exe", pid))?.read_exact(&mut header)?;
+    if &header[..4] != b"\x7fELF" {
+        return Err(Error::Other(format!("/proc/{}/exe isn't an elf file", pid)));
+    }
+    Ok(header[4] == ELFCLASS32)
+}
+
+/// The register set of a 32-bit i386 thread, as returned by PTRACE_GETREGSET
+#[cfg(target_arch = "x86_64")]
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CompatRegisters {
+    pub ebx: u32,
+    pub ecx: u32,
+    pub edx: u32,
+    pub esi: u32,
+    pub edi: u32,
+    pub ebp: u32,
+    pub eax: u32,
+    pub xds: u32,
+    pub xes: u32,
+    pub xfs: u32,
+    pub xgs: u32,
+    pub orig_eax: u32,
+    pub eip: u32,
+    pub xcs: u32,
+    pub eflags: u32,
+    pub esp: u32,
+    pub xss: u32,
+}
+
+#[cfg(target_arch = "x86_64")]
+impl CompatRegisters {
+    pub fn ip(&self) -> u32 {
+        self.eip
+    }
+
+    pub fn sp(&self) -> u32 {
+        self.esp
+    }
+
+    pub fn fp(&self) -> u32 {
+        self.ebp
+    }
+}
+
+/// The register set of a 32-bit arm thread, as returned by PTRACE_GETREGSET
+#[cfg(target_arch = "aarch64")]
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CompatRegisters {
+    /// r0-r15, followed by cpsr and orig_r0
+    pub regs: [u32; 18],
+}
+
+#[cfg(target_arch = "aarch64")]
+impl CompatRegisters {
+    pub fn ip(&self) -> u32 {
+        self.regs[15]
+    }
+
+    pub fn sp(&self) -> u32 {
+        self.regs[13]
+    }
+
+    pub fn fp(&self) -> u32 {
+        // thumb code uses r7 as the frame pointer, arm code uses r11
+        if self.regs[16] & (1 << 5) != 0 {
+            self.regs[7]
+        } else {
+            self.regs[11]
+        }
+    }
+}
+
+impl Thread {
+    /// Returns the registers of a thread in a 32-bit compat process. The thread needs to be
+    /// locked for this to succeed.
+    pub fn compat_registers(&self) -> Result<CompatRegisters, Error> {
+        let mut regs = CompatRegisters::default();
+        // the kernel returns the 32-bit layout of NT_PRSTATUS for compat tasks
+        let len = read_regset(self.tid, libc::NT_PRSTATUS, &mut regs)?;
+        if len != std::mem::size_of::<CompatRegisters>() {
+            return Err(Error::Other(format!(
+                "thread {} isn't a 32-bit compat thread",
+                self.tid
+            )));
+        }
+        Ok(regs)
+    }
+}
+
+/// Unwinds the stack of a thread in a 32-bit compat process by following the chain of
+/// frame pointers, with each frame record holding the saved frame pointer followed by the
+/// return address. The thread needs to be locked while iterating.
+pub struct CompatCursor<'a> {
+    process: &'a Process,
+    ip: u32,
+    fp: u32,
+    initial_frame: bool,
+}
+
+impl<'a> CompatCursor<'a> {
+    pub fn new(process: &'a Process, thread: &Thread) -> Result<CompatCursor<'a>, Error> {
+        let regs = thread.compat_registers()?;
+        Ok(CompatCursor {
+            process,
+            ip: regs.ip(),
+            fp: regs.fp(),
+            initial_frame: true,
+        })
+    }
+}
+
+impl<'a> Iterator for CompatCursor<'a> {
+    type Item = Result<u64, Error>;
+
+    fn next(&mut self) -> Option<Result<u64, Error>> {
+        if self.initial_frame {
+            self.initial_frame = false;
+            return Some(Ok(self.ip as u64));
+        }
+
+        if self.fp == 0 {
+            return None;
+        }
+        let record: [u32; 2] = match self.process.copy_struct(self.fp as usize) {
+            Ok(record) => record,
+            Err(e) => return Some(Err(e)),
+        };
+        let (fp, ip) = (record[0], record[1]);
+
+        // the stack grows down, so the frame pointers should be increasing
+        if ip == 0 || fp <= self.fp {
+            return None;
+        }
+        self.fp = fp;
+        self.ip = ip;
+        Some(Ok(ip as u64))
+    }
+}
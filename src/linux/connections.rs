@@ -0,0 +1,167 @@
+//! Lists the sockets of a process, by matching the socket inodes of its file descriptors
+//! against the tables in /proc/pid/net
+use std::collections::HashSet;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use super::Process;
+use crate::{Connection, Error, Protocol, TcpState};
+
+impl Process {
+    /// Returns the TCP and UDP sockets the process has open
+    pub fn connections(&self) -> Result<Vec<Connection>, Error> {
+        let inodes: HashSet<u64> = self
+            .open_files()?
+            .iter()
+            .filter_map(|file| socket_inode(&file.path))
+            .collect();
+        if inodes.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // the tables under /proc/pid/net are for the network namespace of the process
+        let mut connections = Vec::new();
+        for (table, protocol) in [
+            ("tcp", Protocol::Tcp),
+            ("tcp6", Protocol::Tcp),
+            ("udp", Protocol::Udp),
+            ("udp6", Protocol::Udp),
+        ] {
+            let contents =
+                match std::fs::read_to_string(format!("/proc/{}/net/{}", self.pid, table)) {
+                    Ok(contents) => contents,
+                    // the table is missing when the kernel was built without ipv6
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                    Err(e) => return Err(Error::from_os_error(self.pid, e)),
+                };
+            connections.extend(
+                contents
+                    .lines()
+                    .skip(1)
+                    .filter_map(|line| parse_socket(line, protocol))
+                    .filter(|(inode, _)| inodes.contains(inode))
+                    .map(|(_, connection)| connection),
+            );
+        }
+        Ok(connections)
+    }
+}
+
+/// Returns the inode from the target of a /proc/pid/fd link to a socket, like `socket:[1234]`
+fn socket_inode(path: &str) -> Option<u64> {
+    path.strip_prefix("socket:[")?
+        .strip_suffix(']')?
+        .parse()
+        .ok()
+}
+
+/// Parses a line of /proc/net/{tcp,tcp6,udp,udp6}, returning the socket's inode along with
+/// the connection
+fn parse_socket(line: &str, protocol: Protocol) -> Option<(u64, Connection)> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    if fields.len() < 10 {
+        return None;
+    }
+    let local = parse_address(fields[1])?;
+    let remote = parse_address(fields[2])?;
+    let state = u8::from_str_radix(fields[3], 16).ok()?;
+    let inode = fields[9].parse().ok()?;
+
+    let state = match protocol {
+        Protocol::Tcp => Some(tcp_state(state)?),
+        Protocol::Udp => None,
+    };
+    let remote = if remote.ip().is_unspecified() && remote.port() == 0 {
+        None
+    } else {
+        Some(remote)
+    };
+    Some((
+        inode,
+        Connection {
+            protocol,
+            local,
+            remote,
+            state,
+        },
+    ))
+}
+
+/// Parses an address like `0100007F:0035`. The address is the hex of each 32 bit word of
+/// the address in network order, printed as a native endian integer, and the port is in
+/// host order.
+fn parse_address(address: &str) -> Option<SocketAddr> {
+    let (ip, port) = address.split_once(':')?;
+    let port = u16::from_str_radix(port, 16).ok()?;
+    let mut bytes = Vec::with_capacity(16);
+    for word in 0..ip.len() / 8 {
+        let word = u32::from_str_radix(&ip[word * 8..word * 8 + 8], 16).ok()?;
+        bytes.extend_from_slice(&word.to_ne_bytes());
+    }
+    let ip = match bytes.len() {
+        4 => IpAddr::V4(Ipv4Addr::new(bytes[0], bytes[1], bytes[2], bytes[3])),
+        16 => {
+            let mut octets = [0; 16];
+            octets.copy_from_slice(&bytes);
+            IpAddr::V6(Ipv6Addr::from(octets))
+        }
+        _ => return None,
+    };
+    Some(SocketAddr::new(ip, port))
+}
+
+/// Converts the TCP_* states from include/net/tcp_states.h
+fn tcp_state(state: u8) -> Option<TcpState> {
+    Some(match state {
+        1 => TcpState::Established,
+        2 => TcpState::SynSent,
+        3 => TcpState::SynReceived,
+        4 => TcpState::FinWait1,
+        5 => TcpState::FinWait2,
+        6 => TcpState::TimeWait,
+        7 => TcpState::Closed,
+        8 => TcpState::CloseWait,
+        9 => TcpState::LastAck,
+        10 => TcpState::Listen,
+        11 => TcpState::Closing,
+        // TCP_NEW_SYN_RECV is only used for request sockets, which have no inode
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_socket() {
+        let line = "   0: 0100007F:0277 00000000:0000 0A 00000000:00000000 00:00000000 \
+                    00000000     0        0 21543 1 0000000000000000 100 0 0 10 0";
+        let (inode, connection) = parse_socket(line, Protocol::Tcp).unwrap();
+        assert_eq!(inode, 21543);
+        assert_eq!(connection.local, "127.0.0.1:631".parse().unwrap());
+        assert_eq!(connection.remote, None);
+        assert_eq!(connection.state, Some(TcpState::Listen));
+
+        let address = parse_address("00000000000000000000000001000000:0035").unwrap();
+        assert_eq!(address, "[::1]:53".parse().unwrap());
+    }
+
+    #[test]
+    fn test_connections() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let local = listener.local_addr().unwrap();
+        let client = std::net::TcpStream::connect(local).unwrap();
+
+        let process = Process::new(std::process::id() as i32).unwrap();
+        let connections = process.connections().unwrap();
+        assert!(connections
+            .iter()
+            .any(|connection| connection.local == local
+                && connection.remote.is_none()
+                && connection.state == Some(TcpState::Listen)));
+        assert!(connections.iter().any(|connection| connection.local
+            == client.local_addr().unwrap()
+            && connection.remote == Some(local)
+            && connection.state == Some(TcpState::Established)));
+    }
+}
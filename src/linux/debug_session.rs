@@ -0,0 +1,449 @@
+//! A minimal debugger: software breakpoints, and an event loop that reports breakpoint hits,
+//! signals and threads starting and exiting.
+//!
+//! Every thread of the process is attached to with ptrace for as long as the session
+//! exists, but keeps running until it stops for an event. ptrace only lets the thread that
+//! attached wait for the tracee, so a session can't be moved to another thread.
+use std::collections::HashMap;
+use std::fs::File;
+use std::marker::PhantomData;
+use std::os::unix::fs::FileExt;
+use std::time::{Duration, Instant};
+
+use log::{debug, info, warn};
+use nix::sys::ptrace;
+use nix::sys::signal::Signal;
+use nix::sys::wait::{self, WaitPidFlag, WaitStatus};
+
+use super::step::single_step;
+use super::watchpoint::trap_pending;
+use super::{Pid, Process, Tid};
+use crate::Error;
+
+/// Something that happened to a process being debugged. Threads that report a breakpoint or
+/// a signal stay stopped until `DebugSession::resume` is called for them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugEvent {
+    /// A thread hit a breakpoint, and is stopped at the breakpoint's address
+    Breakpoint { tid: Tid, addr: u64 },
+    /// A thread was sent a signal. The signal is only delivered if it's passed to `resume`.
+    Signal { tid: Tid, signal: i32 },
+    /// A new thread was started. It's attached to, and running.
+    ThreadCreated(Tid),
+    /// A thread is exiting
+    ThreadExited(Tid),
+    /// The process exited with a status code, ending the session
+    Exited(i32),
+    /// The process was killed by a signal, ending the session
+    Killed(i32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ThreadState {
+    Running,
+    /// stopped by an event, along with the breakpoint it's at or the signal it was sent
+    Stopped {
+        breakpoint: Option<u64>,
+        signal: Option<Signal>,
+    },
+}
+
+/// A process attached to for debugging. The breakpoints are removed and the process is left
+/// running when this is dropped.
+pub struct DebugSession {
+    pub pid: Pid,
+    // writes through /proc/pid/mem can patch read-only code while the threads are running
+    memory: File,
+    threads: HashMap<nix::unistd::Pid, ThreadState>,
+    /// the original bytes under each breakpoint
+    breakpoints: HashMap<u64, Vec<u8>>,
+    exited: bool,
+    // ptrace requests have to come from the thread that attached
+    _not_send: PhantomData<*const ()>,
+}
+
+impl DebugSession {
+    /// Attaches to all threads of a process, without stopping them
+    pub fn attach(pid: Pid) -> Result<DebugSession, Error> {
+        let process = Process::new(pid)?;
+        let memory = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(format!("/proc/{}/mem", pid))?;
+        let mut session = DebugSession {
+            pid,
+            memory,
+            threads: HashMap::new(),
+            breakpoints: HashMap::new(),
+            exited: false,
+            _not_send: PhantomData,
+        };
+
+        // threads started by attached threads are attached to automatically, but threads can
+        // also start while we're attaching - so keep going until there are no new ones
+        let options = ptrace::Options::PTRACE_O_TRACECLONE | ptrace::Options::PTRACE_O_TRACEEXIT;
+        loop {
+            let mut attached = false;
+            for thread in process.threads()? {
+                if session.threads.contains_key(&thread.tid) {
+                    continue;
+                }
+                match ptrace::seize(thread.tid, options) {
+                    Ok(()) => {}
+                    // the thread exited before we could attach
+                    Err(nix::errno::Errno::ESRCH) => continue,
+                    Err(e) => return Err(e.into()),
+                }
+                session.threads.insert(thread.tid, ThreadState::Running);
+                attached = true;
+            }
+            if !attached {
+                break;
+            }
+        }
+        info!(
+            "attached to {} threads of {} for debugging",
+            session.threads.len(),
+            pid
+        );
+        Ok(session)
+    }
+
+    /// Sets a software breakpoint at the start of the instruction at `addr`
+    pub fn set_breakpoint(&mut self, addr: u64) -> Result<(), Error> {
+        if self.breakpoints.contains_key(&addr) {
+            return Ok(());
+        }
+        let mut original = vec![0; arch::BREAKPOINT.len()];
+        self.memory.read_exact_at(&mut original, addr)?;
+        self.memory.write_all_at(&arch::BREAKPOINT, addr)?;
+        self.breakpoints.insert(addr, original);
+        debug!("set breakpoint at {:#x} in {}", addr, self.pid);
+        Ok(())
+    }
+
+    /// Removes a breakpoint, restoring the original instruction
+    pub fn remove_breakpoint(&mut self, addr: u64) -> Result<(), Error> {
+        match self.breakpoints.remove(&addr) {
+            Some(original) => Ok(self.memory.write_all_at(&original, addr)?),
+            None => Err(Error::Other(format!("no breakpoint at {:#x}", addr))),
+        }
+    }
+
+    /// The addresses of the breakpoints that are set
+    pub fn breakpoints(&self) -> Vec<u64> {
+        self.breakpoints.keys().copied().collect()
+    }
+
+    /// The threads of the process
+    pub fn threads(&self) -> Vec<Tid> {
+        self.threads.keys().map(|tid| tid.as_raw()).collect()
+    }
+
+    /// Waits up to `timeout` for the next event, returning None if there wasn't one
+    pub fn wait(&mut self, timeout: Duration) -> Result<Option<DebugEvent>, Error> {
+        let start = Instant::now();
+        while !self.exited {
+            let running: Vec<_> = self
+                .threads
+                .iter()
+                .filter(|(_, state)| **state == ThreadState::Running)
+                .map(|(tid, _)| *tid)
+                .collect();
+            for tid in running {
+                let flags = WaitPidFlag::__WALL | WaitPidFlag::WNOHANG;
+                let status = match wait::waitpid(tid, Some(flags)) {
+                    Ok(WaitStatus::StillAlive) => continue,
+                    Ok(status) => status,
+                    // the thread has already exited and been reaped
+                    Err(nix::errno::Errno::ECHILD) => {
+                        self.threads.remove(&tid);
+                        continue;
+                    }
+                    Err(e) => return Err(e.into()),
+                };
+                if let Some(event) = self.handle_status(tid, status)? {
+                    return Ok(Some(event));
+                }
+            }
+            if start.elapsed() >= timeout {
+                return Ok(None);
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        Err(Error::ProcessExited { pid: self.pid })
+    }
+
+    /// Lets a thread stopped by an event carry on running, delivering `signal` to it. A
+    /// thread stopped at a breakpoint steps over it first, with the breakpoint removed just
+    /// for that instruction.
+    pub fn resume(&mut self, tid: Tid, signal: Option<i32>) -> Result<(), Error> {
+        let tid = nix::unistd::Pid::from_raw(tid);
+        let signal = signal.map(Signal::try_from).transpose()?;
+        let breakpoint = match self.threads.get(&tid) {
+            Some(ThreadState::Stopped { breakpoint, .. }) => *breakpoint,
+            _ => return Err(Error::Other(format!("thread {} isn't stopped", tid))),
+        };
+        if let Some(addr) = breakpoint.filter(|addr| self.breakpoints.contains_key(addr)) {
+            self.step_over(tid, addr)?;
+        }
+        ptrace::cont(tid, signal)?;
+        self.threads.insert(tid, ThreadState::Running);
+        Ok(())
+    }
+
+    fn step_over(&mut self, tid: nix::unistd::Pid, addr: u64) -> Result<(), Error> {
+        self.memory.write_all_at(&self.breakpoints[&addr], addr)?;
+        let result = single_step(tid);
+        self.memory.write_all_at(&arch::BREAKPOINT, addr)?;
+        result
+    }
+
+    /// Handles a thread stopping, returning the event to report for it if there is one.
+    /// Threads are resumed if there isn't.
+    fn handle_status(
+        &mut self,
+        tid: nix::unistd::Pid,
+        status: WaitStatus,
+    ) -> Result<Option<DebugEvent>, Error> {
+        let raw = tid.as_raw();
+        Ok(match status {
+            WaitStatus::Stopped(_, Signal::SIGTRAP) => {
+                let ip = arch::ip(tid)?;
+                let addr = ip - arch::BREAKPOINT_IP_OFFSET;
+                if self.breakpoints.contains_key(&addr) {
+                    arch::set_ip(tid, addr)?;
+                    let state = ThreadState::Stopped {
+                        breakpoint: Some(addr),
+                        signal: None,
+                    };
+                    self.threads.insert(tid, state);
+                    Some(DebugEvent::Breakpoint { tid: raw, addr })
+                } else {
+                    let state = ThreadState::Stopped {
+                        breakpoint: None,
+                        signal: Some(Signal::SIGTRAP),
+                    };
+                    self.threads.insert(tid, state);
+                    Some(DebugEvent::Signal {
+                        tid: raw,
+                        signal: Signal::SIGTRAP as i32,
+                    })
+                }
+            }
+            WaitStatus::Stopped(_, signal) => {
+                let state = ThreadState::Stopped {
+                    breakpoint: None,
+                    signal: Some(signal),
+                };
+                self.threads.insert(tid, state);
+                Some(DebugEvent::Signal {
+                    tid: raw,
+                    signal: signal as i32,
+                })
+            }
+            WaitStatus::PtraceEvent(_, _, event)
+                if event == ptrace::Event::PTRACE_EVENT_CLONE as i32 =>
+            {
+                let new = nix::unistd::Pid::from_raw(ptrace::getevent(tid)? as i32);
+                // the new thread is attached, and reports a stop once it starts
+                self.threads.insert(new, ThreadState::Running);
+                ptrace::cont(tid, None)?;
+                Some(DebugEvent::ThreadCreated(new.as_raw()))
+            }
+            WaitStatus::PtraceEvent(_, _, event)
+                if event == ptrace::Event::PTRACE_EVENT_EXIT as i32 =>
+            {
+                ptrace::cont(tid, None)?;
+                Some(DebugEvent::ThreadExited(raw))
+            }
+            // new threads starting, group stops, and anything else we didn't ask for
+            WaitStatus::PtraceEvent(..)
+            | WaitStatus::PtraceSyscall(_)
+            | WaitStatus::Continued(_) => {
+                ptrace::cont(tid, None)?;
+                None
+            }
+            WaitStatus::Exited(_, code) => {
+                self.threads.remove(&tid);
+                if raw != self.pid {
+                    return Ok(None);
+                }
+                self.exited = true;
+                Some(DebugEvent::Exited(code))
+            }
+            WaitStatus::Signaled(_, signal, _) => {
+                self.threads.remove(&tid);
+                if raw != self.pid {
+                    return Ok(None);
+                }
+                self.exited = true;
+                Some(DebugEvent::Killed(signal as i32))
+            }
+            WaitStatus::StillAlive => None,
+        })
+    }
+
+    /// Stops a running thread so it can be detached from, returning the signal to deliver
+    /// to it if it stopped for one of those first
+    fn stop(&mut self, tid: nix::unistd::Pid) -> Result<Option<Signal>, Error> {
+        ptrace::interrupt(tid)?;
+        let mut signal = None;
+        loop {
+            match wait::waitpid(tid, Some(WaitPidFlag::__WALL))? {
+                WaitStatus::PtraceEvent(_, _, event)
+                    if event == ptrace::Event::PTRACE_EVENT_STOP as i32 =>
+                {
+                    return Ok(signal)
+                }
+                WaitStatus::Stopped(_, Signal::SIGTRAP) => {
+                    // a breakpoint that's already been removed, so rewind to run the
+                    // original instruction
+                    let ip = arch::ip(tid)?;
+                    arch::set_ip(tid, ip - arch::BREAKPOINT_IP_OFFSET)?;
+                    ptrace::cont(tid, None)?;
+                }
+                WaitStatus::Stopped(_, other) => {
+                    signal = Some(other);
+                    ptrace::cont(tid, None)?;
+                }
+                WaitStatus::Exited(..) | WaitStatus::Signaled(..) => {
+                    return Err(Error::Other(format!("thread {} has exited", tid)))
+                }
+                _ => ptrace::cont(tid, None)?,
+            }
+        }
+    }
+}
+
+impl Drop for DebugSession {
+    fn drop(&mut self) {
+        if self.exited {
+            return;
+        }
+        for (addr, original) in self.breakpoints.drain() {
+            if let Err(e) = self.memory.write_all_at(&original, addr) {
+                warn!("failed to remove breakpoint at {:#x}: {}", addr, e);
+            }
+        }
+        let threads: Vec<_> = self.threads.drain().collect();
+        for (tid, state) in threads {
+            // signals that stopped a thread still get delivered, as if we weren't here
+            let signal = match state {
+                ThreadState::Stopped { signal, .. } => signal,
+                ThreadState::Running => match self.stop(tid) {
+                    Ok(signal) => signal,
+                    Err(e) => {
+                        warn!("failed to stop thread {}: {}", tid, e);
+                        continue;
+                    }
+                },
+            };
+            // a breakpoint hit while the thread was being stopped leaves a SIGTRAP pending,
+            // which would kill it once detached
+            while trap_pending(tid) {
+                if let Err(e) = ptrace::cont(tid, None)
+                    .map_err(Error::from)
+                    .and_then(|_| self.stop(tid))
+                {
+                    warn!("failed to flush pending traps for thread {}: {}", tid, e);
+                    break;
+                }
+            }
+            if let Err(e) = ptrace::detach(tid, signal) {
+                warn!("failed to detach from thread {}: {}", tid, e);
+            }
+        }
+        debug!("detached from {}", self.pid);
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod arch {
+    use crate::Error;
+    use nix::sys::ptrace;
+
+    /// int3
+    pub const BREAKPOINT: [u8; 1] = [0xcc];
+    /// int3 traps after the instruction, so the instruction pointer is one past it
+    pub const BREAKPOINT_IP_OFFSET: u64 = 1;
+
+    pub fn ip(tid: nix::unistd::Pid) -> Result<u64, Error> {
+        Ok(ptrace::getregs(tid)?.rip)
+    }
+
+    pub fn set_ip(tid: nix::unistd::Pid, ip: u64) -> Result<(), Error> {
+        let mut regs = ptrace::getregs(tid)?;
+        regs.rip = ip;
+        Ok(ptrace::setregs(tid, regs)?)
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod arch {
+    use crate::linux::regset::{read_regset, write_regset};
+    use crate::Error;
+
+    /// brk #0
+    pub const BREAKPOINT: [u8; 4] = 0xd420_0000_u32.to_le_bytes();
+    /// brk traps before the instruction, leaving the pc pointing at it
+    pub const BREAKPOINT_IP_OFFSET: u64 = 0;
+
+    // user_pt_regs: x0-x30, sp, pc, pstate
+    pub fn ip(tid: nix::unistd::Pid) -> Result<u64, Error> {
+        let mut regs = [0_u64; 34];
+        read_regset(tid, libc::NT_PRSTATUS, &mut regs)?;
+        Ok(regs[32])
+    }
+
+    pub fn set_ip(tid: nix::unistd::Pid, ip: u64) -> Result<(), Error> {
+        let mut regs = [0_u64; 34];
+        read_regset(tid, libc::NT_PRSTATUS, &mut regs)?;
+        regs[32] = ip;
+        write_regset(tid, libc::NT_PRSTATUS, &regs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ProcessMemory;
+
+    #[test]
+    fn test_set_remove_breakpoint() {
+        let mut child = std::process::Command::new("sleep")
+            .arg("10")
+            .spawn()
+            .unwrap();
+        let pid = child.id() as Pid;
+        // give the child a chance to exec, so we patch sleep rather than ourselves
+        std::thread::sleep(Duration::from_millis(100));
+
+        let maps = proc_maps::get_process_maps(pid).unwrap();
+        let code = maps.iter().find(|map| map.is_exec()).unwrap();
+        let addr = code.start() as u64;
+
+        let process = Process::new(pid).unwrap();
+        let original: [u8; 4] = process.copy_struct(addr as usize).unwrap();
+        assert!(!process.is_being_debugged().unwrap());
+        {
+            let mut session = DebugSession::attach(pid).unwrap();
+            assert!(process.is_being_debugged().unwrap());
+            assert_eq!(session.threads(), vec![pid]);
+            session.set_breakpoint(addr).unwrap();
+            assert_eq!(session.breakpoints(), vec![addr]);
+            let patched: [u8; 4] = process.copy_struct(addr as usize).unwrap();
+            assert_eq!(&patched[..arch::BREAKPOINT.len()], &arch::BREAKPOINT[..]);
+            session.remove_breakpoint(addr).unwrap();
+            assert!(session.remove_breakpoint(addr).is_err());
+        }
+        let restored: [u8; 4] = process.copy_struct(addr as usize).unwrap();
+        assert_eq!(restored, original);
+        assert!(!process.is_being_debugged().unwrap());
+
+        // the child should carry on running once we've detached
+        assert!(child.try_wait().unwrap().is_none());
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+}
@@ -0,0 +1,742 @@
+//! A DWARF based stack unwinder that uses the .eh_frame sections of each loaded binary.
+//!
+//! Unlike libunwind this doesn't need any native dependencies, and it evaluates the full
+//! DWARF expression language for CFA and register rules (DW_CFA_def_cfa_expression,
+//! DW_CFA_expression and DW_CFA_val_expression), reading memory from the target process
+//! as needed. Code with unusual prologues (hand written assembly, cgo trampolines, signal
+//! frames) often relies on these.
+//!
+//! The unwinder isn't tied to a live process: it can be built from any `ProcessMemory` and a
+//! list of the modules loaded in the target, which lets core dumps and remote targets share
+//! the same unwinding logic.
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock};
+
+use addr2line::gimli::{
+    self, EhFrame, EndianSlice, EvaluationResult, Expression, Location, NativeEndian, Register,
+    RegisterRule, UnwindContext, Value,
+};
+use log::{debug, info, warn};
+use memmap2::Mmap;
+use object::Object;
+
+use super::signal_frame;
+use super::unwind_cache::{self, UnwindInfo};
+use super::{resolve_path, Pid, Process, Thread};
+use crate::unwind::unavailable_register;
+use crate::{Error, ProcessMemory, StackCopy};
+
+pub(super) type Reader<'a> = EndianSlice<'a, NativeEndian>;
+
+/// Number of DWARF registers we track. This covers the general purpose registers on
+/// x86_64 (0-16), aarch64 (0-31) and riscv64 (0-31)
+pub(super) const REGISTER_COUNT: usize = 33;
+
+#[cfg(target_arch = "x86_64")]
+const SP: u16 = 7;
+#[cfg(target_arch = "aarch64")]
+const SP: u16 = 31;
+#[cfg(target_arch = "riscv64")]
+const SP: u16 = 2;
+
+pub(super) type Registers = [Option<u64>; REGISTER_COUNT];
+
+/// Most frames we'll walk through before giving up, in case the unwind info of some function
+/// sends us around in a loop
+const MAX_FRAMES: usize = 4096;
+
+fn unwind_error<E: std::fmt::Display>(err: E) -> Error {
+    Error::UnwindError {
+        message: err.to_string(),
+    }
+}
+
+/// A binary loaded in the target process
+struct Module {
+    /// difference between the addresses in the binary and where it is loaded in memory
+    bias: u64,
+    info: Arc<UnwindInfo>,
+}
+
+/// An executable mapping of a binary. The unwind info for the binary is only loaded the
+/// first time an address inside the mapping is unwound, since most stacks only touch a
+/// handful of the libraries a process has loaded.
+struct Mapping {
+    start: u64,
+    end: u64,
+    offset: u64,
+    /// the load bias, when it's known up front rather than calculated from the file offset
+    bias: Option<u64>,
+    filename: PathBuf,
+    module: OnceLock<Option<Module>>,
+}
+
+/// Provides the registers of the innermost frame of a stack, for a cursor to start
+/// unwinding from
+pub trait RegisterSource {
+    /// Returns the instruction pointer, and the values of the DWARF registers indexed by
+    /// register number. Registers that aren't available can be left as None.
+    fn unwind_registers(&self) -> Result<(u64, Vec<Option<u64>>), Error>;
+}
+
+impl RegisterSource for Thread {
+    /// Reads the registers of the thread, which needs to be locked
+    fn unwind_registers(&self) -> Result<(u64, Vec<Option<u64>>), Error> {
+        let (ip, regs) = initial_registers(self)?;
+        Ok((ip, regs.to_vec()))
+    }
+}
+
+/// Starts from registers that were captured earlier - by `Process::capture_all_registers`,
+/// from a perf sample or in a signal handler - rather than reading them from a live thread
+impl RegisterSource for super::Registers {
+    fn unwind_registers(&self) -> Result<(u64, Vec<Option<u64>>), Error> {
+        let (ip, regs) = user_registers(self);
+        Ok((ip, regs.to_vec()))
+    }
+}
+
+/// Starts from the registers in the copy, so that a copied stack can be unwound by an
+/// unwinder created over the copy
+impl RegisterSource for StackCopy<super::Registers> {
+    fn unwind_registers(&self) -> Result<(u64, Vec<Option<u64>>), Error> {
+        self.registers.unwind_registers()
+    }
+}
+
+/// Unwinds stacks using the .eh_frame sections of the binaries loaded in a process
+pub struct DwarfUnwinder<M = Process> {
+    memory: M,
+    /// the process to reload the list of binaries from, or None if the unwinder was created
+    /// from a fixed list of modules
+    pid: Option<Pid>,
+    /// executable mappings keyed by their end address
+    mappings: BTreeMap<u64, Mapping>,
+}
+
+impl DwarfUnwinder {
+    pub fn new(pid: Pid) -> Result<DwarfUnwinder, Error> {
+        let mut unwinder = DwarfUnwinder {
+            memory: Process::new(pid)?,
+            pid: Some(pid),
+            mappings: BTreeMap::new(),
+        };
+        unwinder.reload()?;
+        Ok(unwinder)
+    }
+}
+
+impl<M: ProcessMemory> DwarfUnwinder<M> {
+    /// Creates an unwinder that reads stacks from `memory`, using the unwind info of the
+    /// given modules. The filename of each module needs to be a path to a copy of the binary
+    /// that can be opened locally.
+    pub fn with_modules(memory: M, modules: &[crate::Module]) -> DwarfUnwinder<M> {
+        let mappings = modules
+            .iter()
+            .map(|module| {
+                let end = module.address + module.size;
+                let mapping = Mapping {
+                    start: module.address,
+                    end,
+                    offset: 0,
+                    bias: Some(module.bias),
+                    filename: PathBuf::from(&module.filename),
+                    module: OnceLock::new(),
+                };
+                (end, mapping)
+            })
+            .collect();
+        DwarfUnwinder {
+            memory,
+            pid: None,
+            mappings,
+        }
+    }
+
+    /// Reloads the list of binaries in the process, picking up any new shared libraries.
+    /// Mappings that haven't changed keep any unwind info that has already been loaded.
+    /// Unwinders created with `with_modules` have nothing to reload.
+    pub fn reload(&mut self) -> Result<(), Error> {
+        let pid = match self.pid {
+            Some(pid) => pid,
+            None => return Ok(()),
+        };
+        let maps = proc_maps::get_process_maps(pid)?;
+        let mut mappings = BTreeMap::new();
+        for m in maps.iter().filter(|m| m.is_exec() && m.is_read()) {
+            let filename = match m.filename() {
+                Some(filename) => filename,
+                None => continue,
+            };
+            let end = (m.start() + m.size()) as u64;
+            if let Some(mapping) = self.mappings.remove(&end) {
+                if mapping.start == m.start() as u64 && mapping.filename == filename {
+                    mappings.insert(end, mapping);
+                    continue;
+                }
+            }
+            mappings.insert(
+                end,
+                Mapping {
+                    start: m.start() as u64,
+                    end,
+                    offset: m.offset as u64,
+                    bias: None,
+                    filename: filename.to_path_buf(),
+                    module: OnceLock::new(),
+                },
+            );
+        }
+        self.mappings = mappings;
+        Ok(())
+    }
+
+    fn load_module(&self, mapping: &Mapping) -> Result<Option<Module>, Error> {
+        let path = match self.pid {
+            Some(pid) => resolve_path(
+                pid,
+                &mapping.filename,
+                mapping.start as usize,
+                mapping.end as usize,
+            ),
+            None => Some(mapping.filename.clone()),
+        };
+        let path = match path {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        let file = File::open(path)?;
+        let mmap = unsafe { Mmap::map(&file)? };
+        let object = object::File::parse(&*mmap).map_err(unwind_error)?;
+
+        // binaries with the same build-id have the same unwind tables, so reuse them if
+        // this binary has already been loaded by this or another process
+        let build_id = object.build_id().ok().flatten();
+        let info = match build_id.and_then(unwind_cache::get) {
+            Some(info) => info,
+            None => match UnwindInfo::from_object(&object).map_err(unwind_error)? {
+                Some(info) => {
+                    let info = Arc::new(info);
+                    if let Some(build_id) = build_id {
+                        unwind_cache::insert(build_id, Arc::clone(&info));
+                    }
+                    info
+                }
+                None => return Ok(None),
+            },
+        };
+
+        let bias = match mapping
+            .bias
+            .or_else(|| info.bias(mapping.start, mapping.offset))
+        {
+            Some(bias) => bias,
+            None => return Ok(None),
+        };
+        Ok(Some(Module { bias, info }))
+    }
+
+    /// Returns the module containing an address, loading its unwind info if this is the
+    /// first time it has been needed
+    fn module(&self, address: u64) -> Option<&Module> {
+        let (_, mapping) = self.mappings.range(address.checked_add(1)?..).next()?;
+        if mapping.start > address {
+            return None;
+        }
+        mapping
+            .module
+            .get_or_init(|| {
+                let filename = mapping.filename.display();
+                match self.load_module(mapping) {
+                    Ok(Some(module)) => {
+                        info!("loaded unwind info from {}", filename);
+                        Some(module)
+                    }
+                    Ok(None) => {
+                        debug!("no unwind info in {}", filename);
+                        None
+                    }
+                    Err(e) => {
+                        warn!("failed to load unwind info {}: {}", filename, e);
+                        None
+                    }
+                }
+            })
+            .as_ref()
+    }
+
+    /// Returns a cursor over the stack of a thread. The thread needs to be locked while
+    /// iterating.
+    pub fn cursor<R: RegisterSource>(&self, thread: &R) -> Result<DwarfCursor<'_, M>, Error> {
+        let (ip, values) = thread.unwind_registers()?;
+        let mut regs: Registers = [None; REGISTER_COUNT];
+        for (reg, value) in regs.iter_mut().zip(values) {
+            *reg = value;
+        }
+        Ok(DwarfCursor {
+            unwinder: self,
+            ctx: Box::new(UnwindContext::new()),
+            regs,
+            ip,
+            initial_frame: true,
+            pending_step: false,
+            exact_ip: true,
+            frames: 0,
+            done: false,
+        })
+    }
+}
+
+impl<M: ProcessMemory> crate::StackUnwinder for DwarfUnwinder<M> {
+    type Cursor<'a>
+        = DwarfCursor<'a, M>
+    where
+        Self: 'a;
+
+    fn cursor<'a>(&'a self, thread: &Thread) -> Result<DwarfCursor<'a, M>, Error> {
+        DwarfUnwinder::cursor(self, thread)
+    }
+}
+
+/// Iterates over the instruction pointers of each frame on a stack
+pub struct DwarfCursor<'a, M = Process> {
+    unwinder: &'a DwarfUnwinder<M>,
+    ctx: Box<UnwindContext<usize>>,
+    regs: Registers,
+    ip: u64,
+    initial_frame: bool,
+    /// true once `ip` has been returned, and the cursor needs to step to the caller before
+    /// returning another frame
+    pending_step: bool,
+    /// true if `ip` is where the frame was interrupted rather than a return address, which
+    /// is the case for the innermost frame and for frames interrupted by a signal
+    exact_ip: bool,
+    /// the number of frames returned so far
+    frames: usize,
+    done: bool,
+}
+
+impl<'a, M: ProcessMemory> DwarfCursor<'a, M> {
+    /// The instruction pointer of the current frame
+    pub fn ip(&self) -> u64 {
+        self.ip
+    }
+
+    /// The value of a DWARF register in the current frame, if it could be recovered
+    pub fn register(&self, register: u16) -> Option<u64> {
+        self.regs.get(register as usize).copied().flatten()
+    }
+
+    /// Moves to the caller of the current frame, returning false at the end of the stack
+    fn step(&mut self) -> Result<bool, Error> {
+        // return addresses point after the call instruction, which might be the start of the
+        // next function - so look up the unwind info for the call itself
+        let address = if self.exact_ip {
+            self.ip
+        } else {
+            self.ip.wrapping_sub(1)
+        };
+        let memory = &self.unwinder.memory;
+
+        let module = match self.unwinder.module(address) {
+            Some(module) => module,
+            None => return self.step_signal_frame(),
+        };
+        let eh_frame = EhFrame::new(&module.info.eh_frame, NativeEndian);
+        let bases = module.info.bases();
+        let svma = address.wrapping_sub(module.bias);
+        let fde = match module.info.fde(&eh_frame, &bases, svma) {
+            Ok(fde) => fde,
+            Err(gimli::Error::NoUnwindInfoForAddress) => return self.step_signal_frame(),
+            Err(e) => return Err(unwind_error(e)),
+        };
+        let encoding = fde.cie().encoding();
+        let return_address_register = fde.cie().return_address_register();
+        let row = fde
+            .unwind_info_for_address(&eh_frame, &bases, &mut self.ctx, svma)
+            .map_err(unwind_error)?;
+
+        let cfa = match row.cfa() {
+            gimli::CfaRule::RegisterAndOffset { register, offset } => {
+                register_value(&self.regs, *register)?.wrapping_add(*offset as u64)
+            }
+            gimli::CfaRule::Expression(expression) => {
+                let expression = expression.get(&eh_frame).map_err(unwind_error)?;
+                match evaluate(memory, expression, encoding, &self.regs, None, module.bias)? {
+                    Evaluated::Address(address) | Evaluated::Value(address) => address,
+                }
+            }
+        };
+
+        let mut regs: Registers = [None; REGISTER_COUNT];
+        for (i, reg) in regs.iter_mut().enumerate() {
+            let register = Register(i as u16);
+            let rule = row.register(register).unwrap_or(RegisterRule::SameValue);
+            *reg = match rule {
+                RegisterRule::Undefined => None,
+                RegisterRule::SameValue => self.regs[i],
+                RegisterRule::Offset(offset) => {
+                    Some(memory.copy_struct(cfa.wrapping_add(offset as u64) as usize)?)
+                }
+                RegisterRule::ValOffset(offset) => Some(cfa.wrapping_add(offset as u64)),
+                RegisterRule::Register(other) => register_value(&self.regs, other).ok(),
+                RegisterRule::Expression(expression) => {
+                    let expression = expression.get(&eh_frame).map_err(unwind_error)?;
+                    match evaluate(
+                        memory,
+                        expression,
+                        encoding,
+                        &self.regs,
+                        Some(cfa),
+                        module.bias,
+                    )? {
+                        Evaluated::Address(address) => Some(memory.copy_struct(address as usize)?),
+                        Evaluated::Value(value) => Some(value),
+                    }
+                }
+                RegisterRule::ValExpression(expression) => {
+                    let expression = expression.get(&eh_frame).map_err(unwind_error)?;
+                    match evaluate(
+                        memory,
+                        expression,
+                        encoding,
+                        &self.regs,
+                        Some(cfa),
+                        module.bias,
+                    )? {
+                        Evaluated::Address(value) | Evaluated::Value(value) => Some(value),
+                    }
+                }
+                RegisterRule::Constant(value) => Some(value),
+                RegisterRule::Architectural => {
+                    return Err(unwind_error("unsupported architectural register rule"))
+                }
+            };
+        }
+        // the stack pointer of the caller is the CFA, unless there is an explicit rule for it
+        if row.register(Register(SP)).is_none() {
+            regs[SP as usize] = Some(cfa);
+        }
+
+        let return_address = match regs
+            .get(return_address_register.0 as usize)
+            .copied()
+            .flatten()
+        {
+            Some(0) | None => return Ok(false),
+            Some(address) => address,
+        };
+
+        // the stack grows down, so the caller's stack pointer has to be above ours - anything
+        // else is corrupt unwind info that could loop forever. Signal handlers can run on an
+        // alternate stack though, so the interrupted code's stack can be anywhere
+        if !fde.is_signal_trampoline() {
+            match (self.regs[SP as usize], regs[SP as usize]) {
+                (Some(sp), Some(caller_sp)) if caller_sp > sp => {}
+                _ => return Ok(false),
+            }
+        }
+        self.regs = regs;
+        self.ip = return_address;
+        // the caller of a signal trampoline is wherever the signal interrupted
+        self.exact_ip = fde.is_signal_trampoline();
+        Ok(true)
+    }
+
+    /// Steps through a sigreturn trampoline that has no unwind info, by reading the
+    /// registers saved by the kernel when the signal was delivered
+    fn step_signal_frame(&mut self) -> Result<bool, Error> {
+        let memory = &self.unwinder.memory;
+        if !signal_frame::is_sigreturn_trampoline(memory, self.ip) {
+            return Ok(false);
+        }
+        let sp = register_value(&self.regs, Register(SP))?;
+        let (ip, regs) = signal_frame::signal_registers(memory, sp)?;
+        if ip == 0 {
+            return Ok(false);
+        }
+        self.regs = regs;
+        self.ip = ip;
+        self.exact_ip = true;
+        Ok(true)
+    }
+}
+
+impl<'a, M: ProcessMemory> crate::StackCursor for DwarfCursor<'a, M> {
+    fn ip(&self) -> Result<u64, Error> {
+        Ok(self.ip)
+    }
+
+    fn sp(&self) -> Result<u64, Error> {
+        crate::StackCursor::register(self, SP)
+    }
+
+    fn register(&self, register: u16) -> Result<u64, Error> {
+        DwarfCursor::register(self, register).ok_or_else(|| unavailable_register(register))
+    }
+}
+
+impl<'a, M: ProcessMemory> Iterator for DwarfCursor<'a, M> {
+    type Item = Result<u64, Error>;
+
+    fn next(&mut self) -> Option<Result<u64, Error>> {
+        if self.done {
+            return None;
+        }
+        if self.frames >= MAX_FRAMES {
+            warn!("stopped unwinding after {} frames", MAX_FRAMES);
+            self.done = true;
+            return None;
+        }
+
+        // only step past the frame we last returned once the next one is asked for, so that
+        // ip() and register() describe the frame the caller was just given
+        if self.pending_step {
+            match self.step() {
+                Ok(true) => self.initial_frame = false,
+                Ok(false) => {
+                    self.done = true;
+                    return None;
+                }
+                Err(e) => {
+                    self.done = true;
+                    // the address of the frame we failed to unwind past has already been
+                    // returned, which is all we get if there is no unwind info for the
+                    // innermost frame
+                    if !self.initial_frame {
+                        return Some(Err(e));
+                    }
+                    warn!("failed to unwind from {:#x}: {}", self.ip, e);
+                    return None;
+                }
+            }
+        }
+        self.pending_step = true;
+        self.frames += 1;
+        Some(Ok(self.ip))
+    }
+}
+
+fn register_value(regs: &Registers, register: Register) -> Result<u64, Error> {
+    regs.get(register.0 as usize)
+        .copied()
+        .flatten()
+        .ok_or_else(|| unwind_error(format!("register {} isn't available", register.0)))
+}
+
+/// The result of evaluating a DWARF expression
+#[derive(Debug, PartialEq, Eq)]
+enum Evaluated {
+    /// The expression gave the address of the value
+    Address(u64),
+    /// The expression gave the value itself
+    Value(u64),
+}
+
+/// Evaluates a DWARF expression, reading registers from `regs` and memory from the target
+/// process. `cfa` is pushed on the stack before evaluating register rules, as the DWARF spec
+/// requires for DW_CFA_expression and DW_CFA_val_expression.
+fn evaluate<M: ProcessMemory>(
+    memory: &M,
+    expression: Expression<Reader>,
+    encoding: gimli::Encoding,
+    regs: &Registers,
+    cfa: Option<u64>,
+    bias: u64,
+) -> Result<Evaluated, Error> {
+    let mut evaluation = expression.evaluation(encoding);
+    if let Some(cfa) = cfa {
+        evaluation.set_initial_value(cfa);
+    }
+
+    let mut result = evaluation.evaluate().map_err(unwind_error)?;
+    loop {
+        result = match result {
+            EvaluationResult::Complete => break,
+            EvaluationResult::RequiresMemory { address, size, .. } => {
+                let mut bytes = [0_u8; 8];
+                let size = (size as usize).min(bytes.len());
+                memory.read(address as usize, &mut bytes[..size])?;
+                let value = Value::Generic(u64::from_ne_bytes(bytes));
+                evaluation.resume_with_memory(value)
+            }
+            EvaluationResult::RequiresRegister { register, .. } => {
+                let value = Value::Generic(register_value(regs, register)?);
+                evaluation.resume_with_register(value)
+            }
+            EvaluationResult::RequiresCallFrameCfa => match cfa {
+                Some(cfa) => evaluation.resume_with_call_frame_cfa(cfa),
+                None => return Err(unwind_error("CFA expression refers to the CFA")),
+            },
+            EvaluationResult::RequiresRelocatedAddress(address) => {
+                evaluation.resume_with_relocated_address(address.wrapping_add(bias))
+            }
+            other => {
+                return Err(unwind_error(format!(
+                    "unsupported operation in unwind expression: {:?}",
+                    other
+                )))
+            }
+        }
+        .map_err(unwind_error)?;
+    }
+
+    let pieces = evaluation.result();
+    match pieces.first().map(|piece| &piece.location) {
+        Some(Location::Address { address }) => Ok(Evaluated::Address(*address)),
+        Some(Location::Value { value }) => {
+            Ok(Evaluated::Value(value.to_u64(!0).map_err(unwind_error)?))
+        }
+        Some(Location::Register { register }) => {
+            Ok(Evaluated::Value(register_value(regs, *register)?))
+        }
+        other => Err(unwind_error(format!(
+            "unsupported unwind expression result {:?}",
+            other
+        ))),
+    }
+}
+
+/// Reads the instruction pointer and DWARF registers of a locked thread
+#[cfg(target_arch = "x86_64")]
+fn initial_registers(thread: &Thread) -> Result<(u64, Registers), Error> {
+    let r = nix::sys::ptrace::getregs(thread.tid)?;
+    Ok(user_registers(&r))
+}
+
+/// Converts the registers ptrace (or a perf sample) reports into DWARF registers
+#[cfg(target_arch = "x86_64")]
+fn user_registers(r: &super::Registers) -> (u64, Registers) {
+    let mut regs: Registers = [None; REGISTER_COUNT];
+    let values = [
+        r.rax, r.rdx, r.rcx, r.rbx, r.rsi, r.rdi, r.rbp, r.rsp, r.r8, r.r9, r.r10, r.r11, r.r12,
+        r.r13, r.r14, r.r15, r.rip,
+    ];
+    for (reg, value) in regs.iter_mut().zip(values) {
+        *reg = Some(value);
+    }
+    (r.rip, regs)
+}
+
+#[cfg(target_arch = "aarch64")]
+fn user_registers(r: &super::Registers) -> (u64, Registers) {
+    let mut regs: Registers = [None; REGISTER_COUNT];
+    for (reg, value) in regs.iter_mut().zip(r.regs) {
+        *reg = Some(value);
+    }
+    regs[SP as usize] = Some(r.sp);
+    (r.pc, regs)
+}
+
+#[cfg(target_arch = "aarch64")]
+fn initial_registers(thread: &Thread) -> Result<(u64, Registers), Error> {
+    Ok(user_registers(&thread.registers()?))
+}
+
+#[cfg(target_arch = "riscv64")]
+fn initial_registers(thread: &Thread) -> Result<(u64, Registers), Error> {
+    Ok(user_registers(&thread.registers()?))
+}
+
+#[cfg(target_arch = "riscv64")]
+fn user_registers(r: &super::Registers) -> (u64, Registers) {
+    let values = [
+        0, r.ra, r.sp, r.gp, r.tp, r.t0, r.t1, r.t2, r.s0, r.s1, r.a0, r.a1, r.a2, r.a3, r.a4,
+        r.a5, r.a6, r.a7, r.s2, r.s3, r.s4, r.s5, r.s6, r.s7, r.s8, r.s9, r.s10, r.s11, r.t3, r.t4,
+        r.t5, r.t6,
+    ];
+    let mut regs: Registers = [None; REGISTER_COUNT];
+    for (reg, value) in regs.iter_mut().zip(values) {
+        *reg = Some(value);
+    }
+    (r.pc, regs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake_memory::FakeMemory;
+
+    fn expression(bytes: &[u8]) -> Expression<Reader<'_>> {
+        Expression(EndianSlice::new(bytes, NativeEndian))
+    }
+
+    const ENCODING: gimli::Encoding = gimli::Encoding {
+        address_size: 8,
+        format: gimli::Format::Dwarf32,
+        version: 4,
+    };
+
+    #[test]
+    fn test_evaluate() {
+        let stack = FakeMemory::from_words(0x1000, &[0_u64, 0x1234]);
+        let mut regs: Registers = [None; REGISTER_COUNT];
+        regs[7] = Some(0x1000);
+
+        // DW_OP_breg7 +8
+        let result = evaluate(&stack, expression(&[0x77, 0x08]), ENCODING, &regs, None, 0);
+        assert_eq!(result.unwrap(), Evaluated::Address(0x1008));
+
+        // DW_OP_breg7 +8; DW_OP_deref
+        let result = evaluate(
+            &stack,
+            expression(&[0x77, 0x08, 0x06]),
+            ENCODING,
+            &regs,
+            None,
+            0,
+        );
+        assert_eq!(result.unwrap(), Evaluated::Address(0x1234));
+
+        // DW_OP_lit16; DW_OP_plus, with the CFA pushed first
+        let result = evaluate(
+            &stack,
+            expression(&[0x40, 0x22]),
+            ENCODING,
+            &regs,
+            Some(0x2000),
+            0,
+        );
+        assert_eq!(result.unwrap(), Evaluated::Address(0x2010));
+
+        // DW_OP_breg3 reads a register we don't have
+        assert!(evaluate(&stack, expression(&[0x73, 0x00]), ENCODING, &regs, None, 0).is_err());
+    }
+
+    #[test]
+    fn test_with_modules() {
+        let module = crate::Module {
+            filename: "/nonexistent/libfoo.so".to_owned(),
+            address: 0x1000,
+            size: 0x1000,
+            bias: 0,
+            id: None,
+        };
+        let mut unwinder =
+            DwarfUnwinder::with_modules(FakeMemory::new(0x1000, Vec::new()), &[module]);
+        // there's no process to reload the module list from
+        unwinder.reload().unwrap();
+        assert_eq!(unwinder.mappings.len(), 1);
+        // the binary can't be opened, so there's no unwind info for the mapping
+        assert!(unwinder.module(0x1800).is_none());
+        assert!(unwinder.module(0x2000).is_none());
+        assert!(unwinder.module(u64::MAX).is_none());
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_captured_registers() {
+        // zero is a valid bit pattern for user_regs_struct, which is all integers
+        let mut registers: crate::Registers = unsafe { std::mem::zeroed() };
+        registers.rip = 0x401000;
+        registers.rsp = 0x7ffe_0000;
+        registers.rbp = 0x7ffe_0010;
+        let (ip, regs) = registers.unwind_registers().unwrap();
+        assert_eq!(ip, 0x401000);
+        assert_eq!(regs[SP as usize], Some(0x7ffe_0000));
+        assert_eq!(regs[6], Some(0x7ffe_0010));
+        assert_eq!(regs[16], Some(0x401000));
+    }
+}
@@ -0,0 +1,40 @@
+//! Reads the registers needed for frame pointer unwinding from a stopped thread
+#[cfg(target_arch = "arm")]
+use super::regset::get_regset;
+use super::Thread;
+use crate::Error;
+
+impl Thread {
+    /// Returns the instruction pointer and frame pointer of this thread. The thread needs to
+    /// be locked for this to succeed.
+    #[cfg(target_arch = "x86_64")]
+    pub fn frame_registers(&self) -> Result<(u64, u64), Error> {
+        let regs = nix::sys::ptrace::getregs(self.tid)?;
+        Ok((regs.rip, regs.rbp))
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    pub fn frame_registers(&self) -> Result<(u64, u64), Error> {
+        // x29 is the frame pointer
+        let regs = self.registers()?;
+        Ok((regs.pc, regs.regs[29]))
+    }
+
+    #[cfg(target_arch = "arm")]
+    pub fn frame_registers(&self) -> Result<(u64, u64), Error> {
+        // r0-r15, cpsr, orig_r0. thumb code uses r7 as the frame pointer, arm code uses r11
+        let regs: [u32; 18] = get_regset(self.tid, libc::NT_PRSTATUS)?;
+        let fp = if regs[16] & (1 << 5) != 0 {
+            regs[7]
+        } else {
+            regs[11]
+        };
+        Ok((regs[15] as u64, fp as u64))
+    }
+
+    #[cfg(any(target_arch = "riscv64", target_arch = "loongarch64"))]
+    pub fn frame_registers(&self) -> Result<(u64, u64), Error> {
+        let regs = self.registers()?;
+        Ok((regs.ip(), regs.fp()))
+    }
+}
@@ -0,0 +1,170 @@
+//! Loads shared libraries into a target process, by making it call dlopen
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileExt, MetadataExt};
+use std::path::Path;
+
+use log::info;
+
+use super::{resolve_path, Pid, Process};
+use crate::{Error, ProcessMemory, Protection};
+
+impl Process {
+    /// Loads a shared library into the process with dlopen, returning the address it was
+    /// loaded at. The path is opened by the target, so it needs to be valid inside of the
+    /// target's mount namespace.
+    ///
+    /// dlopen runs on the main thread wherever it was interrupted, and takes locks in the
+    /// dynamic loader and malloc - so this can deadlock the process if the main thread was
+    /// holding them.
+    pub fn inject_library<P: AsRef<Path>>(&self, path: P) -> Result<u64, Error> {
+        let path = path.as_ref();
+        let dlopen = find_function(self.pid, &["dlopen", "__libc_dlopen_mode"])?;
+
+        let mut name = path.as_os_str().as_bytes().to_vec();
+        name.push(0);
+        let addr = self.alloc(name.len(), Protection::READ_WRITE)?;
+        let result = self
+            .write_memory(addr, &name)
+            .and_then(|_| self.call_function(dlopen, &[addr, libc::RTLD_NOW as u64]));
+        self.free(addr)?;
+
+        if result? == 0 {
+            return Err(Error::Other(format!(
+                "failed to load {} into {}: {}",
+                path.display(),
+                self.pid,
+                self.dlerror().unwrap_or_else(|| "unknown error".to_owned())
+            )));
+        }
+        let base = self.library_base(path)?;
+        info!("loaded {} into {} at {:#x}", path.display(), self.pid, base);
+        Ok(base)
+    }
+
+    /// Returns the message for the last dlopen failure in the process
+    fn dlerror(&self) -> Option<String> {
+        let dlerror = find_function(self.pid, &["dlerror"]).ok()?;
+        let message = self.call_function(dlerror, &[]).ok()?;
+        if message == 0 {
+            return None;
+        }
+        let mut bytes = vec![0; 512];
+        self.read(message as usize, &mut bytes).ok()?;
+        let len = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+        Some(String::from_utf8_lossy(&bytes[..len]).into_owned())
+    }
+
+    /// Finds where a library was loaded, by looking for a mapping of the same file
+    fn library_base(&self, path: &Path) -> Result<u64, Error> {
+        // dlopen searches the library path for bare file names, so only the name can match
+        let inode = match resolve_path(self.pid, path, 0, 0) {
+            Some(resolved) if path.is_absolute() => Some(std::fs::metadata(resolved)?.ino()),
+            _ => None,
+        };
+        proc_maps::get_process_maps(self.pid)?
+            .iter()
+            .filter(|map| map.offset == 0)
+            .filter(|map| match inode {
+                Some(inode) => map.inode as u64 == inode,
+                None => map.filename().and_then(|f| f.file_name()) == path.file_name(),
+            })
+            .map(|map| map.start() as u64)
+            .min()
+            .ok_or_else(|| {
+                Error::Other(format!("{} isn't mapped into {}", path.display(), self.pid))
+            })
+    }
+
+    fn write_memory(&self, addr: u64, data: &[u8]) -> Result<(), Error> {
+        let memory = std::fs::OpenOptions::new()
+            .write(true)
+            .open(format!("/proc/{}/mem", self.pid))?;
+        memory.write_all_at(data, addr)?;
+        Ok(())
+    }
+}
+
+/// Finds the address of an exported function in the C library or dynamic loader of a process,
+/// trying each of `names` in turn
+fn find_function(pid: Pid, names: &[&str]) -> Result<u64, Error> {
+    let maps = proc_maps::get_process_maps(pid)?;
+    for name in names {
+        for map in &maps {
+            let filename = match map.filename() {
+                Some(filename) if map.offset == 0 => filename,
+                _ => continue,
+            };
+            let file_name = filename
+                .file_name()
+                .map(|file_name| file_name.to_string_lossy())
+                .unwrap_or_default();
+            // dlopen lives in libc on glibc 2.34 and musl, and in libdl everywhere else
+            if !["libc.", "libc-", "libdl.", "libdl-", "ld-musl"]
+                .iter()
+                .any(|prefix| file_name.starts_with(prefix))
+            {
+                continue;
+            }
+            let path = match resolve_path(pid, filename, map.start(), map.start() + map.size()) {
+                Some(path) => path,
+                None => continue,
+            };
+            if let Some(offset) = exported_function(&path, name)? {
+                return Ok(map.start() as u64 + offset);
+            }
+        }
+    }
+    Err(Error::Other(format!(
+        "couldn't find {} in process {}",
+        names.join(" or "),
+        pid
+    )))
+}
+
+/// Returns the offset of an exported function from where an ELF file is loaded
+fn exported_function(path: &Path, name: &str) -> Result<Option<u64>, Error> {
+    let data = std::fs::read(path)?;
+    let elf = goblin::elf::Elf::parse(&data)?;
+    // the first segment is mapped at the start of the mapping, whatever its address
+    let first_vaddr = elf
+        .program_headers
+        .iter()
+        .filter(|header| header.p_type == goblin::elf::program_header::PT_LOAD)
+        .map(|header| header.p_vaddr & !(header.p_align.max(1) - 1))
+        .min()
+        .unwrap_or(0);
+    Ok(elf
+        .dynsyms
+        .iter()
+        .find(|sym| {
+            sym.is_function()
+                && sym.st_value != 0
+                && elf.dynstrtab.get_at(sym.st_name) == Some(name)
+        })
+        .map(|sym| sym.st_value - first_vaddr))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inject_library() {
+        let mut child = std::process::Command::new("sleep")
+            .arg("0.5")
+            .spawn()
+            .unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        let process = Process::new(child.id() as i32).unwrap();
+
+        // libm isn't needed by sleep, but is installed everywhere libc is
+        let base = process.inject_library("libm.so.6").unwrap();
+        let header: [u8; 4] = process.copy_struct(base as usize).unwrap();
+        assert_eq!(&header, b"\x7fELF");
+
+        assert!(process.inject_library("/nonexistent/libfoo.so").is_err());
+
+        // the child should carry on sleeping where it left off, and then exit normally
+        assert!(child.wait().unwrap().success());
+    }
+}
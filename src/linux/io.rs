@@ -0,0 +1,39 @@
+use crate::{Error, IoCounters};
+
+/// Parses the counters from /proc/pid/io. rchar and wchar count every byte passed to read
+/// and write like syscalls, including those served from the page cache or sent to a socket.
+pub fn parse_io(contents: &str) -> Result<IoCounters, Error> {
+    let field = |name: &str| -> Result<u64, Error> {
+        contents
+            .lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+            .and_then(|value| value.trim().parse().ok())
+            .ok_or_else(|| Error::Other(format!("Failed to parse {} from /proc/pid/io", name)))
+    };
+    Ok(IoCounters {
+        read_bytes: field("rchar")?,
+        write_bytes: field("wchar")?,
+        read_ops: Some(field("syscr")?),
+        write_ops: Some(field("syscw")?),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_io() {
+        let io = parse_io(
+            "rchar: 323934931\nwchar: 323929600\nsyscr: 632687\nsyscw: 632675\n\
+             read_bytes: 0\nwrite_bytes: 323932160\ncancelled_write_bytes: 0\n",
+        )
+        .unwrap();
+        assert_eq!(io.read_bytes, 323934931);
+        assert_eq!(io.write_bytes, 323929600);
+        assert_eq!(io.read_ops, Some(632687));
+        assert_eq!(io.write_ops, Some(632675));
+
+        assert!(parse_io("rchar: 1\n").is_err());
+    }
+}
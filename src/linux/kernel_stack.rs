@@ -0,0 +1,81 @@
+//! Captures the kernel side of a thread's stack from /proc/tid/stack. Reading this requires
+//! root (CAP_SYS_ADMIN), and shows where in the kernel a blocked thread is waiting - which
+//! is needed to make sense of off-CPU samples.
+use super::Thread;
+use crate::{Error, StackFrame};
+
+/// The module name given to kernel frames, matching what perf uses
+pub const KERNEL_MODULE: &str = "[kernel.kallsyms]";
+
+impl Thread {
+    /// Returns the kernel stack of this thread, innermost frame first. Since these frames are
+    /// all called from the thread's current user space frame, they go before the frames
+    /// returned by unwinding the user space stack.
+    ///
+    /// The kernel hides the addresses of these frames unless kptr_restrict allows it, in
+    /// which case they are reported as 0. The function names are always available.
+    pub fn kernel_stack(&self) -> Result<Vec<StackFrame>, Error> {
+        let tid = self.tid.as_raw();
+        let contents = std::fs::read_to_string(format!("/proc/{}/stack", tid))
+            .map_err(|e| Error::from_os_error(tid, e))?;
+        Ok(parse_kernel_stack(&contents))
+    }
+}
+
+/// Parses the contents of /proc/tid/stack, which has lines like
+/// `[<0>] do_nanosleep+0x6d/0x150`
+fn parse_kernel_stack(contents: &str) -> Vec<StackFrame> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim().strip_prefix("[<")?;
+            let (addr, symbol) = line.split_once(">]")?;
+            let addr = u64::from_str_radix(addr, 16).ok()?;
+            // symbols from loadable modules are followed by the module name in brackets
+            let mut parts = symbol.split_whitespace();
+            let function = parts.next()?.split('+').next()?;
+            let module = parts.next().unwrap_or(KERNEL_MODULE);
+            Some(StackFrame {
+                line: None,
+                column: None,
+                filename: None,
+                function: Some(function.to_owned()),
+                module: module.to_owned(),
+                addr,
+                module_offset: None,
+                inlined: false,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_kernel_stack() {
+        let frames = parse_kernel_stack(
+            "[<0>] do_nanosleep+0x6d/0x150\n\
+             [<ffffffff8a1b2c3d>] hrtimer_nanosleep+0xc0/0x1b0 [some_module]\n\
+             garbage\n",
+        );
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].function.as_deref(), Some("do_nanosleep"));
+        assert_eq!(frames[0].addr, 0);
+        assert_eq!(frames[1].function.as_deref(), Some("hrtimer_nanosleep"));
+        assert_eq!(frames[1].addr, 0xffffffff8a1b2c3d);
+        assert_eq!(frames[0].module, KERNEL_MODULE);
+        assert_eq!(frames[1].module, "[some_module]");
+    }
+
+    #[test]
+    fn test_kernel_stack() {
+        // only root can read kernel stacks
+        if unsafe { libc::geteuid() } != 0 {
+            return;
+        }
+        let thread = Thread::new(std::process::id() as i32).unwrap();
+        assert!(!thread.kernel_stack().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,87 @@
+use crate::{Error, Resource, ResourceLimit};
+
+/// The name of each resource in /proc/pid/limits
+const NAMES: [(&str, Resource); 16] = [
+    ("Max cpu time", Resource::CpuTime),
+    ("Max file size", Resource::FileSize),
+    ("Max data size", Resource::DataSize),
+    ("Max stack size", Resource::StackSize),
+    ("Max core file size", Resource::CoreFileSize),
+    ("Max resident set", Resource::ResidentSet),
+    ("Max processes", Resource::Processes),
+    ("Max open files", Resource::OpenFiles),
+    ("Max locked memory", Resource::LockedMemory),
+    ("Max address space", Resource::AddressSpace),
+    ("Max file locks", Resource::FileLocks),
+    ("Max pending signals", Resource::PendingSignals),
+    ("Max msgqueue size", Resource::MessageQueueSize),
+    ("Max nice priority", Resource::NicePriority),
+    ("Max realtime priority", Resource::RealtimePriority),
+    ("Max realtime timeout", Resource::RealtimeTimeout),
+];
+
+/// Parses /proc/pid/limits. The names of the limits contain spaces, so each line is matched
+/// against the known names rather than split into columns. Limits this doesn't know about
+/// are skipped.
+pub fn parse_limits(contents: &str) -> Result<Vec<ResourceLimit>, Error> {
+    let parse_value = |value: Option<&str>, line: &str| match value {
+        Some("unlimited") => Ok(None),
+        Some(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|_| Error::Other(format!("Failed to parse limit line '{}'", line))),
+        None => Err(Error::Other(format!(
+            "Failed to parse limit line '{}'",
+            line
+        ))),
+    };
+
+    let mut ret = Vec::new();
+    for line in contents.lines().skip(1) {
+        let (rest, resource) = match NAMES
+            .iter()
+            .find_map(|(name, resource)| Some((line.strip_prefix(name)?, *resource)))
+        {
+            Some(found) => found,
+            None => continue,
+        };
+        let mut fields = rest.split_whitespace();
+        ret.push(ResourceLimit {
+            resource,
+            soft: parse_value(fields.next(), line)?,
+            hard: parse_value(fields.next(), line)?,
+        });
+    }
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_limits() {
+        let limits = parse_limits(
+            "Limit                     Soft Limit           Hard Limit           Units     \n\
+             Max cpu time              unlimited            unlimited            seconds   \n\
+             Max stack size            8388608              unlimited            bytes     \n\
+             Max open files            1024                 1048576              files     \n\
+             Max nice priority         0                    0                    \n",
+        )
+        .unwrap();
+        assert_eq!(limits.len(), 4);
+        assert_eq!(limits[0].resource, Resource::CpuTime);
+        assert_eq!((limits[0].soft, limits[0].hard), (None, None));
+        assert_eq!(limits[1].resource, Resource::StackSize);
+        assert_eq!((limits[1].soft, limits[1].hard), (Some(8388608), None));
+        assert_eq!(limits[2].resource, Resource::OpenFiles);
+        assert_eq!(
+            (limits[2].soft, limits[2].hard),
+            (Some(1024), Some(1048576))
+        );
+        assert_eq!(limits[3].resource, Resource::NicePriority);
+        assert_eq!((limits[3].soft, limits[3].hard), (Some(0), Some(0)));
+
+        assert!(parse_limits("Limit\nMax open files  lots  1024  files\n").is_err());
+    }
+}
@@ -0,0 +1,45 @@
+//! Register access for loongarch64 threads
+use super::regset::get_regset;
+use super::Thread;
+use crate::Error;
+
+/// The general purpose registers of a loongarch64 thread, matching `struct user_pt_regs`
+/// from the kernel's asm/ptrace.h
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Registers {
+    /// r0-r31. r1 is the return address, r3 the stack pointer and r22 the frame pointer
+    pub regs: [u64; 32],
+    pub orig_a0: u64,
+    /// the exception return address, which holds the pc of a stopped thread
+    pub csr_era: u64,
+    pub csr_badv: u64,
+    pub reserved: [u64; 10],
+}
+
+impl Registers {
+    pub fn ip(&self) -> u64 {
+        self.csr_era
+    }
+
+    pub fn sp(&self) -> u64 {
+        self.regs[3]
+    }
+
+    pub fn fp(&self) -> u64 {
+        self.regs[22]
+    }
+
+    pub fn ra(&self) -> u64 {
+        self.regs[1]
+    }
+}
+
+impl Thread {
+    /// Returns the general purpose registers of this thread. The thread needs to be locked
+    /// for this to succeed.
+    pub fn registers(&self) -> Result<Registers, Error> {
+        get_regset(self.tid, libc::NT_PRSTATUS)
+    }
+}
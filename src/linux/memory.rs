@@ -0,0 +1,203 @@
+use std::fs::File;
+use std::os::unix::fs::FileExt;
+
+use log::debug;
+use nix::sys::ptrace;
+
+use super::Pid;
+use crate::Error;
+
+/// Selects how memory is read from the target process
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum MemoryBackend {
+    /// Uses process_vm_readv, falling back to /proc/pid/mem and ptrace if that isn't permitted.
+    /// This is what `Process::new` uses: nothing is probed up front, and each read that finds
+    /// process_vm_readv blocked retries with the other methods. Use `detect_best` to pick a
+    /// single backend ahead of time instead.
+    #[default]
+    Auto,
+    /// Uses process_vm_readv only
+    ProcessVmReadv,
+    /// Uses pread on /proc/pid/mem only
+    ProcMem,
+    /// Uses PTRACE_PEEKDATA only. This requires the process to be locked while reading
+    Ptrace,
+}
+
+impl MemoryBackend {
+    /// Returns the fastest backend that can currently read memory from the process, by
+    /// probing a readable mapping with each backend in turn. Returns `Auto` if none of them
+    /// succeed (for instance if ptrace is the only option, but the process isn't locked).
+    pub fn detect_best(pid: Pid) -> MemoryBackend {
+        let maps = match proc_maps::get_process_maps(pid) {
+            Ok(maps) => maps,
+            Err(_) => return MemoryBackend::Auto,
+        };
+        let addr = match maps.iter().find(|m| m.is_read() && m.size() > 0) {
+            Some(m) => m.start(),
+            None => return MemoryBackend::Auto,
+        };
+
+        let mut buf = [0_u8; 1];
+        for backend in [
+            MemoryBackend::ProcessVmReadv,
+            MemoryBackend::ProcMem,
+            MemoryBackend::Ptrace,
+        ] {
+            if backend.read(pid, addr, &mut buf).is_ok() {
+                debug!("using {:?} to read memory from {}", backend, pid);
+                return backend;
+            }
+        }
+        MemoryBackend::Auto
+    }
+
+    pub fn read(&self, pid: Pid, addr: usize, buf: &mut [u8]) -> Result<(), Error> {
+        match self {
+            MemoryBackend::Auto => read(pid, addr, buf),
+            MemoryBackend::ProcessVmReadv => read_vm(pid, addr, buf),
+            MemoryBackend::ProcMem => read_proc_mem(pid, addr, buf),
+            MemoryBackend::Ptrace => read_ptrace(pid, addr, buf),
+        }
+    }
+}
+
+/// Reads memory from the process, falling back to /proc/pid/mem and then ptrace PEEKDATA
+/// when process_vm_readv isn't permitted. Containers frequently block process_vm_readv with
+/// a seccomp filter, even when the other methods of reading memory are allowed.
+pub fn read(pid: Pid, addr: usize, buf: &mut [u8]) -> Result<(), Error> {
+    let err = match read_vm(pid, addr, buf) {
+        Err(Error::IOError(e)) if is_blocked(&e) => e,
+        ret => return ret,
+    };
+    debug!(
+        "process_vm_readv failed for {} ({}), falling back to /proc/{}/mem",
+        pid, err, pid
+    );
+
+    match read_proc_mem(pid, addr, buf) {
+        Err(Error::IOError(ref e)) if is_blocked(e) => {}
+        ret => return ret,
+    }
+    debug!("/proc/{}/mem failed, falling back to PTRACE_PEEKDATA", pid);
+
+    // PEEKDATA only works if we are tracing the process (ie it's locked), return the
+    // original error otherwise since it will be the most informative
+    read_ptrace(pid, addr, buf)
+        .map_err(|_| super::permissions::permission_error(pid, Error::IOError(err)))
+}
+
+/// Reads memory with process_vm_readv
+pub fn read_vm(pid: Pid, addr: usize, buf: &mut [u8]) -> Result<(), Error> {
+    let mut offset = 0;
+    while offset < buf.len() {
+        let local = libc::iovec {
+            iov_base: buf[offset..].as_mut_ptr() as *mut libc::c_void,
+            iov_len: buf.len() - offset,
+        };
+        let remote = libc::iovec {
+            iov_base: (addr + offset) as *mut libc::c_void,
+            iov_len: buf.len() - offset,
+        };
+        let ret = unsafe { libc::process_vm_readv(pid, &local, 1, &remote, 1, 0) };
+        if ret < 0 {
+            return Err(Error::IOError(std::io::Error::last_os_error()));
+        }
+        // reads stop short at an unreadable page, or after MAX_RW_COUNT bytes. Reading again
+        // from where it stopped either continues or fails with the reason it stopped.
+        if ret == 0 {
+            return Err(Error::IOError(std::io::Error::from_raw_os_error(
+                libc::EFAULT,
+            )));
+        }
+        offset += ret as usize;
+    }
+    Ok(())
+}
+
+/// Reads memory with pread on /proc/pid/mem
+pub fn read_proc_mem(pid: Pid, addr: usize, buf: &mut [u8]) -> Result<(), Error> {
+    let file = File::open(format!("/proc/{}/mem", pid))?;
+    file.read_exact_at(buf, addr as u64)?;
+    Ok(())
+}
+
+/// Reads memory a word at a time with PTRACE_PEEKDATA. This requires that the process is
+/// currently being traced by us and is stopped - for instance while holding a Lock on it.
+pub fn read_ptrace(pid: Pid, addr: usize, buf: &mut [u8]) -> Result<(), Error> {
+    let tid = nix::unistd::Pid::from_raw(pid);
+    let word_size = std::mem::size_of::<libc::c_long>();
+    let end = addr + buf.len();
+
+    // PEEKDATA reads have to be word aligned
+    let mut current = addr - addr % word_size;
+    while current < end {
+        let word = ptrace::read(tid, current as ptrace::AddressType)?.to_ne_bytes();
+        let start = std::cmp::max(current, addr);
+        let stop = std::cmp::min(current + word_size, end);
+        buf[start - addr..stop - addr].copy_from_slice(&word[start - current..stop - current]);
+        current += word_size;
+    }
+    Ok(())
+}
+
+fn is_blocked(err: &std::io::Error) -> bool {
+    matches!(
+        err.raw_os_error(),
+        Some(libc::EPERM) | Some(libc::EACCES) | Some(libc::ENOSYS)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_best() {
+        let backend = MemoryBackend::detect_best(std::process::id() as Pid);
+        assert_ne!(backend, MemoryBackend::Ptrace);
+    }
+
+    #[test]
+    fn test_process_default_backend() {
+        // opening a process doesn't probe the backends, that only happens in detect_best
+        let process = crate::Process::new(std::process::id() as Pid).unwrap();
+        assert_eq!(process.memory_backend(), MemoryBackend::Auto);
+    }
+
+    #[test]
+    fn test_read_proc_mem() {
+        let data: [u8; 13] = *b"hello, world!";
+        let mut buf = [0_u8; 13];
+        read_proc_mem(std::process::id() as Pid, data.as_ptr() as usize, &mut buf).unwrap();
+        assert_eq!(buf, data);
+    }
+
+    #[test]
+    fn test_read_partial() {
+        use crate::{Process, ProcessMemory};
+
+        // two pages, with the second made unreadable like a guard page
+        let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let addr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                2 * page,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        assert_ne!(addr, libc::MAP_FAILED);
+        unsafe { libc::mprotect(addr.add(page), page, libc::PROT_NONE) };
+
+        let process = Process::new(std::process::id() as Pid).unwrap();
+        let mut buf = vec![0; 256];
+        let start = addr as usize + page - 100;
+        assert_eq!(process.read_partial(start, &mut buf).unwrap(), 100);
+        assert!(process.read_partial(start + 100, &mut buf).is_err());
+
+        unsafe { libc::munmap(addr, 2 * page) };
+    }
+}
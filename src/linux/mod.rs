@@ -1,7 +1,76 @@
-#[cfg(use_libunwind)]
-pub mod libunwind;
-#[cfg(use_libunwind)]
-mod symbolication;
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+mod alloc;
+#[cfg(target_os = "android")]
+pub mod android;
+#[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
+mod arm_exidx;
+#[cfg(all(feature = "bpf", any(target_arch = "x86_64", target_arch = "aarch64")))]
+mod bpf;
+mod cgroup;
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+mod compat;
+mod connections;
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+mod debug_session;
+#[cfg(any(
+    target_arch = "x86_64",
+    target_arch = "aarch64",
+    target_arch = "riscv64"
+))]
+mod dwarf;
+mod frame_registers;
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+mod inject;
+mod io;
+mod kernel_stack;
+mod limits;
+#[cfg(target_arch = "loongarch64")]
+mod loongarch64;
+mod memory;
+mod normalize;
+#[cfg(all(feature = "perf", any(target_arch = "x86_64", target_arch = "aarch64")))]
+mod perf;
+mod permissions;
+mod regions;
+#[cfg(any(
+    target_arch = "x86_64",
+    target_arch = "aarch64",
+    target_arch = "arm",
+    target_arch = "riscv64",
+    target_arch = "loongarch64"
+))]
+mod regset;
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+mod remote_call;
+#[cfg(target_arch = "riscv64")]
+mod riscv64;
+#[cfg(any(
+    target_arch = "x86_64",
+    target_arch = "aarch64",
+    target_arch = "riscv64"
+))]
+mod signal_frame;
+mod signals;
+#[cfg(any(
+    target_arch = "x86_64",
+    target_arch = "aarch64",
+    target_arch = "riscv64",
+    target_arch = "loongarch64"
+))]
+mod stack_copy;
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+mod step;
+mod syscall_tracer;
+mod threads;
+mod tls;
+#[cfg(any(
+    target_arch = "x86_64",
+    target_arch = "aarch64",
+    target_arch = "riscv64"
+))]
+mod unwind_cache;
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+mod watchpoint;
 
 use lazy_static::lazy_static;
 use libc::pid_t;
@@ -14,26 +83,84 @@ use nix::{
     sys::wait,
 };
 use std::collections::HashMap;
-use std::convert::TryInto;
 use std::fs::File;
 use std::io::Read;
 use std::os::fd::AsFd;
+use std::path::{Path, PathBuf};
 
-use super::Error;
-
-#[cfg(use_libunwind)]
-pub use self::symbolication::*;
-
+use super::{
+    Error, FramePointerCursor, IoCounters, OpenFile, ReadOptions, ResourceLimit, TargetArch,
+    UnwindMode,
+};
 #[cfg(use_libunwind)]
-pub use self::libunwind::Unwinder;
-
-use read_process_memory::{CopyAddress, ProcessHandle};
+use super::{Symbolicator, Unwinder};
+
+#[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
+pub use self::arm_exidx::{ExidxCursor, ExidxTable};
+#[cfg(all(feature = "bpf", any(target_arch = "x86_64", target_arch = "aarch64")))]
+pub use self::bpf::{BpfStack, BpfStackSampler};
+pub use self::cgroup::CGroup;
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+pub use self::compat::{CompatCursor, CompatRegisters};
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+pub use self::debug_session::{DebugEvent, DebugSession};
+#[cfg(any(
+    target_arch = "x86_64",
+    target_arch = "aarch64",
+    target_arch = "riscv64"
+))]
+pub use self::dwarf::{DwarfCursor, DwarfUnwinder, RegisterSource};
+pub use self::kernel_stack::KERNEL_MODULE;
+#[cfg(target_arch = "loongarch64")]
+pub use self::loongarch64::Registers;
+pub use self::memory::MemoryBackend;
+#[cfg(all(feature = "perf", any(target_arch = "x86_64", target_arch = "aarch64")))]
+pub use self::perf::{PerfClock, PerfSample, PerfSampler, PerfSamplerBuilder};
+pub use self::permissions::PtraceRestriction;
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+pub use self::regset::{FpRegisters, Registers};
+#[cfg(target_arch = "riscv64")]
+pub use self::riscv64::Registers;
+pub use self::signals::{SignalSet, SignalState};
+pub use self::syscall_tracer::SyscallTracer;
+pub use self::threads::{SchedStats, ThreadIter};
+#[cfg(any(
+    target_arch = "x86_64",
+    target_arch = "aarch64",
+    target_arch = "riscv64"
+))]
+pub use self::unwind_cache::{
+    clear_unwind_cache, set_unwind_cache_capacity, set_unwind_cache_directory,
+};
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+pub use self::watchpoint::{WatchKind, Watchpoint, WatchpointHit};
 
 pub type Pid = pid_t;
 pub type Tid = pid_t;
 
+/// Persists parsed symbol tables and unwind info in a directory, keyed by build-id, so that
+/// later runs can load them instead of parsing every mapped binary again. Passing None
+/// disables both on-disk caches.
+#[cfg(all(
+    use_libunwind,
+    any(
+        target_arch = "x86_64",
+        target_arch = "aarch64",
+        target_arch = "riscv64"
+    )
+))]
+pub fn set_cache_directory(directory: Option<PathBuf>) -> std::io::Result<()> {
+    crate::set_symbol_cache_directory(directory.clone())?;
+    set_unwind_cache_directory(directory)
+}
+
 pub struct Process {
     pub pid: Pid,
+    memory_backend: MemoryBackend,
+    read_options: ReadOptions,
+    unwind_mode: UnwindMode,
+    /// the length of each block of memory mapped by `alloc`, since munmap needs it
+    allocations: std::sync::Mutex<HashMap<u64, usize>>,
 }
 
 #[derive(Eq, PartialEq, Hash, Copy, Clone)]
@@ -43,21 +170,202 @@ pub struct Thread {
 
 impl Process {
     pub fn new(pid: Pid) -> Result<Process, Error> {
-        Ok(Process { pid })
+        permissions::check_visible(pid)?;
+        Ok(Process {
+            pid,
+            memory_backend: MemoryBackend::default(),
+            read_options: ReadOptions::default(),
+            unwind_mode: UnwindMode::default(),
+            allocations: std::sync::Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Returns a new handle to the same process that can be kept independently of this one.
+    /// Linux processes are addressed by pid rather than by an open handle, so this only checks
+    /// the process is still visible. Memory allocated with `alloc` has to be freed through the
+    /// handle that allocated it.
+    pub fn try_clone(&self) -> Result<Process, Error> {
+        permissions::check_visible(self.pid)?;
+        Ok(Process {
+            pid: self.pid,
+            memory_backend: self.memory_backend,
+            read_options: self.read_options,
+            unwind_mode: self.unwind_mode,
+            allocations: std::sync::Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Returns the method used to read memory from this process
+    pub fn memory_backend(&self) -> MemoryBackend {
+        self.memory_backend
+    }
+
+    /// Sets the method used to read memory from this process. `MemoryBackend::detect_best`
+    /// can be used to pick the fastest method that works in the current environment.
+    pub fn set_memory_backend(&mut self, backend: MemoryBackend) {
+        self.memory_backend = backend;
+    }
+
+    /// Returns how reads of memory from this process are split up and retried
+    pub fn read_options(&self) -> ReadOptions {
+        self.read_options
+    }
+
+    /// Sets how reads of memory from this process are split up and retried
+    pub fn set_read_options(&mut self, options: ReadOptions) {
+        self.read_options = options;
+    }
+
+    /// Returns how stacks in this process are unwound
+    pub fn unwind_mode(&self) -> UnwindMode {
+        self.unwind_mode
+    }
+
+    /// Sets how stacks in this process are unwound. `UnwindMode::FramePointer` is much faster
+    /// than the default, but only works when everything was compiled with frame pointers.
+    pub fn set_unwind_mode(&mut self, mode: UnwindMode) {
+        self.unwind_mode = mode;
+    }
+
+    /// Returns a cursor that unwinds a locked thread by following frame pointers. This doesn't
+    /// need the `unwind` feature.
+    pub fn frame_pointer_cursor(
+        &self,
+        thread: &Thread,
+    ) -> Result<FramePointerCursor<&Self>, Error> {
+        let (ip, fp) = thread.frame_registers()?;
+        Ok(FramePointerCursor::new(self, ip, fp))
     }
 
     pub fn exe(&self) -> Result<String, Error> {
-        let path = std::fs::read_link(format!("/proc/{}/exe", self.pid))?;
+        let path = std::fs::read_link(format!("/proc/{}/exe", self.pid))
+            .map_err(|e| Error::from_os_error(self.pid, e))?;
         Ok(path.to_string_lossy().to_string())
     }
 
     pub fn cwd(&self) -> Result<String, Error> {
-        let path = std::fs::read_link(format!("/proc/{}/cwd", self.pid))?;
+        let path = std::fs::read_link(format!("/proc/{}/cwd", self.pid))
+            .map_err(|e| Error::from_os_error(self.pid, e))?;
         Ok(path.to_string_lossy().to_string())
     }
 
+    /// True if the process is being traced with ptrace, by a debugger or by this process
+    /// holding a lock on it
+    pub fn is_being_debugged(&self) -> Result<bool, Error> {
+        let status = std::fs::read_to_string(format!("/proc/{}/status", self.pid))
+            .map_err(|e| Error::from_os_error(self.pid, e))?;
+        let tracer = status
+            .lines()
+            .find_map(|line| line.strip_prefix("TracerPid:"))
+            .and_then(|tracer| tracer.trim().parse::<Pid>().ok())
+            .ok_or_else(|| Error::Other(format!("no TracerPid for process {}", self.pid)))?;
+        Ok(tracer != 0)
+    }
+
+    /// Returns the file descriptors the process has open, ordered by descriptor
+    pub fn open_files(&self) -> Result<Vec<OpenFile>, Error> {
+        let entries = std::fs::read_dir(format!("/proc/{}/fd", self.pid))
+            .map_err(|e| Error::from_os_error(self.pid, e))?;
+        let mut files = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            let fd = match entry.file_name().to_str().and_then(|fd| fd.parse().ok()) {
+                Some(fd) => fd,
+                None => continue,
+            };
+            // the descriptor could have been closed since listing the directory
+            let path = match std::fs::read_link(entry.path()) {
+                Ok(path) => path,
+                Err(_) => continue,
+            };
+            files.push(OpenFile {
+                fd,
+                path: path.to_string_lossy().into_owned(),
+            });
+        }
+        files.sort_by_key(|file| file.fd);
+        Ok(files)
+    }
+
+    /// Returns a path that can be used to open a file mapped into this process at
+    /// `start..end`. If the process is running inside a container its files won't exist
+    /// at the same path on the host, so this resolves through /proc/pid/root first and then
+    /// through /proc/pid/map_files (which also works for files deleted since being mapped).
+    pub fn resolve_path(&self, filename: &Path, start: usize, end: usize) -> Option<PathBuf> {
+        resolve_path(self.pid, filename, start, end)
+    }
+
+    /// Returns the architecture, pointer size and endianness of the process, from the ELF
+    /// header of its executable
+    pub fn arch(&self) -> Result<TargetArch, Error> {
+        crate::read_elf_arch(self.pid, &format!("/proc/{}/exe", self.pid))
+    }
+
+    /// True if pointers in the process are 64 bits wide
+    pub fn is_64_bit(&self) -> Result<bool, Error> {
+        Ok(self.arch()?.is_64_bit())
+    }
+
+    /// True if this is a 32-bit process running on a 64-bit host. The registers of these
+    /// processes can be read with `Thread::compat_registers`, and their stacks unwound with
+    /// a `CompatCursor`
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    pub fn is_compat(&self) -> Result<bool, Error> {
+        compat::is_compat(self.pid)
+    }
+
+    /// Returns the pid/mnt/net/user namespaces this process is running in, and whether
+    /// each differs from the namespace of the current process
+    pub fn namespaces(&self) -> Result<Namespaces, Error> {
+        Ok(Namespaces {
+            pid: get_namespace(self.pid, "pid")?,
+            mnt: get_namespace(self.pid, "mnt")?,
+            net: get_namespace(self.pid, "net")?,
+            user: get_namespace(self.pid, "user")?,
+        })
+    }
+
+    /// Returns the cgroups this process belongs to
+    pub fn cgroup(&self) -> Result<Vec<CGroup>, Error> {
+        let contents = std::fs::read_to_string(format!("/proc/{}/cgroup", self.pid))
+            .map_err(|e| Error::from_os_error(self.pid, e))?;
+        cgroup::parse_cgroups(&contents)
+    }
+
+    /// Returns the bytes and syscalls the process has read and written, from /proc/pid/io.
+    /// Reading this needs the same permissions as attaching to the process with ptrace.
+    pub fn io_counters(&self) -> Result<IoCounters, Error> {
+        let contents = std::fs::read_to_string(format!("/proc/{}/io", self.pid))
+            .map_err(|e| Error::from_os_error(self.pid, e))?;
+        io::parse_io(&contents)
+    }
+
+    /// Returns the resource limits of the process, from /proc/pid/limits. The number of files
+    /// the process currently has open, to compare against `Resource::OpenFiles`, can be found
+    /// with `open_files`.
+    pub fn rlimits(&self) -> Result<Vec<ResourceLimit>, Error> {
+        let contents = std::fs::read_to_string(format!("/proc/{}/limits", self.pid))
+            .map_err(|e| Error::from_os_error(self.pid, e))?;
+        limits::parse_limits(&contents)
+    }
+
+    /// Returns the signals that are pending, blocked, ignored and caught by the process, from
+    /// /proc/pid/status. The pending and blocked signals are those of the main thread, use
+    /// `Thread::signals` to get them for other threads.
+    pub fn signals(&self) -> Result<SignalState, Error> {
+        let status = std::fs::read_to_string(format!("/proc/{}/status", self.pid))
+            .map_err(|e| Error::from_os_error(self.pid, e))?;
+        signals::parse_signal_state(&status)
+    }
+
+    /// Returns the id of the container this process is running in, as parsed from its cgroups
+    pub fn container_id(&self) -> Result<Option<String>, Error> {
+        Ok(cgroup::container_id(&self.cgroup()?))
+    }
+
     pub fn cmdline(&self) -> Result<Vec<String>, Error> {
-        let mut f = std::fs::File::open(format!("/proc/{}/cmdline", self.pid))?;
+        let mut f = std::fs::File::open(format!("/proc/{}/cmdline", self.pid))
+            .map_err(|e| Error::from_os_error(self.pid, e))?;
         let mut buffer = Vec::new();
         f.read_to_end(&mut buffer)?;
 
@@ -72,59 +380,85 @@ impl Process {
     }
 
     pub fn lock(&self) -> Result<Lock, Error> {
+        Ok(self.lock_threads()?.0)
+    }
+
+    /// Locks the process, and returns a lock containing a consistent snapshot of the threads
+    /// in the process. After suspending, the thread list is re-enumerated and any threads that
+    /// were spawned in the meantime are also suspended - repeating until the set of threads is
+    /// stable. Threads that exited while locking are dropped from the snapshot.
+    pub fn lock_and_snapshot(&self) -> Result<Lock, Error> {
+        let (mut lock, current) = self.lock_threads()?;
+        lock.threads.retain(|thread| current.contains(thread));
+        Ok(lock)
+    }
+
+    /// Locks every thread of the process, returning the lock along with the last listing of
+    /// the threads - which didn't have any threads that hadn't already been locked
+    fn lock_threads(&self) -> Result<(Lock, Vec<Thread>), Error> {
         let mut locks = Vec::new();
-        let mut locked = std::collections::HashSet::new();
-        let mut done = false;
+        // threads that have been tried, including those that exited before they could be
+        // locked, so that a zombie still listed in /proc/pid/task is only tried once
+        let mut attempted = std::collections::HashSet::new();
         let mut all_locks_failed = true;
 
         // we need to lock each individual thread of the process, but
         // while we're doing this new threads could be created. keep
-        // on creating new locks for each thread until no new locks are
-        // created
-        while !done {
-            done = true;
-            for thread in self.threads()? {
+        // on creating new locks for each thread until no new threads
+        // are found
+        loop {
+            let current = self.threads()?;
+            let mut done = true;
+            for thread in current.iter() {
                 let threadid = thread.id()?;
-                if !locked.contains(&threadid) {
-                    match thread.lock() {
-                        Ok(lock) => {
-                            locks.push(lock);
-                            locked.insert(threadid);
-                            done = false;
-                            all_locks_failed = false;
-                        }
-                        Err(Error::NixError(nix::errno::Errno::ESRCH)) => {
-                            // the thread probably exited before we could get a lock
-                            continue;
-                        }
-                        Err(e @ Error::NixError(nix::errno::Errno::EPERM)) => {
-                            if !thread.exists() {
-                                // The thread was probably in the "exiting" state, which returns
-                                // EPERM to the caller. This thread is dead, we can not ptrace
-                                // it and we should just ignore it.
-                                // See https://elixir.bootlin.com/linux/v6.15.3/source/kernel/ptrace.c#L458
-                                continue;
-                            }
-                            // We likely really have no permission, propagate the error
-                            return Err(e);
-                        }
-                        Err(e) => return Err(e),
+                if attempted.insert(threadid) {
+                    done = false;
+                    if let Some(lock) = lock_thread(thread)? {
+                        locks.push(lock);
+                        all_locks_failed = false;
                     }
                 }
             }
+            if done {
+                if all_locks_failed {
+                    return Err(Error::Other(format!("All threads failed to lock")));
+                }
+                let threads = locks.iter().map(|lock| Thread { tid: lock.tid }).collect();
+                return Ok((Lock { locks, threads }, current));
+            }
         }
+    }
 
-        if all_locks_failed {
-            return Err(Error::Other(format!("All threads failed to lock")));
+    /// True if the process has exited but hasn't been reaped by its parent yet, going by the
+    /// state in /proc/pid/stat. Zombies still have their /proc entries (and a thread), so
+    /// this is the only way to tell them apart from a running process.
+    pub(crate) fn is_zombie(&self) -> Result<bool, Error> {
+        let stat = std::fs::read(format!("/proc/{}/stat", self.pid))
+            .map_err(|e| Error::from_os_error(self.pid, e))?;
+        match get_active_status(&stat) {
+            Some(state) => Ok(state == b'Z' || state == b'X'),
+            None => Err(Error::Other(format!(
+                "Failed to parse /proc/{}/stat",
+                self.pid
+            ))),
         }
-
-        Ok(Lock { locks })
     }
 
     pub fn threads(&self) -> Result<Vec<Thread>, Error> {
+        Ok(self
+            .thread_ids()?
+            .into_iter()
+            .map(|tid| Thread {
+                tid: nix::unistd::Pid::from_raw(tid),
+            })
+            .collect())
+    }
+
+    /// Returns the ids of the threads in the process from /proc/pid/task
+    pub fn thread_ids(&self) -> Result<Vec<Tid>, Error> {
         let mut ret = Vec::new();
         let path = format!("/proc/{}/task", self.pid);
-        let tasks = std::fs::read_dir(path)?;
+        let tasks = std::fs::read_dir(path).map_err(|e| Error::from_os_error(self.pid, e))?;
         for entry in tasks {
             let entry = entry?;
             let filename = entry.file_name();
@@ -133,10 +467,8 @@ impl Process {
                 None => continue,
             };
 
-            if let Ok(threadid) = thread.parse::<i32>() {
-                ret.push(Thread {
-                    tid: nix::unistd::Pid::from_raw(threadid),
-                });
+            if let Ok(tid) = thread.parse::<Tid>() {
+                ret.push(tid);
             }
         }
         Ok(ret)
@@ -149,7 +481,18 @@ impl Process {
 
     #[cfg(use_libunwind)]
     pub fn unwinder(&self) -> Result<Unwinder, Error> {
-        Ok(Unwinder::new()?)
+        Unwinder::with_mode(self, self.unwind_mode)
+    }
+
+    /// Returns an unwinder that uses the .eh_frame sections of the loaded binaries directly,
+    /// without needing libunwind
+    #[cfg(any(
+        target_arch = "x86_64",
+        target_arch = "aarch64",
+        target_arch = "riscv64"
+    ))]
+    pub fn dwarf_unwinder(&self) -> Result<DwarfUnwinder, Error> {
+        DwarfUnwinder::new(self.pid)
     }
 
     #[cfg(use_libunwind)]
@@ -160,8 +503,11 @@ impl Process {
 
 impl super::ProcessMemory for Process {
     fn read(&self, addr: usize, buf: &mut [u8]) -> Result<(), Error> {
-        let handle: ProcessHandle = self.pid.try_into()?;
-        Ok(handle.copy_address(addr, buf)?)
+        self.read_options
+            .read_chunked(addr, buf, |addr, buf| {
+                self.memory_backend.read(self.pid, addr, buf)
+            })
+            .map_err(|e| Error::from_read_error(self.pid, addr, buf.len(), e))
     }
 }
 
@@ -172,6 +518,11 @@ impl Thread {
         })
     }
 
+    /// Returns a new handle to the same thread
+    pub fn try_clone(&self) -> Result<Thread, Error> {
+        Ok(*self)
+    }
+
     pub fn lock(&self) -> Result<ThreadLock, Error> {
         Ok(ThreadLock::new(self.tid)?)
     }
@@ -197,6 +548,64 @@ impl Thread {
             ))),
         }
     }
+
+    /// Returns the signals that are pending for and blocked by this thread, along with the
+    /// signals ignored and caught by its process
+    pub fn signals(&self) -> Result<SignalState, Error> {
+        let status = std::fs::read_to_string(format!("/proc/{}/status", self.tid))
+            .map_err(|e| Error::from_os_error(self.tid.as_raw(), e))?;
+        signals::parse_signal_state(&status)
+    }
+}
+
+/// Resolves the path to a file mapped into a process, see `Process::resolve_path`
+fn resolve_path(pid: Pid, filename: &Path, start: usize, end: usize) -> Option<PathBuf> {
+    if filename.is_absolute() {
+        let root = PathBuf::from(format!("/proc/{}/root", pid));
+        let path = root.join(filename.strip_prefix("/").unwrap_or(filename));
+        if path.exists() {
+            return Some(path);
+        }
+    }
+
+    let path = PathBuf::from(format!("/proc/{}/map_files/{:x}-{:x}", pid, start, end));
+    if path.exists() {
+        return Some(path);
+    }
+
+    if filename.exists() {
+        return Some(filename.to_path_buf());
+    }
+
+    // android libraries are often referred to by name only, and live in APEX modules
+    #[cfg(target_os = "android")]
+    if !filename.is_absolute() {
+        return android::find_library(filename);
+    }
+    None
+}
+
+/// Locks a single thread, returning None if the thread exited before we could lock it
+fn lock_thread(thread: &Thread) -> Result<Option<ThreadLock>, Error> {
+    match thread.lock() {
+        Ok(lock) => Ok(Some(lock)),
+        Err(Error::NixError(nix::errno::Errno::ESRCH)) => {
+            // the thread probably exited before we could get a lock
+            Ok(None)
+        }
+        Err(e @ Error::NixError(nix::errno::Errno::EPERM)) => {
+            if !thread.exists() {
+                // The thread was probably in the "exiting" state, which returns
+                // EPERM to the caller. This thread is dead, we can not ptrace
+                // it and we should just ignore it.
+                // See https://elixir.bootlin.com/linux/v6.15.3/source/kernel/ptrace.c#L458
+                return Ok(None);
+            }
+            // We likely really have no permission, propagate the error
+            Err(permissions::permission_error(thread.tid.as_raw(), e))
+        }
+        Err(e) => Err(e),
+    }
 }
 
 fn get_process_tree() -> Result<HashMap<Pid, Pid>, Error> {
@@ -223,6 +632,22 @@ fn get_process_tree() -> Result<HashMap<Pid, Pid>, Error> {
 pub struct Lock {
     #[allow(dead_code)]
     locks: Vec<ThreadLock>,
+    threads: Vec<Thread>,
+}
+
+impl Lock {
+    /// The threads that were suspended by this lock
+    pub fn threads(&self) -> &[Thread] {
+        &self.threads
+    }
+
+    /// The thread ids of the threads that were suspended by this lock
+    pub fn tids(&self) -> Vec<Tid> {
+        self.threads
+            .iter()
+            .map(|thread| thread.tid.as_raw())
+            .collect()
+    }
 }
 
 pub struct ThreadLock {
@@ -297,6 +722,53 @@ impl Drop for ThreadLock {
     }
 }
 
+/// The namespaces a process is running in. Each entry is None if the kernel doesn't
+/// support that namespace type
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Namespaces {
+    pub pid: Option<NamespaceInfo>,
+    pub mnt: Option<NamespaceInfo>,
+    pub net: Option<NamespaceInfo>,
+    pub user: Option<NamespaceInfo>,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NamespaceInfo {
+    /// The inode number identifying the namespace
+    pub inode: u64,
+    /// True if the namespace is different from the one the current process is in
+    pub differs: bool,
+}
+
+fn get_namespace(pid: Pid, name: &str) -> Result<Option<NamespaceInfo>, Error> {
+    let read_inode = |pid: &str| -> Result<Option<u64>, Error> {
+        let link = match std::fs::read_link(format!("/proc/{}/ns/{}", pid, name)) {
+            Ok(link) => link,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        parse_namespace_link(&link.to_string_lossy())
+            .map(Some)
+            .ok_or_else(|| Error::Other(format!("Failed to parse namespace link {:?}", link)))
+    };
+
+    let inode = match read_inode(&pid.to_string())? {
+        Some(inode) => inode,
+        None => return Ok(None),
+    };
+    let differs = read_inode("self")? != Some(inode);
+    Ok(Some(NamespaceInfo { inode, differs }))
+}
+
+fn parse_namespace_link(link: &str) -> Option<u64> {
+    // links look like 'mnt:[4026531840]'
+    let start = link.find(":[")? + 2;
+    let end = link.rfind(']')?;
+    link.get(start..end)?.parse().ok()
+}
+
 pub struct Namespace {
     ns_file: Option<File>,
 }
@@ -406,3 +878,12 @@ fn test_parse_ppid_stat() {
     // Invalid UTF-8 and whitespace:
     assert_eq!(get_ppid_status(b"83 (\xc3\x28)) S ) R 1 19"), Some(1));
 }
+
+#[test]
+fn test_parse_namespace_link() {
+    assert_eq!(parse_namespace_link("mnt:[4026531840]"), Some(4026531840));
+    assert_eq!(parse_namespace_link("user:[4026531837]"), Some(4026531837));
+    assert_eq!(parse_namespace_link("mnt:4026531840"), None);
+    assert_eq!(parse_namespace_link("mnt:[]"), None);
+    assert_eq!(parse_namespace_link(""), None);
+}
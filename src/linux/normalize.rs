@@ -0,0 +1,238 @@
+//! Maps addresses in a process to the binary containing them and their offset in it, without
+//! loading any symbols or debug info - so that stacks can be collected cheaply, and then
+//! symbolicated offline somewhere else.
+//!
+//! Build-ids are read from the headers mapped into the process where possible, so that they
+//! identify the binary that was actually loaded even if the file has since been replaced.
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::Path;
+
+use goblin::container::Ctx;
+use goblin::elf::program_header::{PT_LOAD, PT_NOTE};
+use goblin::elf::{Elf, ProgramHeader};
+use memmap2::Mmap;
+use object::{Object, ObjectSegment};
+use proc_maps::MapRange;
+
+use super::Process;
+use crate::{Error, Module, ModuleId, NormalizedAddress, ProcessMemory};
+
+// the type of the note holding the build-id, which is named "GNU"
+const NT_GNU_BUILD_ID: u32 = 3;
+
+impl Process {
+    /// Returns the binary that contains an address and the address relative to it, which is
+    /// what the binary's own symbols and debug info use. Only the headers of the binary are
+    /// read. This is None for addresses that aren't in a mapped file, like JIT compiled code.
+    pub fn normalize_address(&self, addr: u64) -> Result<Option<NormalizedAddress>, Error> {
+        Ok(self.normalize_addresses(&[addr])?.pop().flatten())
+    }
+
+    /// Normalizes many addresses at once, reading the mappings of the process and the
+    /// headers of each binary only once
+    pub fn normalize_addresses(
+        &self,
+        addrs: &[u64],
+    ) -> Result<Vec<Option<NormalizedAddress>>, Error> {
+        let maps =
+            proc_maps::get_process_maps(self.pid).map_err(|e| Error::from_os_error(self.pid, e))?;
+        let mut modules: HashMap<&Path, Option<Module>> = HashMap::new();
+        Ok(addrs
+            .iter()
+            .map(|&addr| {
+                let map = maps.iter().find(|map| {
+                    map.start() as u64 <= addr && addr < (map.start() + map.size()) as u64
+                })?;
+                let filename = map.filename()?;
+                let module = modules
+                    .entry(filename)
+                    .or_insert_with(|| self.mapped_module(&maps, map, filename))
+                    .as_ref()?;
+                Some(NormalizedAddress {
+                    module: module.clone(),
+                    offset: addr.wrapping_sub(module.bias),
+                })
+            })
+            .collect())
+    }
+
+    /// Reads the load bias and build-id of a mapped binary. The address and size of the
+    /// module are those of its executable mapping, as in `Symbolicator::modules`.
+    fn mapped_module(&self, maps: &[MapRange], map: &MapRange, filename: &Path) -> Option<Module> {
+        let path = self.resolve_path(filename, map.start(), map.start() + map.size())?;
+        let file = File::open(path).ok()?;
+        let data = unsafe { Mmap::map(&file).ok()? };
+        let object = object::File::parse(&*data).ok()?;
+
+        // figure out the load bias from the segment containing this mapping
+        let offset = map.offset as u64;
+        let segment = object.segments().find(|segment| {
+            let (start, size) = segment.file_range();
+            start <= offset && offset < start + size
+        })?;
+        let (segment_offset, _) = segment.file_range();
+        let bias = (map.start() as u64)
+            .wrapping_sub(offset)
+            .wrapping_sub(segment.address().wrapping_sub(segment_offset));
+
+        let executable = maps
+            .iter()
+            .find(|m| m.is_exec() && m.filename() == Some(filename))
+            .unwrap_or(map);
+        let headers = maps
+            .iter()
+            .find(|m| m.offset == 0 && m.filename() == Some(filename));
+        let id = match headers.and_then(|m| self.module_id(m.start() as u64).ok()) {
+            Some(id) => id,
+            None => object
+                .build_id()
+                .ok()
+                .flatten()
+                .map(|id| ModuleId::BuildId(id.to_vec())),
+        };
+        Some(Module {
+            filename: filename.display().to_string(),
+            address: executable.start() as u64,
+            size: executable.size() as u64,
+            bias,
+            id,
+        })
+    }
+
+    /// Reads the GNU build-id of the ELF binary whose headers are mapped at `address` (the
+    /// start of its mapping at file offset 0) from the memory of the process. Unlike reading
+    /// it from the file, this still works when the binary has been deleted or replaced since
+    /// the process loaded it.
+    pub fn module_id(&self, address: u64) -> Result<Option<ModuleId>, Error> {
+        let bytes = self.copy(address as usize, goblin::elf64::header::SIZEOF_EHDR)?;
+        let header = Elf::parse_header(&bytes)?;
+        let ctx = Ctx::new(header.container()?, header.endianness()?);
+        let bytes = self.copy(
+            (address + header.e_phoff) as usize,
+            header.e_phnum as usize * header.e_phentsize as usize,
+        )?;
+        let program_headers = ProgramHeader::parse(&bytes, 0, header.e_phnum as usize, ctx)?;
+
+        // the headers are at the start of the segment that maps offset 0 of the file
+        let bias = match program_headers
+            .iter()
+            .find(|h| h.p_type == PT_LOAD && h.p_offset == 0)
+        {
+            Some(load) => address.wrapping_sub(load.p_vaddr),
+            None => address,
+        };
+        for note in program_headers.iter().filter(|h| h.p_type == PT_NOTE) {
+            let notes = self.copy(
+                bias.wrapping_add(note.p_vaddr) as usize,
+                note.p_filesz as usize,
+            )?;
+            if let Some(build_id) = parse_build_id(&notes, note.p_align, ctx.is_little_endian()) {
+                return Ok(Some(ModuleId::BuildId(build_id)));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Finds the build-id in the contents of a PT_NOTE segment. Each note is a header of the
+/// name size, descriptor size and type, followed by the name and the descriptor - both
+/// padded to the alignment of the segment.
+fn parse_build_id(mut notes: &[u8], align: u64, little_endian: bool) -> Option<Vec<u8>> {
+    let align = align.max(4) as usize;
+    let padded = |len: usize| len.checked_next_multiple_of(align);
+    let word = |bytes: &[u8]| {
+        let bytes = bytes.try_into().ok()?;
+        Some(if little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        })
+    };
+    while notes.len() >= 12 {
+        let name_size = word(&notes[0..4])? as usize;
+        let desc_size = word(&notes[4..8])? as usize;
+        let note_type = word(&notes[8..12])?;
+        let name = notes.get(12..12 + name_size)?;
+        let desc_start = 12 + padded(name_size)?;
+        let desc = notes.get(desc_start..desc_start + desc_size)?;
+        if note_type == NT_GNU_BUILD_ID && name == b"GNU\0" {
+            return Some(desc.to_vec());
+        }
+        notes = notes.get(desc_start + padded(desc_size)?..)?;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_address() {
+        let process = Process::new(std::process::id() as i32).unwrap();
+        let addr = test_normalize_address as *const () as usize as u64;
+        let normalized = process.normalize_address(addr).unwrap().unwrap();
+        assert_eq!(
+            Path::new(&normalized.module.filename),
+            std::env::current_exe().unwrap()
+        );
+        assert!(normalized.module.address <= addr);
+        assert_eq!(normalized.offset, addr - normalized.module.bias);
+
+        // heap memory isn't in any binary
+        let value = Box::new(0u64);
+        let addrs = [&*value as *const u64 as u64, addr];
+        let batch = process.normalize_addresses(&addrs).unwrap();
+        assert_eq!(batch[0], None);
+        assert_eq!(batch[1], Some(normalized));
+    }
+
+    #[test]
+    fn test_module_id() {
+        let process = Process::new(std::process::id() as i32).unwrap();
+        let exe = std::fs::read_link("/proc/self/exe").unwrap();
+        let data = std::fs::read("/proc/self/exe").unwrap();
+        let expected = object::File::parse(&*data)
+            .unwrap()
+            .build_id()
+            .unwrap()
+            .expect("test binary has no build-id");
+
+        // read the build-id from the headers mapped into this process
+        let maps = proc_maps::get_process_maps(process.pid).unwrap();
+        let headers = maps
+            .iter()
+            .find(|m| m.offset == 0 && m.filename() == Some(exe.as_path()))
+            .unwrap();
+        assert_eq!(
+            process.module_id(headers.start() as u64).unwrap(),
+            Some(ModuleId::BuildId(expected.to_vec()))
+        );
+
+        // addresses that aren't the start of an elf image fail rather than returning garbage
+        assert!(process.module_id(headers.start() as u64 + 1).is_err());
+    }
+
+    #[test]
+    fn test_parse_build_id() {
+        let mut notes = Vec::new();
+        // a note that isn't the build-id, with a name that needs padding
+        notes.extend(5u32.to_le_bytes());
+        notes.extend(4u32.to_le_bytes());
+        notes.extend(1u32.to_le_bytes());
+        notes.extend(b"CORE\0\0\0\0");
+        notes.extend([0u8; 4]);
+        notes.extend(4u32.to_le_bytes());
+        notes.extend(3u32.to_le_bytes());
+        notes.extend(NT_GNU_BUILD_ID.to_le_bytes());
+        notes.extend(b"GNU\0");
+        notes.extend([0xde, 0xad, 0xbe]);
+        notes.push(0);
+        assert_eq!(
+            parse_build_id(&notes, 4, true),
+            Some(vec![0xde, 0xad, 0xbe])
+        );
+        assert_eq!(parse_build_id(&notes[..20], 4, true), None);
+    }
+}
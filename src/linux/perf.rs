@@ -0,0 +1,529 @@
+//! Samples the threads of a process with perf_event_open, without stopping them.
+//!
+//! Each sample carries the user mode registers of the thread and a copy of the top of its
+//! stack, taken by the kernel at the moment the sampling clock fired. These are returned as
+//! a `StackCopy`, which can be unwound with a `DwarfUnwinder` created over the copy - so
+//! the target never has to be suspended, and sampling at high frequencies costs it very
+//! little.
+//!
+//! Sampling another process needs the same permissions as attaching to it with ptrace, and
+//! a perf_event_paranoid setting of 2 or lower.
+use std::collections::HashMap;
+use std::sync::atomic::{fence, AtomicU64, Ordering};
+use std::time::Duration;
+
+use log::{debug, info};
+
+use super::{Pid, Process, Registers, Tid};
+use crate::{Error, StackCopy};
+
+const PERF_TYPE_SOFTWARE: u32 = 1;
+const PERF_COUNT_SW_CPU_CLOCK: u64 = 0;
+const PERF_COUNT_SW_TASK_CLOCK: u64 = 1;
+
+const PERF_SAMPLE_TID: u64 = 1 << 1;
+const PERF_SAMPLE_TIME: u64 = 1 << 2;
+const PERF_SAMPLE_REGS_USER: u64 = 1 << 12;
+const PERF_SAMPLE_STACK_USER: u64 = 1 << 13;
+
+// bits of the flags in perf_event_attr
+const ATTR_EXCLUDE_KERNEL: u64 = 1 << 5;
+const ATTR_EXCLUDE_HV: u64 = 1 << 6;
+const ATTR_FREQ: u64 = 1 << 10;
+
+const PERF_FLAG_FD_CLOEXEC: libc::c_ulong = 1 << 3;
+
+const PERF_RECORD_LOST: u32 = 2;
+const PERF_RECORD_SAMPLE: u32 = 9;
+
+const PERF_SAMPLE_REGS_ABI_NONE: u64 = 0;
+
+// the perf register numbers sampled, from arch/x86/include/uapi/asm/perf_regs.h. DS, ES, FS
+// and GS can't be sampled on x86_64, so are left out of the mask.
+#[cfg(target_arch = "x86_64")]
+const SAMPLE_REGS: u64 = 0x00ff_0fff;
+// x0-x30, sp and pc, from arch/arm64/include/uapi/asm/perf_regs.h
+#[cfg(target_arch = "aarch64")]
+const SAMPLE_REGS: u64 = (1 << 33) - 1;
+
+/// The offsets of data_head and data_tail in `struct perf_event_mmap_page`
+const DATA_HEAD_OFFSET: usize = 1024;
+const DATA_TAIL_OFFSET: usize = 1032;
+
+/// `struct perf_event_attr` from linux/perf_event.h, as of PERF_ATTR_SIZE_VER5
+#[repr(C)]
+#[derive(Default)]
+#[allow(dead_code)]
+pub(super) struct PerfEventAttr {
+    type_: u32,
+    size: u32,
+    config: u64,
+    sample_freq: u64,
+    sample_type: u64,
+    read_format: u64,
+    flags: u64,
+    wakeup_events: u32,
+    bp_type: u32,
+    config1: u64,
+    config2: u64,
+    branch_sample_type: u64,
+    sample_regs_user: u64,
+    sample_stack_user: u32,
+    clockid: i32,
+    sample_regs_intr: u64,
+    aux_watermark: u32,
+    sample_max_stack: u16,
+    _reserved: u16,
+}
+
+impl PerfEventAttr {
+    /// Samples user mode code on `clock`, `frequency` times a second
+    pub(super) fn sampling(clock: PerfClock, frequency: u64) -> PerfEventAttr {
+        PerfEventAttr {
+            type_: PERF_TYPE_SOFTWARE,
+            size: std::mem::size_of::<PerfEventAttr>() as u32,
+            config: match clock {
+                PerfClock::CpuClock => PERF_COUNT_SW_CPU_CLOCK,
+                PerfClock::TaskClock => PERF_COUNT_SW_TASK_CLOCK,
+            },
+            sample_freq: frequency,
+            flags: ATTR_EXCLUDE_KERNEL | ATTR_EXCLUDE_HV | ATTR_FREQ,
+            ..Default::default()
+        }
+    }
+}
+
+/// Opens a perf event on a single thread, returning its file descriptor
+pub(super) fn perf_event_open(tid: Tid, attr: &PerfEventAttr) -> Result<libc::c_int, Error> {
+    let fd = unsafe {
+        libc::syscall(
+            libc::SYS_perf_event_open,
+            attr as *const PerfEventAttr,
+            tid,
+            -1,
+            -1,
+            PERF_FLAG_FD_CLOEXEC,
+        )
+    } as libc::c_int;
+    if fd < 0 {
+        return Err(Error::from_os_error(tid, std::io::Error::last_os_error()));
+    }
+    Ok(fd)
+}
+
+/// Which clock samples are taken on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PerfClock {
+    /// Wall clock time, so threads are sampled whether they're running or not. Threads that
+    /// are blocked in the kernel have nothing new to sample, so this is mostly the same as
+    /// `TaskClock` for user mode stacks.
+    CpuClock,
+    /// The time each thread spends running on a CPU
+    TaskClock,
+}
+
+/// A sample of a thread taken by a `PerfSampler`
+pub struct PerfSample {
+    /// The time the sample was taken, in nanoseconds of CLOCK_MONOTONIC
+    pub time: u64,
+    /// The registers of the thread, and its stack from the stack pointer up
+    pub stack: StackCopy<Registers>,
+}
+
+/// Configures a `PerfSampler`, created by `PerfSampler::builder`
+pub struct PerfSamplerBuilder {
+    pid: Pid,
+    frequency: u64,
+    stack_size: u32,
+    clock: PerfClock,
+    pages: usize,
+}
+
+impl PerfSamplerBuilder {
+    /// How many times a second each thread is sampled. Defaults to 99.
+    pub fn frequency(mut self, frequency: u64) -> PerfSamplerBuilder {
+        self.frequency = frequency;
+        self
+    }
+
+    /// How many bytes of each stack are copied, from the stack pointer up. Stacks that are
+    /// deeper than this can only be partially unwound. The kernel rounds this down to a
+    /// multiple of 8 and limits it to a little under 64KB. Defaults to 32KB.
+    pub fn stack_size(mut self, stack_size: u32) -> PerfSamplerBuilder {
+        self.stack_size = stack_size;
+        self
+    }
+
+    /// Which clock samples are taken on. Defaults to `PerfClock::TaskClock`.
+    pub fn clock(mut self, clock: PerfClock) -> PerfSamplerBuilder {
+        self.clock = clock;
+        self
+    }
+
+    /// The number of pages in the buffer each thread's samples are written to, which is
+    /// rounded up to a power of two. Samples are dropped when a buffer fills up before
+    /// being read. Defaults to 64.
+    pub fn buffer_pages(mut self, pages: usize) -> PerfSamplerBuilder {
+        self.pages = pages.max(1).next_power_of_two();
+        self
+    }
+
+    /// Starts sampling every thread in the process
+    pub fn open(self) -> Result<PerfSampler, Error> {
+        let mut sampler = PerfSampler {
+            pid: self.pid,
+            options: self,
+            events: HashMap::new(),
+            lost: 0,
+        };
+        sampler.refresh()?;
+        info!(
+            "sampling {} threads of {} with perf events",
+            sampler.events.len(),
+            sampler.pid
+        );
+        Ok(sampler)
+    }
+}
+
+/// Samples the registers and stacks of the threads of a process with perf events. Threads
+/// started after the sampler was opened are only sampled once `refresh` has been called.
+pub struct PerfSampler {
+    pub pid: Pid,
+    options: PerfSamplerBuilder,
+    events: HashMap<Tid, PerfEvent>,
+    lost: u64,
+}
+
+impl PerfSampler {
+    pub fn builder(pid: Pid) -> PerfSamplerBuilder {
+        PerfSamplerBuilder {
+            pid,
+            frequency: 99,
+            stack_size: 32768,
+            clock: PerfClock::TaskClock,
+            pages: 64,
+        }
+    }
+
+    /// Starts sampling every thread in the process with the default options
+    pub fn open(pid: Pid) -> Result<PerfSampler, Error> {
+        PerfSampler::builder(pid).open()
+    }
+
+    /// Starts sampling threads created since the sampler was opened or last refreshed, and
+    /// stops tracking threads that have exited
+    pub fn refresh(&mut self) -> Result<(), Error> {
+        let tids = Process::new(self.pid)?.thread_ids()?;
+        self.events.retain(|tid, _| tids.contains(tid));
+        for tid in tids {
+            if self.events.contains_key(&tid) {
+                continue;
+            }
+            match PerfEvent::open(tid, &self.options) {
+                Ok(event) => {
+                    self.events.insert(tid, event);
+                }
+                // the thread exited before the event could be opened
+                Err(Error::NoSuchProcess { .. }) => debug!("thread {} exited", tid),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// The number of threads being sampled
+    pub fn thread_count(&self) -> usize {
+        self.events.len()
+    }
+
+    /// The number of samples that have been dropped because a buffer was full
+    pub fn lost(&self) -> u64 {
+        self.lost
+    }
+
+    /// Returns the samples taken since the last call, without waiting for any more
+    pub fn read(&mut self) -> Result<Vec<PerfSample>, Error> {
+        let mut samples = Vec::new();
+        for (tid, event) in self.events.iter_mut() {
+            event.read(*tid, &mut samples, &mut self.lost)?;
+        }
+        samples.sort_by_key(|sample| sample.time);
+        Ok(samples)
+    }
+
+    /// Waits up to `timeout` for samples to be taken, and returns them. This returns as soon
+    /// as any thread's buffer has samples in it, so can return fewer samples than `read`
+    /// would after the full timeout.
+    pub fn wait(&mut self, timeout: Duration) -> Result<Vec<PerfSample>, Error> {
+        let mut fds: Vec<libc::pollfd> = self
+            .events
+            .values()
+            .map(|event| libc::pollfd {
+                fd: event.fd,
+                events: libc::POLLIN,
+                revents: 0,
+            })
+            .collect();
+        let timeout = timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+        let ret = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) };
+        if ret < 0 {
+            let error = std::io::Error::last_os_error();
+            if error.kind() != std::io::ErrorKind::Interrupted {
+                return Err(error.into());
+            }
+        }
+        self.read()
+    }
+}
+
+/// A perf event sampling a single thread, and the ring buffer the kernel writes samples to
+struct PerfEvent {
+    fd: libc::c_int,
+    buffer: *mut u8,
+    /// the size of the mapping, which is a metadata page followed by the ring buffer
+    mapped: usize,
+    page_size: usize,
+}
+
+// the buffer is only accessed through &mut self, and is owned by the event
+unsafe impl Send for PerfEvent {}
+unsafe impl Sync for PerfEvent {}
+
+impl PerfEvent {
+    fn open(tid: Tid, options: &PerfSamplerBuilder) -> Result<PerfEvent, Error> {
+        let attr = PerfEventAttr {
+            sample_type: PERF_SAMPLE_TID
+                | PERF_SAMPLE_TIME
+                | PERF_SAMPLE_REGS_USER
+                | PERF_SAMPLE_STACK_USER,
+            sample_regs_user: SAMPLE_REGS,
+            sample_stack_user: options.stack_size & !7,
+            // wake up `wait` as soon as there is a sample to read
+            wakeup_events: 1,
+            clockid: libc::CLOCK_MONOTONIC,
+            ..PerfEventAttr::sampling(options.clock, options.frequency)
+        };
+        let fd = perf_event_open(tid, &attr)?;
+
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let mapped = (options.pages + 1) * page_size;
+        let buffer = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                mapped,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                fd,
+                0,
+            )
+        };
+        if buffer == libc::MAP_FAILED {
+            let error = std::io::Error::last_os_error();
+            unsafe { libc::close(fd) };
+            return Err(error.into());
+        }
+        Ok(PerfEvent {
+            fd,
+            buffer: buffer as *mut u8,
+            mapped,
+            page_size,
+        })
+    }
+
+    fn head(&self) -> &AtomicU64 {
+        unsafe { &*(self.buffer.add(DATA_HEAD_OFFSET) as *const AtomicU64) }
+    }
+
+    fn tail(&self) -> &AtomicU64 {
+        unsafe { &*(self.buffer.add(DATA_TAIL_OFFSET) as *const AtomicU64) }
+    }
+
+    /// Copies `len` bytes out of the ring buffer from `offset`, wrapping around its end
+    fn copy(&self, offset: u64, len: usize) -> Vec<u8> {
+        let size = self.mapped - self.page_size;
+        let data = unsafe { std::slice::from_raw_parts(self.buffer.add(self.page_size), size) };
+        let start = (offset % size as u64) as usize;
+        let mut ret = Vec::with_capacity(len);
+        let first = len.min(size - start);
+        ret.extend_from_slice(&data[start..start + first]);
+        ret.extend_from_slice(&data[..len - first]);
+        ret
+    }
+
+    /// Reads every record in the buffer, adding the samples to `samples`
+    fn read(
+        &mut self,
+        tid: Tid,
+        samples: &mut Vec<PerfSample>,
+        lost: &mut u64,
+    ) -> Result<(), Error> {
+        let head = self.head().load(Ordering::Acquire);
+        let mut tail = self.tail().load(Ordering::Relaxed);
+        while tail + 8 <= head {
+            let header = self.copy(tail, 8);
+            let kind = u32::from_ne_bytes(header[0..4].try_into().unwrap());
+            let size = u16::from_ne_bytes(header[6..8].try_into().unwrap()) as u64;
+            if size < 8 || tail + size > head {
+                break;
+            }
+            let record = self.copy(tail + 8, size as usize - 8);
+            match kind {
+                PERF_RECORD_SAMPLE => match parse_sample(&record) {
+                    Some(sample) => samples.push(sample),
+                    None => debug!("failed to parse perf sample of thread {}", tid),
+                },
+                // the id of the event followed by the number of samples lost
+                PERF_RECORD_LOST => {
+                    if let Some(count) = record.get(8..16) {
+                        *lost += u64::from_ne_bytes(count.try_into().unwrap());
+                    }
+                }
+                _ => {}
+            }
+            tail += size;
+        }
+        // the records have to be read before the kernel is told it can overwrite them
+        fence(Ordering::SeqCst);
+        self.tail().store(tail, Ordering::Release);
+        Ok(())
+    }
+}
+
+impl Drop for PerfEvent {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.buffer as *mut libc::c_void, self.mapped);
+            libc::close(self.fd);
+        }
+    }
+}
+
+/// Parses the body of a PERF_RECORD_SAMPLE, which with the sample type used here is the
+/// pid and tid, the time, the registers and the stack
+fn parse_sample(record: &[u8]) -> Option<PerfSample> {
+    let mut offset = 0;
+    let mut next = move |len: usize| {
+        let data = record.get(offset..offset + len);
+        offset += len;
+        data
+    };
+    let u64_at = |data: &[u8]| u64::from_ne_bytes(data.try_into().unwrap());
+
+    let ids = next(8)?;
+    let tid = i32::from_ne_bytes(ids[4..8].try_into().unwrap());
+    let time = u64_at(next(8)?);
+
+    // threads sampled while in the kernel with no user mode state have no registers
+    let abi = u64_at(next(8)?);
+    if abi == PERF_SAMPLE_REGS_ABI_NONE {
+        return None;
+    }
+    let values: Vec<u64> = (0..SAMPLE_REGS.count_ones())
+        .map(|_| next(8).map(u64_at))
+        .collect::<Option<_>>()?;
+    let registers = registers(&values);
+
+    let size = u64_at(next(8)?) as usize;
+    let stack = next(size)?.to_vec();
+    let len = if size > 0 {
+        (u64_at(next(8)?) as usize).min(size)
+    } else {
+        0
+    };
+
+    #[cfg(target_arch = "x86_64")]
+    let sp = registers.rsp;
+    #[cfg(target_arch = "aarch64")]
+    let sp = registers.sp;
+
+    Some(PerfSample {
+        time,
+        stack: StackCopy {
+            tid,
+            registers,
+            sp,
+            stack: stack[..len].to_vec(),
+        },
+    })
+}
+
+/// Fills in the registers of a thread from the values in a sample, which are in order of
+/// perf register number
+#[cfg(target_arch = "x86_64")]
+fn registers(values: &[u64]) -> Registers {
+    // zero is a valid bit pattern for user_regs_struct, which is all integers
+    let mut r: Registers = unsafe { std::mem::zeroed() };
+    let fields = [
+        &mut r.rax,
+        &mut r.rbx,
+        &mut r.rcx,
+        &mut r.rdx,
+        &mut r.rsi,
+        &mut r.rdi,
+        &mut r.rbp,
+        &mut r.rsp,
+        &mut r.rip,
+        &mut r.eflags,
+        &mut r.cs,
+        &mut r.ss,
+        &mut r.r8,
+        &mut r.r9,
+        &mut r.r10,
+        &mut r.r11,
+        &mut r.r12,
+        &mut r.r13,
+        &mut r.r14,
+        &mut r.r15,
+    ];
+    for (field, value) in fields.into_iter().zip(values) {
+        *field = *value;
+    }
+    r
+}
+
+#[cfg(target_arch = "aarch64")]
+fn registers(values: &[u64]) -> Registers {
+    // zero is a valid bit pattern for user_regs_struct, which is all integers
+    let mut r: Registers = unsafe { std::mem::zeroed() };
+    r.regs.copy_from_slice(&values[..31]);
+    r.sp = values[31];
+    r.pc = values[32];
+    r
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_parse_sample() {
+        let mut record = Vec::new();
+        record.extend_from_slice(&100_i32.to_ne_bytes());
+        record.extend_from_slice(&101_i32.to_ne_bytes());
+        record.extend_from_slice(&5000_u64.to_ne_bytes());
+        record.extend_from_slice(&2_u64.to_ne_bytes());
+        for value in 0..20_u64 {
+            record.extend_from_slice(&value.to_ne_bytes());
+        }
+        record.extend_from_slice(&16_u64.to_ne_bytes());
+        record.extend((0..16).map(|b| b as u8));
+        record.extend_from_slice(&12_u64.to_ne_bytes());
+
+        let sample = parse_sample(&record).unwrap();
+        assert_eq!(sample.time, 5000);
+        assert_eq!(sample.stack.tid, 101);
+        assert_eq!(sample.stack.registers.rax, 0);
+        assert_eq!(sample.stack.registers.rsp, 7);
+        assert_eq!(sample.stack.registers.rip, 8);
+        assert_eq!(sample.stack.registers.r15, 19);
+        assert_eq!(sample.stack.sp, 7);
+        assert_eq!(sample.stack.stack, (0..12).collect::<Vec<u8>>());
+
+        // no registers for samples without user mode state
+        let mut record = record[..24].to_vec();
+        record[16..24].copy_from_slice(&0_u64.to_ne_bytes());
+        assert!(parse_sample(&record).is_none());
+    }
+}
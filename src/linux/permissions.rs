@@ -0,0 +1,173 @@
+use log::debug;
+
+use super::{get_parent_pid, Pid};
+use crate::Error;
+
+const CAP_SYS_PTRACE: u32 = 19;
+
+/// Why the kernel refused to let us ptrace or read memory from a process
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PtraceRestriction {
+    /// kernel.yama.ptrace_scope is 1, which only allows tracing descendant processes
+    YamaDescendantsOnly,
+    /// kernel.yama.ptrace_scope is 2, which only allows tracing with CAP_SYS_PTRACE
+    YamaAdminOnly,
+    /// kernel.yama.ptrace_scope is 3, which disables ptrace entirely
+    YamaDisabled,
+    /// The target is owned by another user, and we don't have CAP_SYS_PTRACE
+    MissingCapability,
+    /// We are running under a seccomp filter, which is likely blocking ptrace or
+    /// process_vm_readv (this is the default inside docker containers)
+    Seccomp,
+}
+
+impl std::fmt::Display for PtraceRestriction {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            PtraceRestriction::YamaDescendantsOnly => write!(
+                f,
+                "Permission denied: kernel.yama.ptrace_scope is set to 1, which only allows tracing \
+                 child processes. Try running as root, granting CAP_SYS_PTRACE, or setting \
+                 'sysctl kernel.yama.ptrace_scope=0'"
+            ),
+            PtraceRestriction::YamaAdminOnly => write!(
+                f,
+                "Permission denied: kernel.yama.ptrace_scope is set to 2, which requires \
+                 CAP_SYS_PTRACE. Try running as root or granting CAP_SYS_PTRACE"
+            ),
+            PtraceRestriction::YamaDisabled => write!(
+                f,
+                "Permission denied: kernel.yama.ptrace_scope is set to 3, which disables ptrace \
+                 until the next reboot"
+            ),
+            PtraceRestriction::MissingCapability => write!(
+                f,
+                "Permission denied: the process is owned by another user. Try running as root \
+                 or granting CAP_SYS_PTRACE"
+            ),
+            PtraceRestriction::Seccomp => write!(
+                f,
+                "Permission denied: this process is running under a seccomp filter. If running in \
+                 docker, try adding '--cap-add SYS_PTRACE' (or '--security-opt seccomp=unconfined')"
+            ),
+        }
+    }
+}
+
+/// Replaces a permission error from accessing the process with a PtraceRestricted error
+/// describing why access was denied, if the cause can be detected
+pub fn permission_error(pid: Pid, err: Error) -> Error {
+    let denied = match &err {
+        Error::NixError(nix::errno::Errno::EPERM) => true,
+        Error::IOError(e) => e.raw_os_error() == Some(libc::EPERM),
+        _ => false,
+    };
+    if !denied {
+        return err;
+    }
+    match detect_restriction(pid) {
+        Some(restriction) => {
+            debug!("access to {} denied: {:?}", pid, restriction);
+            Error::PtraceRestricted(restriction)
+        }
+        None => err,
+    }
+}
+
+/// Checks that a process is visible to us in /proc. When /proc is mounted with hidepid=2
+/// (the default on Android) processes owned by other users don't show up there at all,
+/// even though they still exist.
+pub fn check_visible(pid: Pid) -> Result<(), Error> {
+    if std::path::Path::new(&format!("/proc/{}", pid)).exists() {
+        return Ok(());
+    }
+    match nix::sys::signal::kill(nix::unistd::Pid::from_raw(pid), None) {
+        Err(nix::errno::Errno::EPERM) => Err(Error::PermissionDenied {
+            pid,
+            source: std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                "process is hidden in /proc by the hidepid mount option",
+            ),
+        }),
+        _ => Ok(()),
+    }
+}
+
+fn detect_restriction(pid: Pid) -> Option<PtraceRestriction> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let has_capability =
+        parse_status_hex(&status, "CapEff:").is_some_and(|caps| caps & (1 << CAP_SYS_PTRACE) != 0);
+
+    // the file doesn't exist if yama isn't enabled
+    let scope = std::fs::read_to_string("/proc/sys/kernel/yama/ptrace_scope")
+        .ok()
+        .and_then(|scope| scope.trim().parse::<u32>().ok())
+        .unwrap_or(0);
+
+    match scope {
+        3 => return Some(PtraceRestriction::YamaDisabled),
+        2 if !has_capability => return Some(PtraceRestriction::YamaAdminOnly),
+        1 if !has_capability && !is_descendant(pid) => {
+            return Some(PtraceRestriction::YamaDescendantsOnly)
+        }
+        _ => {}
+    }
+
+    if !has_capability {
+        let target_status = std::fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+        let uid = parse_status_uid(&target_status)?;
+        if uid != unsafe { libc::geteuid() } {
+            return Some(PtraceRestriction::MissingCapability);
+        }
+    }
+
+    // Seccomp: 2 means we're running under a seccomp filter
+    if parse_status_hex(&status, "Seccomp:") == Some(2) {
+        return Some(PtraceRestriction::Seccomp);
+    }
+    None
+}
+
+fn is_descendant(pid: Pid) -> bool {
+    let current = std::process::id() as Pid;
+    let mut pid = pid;
+    while pid > 1 {
+        pid = match get_parent_pid(pid) {
+            Ok(ppid) => ppid,
+            Err(_) => return false,
+        };
+        if pid == current {
+            return true;
+        }
+    }
+    false
+}
+
+pub(super) fn parse_status_hex(status: &str, field: &str) -> Option<u64> {
+    let value = status.lines().find_map(|line| line.strip_prefix(field))?;
+    u64::from_str_radix(value.trim(), 16).ok()
+}
+
+fn parse_status_uid(status: &str) -> Option<u32> {
+    // Uid line has the real, effective, saved and filesystem uids
+    let value = status.lines().find_map(|line| line.strip_prefix("Uid:"))?;
+    value.split_whitespace().nth(1)?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STATUS: &str =
+        "Name:\tbash\nUid:\t1000\t1001\t1000\t1000\nCapEff:\t000001ffffffffff\nSeccomp:\t2\n";
+
+    #[test]
+    fn test_parse_status() {
+        assert_eq!(parse_status_hex(STATUS, "CapEff:"), Some(0x1ffffffffff));
+        assert_eq!(parse_status_hex(STATUS, "Seccomp:"), Some(2));
+        assert_eq!(parse_status_hex(STATUS, "CapPrm:"), None);
+        assert_eq!(parse_status_uid(STATUS), Some(1001));
+        assert_eq!(parse_status_uid("Name:\tbash\n"), None);
+    }
+}
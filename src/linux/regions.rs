@@ -0,0 +1,41 @@
+//! Looks up the mapping that contains an address in /proc/pid/maps
+use super::Process;
+use crate::{Error, Protection};
+
+impl Process {
+    /// Returns the protection of the mapping that contains `addr`, or None if the address
+    /// isn't mapped in the process
+    pub fn query_address(&self, addr: u64) -> Result<Option<Protection>, Error> {
+        let maps =
+            proc_maps::get_process_maps(self.pid).map_err(|e| Error::from_os_error(self.pid, e))?;
+        Ok(maps
+            .iter()
+            .find(|map| map.start() as u64 <= addr && addr < (map.start() + map.size()) as u64)
+            .map(|map| Protection {
+                read: map.is_read(),
+                write: map.is_write(),
+                execute: map.is_exec(),
+            }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query_address() {
+        let process = Process::new(std::process::id() as i32).unwrap();
+        let value = Box::new(0u64);
+        assert_eq!(
+            process.query_address(&*value as *const u64 as u64).unwrap(),
+            Some(Protection::READ_WRITE)
+        );
+        let code = process
+            .query_address(test_query_address as *const () as usize as u64)
+            .unwrap()
+            .unwrap();
+        assert!(code.execute && !code.write);
+        assert_eq!(process.query_address(0).unwrap(), None);
+    }
+}
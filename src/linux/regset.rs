@@ -0,0 +1,181 @@
+//! Reads and writes the register sets of a stopped thread with PTRACE_GETREGSET and
+//! PTRACE_SETREGSET. Each register set is named by the note type it has in a core dump, like
+//! NT_PRSTATUS for the general purpose registers. On x86_64 and aarch64 this also has the
+//! public accessors for the general purpose and floating point registers of a thread.
+use nix::unistd::Pid;
+
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+use super::{Process, Thread, Tid};
+use crate::Error;
+
+/// The general purpose registers of a thread, as returned by PTRACE_GETREGSET
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+pub type Registers = libc::user_regs_struct;
+
+/// The floating point and SSE registers of a thread, in the layout FXSAVE writes them
+#[cfg(target_arch = "x86_64")]
+pub type FpRegisters = libc::user_fpregs_struct;
+
+/// The floating point and NEON registers of a thread
+#[cfg(target_arch = "aarch64")]
+pub type FpRegisters = libc::user_fpsimd_struct;
+
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+const NT_PRFPREG: libc::c_int = 2;
+
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+impl Thread {
+    /// Returns the general purpose registers of this thread. The thread needs to be locked
+    /// for this to succeed.
+    pub fn registers(&self) -> Result<Registers, Error> {
+        // zero is a valid bit pattern for user_regs_struct, which is all integers
+        let mut regs: Registers = unsafe { std::mem::zeroed() };
+        read_regset(self.tid, libc::NT_PRSTATUS, &mut regs)?;
+        Ok(regs)
+    }
+
+    /// Returns the floating point and vector registers of this thread. The thread needs to
+    /// be locked for this to succeed.
+    pub fn fp_registers(&self) -> Result<FpRegisters, Error> {
+        // zero is a valid bit pattern for the fp registers, which are all integers
+        let mut regs: FpRegisters = unsafe { std::mem::zeroed() };
+        read_regset(self.tid, NT_PRFPREG, &mut regs)?;
+        Ok(regs)
+    }
+
+    /// Overwrites the floating point and vector registers of this thread, which needs to be
+    /// locked. Floating point arguments to a function are passed in these registers.
+    pub fn set_fp_registers(&self, regs: &FpRegisters) -> Result<(), Error> {
+        write_regset(self.tid, NT_PRFPREG, regs)
+    }
+}
+
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+impl Process {
+    /// Returns the general purpose and floating point registers of every thread in the
+    /// process, reading them all while the process is locked once. This is slower than
+    /// `capture_all_registers`, since there are twice as many registers to read.
+    pub fn capture_all_registers_with_fp(
+        &self,
+    ) -> Result<Vec<(Tid, Registers, FpRegisters)>, Error> {
+        let lock = self.lock_and_snapshot()?;
+        lock.threads()
+            .iter()
+            .map(|thread| {
+                Ok((
+                    thread.tid.as_raw(),
+                    thread.registers()?,
+                    thread.fp_registers()?,
+                ))
+            })
+            .collect()
+    }
+}
+
+/// Reads a register set of a stopped thread that fills all of a `T`
+#[cfg(any(
+    target_arch = "aarch64",
+    target_arch = "arm",
+    target_arch = "riscv64",
+    target_arch = "loongarch64"
+))]
+pub(crate) fn get_regset<T: Default>(tid: Pid, kind: libc::c_int) -> Result<T, Error> {
+    let mut regs = T::default();
+    read_regset(tid, kind, &mut regs)?;
+    Ok(regs)
+}
+
+/// Reads a register set of a stopped thread into a buffer, returning the number of bytes the
+/// kernel filled in - which is less than the size of the buffer for sets whose size depends
+/// on the cpu or on the thread
+pub(crate) fn read_regset<T: ?Sized>(
+    tid: Pid,
+    kind: libc::c_int,
+    regs: &mut T,
+) -> Result<usize, Error> {
+    regset(
+        tid,
+        kind,
+        regs as *mut T as *mut libc::c_void,
+        std::mem::size_of_val(regs),
+        false,
+    )
+}
+
+/// Overwrites a register set of a stopped thread
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+pub(crate) fn write_regset<T: ?Sized>(tid: Pid, kind: libc::c_int, regs: &T) -> Result<(), Error> {
+    // the kernel only reads from the buffer for PTRACE_SETREGSET
+    regset(
+        tid,
+        kind,
+        regs as *const T as *mut libc::c_void,
+        std::mem::size_of_val(regs),
+        true,
+    )?;
+    Ok(())
+}
+
+fn regset(
+    tid: Pid,
+    kind: libc::c_int,
+    base: *mut libc::c_void,
+    len: usize,
+    set: bool,
+) -> Result<usize, Error> {
+    let mut iov = libc::iovec {
+        iov_base: base,
+        iov_len: len,
+    };
+    let request = if set {
+        libc::PTRACE_SETREGSET
+    } else {
+        libc::PTRACE_GETREGSET
+    };
+    let ret = unsafe {
+        libc::ptrace(
+            request,
+            tid.as_raw(),
+            kind as usize as *mut libc::c_void,
+            &mut iov as *mut libc::iovec,
+        )
+    };
+    if ret < 0 {
+        return Err(Error::IOError(std::io::Error::last_os_error()));
+    }
+    Ok(iov.iov_len)
+}
+
+#[cfg(all(test, target_arch = "x86_64"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fp_registers() {
+        let mut child = std::process::Command::new("sleep")
+            .arg("10")
+            .spawn()
+            .unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(100));
+
+        let thread = Thread::new(child.id() as i32).unwrap();
+        {
+            let _lock = thread.lock().unwrap();
+            let mut regs = thread.fp_registers().unwrap();
+            // sleep doesn't change the rounding mode or unmask any exceptions
+            assert_eq!(regs.mxcsr, 0x1f80);
+
+            regs.xmm_space[0] = 0x3f80_0000;
+            thread.set_fp_registers(&regs).unwrap();
+            assert_eq!(thread.fp_registers().unwrap().xmm_space[0], 0x3f80_0000);
+        }
+
+        let process = Process::new(child.id() as i32).unwrap();
+        let registers = process.capture_all_registers_with_fp().unwrap();
+        assert_eq!(registers.len(), 1);
+        assert_eq!(registers[0].2.xmm_space[0], 0x3f80_0000);
+
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+}
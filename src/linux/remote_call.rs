@@ -0,0 +1,376 @@
+//! Calls functions and makes syscalls inside of a target process, by hijacking one of its
+//! threads.
+//!
+//! The registers of the thread are saved, and then set up as if the thread had just called
+//! the function - with the arguments in the argument registers, and a return address of 0
+//! on its stack. When the function returns the thread faults at address 0, which is where we
+//! pick up the return value and put the original registers back. Syscalls work the same way,
+//! except that the instruction at the thread's instruction pointer is briefly replaced with
+//! a syscall instruction followed by a breakpoint.
+use log::debug;
+use nix::sys::ptrace;
+use nix::sys::signal::Signal;
+use nix::sys::wait::{self, WaitPidFlag, WaitStatus};
+
+use super::{Process, Thread};
+use crate::Error;
+
+/// Bytes to leave untouched below the stack pointer of the hijacked thread. x86_64 leaf
+/// functions can keep data in the 128 byte red zone, so the call has to start below that.
+const STACK_GAP: u64 = 256;
+
+impl Process {
+    /// Calls a function in the process with up to six integer or pointer arguments (eight
+    /// on aarch64), returning what it returned. The main thread is locked and runs the
+    /// function, so this fails if the process is already locked.
+    ///
+    /// The function runs in whatever state the main thread was interrupted in - it could
+    /// be holding locks, or be inside of a signal handler - so this is only safe for
+    /// functions that are async signal safe.
+    pub fn call_function(&self, addr: u64, args: &[u64]) -> Result<u64, Error> {
+        let thread = Thread::new(self.pid)?;
+        let _lock = thread.lock()?;
+        thread.call_function(addr, args)
+    }
+}
+
+impl Thread {
+    /// Makes this thread call a function with up to six integer or pointer arguments (eight
+    /// on aarch64), returning what it returned. The thread needs to be locked, and carries
+    /// on from where it was stopped once unlocked.
+    pub fn call_function(&self, addr: u64, args: &[u64]) -> Result<u64, Error> {
+        if args.len() > arch::MAX_ARGS {
+            return Err(Error::Other(format!(
+                "can't pass {} arguments to a remote function",
+                args.len()
+            )));
+        }
+        let saved = arch::save(self.tid)?;
+        let result = call(self.tid, &saved, addr, args);
+        // put everything back even if the call failed, since the thread is likely
+        // somewhere it can't carry on from
+        arch::restore(self.tid, &saved)?;
+        result
+    }
+
+    /// Makes this thread run a syscall with up to six arguments, returning the raw result -
+    /// which is a negated errno if the syscall failed. The thread needs to be locked.
+    ///
+    /// The code at the thread's instruction pointer is patched while the syscall runs, so
+    /// any other threads that could run that code should be locked too.
+    pub fn syscall(&self, number: u64, args: &[u64]) -> Result<i64, Error> {
+        if args.len() > 6 {
+            return Err(Error::Other(format!(
+                "can't pass {} arguments to a syscall",
+                args.len()
+            )));
+        }
+        let saved = arch::save(self.tid)?;
+        let ip = arch::saved_ip(&saved) as ptrace::AddressType;
+        let original = ptrace::read(self.tid, ip)?;
+        let mut code = original.to_ne_bytes();
+        code[..arch::SYSCALL.len()].copy_from_slice(&arch::SYSCALL);
+        ptrace::write(self.tid, ip, libc::c_long::from_ne_bytes(code))?;
+
+        let result = syscall(self.tid, &saved, number, args);
+        ptrace::write(self.tid, ip, original)?;
+        arch::restore(self.tid, &saved)?;
+        result
+    }
+}
+
+fn call(tid: nix::unistd::Pid, saved: &arch::Saved, addr: u64, args: &[u64]) -> Result<u64, Error> {
+    let sp = (arch::sp(saved) - STACK_GAP) & !0xf;
+    arch::setup_call(tid, saved, addr, args, sp)?;
+    debug!("calling {:#x} in thread {}", addr, tid);
+
+    run_until(tid, Signal::SIGSEGV)?;
+    let ip = arch::ip(tid)?;
+    if ip != 0 {
+        return Err(Error::Other(format!(
+            "remote function {:#x} crashed at {:#x}",
+            addr, ip
+        )));
+    }
+    arch::return_value(tid)
+}
+
+fn syscall(
+    tid: nix::unistd::Pid,
+    saved: &arch::Saved,
+    number: u64,
+    args: &[u64],
+) -> Result<i64, Error> {
+    arch::setup_syscall(tid, saved, number, args)?;
+    debug!("making syscall {} in thread {}", number, tid);
+
+    run_until(tid, Signal::SIGTRAP)?;
+    Ok(arch::return_value(tid)? as i64)
+}
+
+/// Resumes a hijacked thread, and waits for it to stop with `signal`
+fn run_until(tid: nix::unistd::Pid, signal: Signal) -> Result<(), Error> {
+    ptrace::cont(tid, None)?;
+    loop {
+        match wait::waitpid(tid, Some(WaitPidFlag::__WALL))? {
+            WaitStatus::Stopped(_, stopped) if stopped == signal => return Ok(()),
+            WaitStatus::Exited(..) | WaitStatus::Signaled(..) => {
+                return Err(Error::ProcessExited { pid: tid.as_raw() })
+            }
+            // other signals are delivered as usual, and their handlers return to us
+            WaitStatus::Stopped(_, other) => ptrace::cont(tid, other)?,
+            _ => ptrace::cont(tid, None)?,
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod arch {
+    use crate::linux::regset::{read_regset, write_regset};
+    use crate::Error;
+    use nix::sys::ptrace;
+
+    pub const MAX_ARGS: usize = 6;
+
+    /// syscall, int3
+    pub const SYSCALL: [u8; 3] = [0x0f, 0x05, 0xcc];
+
+    /// NT_X86_XSTATE, which covers the AVX registers as well as the x87 and SSE ones
+    const NT_X86_XSTATE: libc::c_int = 0x202;
+    /// large enough for the xsave area of any current cpu, including AMX tiles
+    const XSTATE_SIZE: usize = 16384;
+
+    pub struct Saved {
+        regs: libc::user_regs_struct,
+        xstate: Vec<u8>,
+    }
+
+    pub fn save(tid: nix::unistd::Pid) -> Result<Saved, Error> {
+        let regs = ptrace::getregs(tid)?;
+        let mut xstate = vec![0; XSTATE_SIZE];
+        let len = read_regset(tid, NT_X86_XSTATE, &mut xstate[..])?;
+        xstate.truncate(len);
+        Ok(Saved { regs, xstate })
+    }
+
+    pub fn restore(tid: nix::unistd::Pid, saved: &Saved) -> Result<(), Error> {
+        ptrace::setregs(tid, saved.regs)?;
+        write_regset(tid, NT_X86_XSTATE, &saved.xstate[..])?;
+        Ok(())
+    }
+
+    pub fn sp(saved: &Saved) -> u64 {
+        saved.regs.rsp
+    }
+
+    pub fn saved_ip(saved: &Saved) -> u64 {
+        saved.regs.rip
+    }
+
+    pub fn ip(tid: nix::unistd::Pid) -> Result<u64, Error> {
+        Ok(ptrace::getregs(tid)?.rip)
+    }
+
+    pub fn setup_call(
+        tid: nix::unistd::Pid,
+        saved: &Saved,
+        addr: u64,
+        args: &[u64],
+        sp: u64,
+    ) -> Result<(), Error> {
+        let mut regs = saved.regs;
+        // push a return address of 0, leaving the stack aligned the way a call would
+        let sp = sp - 8;
+        ptrace::write(tid, sp as ptrace::AddressType, 0)?;
+        regs.rsp = sp;
+        regs.rip = addr;
+        // the number of vector registers used by a variadic call
+        regs.rax = 0;
+        let registers = [
+            &mut regs.rdi,
+            &mut regs.rsi,
+            &mut regs.rdx,
+            &mut regs.rcx,
+            &mut regs.r8,
+            &mut regs.r9,
+        ];
+        for (reg, arg) in registers.into_iter().zip(args) {
+            *reg = *arg;
+        }
+        // otherwise a thread stopped in a syscall would have its instruction pointer moved
+        // back to restart the syscall when resumed
+        regs.orig_rax = u64::MAX;
+        Ok(ptrace::setregs(tid, regs)?)
+    }
+
+    pub fn setup_syscall(
+        tid: nix::unistd::Pid,
+        saved: &Saved,
+        number: u64,
+        args: &[u64],
+    ) -> Result<(), Error> {
+        let mut regs = saved.regs;
+        regs.rax = number;
+        let registers = [
+            &mut regs.rdi,
+            &mut regs.rsi,
+            &mut regs.rdx,
+            &mut regs.r10,
+            &mut regs.r8,
+            &mut regs.r9,
+        ];
+        for (reg, arg) in registers.into_iter().zip(args) {
+            *reg = *arg;
+        }
+        regs.orig_rax = u64::MAX;
+        Ok(ptrace::setregs(tid, regs)?)
+    }
+
+    pub fn return_value(tid: nix::unistd::Pid) -> Result<u64, Error> {
+        Ok(ptrace::getregs(tid)?.rax)
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod arch {
+    use crate::linux::regset::{read_regset, write_regset};
+    use crate::Error;
+
+    pub const MAX_ARGS: usize = 8;
+
+    /// svc #0, brk #0
+    pub const SYSCALL: [u8; 8] = [0x01, 0x00, 0x00, 0xd4, 0x00, 0x00, 0x20, 0xd4];
+
+    const NT_PRSTATUS: libc::c_int = 1;
+    const NT_PRFPREG: libc::c_int = 2;
+    const NT_ARM_SYSTEM_CALL: libc::c_int = 0x404;
+
+    /// user_pt_regs: x0-x30, sp, pc, pstate
+    type Registers = [u64; 34];
+
+    pub struct Saved {
+        regs: Registers,
+        /// user_fpsimd_state: v0-v31, fpsr, fpcr
+        fpsimd: [u8; 528],
+        syscall: [u8; 4],
+    }
+
+    fn get_regs(tid: nix::unistd::Pid) -> Result<Registers, Error> {
+        let mut regs = [0_u64; 34];
+        read_regset(tid, NT_PRSTATUS, &mut regs)?;
+        Ok(regs)
+    }
+
+    fn set_regs(tid: nix::unistd::Pid, regs: Registers) -> Result<(), Error> {
+        write_regset(tid, NT_PRSTATUS, &regs)
+    }
+
+    pub fn save(tid: nix::unistd::Pid) -> Result<Saved, Error> {
+        let mut saved = Saved {
+            regs: get_regs(tid)?,
+            fpsimd: [0; 528],
+            syscall: [0; 4],
+        };
+        read_regset(tid, NT_PRFPREG, &mut saved.fpsimd)?;
+        read_regset(tid, NT_ARM_SYSTEM_CALL, &mut saved.syscall)?;
+        Ok(saved)
+    }
+
+    pub fn restore(tid: nix::unistd::Pid, saved: &Saved) -> Result<(), Error> {
+        set_regs(tid, saved.regs)?;
+        write_regset(tid, NT_PRFPREG, &saved.fpsimd)?;
+        write_regset(tid, NT_ARM_SYSTEM_CALL, &saved.syscall)?;
+        Ok(())
+    }
+
+    pub fn sp(saved: &Saved) -> u64 {
+        saved.regs[31]
+    }
+
+    pub fn saved_ip(saved: &Saved) -> u64 {
+        saved.regs[32]
+    }
+
+    pub fn ip(tid: nix::unistd::Pid) -> Result<u64, Error> {
+        Ok(get_regs(tid)?[32])
+    }
+
+    pub fn setup_call(
+        tid: nix::unistd::Pid,
+        saved: &Saved,
+        addr: u64,
+        args: &[u64],
+        sp: u64,
+    ) -> Result<(), Error> {
+        let mut regs = saved.regs;
+        regs[..args.len()].copy_from_slice(args);
+        // returning to the link register faults at 0
+        regs[30] = 0;
+        regs[31] = sp;
+        regs[32] = addr;
+        set_regs(tid, regs)?;
+        // otherwise a thread stopped in a syscall would have its instruction pointer moved
+        // back to restart the syscall when resumed
+        write_regset(tid, NT_ARM_SYSTEM_CALL, &(-1_i32))?;
+        Ok(())
+    }
+
+    pub fn setup_syscall(
+        tid: nix::unistd::Pid,
+        saved: &Saved,
+        number: u64,
+        args: &[u64],
+    ) -> Result<(), Error> {
+        let mut regs = saved.regs;
+        regs[..args.len()].copy_from_slice(args);
+        regs[8] = number;
+        set_regs(tid, regs)?;
+        write_regset(tid, NT_ARM_SYSTEM_CALL, &(-1_i32))?;
+        Ok(())
+    }
+
+    pub fn return_value(tid: nix::unistd::Pid) -> Result<u64, Error> {
+        Ok(get_regs(tid)?[0])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_call_function() {
+        let mut child = std::process::Command::new("sleep")
+            .arg("0.5")
+            .spawn()
+            .unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        let process = Process::new(child.id() as i32).unwrap();
+
+        // libc functions are at the same offset into the libc the child has loaded
+        let ours_maps = proc_maps::get_process_maps(std::process::id() as i32).unwrap();
+        let theirs_maps = proc_maps::get_process_maps(process.pid).unwrap();
+        let resolve = |ours: usize| {
+            let ours_map = ours_maps
+                .iter()
+                .find(|map| ours >= map.start() && ours < map.start() + map.size())
+                .unwrap();
+            let theirs_map = theirs_maps
+                .iter()
+                .find(|map| map.filename() == ours_map.filename() && map.offset == ours_map.offset)
+                .unwrap();
+            (ours - ours_map.start() + theirs_map.start()) as u64
+        };
+
+        let getpid = resolve(libc::getpid as *const () as usize);
+        assert_eq!(
+            process.call_function(getpid, &[]).unwrap(),
+            child.id() as u64
+        );
+        let labs = resolve(libc::labs as *const () as usize);
+        assert_eq!(process.call_function(labs, &[-42_i64 as u64]).unwrap(), 42);
+
+        // the child should carry on sleeping where it left off, and then exit normally
+        assert!(child.wait().unwrap().success());
+    }
+}
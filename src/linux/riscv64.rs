@@ -0,0 +1,68 @@
+//! Register access for riscv64 threads
+use super::regset::get_regset;
+use super::Thread;
+use crate::Error;
+
+/// The general purpose registers of a riscv64 thread, matching `struct user_regs_struct`
+/// from the kernel's asm/ptrace.h
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Registers {
+    pub pc: u64,
+    pub ra: u64,
+    pub sp: u64,
+    pub gp: u64,
+    pub tp: u64,
+    pub t0: u64,
+    pub t1: u64,
+    pub t2: u64,
+    pub s0: u64,
+    pub s1: u64,
+    pub a0: u64,
+    pub a1: u64,
+    pub a2: u64,
+    pub a3: u64,
+    pub a4: u64,
+    pub a5: u64,
+    pub a6: u64,
+    pub a7: u64,
+    pub s2: u64,
+    pub s3: u64,
+    pub s4: u64,
+    pub s5: u64,
+    pub s6: u64,
+    pub s7: u64,
+    pub s8: u64,
+    pub s9: u64,
+    pub s10: u64,
+    pub s11: u64,
+    pub t3: u64,
+    pub t4: u64,
+    pub t5: u64,
+    pub t6: u64,
+}
+
+impl Registers {
+    pub fn ip(&self) -> u64 {
+        self.pc
+    }
+
+    pub fn sp(&self) -> u64 {
+        self.sp
+    }
+
+    /// s0 doubles as the frame pointer when code is compiled with frame pointers
+    pub fn fp(&self) -> u64 {
+        self.s0
+    }
+}
+
+impl Thread {
+    /// Returns the general purpose registers of this thread. The thread needs to be locked
+    /// for this to succeed.
+    pub fn registers(&self) -> Result<Registers, Error> {
+        // riscv64 has no PTRACE_GETREGS, so this has to go through PTRACE_GETREGSET
+        get_regset(self.tid, libc::NT_PRSTATUS)
+    }
+}
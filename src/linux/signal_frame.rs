@@ -0,0 +1,133 @@
+//! Unwinding through signal handlers.
+//!
+//! When the kernel delivers a signal it saves the registers of the interrupted code in a
+//! ucontext on the stack, and sets the handler up to return into a sigreturn trampoline
+//! which restores them. glibc marks its trampolines with CFI describing the saved registers,
+//! but musl and the vdso on some architectures don't - so the trampoline is recognised by
+//! its instructions instead, and the registers are read directly out of the ucontext.
+use super::dwarf::{Registers, REGISTER_COUNT};
+use crate::{Error, ProcessMemory};
+
+#[cfg(target_arch = "x86_64")]
+mod arch {
+    use super::{Registers, REGISTER_COUNT};
+
+    // mov $0xf, %rax (rt_sigreturn); syscall
+    pub const TRAMPOLINE: &[u8] = &[0x48, 0xc7, 0xc0, 0x0f, 0x00, 0x00, 0x00, 0x0f, 0x05];
+
+    // the handler returns into the trampoline by popping the return address, which leaves
+    // the stack pointer at the ucontext. The mcontext follows uc_flags, uc_link and uc_stack
+    pub const MCONTEXT_OFFSET: u64 = 40;
+
+    // sigcontext starts r8-r15, rdi, rsi, rbp, rbx, rdx, rax, rcx, rsp, rip
+    pub const SAVED_REGISTERS: usize = 17;
+    pub const IP_INDEX: usize = 16;
+
+    pub fn registers(saved: &[u64; SAVED_REGISTERS]) -> Registers {
+        // the sigcontext index of each DWARF register
+        let order = [13, 12, 14, 11, 9, 8, 10, 15, 0, 1, 2, 3, 4, 5, 6, 7];
+        let mut regs: Registers = [None; REGISTER_COUNT];
+        for (reg, index) in regs.iter_mut().zip(order) {
+            *reg = Some(saved[index]);
+        }
+        regs
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod arch {
+    use super::{Registers, REGISTER_COUNT};
+
+    // mov x8, #139 (rt_sigreturn); svc #0
+    pub const TRAMPOLINE: &[u8] = &[0x68, 0x11, 0x80, 0xd2, 0x01, 0x00, 0x00, 0xd4];
+
+    // the stack pointer is left at the rt_sigframe, which is a 128 byte siginfo followed by
+    // the ucontext. The mcontext is 16 byte aligned after the 128 byte signal mask, and
+    // starts with the fault address
+    pub const MCONTEXT_OFFSET: u64 = 128 + 176 + 8;
+
+    // sigcontext regs are x0-x30, sp, pc
+    pub const SAVED_REGISTERS: usize = 33;
+    pub const IP_INDEX: usize = 32;
+
+    pub fn registers(saved: &[u64; SAVED_REGISTERS]) -> Registers {
+        // DWARF register numbers match the order of the sigcontext
+        let mut regs: Registers = [None; REGISTER_COUNT];
+        for (reg, value) in regs.iter_mut().zip(&saved[..32]) {
+            *reg = Some(*value);
+        }
+        regs
+    }
+}
+
+#[cfg(target_arch = "riscv64")]
+mod arch {
+    use super::{Registers, REGISTER_COUNT};
+
+    // li a7, 139 (rt_sigreturn); ecall
+    pub const TRAMPOLINE: &[u8] = &[0x93, 0x08, 0xb0, 0x08, 0x73, 0x00, 0x00, 0x00];
+
+    // the stack pointer is left at the rt_sigframe, which is a 128 byte siginfo followed by
+    // the ucontext. The mcontext is 16 byte aligned after the 128 byte signal mask
+    pub const MCONTEXT_OFFSET: u64 = 128 + 176;
+
+    // sigcontext regs are pc, then x1-x31
+    pub const SAVED_REGISTERS: usize = 32;
+    pub const IP_INDEX: usize = 0;
+
+    pub fn registers(saved: &[u64; SAVED_REGISTERS]) -> Registers {
+        // x0 is hardwired to zero, so isn't saved
+        let mut regs: Registers = [None; REGISTER_COUNT];
+        regs[0] = Some(0);
+        for (reg, value) in regs[1..].iter_mut().zip(&saved[1..]) {
+            *reg = Some(*value);
+        }
+        regs
+    }
+}
+
+/// True if the instruction pointer is at the start of a sigreturn trampoline
+pub fn is_sigreturn_trampoline<M: ProcessMemory>(memory: &M, ip: u64) -> bool {
+    let mut code = [0_u8; 16];
+    let code = &mut code[..arch::TRAMPOLINE.len()];
+    memory.read(ip as usize, code).is_ok() && code == arch::TRAMPOLINE
+}
+
+/// Reads the registers of the code interrupted by a signal, given the stack pointer of the
+/// sigreturn trampoline's frame. Returns the interrupted instruction pointer along with
+/// the DWARF registers.
+pub fn signal_registers<M: ProcessMemory>(memory: &M, sp: u64) -> Result<(u64, Registers), Error> {
+    let saved: [u64; arch::SAVED_REGISTERS] =
+        memory.copy_struct((sp + arch::MCONTEXT_OFFSET) as usize)?;
+    Ok((saved[arch::IP_INDEX], arch::registers(&saved)))
+}
+
+#[cfg(all(test, target_arch = "x86_64"))]
+mod tests {
+    use super::*;
+    use crate::fake_memory::FakeMemory;
+
+    #[test]
+    fn test_signal_registers() {
+        let mut data = vec![0_u8; arch::MCONTEXT_OFFSET as usize];
+        for value in 0..arch::SAVED_REGISTERS as u64 {
+            data.extend_from_slice(&(0x100 + value).to_ne_bytes());
+        }
+        let (ip, regs) = signal_registers(&FakeMemory::new(0, data), 0).unwrap();
+        assert_eq!(ip, 0x110);
+        // rax, rsp and r8
+        assert_eq!(regs[0], Some(0x10d));
+        assert_eq!(regs[7], Some(0x10f));
+        assert_eq!(regs[8], Some(0x100));
+    }
+
+    #[test]
+    fn test_is_sigreturn_trampoline() {
+        let mut data = vec![0x90; 4];
+        data.extend_from_slice(arch::TRAMPOLINE);
+        let stack = FakeMemory::new(0, data);
+        assert!(is_sigreturn_trampoline(&stack, 4));
+        assert!(!is_sigreturn_trampoline(&stack, 0));
+        assert!(!is_sigreturn_trampoline(&stack, 400));
+    }
+}
@@ -0,0 +1,80 @@
+use super::permissions::parse_status_hex;
+use crate::Error;
+
+/// A set of signals, as shown in the hex masks of /proc/pid/status
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SignalSet(pub u64);
+
+impl SignalSet {
+    /// True if the signal with this number (like `libc::SIGTERM`) is in the set
+    pub fn contains(&self, signal: i32) -> bool {
+        (1..=64).contains(&signal) && self.0 & (1 << (signal - 1)) != 0
+    }
+
+    /// Returns the numbers of the signals in the set, in increasing order
+    pub fn signals(&self) -> impl Iterator<Item = i32> + '_ {
+        (1..=64).filter(move |signal| self.contains(*signal))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+/// How a process or thread is handling signals, returned by `Process::signals` and
+/// `Thread::signals`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SignalState {
+    /// Signals pending for the thread. For a process this is the main thread.
+    pub pending: SignalSet,
+    /// Signals pending for the whole process, that any thread not blocking them can handle
+    pub shared_pending: SignalSet,
+    /// Signals blocked by the thread's signal mask. For a process this is the main thread.
+    pub blocked: SignalSet,
+    pub ignored: SignalSet,
+    /// Signals with a handler installed
+    pub caught: SignalSet,
+}
+
+pub fn parse_signal_state(status: &str) -> Result<SignalState, Error> {
+    let field = |name: &str| {
+        parse_status_hex(status, name)
+            .map(SignalSet)
+            .ok_or_else(|| Error::Other(format!("Failed to parse {} from status", name)))
+    };
+    Ok(SignalState {
+        pending: field("SigPnd:")?,
+        shared_pending: field("ShdPnd:")?,
+        blocked: field("SigBlk:")?,
+        ignored: field("SigIgn:")?,
+        caught: field("SigCgt:")?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_signal_state() {
+        let state = parse_signal_state(
+            "Name:\tsleep\nSigQ:\t0/63499\nSigPnd:\t0000000000000000\nShdPnd:\t0000000000004000\n\
+             SigBlk:\t0000000000010002\nSigIgn:\t0000000000001000\nSigCgt:\t0000000180000000\n",
+        )
+        .unwrap();
+        assert!(state.pending.is_empty());
+        assert!(state.shared_pending.contains(libc::SIGTERM));
+        assert_eq!(
+            state.blocked.signals().collect::<Vec<_>>(),
+            vec![libc::SIGINT, libc::SIGCHLD]
+        );
+        assert!(state.ignored.contains(libc::SIGPIPE));
+        assert!(!state.ignored.contains(0));
+        assert!(!state.ignored.contains(65));
+        assert_eq!(state.caught.signals().collect::<Vec<_>>(), vec![32, 33]);
+
+        assert!(parse_signal_state("Name:\tsleep\n").is_err());
+    }
+}
@@ -0,0 +1,101 @@
+//! Copies the registers and stack of a locked thread, so that it can be resumed right away
+//! and unwound later from the copy
+use super::{Process, Registers, Thread, Tid};
+use crate::{Error, ProcessMemory, StackCopy};
+
+impl Process {
+    /// Returns the registers of every thread in the process. The process is locked once, the
+    /// registers of its threads are read one after another, and then it's resumed right
+    /// away - so it's stopped for as short a time as possible.
+    pub fn capture_all_registers(&self) -> Result<Vec<(Tid, Registers)>, Error> {
+        let lock = self.lock_and_snapshot()?;
+        lock.threads()
+            .iter()
+            .map(|thread| Ok((thread.tid.as_raw(), thread.registers()?)))
+            .collect()
+    }
+}
+
+impl Thread {
+    /// Copies the registers of this thread, and its stack from the stack pointer up to the
+    /// end of the mapping that contains it - at most `max_bytes` of it. The thread needs to
+    /// be locked, but only for as long as this takes: the copy can be unwound after the lock
+    /// has been released, since it implements `ProcessMemory` itself.
+    pub fn copy_stack(&self, max_bytes: usize) -> Result<StackCopy<Registers>, Error> {
+        let registers = self.registers()?;
+        #[cfg(target_arch = "x86_64")]
+        let sp = registers.rsp;
+        #[cfg(target_arch = "aarch64")]
+        let sp = registers.sp;
+        #[cfg(any(target_arch = "riscv64", target_arch = "loongarch64"))]
+        let sp = registers.sp();
+
+        let tid = self.tid.as_raw();
+        let maps = proc_maps::get_process_maps(tid).map_err(|e| Error::from_os_error(tid, e))?;
+        let base = maps
+            .iter()
+            .find(|map| map.start() as u64 <= sp && sp < (map.start() + map.size()) as u64)
+            .map(|map| (map.start() + map.size()) as u64)
+            .ok_or_else(|| {
+                Error::Other(format!(
+                    "stack pointer {:#x} of thread {} isn't in a mapping",
+                    sp, tid
+                ))
+            })?;
+
+        let len = ((base - sp) as usize).min(max_bytes);
+        let stack = Process::new(tid)?.copy(sp as usize, len)?;
+        Ok(StackCopy {
+            tid,
+            registers,
+            sp,
+            stack,
+        })
+    }
+}
+
+#[cfg(all(test, target_arch = "x86_64"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_copy_stack() {
+        let mut child = std::process::Command::new("sleep")
+            .arg("10")
+            .spawn()
+            .unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(100));
+
+        let process = Process::new(child.id() as i32).unwrap();
+        let thread = Thread::new(child.id() as i32).unwrap();
+        let copy = {
+            let _lock = thread.lock().unwrap();
+            thread.copy_stack(1 << 20).unwrap()
+        };
+        assert_eq!(copy.tid, child.id() as i32);
+        assert_eq!(copy.sp, copy.registers.rsp);
+        assert!(!copy.stack.is_empty());
+
+        // the copy matches the stack, which sleep leaves alone while it's blocked
+        let top = copy.stack.len().min(64);
+        assert_eq!(
+            copy.copy(copy.sp as usize, top).unwrap(),
+            process.copy(copy.sp as usize, top).unwrap()
+        );
+        assert!(copy.copy(copy.sp as usize - 8, 8).is_err());
+
+        let small = {
+            let _lock = thread.lock().unwrap();
+            thread.copy_stack(16).unwrap()
+        };
+        assert_eq!(small.stack.len(), 16);
+
+        let registers = process.capture_all_registers().unwrap();
+        assert_eq!(registers.len(), 1);
+        assert_eq!(registers[0].0, child.id() as i32);
+        assert_eq!(registers[0].1.rsp, copy.registers.rsp);
+
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+}
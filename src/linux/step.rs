@@ -0,0 +1,84 @@
+//! Single-stepping a thread one instruction at a time with PTRACE_SINGLESTEP
+use log::debug;
+use nix::sys::ptrace;
+use nix::sys::signal::Signal;
+use nix::sys::wait::{self, WaitPidFlag, WaitStatus};
+
+use super::{Registers, Thread};
+use crate::Error;
+
+impl Thread {
+    /// Executes a single instruction of this thread, and returns its registers afterwards.
+    /// The thread needs to be locked, and stays stopped once the instruction has run.
+    ///
+    /// If a signal arrives for the thread first it's delivered, and the step stops at the
+    /// first instruction of the signal handler instead. SIGSTOP is the exception, and is
+    /// discarded rather than stopping the thread a second time.
+    pub fn step(&self) -> Result<Registers, Error> {
+        single_step(self.tid)?;
+        self.registers()
+    }
+}
+
+/// Steps a stopped thread over one instruction, waiting for it to stop again
+pub(super) fn single_step(tid: nix::unistd::Pid) -> Result<(), Error> {
+    ptrace::step(tid, None)?;
+    loop {
+        match wait::waitpid(tid, Some(WaitPidFlag::__WALL))? {
+            WaitStatus::Stopped(_, Signal::SIGTRAP) => return Ok(()),
+            WaitStatus::Exited(..) | WaitStatus::Signaled(..) => {
+                return Err(Error::Other(format!(
+                    "thread {} exited while stepping",
+                    tid
+                )))
+            }
+            // stopping the thread again would leave it in a group stop rather than stepping,
+            // and it's already stopped for as long as the caller holds it
+            WaitStatus::Stopped(_, Signal::SIGSTOP) => {
+                debug!("thread {} got SIGSTOP while stepping, suppressing it", tid);
+                ptrace::step(tid, None)?;
+            }
+            // any other signal arriving first is delivered as part of the step, rather than lost
+            WaitStatus::Stopped(_, signal) => {
+                debug!("thread {} got {} while stepping", tid, signal);
+                ptrace::step(tid, signal)?;
+            }
+            status => {
+                debug!("thread {} stopped with {:?} while stepping", tid, status);
+                ptrace::step(tid, None)?;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_step() {
+        // a busy loop that doesn't block in a syscall, where a step could wait indefinitely
+        let mut child = std::process::Command::new("sh")
+            .args(["-c", "while :; do :; done"])
+            .spawn()
+            .unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(100));
+
+        let thread = Thread::new(child.id() as i32).unwrap();
+        {
+            let _lock = thread.lock().unwrap();
+            let (ip, _) = thread.frame_registers().unwrap();
+            let regs = thread.step().unwrap();
+            #[cfg(target_arch = "x86_64")]
+            let stepped = regs.rip;
+            #[cfg(target_arch = "aarch64")]
+            let stepped = regs.pc;
+            assert_ne!(stepped, ip);
+            assert_eq!(thread.frame_registers().unwrap().0, stepped);
+        }
+
+        assert!(child.try_wait().unwrap().is_none());
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+}
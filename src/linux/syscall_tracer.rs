@@ -0,0 +1,291 @@
+//! Traces the syscalls made by every thread of a process with PTRACE_SYSCALL.
+//!
+//! The arguments and return values are read with PTRACE_GET_SYSCALL_INFO, which needs linux
+//! 5.3 or later, but saves knowing the syscall calling convention of each architecture.
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::time::{Duration, Instant};
+
+use log::{debug, info, warn};
+use nix::sys::ptrace;
+use nix::sys::signal::Signal;
+use nix::sys::wait::{self, WaitPidFlag, WaitStatus};
+
+use super::{Pid, Process};
+use crate::{Error, SyscallEvent};
+
+const PTRACE_GET_SYSCALL_INFO: libc::c_uint = 0x420e;
+const PTRACE_SYSCALL_INFO_ENTRY: u8 = 1;
+const PTRACE_SYSCALL_INFO_EXIT: u8 = 2;
+const PTRACE_SYSCALL_INFO_SECCOMP: u8 = 3;
+
+/// `struct ptrace_syscall_info` from linux/ptrace.h. libc only has this for glibc targets,
+/// and the layout is the same everywhere.
+#[repr(C)]
+#[derive(Default)]
+struct SyscallInfo {
+    op: u8,
+    _pad: [u8; 3],
+    _arch: u32,
+    _instruction_pointer: u64,
+    _stack_pointer: u64,
+    /// the entry and seccomp variants are the syscall number followed by the arguments,
+    /// and the exit variant is the return value followed by an is_error byte
+    data: [u64; 8],
+}
+
+/// Reports the syscalls entered and returned from by the threads of a process.
+///
+/// Every thread is attached to with ptrace until this is dropped, and stops briefly on
+/// each syscall entry and exit - which slows down syscall heavy programs considerably.
+pub struct SyscallTracer {
+    pub pid: Pid,
+    threads: Vec<nix::unistd::Pid>,
+    /// the syscall each thread is inside of, so that exits can report it
+    syscalls: HashMap<nix::unistd::Pid, u64>,
+    exited: bool,
+    // ptrace requests have to come from the thread that attached
+    _not_send: PhantomData<*const ()>,
+}
+
+impl SyscallTracer {
+    /// Attaches to all threads of a process and starts tracing their syscalls
+    pub fn attach(pid: Pid) -> Result<SyscallTracer, Error> {
+        let process = Process::new(pid)?;
+        let mut tracer = SyscallTracer {
+            pid,
+            threads: Vec::new(),
+            syscalls: HashMap::new(),
+            exited: false,
+            _not_send: PhantomData,
+        };
+
+        // threads started by attached threads are attached to automatically, but threads can
+        // also start while we're attaching - so keep going until there are no new ones
+        let options = ptrace::Options::PTRACE_O_TRACESYSGOOD
+            | ptrace::Options::PTRACE_O_TRACECLONE
+            | ptrace::Options::PTRACE_O_TRACEEXIT;
+        loop {
+            let mut attached = false;
+            for thread in process.threads()? {
+                if tracer.threads.contains(&thread.tid) {
+                    continue;
+                }
+                match ptrace::seize(thread.tid, options) {
+                    Ok(()) => {}
+                    // the thread exited before we could attach
+                    Err(nix::errno::Errno::ESRCH) => continue,
+                    Err(e) => return Err(e.into()),
+                }
+                // a seized thread only stops at syscalls once it's been restarted with
+                // PTRACE_SYSCALL, which happens when it reports the stop requested here
+                ptrace::interrupt(thread.tid)?;
+                tracer.threads.push(thread.tid);
+                attached = true;
+            }
+            if !attached {
+                break;
+            }
+        }
+        info!(
+            "tracing syscalls of {} threads of {}",
+            tracer.threads.len(),
+            pid
+        );
+        Ok(tracer)
+    }
+
+    /// Waits up to `timeout` for the next syscall entry or exit, returning None if there
+    /// wasn't one
+    pub fn wait(&mut self, timeout: Duration) -> Result<Option<SyscallEvent>, Error> {
+        let start = Instant::now();
+        while !self.exited {
+            for tid in self.threads.clone() {
+                let flags = WaitPidFlag::__WALL | WaitPidFlag::WNOHANG;
+                let status = match wait::waitpid(tid, Some(flags)) {
+                    Ok(WaitStatus::StillAlive) => continue,
+                    Ok(status) => status,
+                    // the thread has already exited and been reaped
+                    Err(nix::errno::Errno::ECHILD) => {
+                        self.remove_thread(tid);
+                        continue;
+                    }
+                    Err(e) => return Err(e.into()),
+                };
+                if let Some(event) = self.handle_status(tid, status)? {
+                    return Ok(Some(event));
+                }
+            }
+            if start.elapsed() >= timeout {
+                return Ok(None);
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        Err(Error::ProcessExited { pid: self.pid })
+    }
+
+    /// Handles a change in state of a thread, restarting it and returning the syscall event
+    /// it reported if any
+    fn handle_status(
+        &mut self,
+        tid: nix::unistd::Pid,
+        status: WaitStatus,
+    ) -> Result<Option<SyscallEvent>, Error> {
+        match status {
+            WaitStatus::PtraceSyscall(_) => {
+                let event = self.syscall_event(tid);
+                ptrace::syscall(tid, None)?;
+                event
+            }
+            WaitStatus::PtraceEvent(_, _, event) => {
+                if event == ptrace::Event::PTRACE_EVENT_CLONE as i32 {
+                    // the new thread is attached to already, and stops before it starts
+                    let new = nix::unistd::Pid::from_raw(ptrace::getevent(tid)? as i32);
+                    debug!("thread {} started thread {}", tid, new);
+                    if !self.threads.contains(&new) {
+                        self.threads.push(new);
+                    }
+                }
+                ptrace::syscall(tid, None)?;
+                Ok(None)
+            }
+            WaitStatus::Stopped(_, signal) => {
+                // signals weren't sent by us, so pass them on
+                ptrace::syscall(tid, signal)?;
+                Ok(None)
+            }
+            WaitStatus::Exited(..) | WaitStatus::Signaled(..) => {
+                self.remove_thread(tid);
+                Ok(None)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn syscall_event(&mut self, tid: nix::unistd::Pid) -> Result<Option<SyscallEvent>, Error> {
+        let info = syscall_info(tid)?;
+        match info.op {
+            PTRACE_SYSCALL_INFO_ENTRY | PTRACE_SYSCALL_INFO_SECCOMP => {
+                let number = info.data[0];
+                self.syscalls.insert(tid, number);
+                let mut args = [0; 6];
+                args.copy_from_slice(&info.data[1..7]);
+                Ok(Some(SyscallEvent::Enter {
+                    tid: tid.as_raw(),
+                    number,
+                    args,
+                }))
+            }
+            PTRACE_SYSCALL_INFO_EXIT => {
+                // threads attached to in the middle of a syscall exit without entering it
+                let number = match self.syscalls.remove(&tid) {
+                    Some(number) => number,
+                    None => return Ok(None),
+                };
+                Ok(Some(SyscallEvent::Exit {
+                    tid: tid.as_raw(),
+                    number,
+                    ret: info.data[0] as i64,
+                }))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn remove_thread(&mut self, tid: nix::unistd::Pid) {
+        self.threads.retain(|t| *t != tid);
+        self.syscalls.remove(&tid);
+        if self.threads.is_empty() {
+            self.exited = true;
+        }
+    }
+}
+
+impl Drop for SyscallTracer {
+    fn drop(&mut self) {
+        if self.exited {
+            return;
+        }
+        for tid in std::mem::take(&mut self.threads) {
+            let signal = match stop(tid) {
+                Ok(signal) => signal,
+                Err(e) => {
+                    warn!("failed to stop thread {}: {}", tid, e);
+                    continue;
+                }
+            };
+            if let Err(e) = ptrace::detach(tid, signal) {
+                warn!("failed to detach from thread {}: {}", tid, e);
+            }
+        }
+        debug!("stopped tracing syscalls of {}", self.pid);
+    }
+}
+
+/// Stops a running thread so that it can be detached from, returning any signal it was
+/// stopped with rather than by us
+fn stop(tid: nix::unistd::Pid) -> Result<Option<Signal>, Error> {
+    ptrace::interrupt(tid)?;
+    match wait::waitpid(tid, Some(WaitPidFlag::__WALL))? {
+        WaitStatus::Stopped(_, signal) => Ok(Some(signal)),
+        WaitStatus::Exited(..) | WaitStatus::Signaled(..) => {
+            Err(Error::Other(format!("thread {} exited", tid)))
+        }
+        // stopped at a syscall or ptrace event, which is as good as the interrupt
+        _ => Ok(None),
+    }
+}
+
+fn syscall_info(tid: nix::unistd::Pid) -> Result<SyscallInfo, Error> {
+    let mut info = SyscallInfo::default();
+    let ret = unsafe {
+        libc::ptrace(
+            PTRACE_GET_SYSCALL_INFO,
+            tid.as_raw(),
+            std::mem::size_of::<SyscallInfo>(),
+            &mut info as *mut SyscallInfo,
+        )
+    };
+    if ret < 0 {
+        return Err(Error::IOError(std::io::Error::last_os_error()));
+    }
+    Ok(info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trace_syscalls() {
+        // a shell loop that keeps making syscalls, without starting new processes
+        let mut child = std::process::Command::new("sh")
+            .args(["-c", "while :; do read x < /dev/null; done"])
+            .spawn()
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        let pid = child.id() as Pid;
+
+        {
+            let mut tracer = SyscallTracer::attach(pid).unwrap();
+            let mut entered = None;
+            let mut exited = false;
+            while !exited {
+                match tracer.wait(Duration::from_secs(5)).unwrap().unwrap() {
+                    SyscallEvent::Enter { tid, number, .. } => {
+                        assert_eq!(tid, pid);
+                        entered.get_or_insert(number);
+                    }
+                    SyscallEvent::Exit { tid, number, .. } => {
+                        assert_eq!(tid, pid);
+                        exited = Some(number) == entered;
+                    }
+                }
+            }
+        }
+
+        assert!(child.try_wait().unwrap().is_none());
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+}
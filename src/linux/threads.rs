@@ -0,0 +1,247 @@
+//! Details of the threads of a process. `threads_iter` lists the threads lazily, reading
+//! /proc/pid/task/tid/stat for each thread only when the iterator reaches it.
+use std::time::Duration;
+
+use nix::sched::{sched_getaffinity, CpuSet};
+
+use super::{Pid, Process, Thread, Tid};
+use crate::{Error, ThreadInfo, ThreadState};
+
+/// How long a thread has spent running and waiting to run, from /proc/pid/task/tid/schedstat
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SchedStats {
+    /// Time spent running on a CPU
+    pub run_time: Duration,
+    /// Time spent runnable, but waiting on a run queue for a CPU
+    pub wait_time: Duration,
+    /// The number of times the thread has been scheduled to run
+    pub timeslices: u64,
+}
+
+/// An iterator over the threads of a process, returned by `Process::threads_iter`
+pub struct ThreadIter {
+    pid: Pid,
+    tasks: std::fs::ReadDir,
+}
+
+impl Process {
+    /// Returns an iterator over the threads of the process, with the name, state and CPU
+    /// time of each. Threads that exit while iterating are skipped.
+    pub fn threads_iter(&self) -> Result<ThreadIter, Error> {
+        let tasks = std::fs::read_dir(format!("/proc/{}/task", self.pid))
+            .map_err(|e| Error::from_os_error(self.pid, e))?;
+        Ok(ThreadIter {
+            pid: self.pid,
+            tasks,
+        })
+    }
+}
+
+impl Iterator for ThreadIter {
+    type Item = Result<ThreadInfo, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let entry = match self.tasks.next()? {
+                Ok(entry) => entry,
+                Err(e) => return Some(Err(Error::from_os_error(self.pid, e))),
+            };
+            let tid: Tid = match entry.file_name().to_str().and_then(|s| s.parse().ok()) {
+                Some(tid) => tid,
+                None => continue,
+            };
+            let stat = match std::fs::read(format!("/proc/{}/task/{}/stat", self.pid, tid)) {
+                Ok(stat) => stat,
+                // the thread exited after the directory was read
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) if e.raw_os_error() == Some(libc::ESRCH) => continue,
+                Err(e) => return Some(Err(Error::from_os_error(self.pid, e))),
+            };
+            return Some(parse_stat(tid, &stat).ok_or_else(|| {
+                Error::Other(format!(
+                    "Failed to parse /proc/{}/task/{}/stat",
+                    self.pid, tid
+                ))
+            }));
+        }
+    }
+}
+
+impl Process {
+    /// Returns the CPUs the process is allowed to run on. Each thread has its own affinity
+    /// on linux, and this is the affinity of the main thread.
+    pub fn affinity(&self) -> Result<Vec<usize>, Error> {
+        let cpus = sched_getaffinity(nix::unistd::Pid::from_raw(self.pid))
+            .map_err(|e| Error::from_os_error(self.pid, e.into()))?;
+        Ok(cpu_list(&cpus))
+    }
+}
+
+impl Thread {
+    /// Returns the CPUs the thread is allowed to run on
+    pub fn affinity(&self) -> Result<Vec<usize>, Error> {
+        let cpus = sched_getaffinity(self.tid)?;
+        Ok(cpu_list(&cpus))
+    }
+
+    /// Returns the CPU the thread last ran on
+    pub fn last_cpu(&self) -> Result<Option<usize>, Error> {
+        let pid = self.tid.as_raw();
+        let stat = std::fs::read(format!("/proc/{}/stat", pid))
+            .map_err(|e| Error::from_os_error(pid, e))?;
+        match parse_last_cpu(&stat) {
+            Some(cpu) => Ok(Some(cpu)),
+            None => Err(Error::Other(format!("Failed to parse /proc/{}/stat", pid))),
+        }
+    }
+
+    /// Returns how long the thread has spent running and waiting to run. This needs a kernel
+    /// built with CONFIG_SCHED_INFO, which most distributions enable.
+    pub fn sched_stats(&self) -> Result<SchedStats, Error> {
+        let pid = self.tid.as_raw();
+        let schedstat = std::fs::read_to_string(format!("/proc/{}/schedstat", pid))
+            .map_err(|e| Error::from_os_error(pid, e))?;
+        parse_schedstat(&schedstat)
+            .ok_or_else(|| Error::Other(format!("Failed to parse /proc/{}/schedstat", pid)))
+    }
+}
+
+fn cpu_list(cpus: &CpuSet) -> Vec<usize> {
+    (0..CpuSet::count())
+        .filter(|cpu| cpus.is_set(*cpu).unwrap_or(false))
+        .collect()
+}
+
+/// Parses the name, state and CPU time of a thread from its stat file
+fn parse_stat(tid: Tid, stat: &[u8]) -> Option<ThreadInfo> {
+    let stat = String::from_utf8_lossy(stat);
+    // the name can contain spaces and ')', so it ends at the last ')' in the line
+    let start = stat.find('(')?;
+    let end = stat.rfind(')')?;
+    let name = stat.get(start + 1..end)?.to_owned();
+    let fields: Vec<&str> = stat[end + 1..].split_whitespace().collect();
+
+    let state = match *fields.first()? {
+        "R" => ThreadState::Running,
+        "S" | "I" => ThreadState::Sleeping,
+        "D" => ThreadState::Uninterruptible,
+        "T" | "t" => ThreadState::Stopped,
+        "Z" | "X" => ThreadState::Zombie,
+        _ => ThreadState::Unknown,
+    };
+    // utime and stime are fields 14 and 15 of the file, in clock ticks
+    let utime: u64 = fields.get(11)?.parse().ok()?;
+    let stime: u64 = fields.get(12)?.parse().ok()?;
+    let ticks = match unsafe { libc::sysconf(libc::_SC_CLK_TCK) } {
+        ticks if ticks > 0 => ticks as u64,
+        _ => 100,
+    };
+    let cpu_time = Duration::from_nanos((utime + stime) * 1_000_000_000 / ticks);
+
+    Some(ThreadInfo {
+        tid,
+        name: Some(name),
+        state,
+        cpu_time,
+    })
+}
+
+/// Parses the processor a thread last ran on from its stat file
+fn parse_last_cpu(stat: &[u8]) -> Option<usize> {
+    let stat = String::from_utf8_lossy(stat);
+    let end = stat.rfind(')')?;
+    // the processor is field 39 of the file
+    stat[end + 1..].split_whitespace().nth(36)?.parse().ok()
+}
+
+/// Parses the run time and wait time in nanoseconds, and the number of timeslices, from a
+/// schedstat file
+fn parse_schedstat(schedstat: &str) -> Option<SchedStats> {
+    let mut fields = schedstat
+        .split_whitespace()
+        .map(|field| field.parse::<u64>());
+    Some(SchedStats {
+        run_time: Duration::from_nanos(fields.next()?.ok()?),
+        wait_time: Duration::from_nanos(fields.next()?.ok()?),
+        timeslices: fields.next()?.ok()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_stat() {
+        let stat = b"1234 (my (thread)) S 1 1234 1234 0 -1 4194368 164 0 0 0 250 50 0 0 20 0 \
+                     1 0 5010 10854400 524 18446744073709551615 1 1 0 0 0 0 0 4096 1260 0 0 0 \
+                     17 3 0 0 0 0 0";
+        let info = parse_stat(1234, stat).unwrap();
+        assert_eq!(info.tid, 1234);
+        assert_eq!(info.name.as_deref(), Some("my (thread)"));
+        assert_eq!(info.state, ThreadState::Sleeping);
+        let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) } as u64;
+        assert_eq!(
+            info.cpu_time,
+            Duration::from_nanos(300 * 1_000_000_000 / ticks)
+        );
+
+        assert_eq!(parse_last_cpu(stat), Some(3));
+
+        assert!(parse_stat(1234, b"1234 (truncated").is_none());
+        assert!(parse_last_cpu(b"1234 (truncated) S 1").is_none());
+    }
+
+    #[test]
+    fn test_parse_schedstat() {
+        let stats = parse_schedstat("68297310 2406812 457\n").unwrap();
+        assert_eq!(stats.run_time, Duration::from_nanos(68297310));
+        assert_eq!(stats.wait_time, Duration::from_nanos(2406812));
+        assert_eq!(stats.timeslices, 457);
+
+        assert!(parse_schedstat("68297310 2406812").is_none());
+        assert!(parse_schedstat("garbage 1 2").is_none());
+    }
+
+    #[test]
+    fn test_threads_iter() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let (done_sender, done_receiver) = std::sync::mpsc::channel::<()>();
+        let thread = std::thread::Builder::new()
+            .name("iter-test".to_owned())
+            .spawn(move || {
+                sender.send(unsafe { libc::gettid() }).unwrap();
+                done_receiver.recv().unwrap();
+            })
+            .unwrap();
+        let tid = receiver.recv().unwrap();
+
+        let process = Process::new(std::process::id() as i32).unwrap();
+        let threads = process
+            .threads_iter()
+            .unwrap()
+            .collect::<Result<Vec<_>, Error>>()
+            .unwrap();
+        let info = threads.iter().find(|info| info.tid == tid).unwrap();
+        assert_eq!(info.name.as_deref(), Some("iter-test"));
+        assert!(threads
+            .iter()
+            .any(|info| info.tid == std::process::id() as i32));
+
+        done_sender.send(()).unwrap();
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn test_affinity() {
+        let thread = Thread::new(unsafe { libc::gettid() }).unwrap();
+        let cpus = sched_getaffinity(nix::unistd::Pid::from_raw(0)).unwrap();
+        let affinity = thread.affinity().unwrap();
+        assert!(!affinity.is_empty());
+        assert!(affinity.iter().all(|cpu| cpus.is_set(*cpu).unwrap()));
+
+        let ncpus = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_CONF) } as usize;
+        assert!(thread.last_cpu().unwrap().unwrap() < ncpus);
+    }
+}
@@ -0,0 +1,74 @@
+//! Reads the thread pointer of a thread, which its thread local storage is addressed from
+#[cfg(target_arch = "aarch64")]
+use super::regset::get_regset;
+use super::Thread;
+use crate::{Error, ProcessMemory};
+
+impl Thread {
+    /// Returns the thread pointer of this thread: fs_base on x86_64, tpidr_el0 on aarch64 and
+    /// tp on riscv64. Static TLS variables are at fixed offsets from this, below it on x86_64
+    /// and above it on aarch64 and riscv64. The thread needs to be locked for this to succeed.
+    ///
+    /// 32-bit processes on x86_64 address TLS through a gs segment instead, so fs_base is 0
+    /// for them.
+    #[cfg(target_arch = "x86_64")]
+    pub fn tls_base(&self) -> Result<u64, Error> {
+        Ok(nix::sys::ptrace::getregs(self.tid)?.fs_base)
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    pub fn tls_base(&self) -> Result<u64, Error> {
+        const NT_ARM_TLS: libc::c_int = 0x401;
+        get_regset(self.tid, NT_ARM_TLS)
+    }
+
+    #[cfg(target_arch = "riscv64")]
+    pub fn tls_base(&self) -> Result<u64, Error> {
+        Ok(self.registers()?.tp)
+    }
+
+    /// Reads a value at an offset from the thread pointer of this thread, like a static TLS
+    /// variable or a field of the thread control block. The thread needs to be locked.
+    #[cfg(any(
+        target_arch = "x86_64",
+        target_arch = "aarch64",
+        target_arch = "riscv64"
+    ))]
+    pub fn read_tls<T: Copy, P: ProcessMemory>(
+        &self,
+        process: &P,
+        offset: i64,
+    ) -> Result<T, Error> {
+        let addr = self.tls_base()?.wrapping_add(offset as u64);
+        process.copy_struct(addr as usize)
+    }
+}
+
+#[cfg(all(test, target_arch = "x86_64"))]
+mod tests {
+    use super::*;
+    use crate::Process;
+
+    #[test]
+    fn test_tls_base() {
+        let mut child = std::process::Command::new("sleep")
+            .arg("10")
+            .spawn()
+            .unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(100));
+
+        let process = Process::new(child.id() as i32).unwrap();
+        let thread = Thread::new(child.id() as i32).unwrap();
+        {
+            let _lock = thread.lock().unwrap();
+            let base = thread.tls_base().unwrap();
+            assert_ne!(base, 0);
+            // the first field of glibc and musl's thread control block points to itself
+            let tcb: u64 = thread.read_tls(&process, 0).unwrap();
+            assert_eq!(tcb, base);
+        }
+
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+}
@@ -0,0 +1,385 @@
+//! Caches the unwind information parsed out of each binary, keyed by its build-id.
+//!
+//! Every process that maps the same library (or a process that is restarted) can then share
+//! the parsed tables instead of opening and copying the sections out of the binary again.
+//! Recently used entries are kept in memory, and can optionally be written to a directory
+//! on disk so that they survive between runs.
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use addr2line::gimli::{self, BaseAddresses, EhFrame, EhFrameHdr, NativeEndian, UnwindSection};
+use lazy_static::lazy_static;
+use log::{debug, warn};
+use object::{Object, ObjectSection, ObjectSegment};
+
+use super::dwarf::Reader;
+
+const DEFAULT_CAPACITY: usize = 256;
+const MAGIC: &[u8; 8] = b"RPUNWND1";
+
+lazy_static! {
+    static ref CACHE: Mutex<UnwindCache> = Mutex::new(UnwindCache::new(DEFAULT_CAPACITY));
+}
+
+/// A loadable segment of a binary, used to compute the load bias of each mapping
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Segment {
+    file_offset: u64,
+    file_size: u64,
+    address: u64,
+}
+
+/// The unwind tables from a single binary. These don't depend on where the binary is
+/// loaded, so can be shared between processes.
+#[derive(Debug, PartialEq, Eq)]
+pub struct UnwindInfo {
+    pub eh_frame: Vec<u8>,
+    pub eh_frame_address: u64,
+    /// sorted lookup table for the FDEs in .eh_frame, empty if the binary doesn't have one
+    pub eh_frame_hdr: Vec<u8>,
+    pub eh_frame_hdr_address: u64,
+    pub text_address: u64,
+    segments: Vec<Segment>,
+}
+
+impl UnwindInfo {
+    /// Copies the unwind tables out of a binary, returning None if it doesn't have any
+    pub fn from_object(object: &object::File) -> Result<Option<UnwindInfo>, object::Error> {
+        let eh_frame = match object.section_by_name(".eh_frame") {
+            Some(section) => section,
+            None => return Ok(None),
+        };
+        let (eh_frame_hdr, eh_frame_hdr_address) = match object.section_by_name(".eh_frame_hdr") {
+            Some(section) => (section.data()?.to_vec(), section.address()),
+            None => (Vec::new(), 0),
+        };
+        let text_address = object
+            .section_by_name(".text")
+            .map(|s| s.address())
+            .unwrap_or(0);
+        let segments = object
+            .segments()
+            .map(|s| {
+                let (file_offset, file_size) = s.file_range();
+                Segment {
+                    file_offset,
+                    file_size,
+                    address: s.address(),
+                }
+            })
+            .collect();
+
+        Ok(Some(UnwindInfo {
+            eh_frame: eh_frame.data()?.to_vec(),
+            eh_frame_address: eh_frame.address(),
+            eh_frame_hdr,
+            eh_frame_hdr_address,
+            text_address,
+            segments,
+        }))
+    }
+
+    /// Returns the difference between addresses in the binary and where they are loaded in
+    /// memory, for a mapping of the binary at `offset` in the file that starts at `start`
+    pub fn bias(&self, start: u64, offset: u64) -> Option<u64> {
+        let segment = self
+            .segments
+            .iter()
+            .find(|s| s.file_offset <= offset && offset < s.file_offset + s.file_size)?;
+        Some(
+            start
+                .wrapping_sub(offset)
+                .wrapping_sub(segment.address.wrapping_sub(segment.file_offset)),
+        )
+    }
+
+    pub fn bases(&self) -> BaseAddresses {
+        BaseAddresses::default()
+            .set_eh_frame(self.eh_frame_address)
+            .set_eh_frame_hdr(self.eh_frame_hdr_address)
+            .set_text(self.text_address)
+    }
+
+    /// Finds the FDE covering an address. This binary searches the table in .eh_frame_hdr
+    /// when there is one, and only falls back to scanning every entry in .eh_frame otherwise
+    pub fn fde<'a>(
+        &'a self,
+        eh_frame: &EhFrame<Reader<'a>>,
+        bases: &BaseAddresses,
+        address: u64,
+    ) -> gimli::Result<gimli::FrameDescriptionEntry<Reader<'a>>> {
+        if !self.eh_frame_hdr.is_empty() {
+            let address_size = std::mem::size_of::<usize>() as u8;
+            let hdr =
+                EhFrameHdr::new(&self.eh_frame_hdr, NativeEndian).parse(bases, address_size)?;
+            if let Some(table) = hdr.table() {
+                return table.fde_for_address(eh_frame, bases, address, EhFrame::cie_from_offset);
+            }
+        }
+        eh_frame.fde_for_address(bases, address, EhFrame::cie_from_offset)
+    }
+
+    fn write<W: Write>(&self, mut w: W) -> std::io::Result<()> {
+        w.write_all(MAGIC)?;
+        for value in [
+            self.eh_frame_address,
+            self.eh_frame_hdr_address,
+            self.text_address,
+            self.segments.len() as u64,
+        ] {
+            w.write_all(&value.to_le_bytes())?;
+        }
+        for segment in &self.segments {
+            w.write_all(&segment.file_offset.to_le_bytes())?;
+            w.write_all(&segment.file_size.to_le_bytes())?;
+            w.write_all(&segment.address.to_le_bytes())?;
+        }
+        for data in [&self.eh_frame, &self.eh_frame_hdr] {
+            w.write_all(&(data.len() as u64).to_le_bytes())?;
+            w.write_all(data)?;
+        }
+        Ok(())
+    }
+
+    fn read<R: Read>(mut r: R) -> std::io::Result<UnwindInfo> {
+        let mut magic = [0_u8; 8];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "not an unwind cache file",
+            ));
+        }
+        let read_u64 = |r: &mut R| -> std::io::Result<u64> {
+            let mut bytes = [0_u8; 8];
+            r.read_exact(&mut bytes)?;
+            Ok(u64::from_le_bytes(bytes))
+        };
+        let eh_frame_address = read_u64(&mut r)?;
+        let eh_frame_hdr_address = read_u64(&mut r)?;
+        let text_address = read_u64(&mut r)?;
+        let segment_count = read_u64(&mut r)?;
+        let mut segments = Vec::new();
+        for _ in 0..segment_count {
+            segments.push(Segment {
+                file_offset: read_u64(&mut r)?,
+                file_size: read_u64(&mut r)?,
+                address: read_u64(&mut r)?,
+            });
+        }
+        let read_data = |r: &mut R| -> std::io::Result<Vec<u8>> {
+            let len = read_u64(r)?;
+            let mut data = Vec::new();
+            r.take(len).read_to_end(&mut data)?;
+            if data.len() as u64 != len {
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
+            Ok(data)
+        };
+        let eh_frame = read_data(&mut r)?;
+        let eh_frame_hdr = read_data(&mut r)?;
+        Ok(UnwindInfo {
+            eh_frame,
+            eh_frame_address,
+            eh_frame_hdr,
+            eh_frame_hdr_address,
+            text_address,
+            segments,
+        })
+    }
+}
+
+struct Entry {
+    info: Arc<UnwindInfo>,
+    last_used: u64,
+}
+
+/// A least recently used cache of unwind info, with an optional on-disk backing directory
+struct UnwindCache {
+    entries: HashMap<Vec<u8>, Entry>,
+    capacity: usize,
+    clock: u64,
+    directory: Option<PathBuf>,
+}
+
+impl UnwindCache {
+    fn new(capacity: usize) -> UnwindCache {
+        UnwindCache {
+            entries: HashMap::new(),
+            capacity,
+            clock: 0,
+            directory: None,
+        }
+    }
+
+    fn get(&mut self, build_id: &[u8]) -> Option<Arc<UnwindInfo>> {
+        self.clock += 1;
+        if let Some(entry) = self.entries.get_mut(build_id) {
+            entry.last_used = self.clock;
+            return Some(Arc::clone(&entry.info));
+        }
+
+        let path = self.path(build_id)?;
+        let file = std::fs::File::open(&path).ok()?;
+        match UnwindInfo::read(std::io::BufReader::new(file)) {
+            Ok(info) => {
+                debug!("loaded unwind info from {}", path.display());
+                let info = Arc::new(info);
+                self.insert_entry(build_id, Arc::clone(&info));
+                Some(info)
+            }
+            Err(e) => {
+                warn!("failed to read unwind cache {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    fn insert(&mut self, build_id: &[u8], info: Arc<UnwindInfo>) {
+        if let Some(path) = self.path(build_id) {
+            if let Err(e) = write_file(&path, &info) {
+                warn!("failed to write unwind cache {}: {}", path.display(), e);
+            }
+        }
+        self.insert_entry(build_id, info);
+    }
+
+    fn insert_entry(&mut self, build_id: &[u8], info: Arc<UnwindInfo>) {
+        self.clock += 1;
+        self.entries.insert(
+            build_id.to_vec(),
+            Entry {
+                info,
+                last_used: self.clock,
+            },
+        );
+        self.evict();
+    }
+
+    fn evict(&mut self) {
+        while self.entries.len() > self.capacity {
+            let oldest = match self.entries.iter().min_by_key(|(_, e)| e.last_used) {
+                Some((key, _)) => key.clone(),
+                None => return,
+            };
+            self.entries.remove(&oldest);
+        }
+    }
+
+    fn path(&self, build_id: &[u8]) -> Option<PathBuf> {
+        let directory = self.directory.as_ref()?;
+        let name: String = build_id.iter().map(|b| format!("{:02x}", b)).collect();
+        Some(directory.join(format!("{}.unwind", name)))
+    }
+}
+
+/// Writes to a temporary file first, so that concurrent readers never see a partial file
+fn write_file(path: &Path, info: &UnwindInfo) -> std::io::Result<()> {
+    let temp = path.with_extension(format!("tmp{}", std::process::id()));
+    let mut file = std::io::BufWriter::new(std::fs::File::create(&temp)?);
+    info.write(&mut file)?;
+    file.flush()?;
+    drop(file);
+    std::fs::rename(&temp, path)
+}
+
+/// Returns the cached unwind info for a binary with the given build-id
+pub fn get(build_id: &[u8]) -> Option<Arc<UnwindInfo>> {
+    CACHE.lock().unwrap().get(build_id)
+}
+
+/// Adds the unwind info for a binary to the cache
+pub fn insert(build_id: &[u8], info: Arc<UnwindInfo>) {
+    CACHE.lock().unwrap().insert(build_id, info)
+}
+
+/// Sets the maximum number of binaries to keep unwind info for in memory
+pub fn set_unwind_cache_capacity(capacity: usize) {
+    let mut cache = CACHE.lock().unwrap();
+    cache.capacity = capacity;
+    cache.evict();
+}
+
+/// Sets a directory to persist unwind info in, so that it can be reused across runs.
+/// Passing None disables the on-disk cache.
+pub fn set_unwind_cache_directory(directory: Option<PathBuf>) -> std::io::Result<()> {
+    if let Some(directory) = &directory {
+        std::fs::create_dir_all(directory)?;
+    }
+    CACHE.lock().unwrap().directory = directory;
+    Ok(())
+}
+
+/// Removes every entry from the in-memory cache
+pub fn clear_unwind_cache() {
+    CACHE.lock().unwrap().entries.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(eh_frame: &[u8]) -> UnwindInfo {
+        UnwindInfo {
+            eh_frame: eh_frame.to_vec(),
+            eh_frame_address: 0x2000,
+            eh_frame_hdr: vec![1, 2, 3],
+            eh_frame_hdr_address: 0x1f00,
+            text_address: 0x1000,
+            segments: vec![Segment {
+                file_offset: 0x1000,
+                file_size: 0x4000,
+                address: 0x401000,
+            }],
+        }
+    }
+
+    #[test]
+    fn test_serialize() {
+        let info = info(&[4, 5, 6, 7]);
+        let mut buffer = Vec::new();
+        info.write(&mut buffer).unwrap();
+        assert_eq!(UnwindInfo::read(buffer.as_slice()).unwrap(), info);
+
+        buffer.truncate(buffer.len() - 1);
+        assert!(UnwindInfo::read(buffer.as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_bias() {
+        let info = info(&[]);
+        assert_eq!(info.bias(0x7f0000001000, 0x1000), Some(0x7effffc00000));
+        assert_eq!(info.bias(0x7f0000001000, 0x8000), None);
+    }
+
+    #[test]
+    fn test_lru() {
+        let mut cache = UnwindCache::new(2);
+        cache.insert(b"a", Arc::new(info(&[1])));
+        cache.insert(b"b", Arc::new(info(&[2])));
+        assert!(cache.get(b"a").is_some());
+        cache.insert(b"c", Arc::new(info(&[3])));
+        // b was the least recently used
+        assert!(cache.get(b"b").is_none());
+        assert!(cache.get(b"a").is_some());
+        assert!(cache.get(b"c").is_some());
+    }
+
+    #[test]
+    fn test_directory() {
+        let directory = std::env::temp_dir().join(format!("unwind-cache-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+
+        let mut cache = UnwindCache::new(1);
+        cache.directory = Some(directory.clone());
+        cache.insert(b"\x12\x34", Arc::new(info(&[1])));
+        cache.insert(b"\x56", Arc::new(info(&[2])));
+        assert!(directory.join("1234.unwind").exists());
+
+        // evicted from memory, but still available on disk
+        assert_eq!(cache.get(b"\x12\x34").unwrap().eh_frame, vec![1]);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
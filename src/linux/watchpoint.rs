@@ -0,0 +1,554 @@
+//! Hardware watchpoints, set through the debug registers of a thread.
+//!
+//! A watchpoint being hit raises a SIGTRAP in the thread, which would kill it if nothing
+//! was tracing it - so a thread stays attached with ptrace (but running) for as long as any
+//! watchpoints are set on it. ptrace only lets the thread that attached wait for the tracee,
+//! so watchpoints can't be moved to another thread than the one that set them.
+use std::cell::RefCell;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
+use std::marker::PhantomData;
+use std::time::{Duration, Instant};
+
+use log::{debug, info, warn};
+use nix::sys::ptrace;
+use nix::sys::signal::Signal;
+use nix::sys::wait::{self, WaitPidFlag, WaitStatus};
+
+use super::Thread;
+use crate::Error;
+
+/// Which accesses to a watched address trigger a watchpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    /// Writes to the address
+    Write,
+    /// Reads or writes of the address. x86 doesn't support watching reads alone.
+    ReadWrite,
+    /// Executing the instruction at the address
+    Execute,
+}
+
+/// A watchpoint being triggered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchpointHit {
+    /// The address the watchpoint was set on
+    pub addr: u64,
+    /// The instruction pointer of the thread when it stopped. For data watchpoints this is
+    /// the instruction after the one that accessed the address.
+    pub ip: u64,
+}
+
+/// A hardware watchpoint set by `Thread::set_watchpoint`, which is removed when dropped
+pub struct Watchpoint {
+    tid: nix::unistd::Pid,
+    slot: usize,
+    /// The address being watched
+    pub addr: u64,
+    // ptrace requests have to come from the thread that attached
+    _not_send: PhantomData<*const ()>,
+}
+
+/// A thread that's attached to so that watchpoints can be set on it
+#[derive(Default)]
+struct Traced {
+    /// the address watched by each debug register, if it's in use
+    slots: Vec<Option<u64>>,
+    /// hits that were seen while waiting for something else
+    pending: VecDeque<WatchpointHit>,
+}
+
+thread_local! {
+    static TRACED: RefCell<HashMap<nix::unistd::Pid, Traced>> = RefCell::new(HashMap::new());
+}
+
+impl Thread {
+    /// Sets a hardware watchpoint on `len` bytes at `addr`, which needs to be aligned to
+    /// `len`. Supported lengths are 1, 2, 4 or 8 bytes, and execute watchpoints need a length
+    /// of 1. Hits are reported by `Watchpoint::wait`.
+    ///
+    /// The thread must not be locked, and stays attached with ptrace until all of its
+    /// watchpoints are dropped.
+    pub fn set_watchpoint(
+        &self,
+        addr: u64,
+        len: usize,
+        kind: WatchKind,
+    ) -> Result<Watchpoint, Error> {
+        if !matches!(len, 1 | 2 | 4 | 8) || !addr.is_multiple_of(len as u64) {
+            return Err(Error::Other(format!(
+                "invalid watchpoint length {} at {:#x}",
+                len, addr
+            )));
+        }
+        if kind == WatchKind::Execute && len != 1 {
+            return Err(Error::Other(
+                "execute watchpoints need a length of 1".to_owned(),
+            ));
+        }
+
+        let tid = self.tid;
+        TRACED.with(|traced| {
+            let mut traced = traced.borrow_mut();
+            let state = match traced.entry(tid) {
+                Entry::Occupied(entry) => {
+                    let state = entry.into_mut();
+                    stop(tid, state)?;
+                    state
+                }
+                Entry::Vacant(entry) => {
+                    attach(tid)?;
+                    let slots = match arch::slot_count(tid) {
+                        Ok(slots) => slots,
+                        Err(e) => {
+                            detach(tid, &mut Traced::default());
+                            return Err(e);
+                        }
+                    };
+                    entry.insert(Traced {
+                        slots: vec![None; slots],
+                        pending: VecDeque::new(),
+                    })
+                }
+            };
+
+            let result = arch::slots(tid, kind).and_then(|mut slots| {
+                match slots.find(|slot| state.slots[*slot].is_none()) {
+                    Some(slot) => arch::set(tid, slot, addr, len, kind).map(|_| slot),
+                    None => Err(Error::Other(format!(
+                        "all debug registers of thread {} are in use",
+                        tid
+                    ))),
+                }
+            });
+            match result {
+                Ok(slot) => {
+                    state.slots[slot] = Some(addr);
+                    ptrace::cont(tid, None)?;
+                    info!("set watchpoint {} on {:#x} in thread {}", slot, addr, tid);
+                    Ok(Watchpoint {
+                        tid,
+                        slot,
+                        addr,
+                        _not_send: PhantomData,
+                    })
+                }
+                Err(e) => {
+                    if state.slots.iter().all(Option::is_none) {
+                        detach(tid, state);
+                        traced.remove(&tid);
+                    } else {
+                        ptrace::cont(tid, None)?;
+                    }
+                    Err(e)
+                }
+            }
+        })
+    }
+}
+
+impl Watchpoint {
+    /// Waits up to `timeout` for any watchpoint on this thread to be hit, returning None if
+    /// none were. The thread carries on running after each hit.
+    pub fn wait(&self, timeout: Duration) -> Result<Option<WatchpointHit>, Error> {
+        let start = Instant::now();
+        TRACED.with(|traced| {
+            let mut traced = traced.borrow_mut();
+            let state = traced
+                .get_mut(&self.tid)
+                .ok_or_else(|| Error::Other("watchpoint thread isn't attached".to_owned()))?;
+            loop {
+                if let Some(hit) = state.pending.pop_front() {
+                    return Ok(Some(hit));
+                }
+                let flags = WaitPidFlag::__WALL | WaitPidFlag::WNOHANG;
+                match wait::waitpid(self.tid, Some(flags))? {
+                    WaitStatus::StillAlive => {
+                        if start.elapsed() >= timeout {
+                            return Ok(None);
+                        }
+                        std::thread::sleep(Duration::from_millis(1));
+                    }
+                    status => handle_status(self.tid, state, status)?,
+                }
+            }
+        })
+    }
+}
+
+impl Drop for Watchpoint {
+    fn drop(&mut self) {
+        TRACED.with(|traced| {
+            let mut traced = traced.borrow_mut();
+            let state = match traced.get_mut(&self.tid) {
+                Some(state) => state,
+                None => return,
+            };
+            // stop the thread first, so that a hit arriving in the meantime is still ours
+            if let Err(e) = stop(self.tid, state) {
+                warn!("failed to remove watchpoint from {}: {}", self.tid, e);
+                return;
+            }
+            if state.slots.iter().filter(|slot| slot.is_some()).count() == 1 {
+                detach(self.tid, state);
+                traced.remove(&self.tid);
+                return;
+            }
+            state.slots[self.slot] = None;
+            let result =
+                arch::clear(self.tid, self.slot).and_then(|_| Ok(ptrace::cont(self.tid, None)?));
+            if let Err(e) = result {
+                warn!("failed to remove watchpoint from {}: {}", self.tid, e);
+            }
+        })
+    }
+}
+
+/// Attaches to a thread, leaving it stopped
+fn attach(tid: nix::unistd::Pid) -> Result<(), Error> {
+    ptrace::seize(tid, ptrace::Options::PTRACE_O_TRACEEXIT)?;
+    if let Err(e) = stop(tid, &mut Traced::default()) {
+        if let Err(e) = ptrace::detach(tid, None) {
+            warn!("failed to detach from thread {} for cleanup: {}", tid, e);
+        }
+        return Err(e);
+    }
+    debug!("attached to thread {} for watchpoints", tid);
+    Ok(())
+}
+
+/// Clears the watchpoints from a stopped thread and detaches from it, which lets it carry on
+/// running
+fn detach(tid: nix::unistd::Pid, state: &mut Traced) {
+    for (slot, _) in state
+        .slots
+        .iter()
+        .enumerate()
+        .filter(|(_, addr)| addr.is_some())
+    {
+        if let Err(e) = arch::clear(tid, slot) {
+            warn!("failed to clear watchpoint {} from {}: {}", slot, tid, e);
+        }
+    }
+    // a watchpoint can trigger while the thread is being stopped, leaving a SIGTRAP pending
+    // that would kill the thread once nothing is tracing it. Let the thread take those
+    // first, while we can still swallow them.
+    while trap_pending(tid) {
+        if let Err(e) = ptrace::cont(tid, None)
+            .map_err(Error::from)
+            .and_then(|_| stop(tid, state))
+        {
+            warn!("failed to flush pending traps for thread {}: {}", tid, e);
+            break;
+        }
+    }
+    match ptrace::detach(tid, None) {
+        Ok(()) => debug!("detached from thread {}", tid),
+        Err(e) => warn!("failed to detach from thread {}: {}", tid, e),
+    }
+}
+
+/// True if a SIGTRAP is waiting to be delivered to a thread
+pub(super) fn trap_pending(tid: nix::unistd::Pid) -> bool {
+    let status = match std::fs::read_to_string(format!("/proc/{}/status", tid)) {
+        Ok(status) => status,
+        Err(_) => return false,
+    };
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("SigPnd:"))
+        .and_then(|mask| u64::from_str_radix(mask.trim(), 16).ok())
+        .map(|mask| mask & (1 << (Signal::SIGTRAP as i32 - 1)) != 0)
+        .unwrap_or(false)
+}
+
+/// Interrupts a running thread and waits for it to stop, handling any watchpoint hits and
+/// signals that arrive first
+fn stop(tid: nix::unistd::Pid, state: &mut Traced) -> Result<(), Error> {
+    ptrace::interrupt(tid)?;
+    loop {
+        match wait::waitpid(tid, Some(WaitPidFlag::__WALL))? {
+            WaitStatus::PtraceEvent(_, _, event)
+                if event == ptrace::Event::PTRACE_EVENT_STOP as i32 =>
+            {
+                return Ok(())
+            }
+            status => handle_status(tid, state, status)?,
+        }
+    }
+}
+
+/// Handles the thread stopping for anything other than our interrupt, and resumes it
+fn handle_status(
+    tid: nix::unistd::Pid,
+    state: &mut Traced,
+    status: WaitStatus,
+) -> Result<(), Error> {
+    match status {
+        WaitStatus::Stopped(_, Signal::SIGTRAP) => {
+            match arch::triggered(tid, &state.slots)? {
+                Some(addr) => {
+                    let ip = arch::ip(tid)?;
+                    debug!("watchpoint on {:#x} hit at {:#x} in {}", addr, ip, tid);
+                    state.pending.push_back(WatchpointHit { addr, ip });
+                    ptrace::cont(tid, None)?;
+                }
+                // a trap that isn't ours, like a software breakpoint
+                None => ptrace::cont(tid, Signal::SIGTRAP)?,
+            }
+        }
+        WaitStatus::Stopped(_, signal) => ptrace::cont(tid, signal)?,
+        // a group stop, or the thread exiting
+        WaitStatus::PtraceEvent(_, _, event) => {
+            ptrace::cont(tid, None)?;
+            if event == ptrace::Event::PTRACE_EVENT_EXIT as i32 {
+                return Err(Error::Other(format!("thread {} is exiting", tid)));
+            }
+        }
+        WaitStatus::Exited(..) | WaitStatus::Signaled(..) => {
+            return Err(Error::Other(format!("thread {} has exited", tid)));
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+#[cfg(target_arch = "x86_64")]
+mod arch {
+    use std::ops::Range;
+
+    use super::WatchKind;
+    use crate::Error;
+    use nix::sys::ptrace;
+
+    const DEBUG_REGISTERS: usize = std::mem::offset_of!(libc::user, u_debugreg);
+
+    fn read(tid: nix::unistd::Pid, register: usize) -> Result<u64, Error> {
+        let offset = DEBUG_REGISTERS + register * 8;
+        Ok(ptrace::read_user(tid, offset as ptrace::AddressType)? as u64)
+    }
+
+    fn write(tid: nix::unistd::Pid, register: usize, value: u64) -> Result<(), Error> {
+        let offset = DEBUG_REGISTERS + register * 8;
+        Ok(ptrace::write_user(
+            tid,
+            offset as ptrace::AddressType,
+            value as libc::c_long,
+        )?)
+    }
+
+    pub fn slot_count(_tid: nix::unistd::Pid) -> Result<usize, Error> {
+        Ok(4)
+    }
+
+    /// Returns the slots that can hold a watchpoint of a kind
+    pub fn slots(_tid: nix::unistd::Pid, _kind: WatchKind) -> Result<Range<usize>, Error> {
+        Ok(0..4)
+    }
+
+    /// Returns the DR7 bits that enable a watchpoint in a slot
+    pub fn control(slot: usize, len: usize, kind: WatchKind) -> u64 {
+        let condition = match kind {
+            WatchKind::Execute => 0b00,
+            WatchKind::Write => 0b01,
+            WatchKind::ReadWrite => 0b11,
+        };
+        let size = match len {
+            1 => 0b00,
+            2 => 0b01,
+            8 => 0b10,
+            _ => 0b11,
+        };
+        (1 << (slot * 2)) | (condition << (16 + slot * 4)) | (size << (18 + slot * 4))
+    }
+
+    /// The DR7 bits used by a slot
+    fn mask(slot: usize) -> u64 {
+        (0b11 << (slot * 2)) | (0b1111 << (16 + slot * 4))
+    }
+
+    pub fn set(
+        tid: nix::unistd::Pid,
+        slot: usize,
+        addr: u64,
+        len: usize,
+        kind: WatchKind,
+    ) -> Result<(), Error> {
+        write(tid, slot, addr)?;
+        let dr7 = read(tid, 7)? & !mask(slot);
+        write(tid, 7, dr7 | control(slot, len, kind))
+    }
+
+    pub fn clear(tid: nix::unistd::Pid, slot: usize) -> Result<(), Error> {
+        let dr7 = read(tid, 7)? & !mask(slot);
+        write(tid, 7, dr7)?;
+        write(tid, slot, 0)
+    }
+
+    /// Returns the address of the watchpoint that stopped the thread, from the status bits
+    /// in DR6
+    pub fn triggered(tid: nix::unistd::Pid, slots: &[Option<u64>]) -> Result<Option<u64>, Error> {
+        let dr6 = read(tid, 6)?;
+        let addr = slots
+            .iter()
+            .enumerate()
+            .find(|(slot, addr)| addr.is_some() && dr6 & (1 << slot) != 0)
+            .and_then(|(_, addr)| *addr);
+        if addr.is_some() {
+            // the status bits are sticky, so need clearing before the next hit
+            write(tid, 6, dr6 & !0b1111)?;
+        }
+        Ok(addr)
+    }
+
+    pub fn ip(tid: nix::unistd::Pid) -> Result<u64, Error> {
+        Ok(ptrace::getregs(tid)?.rip)
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod arch {
+    use std::ops::Range;
+
+    use super::WatchKind;
+    use crate::linux::regset::{get_regset, read_regset, write_regset};
+    use crate::Error;
+
+    const NT_ARM_HW_BREAK: libc::c_int = 0x402;
+    const NT_ARM_HW_WATCH: libc::c_int = 0x403;
+
+    // struct user_hwdebug_state from asm/ptrace.h
+    #[repr(C)]
+    #[derive(Clone, Copy, Default)]
+    struct DebugState {
+        dbg_info: u32,
+        pad: u32,
+        regs: [DebugRegister; 16],
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy, Default)]
+    struct DebugRegister {
+        addr: u64,
+        ctrl: u32,
+        pad: u32,
+    }
+
+    fn get(tid: nix::unistd::Pid, regset: libc::c_int) -> Result<DebugState, Error> {
+        get_regset(tid, regset)
+    }
+
+    fn put(tid: nix::unistd::Pid, regset: libc::c_int, state: &DebugState) -> Result<(), Error> {
+        let count = ((state.dbg_info & 0xff) as usize).min(state.regs.len());
+        // the kernel expects the header followed by just the registers that exist
+        let len = 8 + count * std::mem::size_of::<DebugRegister>();
+        let bytes = unsafe { std::slice::from_raw_parts(state as *const _ as *const u8, len) };
+        write_regset(tid, regset, bytes)
+    }
+
+    /// The watchpoint and breakpoint registers are separate, but are handed out from a
+    /// single list of slots: the first slots are watchpoints, and the rest breakpoints
+    pub fn slot_count(tid: nix::unistd::Pid) -> Result<usize, Error> {
+        let watch = get(tid, NT_ARM_HW_WATCH)?.dbg_info & 0xff;
+        let breaks = get(tid, NT_ARM_HW_BREAK)?.dbg_info & 0xff;
+        Ok((watch + breaks) as usize)
+    }
+
+    /// Returns the slots that can hold a watchpoint of a kind
+    pub fn slots(tid: nix::unistd::Pid, kind: WatchKind) -> Result<Range<usize>, Error> {
+        let watch = (get(tid, NT_ARM_HW_WATCH)?.dbg_info & 0xff) as usize;
+        Ok(match kind {
+            WatchKind::Execute => watch..slot_count(tid)?,
+            _ => 0..watch,
+        })
+    }
+
+    /// Returns the regset and register index for a slot
+    fn register(tid: nix::unistd::Pid, slot: usize) -> Result<(libc::c_int, usize), Error> {
+        let watch = (get(tid, NT_ARM_HW_WATCH)?.dbg_info & 0xff) as usize;
+        Ok(if slot < watch {
+            (NT_ARM_HW_WATCH, slot)
+        } else {
+            (NT_ARM_HW_BREAK, slot - watch)
+        })
+    }
+
+    /// Returns the control register value enabling a watchpoint: the byte address select
+    /// bits for the watched bytes within an 8 byte aligned doubleword, the load/store
+    /// bits, EL0 privilege and the enable bit
+    pub fn control(addr: u64, len: usize, kind: WatchKind) -> u32 {
+        let access = match kind {
+            WatchKind::Execute => 0b00,
+            WatchKind::Write => 0b10,
+            WatchKind::ReadWrite => 0b11,
+        };
+        let bytes = match kind {
+            // breakpoints cover a 4 byte instruction
+            WatchKind::Execute => 0b1111,
+            _ => ((1_u32 << len) - 1) << (addr % 8),
+        };
+        (bytes << 5) | (access << 3) | (0b10 << 1) | 1
+    }
+
+    pub fn set(
+        tid: nix::unistd::Pid,
+        slot: usize,
+        addr: u64,
+        len: usize,
+        kind: WatchKind,
+    ) -> Result<(), Error> {
+        let (regset, index) = register(tid, slot)?;
+        let mut state = get(tid, regset)?;
+        state.regs[index] = DebugRegister {
+            addr: addr & !7,
+            ctrl: control(addr, len, kind),
+            pad: 0,
+        };
+        put(tid, regset, &state)
+    }
+
+    pub fn clear(tid: nix::unistd::Pid, slot: usize) -> Result<(), Error> {
+        let (regset, index) = register(tid, slot)?;
+        let mut state = get(tid, regset)?;
+        state.regs[index] = DebugRegister::default();
+        put(tid, regset, &state)
+    }
+
+    /// Returns the address of the watchpoint that stopped the thread, from the address
+    /// reported in the signal info
+    pub fn triggered(tid: nix::unistd::Pid, slots: &[Option<u64>]) -> Result<Option<u64>, Error> {
+        let info = nix::sys::ptrace::getsiginfo(tid)?;
+        // TRAP_HWBKPT
+        if info.si_code != 4 {
+            return Ok(None);
+        }
+        let fault = unsafe { info.si_addr() } as u64;
+        Ok(slots
+            .iter()
+            .flatten()
+            .copied()
+            .find(|addr| fault & !7 == addr & !7))
+    }
+
+    pub fn ip(tid: nix::unistd::Pid) -> Result<u64, Error> {
+        // user_pt_regs: x0-x30, sp, pc, pstate
+        let mut regs = [0_u64; 34];
+        read_regset(tid, libc::NT_PRSTATUS, &mut regs)?;
+        Ok(regs[32])
+    }
+}
+
+#[cfg(all(test, target_arch = "x86_64"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_control() {
+        // slot 0, 4 byte write watchpoint
+        assert_eq!(arch::control(0, 4, WatchKind::Write), 0x000d_0001);
+        // slot 1, 8 byte read/write watchpoint
+        assert_eq!(arch::control(1, 8, WatchKind::ReadWrite), 0x00b0_0004);
+        // slot 3, execute breakpoint
+        assert_eq!(arch::control(3, 1, WatchKind::Execute), 0x0000_0040);
+    }
+}
@@ -0,0 +1,67 @@
+//! Allocates memory in a target task with mach_vm_allocate
+use mach::kern_return::KERN_SUCCESS;
+use mach::vm::{mach_vm_allocate, mach_vm_deallocate, mach_vm_protect};
+use mach::vm_prot::{vm_prot_t, VM_PROT_EXECUTE, VM_PROT_NONE, VM_PROT_READ, VM_PROT_WRITE};
+use mach::vm_statistics::VM_FLAGS_ANYWHERE;
+use mach::vm_types::mach_vm_address_t;
+
+use super::Process;
+use crate::{Error, Protection};
+
+impl Process {
+    /// Allocates `len` bytes of zeroed memory in the process, returning its address
+    pub fn alloc(&self, len: usize, prot: Protection) -> Result<u64, Error> {
+        let mut addr: mach_vm_address_t = 0;
+        let result =
+            unsafe { mach_vm_allocate(self.task, &mut addr, len as u64, VM_FLAGS_ANYWHERE) };
+        if result != KERN_SUCCESS {
+            return Err(Error::Other(format!("mach_vm_allocate failed: {}", result)));
+        }
+        // memory is allocated read/write, so anything else needs changing afterwards
+        let flags = protection(prot);
+        if flags != VM_PROT_READ | VM_PROT_WRITE {
+            let result = unsafe { mach_vm_protect(self.task, addr, len as u64, 0, flags) };
+            if result != KERN_SUCCESS {
+                unsafe { mach_vm_deallocate(self.task, addr, len as u64) };
+                return Err(Error::Other(format!("mach_vm_protect failed: {}", result)));
+            }
+        }
+        self.allocations.lock().unwrap().insert(addr, len);
+        Ok(addr)
+    }
+
+    /// Frees memory returned by `alloc` on this `Process`
+    pub fn free(&self, addr: u64) -> Result<(), Error> {
+        // neighbouring regions with the same protection are coalesced, so the length can't
+        // be found with mach_vm_region
+        let len = self
+            .allocations
+            .lock()
+            .unwrap()
+            .remove(&addr)
+            .ok_or_else(|| Error::Other(format!("no memory allocated at {:#x}", addr)))?;
+        let result = unsafe { mach_vm_deallocate(self.task, addr, len as u64) };
+        if result != KERN_SUCCESS {
+            self.allocations.lock().unwrap().insert(addr, len);
+            return Err(Error::Other(format!(
+                "mach_vm_deallocate failed: {}",
+                result
+            )));
+        }
+        Ok(())
+    }
+}
+
+fn protection(prot: Protection) -> vm_prot_t {
+    let mut flags = VM_PROT_NONE;
+    if prot.read {
+        flags |= VM_PROT_READ;
+    }
+    if prot.write {
+        flags |= VM_PROT_WRITE;
+    }
+    if prot.execute {
+        flags |= VM_PROT_EXECUTE;
+    }
+    flags
+}
@@ -0,0 +1,93 @@
+use libc::{c_int, c_uint, c_void, pid_t, size_t};
+
+// code signing flags from <kern/cs_blobs.h>
+const CS_OPS_STATUS: c_uint = 0;
+const CS_GET_TASK_ALLOW: u32 = 0x4;
+const CS_RESTRICT: u32 = 0x800;
+const CS_RUNTIME: u32 = 0x10000;
+const CS_PLATFORM_BINARY: u32 = 0x4000000;
+
+/// Why task_for_pid failed to give us access to a process
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum AttachFailure {
+    /// The process doesn't exist (or has already exited)
+    NoSuchProcess,
+    /// The target is a system binary protected by System Integrity Protection
+    SipProtected,
+    /// The target was built with the hardened runtime, and isn't signed with the
+    /// com.apple.security.get-task-allow entitlement
+    HardenedRuntime,
+    /// We aren't running as root
+    NotRoot,
+    /// We are root, but task_for_pid still failed - which generally means this binary is
+    /// missing the com.apple.security.cs.debugger entitlement or a valid code signature
+    MissingEntitlement,
+}
+
+impl std::fmt::Display for AttachFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            AttachFailure::NoSuchProcess => write!(f, "Failed to attach: no such process"),
+            AttachFailure::SipProtected => write!(
+                f,
+                "Failed to attach: the process is protected by System Integrity Protection"
+            ),
+            AttachFailure::HardenedRuntime => write!(
+                f,
+                "Failed to attach: the process uses the hardened runtime. Re-sign it with the \
+                 com.apple.security.get-task-allow entitlement to allow attaching"
+            ),
+            AttachFailure::NotRoot => {
+                write!(f, "Failed to attach: this program needs to be run as root")
+            }
+            AttachFailure::MissingEntitlement => write!(
+                f,
+                "Failed to attach: this program needs to be signed with the \
+                 com.apple.security.cs.debugger entitlement"
+            ),
+        }
+    }
+}
+
+extern "C" {
+    fn csops(pid: pid_t, ops: c_uint, useraddr: *mut c_void, usersize: size_t) -> c_int;
+}
+
+/// Works out why task_for_pid failed for a process
+pub fn classify_failure(pid: pid_t) -> AttachFailure {
+    if unsafe { libc::kill(pid, 0) } != 0
+        && std::io::Error::last_os_error().raw_os_error() == Some(libc::ESRCH)
+    {
+        return AttachFailure::NoSuchProcess;
+    }
+
+    if let Some(flags) = code_signing_flags(pid) {
+        if flags & (CS_PLATFORM_BINARY | CS_RESTRICT) != 0 {
+            return AttachFailure::SipProtected;
+        }
+        if flags & CS_RUNTIME != 0 && flags & CS_GET_TASK_ALLOW == 0 {
+            return AttachFailure::HardenedRuntime;
+        }
+    }
+
+    if unsafe { libc::geteuid() } != 0 {
+        return AttachFailure::NotRoot;
+    }
+    AttachFailure::MissingEntitlement
+}
+
+fn code_signing_flags(pid: pid_t) -> Option<u32> {
+    let mut flags: u32 = 0;
+    let ret = unsafe {
+        csops(
+            pid,
+            CS_OPS_STATUS,
+            &mut flags as *mut u32 as *mut c_void,
+            std::mem::size_of::<u32>(),
+        )
+    };
+    if ret != 0 {
+        return None;
+    }
+    Some(flags)
+}
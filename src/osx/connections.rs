@@ -0,0 +1,104 @@
+//! Lists the sockets of a process with proc_pidfdinfo
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use libproc::libproc::file_info::{pidfdinfo, ListFDs, ProcFDType};
+use libproc::libproc::net_info::{InSockInfo, SocketFDInfo, SocketInfoKind};
+use libproc::libproc::proc_pid::{listpidinfo, pidinfo};
+use libproc::libproc::task_info::TaskAllInfo;
+
+use super::Process;
+use crate::{Connection, Error, Protocol, TcpState};
+
+// insi_vflag values from sys/proc_info.h
+const INI_IPV4: u8 = 0x1;
+const INI_IPV6: u8 = 0x2;
+
+impl Process {
+    /// Returns the TCP and UDP sockets the process has open
+    pub fn connections(&self) -> Result<Vec<Connection>, Error> {
+        let info = pidinfo::<TaskAllInfo>(self.pid, 0)
+            .map_err(|e| Error::Other(format!("proc_pidinfo failed: {}", e)))?;
+        let fds = listpidinfo::<ListFDs>(self.pid, info.pbsd.pbi_nfiles as usize)
+            .map_err(|e| Error::Other(format!("proc_pidinfo failed: {}", e)))?;
+
+        let mut connections = Vec::new();
+        for fd in fds {
+            if fd.proc_fdtype != ProcFDType::Socket as u32 {
+                continue;
+            }
+            // the descriptor could have been closed since listing them
+            let socket = match pidfdinfo::<SocketFDInfo>(self.pid, fd.proc_fd) {
+                Ok(socket) => socket.psi,
+                Err(_) => continue,
+            };
+            let connection = match SocketInfoKind::from(socket.soi_kind) {
+                SocketInfoKind::Tcp => unsafe {
+                    let tcp = socket.soi_proto.pri_tcp;
+                    connection(Protocol::Tcp, &tcp.tcpsi_ini, tcp_state(tcp.tcpsi_state))
+                },
+                SocketInfoKind::In if socket.soi_protocol == libc::IPPROTO_UDP => unsafe {
+                    connection(Protocol::Udp, &socket.soi_proto.pri_in, None)
+                },
+                _ => None,
+            };
+            connections.extend(connection);
+        }
+        Ok(connections)
+    }
+}
+
+unsafe fn connection(
+    protocol: Protocol,
+    info: &InSockInfo,
+    state: Option<TcpState>,
+) -> Option<Connection> {
+    // the ports are in network order
+    let local_port = u16::from_be(info.insi_lport as u16);
+    let remote_port = u16::from_be(info.insi_fport as u16);
+    let (local, remote) = if info.insi_vflag & INI_IPV4 != 0 {
+        (
+            IpAddr::V4(Ipv4Addr::from(u32::from_be(
+                info.insi_laddr.ina_46.i46a_addr4.s_addr,
+            ))),
+            IpAddr::V4(Ipv4Addr::from(u32::from_be(
+                info.insi_faddr.ina_46.i46a_addr4.s_addr,
+            ))),
+        )
+    } else if info.insi_vflag & INI_IPV6 != 0 {
+        (
+            IpAddr::V6(Ipv6Addr::from(info.insi_laddr.ina_6.s6_addr)),
+            IpAddr::V6(Ipv6Addr::from(info.insi_faddr.ina_6.s6_addr)),
+        )
+    } else {
+        return None;
+    };
+    let remote = if remote.is_unspecified() && remote_port == 0 {
+        None
+    } else {
+        Some(SocketAddr::new(remote, remote_port))
+    };
+    Some(Connection {
+        protocol,
+        local: SocketAddr::new(local, local_port),
+        remote,
+        state,
+    })
+}
+
+/// Converts the TSI_S_* states from sys/proc_info.h
+fn tcp_state(state: libc::c_int) -> Option<TcpState> {
+    Some(match state {
+        0 => TcpState::Closed,
+        1 => TcpState::Listen,
+        2 => TcpState::SynSent,
+        3 => TcpState::SynReceived,
+        4 => TcpState::Established,
+        5 => TcpState::CloseWait,
+        6 => TcpState::FinWait1,
+        7 => TcpState::Closing,
+        8 => TcpState::LastAck,
+        9 => TcpState::FinWait2,
+        10 => TcpState::TimeWait,
+        _ => return None,
+    })
+}
@@ -0,0 +1,151 @@
+//! Finds the dSYM bundles holding the DWARF debug info for Mach-O binaries. Xcode and
+//! `dsymutil` leave debug info out of release binaries, putting it into a separate bundle
+//! that is matched to the binary by UUID - either next to it, or somewhere Spotlight has
+//! indexed.
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use goblin::mach::load_command::CommandVariant;
+use goblin::mach::{Mach, MachO, SingleArch};
+use log::debug;
+
+use crate::Error;
+
+/// Returns the UUIDs of a Mach-O binary, one per architecture for universal binaries
+pub fn macho_uuids(path: &Path) -> Result<Vec<[u8; 16]>, Error> {
+    let data = std::fs::read(path)?;
+    let mach = Mach::parse(&data)
+        .map_err(|e| Error::Other(format!("Failed to parse {}: {}", path.display(), e)))?;
+    Ok(match mach {
+        Mach::Binary(macho) => uuid(&macho).into_iter().collect(),
+        Mach::Fat(fat) => (0..fat.narches)
+            .filter_map(|i| match fat.get(i) {
+                Ok(SingleArch::MachO(macho)) => uuid(&macho),
+                _ => None,
+            })
+            .collect(),
+    })
+}
+
+fn uuid(macho: &MachO) -> Option<[u8; 16]> {
+    macho
+        .load_commands
+        .iter()
+        .find_map(|command| match &command.command {
+            CommandVariant::Uuid(uuid) => Some(uuid.uuid),
+            _ => None,
+        })
+}
+
+/// Returns the DWARF file inside the dSYM bundle for a binary, checking the places Xcode
+/// puts bundles next to the binary before asking Spotlight to search for its UUID
+pub fn find_dsym(binary: &Path) -> Option<PathBuf> {
+    let uuids = macho_uuids(binary).ok()?;
+    if uuids.is_empty() {
+        return None;
+    }
+    let matches = |candidate: &Path| {
+        macho_uuids(candidate)
+            .map(|found| found.iter().any(|uuid| uuids.contains(uuid)))
+            .unwrap_or(false)
+    };
+
+    for bundle in sibling_bundles(binary) {
+        if let Some(dwarf) = dwarf_files(&bundle).into_iter().find(|f| matches(f)) {
+            return Some(dwarf);
+        }
+    }
+
+    for uuid in &uuids {
+        for bundle in spotlight_bundles(uuid) {
+            if let Some(dwarf) = dwarf_files(&bundle).into_iter().find(|f| matches(f)) {
+                return Some(dwarf);
+            }
+        }
+    }
+    None
+}
+
+/// `foo.dSYM` next to `foo`, and `Foo.app.dSYM` next to the app bundle that
+/// `Foo.app/Contents/MacOS/Foo` is in
+fn sibling_bundles(binary: &Path) -> Vec<PathBuf> {
+    let mut bundles = Vec::new();
+    let mut bundle = binary.as_os_str().to_owned();
+    bundle.push(".dSYM");
+    bundles.push(PathBuf::from(bundle));
+
+    if let Some(app) = binary.ancestors().find(|p| {
+        p.extension()
+            .is_some_and(|ext| ext == "app" || ext == "framework")
+    }) {
+        let mut bundle = app.as_os_str().to_owned();
+        bundle.push(".dSYM");
+        bundles.push(PathBuf::from(bundle));
+    }
+    bundles
+}
+
+/// The dSYM bundles that Spotlight has indexed for a UUID
+fn spotlight_bundles(uuid: &[u8; 16]) -> Vec<PathBuf> {
+    let query = format!("com_apple_xcode_dsym_uuids == {}", format_uuid(uuid));
+    let output = match Command::new("mdfind").arg(&query).output() {
+        Ok(output) if output.status.success() => output,
+        Ok(_) | Err(_) => {
+            debug!("mdfind failed for {}", query);
+            return Vec::new();
+        }
+    };
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(PathBuf::from)
+        .collect()
+}
+
+fn dwarf_files(bundle: &Path) -> Vec<PathBuf> {
+    match std::fs::read_dir(bundle.join("Contents/Resources/DWARF")) {
+        Ok(entries) => entries.filter_map(|e| Some(e.ok()?.path())).collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Formats a UUID the way Spotlight indexes it, like `01234567-89AB-CDEF-0123-456789ABCDEF`
+fn format_uuid(uuid: &[u8; 16]) -> String {
+    let hex: String = uuid.iter().map(|b| format!("{:02X}", b)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_uuid() {
+        let uuid = [
+            0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef, 0x01, 0x23, 0x45, 0x67, 0x89, 0xab,
+            0xcd, 0xef,
+        ];
+        assert_eq!(format_uuid(&uuid), "01234567-89AB-CDEF-0123-456789ABCDEF");
+    }
+
+    #[test]
+    fn test_sibling_bundles() {
+        assert_eq!(
+            sibling_bundles(Path::new("/Applications/Foo.app/Contents/MacOS/Foo")),
+            vec![
+                PathBuf::from("/Applications/Foo.app/Contents/MacOS/Foo.dSYM"),
+                PathBuf::from("/Applications/Foo.app.dSYM"),
+            ]
+        );
+        assert_eq!(
+            sibling_bundles(Path::new("/usr/local/bin/foo")),
+            vec![PathBuf::from("/usr/local/bin/foo.dSYM")]
+        );
+    }
+}
@@ -62,6 +62,7 @@ pub type mach_port_t = __darwin_mach_port_t;
 pub type mach_msg_type_number_t = natural_t;
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct time_value {
     pub seconds: integer_t,
     pub microseconds: integer_t,
@@ -72,6 +73,7 @@ pub type thread_flavor_t = natural_t;
 pub type thread_info_t = *mut integer_t;
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct thread_basic_info {
     pub user_time: time_value_t,
     pub system_time: time_value_t,
@@ -84,6 +86,7 @@ pub struct thread_basic_info {
 }
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct thread_identifier_info {
     pub thread_id: u64,
     pub thread_handle: u64,
@@ -120,6 +123,7 @@ pub const x86_THREAD_STATE64: u32 = 4;
 pub type __uint64_t = ::std::os::raw::c_ulonglong;
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct __darwin_x86_thread_state64 {
     pub __rax: __uint64_t,
     pub __rbx: __uint64_t,
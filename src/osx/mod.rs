@@ -1,13 +1,24 @@
+mod alloc;
+mod attach;
+mod connections;
+mod dsym;
 mod mach_thread_bindings;
+mod module_id;
+mod regions;
+mod threads;
+mod tls;
 mod utils;
 
 use mach;
 use std;
 use std::convert::TryInto;
 
-use super::Error;
+use super::{Architecture, Endianness, Error, IoCounters, OpenFile, ReadOptions, TargetArch};
 use mach::kern_return::KERN_SUCCESS;
-use mach::port::{mach_port_name_t, mach_port_t, MACH_PORT_NULL};
+use mach::port::{
+    mach_port_delta_t, mach_port_name_t, mach_port_right_t, mach_port_t, MACH_PORT_NULL,
+    MACH_PORT_RIGHT_SEND,
+};
 use mach::traps::{mach_task_self, task_for_pid};
 use read_process_memory::{CopyAddress, ProcessHandle};
 
@@ -20,9 +31,23 @@ use mach::thread_act::thread_get_state;
 use mach::thread_status::x86_THREAD_STATE64;
 use mach::vm_types::{mach_vm_address_t, mach_vm_size_t};
 
+pub use self::attach::AttachFailure;
+pub use self::dsym::{find_dsym, macho_uuids};
+pub use self::threads::ThreadIter;
 pub use self::utils::{TaskLock, ThreadLock};
 
-use libproc::libproc::proc_pid::{pidinfo, pidpath, PIDInfo, PidInfoFlavor};
+use libproc::libproc::bsd_info::BSDInfo;
+use libproc::libproc::file_info::{pidfdinfo, ListFDs, PIDFDInfo, PIDFDInfoFlavor, ProcFDType};
+use libproc::libproc::pid_rusage::{pidrusage, RUsageInfoV2};
+use libproc::libproc::proc_pid::{listpidinfo, pidinfo, pidpath, PIDInfo, PidInfoFlavor};
+use libproc::libproc::task_info::TaskAllInfo;
+
+// from sys/proc_info.h
+const PROC_FLAG_TRACED: u32 = 0x2;
+
+// from sys/proc.h
+const P_LP64: i32 = 0x4;
+const P_TRANSLATED: i32 = 0x20000;
 
 pub type Pid = pid_t;
 pub type Tid = u32;
@@ -30,6 +55,9 @@ pub type Tid = u32;
 pub struct Process {
     pub pid: Pid,
     pub task: mach_port_name_t,
+    read_options: ReadOptions,
+    /// the length of each block of memory allocated by `alloc`, since freeing needs it
+    allocations: std::sync::Mutex<std::collections::HashMap<u64, usize>>,
 }
 
 #[derive(Eq, PartialEq, Hash, Copy, Clone)]
@@ -42,9 +70,48 @@ impl Process {
         let mut task: mach_port_name_t = MACH_PORT_NULL;
         let result = unsafe { task_for_pid(mach_task_self(), pid as c_int, &mut task) };
         if result != KERN_SUCCESS {
-            return Err(Error::IOError(std::io::Error::last_os_error()));
+            return Err(match attach::classify_failure(pid) {
+                AttachFailure::NoSuchProcess => Error::NoSuchProcess { pid },
+                failure => Error::AttachFailed(failure),
+            });
+        }
+        Ok(Process {
+            pid,
+            task,
+            read_options: ReadOptions::default(),
+            allocations: std::sync::Mutex::new(std::collections::HashMap::new()),
+        })
+    }
+
+    /// Returns a new handle to the same process that can be kept independently of this one,
+    /// adding a reference to the send right for the task port that is released when the new
+    /// handle is dropped. Memory allocated with `alloc`
+    /// has to be freed through the handle that allocated it.
+    pub fn try_clone(&self) -> Result<Process, Error> {
+        let result =
+            unsafe { mach_port_mod_refs(mach_task_self(), self.task, MACH_PORT_RIGHT_SEND, 1) };
+        if result != KERN_SUCCESS {
+            return Err(Error::Other(format!(
+                "mach_port_mod_refs failed for process {}: {}",
+                self.pid, result
+            )));
         }
-        Ok(Process { pid, task })
+        Ok(Process {
+            pid: self.pid,
+            task: self.task,
+            read_options: self.read_options,
+            allocations: std::sync::Mutex::new(std::collections::HashMap::new()),
+        })
+    }
+
+    /// Returns how reads of memory from this process are split up and retried
+    pub fn read_options(&self) -> ReadOptions {
+        self.read_options
+    }
+
+    /// Sets how reads of memory from this process are split up and retried
+    pub fn set_read_options(&mut self, options: ReadOptions) {
+        self.read_options = options;
     }
 
     pub fn exe(&self) -> Result<String, Error> {
@@ -61,6 +128,104 @@ impl Process {
         )
     }
 
+    /// Returns the file descriptors the process has open that refer to a path, ordered by
+    /// descriptor. Sockets, pipes and other descriptors without a path aren't included.
+    pub fn open_files(&self) -> Result<Vec<OpenFile>, Error> {
+        let info = pidinfo::<TaskAllInfo>(self.pid, 0)
+            .map_err(|e| Error::Other(format!("proc_pidinfo failed: {}", e)))?;
+        let fds = listpidinfo::<ListFDs>(self.pid, info.pbsd.pbi_nfiles as usize)
+            .map_err(|e| Error::Other(format!("proc_pidinfo failed: {}", e)))?;
+        let mut files = Vec::new();
+        for fd in fds {
+            if fd.proc_fdtype != ProcFDType::VNode as u32 {
+                continue;
+            }
+            // the descriptor could have been closed since listing them
+            let info = match pidfdinfo::<vnode_fdinfowithpath>(self.pid, fd.proc_fd) {
+                Ok(info) => info,
+                Err(_) => continue,
+            };
+            let path = unsafe { std::ffi::CStr::from_ptr(info.pvip.vip_path.as_ptr()) };
+            files.push(OpenFile {
+                fd: fd.proc_fd as u64,
+                path: path.to_string_lossy().into_owned(),
+            });
+        }
+        files.sort_by_key(|file| file.fd);
+        Ok(files)
+    }
+
+    /// Returns the architecture, pointer size and endianness of the process, from the flags
+    /// returned by sysctl. Processes translated by Rosetta are reported as x86_64.
+    pub fn arch(&self) -> Result<TargetArch, Error> {
+        let mut mib: [c_int; 4] = [
+            libc::CTL_KERN,
+            libc::KERN_PROC,
+            libc::KERN_PROC_PID,
+            self.pid,
+        ];
+        let mut info: libc::kinfo_proc = unsafe { std::mem::zeroed() };
+        let mut size = std::mem::size_of::<libc::kinfo_proc>();
+        let ret = unsafe {
+            libc::sysctl(
+                mib.as_mut_ptr(),
+                mib.len() as u32,
+                &mut info as *mut _ as *mut c_void,
+                &mut size,
+                std::ptr::null_mut(),
+                0,
+            )
+        };
+        if ret < 0 {
+            return Err(Error::from_os_error(
+                self.pid,
+                std::io::Error::last_os_error(),
+            ));
+        }
+        if size == 0 {
+            return Err(Error::NoSuchProcess { pid: self.pid });
+        }
+
+        let flags = info.kp_proc.p_flag;
+        let lp64 = flags & P_LP64 != 0;
+        let architecture = if cfg!(target_arch = "aarch64") && flags & P_TRANSLATED == 0 {
+            Architecture::Aarch64
+        } else if lp64 {
+            Architecture::X86_64
+        } else {
+            Architecture::X86
+        };
+        Ok(TargetArch {
+            architecture,
+            pointer_size: if lp64 { 8 } else { 4 },
+            endianness: Endianness::Little,
+        })
+    }
+
+    /// True if pointers in the process are 64 bits wide
+    pub fn is_64_bit(&self) -> Result<bool, Error> {
+        Ok(self.arch()?.is_64_bit())
+    }
+
+    /// Returns the bytes the process has read from and written to disk, from proc_pid_rusage
+    pub fn io_counters(&self) -> Result<IoCounters, Error> {
+        let usage = pidrusage::<RUsageInfoV2>(self.pid)
+            .map_err(|e| Error::Other(format!("proc_pid_rusage failed: {}", e)))?;
+        Ok(IoCounters {
+            read_bytes: usage.ri_diskio_bytesread,
+            write_bytes: usage.ri_diskio_byteswritten,
+            read_ops: None,
+            write_ops: None,
+        })
+    }
+
+    /// True if the process is being traced by a debugger
+    pub fn is_being_debugged(&self) -> Result<bool, Error> {
+        let info = pidinfo::<BSDInfo>(self.pid, 0)
+            .map_err(|e| Error::Other(format!("proc_pidinfo failed: {}", e)))?;
+        Ok(info.pbi_flags & PROC_FLAG_TRACED != 0)
+    }
+
     pub fn cmdline(&self) -> Result<Vec<String>, Error> {
         unsafe {
             let mib: [i32; 3] = [libc::CTL_KERN, libc::KERN_PROCARGS2, self.pid];
@@ -109,30 +274,36 @@ impl Process {
         Ok(TaskLock::new(self.task)?)
     }
 
-    pub fn threads(&self) -> Result<Vec<Thread>, Error> {
-        let mut threads: mach::mach_types::thread_act_array_t = unsafe { std::mem::zeroed() };
-        let mut thread_count: u32 = 0;
-        let result =
-            unsafe { mach::task::task_threads(self.task, &mut threads, &mut thread_count) };
-        if result != KERN_SUCCESS {
-            return Err(Error::IOError(std::io::Error::last_os_error()));
-        }
+    /// Returns the registers of every thread in the process. The task is suspended once, the
+    /// state of its threads is read one after another, and then it's resumed right away - so
+    /// it's stopped for as short a time as possible.
+    pub fn capture_all_registers(&self) -> Result<Vec<(Tid, x86_thread_state64_t)>, Error> {
+        let lock = self.lock()?;
+        lock.threads()
+            .iter()
+            .map(|thread| Ok((thread.tid, thread.registers()?)))
+            .collect()
+    }
 
-        let mut ret = Vec::new();
-        for i in 0..thread_count {
-            let tid = unsafe { *threads.offset(i as isize) };
-            ret.push(Thread { tid });
-        }
+    /// Locks the process, and returns a lock containing a consistent snapshot of the threads
+    /// in the process. task_suspend holds every thread in the task - including threads
+    /// created after the task was suspended - so the thread list gathered by the lock is
+    /// already stable.
+    pub fn lock_and_snapshot(&self) -> Result<TaskLock, Error> {
+        self.lock()
+    }
 
-        let memsize = thread_count as usize * std::mem::size_of::<Tid>();
-        unsafe {
-            vm_deallocate(
-                mach_task_self(),
-                threads as mach_vm_address_t,
-                memsize as mach_vm_size_t,
-            );
-        }
-        Ok(ret)
+    pub fn threads(&self) -> Result<Vec<Thread>, Error> {
+        get_threads(self.task)
+    }
+
+    /// Returns the ids of the threads in the process. On OSX a thread id is the mach port
+    /// for the thread, so unlike other platforms this is the same as `threads`.
+    pub fn thread_ids(&self) -> Result<Vec<Tid>, Error> {
+        Ok(get_threads(self.task)?
+            .into_iter()
+            .map(|thread| thread.tid)
+            .collect())
     }
 
     pub fn child_processes(&self) -> Result<Vec<(Pid, Pid)>, Error> {
@@ -149,6 +320,31 @@ impl Process {
     }
 }
 
+fn get_threads(task: mach_port_name_t) -> Result<Vec<Thread>, Error> {
+    let mut threads: mach::mach_types::thread_act_array_t = unsafe { std::mem::zeroed() };
+    let mut thread_count: u32 = 0;
+    let result = unsafe { mach::task::task_threads(task, &mut threads, &mut thread_count) };
+    if result != KERN_SUCCESS {
+        return Err(Error::IOError(std::io::Error::last_os_error()));
+    }
+
+    let mut ret = Vec::new();
+    for i in 0..thread_count {
+        let tid = unsafe { *threads.offset(i as isize) };
+        ret.push(Thread { tid });
+    }
+
+    let memsize = thread_count as usize * std::mem::size_of::<Tid>();
+    unsafe {
+        vm_deallocate(
+            mach_task_self(),
+            threads as mach_vm_address_t,
+            memsize as mach_vm_size_t,
+        );
+    }
+    Ok(ret)
+}
+
 fn childpids(pid: Pid) -> Result<Vec<Pid>, Error> {
     let size = unsafe { proc_listchildpids(pid, std::ptr::null_mut(), 0) };
     if size < 0 {
@@ -168,13 +364,16 @@ fn childpids(pid: Pid) -> Result<Vec<Pid>, Error> {
 impl super::ProcessMemory for Process {
     fn read(&self, addr: usize, buf: &mut [u8]) -> Result<(), Error> {
         let handle: ProcessHandle = self.task.try_into()?;
-        Ok(handle.copy_address(addr, buf)?)
+        self.read_options
+            .read_chunked(addr, buf, |addr, buf| Ok(handle.copy_address(addr, buf)?))
+            .map_err(|e| Error::from_read_error(self.pid, addr, buf.len(), e))
     }
 }
 
 use self::mach_thread_bindings::{
-    thread_basic_info, thread_identifier_info, thread_info, THREAD_BASIC_INFO,
-    THREAD_IDENTIFIER_INFO, TH_FLAGS_IDLE, TH_STATE_RUNNING,
+    thread_basic_info, thread_extended_info, thread_identifier_info, thread_info,
+    THREAD_BASIC_INFO, THREAD_EXTENDED_INFO, THREAD_IDENTIFIER_INFO, TH_FLAGS_IDLE,
+    TH_STATE_RUNNING,
 };
 
 extern "C" {
@@ -183,6 +382,23 @@ extern "C" {
         address: mach_vm_address_t,
         size: mach_vm_size_t,
     ) -> kern_return_t;
+    fn mach_port_mod_refs(
+        task: mach_port_t,
+        name: mach_port_name_t,
+        right: mach_port_right_t,
+        delta: mach_port_delta_t,
+    ) -> kern_return_t;
+    fn mach_port_deallocate(task: mach_port_t, name: mach_port_name_t) -> kern_return_t;
+}
+
+impl Drop for Process {
+    /// Releases the reference to the send right for the task port that `new` or `try_clone`
+    /// took, so that the port goes away once every handle to the process is dropped
+    fn drop(&mut self) {
+        if self.task != MACH_PORT_NULL {
+            unsafe { mach_port_deallocate(mach_task_self(), self.task) };
+        }
+    }
 }
 
 impl Thread {
@@ -190,6 +406,12 @@ impl Thread {
         Ok(Thread { tid })
     }
 
+    /// Returns a new handle to the same thread. Threads are looked up by id, so this doesn't
+    /// hold a reference to any port that would need releasing
+    pub fn try_clone(&self) -> Result<Thread, Error> {
+        Ok(*self)
+    }
+
     pub fn id(&self) -> Result<Tid, Error> {
         Ok(self.tid)
     }
@@ -261,6 +483,24 @@ impl Thread {
         }
         Ok(thread_id)
     }
+
+    pub fn get_thread_extended_info(&self) -> Result<thread_extended_info, std::io::Error> {
+        let mut info: thread_extended_info = unsafe { std::mem::zeroed() };
+        let mut info_size: u32 =
+            (std::mem::size_of::<thread_extended_info>() / std::mem::size_of::<i32>()) as u32;
+        let result = unsafe {
+            thread_info(
+                self.tid,
+                THREAD_EXTENDED_INFO,
+                &mut info as *mut thread_extended_info as *mut i32,
+                &mut info_size,
+            )
+        };
+        if result != KERN_SUCCESS {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(info)
+    }
 }
 
 // extra struct definitions needed to get CWD from proc_pidinfo
@@ -287,6 +527,24 @@ impl PIDInfo for proc_vnodepathinfo {
     }
 }
 
+// `struct vnode_fdinfowithpath` for getting the path of a descriptor from proc_pidfdinfo
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct vnode_fdinfowithpath {
+    _pfi: [u8; 24],
+    pub pvip: vnode_info_path,
+}
+impl Default for vnode_fdinfowithpath {
+    fn default() -> Self {
+        unsafe { ::std::mem::zeroed() }
+    }
+}
+impl PIDFDInfo for vnode_fdinfowithpath {
+    fn flavor() -> PIDFDInfoFlavor {
+        PIDFDInfoFlavor::VNodePathInfo
+    }
+}
+
 #[cfg(target_os = "macos")]
 #[link(name = "proc", kind = "dylib")]
 extern "C" {
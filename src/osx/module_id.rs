@@ -0,0 +1,77 @@
+//! Reads the UUID of a Mach-O image from the memory of a process, which identifies the binary
+//! that was actually loaded - even if the file on disk has been replaced since.
+use super::Process;
+use crate::{Error, ModuleId, ProcessMemory};
+
+const MH_MAGIC: u32 = 0xfeed_face;
+const MH_MAGIC_64: u32 = 0xfeed_facf;
+const LC_UUID: u32 = 0x1b;
+
+impl Process {
+    /// Reads the LC_UUID load command of the Mach-O image whose header is at `address`, from
+    /// the memory of the process rather than from the file
+    pub fn module_id(&self, address: u64) -> Result<Option<ModuleId>, Error> {
+        // struct mach_header { magic, cputype, cpusubtype, filetype, ncmds, sizeofcmds, flags },
+        // which mach_header_64 follows with a reserved field
+        let header: [u32; 7] = self.copy_struct(address as usize)?;
+        let header_size = match header[0] {
+            MH_MAGIC_64 => 32,
+            MH_MAGIC => 28,
+            _ => return Ok(None),
+        };
+        let commands = self.copy(address as usize + header_size, header[5] as usize)?;
+        Ok(find_uuid(&commands, header[4]).map(ModuleId::Uuid))
+    }
+}
+
+/// Walks the load commands after a Mach-O header, each of which starts with its type and size
+fn find_uuid(mut commands: &[u8], count: u32) -> Option<[u8; 16]> {
+    for _ in 0..count {
+        let command = u32::from_ne_bytes(commands.get(0..4)?.try_into().ok()?);
+        let size = u32::from_ne_bytes(commands.get(4..8)?.try_into().ok()?) as usize;
+        if command == LC_UUID {
+            return commands.get(8..24)?.try_into().ok();
+        }
+        if size < 8 {
+            return None;
+        }
+        commands = commands.get(size..)?;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_uuid() {
+        let mut commands = Vec::new();
+        // LC_SEGMENT_64, with its contents left out
+        commands.extend(0x19u32.to_ne_bytes());
+        commands.extend(16u32.to_ne_bytes());
+        commands.extend([0u8; 8]);
+        commands.extend(LC_UUID.to_ne_bytes());
+        commands.extend(24u32.to_ne_bytes());
+        commands.extend(1..=16u8);
+        assert_eq!(
+            find_uuid(&commands, 2),
+            Some([1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16])
+        );
+        assert_eq!(find_uuid(&commands, 1), None);
+    }
+
+    #[test]
+    fn test_module_id() {
+        let process = Process::new(std::process::id() as libc::pid_t).unwrap();
+        let mut info: libc::Dl_info = unsafe { std::mem::zeroed() };
+        let addr = test_module_id as *const () as *const libc::c_void;
+        assert_ne!(unsafe { libc::dladdr(addr, &mut info) }, 0);
+
+        let uuids = crate::macho_uuids(&std::env::current_exe().unwrap()).unwrap();
+        match process.module_id(info.dli_fbase as u64).unwrap() {
+            Some(ModuleId::Uuid(uuid)) => assert!(uuids.contains(&uuid)),
+            id => panic!("unexpected module id {:?}", id),
+        }
+    }
+}
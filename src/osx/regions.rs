@@ -0,0 +1,52 @@
+//! Looks up the region of a task's address space that contains an address with
+//! mach_vm_region
+use mach::kern_return::{KERN_INVALID_ADDRESS, KERN_SUCCESS};
+use mach::message::mach_msg_type_number_t;
+use mach::port::mach_port_t;
+use mach::vm::mach_vm_region;
+use mach::vm_prot::{VM_PROT_EXECUTE, VM_PROT_READ, VM_PROT_WRITE};
+use mach::vm_region::{vm_region_basic_info_64, vm_region_info_t, VM_REGION_BASIC_INFO_64};
+use mach::vm_types::{mach_vm_address_t, mach_vm_size_t};
+
+use super::Process;
+use crate::{Error, Protection};
+
+impl Process {
+    /// Returns the protection of the region that contains `addr`, or None if the address
+    /// isn't mapped in the task
+    pub fn query_address(&self, addr: u64) -> Result<Option<Protection>, Error> {
+        let mut start: mach_vm_address_t = addr;
+        let mut size: mach_vm_size_t = 0;
+        let mut info: vm_region_basic_info_64 = unsafe { std::mem::zeroed() };
+        let mut count = (std::mem::size_of::<vm_region_basic_info_64>()
+            / std::mem::size_of::<i32>()) as mach_msg_type_number_t;
+        let mut object_name: mach_port_t = 0;
+        let result = unsafe {
+            mach_vm_region(
+                self.task,
+                &mut start,
+                &mut size,
+                VM_REGION_BASIC_INFO_64,
+                &mut info as *mut vm_region_basic_info_64 as vm_region_info_t,
+                &mut count,
+                &mut object_name,
+            )
+        };
+        // there are no regions at or past addr
+        if result == KERN_INVALID_ADDRESS {
+            return Ok(None);
+        }
+        if result != KERN_SUCCESS {
+            return Err(Error::Other(format!("mach_vm_region failed: {}", result)));
+        }
+        // otherwise this is the first region at or past addr, which may start after it
+        if addr < start {
+            return Ok(None);
+        }
+        Ok(Some(Protection {
+            read: info.protection & VM_PROT_READ != 0,
+            write: info.protection & VM_PROT_WRITE != 0,
+            execute: info.protection & VM_PROT_EXECUTE != 0,
+        }))
+    }
+}
@@ -0,0 +1,77 @@
+//! Details of the threads of a process. `threads_iter` lists the threads lazily, only
+//! calling thread_info for each thread as the iterator reaches it.
+use std::time::Duration;
+
+use super::mach_thread_bindings::{
+    TH_STATE_HALTED, TH_STATE_RUNNING, TH_STATE_STOPPED, TH_STATE_UNINTERRUPTIBLE, TH_STATE_WAITING,
+};
+use super::{get_threads, Process, Thread};
+use crate::{Error, ThreadInfo, ThreadState};
+
+/// An iterator over the threads of a process, returned by `Process::threads_iter`
+pub struct ThreadIter {
+    threads: std::vec::IntoIter<Thread>,
+}
+
+impl Process {
+    /// Returns an iterator over the threads of the process, with the name, state and CPU
+    /// time of each. Threads that exit while iterating are skipped.
+    pub fn threads_iter(&self) -> Result<ThreadIter, Error> {
+        Ok(ThreadIter {
+            threads: get_threads(self.task)?.into_iter(),
+        })
+    }
+}
+
+impl Iterator for ThreadIter {
+    type Item = Result<ThreadInfo, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // thread_info fails for threads that exited after task_threads was called
+        self.threads.by_ref().find_map(|thread| {
+            let info = thread.get_thread_extended_info().ok()?;
+            let state = match info.pth_run_state as u32 {
+                TH_STATE_RUNNING => ThreadState::Running,
+                TH_STATE_STOPPED => ThreadState::Stopped,
+                TH_STATE_WAITING => ThreadState::Sleeping,
+                TH_STATE_UNINTERRUPTIBLE => ThreadState::Uninterruptible,
+                TH_STATE_HALTED => ThreadState::Zombie,
+                _ => ThreadState::Unknown,
+            };
+            let name = unsafe { std::ffi::CStr::from_ptr(info.pth_name.as_ptr()) }
+                .to_string_lossy()
+                .into_owned();
+            Some(Ok(ThreadInfo {
+                tid: thread.tid,
+                name: if name.is_empty() { None } else { Some(name) },
+                state,
+                cpu_time: Duration::from_nanos(info.pth_user_time + info.pth_system_time),
+            }))
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.threads.len()))
+    }
+}
+
+impl Process {
+    /// Returns the CPUs the process is allowed to run on. OSX has no way to pin processes to
+    /// CPUs, so this is every online CPU.
+    pub fn affinity(&self) -> Result<Vec<usize>, Error> {
+        Ok((0..crate::online_cpus()?).collect())
+    }
+}
+
+impl Thread {
+    /// Returns the CPUs the thread is allowed to run on. OSX has no way to pin threads to
+    /// CPUs, so this is every online CPU.
+    pub fn affinity(&self) -> Result<Vec<usize>, Error> {
+        Ok((0..crate::online_cpus()?).collect())
+    }
+
+    /// OSX doesn't report the CPU a thread last ran on, so this is always None
+    pub fn last_cpu(&self) -> Result<Option<usize>, Error> {
+        Ok(None)
+    }
+}
@@ -0,0 +1,45 @@
+//! Finds the thread specific data (TSD) of a thread from its pthread_t, which
+//! pthread_getspecific reads from
+use super::Thread;
+use crate::{Error, ProcessMemory};
+
+/// offsetof(struct _pthread, tsd) in 64-bit processes
+const PTHREAD_TSD_OFFSET: u64 = 224;
+
+/// _EXTERNAL_POSIX_THREAD_KEYS_MAX + _INTERNAL_POSIX_THREAD_KEYS_MAX
+const TSD_SLOTS: u64 = 768;
+
+impl Thread {
+    /// Returns the address of the thread's TSD slots, which gs points to on x86_64 and
+    /// tpidrro_el0 on arm64. This is found from the pthread_t of the thread, so is only
+    /// available for threads created by pthreads.
+    pub fn tls_base(&self) -> Result<u64, Error> {
+        let pthread = self.thread_handle()?;
+        if pthread == 0 {
+            return Err(Error::Other(format!(
+                "thread {} isn't a pthread, so has no TSD",
+                self.tid
+            )));
+        }
+        Ok(pthread + PTHREAD_TSD_OFFSET)
+    }
+
+    /// Reads a value at an offset from the thread's TSD slots
+    pub fn read_tls<T: Copy, P: ProcessMemory>(
+        &self,
+        process: &P,
+        offset: i64,
+    ) -> Result<T, Error> {
+        let addr = self.tls_base()?.wrapping_add(offset as u64);
+        process.copy_struct(addr as usize)
+    }
+
+    /// Returns the value of a pthread key for the thread, like pthread_getspecific would in
+    /// the thread itself
+    pub fn thread_specific<P: ProcessMemory>(&self, process: &P, key: u64) -> Result<u64, Error> {
+        if key >= TSD_SLOTS {
+            return Err(Error::Other(format!("invalid pthread key {}", key)));
+        }
+        self.read_tls(process, key as i64 * 8)
+    }
+}
@@ -8,15 +8,33 @@ extern "C" {
 
 pub struct TaskLock {
     task: mach_port_name_t,
+    threads: Vec<Thread>,
 }
 
 impl TaskLock {
-    pub fn new(task: mach_port_name_t) -> Result<TaskLock, std::io::Error> {
+    pub fn new(task: mach_port_name_t) -> Result<TaskLock, Error> {
         let result = unsafe { mach::task::task_suspend(task) };
         if result != KERN_SUCCESS {
-            return Err(std::io::Error::last_os_error());
+            return Err(Error::IOError(std::io::Error::last_os_error()));
         }
-        Ok(TaskLock { task })
+        // the task is suspended, so the thread list is stable here. If enumerating
+        // fails the lock gets dropped, which resumes the task
+        let mut lock = TaskLock {
+            task,
+            threads: Vec::new(),
+        };
+        lock.threads = get_threads(task)?;
+        Ok(lock)
+    }
+
+    /// The threads that were suspended by this lock
+    pub fn threads(&self) -> &[Thread] {
+        &self.threads
+    }
+
+    /// The thread ids of the threads that were suspended by this lock
+    pub fn tids(&self) -> Vec<Tid> {
+        self.threads.iter().map(|thread| thread.tid).collect()
     }
 }
 impl Drop for TaskLock {
@@ -0,0 +1,348 @@
+//! Aggregates symbolicated stack traces into the pprof profile format, so that the samples
+//! collected by a profiler can be viewed with `go tool pprof` and the many other tools that
+//! read it.
+//!
+//! The profile is written as an uncompressed protobuf message, which pprof accepts as is -
+//! though files are conventionally gzipped.
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::{Module, ModuleId, StackFrame, Tid};
+
+/// Builds a pprof profile from stack traces, merging identical stacks into a single sample
+pub struct PprofBuilder {
+    period: Duration,
+    start: SystemTime,
+    strings: Vec<String>,
+    string_ids: HashMap<String, i64>,
+    modules: Vec<Module>,
+    // keyed by module name, with the index into `modules` if it was passed to add_modules
+    mapping_ids: HashMap<String, (u64, Option<usize>)>,
+    function_ids: HashMap<(i64, i64), u64>,
+    location_ids: HashMap<LocationKey, u64>,
+    locations: Vec<Location>,
+    samples: HashMap<(Vec<u64>, Tid), i64>,
+}
+
+// the address, mapping and (function, line, column) of each frame at a location
+type LocationKey = (u64, u64, Vec<(u64, i64, i64)>);
+
+struct Location {
+    address: u64,
+    mapping_id: u64,
+    lines: Vec<(u64, i64, i64)>,
+}
+
+impl PprofBuilder {
+    /// Creates a profile for stacks sampled every `period`, which is used to show how much
+    /// CPU time each sample represents
+    pub fn new(period: Duration) -> PprofBuilder {
+        let mut builder = PprofBuilder {
+            period,
+            start: SystemTime::now(),
+            strings: Vec::new(),
+            string_ids: HashMap::new(),
+            modules: Vec::new(),
+            mapping_ids: HashMap::new(),
+            function_ids: HashMap::new(),
+            location_ids: HashMap::new(),
+            locations: Vec::new(),
+            samples: HashMap::new(),
+        };
+        // the first entry of the string table must always be the empty string
+        builder.string("");
+        builder
+    }
+
+    /// Adds the address ranges and build-ids of the modules that the stacks were collected
+    /// from, like those returned by `Symbolicator::modules`. This is optional, but lets
+    /// pprof match the profile up to binaries for symbolization and disassembly.
+    pub fn add_modules(&mut self, modules: &[Module]) {
+        self.modules.extend_from_slice(modules);
+    }
+
+    /// Adds a stack trace from a thread. The frames should be in the order they are returned
+    /// by the symbolicator: innermost first, with any inlined frames before the frame they
+    /// were inlined into.
+    pub fn add_sample(&mut self, tid: Tid, frames: &[StackFrame]) {
+        let mut location_ids = Vec::new();
+        let mut start = 0;
+        while start < frames.len() {
+            // frames inlined at an address share a location with the frame they're inlined into
+            let mut end = start;
+            while end + 1 < frames.len()
+                && frames[end].inlined
+                && frames[end + 1].addr == frames[start].addr
+            {
+                end += 1;
+            }
+            location_ids.push(self.location(&frames[start..=end]));
+            start = end + 1;
+        }
+        *self.samples.entry((location_ids, tid)).or_insert(0) += 1;
+    }
+
+    /// Returns the profile as an encoded protobuf message
+    pub fn encode(&self) -> Vec<u8> {
+        let duration = self.start.elapsed().unwrap_or_default();
+        let time_nanos = self
+            .start
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as i64;
+
+        // all strings need to be in the table before it's written, so intern the ones that
+        // aren't known yet into a copy
+        let mut strings = self.strings.clone();
+        let mut string_ids = self.string_ids.clone();
+        let mut intern = |s: &str| -> i64 {
+            if let Some(id) = string_ids.get(s) {
+                return *id;
+            }
+            let id = strings.len() as i64;
+            strings.push(s.to_owned());
+            string_ids.insert(s.to_owned(), id);
+            id
+        };
+        let samples = intern("samples");
+        let count = intern("count");
+        let cpu = intern("cpu");
+        let nanoseconds = intern("nanoseconds");
+        let thread = intern("thread");
+
+        let mut profile = Vec::new();
+        for (kind, unit) in [(samples, count), (cpu, nanoseconds)] {
+            let mut value_type = Vec::new();
+            int_field(&mut value_type, 1, kind as u64);
+            int_field(&mut value_type, 2, unit as u64);
+            bytes_field(&mut profile, 1, &value_type);
+        }
+
+        let period = self.period.as_nanos() as i64;
+        let mut samples: Vec<_> = self.samples.iter().collect();
+        samples.sort();
+        for ((location_ids, tid), count) in samples {
+            let mut sample = Vec::new();
+            packed_field(&mut sample, 1, location_ids.iter().copied());
+            packed_field(
+                &mut sample,
+                2,
+                [*count, count * period].iter().map(|v| *v as u64),
+            );
+            let mut label = Vec::new();
+            int_field(&mut label, 1, thread as u64);
+            int_field(&mut label, 3, i64::from(*tid) as u64);
+            bytes_field(&mut sample, 3, &label);
+            bytes_field(&mut profile, 2, &sample);
+        }
+
+        let mut mappings: Vec<_> = self.mapping_ids.iter().collect();
+        mappings.sort_by_key(|(_, (id, _))| *id);
+        for (name, (id, module)) in mappings {
+            let mut mapping = Vec::new();
+            int_field(&mut mapping, 1, *id);
+            if let Some(module) = module.map(|i| &self.modules[i]) {
+                int_field(&mut mapping, 2, module.address);
+                int_field(&mut mapping, 3, module.address + module.size);
+                if let Some(build_id) = module.id.as_ref() {
+                    int_field(&mut mapping, 6, intern(&format_id(build_id)) as u64);
+                }
+            }
+            int_field(&mut mapping, 5, intern(name) as u64);
+            // every location has already been symbolicated
+            int_field(&mut mapping, 7, 1);
+            bytes_field(&mut profile, 3, &mapping);
+        }
+
+        for (i, location) in self.locations.iter().enumerate() {
+            let mut encoded = Vec::new();
+            int_field(&mut encoded, 1, i as u64 + 1);
+            int_field(&mut encoded, 2, location.mapping_id);
+            int_field(&mut encoded, 3, location.address);
+            for (function_id, line_number, column) in &location.lines {
+                let mut line = Vec::new();
+                int_field(&mut line, 1, *function_id);
+                int_field(&mut line, 2, *line_number as u64);
+                int_field(&mut line, 3, *column as u64);
+                bytes_field(&mut encoded, 4, &line);
+            }
+            bytes_field(&mut profile, 4, &encoded);
+        }
+
+        let mut functions: Vec<_> = self.function_ids.iter().collect();
+        functions.sort_by_key(|(_, id)| **id);
+        for ((name, filename), id) in functions {
+            let mut function = Vec::new();
+            int_field(&mut function, 1, *id);
+            int_field(&mut function, 2, *name as u64);
+            int_field(&mut function, 3, *name as u64);
+            int_field(&mut function, 4, *filename as u64);
+            bytes_field(&mut profile, 5, &function);
+        }
+
+        for s in &strings {
+            bytes_field(&mut profile, 6, s.as_bytes());
+        }
+        int_field(&mut profile, 9, time_nanos as u64);
+        int_field(&mut profile, 10, duration.as_nanos() as u64);
+        let mut period_type = Vec::new();
+        int_field(&mut period_type, 1, cpu as u64);
+        int_field(&mut period_type, 2, nanoseconds as u64);
+        bytes_field(&mut profile, 11, &period_type);
+        int_field(&mut profile, 12, period as u64);
+        profile
+    }
+
+    fn string(&mut self, s: &str) -> i64 {
+        if let Some(id) = self.string_ids.get(s) {
+            return *id;
+        }
+        let id = self.strings.len() as i64;
+        self.strings.push(s.to_owned());
+        self.string_ids.insert(s.to_owned(), id);
+        id
+    }
+
+    fn mapping(&mut self, name: &str) -> u64 {
+        if let Some((id, _)) = self.mapping_ids.get(name) {
+            return *id;
+        }
+        let id = self.mapping_ids.len() as u64 + 1;
+        let module = self.modules.iter().position(|m| m.filename == name);
+        self.mapping_ids.insert(name.to_owned(), (id, module));
+        id
+    }
+
+    fn function(&mut self, frame: &StackFrame) -> u64 {
+        let name = self.string(frame.function.as_deref().unwrap_or("?"));
+        let filename = self.string(frame.filename.as_deref().unwrap_or(""));
+        let next = self.function_ids.len() as u64 + 1;
+        *self.function_ids.entry((name, filename)).or_insert(next)
+    }
+
+    fn location(&mut self, frames: &[StackFrame]) -> u64 {
+        let address = frames[0].addr;
+        let mapping_id = self.mapping(&frames[0].module);
+        let lines: Vec<_> = frames
+            .iter()
+            .map(|frame| {
+                (
+                    self.function(frame),
+                    frame.line.unwrap_or(0) as i64,
+                    frame.column.unwrap_or(0) as i64,
+                )
+            })
+            .collect();
+        let key = (address, mapping_id, lines);
+        if let Some(id) = self.location_ids.get(&key) {
+            return *id;
+        }
+        let id = self.locations.len() as u64 + 1;
+        self.locations.push(Location {
+            address,
+            mapping_id,
+            lines: key.2.clone(),
+        });
+        self.location_ids.insert(key, id);
+        id
+    }
+}
+
+/// Formats a module id the way pprof expects build ids: as lowercase hex
+fn format_id(id: &ModuleId) -> String {
+    let hex = |bytes: &[u8]| -> String { bytes.iter().map(|b| format!("{:02x}", b)).collect() };
+    match id {
+        ModuleId::BuildId(build_id) => hex(build_id),
+        ModuleId::Uuid(uuid) => hex(uuid),
+        ModuleId::Pdb { guid, age, .. } => format!("{}{:x}", hex(guid), age),
+    }
+}
+
+fn varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+fn int_field(buffer: &mut Vec<u8>, field: u32, value: u64) {
+    // zero is the default, so can be left out
+    if value != 0 {
+        varint(buffer, (field as u64) << 3);
+        varint(buffer, value);
+    }
+}
+
+fn bytes_field(buffer: &mut Vec<u8>, field: u32, bytes: &[u8]) {
+    varint(buffer, ((field as u64) << 3) | 2);
+    varint(buffer, bytes.len() as u64);
+    buffer.extend_from_slice(bytes);
+}
+
+fn packed_field(buffer: &mut Vec<u8>, field: u32, values: impl Iterator<Item = u64>) {
+    let mut packed = Vec::new();
+    for value in values {
+        varint(&mut packed, value);
+    }
+    bytes_field(buffer, field, &packed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(addr: u64, function: &str, inlined: bool) -> StackFrame {
+        StackFrame {
+            line: Some(10),
+            column: None,
+            filename: Some("foo.c".to_owned()),
+            function: Some(function.to_owned()),
+            module: "/usr/bin/foo".to_owned(),
+            addr,
+            module_offset: None,
+            inlined,
+        }
+    }
+
+    #[test]
+    fn test_varint() {
+        let mut buffer = Vec::new();
+        varint(&mut buffer, 1);
+        varint(&mut buffer, 300);
+        assert_eq!(buffer, [0x01, 0xac, 0x02]);
+    }
+
+    #[test]
+    fn test_aggregate() {
+        let mut builder = PprofBuilder::new(Duration::from_millis(10));
+        let stack = [
+            frame(0x1010, "inner", true),
+            frame(0x1010, "outer", false),
+            frame(0x2000, "main", false),
+        ];
+        builder.add_sample(1, &stack);
+        builder.add_sample(1, &stack);
+        builder.add_sample(2, &stack[1..]);
+
+        // the inlined frame shares a location with the frame it was inlined into
+        assert_eq!(builder.locations.len(), 3);
+        assert_eq!(builder.locations[0].lines.len(), 2);
+        assert_eq!(builder.function_ids.len(), 3);
+        assert_eq!(builder.mapping_ids.len(), 1);
+        assert_eq!(builder.samples.get(&(vec![1, 2], 1)), Some(&2));
+        assert_eq!(builder.samples.get(&(vec![3, 2], 2)), Some(&1));
+        assert_eq!(builder.strings[0], "");
+    }
+
+    #[test]
+    fn test_format_id() {
+        assert_eq!(format_id(&ModuleId::BuildId(vec![0xab, 0x01])), "ab01");
+        let pdb = ModuleId::Pdb {
+            guid: [0x11; 16],
+            age: 0x1a,
+            name: "foo.pdb".to_owned(),
+        };
+        assert_eq!(format_id(&pdb), format!("{}1a", "11".repeat(16)));
+    }
+}
@@ -0,0 +1,140 @@
+//! Reading and changing the scheduling priority of a process. Unix nice values and windows
+//! priority classes are both mapped to the levels of `Priority`.
+#[cfg(unix)]
+use crate::{Error, Pid, Process};
+
+/// The scheduling priority of a process, from lowest to highest
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Priority {
+    Idle,
+    BelowNormal,
+    Normal,
+    AboveNormal,
+    High,
+    /// The highest priority class on windows. On unix this is only the lowest nice value,
+    /// and doesn't change the scheduling policy of the process.
+    Realtime,
+}
+
+impl Priority {
+    /// Returns the priority that a unix nice value (from -20 to 19) falls into
+    pub fn from_nice(nice: i32) -> Priority {
+        match nice {
+            i32::MIN..=-16 => Priority::Realtime,
+            -15..=-8 => Priority::High,
+            -7..=-1 => Priority::AboveNormal,
+            0 => Priority::Normal,
+            1..=14 => Priority::BelowNormal,
+            _ => Priority::Idle,
+        }
+    }
+
+    /// Returns the unix nice value used to set this priority
+    pub fn nice(&self) -> i32 {
+        match self {
+            Priority::Idle => 19,
+            Priority::BelowNormal => 10,
+            Priority::Normal => 0,
+            Priority::AboveNormal => -5,
+            Priority::High => -10,
+            Priority::Realtime => -20,
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Process {
+    /// Returns the priority of the process, from its nice value. On linux each thread has its
+    /// own nice value, and this is the nice value of the main thread.
+    pub fn priority(&self) -> Result<Priority, Error> {
+        // getpriority can return -1 on success, so errno has to be checked instead
+        unsafe { *errno() = 0 };
+        let nice = unsafe { libc::getpriority(libc::PRIO_PROCESS, self.pid as libc::id_t) };
+        let error = std::io::Error::last_os_error();
+        if nice == -1 && error.raw_os_error() != Some(0) {
+            return Err(Error::from_os_error(self.pid, error));
+        }
+        Ok(Priority::from_nice(nice))
+    }
+
+    /// Sets the priority of the process by changing its nice value. Raising the priority above
+    /// `Priority::Normal` needs to be root (or CAP_SYS_NICE on linux).
+    pub fn set_priority(&self, priority: Priority) -> Result<(), Error> {
+        // linux sets the nice value of a single thread, so each of them has to be set
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        let ids = self.thread_ids()?;
+        #[cfg(not(any(target_os = "linux", target_os = "android")))]
+        let ids = [self.pid];
+
+        for id in ids {
+            set_nice(self.pid, id, priority.nice())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn set_nice(pid: Pid, id: Pid, nice: i32) -> Result<(), Error> {
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS, id as libc::id_t, nice) } != 0 {
+        return Err(Error::from_os_error(pid, std::io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+unsafe fn errno() -> *mut libc::c_int {
+    libc::__errno_location()
+}
+
+#[cfg(any(target_os = "macos", target_os = "freebsd"))]
+unsafe fn errno() -> *mut libc::c_int {
+    libc::__error()
+}
+
+#[cfg(any(target_os = "openbsd", target_os = "netbsd"))]
+unsafe fn errno() -> *mut libc::c_int {
+    libc::__errno()
+}
+
+#[cfg(any(target_os = "illumos", target_os = "solaris"))]
+unsafe fn errno() -> *mut libc::c_int {
+    libc::___errno()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nice_round_trip() {
+        for priority in [
+            Priority::Idle,
+            Priority::BelowNormal,
+            Priority::Normal,
+            Priority::AboveNormal,
+            Priority::High,
+            Priority::Realtime,
+        ] {
+            assert_eq!(Priority::from_nice(priority.nice()), priority);
+        }
+        assert_eq!(Priority::from_nice(5), Priority::BelowNormal);
+        assert_eq!(Priority::from_nice(-20), Priority::Realtime);
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    #[test]
+    fn test_priority() {
+        let mut child = std::process::Command::new("sleep")
+            .arg("10")
+            .spawn()
+            .unwrap();
+        let process = Process::try_from(&child).unwrap();
+        let current = Process::new(std::process::id() as Pid).unwrap();
+        assert_eq!(process.priority().unwrap(), current.priority().unwrap());
+        process.set_priority(Priority::Idle).unwrap();
+        assert_eq!(process.priority().unwrap(), Priority::Idle);
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+}
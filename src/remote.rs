@@ -0,0 +1,348 @@
+//! Reads a process on another machine, or in another container, through a small agent
+//! running next to it. The agent calls `serve` with a connection from a TCP or unix socket,
+//! and the profiler uses a `RemoteProcess` connected to it in place of a local `Process`.
+//!
+//! There's no authentication: anyone who can connect to the agent can read all of the
+//! process's memory and stop it. Only serve connections from a trusted interface, like a unix
+//! socket or the loopback address that `serve_tcp` listens on by default.
+//!
+//! Messages in both directions are framed by a little endian u32 length. Each request is an
+//! opcode followed by its arguments, and each reply is a status byte followed by either the
+//! result or an error message.
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::Mutex;
+
+use log::{debug, warn};
+
+use crate::{Error, Pid, Process, ProcessMemory};
+
+const OP_INFO: u8 = 0;
+const OP_THREADS: u8 = 1;
+const OP_READ: u8 = 2;
+const OP_LOCK: u8 = 3;
+const OP_UNLOCK: u8 = 4;
+
+const STATUS_OK: u8 = 0;
+const STATUS_ERROR: u8 = 1;
+const STATUS_INVALID_ADDRESS: u8 = 2;
+
+// the largest message either side will accept, so that a corrupted length can't make us
+// allocate all of memory
+const MAX_MESSAGE_SIZE: usize = 64 * 1024 * 1024;
+
+/// A bidirectional byte stream between an agent and a client, like a TCP or unix socket
+pub trait RemoteConnection: Read + Write + Send {}
+impl<T: Read + Write + Send> RemoteConnection for T {}
+
+/// Answers requests from a `RemoteProcess` on a connection, until the client disconnects.
+/// The process is resumed if the client disconnects while holding the lock.
+///
+/// The client gets full access to the process's memory, so the connection must only come
+/// from a trusted interface.
+pub fn serve<C: Read + Write>(process: &Process, mut connection: C) -> Result<(), Error> {
+    // the lock types differ between platforms, but all of them resume the process on drop
+    let mut lock: Option<Box<dyn std::any::Any>> = None;
+    while let Some(request) = read_message(&mut connection)? {
+        let reply = match handle_request(process, &request, &mut lock) {
+            Ok(data) => [&[STATUS_OK], data.as_slice()].concat(),
+            Err(Error::InvalidAddress { source, .. }) => {
+                [&[STATUS_INVALID_ADDRESS], source.to_string().as_bytes()].concat()
+            }
+            Err(e) => [&[STATUS_ERROR], e.to_string().as_bytes()].concat(),
+        };
+        write_message(&mut connection, &reply)?;
+    }
+    debug!("remote client for pid {} disconnected", process.pid);
+    Ok(())
+}
+
+/// Listens for clients on a TCP address, and serves each one that connects in turn. Only
+/// loopback addresses are allowed unless `allow_remote` is set, since anyone who can reach
+/// the port can read the process's memory.
+pub fn serve_tcp<A: ToSocketAddrs>(
+    process: &Process,
+    addr: A,
+    allow_remote: bool,
+) -> Result<(), Error> {
+    let addrs: Vec<_> = addr.to_socket_addrs()?.collect();
+    if let Some(addr) = addrs.iter().find(|addr| !addr.ip().is_loopback()) {
+        if !allow_remote {
+            return Err(Error::Other(format!(
+                "refusing to serve on {}, which isn't a loopback address",
+                addr
+            )));
+        }
+        warn!(
+            "serving pid {} to anyone who can reach {}",
+            process.pid, addr
+        );
+    }
+
+    let listener = TcpListener::bind(addrs.as_slice())?;
+    loop {
+        let (stream, peer) = listener.accept()?;
+        debug!("remote client {} connected for pid {}", peer, process.pid);
+        stream.set_nodelay(true)?;
+        if let Err(e) = serve(process, stream) {
+            warn!("remote client {} failed: {}", peer, e);
+        }
+    }
+}
+
+fn handle_request(
+    process: &Process,
+    request: &[u8],
+    lock: &mut Option<Box<dyn std::any::Any>>,
+) -> Result<Vec<u8>, Error> {
+    let (op, args) = request
+        .split_first()
+        .ok_or_else(|| Error::Other("empty request".to_owned()))?;
+    match *op {
+        OP_INFO => {
+            let mut reply = (process.pid as i64).to_le_bytes().to_vec();
+            reply.extend_from_slice(process.exe()?.as_bytes());
+            Ok(reply)
+        }
+        OP_THREADS => {
+            let mut reply = Vec::new();
+            for thread in process.threads()? {
+                reply.extend_from_slice(&(thread.id()? as u64).to_le_bytes());
+            }
+            Ok(reply)
+        }
+        OP_READ => {
+            if args.len() != 12 {
+                return Err(Error::Other("invalid read request".to_owned()));
+            }
+            let addr = u64::from_le_bytes(args[..8].try_into().unwrap()) as usize;
+            let len = u32::from_le_bytes(args[8..].try_into().unwrap()) as usize;
+            if len > MAX_MESSAGE_SIZE {
+                return Err(Error::Other(format!("read of {} bytes is too large", len)));
+            }
+            let mut buf = vec![0; len];
+            process.read(addr, &mut buf)?;
+            Ok(buf)
+        }
+        OP_LOCK => {
+            if lock.is_none() {
+                *lock = Some(Box::new(process.lock()?));
+            }
+            Ok(Vec::new())
+        }
+        OP_UNLOCK => {
+            *lock = None;
+            Ok(Vec::new())
+        }
+        _ => Err(Error::Other(format!("unknown request {}", op))),
+    }
+}
+
+/// A process being read through an agent calling `serve`
+pub struct RemoteProcess {
+    connection: Mutex<Box<dyn RemoteConnection>>,
+    /// The process id of the process on the agent's machine
+    pub pid: Pid,
+    exe: String,
+}
+
+impl RemoteProcess {
+    /// Connects to an agent listening on a TCP port
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> Result<RemoteProcess, Error> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        RemoteProcess::new(Box::new(stream))
+    }
+
+    /// Connects to an agent listening on a unix socket
+    #[cfg(unix)]
+    pub fn connect_unix<P: AsRef<std::path::Path>>(path: P) -> Result<RemoteProcess, Error> {
+        let stream = std::os::unix::net::UnixStream::connect(path)?;
+        RemoteProcess::new(Box::new(stream))
+    }
+
+    /// Talks to an agent over an existing connection
+    pub fn new(mut connection: Box<dyn RemoteConnection>) -> Result<RemoteProcess, Error> {
+        let info = request(&mut connection, &[OP_INFO])?;
+        if info.len() < 8 {
+            return Err(Error::Other("invalid reply from remote agent".to_owned()));
+        }
+        let pid = i64::from_le_bytes(info[..8].try_into().unwrap()) as Pid;
+        let exe = String::from_utf8_lossy(&info[8..]).into_owned();
+        debug!("connected to remote agent for pid {} ({})", pid, exe);
+        Ok(RemoteProcess {
+            connection: Mutex::new(connection),
+            pid,
+            exe,
+        })
+    }
+
+    /// Returns the path of the executable, as seen by the agent
+    pub fn exe(&self) -> Result<String, Error> {
+        Ok(self.exe.clone())
+    }
+
+    /// Lists the thread ids of the process
+    pub fn threads(&self) -> Result<Vec<u64>, Error> {
+        let reply = self.request(&[OP_THREADS])?;
+        Ok(reply
+            .chunks_exact(8)
+            .map(|id| u64::from_le_bytes(id.try_into().unwrap()))
+            .collect())
+    }
+
+    /// Suspends the process until the returned lock is dropped
+    pub fn lock(&self) -> Result<RemoteLock<'_>, Error> {
+        self.request(&[OP_LOCK])?;
+        Ok(RemoteLock { process: self })
+    }
+
+    fn request(&self, message: &[u8]) -> Result<Vec<u8>, Error> {
+        request(&mut *self.connection.lock().unwrap(), message)
+    }
+}
+
+impl ProcessMemory for RemoteProcess {
+    fn read(&self, addr: usize, buf: &mut [u8]) -> Result<(), Error> {
+        let mut connection = self.connection.lock().unwrap();
+        for (i, chunk) in buf.chunks_mut(MAX_MESSAGE_SIZE).enumerate() {
+            let address = addr + i * MAX_MESSAGE_SIZE;
+            let mut message = vec![OP_READ];
+            message.extend_from_slice(&(address as u64).to_le_bytes());
+            message.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+            write_message(&mut *connection, &message)?;
+            let reply = read_reply(&mut *connection)?;
+            match reply.split_first() {
+                Some((&STATUS_OK, data)) if data.len() == chunk.len() => {
+                    chunk.copy_from_slice(data)
+                }
+                Some((&STATUS_INVALID_ADDRESS, message)) => {
+                    return Err(Error::InvalidAddress {
+                        pid: self.pid,
+                        addr: address,
+                        len: chunk.len(),
+                        source: std::io::Error::other(String::from_utf8_lossy(message)),
+                    })
+                }
+                _ => return Err(reply_error(&reply)),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Keeps a remote process suspended, resuming it when dropped
+pub struct RemoteLock<'a> {
+    process: &'a RemoteProcess,
+}
+
+impl Drop for RemoteLock<'_> {
+    fn drop(&mut self) {
+        if let Err(e) = self.process.request(&[OP_UNLOCK]) {
+            warn!(
+                "failed to resume remote process {}: {}",
+                self.process.pid, e
+            );
+        }
+    }
+}
+
+/// Sends a request and returns the data from a successful reply
+fn request<C: Read + Write + ?Sized>(connection: &mut C, message: &[u8]) -> Result<Vec<u8>, Error> {
+    write_message(connection, message)?;
+    let reply = read_reply(connection)?;
+    match reply.split_first() {
+        Some((&STATUS_OK, _)) => Ok(reply[1..].to_vec()),
+        _ => Err(reply_error(&reply)),
+    }
+}
+
+fn read_reply<C: Read + ?Sized>(connection: &mut C) -> Result<Vec<u8>, Error> {
+    read_message(connection)?
+        .ok_or_else(|| Error::Other("remote agent closed the connection".to_owned()))
+}
+
+fn reply_error(reply: &[u8]) -> Error {
+    let message = reply.get(1..).unwrap_or_default();
+    Error::Other(format!(
+        "remote agent error: {}",
+        String::from_utf8_lossy(message)
+    ))
+}
+
+fn write_message<C: Write + ?Sized>(connection: &mut C, message: &[u8]) -> Result<(), Error> {
+    let mut framed = Vec::with_capacity(message.len() + 4);
+    framed.extend_from_slice(&(message.len() as u32).to_le_bytes());
+    framed.extend_from_slice(message);
+    connection.write_all(&framed)?;
+    connection.flush()?;
+    Ok(())
+}
+
+/// Reads a message, returning None if the connection was closed cleanly before it started
+fn read_message<C: Read + ?Sized>(connection: &mut C) -> Result<Option<Vec<u8>>, Error> {
+    let mut length = [0_u8; 4];
+    match connection.read_exact(&mut length) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let length = u32::from_le_bytes(length) as usize;
+    if length > MAX_MESSAGE_SIZE + 1 {
+        return Err(Error::Other(format!(
+            "message of {} bytes is too large",
+            length
+        )));
+    }
+    let mut message = vec![0; length];
+    connection.read_exact(&mut message)?;
+    Ok(Some(message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_framing() {
+        let mut buffer = Vec::new();
+        write_message(&mut buffer, b"hello").unwrap();
+        assert_eq!(&buffer[..4], &[5, 0, 0, 0]);
+
+        let mut reader = buffer.as_slice();
+        assert_eq!(read_message(&mut reader).unwrap().unwrap(), b"hello");
+        assert!(read_message(&mut reader).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_serve_current_process() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let process = Process::new(std::process::id() as Pid).unwrap();
+            let (stream, _) = listener.accept().unwrap();
+            serve(&process, stream).unwrap();
+        });
+
+        let remote = RemoteProcess::connect(addr).unwrap();
+        assert_eq!(remote.pid, std::process::id() as Pid);
+        assert!(!remote.threads().unwrap().is_empty());
+
+        let value: u64 = 0x1234_5678_9abc_def0;
+        let read: u64 = remote.copy_struct(&value as *const u64 as usize).unwrap();
+        assert_eq!(read, value);
+        assert!(matches!(
+            remote.copy_struct::<u64>(0),
+            Err(Error::InvalidAddress { .. })
+        ));
+
+        drop(remote);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_serve_tcp_loopback_only() {
+        let process = Process::new(std::process::id() as Pid).unwrap();
+        assert!(serve_tcp(&process, "0.0.0.0:0", false).is_err());
+    }
+}
@@ -0,0 +1,234 @@
+//! Snapshots of selected regions of memory of a process, which can be compared against a
+//! later snapshot of the same regions to find what changed in between
+use crate::{Error, ProcessMemory};
+
+/// How much memory is compared at once when diffing. Chunks that are equal are skipped
+/// without looking at each byte.
+const CHUNK_SIZE: usize = 4096;
+
+/// A copy of some regions of memory of a process, taken with `MemorySnapshot::capture`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemorySnapshot {
+    /// The address and contents of each region, sorted by address
+    regions: Vec<(u64, Vec<u8>)>,
+}
+
+/// A range of bytes that differs between two snapshots
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MemoryChange {
+    pub addr: u64,
+    /// The bytes in the earlier snapshot
+    pub old: Vec<u8>,
+    /// The bytes in the later snapshot
+    pub new: Vec<u8>,
+}
+
+impl MemorySnapshot {
+    /// Copies each of the regions, given as an address and a length, from the process
+    pub fn capture<P: ProcessMemory>(
+        process: &P,
+        regions: &[(u64, usize)],
+    ) -> Result<MemorySnapshot, Error> {
+        let mut copied = Vec::with_capacity(regions.len());
+        for &(addr, len) in regions {
+            copied.push((addr, process.copy(addr as usize, len)?));
+        }
+        copied.sort_by_key(|(addr, _)| *addr);
+        Ok(MemorySnapshot { regions: copied })
+    }
+
+    /// Takes a new snapshot of the same regions as this one, to diff against it
+    pub fn recapture<P: ProcessMemory>(&self, process: &P) -> Result<MemorySnapshot, Error> {
+        let regions: Vec<(u64, usize)> = self
+            .regions
+            .iter()
+            .map(|(addr, data)| (*addr, data.len()))
+            .collect();
+        MemorySnapshot::capture(process, &regions)
+    }
+
+    /// Returns the address and contents of each region in the snapshot, sorted by address
+    pub fn regions(&self) -> impl Iterator<Item = (u64, &[u8])> {
+        self.regions
+            .iter()
+            .map(|(addr, data)| (*addr, data.as_slice()))
+    }
+
+    /// Returns the ranges of bytes that differ in `later`, sorted by address. Only the
+    /// regions that both snapshots have at the same address are compared, and only as far
+    /// as the shorter of the two extends.
+    pub fn diff(&self, later: &MemorySnapshot) -> Vec<MemoryChange> {
+        let mut changes = Vec::new();
+        for (addr, old) in &self.regions {
+            let new = match later.region(*addr) {
+                Some(new) => new,
+                None => continue,
+            };
+            let len = old.len().min(new.len());
+            let mut start = 0;
+            while start < len {
+                let end = (start + CHUNK_SIZE).min(len);
+                if old[start..end] != new[start..end] {
+                    diff_bytes(*addr, old, new, start, end, &mut changes);
+                }
+                start = end;
+            }
+        }
+        changes
+    }
+
+    /// Returns the addresses of the pages that have at least one byte that differs in
+    /// `later`, for pages of `page_size` bytes
+    pub fn changed_pages(&self, later: &MemorySnapshot, page_size: u64) -> Vec<u64> {
+        let mut pages: Vec<u64> = Vec::new();
+        for change in self.diff(later) {
+            let first = change.addr / page_size;
+            let last = (change.addr + change.old.len() as u64 - 1) / page_size;
+            for page in first..=last {
+                if pages.last() != Some(&(page * page_size)) {
+                    pages.push(page * page_size);
+                }
+            }
+        }
+        pages
+    }
+
+    fn region(&self, addr: u64) -> Option<&[u8]> {
+        let index = self
+            .regions
+            .binary_search_by_key(&addr, |(addr, _)| *addr)
+            .ok()?;
+        Some(&self.regions[index].1)
+    }
+}
+
+/// Adds the runs of differing bytes in `start..end` of a region, extending the last change
+/// if it ends right where the first run starts
+fn diff_bytes(
+    addr: u64,
+    old: &[u8],
+    new: &[u8],
+    start: usize,
+    end: usize,
+    changes: &mut Vec<MemoryChange>,
+) {
+    let mut i = start;
+    while i < end {
+        if old[i] == new[i] {
+            i += 1;
+            continue;
+        }
+        let run = i;
+        while i < end && old[i] != new[i] {
+            i += 1;
+        }
+        let run_addr = addr + run as u64;
+        match changes.last_mut() {
+            Some(last) if last.addr + last.old.len() as u64 == run_addr => {
+                last.old.extend_from_slice(&old[run..i]);
+                last.new.extend_from_slice(&new[run..i]);
+            }
+            _ => changes.push(MemoryChange {
+                addr: run_addr,
+                old: old[run..i].to_vec(),
+                new: new[run..i].to_vec(),
+            }),
+        }
+    }
+}
+
+impl ProcessMemory for MemorySnapshot {
+    fn read(&self, addr: usize, buf: &mut [u8]) -> Result<(), Error> {
+        let addr = addr as u64;
+        // the last region starting at or before addr is the only one that can contain it
+        let index = self.regions.partition_point(|(start, _)| *start <= addr);
+        let data = index
+            .checked_sub(1)
+            .and_then(|index| {
+                let (start, data) = &self.regions[index];
+                let offset = (addr - start) as usize;
+                data.get(offset..offset.checked_add(buf.len())?)
+            })
+            .ok_or_else(|| {
+                Error::Other(format!("address {:#x} isn't in the memory snapshot", addr))
+            })?;
+        buf.copy_from_slice(data);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LocalProcess;
+
+    #[test]
+    fn test_diff() {
+        let mut first = vec![0u8; 3 * CHUNK_SIZE];
+        let mut second = vec![7u8; 16];
+        let regions = [
+            (second.as_ptr() as u64, second.len()),
+            (first.as_ptr() as u64, first.len()),
+        ];
+        let before = MemorySnapshot::capture(&LocalProcess, &regions).unwrap();
+        assert_eq!(before.diff(&before), vec![]);
+
+        // a run that crosses a chunk boundary is reported as one change
+        first[CHUNK_SIZE - 2..CHUNK_SIZE + 2].copy_from_slice(&[1, 2, 3, 4]);
+        first[3 * CHUNK_SIZE - 1] = 5;
+        second[0] = 8;
+        let after = before.recapture(&LocalProcess).unwrap();
+
+        let base = first.as_ptr() as u64;
+        let mut expected = vec![
+            MemoryChange {
+                addr: base + CHUNK_SIZE as u64 - 2,
+                old: vec![0; 4],
+                new: vec![1, 2, 3, 4],
+            },
+            MemoryChange {
+                addr: base + 3 * CHUNK_SIZE as u64 - 1,
+                old: vec![0],
+                new: vec![5],
+            },
+            MemoryChange {
+                addr: second.as_ptr() as u64,
+                old: vec![7],
+                new: vec![8],
+            },
+        ];
+        expected.sort_by_key(|change| change.addr);
+        assert_eq!(before.diff(&after), expected);
+
+        let mut pages: Vec<u64> = expected
+            .iter()
+            .flat_map(|change| {
+                let last = change.addr + change.old.len() as u64 - 1;
+                vec![change.addr / 4096 * 4096, last / 4096 * 4096]
+            })
+            .collect();
+        pages.sort();
+        pages.dedup();
+        assert_eq!(before.changed_pages(&after, 4096), pages);
+    }
+
+    #[test]
+    fn test_read() {
+        let data: Vec<u8> = (0..32).collect();
+        let addr = data.as_ptr() as u64;
+        let snapshot =
+            MemorySnapshot::capture(&LocalProcess, &[(addr + 8, 8), (addr + 24, 8)]).unwrap();
+        assert_eq!(
+            snapshot.copy(addr as usize + 10, 4).unwrap(),
+            [10, 11, 12, 13]
+        );
+        assert_eq!(snapshot.copy(addr as usize + 24, 8).unwrap(), data[24..]);
+        assert!(snapshot.copy(addr as usize + 14, 4).is_err());
+        assert!(snapshot.copy(addr as usize, 4).is_err());
+        assert_eq!(
+            snapshot.regions().map(|(addr, _)| addr).collect::<Vec<_>>(),
+            [addr + 8, addr + 24]
+        );
+    }
+}
@@ -0,0 +1,195 @@
+//! A trait for the things that turn addresses into stack frames, so that resolvers for code
+//! the OS symbolicator doesn't know about - like JIT compiled functions, or the frames of
+//! an interpreter - can be layered over the native `Symbolicator` and used in its place.
+use crate::{Error, Frames, Module, StackFrame};
+
+/// Resolves addresses in a process to stack frames. This is implemented by the native
+/// `Symbolicator` of each platform, and can be implemented by custom resolvers that are
+/// combined with it in a `SymbolResolverChain`.
+pub trait SymbolResolver {
+    /// Reloads the resolver's view of the process, after it has loaded or generated new code
+    fn reload(&mut self) -> Result<(), Error>;
+
+    /// Calls the callback with the frames for an address, innermost inlined function first.
+    /// Resolvers should return `Error::NoBinaryForAddress` without calling the callback for
+    /// addresses they don't cover, which lets a chain try the next resolver instead.
+    fn symbolicate(
+        &self,
+        addr: u64,
+        line_info: bool,
+        callback: &mut dyn FnMut(&StackFrame),
+    ) -> Result<(), Error>;
+
+    /// Symbolicates many addresses at once, returning the frames for each in the same order
+    /// as the addresses
+    fn symbolicate_many(&self, addrs: &[u64], line_info: bool) -> Vec<Frames> {
+        addrs
+            .iter()
+            .map(|&addr| {
+                let mut frames = Vec::new();
+                self.symbolicate(addr, line_info, &mut |frame| frames.push(frame.clone()))
+                    .map(|()| frames)
+            })
+            .collect()
+    }
+
+    /// Returns the modules the resolver symbolicates, if it has any
+    fn modules(&self) -> Vec<Module> {
+        Vec::new()
+    }
+}
+
+/// Combines several resolvers into one. Each address is given to the resolvers in turn,
+/// most recently added first, until one of them covers it - so custom resolvers are added
+/// on top of a fallback like the native `Symbolicator`:
+///
+/// ```rust,ignore
+/// let mut symbolicator = SymbolResolverChain::new(process.symbolicator()?);
+/// symbolicator.add(JitResolver::new(pid));
+/// ```
+pub struct SymbolResolverChain {
+    // in the order they're tried in, so the fallback is last
+    resolvers: Vec<Box<dyn SymbolResolver>>,
+}
+
+impl SymbolResolverChain {
+    /// Creates a chain with a resolver that is tried after all the others
+    pub fn new<R: SymbolResolver + 'static>(fallback: R) -> SymbolResolverChain {
+        SymbolResolverChain {
+            resolvers: vec![Box::new(fallback)],
+        }
+    }
+
+    /// Adds a resolver, which is tried before all of the resolvers already in the chain
+    pub fn add<R: SymbolResolver + 'static>(&mut self, resolver: R) {
+        self.resolvers.insert(0, Box::new(resolver));
+    }
+}
+
+impl SymbolResolver for SymbolResolverChain {
+    fn reload(&mut self) -> Result<(), Error> {
+        for resolver in self.resolvers.iter_mut() {
+            resolver.reload()?;
+        }
+        Ok(())
+    }
+
+    fn symbolicate(
+        &self,
+        addr: u64,
+        line_info: bool,
+        callback: &mut dyn FnMut(&StackFrame),
+    ) -> Result<(), Error> {
+        for resolver in self.resolvers.iter() {
+            match resolver.symbolicate(addr, line_info, callback) {
+                Err(Error::NoBinaryForAddress(_)) => continue,
+                result => return result,
+            }
+        }
+        Err(Error::NoBinaryForAddress(addr))
+    }
+
+    /// Passes all the addresses to each resolver in a single batch, so that the native
+    /// symbolicator can resolve them in sorted order. Only the addresses that earlier
+    /// resolvers didn't cover are passed on to the next.
+    fn symbolicate_many(&self, addrs: &[u64], line_info: bool) -> Vec<Frames> {
+        let mut results: Vec<Frames> = addrs
+            .iter()
+            .map(|&addr| Err(Error::NoBinaryForAddress(addr)))
+            .collect();
+        let mut remaining: Vec<usize> = (0..addrs.len()).collect();
+        for resolver in self.resolvers.iter() {
+            if remaining.is_empty() {
+                break;
+            }
+            let batch: Vec<u64> = remaining.iter().map(|&i| addrs[i]).collect();
+            let frames = resolver.symbolicate_many(&batch, line_info);
+            let mut unresolved = Vec::new();
+            for (i, frames) in remaining.into_iter().zip(frames) {
+                match frames {
+                    Err(Error::NoBinaryForAddress(_)) => unresolved.push(i),
+                    frames => results[i] = frames,
+                }
+            }
+            remaining = unresolved;
+        }
+        results
+    }
+
+    fn modules(&self) -> Vec<Module> {
+        self.resolvers
+            .iter()
+            .flat_map(|resolver| resolver.modules())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // resolves the addresses in a range to a single frame named after the resolver
+    struct RangeResolver {
+        name: &'static str,
+        range: std::ops::Range<u64>,
+    }
+
+    impl SymbolResolver for RangeResolver {
+        fn reload(&mut self) -> Result<(), Error> {
+            Ok(())
+        }
+
+        fn symbolicate(
+            &self,
+            addr: u64,
+            _line_info: bool,
+            callback: &mut dyn FnMut(&StackFrame),
+        ) -> Result<(), Error> {
+            if !self.range.contains(&addr) {
+                return Err(Error::NoBinaryForAddress(addr));
+            }
+            callback(&StackFrame {
+                line: None,
+                column: None,
+                filename: None,
+                function: Some(self.name.to_owned()),
+                module: self.name.to_owned(),
+                addr,
+                module_offset: Some(addr - self.range.start),
+                inlined: false,
+            });
+            Ok(())
+        }
+    }
+
+    fn function(frames: &Frames) -> Option<&str> {
+        frames.as_ref().ok()?.first()?.function.as_deref()
+    }
+
+    #[test]
+    fn test_chain() {
+        let mut chain = SymbolResolverChain::new(RangeResolver {
+            name: "native",
+            range: 0x1000..0x3000,
+        });
+        chain.add(RangeResolver {
+            name: "jit",
+            range: 0x2000..0x4000,
+        });
+
+        let addrs = [0x1500, 0x2500, 0x3500, 0x5000];
+        let frames = chain.symbolicate_many(&addrs, false);
+        assert_eq!(function(&frames[0]), Some("native"));
+        assert_eq!(function(&frames[1]), Some("jit"));
+        assert_eq!(function(&frames[2]), Some("jit"));
+        assert!(matches!(frames[3], Err(Error::NoBinaryForAddress(0x5000))));
+
+        for (addr, frames) in addrs.iter().zip(frames.iter()) {
+            let mut name = None;
+            let result =
+                chain.symbolicate(*addr, false, &mut |frame| name = frame.function.clone());
+            assert_eq!(result.is_ok(), frames.is_ok());
+            assert_eq!(name.as_deref(), function(frames));
+        }
+    }
+}
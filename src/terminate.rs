@@ -0,0 +1,77 @@
+//! Stopping a process by asking it to exit, and killing it if it doesn't exit in time
+#[cfg(unix)]
+use std::time::{Duration, Instant};
+
+#[cfg(unix)]
+use crate::{Error, Pid, Process};
+
+/// How a process was stopped by `Process::terminate`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Termination {
+    /// The process exited by itself within the grace period, or had already exited
+    Exited,
+    /// The process was still running after the grace period, and was killed
+    Killed,
+}
+
+/// How often to check if the process has exited during the grace period
+#[cfg(unix)]
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+#[cfg(unix)]
+impl Process {
+    /// Sends SIGTERM to the process, and SIGKILL if it's still running after `grace`. A child
+    /// of this process stays a zombie until it's waited on, which looks like it's still
+    /// running - so children should be waited on from another thread while this runs.
+    pub fn terminate(&self, grace: Duration) -> Result<Termination, Error> {
+        if !signal(self.pid, libc::SIGTERM)? {
+            return Ok(Termination::Exited);
+        }
+        let deadline = Instant::now() + grace;
+        while Instant::now() < deadline {
+            if !signal(self.pid, 0)? {
+                return Ok(Termination::Exited);
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+        if !signal(self.pid, libc::SIGKILL)? {
+            return Ok(Termination::Exited);
+        }
+        Ok(Termination::Killed)
+    }
+}
+
+/// Sends a signal to a process, returning false if the process no longer exists. Signal 0
+/// just checks that the process exists.
+#[cfg(unix)]
+fn signal(pid: Pid, signal: libc::c_int) -> Result<bool, Error> {
+    if unsafe { libc::kill(pid, signal) } == 0 {
+        return Ok(true);
+    }
+    let error = std::io::Error::last_os_error();
+    if error.raw_os_error() == Some(libc::ESRCH) {
+        return Ok(false);
+    }
+    Err(Error::from_os_error(pid, error))
+}
+
+#[cfg(all(test, any(target_os = "linux", target_os = "macos")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_terminate() {
+        let mut child = std::process::Command::new("sleep")
+            .arg("10")
+            .spawn()
+            .unwrap();
+        let process = Process::try_from(&child).unwrap();
+        let waiter = std::thread::spawn(move || child.wait().unwrap());
+        assert_eq!(
+            process.terminate(Duration::from_secs(10)).unwrap(),
+            Termination::Exited
+        );
+        waiter.join().unwrap();
+    }
+}
@@ -0,0 +1,36 @@
+//! Traits implemented by the stack unwinders of every platform, so that code walking stacks
+//! can be written once against these rather than against each backend's own types.
+use crate::{Error, Thread};
+
+/// Creates cursors that walk the stacks of threads
+pub trait StackUnwinder {
+    type Cursor<'a>: StackCursor
+    where
+        Self: 'a;
+
+    /// Returns a cursor at the innermost frame of a thread, which needs to be locked while
+    /// the cursor is used
+    fn cursor<'a>(&'a self, thread: &Thread) -> Result<Self::Cursor<'a>, Error>;
+}
+
+/// Walks the frames of a stack. Iterating returns the instruction pointer of each frame,
+/// innermost first, and the cursor can be asked for the registers of the frame it last
+/// returned.
+pub trait StackCursor: Iterator<Item = Result<u64, Error>> {
+    /// The instruction pointer of the current frame
+    fn ip(&self) -> Result<u64, Error>;
+
+    /// The stack pointer of the current frame
+    fn sp(&self) -> Result<u64, Error>;
+
+    /// The value of a register in the current frame, by its DWARF register number. Each
+    /// backend recovers a different set of registers, and this fails for the ones it
+    /// doesn't track.
+    fn register(&self, register: u16) -> Result<u64, Error>;
+}
+
+pub(crate) fn unavailable_register(register: u16) -> Error {
+    Error::UnwindError {
+        message: format!("register {} isn't available in this frame", register),
+    }
+}
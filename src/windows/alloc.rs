@@ -0,0 +1,72 @@
+//! Allocates memory in a target process with VirtualAllocEx
+use winapi::shared::minwindef::{DWORD, FALSE};
+use winapi::shared::ntdef::NULL;
+use winapi::um::handleapi::CloseHandle;
+use winapi::um::memoryapi::{VirtualAllocEx, VirtualFreeEx};
+use winapi::um::processthreadsapi::OpenProcess;
+use winapi::um::winnt::{
+    HANDLE, MEM_COMMIT, MEM_RELEASE, MEM_RESERVE, PAGE_EXECUTE, PAGE_EXECUTE_READ,
+    PAGE_EXECUTE_READWRITE, PAGE_NOACCESS, PAGE_READONLY, PAGE_READWRITE, PROCESS_VM_OPERATION,
+};
+
+use super::Process;
+use crate::{Error, Protection};
+
+impl Process {
+    /// Allocates `len` bytes of zeroed memory in the process, returning its address
+    pub fn alloc(&self, len: usize, prot: Protection) -> Result<u64, Error> {
+        let handle = self.vm_operation_handle()?;
+        let addr = unsafe {
+            VirtualAllocEx(
+                handle,
+                NULL,
+                len,
+                MEM_COMMIT | MEM_RESERVE,
+                protection(prot),
+            )
+        };
+        let error = std::io::Error::last_os_error();
+        unsafe { CloseHandle(handle) };
+        if addr.is_null() {
+            return Err(error.into());
+        }
+        Ok(addr as u64)
+    }
+
+    /// Frees memory returned by `alloc`
+    pub fn free(&self, addr: u64) -> Result<(), Error> {
+        let handle = self.vm_operation_handle()?;
+        let ret = unsafe { VirtualFreeEx(handle, addr as _, 0, MEM_RELEASE) };
+        let error = std::io::Error::last_os_error();
+        unsafe { CloseHandle(handle) };
+        if ret == 0 {
+            return Err(error.into());
+        }
+        Ok(())
+    }
+
+    /// Opens a handle that can change the memory of the process, which the handle we keep
+    /// open for reading memory can't
+    fn vm_operation_handle(&self) -> Result<HANDLE, Error> {
+        let handle = unsafe { OpenProcess(PROCESS_VM_OPERATION, FALSE, self.pid) };
+        if handle.is_null() {
+            return Err(Error::from_os_error(
+                self.pid,
+                std::io::Error::last_os_error(),
+            ));
+        }
+        Ok(handle)
+    }
+}
+
+fn protection(prot: Protection) -> DWORD {
+    // windows has no write-only pages, so writable memory is always readable too
+    match (prot.read, prot.write, prot.execute) {
+        (_, true, true) => PAGE_EXECUTE_READWRITE,
+        (_, true, false) => PAGE_READWRITE,
+        (true, false, true) => PAGE_EXECUTE_READ,
+        (false, false, true) => PAGE_EXECUTE,
+        (true, false, false) => PAGE_READONLY,
+        (false, false, false) => PAGE_NOACCESS,
+    }
+}
@@ -0,0 +1,248 @@
+//! Lists the sockets of a process from the system wide TCP and UDP tables
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use winapi::shared::minwindef::{BOOL, DWORD, FALSE, ULONG};
+use winapi::shared::ntdef::PVOID;
+
+use super::{Pid, Process};
+use crate::{Connection, Error, Protocol, TcpState};
+
+const AF_INET: ULONG = 2;
+const AF_INET6: ULONG = 23;
+const TCP_TABLE_OWNER_PID_ALL: u32 = 5;
+const UDP_TABLE_OWNER_PID: u32 = 1;
+const ERROR_INSUFFICIENT_BUFFER: DWORD = 122;
+
+#[link(name = "iphlpapi")]
+extern "system" {
+    fn GetExtendedTcpTable(
+        table: PVOID,
+        size: *mut DWORD,
+        order: BOOL,
+        family: ULONG,
+        class: u32,
+        reserved: ULONG,
+    ) -> DWORD;
+    fn GetExtendedUdpTable(
+        table: PVOID,
+        size: *mut DWORD,
+        order: BOOL,
+        family: ULONG,
+        class: u32,
+        reserved: ULONG,
+    ) -> DWORD;
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct MibTcpRowOwnerPid {
+    state: DWORD,
+    local_addr: DWORD,
+    local_port: DWORD,
+    remote_addr: DWORD,
+    remote_port: DWORD,
+    owning_pid: DWORD,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct MibTcp6RowOwnerPid {
+    local_addr: [u8; 16],
+    _local_scope_id: DWORD,
+    local_port: DWORD,
+    remote_addr: [u8; 16],
+    _remote_scope_id: DWORD,
+    remote_port: DWORD,
+    state: DWORD,
+    owning_pid: DWORD,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct MibUdpRowOwnerPid {
+    local_addr: DWORD,
+    local_port: DWORD,
+    owning_pid: DWORD,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct MibUdp6RowOwnerPid {
+    local_addr: [u8; 16],
+    _local_scope_id: DWORD,
+    local_port: DWORD,
+    owning_pid: DWORD,
+}
+
+impl Process {
+    /// Returns the TCP and UDP sockets the process has open
+    pub fn connections(&self) -> Result<Vec<Connection>, Error> {
+        let mut connections = Vec::new();
+        for row in table::<MibTcpRowOwnerPid>(
+            self.pid,
+            GetExtendedTcpTable,
+            AF_INET,
+            TCP_TABLE_OWNER_PID_ALL,
+        )? {
+            connections.push(connection(
+                Protocol::Tcp,
+                IpAddr::V4(ipv4(row.local_addr)),
+                row.local_port,
+                Some((IpAddr::V4(ipv4(row.remote_addr)), row.remote_port)),
+                tcp_state(row.state),
+            ));
+        }
+        for row in table::<MibTcp6RowOwnerPid>(
+            self.pid,
+            GetExtendedTcpTable,
+            AF_INET6,
+            TCP_TABLE_OWNER_PID_ALL,
+        )? {
+            connections.push(connection(
+                Protocol::Tcp,
+                IpAddr::V6(Ipv6Addr::from(row.local_addr)),
+                row.local_port,
+                Some((IpAddr::V6(Ipv6Addr::from(row.remote_addr)), row.remote_port)),
+                tcp_state(row.state),
+            ));
+        }
+        for row in
+            table::<MibUdpRowOwnerPid>(self.pid, GetExtendedUdpTable, AF_INET, UDP_TABLE_OWNER_PID)?
+        {
+            connections.push(connection(
+                Protocol::Udp,
+                IpAddr::V4(ipv4(row.local_addr)),
+                row.local_port,
+                None,
+                None,
+            ));
+        }
+        for row in table::<MibUdp6RowOwnerPid>(
+            self.pid,
+            GetExtendedUdpTable,
+            AF_INET6,
+            UDP_TABLE_OWNER_PID,
+        )? {
+            connections.push(connection(
+                Protocol::Udp,
+                IpAddr::V6(Ipv6Addr::from(row.local_addr)),
+                row.local_port,
+                None,
+                None,
+            ));
+        }
+        Ok(connections)
+    }
+}
+
+/// A row of one of the tables, all of which end with the pid of the owning process
+trait OwnedRow: Copy {
+    fn owning_pid(&self) -> DWORD;
+}
+
+impl OwnedRow for MibTcpRowOwnerPid {
+    fn owning_pid(&self) -> DWORD {
+        self.owning_pid
+    }
+}
+
+impl OwnedRow for MibTcp6RowOwnerPid {
+    fn owning_pid(&self) -> DWORD {
+        self.owning_pid
+    }
+}
+
+impl OwnedRow for MibUdpRowOwnerPid {
+    fn owning_pid(&self) -> DWORD {
+        self.owning_pid
+    }
+}
+
+impl OwnedRow for MibUdp6RowOwnerPid {
+    fn owning_pid(&self) -> DWORD {
+        self.owning_pid
+    }
+}
+
+type GetTable = unsafe extern "system" fn(PVOID, *mut DWORD, BOOL, ULONG, u32, ULONG) -> DWORD;
+
+/// Returns the rows of a TCP or UDP table owned by a process
+fn table<T: OwnedRow>(pid: Pid, get: GetTable, family: ULONG, class: u32) -> Result<Vec<T>, Error> {
+    // the table is a count followed by the rows. u64s keep the buffer aligned for them, and
+    // sockets can be opened between calls so keep retrying until it's big enough
+    let mut buffer: Vec<u64> = vec![0; 2048];
+    loop {
+        let mut size = (buffer.len() * std::mem::size_of::<u64>()) as DWORD;
+        let ret = unsafe {
+            get(
+                buffer.as_mut_ptr() as PVOID,
+                &mut size,
+                FALSE,
+                family,
+                class,
+                0,
+            )
+        };
+        if ret == ERROR_INSUFFICIENT_BUFFER {
+            buffer.resize(size as usize / std::mem::size_of::<u64>() + 1024, 0);
+            continue;
+        }
+        if ret != 0 {
+            return Err(Error::from(std::io::Error::from_raw_os_error(ret as i32)));
+        }
+        break;
+    }
+
+    let rows = unsafe {
+        let table = buffer.as_ptr() as *const DWORD;
+        std::slice::from_raw_parts(table.add(1) as *const T, *table as usize)
+    };
+    Ok(rows
+        .iter()
+        .filter(|row| row.owning_pid() == pid)
+        .copied()
+        .collect())
+}
+
+fn connection(
+    protocol: Protocol,
+    local: IpAddr,
+    local_port: DWORD,
+    remote: Option<(IpAddr, DWORD)>,
+    state: Option<TcpState>,
+) -> Connection {
+    // ports are in network order in the low 16 bits
+    let remote = remote
+        .map(|(ip, port)| SocketAddr::new(ip, u16::from_be(port as u16)))
+        .filter(|remote| !(remote.ip().is_unspecified() && remote.port() == 0));
+    Connection {
+        protocol,
+        local: SocketAddr::new(local, u16::from_be(local_port as u16)),
+        remote,
+        state,
+    }
+}
+
+/// Converts an address stored in network order
+fn ipv4(addr: DWORD) -> Ipv4Addr {
+    Ipv4Addr::from(addr.to_ne_bytes())
+}
+
+/// Converts the MIB_TCP_STATE values
+fn tcp_state(state: DWORD) -> Option<TcpState> {
+    Some(match state {
+        1 => TcpState::Closed,
+        2 => TcpState::Listen,
+        3 => TcpState::SynSent,
+        4 => TcpState::SynReceived,
+        5 => TcpState::Established,
+        6 => TcpState::FinWait1,
+        7 => TcpState::FinWait2,
+        8 => TcpState::CloseWait,
+        9 => TcpState::Closing,
+        10 => TcpState::LastAck,
+        11 => TcpState::TimeWait,
+        // MIB_TCP_STATE_DELETE_TCB
+        _ => return None,
+    })
+}
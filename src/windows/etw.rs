@@ -0,0 +1,513 @@
+//! Samples the stacks of a process with the kernel's profiling events, through ETW.
+//!
+//! The NT Kernel Logger is started with SampledProfile events, and with stack walking
+//! enabled for them - so the kernel records the stack of whatever thread was running on each
+//! CPU at every profiling interrupt, and the target is never suspended. Stacks are delivered
+//! in real time to a thread that keeps the ones belonging to the target process.
+//!
+//! This needs to run as an administrator. There is only one NT Kernel Logger on a system, so
+//! this fails if another tool (like xperf or WPR) is already using it.
+use std::ffi::c_void;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use log::{debug, info, warn};
+use winapi::shared::guiddef::GUID;
+use winapi::shared::minwindef::{DWORD, ULONG};
+use winapi::shared::winerror::{ERROR_ALREADY_EXISTS, ERROR_SUCCESS};
+use winapi::um::winnt::{HANDLE, LPCWSTR, LPWSTR};
+
+use super::privilege::enable_privilege;
+use super::{Pid, Symbolicator, Tid};
+use crate::{Error, StackFrame};
+
+type TraceHandle = u64;
+
+const INVALID_PROCESSTRACE_HANDLE: TraceHandle = u64::MAX;
+
+const WNODE_FLAG_TRACED_GUID: ULONG = 0x0002_0000;
+const EVENT_TRACE_REAL_TIME_MODE: ULONG = 0x0000_0100;
+const EVENT_TRACE_FLAG_PROFILE: ULONG = 0x0100_0000;
+const EVENT_TRACE_CONTROL_STOP: ULONG = 1;
+const PROCESS_TRACE_MODE_REAL_TIME: ULONG = 0x0000_0100;
+const PROCESS_TRACE_MODE_EVENT_RECORD: ULONG = 0x1000_0000;
+
+const EVENT_HEADER_FLAG_64_BIT_HEADER: u16 = 0x0020;
+
+// TRACE_INFO_CLASS values
+const TRACE_STACK_TRACING_INFO: u32 = 3;
+const TRACE_SAMPLED_PROFILE_INTERVAL_INFO: u32 = 5;
+
+/// The opcode of SampledProfile events in the PerfInfo class
+const SAMPLED_PROFILE: u8 = 46;
+/// The opcode of StackWalk events
+const STACK_WALK: u8 = 32;
+
+/// User mode addresses are in the lower half of the address space
+const KERNEL_ADDRESS: u64 = 0x8000_0000_0000_0000;
+
+const KERNEL_LOGGER_NAME: &str = "NT Kernel Logger";
+
+/// SystemTraceControlGuid, which identifies the NT Kernel Logger
+const SYSTEM_TRACE_CONTROL_GUID: GUID = GUID {
+    Data1: 0x9e81_4aad,
+    Data2: 0x3204,
+    Data3: 0x11d2,
+    Data4: [0x9a, 0x82, 0x00, 0x60, 0x08, 0xa8, 0x69, 0x39],
+};
+
+/// PerfInfoGuid, the class of SampledProfile events
+const PERF_INFO_GUID: GUID = GUID {
+    Data1: 0xce1d_bfb4,
+    Data2: 0x137e,
+    Data3: 0x4da6,
+    Data4: [0x87, 0xb0, 0x3f, 0x59, 0xaa, 0x10, 0x2c, 0xbc],
+};
+
+/// StackWalkGuid, the class of the events holding the stacks of other events
+const STACK_WALK_GUID: GUID = GUID {
+    Data1: 0xdef2_fe46,
+    Data2: 0x7bd6,
+    Data3: 0x4b80,
+    Data4: [0xbd, 0x94, 0xf5, 0x7f, 0xe2, 0x0d, 0x0c, 0xe3],
+};
+
+#[repr(C)]
+#[allow(dead_code)]
+struct WnodeHeader {
+    buffer_size: ULONG,
+    provider_id: ULONG,
+    historical_context: u64,
+    time_stamp: i64,
+    guid: GUID,
+    client_context: ULONG,
+    flags: ULONG,
+}
+
+/// `EVENT_TRACE_PROPERTIES`, which the name of the session is written after
+#[repr(C)]
+#[allow(dead_code)]
+struct EventTraceProperties {
+    wnode: WnodeHeader,
+    buffer_size: ULONG,
+    minimum_buffers: ULONG,
+    maximum_buffers: ULONG,
+    maximum_file_size: ULONG,
+    log_file_mode: ULONG,
+    flush_timer: ULONG,
+    enable_flags: ULONG,
+    age_limit: i32,
+    number_of_buffers: ULONG,
+    free_buffers: ULONG,
+    events_lost: ULONG,
+    buffers_written: ULONG,
+    log_buffers_lost: ULONG,
+    real_time_buffers_lost: ULONG,
+    logger_thread_id: HANDLE,
+    log_file_name_offset: ULONG,
+    logger_name_offset: ULONG,
+}
+
+/// `EVENT_TRACE_PROPERTIES` along with space for the session name
+#[repr(C)]
+#[allow(dead_code)]
+struct SessionProperties {
+    properties: EventTraceProperties,
+    name: [u16; 1024],
+}
+
+#[repr(C)]
+#[allow(dead_code)]
+struct ClassicEventId {
+    event_guid: GUID,
+    event_type: u8,
+    reserved: [u8; 7],
+}
+
+#[repr(C)]
+#[allow(dead_code)]
+struct TraceProfileInterval {
+    source: ULONG,
+    interval: ULONG,
+}
+
+/// `EVENT_TRACE_LOGFILEW`, with the members that are only used for reading trace files left
+/// opaque
+#[repr(C)]
+#[allow(dead_code)]
+struct EventTraceLogfile {
+    log_file_name: LPWSTR,
+    logger_name: LPWSTR,
+    current_time: i64,
+    buffers_read: ULONG,
+    process_trace_mode: ULONG,
+    current_event: [u64; 11],
+    logfile_header: [u64; 35],
+    buffer_callback: *mut c_void,
+    buffer_size: ULONG,
+    filled: ULONG,
+    events_lost: ULONG,
+    event_record_callback: Option<unsafe extern "system" fn(*const EventRecord)>,
+    is_kernel_trace: ULONG,
+    context: *mut c_void,
+}
+
+#[repr(C)]
+#[allow(dead_code)]
+struct EventDescriptor {
+    id: u16,
+    version: u8,
+    channel: u8,
+    level: u8,
+    opcode: u8,
+    task: u16,
+    keyword: u64,
+}
+
+#[repr(C)]
+#[allow(dead_code)]
+struct EventHeader {
+    size: u16,
+    header_type: u16,
+    flags: u16,
+    event_property: u16,
+    thread_id: ULONG,
+    process_id: ULONG,
+    time_stamp: i64,
+    provider_id: GUID,
+    descriptor: EventDescriptor,
+    processor_time: u64,
+    activity_id: GUID,
+}
+
+#[repr(C)]
+#[allow(dead_code)]
+struct EventRecord {
+    header: EventHeader,
+    buffer_context: u32,
+    extended_data_count: u16,
+    user_data_length: u16,
+    extended_data: *mut c_void,
+    user_data: *const u8,
+    user_context: *mut c_void,
+}
+
+extern "system" {
+    fn StartTraceW(
+        session: *mut TraceHandle,
+        name: LPCWSTR,
+        properties: *mut EventTraceProperties,
+    ) -> ULONG;
+    fn ControlTraceW(
+        session: TraceHandle,
+        name: LPCWSTR,
+        properties: *mut EventTraceProperties,
+        control: ULONG,
+    ) -> ULONG;
+    fn TraceSetInformation(
+        session: TraceHandle,
+        class: u32,
+        information: *const c_void,
+        length: ULONG,
+    ) -> ULONG;
+    fn OpenTraceW(logfile: *mut EventTraceLogfile) -> TraceHandle;
+    fn ProcessTrace(
+        handles: *const TraceHandle,
+        count: ULONG,
+        start: *const c_void,
+        end: *const c_void,
+    ) -> ULONG;
+    fn CloseTrace(handle: TraceHandle) -> ULONG;
+}
+
+/// A stack of a thread in the target, recorded by an `EtwSampler`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EtwStack {
+    pub tid: Tid,
+    /// The time the sample was taken, as a QueryPerformanceCounter value
+    pub timestamp: u64,
+    /// The user mode return addresses of the stack, innermost first. Kernel frames are left
+    /// out, since they can't be symbolicated in the context of the target.
+    pub addrs: Vec<u64>,
+}
+
+impl EtwStack {
+    /// Symbolicates the stack, innermost frame first. Addresses that can't be symbolicated
+    /// are returned as frames without a function or module.
+    pub fn frames(&self, symbolicator: &Symbolicator, line_info: bool) -> Vec<StackFrame> {
+        let mut ret = Vec::new();
+        for (addr, frames) in self
+            .addrs
+            .iter()
+            .zip(symbolicator.symbolicate_many(&self.addrs, line_info))
+        {
+            match frames {
+                Ok(frames) => ret.extend(frames),
+                Err(_) => ret.push(StackFrame {
+                    line: None,
+                    column: None,
+                    filename: None,
+                    function: None,
+                    module: String::new(),
+                    addr: *addr,
+                    module_offset: None,
+                    inlined: false,
+                }),
+            }
+        }
+        ret
+    }
+}
+
+/// What the thread consuming events needs, passed to the event callback as its context
+struct Consumer {
+    pid: Pid,
+    sender: Sender<EtwStack>,
+}
+
+/// Samples the stacks of a process with the NT Kernel Logger. The session is stopped when
+/// this is dropped.
+pub struct EtwSampler {
+    pub pid: Pid,
+    receiver: Receiver<EtwStack>,
+    trace: TraceHandle,
+    thread: Option<JoinHandle<()>>,
+    consumer: *mut Consumer,
+}
+
+// the consumer is only accessed by the thread processing events, until it has exited
+unsafe impl Send for EtwSampler {}
+
+impl EtwSampler {
+    /// Starts sampling at the system's default profiling interval, which is 1ms
+    pub fn start(pid: Pid) -> Result<EtwSampler, Error> {
+        EtwSampler::with_interval(pid, None)
+    }
+
+    /// Starts sampling, changing the system wide profiling interval to `interval` if it's
+    /// given. The interval is rounded to the nearest 100ns, and the kernel clamps it to
+    /// between 0.1ms and 1s.
+    pub fn with_interval(pid: Pid, interval: Option<Duration>) -> Result<EtwSampler, Error> {
+        // needed to change the profiling interval, and to enable profiling on newer systems
+        if let Err(e) = enable_privilege("SeSystemProfilePrivilege") {
+            warn!("failed to enable SeSystemProfilePrivilege: {}", e);
+        }
+        if let Some(interval) = interval {
+            let interval = TraceProfileInterval {
+                source: 0,
+                interval: (interval.as_nanos() / 100).min(u32::MAX as u128) as ULONG,
+            };
+            check(unsafe {
+                TraceSetInformation(
+                    0,
+                    TRACE_SAMPLED_PROFILE_INTERVAL_INFO,
+                    &interval as *const TraceProfileInterval as *const c_void,
+                    std::mem::size_of::<TraceProfileInterval>() as ULONG,
+                )
+            })?;
+        }
+
+        let name = wide(KERNEL_LOGGER_NAME);
+        let mut properties = session_properties();
+        properties.properties.enable_flags = EVENT_TRACE_FLAG_PROFILE;
+        let mut session: TraceHandle = 0;
+        let ret = unsafe { StartTraceW(&mut session, name.as_ptr(), &mut properties.properties) };
+        if ret == ERROR_ALREADY_EXISTS {
+            return Err(Error::Other(format!(
+                "the {} is already running, stop it with `xperf -stop` or `logman stop \"{}\" -ets`",
+                KERNEL_LOGGER_NAME, KERNEL_LOGGER_NAME
+            )));
+        }
+        check(ret)?;
+
+        let stack_tracing = ClassicEventId {
+            event_guid: PERF_INFO_GUID,
+            event_type: SAMPLED_PROFILE,
+            reserved: [0; 7],
+        };
+        if let Err(e) = check(unsafe {
+            TraceSetInformation(
+                session,
+                TRACE_STACK_TRACING_INFO,
+                &stack_tracing as *const ClassicEventId as *const c_void,
+                std::mem::size_of::<ClassicEventId>() as ULONG,
+            )
+        }) {
+            stop_session();
+            return Err(e);
+        }
+
+        let (sender, receiver) = channel();
+        let consumer = Box::into_raw(Box::new(Consumer { pid, sender }));
+        let mut name = wide(KERNEL_LOGGER_NAME);
+        // zero is a valid value for the pointers and integers of EVENT_TRACE_LOGFILEW
+        let mut logfile: EventTraceLogfile = unsafe { std::mem::zeroed() };
+        logfile.logger_name = name.as_mut_ptr();
+        logfile.process_trace_mode = PROCESS_TRACE_MODE_REAL_TIME | PROCESS_TRACE_MODE_EVENT_RECORD;
+        logfile.event_record_callback = Some(event_callback);
+        logfile.context = consumer as *mut c_void;
+        let trace = unsafe { OpenTraceW(&mut logfile) };
+        if trace == INVALID_PROCESSTRACE_HANDLE {
+            let error = std::io::Error::last_os_error();
+            stop_session();
+            drop(unsafe { Box::from_raw(consumer) });
+            return Err(error.into());
+        }
+
+        // ProcessTrace delivers events until the trace is closed
+        let thread = std::thread::spawn(move || {
+            let ret = unsafe { ProcessTrace(&trace, 1, std::ptr::null(), std::ptr::null()) };
+            if ret != ERROR_SUCCESS {
+                debug!("ProcessTrace returned {}", ret);
+            }
+        });
+        info!("sampling {} with the {}", pid, KERNEL_LOGGER_NAME);
+        Ok(EtwSampler {
+            pid,
+            receiver,
+            trace,
+            thread: Some(thread),
+            consumer,
+        })
+    }
+
+    /// Returns the stacks sampled since the last call, without waiting for any more
+    pub fn stacks(&self) -> Vec<EtwStack> {
+        self.receiver.try_iter().collect()
+    }
+
+    /// Waits up to `timeout` for a stack to be sampled
+    pub fn next_stack(&self, timeout: Duration) -> Option<EtwStack> {
+        self.receiver.recv_timeout(timeout).ok()
+    }
+}
+
+impl Drop for EtwSampler {
+    fn drop(&mut self) {
+        stop_session();
+        unsafe { CloseTrace(self.trace) };
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        drop(unsafe { Box::from_raw(self.consumer) });
+    }
+}
+
+/// Receives every event of the kernel logger, passing on the stacks of the target process
+unsafe extern "system" fn event_callback(record: *const EventRecord) {
+    let record = &*record;
+    if !same_guid(&record.header.provider_id, &STACK_WALK_GUID)
+        || record.header.descriptor.opcode != STACK_WALK
+    {
+        return;
+    }
+    let consumer = &*(record.user_context as *const Consumer);
+    let data = std::slice::from_raw_parts(record.user_data, record.user_data_length as usize);
+    let pointer_size = if record.header.flags & EVENT_HEADER_FLAG_64_BIT_HEADER != 0 {
+        8
+    } else {
+        4
+    };
+    if let Some((pid, stack)) = parse_stack_walk(data, pointer_size) {
+        if pid == consumer.pid {
+            let _ = consumer.sender.send(stack);
+        }
+    }
+}
+
+/// Parses the payload of a StackWalk event, returning the process it's from and the stack
+fn parse_stack_walk(data: &[u8], pointer_size: usize) -> Option<(Pid, EtwStack)> {
+    let timestamp = u64::from_le_bytes(data.get(0..8)?.try_into().unwrap());
+    let pid = u32::from_le_bytes(data.get(8..12)?.try_into().unwrap());
+    let tid = u32::from_le_bytes(data.get(12..16)?.try_into().unwrap());
+    let addrs = data[16..]
+        .chunks_exact(pointer_size)
+        .map(|addr| match pointer_size {
+            8 => u64::from_le_bytes(addr.try_into().unwrap()),
+            _ => u32::from_le_bytes(addr.try_into().unwrap()) as u64,
+        })
+        .filter(|&addr| addr < KERNEL_ADDRESS)
+        .collect();
+    Some((
+        pid,
+        EtwStack {
+            tid,
+            timestamp,
+            addrs,
+        },
+    ))
+}
+
+/// Stops the NT Kernel Logger
+fn stop_session() {
+    let name = wide(KERNEL_LOGGER_NAME);
+    let mut properties = session_properties();
+    let ret = unsafe {
+        ControlTraceW(
+            0,
+            name.as_ptr(),
+            &mut properties.properties,
+            EVENT_TRACE_CONTROL_STOP,
+        )
+    };
+    if ret != ERROR_SUCCESS {
+        debug!("failed to stop the {}: {}", KERNEL_LOGGER_NAME, ret);
+    }
+}
+
+fn session_properties() -> Box<SessionProperties> {
+    // zero is a valid value for every member of the properties
+    let mut properties: Box<SessionProperties> = Box::new(unsafe { std::mem::zeroed() });
+    properties.properties.wnode.buffer_size = std::mem::size_of::<SessionProperties>() as ULONG;
+    properties.properties.wnode.guid = SYSTEM_TRACE_CONTROL_GUID;
+    // timestamp events with QueryPerformanceCounter
+    properties.properties.wnode.client_context = 1;
+    properties.properties.wnode.flags = WNODE_FLAG_TRACED_GUID;
+    properties.properties.log_file_mode = EVENT_TRACE_REAL_TIME_MODE;
+    properties.properties.logger_name_offset = std::mem::size_of::<EventTraceProperties>() as ULONG;
+    properties
+}
+
+fn same_guid(a: &GUID, b: &GUID) -> bool {
+    a.Data1 == b.Data1 && a.Data2 == b.Data2 && a.Data3 == b.Data3 && a.Data4 == b.Data4
+}
+
+fn wide(s: &str) -> Vec<u16> {
+    s.encode_utf16().chain(std::iter::once(0)).collect()
+}
+
+fn check(ret: ULONG) -> Result<(), Error> {
+    if ret == ERROR_SUCCESS {
+        Ok(())
+    } else {
+        Err(std::io::Error::from_raw_os_error(ret as DWORD as i32).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_stack_walk() {
+        let mut data = Vec::new();
+        data.extend_from_slice(&12345_u64.to_le_bytes());
+        data.extend_from_slice(&100_u32.to_le_bytes());
+        data.extend_from_slice(&104_u32.to_le_bytes());
+        for addr in [
+            0xffff_f800_0000_1000_u64,
+            0x7ff6_0000_1000,
+            0x7ff6_0000_2000,
+        ] {
+            data.extend_from_slice(&addr.to_le_bytes());
+        }
+        let (pid, stack) = parse_stack_walk(&data, 8).unwrap();
+        assert_eq!(pid, 100);
+        assert_eq!(stack.tid, 104);
+        assert_eq!(stack.timestamp, 12345);
+        assert_eq!(stack.addrs, vec![0x7ff6_0000_1000, 0x7ff6_0000_2000]);
+
+        assert!(parse_stack_walk(&data[..12], 8).is_none());
+    }
+}
@@ -0,0 +1,354 @@
+//! Lists the handles a process has open, from the system wide handle table returned by
+//! NtQuerySystemInformation
+use std::collections::HashMap;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::time::Duration;
+
+use log::warn;
+use winapi::shared::minwindef::{DWORD, FALSE, ULONG};
+use winapi::shared::ntdef::{NTSTATUS, PVOID, UNICODE_STRING};
+use winapi::um::handleapi::{CloseHandle, DuplicateHandle};
+use winapi::um::processthreadsapi::{GetCurrentProcess, OpenProcess};
+use winapi::um::winnt::{DUPLICATE_SAME_ACCESS, HANDLE, PROCESS_DUP_HANDLE};
+
+use super::{NtQuerySystemInformation, OwnedHandle, Pid, Process, RtlNtStatusToDosError};
+use crate::{Error, OpenFile};
+
+const SYSTEM_EXTENDED_HANDLE_INFORMATION: u32 = 64;
+const OBJECT_NAME_INFORMATION: u32 = 1;
+const OBJECT_TYPE_INFORMATION: u32 = 2;
+const STATUS_INFO_LENGTH_MISMATCH: NTSTATUS = 0xC0000004_u32 as NTSTATUS;
+const STATUS_BUFFER_OVERFLOW: NTSTATUS = 0x80000005_u32 as NTSTATUS;
+
+/// How long to wait for the name of a handle before giving up on it
+const NAME_TIMEOUT: Duration = Duration::from_millis(200);
+const FILE_TYPE_DISK: DWORD = 1;
+
+#[link(name = "ntdll")]
+extern "system" {
+    fn NtQueryObject(
+        handle: HANDLE,
+        info_class: u32,
+        info: PVOID,
+        info_len: ULONG,
+        ret_len: *mut ULONG,
+    ) -> NTSTATUS;
+}
+
+extern "system" {
+    fn GetFileType(file: HANDLE) -> DWORD;
+    fn GetFinalPathNameByHandleW(file: HANDLE, path: *mut u16, len: DWORD, flags: DWORD) -> DWORD;
+}
+
+/// `SYSTEM_HANDLE_TABLE_ENTRY_INFO_EX`, an entry in the table returned for
+/// SystemExtendedHandleInformation
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub(super) struct HandleEntry {
+    /// the address of the object in the kernel, which is the same for every handle to it
+    pub(super) object: usize,
+    pub(super) process_id: usize,
+    pub(super) handle: usize,
+    granted_access: u32,
+    _creator_back_trace_index: u16,
+    pub(super) object_type_index: u16,
+    _handle_attributes: u32,
+    _reserved: u32,
+}
+
+/// A handle a process has open, returned by `Process::handles`
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Handle {
+    /// The value of the handle in the process
+    pub handle: u64,
+    /// The access rights the handle was opened with
+    pub granted_access: u32,
+    /// The type of object the handle is for, like "File", "Event" or "Key"
+    pub type_name: Option<String>,
+    /// The name of the object, which is None for unnamed objects and for objects whose
+    /// name couldn't be read
+    pub name: Option<String>,
+}
+
+impl Process {
+    /// Returns all of the handles the process has open.
+    ///
+    /// This needs to duplicate each handle into the current process to find out about it,
+    /// handles that can't be duplicated are returned without a type or name. Getting the
+    /// name of some handles blocks forever - like synchronous pipes with a pending read - so
+    /// names are read in another thread, and given up on after a short timeout.
+    pub fn handles(&self) -> Result<Vec<Handle>, Error> {
+        let process = unsafe { OpenProcess(PROCESS_DUP_HANDLE, FALSE, self.pid) };
+        if process.is_null() {
+            return Err(Error::from_os_error(
+                self.pid,
+                std::io::Error::last_os_error(),
+            ));
+        }
+        let process = OwnedHandle(process);
+
+        // every handle to the same type of object has the same type index
+        let mut types: HashMap<u16, String> = HashMap::new();
+        let mut resolver = NameResolver::default();
+        let mut handles = Vec::new();
+        for entry in process_handles(self.pid)? {
+            let mut type_name = types.get(&entry.object_type_index).cloned();
+            let mut name = None;
+            if let Some(handle) = duplicate(process.0, entry.handle) {
+                if type_name.is_none() {
+                    type_name = object_type(handle.0);
+                    if let Some(type_name) = &type_name {
+                        types.insert(entry.object_type_index, type_name.clone());
+                    }
+                }
+                name = resolver.name(handle);
+            }
+            handles.push(Handle {
+                handle: entry.handle as u64,
+                granted_access: entry.granted_access,
+                type_name,
+                name,
+            });
+        }
+        handles.sort_by_key(|handle| handle.handle);
+        Ok(handles)
+    }
+
+    /// Returns the handles the process has open to files on disk, ordered by handle value.
+    /// Pipes, consoles and other kinds of files aren't included, since getting their names
+    /// can block.
+    pub fn open_files(&self) -> Result<Vec<OpenFile>, Error> {
+        let process = unsafe { OpenProcess(PROCESS_DUP_HANDLE, FALSE, self.pid) };
+        if process.is_null() {
+            return Err(Error::from_os_error(
+                self.pid,
+                std::io::Error::last_os_error(),
+            ));
+        }
+        let process = OwnedHandle(process);
+
+        let mut files = Vec::new();
+        for entry in process_handles(self.pid)? {
+            // handles can be closed while we're going through them, or be to objects we
+            // don't have the access to duplicate
+            let handle = match duplicate(process.0, entry.handle) {
+                Some(handle) => handle,
+                None => continue,
+            };
+            if object_type(handle.0).as_deref() != Some("File")
+                || unsafe { GetFileType(handle.0) } != FILE_TYPE_DISK
+            {
+                continue;
+            }
+            if let Some(path) = final_path(handle.0) {
+                files.push(OpenFile {
+                    fd: entry.handle as u64,
+                    path,
+                });
+            }
+        }
+        files.sort_by_key(|file| file.fd);
+        Ok(files)
+    }
+}
+
+/// Returns the entries of the system handle table belonging to a process
+fn process_handles(pid: Pid) -> Result<Vec<HandleEntry>, Error> {
+    Ok(system_handles()?
+        .into_iter()
+        .filter(|entry| entry.process_id == pid as usize)
+        .collect())
+}
+
+/// Returns every entry of the system handle table
+pub(super) fn system_handles() -> Result<Vec<HandleEntry>, Error> {
+    // the table can grow between calls, so keep retrying with a bigger buffer. u64s keep the
+    // buffer aligned for the entries
+    let mut buffer: Vec<u64> = vec![0; 1 << 16];
+    loop {
+        let len = buffer.len() * std::mem::size_of::<u64>();
+        let mut needed: ULONG = 0;
+        let ret = unsafe {
+            NtQuerySystemInformation(
+                SYSTEM_EXTENDED_HANDLE_INFORMATION,
+                buffer.as_mut_ptr() as PVOID,
+                len as ULONG,
+                &mut needed,
+            )
+        };
+        if ret == STATUS_INFO_LENGTH_MISMATCH {
+            let needed = (needed as usize).max(len * 2);
+            buffer.resize(needed / std::mem::size_of::<u64>() + 1024, 0);
+            continue;
+        }
+        if ret != 0 {
+            return Err(Error::from(std::io::Error::from_raw_os_error(unsafe {
+                RtlNtStatusToDosError(ret) as i32
+            })));
+        }
+        break;
+    }
+
+    // the table is a count and a reserved field, followed by the entries
+    let count = buffer[0] as usize;
+    let entries = unsafe {
+        std::slice::from_raw_parts(
+            (buffer.as_ptr() as *const usize).add(2) as *const HandleEntry,
+            count,
+        )
+    };
+    Ok(entries.to_vec())
+}
+
+/// Duplicates a handle from another process into ours
+pub(super) fn duplicate(process: HANDLE, handle: usize) -> Option<OwnedHandle> {
+    let mut duplicate: HANDLE = std::ptr::null_mut();
+    let ret = unsafe {
+        DuplicateHandle(
+            process,
+            handle as HANDLE,
+            GetCurrentProcess(),
+            &mut duplicate,
+            0,
+            FALSE,
+            DUPLICATE_SAME_ACCESS,
+        )
+    };
+    if ret == 0 {
+        return None;
+    }
+    Some(OwnedHandle(duplicate))
+}
+
+/// Returns the name of the type of object a handle refers to, like "File" or "Event"
+fn object_type(handle: HANDLE) -> Option<String> {
+    // PUBLIC_OBJECT_TYPE_INFORMATION is the type name followed by reserved fields, and the
+    // name is stored in the buffer after it
+    let mut buffer: Vec<u64> = vec![0; 512];
+    let mut needed: ULONG = 0;
+    let ret = unsafe {
+        NtQueryObject(
+            handle,
+            OBJECT_TYPE_INFORMATION,
+            buffer.as_mut_ptr() as PVOID,
+            (buffer.len() * std::mem::size_of::<u64>()) as ULONG,
+            &mut needed,
+        )
+    };
+    if ret != 0 {
+        return None;
+    }
+    let name = unsafe { &*(buffer.as_ptr() as *const UNICODE_STRING) };
+    Some(unicode_string(name))
+}
+
+/// Reads the names of handles in a worker thread, so that handles whose names can't be read
+/// don't hang the caller
+#[derive(Default)]
+struct NameResolver {
+    worker: Option<(Sender<usize>, Receiver<Option<String>>)>,
+}
+
+impl NameResolver {
+    /// Returns the name of the object a handle is for, taking ownership of the handle
+    fn name(&mut self, handle: OwnedHandle) -> Option<String> {
+        let (requests, names) = self.worker.get_or_insert_with(|| {
+            let (requests, received) = channel::<usize>();
+            let (sender, names) = channel();
+            std::thread::spawn(move || {
+                for handle in received {
+                    // handles aren't Send, so are passed to the worker as integers
+                    let handle = OwnedHandle(handle as HANDLE);
+                    if sender.send(object_name(handle.0)).is_err() {
+                        break;
+                    }
+                }
+            });
+            (requests, names)
+        });
+
+        // the worker closes the handle once it's done with it, which might be never
+        let raw = handle.0 as usize;
+        std::mem::forget(handle);
+        if requests.send(raw).is_err() {
+            unsafe { CloseHandle(raw as HANDLE) };
+            self.worker = None;
+            return None;
+        }
+        match names.recv_timeout(NAME_TIMEOUT) {
+            Ok(name) => name,
+            Err(_) => {
+                // the worker is stuck, so leave it behind and start a new one for the rest
+                warn!("timed out getting the name of handle {:#x}", raw);
+                self.worker = None;
+                None
+            }
+        }
+    }
+}
+
+/// Returns the name of the object a handle is for, or None if it's unnamed
+fn object_name(handle: HANDLE) -> Option<String> {
+    // OBJECT_NAME_INFORMATION is a UNICODE_STRING, with the name stored after it
+    let mut buffer: Vec<u64> = vec![0; 128];
+    loop {
+        let mut needed: ULONG = 0;
+        let ret = unsafe {
+            NtQueryObject(
+                handle,
+                OBJECT_NAME_INFORMATION,
+                buffer.as_mut_ptr() as PVOID,
+                (buffer.len() * std::mem::size_of::<u64>()) as ULONG,
+                &mut needed,
+            )
+        };
+        if (ret == STATUS_INFO_LENGTH_MISMATCH || ret == STATUS_BUFFER_OVERFLOW)
+            && needed as usize > buffer.len() * std::mem::size_of::<u64>()
+        {
+            buffer.resize(needed as usize / std::mem::size_of::<u64>() + 1, 0);
+            continue;
+        }
+        if ret != 0 {
+            return None;
+        }
+        let name = unicode_string(unsafe { &*(buffer.as_ptr() as *const UNICODE_STRING) });
+        return if name.is_empty() { None } else { Some(name) };
+    }
+}
+
+/// Returns the path of a file on disk, without the \\?\ prefix
+fn final_path(file: HANDLE) -> Option<String> {
+    let mut path: Vec<u16> = vec![0; 1024];
+    loop {
+        let len =
+            unsafe { GetFinalPathNameByHandleW(file, path.as_mut_ptr(), path.len() as DWORD, 0) }
+                as usize;
+        if len == 0 {
+            return None;
+        }
+        // a length larger than the buffer is the size needed, including the terminator
+        if len >= path.len() {
+            path.resize(len + 1, 0);
+            continue;
+        }
+        let path = String::from_utf16_lossy(&path[..len]);
+        return Some(match path.strip_prefix(r"\\?\UNC\") {
+            Some(share) => format!(r"\\{}", share),
+            None => path.strip_prefix(r"\\?\").unwrap_or(&path).to_owned(),
+        });
+    }
+}
+
+fn unicode_string(string: &UNICODE_STRING) -> String {
+    if string.Buffer.is_null() {
+        return String::new();
+    }
+    let chars = unsafe {
+        std::slice::from_raw_parts(
+            string.Buffer,
+            string.Length as usize / std::mem::size_of::<u16>(),
+        )
+    };
+    String::from_utf16_lossy(chars)
+}
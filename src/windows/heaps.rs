@@ -0,0 +1,191 @@
+//! Walks the NT heaps of a process with RtlQueryProcessDebugInformation, which runs a thread
+//! in the target to collect the heap information into a section shared with us
+use winapi::shared::minwindef::ULONG;
+use winapi::shared::ntdef::{BOOLEAN, HANDLE, NTSTATUS, PVOID};
+
+use super::{Process, RtlNtStatusToDosError};
+use crate::Error;
+
+const PDI_HEAPS: ULONG = 0x04;
+const PDI_HEAP_BLOCKS: ULONG = 0x10;
+
+const RTL_HEAP_BUSY: u16 = 0x0001;
+const RTL_HEAP_SEGMENT: u16 = 0x0002;
+const RTL_HEAP_UNCOMMITTED_RANGE: u16 = 0x0100;
+
+#[link(name = "ntdll")]
+extern "system" {
+    fn RtlCreateQueryDebugBuffer(maximum_commit: ULONG, use_event_pair: BOOLEAN) -> PVOID;
+    fn RtlQueryProcessDebugInformation(pid: HANDLE, flags: ULONG, buffer: PVOID) -> NTSTATUS;
+    fn RtlDestroyQueryDebugBuffer(buffer: PVOID) -> NTSTATUS;
+}
+
+/// The start of `RTL_DEBUG_INFORMATION`, up to the heaps
+#[repr(C)]
+struct DebugInformation {
+    _section_handle_client: HANDLE,
+    _view_base_client: PVOID,
+    _view_base_target: PVOID,
+    _view_base_delta: usize,
+    _event_pair_client: HANDLE,
+    _event_pair_target: HANDLE,
+    _target_process_id: HANDLE,
+    _target_thread_handle: HANDLE,
+    _flags: ULONG,
+    _offset_free: usize,
+    _commit_size: usize,
+    _view_size: usize,
+    _modules: PVOID,
+    _back_traces: PVOID,
+    heaps: *const ProcessHeaps,
+}
+
+/// `RTL_PROCESS_HEAPS`, a count followed by that many `RTL_HEAP_INFORMATION`s
+#[repr(C)]
+struct ProcessHeaps {
+    number_of_heaps: ULONG,
+    heaps: [HeapInformation; 1],
+}
+
+#[repr(C)]
+struct HeapInformation {
+    base_address: PVOID,
+    flags: ULONG,
+    entry_overhead: u16,
+    _creator_back_trace_index: u16,
+    bytes_allocated: usize,
+    bytes_committed: usize,
+    _number_of_tags: ULONG,
+    number_of_entries: ULONG,
+    _number_of_pseudo_tags: ULONG,
+    _pseudo_tag_granularity: ULONG,
+    _reserved: [ULONG; 5],
+    _tags: PVOID,
+    entries: *const HeapEntry,
+}
+
+/// `RTL_HEAP_ENTRY`. For segments the last two fields are the committed size and the
+/// address of the first block, for blocks they're a settable value and a tag
+#[repr(C)]
+struct HeapEntry {
+    size: usize,
+    flags: u16,
+    _allocator_back_trace_index: u16,
+    _committed_size: usize,
+    first_block: usize,
+}
+
+/// A heap of a process, returned by `Process::heaps`
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Heap {
+    /// The address of the heap, which is the HANDLE returned by HeapCreate
+    pub base: u64,
+    /// The HEAP_* flags the heap was created with
+    pub flags: u32,
+    /// The bytes allocated from the heap, in busy blocks
+    pub bytes_allocated: u64,
+    pub bytes_committed: u64,
+    /// The bytes of each block used by the heap's bookkeeping
+    pub entry_overhead: u64,
+    pub blocks: Vec<HeapBlock>,
+}
+
+/// A block of memory in a heap
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HeapBlock {
+    /// The start of the block, including the heap's header for it
+    pub address: u64,
+    pub size: u64,
+    /// True if the block is allocated, false if it's free
+    pub busy: bool,
+}
+
+impl Heap {
+    /// Returns the blocks that are allocated
+    pub fn allocations(&self) -> impl Iterator<Item = &HeapBlock> {
+        self.blocks.iter().filter(|block| block.busy)
+    }
+}
+
+impl Process {
+    /// Returns the heaps of the process, along with every block in them.
+    ///
+    /// The heap information is gathered by a thread that ntdll starts in the target, so this
+    /// needs the target to be running, and only reports the native heaps of WoW64 processes.
+    pub fn heaps(&self) -> Result<Vec<Heap>, Error> {
+        let buffer = unsafe { RtlCreateQueryDebugBuffer(0, 0) };
+        if buffer.is_null() {
+            return Err(Error::Other(
+                "failed to create a debug buffer for the heaps".to_owned(),
+            ));
+        }
+        let ret = unsafe {
+            RtlQueryProcessDebugInformation(
+                self.pid as usize as HANDLE,
+                PDI_HEAPS | PDI_HEAP_BLOCKS,
+                buffer,
+            )
+        };
+        let heaps = if ret == 0 {
+            Ok(unsafe { read_heaps(&*(buffer as *const DebugInformation)) })
+        } else {
+            Err(Error::from_os_error(
+                self.pid,
+                std::io::Error::from_raw_os_error(unsafe { RtlNtStatusToDosError(ret) } as i32),
+            ))
+        };
+        unsafe { RtlDestroyQueryDebugBuffer(buffer) };
+        heaps
+    }
+}
+
+unsafe fn read_heaps(info: &DebugInformation) -> Vec<Heap> {
+    if info.heaps.is_null() {
+        return Vec::new();
+    }
+    let count = (*info.heaps).number_of_heaps as usize;
+    let heaps = std::slice::from_raw_parts((*info.heaps).heaps.as_ptr(), count);
+    heaps
+        .iter()
+        .map(|heap| {
+            let entries = if heap.entries.is_null() {
+                &[]
+            } else {
+                std::slice::from_raw_parts(heap.entries, heap.number_of_entries as usize)
+            };
+            Heap {
+                base: heap.base_address as u64,
+                flags: heap.flags,
+                bytes_allocated: heap.bytes_allocated as u64,
+                bytes_committed: heap.bytes_committed as u64,
+                entry_overhead: heap.entry_overhead as u64,
+                blocks: heap_blocks(entries),
+            }
+        })
+        .collect()
+}
+
+/// Finds the blocks of a heap from its entries. Blocks don't include their addresses, but
+/// follow each other from the first block of the segment they're in.
+fn heap_blocks(entries: &[HeapEntry]) -> Vec<HeapBlock> {
+    let mut blocks = Vec::new();
+    let mut address = 0;
+    for entry in entries {
+        if entry.flags & RTL_HEAP_SEGMENT != 0 {
+            address = entry.first_block as u64;
+            continue;
+        }
+        let size = entry.size as u64;
+        if entry.flags & RTL_HEAP_UNCOMMITTED_RANGE == 0 {
+            blocks.push(HeapBlock {
+                address,
+                size,
+                busy: entry.flags & RTL_HEAP_BUSY != 0,
+            });
+        }
+        address += size;
+    }
+    blocks
+}
@@ -0,0 +1,182 @@
+//! Loads DLLs into a target process, by starting a thread in it that calls LoadLibraryW
+use std::os::windows::ffi::OsStrExt;
+use std::path::Path;
+
+use log::info;
+use winapi::shared::minwindef::{BOOL, DWORD, FALSE, HMODULE, LPCVOID, LPVOID};
+use winapi::shared::ntdef::{LPCSTR, LPCWSTR, NULL};
+use winapi::um::memoryapi::{VirtualAllocEx, VirtualFreeEx, WriteProcessMemory};
+use winapi::um::processthreadsapi::{
+    CreateRemoteThread, GetCurrentProcess, GetExitCodeThread, OpenProcess,
+};
+use winapi::um::winnt::{
+    HANDLE, MEM_COMMIT, MEM_RELEASE, MEM_RESERVE, PAGE_READWRITE, PROCESS_CREATE_THREAD,
+    PROCESS_QUERY_INFORMATION, PROCESS_VM_OPERATION, PROCESS_VM_READ, PROCESS_VM_WRITE,
+};
+
+use super::{is_wow64, OwnedHandle, Process};
+use crate::Error;
+
+const INFINITE: DWORD = 0xffffffff;
+const WAIT_FAILED: DWORD = 0xffffffff;
+
+extern "system" {
+    fn GetModuleHandleW(name: LPCWSTR) -> HMODULE;
+    fn GetProcAddress(module: HMODULE, name: LPCSTR) -> LPVOID;
+    fn WaitForSingleObject(handle: HANDLE, milliseconds: DWORD) -> DWORD;
+    fn K32EnumProcessModules(
+        process: HANDLE,
+        modules: *mut HMODULE,
+        size: DWORD,
+        needed: *mut DWORD,
+    ) -> BOOL;
+}
+
+impl Process {
+    /// Loads a DLL into the process with LoadLibraryW, returning the address it was loaded
+    /// at. The DLL runs its DllMain in a new thread of the process, which this waits for.
+    ///
+    /// This relies on kernel32 being loaded at the same address in every process, so the
+    /// target has to have the same bitness as the current process.
+    pub fn inject_library<P: AsRef<Path>>(&self, path: P) -> Result<u64, Error> {
+        let path = path.as_ref();
+        if self.is_wow64()? != is_wow64(unsafe { GetCurrentProcess() })? {
+            return Err(Error::Other(
+                "can't inject into a process with a different bitness".to_owned(),
+            ));
+        }
+        let name: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+        let size = name.len() * std::mem::size_of::<u16>();
+
+        let access = PROCESS_CREATE_THREAD
+            | PROCESS_QUERY_INFORMATION
+            | PROCESS_VM_OPERATION
+            | PROCESS_VM_WRITE
+            | PROCESS_VM_READ;
+        let process = unsafe { OpenProcess(access, FALSE, self.pid) };
+        if process.is_null() {
+            return Err(Error::from_os_error(
+                self.pid,
+                std::io::Error::last_os_error(),
+            ));
+        }
+        let process = OwnedHandle(process);
+
+        let load_library = unsafe {
+            let kernel32: Vec<u16> = "kernel32.dll\0".encode_utf16().collect();
+            GetProcAddress(
+                GetModuleHandleW(kernel32.as_ptr()),
+                b"LoadLibraryW\0".as_ptr() as LPCSTR,
+            )
+        };
+        if load_library.is_null() {
+            return Err(std::io::Error::last_os_error().into());
+        }
+
+        let addr = unsafe {
+            VirtualAllocEx(
+                process.0,
+                NULL,
+                size,
+                MEM_COMMIT | MEM_RESERVE,
+                PAGE_READWRITE,
+            )
+        };
+        if addr.is_null() {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        let result = run_load_library(process.0, load_library, addr, &name);
+        unsafe { VirtualFreeEx(process.0, addr, 0, MEM_RELEASE) };
+
+        // the thread's exit code is the low 32 bits of the module handle, which is enough to
+        // pick it out of the modules of the process
+        let handle = result?;
+        if handle == 0 {
+            return Err(Error::Other(format!(
+                "LoadLibraryW failed to load {} into {}",
+                path.display(),
+                self.pid
+            )));
+        }
+        let base = loaded_modules(process.0)?
+            .into_iter()
+            .find(|module| *module as u64 & 0xffffffff == handle as u64)
+            .ok_or_else(|| Error::Other(format!("{} isn't loaded", path.display())))?;
+        info!(
+            "loaded {} into {} at {:#x}",
+            path.display(),
+            self.pid,
+            base as u64
+        );
+        Ok(base as u64)
+    }
+}
+
+/// Writes the name of the DLL into the process, and runs LoadLibraryW on it in a new thread,
+/// returning the thread's exit code
+fn run_load_library(
+    process: HANDLE,
+    load_library: LPVOID,
+    addr: LPVOID,
+    name: &[u16],
+) -> Result<DWORD, Error> {
+    let size = std::mem::size_of_val(name);
+    let ret = unsafe {
+        WriteProcessMemory(
+            process,
+            addr,
+            name.as_ptr() as LPCVOID,
+            size,
+            std::ptr::null_mut(),
+        )
+    };
+    if ret == 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+
+    let thread = unsafe {
+        CreateRemoteThread(
+            process,
+            std::ptr::null_mut(),
+            0,
+            Some(std::mem::transmute::<
+                LPVOID,
+                unsafe extern "system" fn(LPVOID) -> DWORD,
+            >(load_library)),
+            addr,
+            0,
+            std::ptr::null_mut(),
+        )
+    };
+    if thread.is_null() {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    let thread = OwnedHandle(thread);
+    if unsafe { WaitForSingleObject(thread.0, INFINITE) } == WAIT_FAILED {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    let mut code: DWORD = 0;
+    if unsafe { GetExitCodeThread(thread.0, &mut code) } == 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(code)
+}
+
+fn loaded_modules(process: HANDLE) -> Result<Vec<HMODULE>, Error> {
+    let mut modules: Vec<HMODULE> = vec![std::ptr::null_mut(); 1024];
+    loop {
+        let size = (modules.len() * std::mem::size_of::<HMODULE>()) as DWORD;
+        let mut needed: DWORD = 0;
+        let ret =
+            unsafe { K32EnumProcessModules(process, modules.as_mut_ptr(), size, &mut needed) };
+        if ret == 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        let count = needed as usize / std::mem::size_of::<HMODULE>();
+        if needed <= size {
+            modules.truncate(count);
+            return Ok(modules);
+        }
+        modules.resize(count, std::ptr::null_mut());
+    }
+}
@@ -0,0 +1,208 @@
+//! Finds the job object a process is in, and reads its limits and accounting.
+//!
+//! There's no way to open the job of another process directly, so this looks through the
+//! system handle table for a handle to a job that contains the process, and duplicates it.
+use std::collections::HashSet;
+use std::time::Duration;
+
+use winapi::shared::minwindef::{BOOL, DWORD, FALSE, LPVOID};
+use winapi::shared::ntdef::{LPCWSTR, NULL};
+use winapi::um::minwinbase::LPSECURITY_ATTRIBUTES;
+use winapi::um::processthreadsapi::{GetCurrentProcessId, OpenProcess};
+use winapi::um::winnt::{
+    HANDLE, JOBOBJECT_BASIC_ACCOUNTING_INFORMATION, JOBOBJECT_EXTENDED_LIMIT_INFORMATION,
+    JOB_OBJECT_LIMIT_ACTIVE_PROCESS, JOB_OBJECT_LIMIT_JOB_MEMORY, JOB_OBJECT_LIMIT_PROCESS_MEMORY,
+    PROCESS_DUP_HANDLE,
+};
+
+use super::handles::{duplicate, system_handles};
+use super::{OwnedHandle, Process};
+use crate::Error;
+
+const JOB_OBJECT_BASIC_ACCOUNTING_INFORMATION: u32 = 1;
+const JOB_OBJECT_EXTENDED_LIMIT_INFORMATION: u32 = 9;
+const JOB_OBJECT_CPU_RATE_CONTROL_INFORMATION: u32 = 15;
+
+const JOB_OBJECT_CPU_RATE_CONTROL_ENABLE: DWORD = 0x1;
+const JOB_OBJECT_CPU_RATE_CONTROL_HARD_CAP: DWORD = 0x4;
+
+extern "system" {
+    fn IsProcessInJob(process: HANDLE, job: HANDLE, result: *mut BOOL) -> BOOL;
+    fn CreateJobObjectW(attributes: LPSECURITY_ATTRIBUTES, name: LPCWSTR) -> HANDLE;
+    fn QueryInformationJobObject(
+        job: HANDLE,
+        class: u32,
+        info: LPVOID,
+        len: DWORD,
+        ret_len: *mut DWORD,
+    ) -> BOOL;
+}
+
+/// `JOBOBJECT_CPU_RATE_CONTROL_INFORMATION`, where the rate is a union with the weight and
+/// the min and max rates
+#[repr(C)]
+struct CpuRateControl {
+    control_flags: DWORD,
+    cpu_rate: DWORD,
+}
+
+/// The limits and accounting of the job a process is in
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct JobInfo {
+    /// The JOB_OBJECT_LIMIT_* flags of the job
+    pub limit_flags: u32,
+    pub active_processes: u32,
+    pub total_processes: u32,
+    /// The CPU time used by every process that has been in the job
+    pub total_user_time: Duration,
+    pub total_kernel_time: Duration,
+    /// The commit limit of each process in the job, in bytes
+    pub process_memory_limit: Option<u64>,
+    /// The commit limit of the job as a whole, in bytes
+    pub job_memory_limit: Option<u64>,
+    pub active_process_limit: Option<u32>,
+    /// The hard cap on the CPU the job can use, in hundredths of a percent of the machine
+    pub cpu_rate_limit: Option<u32>,
+}
+
+impl Process {
+    /// True if the process is in a job object
+    pub fn is_in_job(&self) -> Result<bool, Error> {
+        let mut in_job: BOOL = FALSE;
+        if unsafe { IsProcessInJob(*self.handle as HANDLE, NULL, &mut in_job) } == 0 {
+            return Err(Error::from_os_error(
+                self.pid,
+                std::io::Error::last_os_error(),
+            ));
+        }
+        Ok(in_job != FALSE)
+    }
+
+    /// Returns the limits and accounting of the job the process is in, or None if it isn't
+    /// in one. For nested jobs this is the innermost job.
+    ///
+    /// This needs a handle to the job to be open in some process we can duplicate handles
+    /// from - which fails when the job was created by a more privileged process.
+    pub fn job_info(&self) -> Result<Option<JobInfo>, Error> {
+        if !self.is_in_job()? {
+            return Ok(None);
+        }
+        let job = self.find_job()?.ok_or_else(|| {
+            Error::Other(format!(
+                "process {} is in a job, but no handle to it could be found",
+                self.pid
+            ))
+        })?;
+
+        let accounting: JOBOBJECT_BASIC_ACCOUNTING_INFORMATION =
+            query(job.0, JOB_OBJECT_BASIC_ACCOUNTING_INFORMATION)?;
+        let limits: JOBOBJECT_EXTENDED_LIMIT_INFORMATION =
+            query(job.0, JOB_OBJECT_EXTENDED_LIMIT_INFORMATION)?;
+        // CPU rate control was added in windows 8
+        let cpu_rate: Option<CpuRateControl> =
+            query(job.0, JOB_OBJECT_CPU_RATE_CONTROL_INFORMATION).ok();
+
+        let flags = limits.BasicLimitInformation.LimitFlags;
+        let limit = |flag: DWORD, value: u64| if flags & flag != 0 { Some(value) } else { None };
+        let hard_cap = JOB_OBJECT_CPU_RATE_CONTROL_ENABLE | JOB_OBJECT_CPU_RATE_CONTROL_HARD_CAP;
+        Ok(Some(JobInfo {
+            limit_flags: flags,
+            active_processes: accounting.ActiveProcesses,
+            total_processes: accounting.TotalProcesses,
+            total_user_time: filetime_duration(unsafe { *accounting.TotalUserTime.QuadPart() }),
+            total_kernel_time: filetime_duration(unsafe { *accounting.TotalKernelTime.QuadPart() }),
+            process_memory_limit: limit(
+                JOB_OBJECT_LIMIT_PROCESS_MEMORY,
+                limits.ProcessMemoryLimit as u64,
+            ),
+            job_memory_limit: limit(JOB_OBJECT_LIMIT_JOB_MEMORY, limits.JobMemoryLimit as u64),
+            active_process_limit: limit(
+                JOB_OBJECT_LIMIT_ACTIVE_PROCESS,
+                limits.BasicLimitInformation.ActiveProcessLimit as u64,
+            )
+            .map(|limit| limit as u32),
+            cpu_rate_limit: cpu_rate
+                .filter(|rate| rate.control_flags & hard_cap == hard_cap)
+                .map(|rate| rate.cpu_rate),
+        }))
+    }
+
+    /// Finds a handle to the innermost job containing the process, by trying every handle
+    /// to a job in the system
+    fn find_job(&self) -> Result<Option<OwnedHandle>, Error> {
+        let job_type = job_type_index()?;
+        let mut seen = HashSet::new();
+        let mut innermost: Option<(u32, OwnedHandle)> = None;
+        for entry in system_handles()? {
+            if entry.object_type_index != job_type || seen.contains(&entry.object) {
+                continue;
+            }
+            let owner = unsafe { OpenProcess(PROCESS_DUP_HANDLE, FALSE, entry.process_id as _) };
+            if owner.is_null() {
+                continue;
+            }
+            let owner = OwnedHandle(owner);
+            let job = match duplicate(owner.0, entry.handle) {
+                Some(job) => job,
+                None => continue,
+            };
+            // other handles to a job only need trying if this one couldn't be duplicated
+            seen.insert(entry.object);
+            let mut in_job: BOOL = FALSE;
+            let ret = unsafe { IsProcessInJob(*self.handle as HANDLE, job.0, &mut in_job) };
+            if ret == 0 || in_job == FALSE {
+                continue;
+            }
+            // jobs nest, and the innermost has the fewest processes in it
+            let accounting: JOBOBJECT_BASIC_ACCOUNTING_INFORMATION =
+                match query(job.0, JOB_OBJECT_BASIC_ACCOUNTING_INFORMATION) {
+                    Ok(accounting) => accounting,
+                    Err(_) => continue,
+                };
+            match &innermost {
+                Some((active, _)) if *active <= accounting.ActiveProcesses => {}
+                _ => innermost = Some((accounting.ActiveProcesses, job)),
+            }
+        }
+        Ok(innermost.map(|(_, job)| job))
+    }
+}
+
+/// Returns the index of the job object type in the system handle table, by creating a job
+/// and looking for our handle to it
+fn job_type_index() -> Result<u16, Error> {
+    let job = unsafe { CreateJobObjectW(std::ptr::null_mut(), std::ptr::null()) };
+    if job.is_null() {
+        return Err(Error::from(std::io::Error::last_os_error()));
+    }
+    let job = OwnedHandle(job);
+    let pid = unsafe { GetCurrentProcessId() } as usize;
+    system_handles()?
+        .iter()
+        .find(|entry| entry.process_id == pid && entry.handle == job.0 as usize)
+        .map(|entry| entry.object_type_index)
+        .ok_or_else(|| Error::Other("failed to find the type of job objects".to_owned()))
+}
+
+fn query<T>(job: HANDLE, class: u32) -> Result<T, Error> {
+    let mut info: T = unsafe { std::mem::zeroed() };
+    let ret = unsafe {
+        QueryInformationJobObject(
+            job,
+            class,
+            &mut info as *mut T as LPVOID,
+            std::mem::size_of::<T>() as DWORD,
+            std::ptr::null_mut(),
+        )
+    };
+    if ret == 0 {
+        return Err(Error::from(std::io::Error::last_os_error()));
+    }
+    Ok(info)
+}
+
+/// Converts a time in 100ns units
+fn filetime_duration(time: i64) -> Duration {
+    Duration::from_nanos(time.max(0) as u64 * 100)
+}
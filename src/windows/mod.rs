@@ -1,37 +1,166 @@
 use std::ffi::OsString;
 use std::os::windows::ffi::OsStringExt;
-use std::os::windows::io::RawHandle;
-use winapi::shared::minwindef::{DWORD, FALSE, MAX_PATH, ULONG};
+use std::os::windows::io::{AsRawHandle, RawHandle};
+
+use log::warn;
+use winapi::shared::minwindef::{BOOL, DWORD, FALSE, HMODULE, LPVOID, MAX_PATH, ULONG};
 use winapi::shared::ntdef::PUNICODE_STRING;
-use winapi::shared::ntdef::{NTSTATUS, NULL, PVOID, USHORT, VOID};
+use winapi::shared::ntdef::{LPCSTR, LPCWSTR, NTSTATUS, NULL, PVOID, USHORT, VOID};
+use winapi::shared::winerror::ERROR_ACCESS_DENIED;
+use winapi::um::handleapi::{CloseHandle, DuplicateHandle};
+use winapi::um::minwinbase::STILL_ACTIVE;
 use winapi::um::processthreadsapi::{
-    GetThreadId, OpenProcess, OpenThread, ResumeThread, SuspendThread,
+    GetCurrentProcess, GetExitCodeThread, GetProcessId, GetThreadId, OpenProcess, OpenThread,
+    ResumeThread, SuspendThread,
 };
-use winapi::um::winbase::QueryFullProcessImageNameW;
+use winapi::um::winbase::{GetProcessIoCounters, QueryFullProcessImageNameW};
 use winapi::um::winnt::{
-    ACCESS_MASK, HANDLE, MAXIMUM_ALLOWED, PROCESS_QUERY_INFORMATION, PROCESS_SUSPEND_RESUME,
-    PROCESS_VM_READ, THREAD_ALL_ACCESS, THREAD_GET_CONTEXT, THREAD_QUERY_INFORMATION, WCHAR,
+    ACCESS_MASK, DUPLICATE_SAME_ACCESS, HANDLE, IMAGE_FILE_MACHINE_AMD64, IMAGE_FILE_MACHINE_ARM64,
+    IMAGE_FILE_MACHINE_ARMNT, IMAGE_FILE_MACHINE_I386, IMAGE_FILE_MACHINE_UNKNOWN, IO_COUNTERS,
+    MAXIMUM_ALLOWED, PROCESS_QUERY_INFORMATION, PROCESS_SUSPEND_RESUME, PROCESS_VM_READ,
+    THREAD_ALL_ACCESS, THREAD_GET_CONTEXT, THREAD_QUERY_INFORMATION, WCHAR,
 };
+use winapi::um::wow64apiset::IsWow64Process;
 
 pub use read_process_memory::{CopyAddress, Pid, ProcessHandle};
 
 pub type Tid = Pid;
 
-use super::Error;
-
+use super::{Architecture, Endianness, Error, IoCounters, ReadOptions, TargetArch};
+
+mod alloc;
+mod connections;
+#[cfg(all(feature = "etw", target_pointer_width = "64"))]
+mod etw;
+mod handles;
+mod heaps;
+mod inject;
+mod jobs;
+mod normalize;
+#[cfg(feature = "unwind")]
+mod pdata;
+mod peb;
+mod priority;
+mod privilege;
+mod protected;
+mod pss;
+mod regions;
+mod stack_copy;
+#[cfg(all(feature = "unwind", feature = "symbol-server"))]
+mod symbol_server;
 #[cfg(feature = "unwind")]
 mod symbolication;
+mod sysinfo;
+mod terminate;
+mod threads;
+mod tls;
+mod token;
 #[cfg(feature = "unwind")]
 mod unwinder;
 
+#[cfg(all(feature = "etw", target_pointer_width = "64"))]
+pub use self::etw::{EtwSampler, EtwStack};
+pub use self::handles::Handle;
+pub use self::heaps::{Heap, HeapBlock};
+pub use self::jobs::JobInfo;
+pub use self::protected::{
+    process_name, process_protection, ProcessProtection, ProtectedProcess, ProtectionSigner,
+};
+pub use self::pss::{ProcessSnapshot, SnapshotThread};
+pub use self::regions::{MemoryRegion, RegionState, RegionType};
+#[cfg(all(feature = "unwind", feature = "symbol-server"))]
+pub use self::symbol_server::{SymbolPath, SymbolServer};
 #[cfg(feature = "unwind")]
 pub use self::symbolication::Symbolicator;
+pub use self::threads::ThreadIter;
+pub use self::token::{IntegrityLevel, TokenGroup, TokenInfo};
 #[cfg(feature = "unwind")]
 pub use self::unwinder::Unwinder;
 
 pub struct Process {
     pub pid: Pid,
     pub handle: ProcessHandle,
+    debug_privilege: bool,
+    read_options: ReadOptions,
+}
+
+/// Attaches to a process spawned by this one, reusing the handle held by the `Child`
+impl TryFrom<&std::process::Child> for Process {
+    type Error = Error;
+
+    fn try_from(child: &std::process::Child) -> Result<Process, Error> {
+        Process::from_raw_handle(child.as_raw_handle())
+    }
+}
+
+/// Configures how a process is opened, created by `Process::builder`
+pub struct ProcessBuilder {
+    pid: Pid,
+    debug_privilege: bool,
+}
+
+impl ProcessBuilder {
+    /// Attempts to enable SeDebugPrivilege before opening the process, which is needed to
+    /// attach to services and processes owned by other users. Failing to enable the privilege
+    /// isn't an error by itself, use `Process::debug_privilege_enabled` to check if it worked.
+    pub fn debug_privilege(mut self, enable: bool) -> ProcessBuilder {
+        self.debug_privilege = enable;
+        self
+    }
+
+    pub fn open(self) -> Result<Process, Error> {
+        let debug_privilege = if self.debug_privilege {
+            match privilege::enable_debug_privilege() {
+                Ok(()) => true,
+                Err(e) => {
+                    warn!("Failed to enable SeDebugPrivilege: {}", e);
+                    false
+                }
+            }
+        } else {
+            false
+        };
+
+        // we can't just use try_into_process_handle here because we need some additional permissions
+        unsafe {
+            let handle = OpenProcess(
+                PROCESS_VM_READ
+                    | PROCESS_SUSPEND_RESUME
+                    | PROCESS_QUERY_INFORMATION
+                    | THREAD_QUERY_INFORMATION
+                    | THREAD_GET_CONTEXT,
+                FALSE,
+                self.pid,
+            );
+            if handle.is_null() {
+                let error = std::io::Error::last_os_error();
+                // protected processes deny PROCESS_VM_READ to everyone, so explain that
+                // rather than suggesting more privileges would help
+                if error.raw_os_error() == Some(ERROR_ACCESS_DENIED as i32) {
+                    if let Ok(Some(protection)) = protected::process_protection(self.pid) {
+                        return Err(Error::ProtectedProcess(ProtectedProcess {
+                            pid: self.pid,
+                            name: protected::process_name(self.pid),
+                            protection,
+                        }));
+                    }
+                }
+                return Err(Error::from_os_error(self.pid, error));
+            }
+            Ok(Process {
+                pid: self.pid,
+                handle: (handle as RawHandle).into(),
+                debug_privilege,
+                read_options: ReadOptions::default(),
+            })
+        }
+    }
+}
+
+extern "system" {
+    fn CheckRemoteDebuggerPresent(process: HANDLE, present: *mut BOOL) -> BOOL;
+    fn GetModuleHandleW(name: LPCWSTR) -> HMODULE;
+    fn GetProcAddress(module: HMODULE, name: LPCSTR) -> LPVOID;
 }
 
 #[link(name = "ntdll")]
@@ -57,6 +186,12 @@ extern "system" {
         info_len: ULONG,
         ret_len: *mut ULONG,
     ) -> NTSTATUS;
+    fn NtQuerySystemInformation(
+        info_class: u32,
+        info: PVOID,
+        info_len: ULONG,
+        ret_len: *mut ULONG,
+    ) -> NTSTATUS;
 
     fn NtGetNextThread(
         process: HANDLE,
@@ -78,31 +213,62 @@ extern "system" {
 
 impl Process {
     pub fn new(pid: Pid) -> Result<Process, Error> {
-        // we can't just use try_into_process_handle here because we need some additional permissions
-        unsafe {
-            let handle = OpenProcess(
-                PROCESS_VM_READ
-                    | PROCESS_SUSPEND_RESUME
-                    | PROCESS_QUERY_INFORMATION
-                    | THREAD_QUERY_INFORMATION
-                    | THREAD_GET_CONTEXT,
-                FALSE,
-                pid,
-            );
-            if handle.is_null() {
-                return Err(Error::from(std::io::Error::last_os_error()));
-            }
-            Ok(Process {
-                pid,
-                handle: (handle as RawHandle).into(),
-            })
+        Process::builder(pid).open()
+    }
+
+    pub fn builder(pid: Pid) -> ProcessBuilder {
+        ProcessBuilder {
+            pid,
+            debug_privilege: false,
         }
     }
 
+    /// Creates a process from a handle to it, like the one held by a `std::process::Child`.
+    /// The handle is duplicated rather than the process being opened again by pid, so the
+    /// caller keeps ownership of it and it has whatever access rights it was created with.
+    pub fn from_raw_handle(handle: RawHandle) -> Result<Process, Error> {
+        let pid = unsafe { GetProcessId(handle as HANDLE) };
+        if pid == 0 {
+            return Err(Error::from(std::io::Error::last_os_error()));
+        }
+        Ok(Process {
+            pid,
+            handle: duplicate_handle(handle as HANDLE)?,
+            debug_privilege: false,
+            read_options: ReadOptions::default(),
+        })
+    }
+
+    /// True if SeDebugPrivilege was successfully enabled when opening this process
+    pub fn debug_privilege_enabled(&self) -> bool {
+        self.debug_privilege
+    }
+
+    /// Returns how reads of memory from this process are split up and retried
+    pub fn read_options(&self) -> ReadOptions {
+        self.read_options
+    }
+
+    /// Sets how reads of memory from this process are split up and retried
+    pub fn set_read_options(&mut self, options: ReadOptions) {
+        self.read_options = options;
+    }
+
     pub fn handle(&self) -> ProcessHandle {
         self.handle.clone()
     }
 
+    /// Returns a new handle to the same process that can be kept independently of this one,
+    /// duplicating the underlying process handle with the same access rights
+    pub fn try_clone(&self) -> Result<Process, Error> {
+        Ok(Process {
+            pid: self.pid,
+            handle: duplicate_handle(*self.handle as HANDLE)?,
+            debug_privilege: self.debug_privilege,
+            read_options: self.read_options,
+        })
+    }
+
     pub fn exe(&self) -> Result<String, Error> {
         unsafe {
             let mut size = MAX_PATH as DWORD;
@@ -126,15 +292,128 @@ impl Process {
         Ok(Lock::new(self.handle.clone())?)
     }
 
+    /// Locks the process, and returns a lock containing a consistent snapshot of the threads
+    /// in the process. Threads can still be started in a suspended process (for instance
+    /// with CreateRemoteThread), so after suspending the thread list is re-enumerated and
+    /// any new threads are suspended individually until the set of threads is stable. Threads
+    /// that exit before they can be suspended are left out of the snapshot.
+    pub fn lock_and_snapshot(&self) -> Result<Lock, Error> {
+        let mut lock = self.lock()?;
+        let mut exited = std::collections::HashSet::new();
+        loop {
+            let known: std::collections::HashSet<Tid> = lock.tids().into_iter().collect();
+            let current = self.threads()?;
+            let mut current_ids = std::collections::HashSet::new();
+            let mut new_threads = Vec::new();
+            for thread in current {
+                let tid = thread.id()?;
+                current_ids.insert(tid);
+                if !known.contains(&tid) && !exited.contains(&tid) {
+                    new_threads.push(thread);
+                }
+            }
+
+            if new_threads.is_empty() {
+                lock.threads.retain(|thread| match thread.id() {
+                    Ok(tid) => current_ids.contains(&tid),
+                    Err(_) => false,
+                });
+                return Ok(lock);
+            }
+
+            for thread in new_threads {
+                match thread.lock() {
+                    Ok(thread_lock) => lock.thread_locks.push(thread_lock),
+                    // threads can still exit while the process is suspended, and there's
+                    // nothing to snapshot for those
+                    Err(_) if thread.exited() => {
+                        exited.insert(thread.id()?);
+                        continue;
+                    }
+                    Err(e) => return Err(e),
+                }
+                lock.threads.push(thread);
+            }
+        }
+    }
+
     pub fn cwd(&self) -> Result<String, Error> {
-        // TODO: get the CWD.
-        // seems a little involved: http://wj32.org/wp/2009/01/24/howto-get-the-command-line-of-processes/
-        // steps:
-        //      1) NtQueryInformationProcess to get PebBaseAddress, which ProcessParameters
-        //          is at some constant offset (+10 on 32 bit etc)
-        //      2) ReadProcessMemory to get RTL_USER_PROCESS_PARAMETERS struct
-        //      3) get CWD from the struct (has UNICODE_DATA object with ptr + length to CWD)
-        unimplemented!("cwd is unimplemented on windows")
+        let cwd = peb::read_process_parameter(
+            self,
+            self.is_wow64()?,
+            peb::ProcessParameter::CurrentDirectory,
+        )?;
+        // the current directory is stored with a trailing separator, which we only want
+        // to keep for the root of a drive
+        match cwd.strip_suffix('\\') {
+            Some(stripped) if !stripped.ends_with(':') => Ok(stripped.to_owned()),
+            _ => Ok(cwd),
+        }
+    }
+
+    /// True if a debugger is attached to the process
+    pub fn is_being_debugged(&self) -> Result<bool, Error> {
+        let mut present: BOOL = FALSE;
+        if unsafe { CheckRemoteDebuggerPresent(*self.handle as HANDLE, &mut present) } == 0 {
+            return Err(Error::from_os_error(
+                self.pid,
+                std::io::Error::last_os_error(),
+            ));
+        }
+        Ok(present != FALSE)
+    }
+
+    /// True if this is a 32-bit process running under WoW64 on a 64-bit host
+    pub fn is_wow64(&self) -> Result<bool, Error> {
+        is_wow64(*self.handle as HANDLE)
+    }
+
+    /// Returns the architecture, pointer size and endianness of the process. This uses
+    /// IsWow64Process2 where it's available (windows 10 1511 and later), and otherwise
+    /// assumes the host has the same architecture as the current process.
+    pub fn arch(&self) -> Result<TargetArch, Error> {
+        let machine = match process_machine(*self.handle as HANDLE)? {
+            Some(machine) => machine,
+            None if self.is_wow64()? => IMAGE_FILE_MACHINE_I386,
+            None if cfg!(target_arch = "aarch64") => IMAGE_FILE_MACHINE_ARM64,
+            None if cfg!(target_arch = "x86") => IMAGE_FILE_MACHINE_I386,
+            None => IMAGE_FILE_MACHINE_AMD64,
+        };
+        let (architecture, pointer_size) = match machine {
+            IMAGE_FILE_MACHINE_I386 => (Architecture::X86, 4),
+            IMAGE_FILE_MACHINE_AMD64 => (Architecture::X86_64, 8),
+            IMAGE_FILE_MACHINE_ARMNT => (Architecture::Arm, 4),
+            IMAGE_FILE_MACHINE_ARM64 => (Architecture::Aarch64, 8),
+            _ => (Architecture::Unknown, std::mem::size_of::<usize>()),
+        };
+        Ok(TargetArch {
+            architecture,
+            pointer_size,
+            endianness: Endianness::Little,
+        })
+    }
+
+    /// True if pointers in the process are 64 bits wide
+    pub fn is_64_bit(&self) -> Result<bool, Error> {
+        Ok(self.arch()?.is_64_bit())
+    }
+
+    /// Returns the bytes and operations the process has read and written, from
+    /// GetProcessIoCounters
+    pub fn io_counters(&self) -> Result<IoCounters, Error> {
+        let mut counters: IO_COUNTERS = unsafe { std::mem::zeroed() };
+        if unsafe { GetProcessIoCounters(*self.handle as HANDLE, &mut counters) } == 0 {
+            return Err(Error::from_os_error(
+                self.pid,
+                std::io::Error::last_os_error(),
+            ));
+        }
+        Ok(IoCounters {
+            read_bytes: counters.ReadTransferCount,
+            write_bytes: counters.WriteTransferCount,
+            read_ops: Some(counters.ReadOperationCount),
+            write_ops: Some(counters.WriteOperationCount),
+        })
     }
 
     pub fn cmdline(&self) -> Result<Vec<String>, Error> {
@@ -181,24 +460,20 @@ impl Process {
     }
 
     pub fn threads(&self) -> Result<Vec<Thread>, Error> {
-        let mut ret = Vec::new();
-        unsafe {
-            let mut thread: HANDLE = std::mem::zeroed();
-            while NtGetNextThread(
-                *self.handle as HANDLE,
-                thread,
-                MAXIMUM_ALLOWED,
-                0,
-                0,
-                &mut thread as *mut HANDLE,
-            ) == 0
-            {
-                ret.push(Thread {
-                    thread: (thread as RawHandle).into(),
-                });
-            }
-        }
-        Ok(ret)
+        get_threads(*self.handle as HANDLE)
+    }
+
+    /// Returns the ids of the threads in the process, from the system process list rather
+    /// than opening a handle to each thread
+    pub fn thread_ids(&self) -> Result<Vec<Tid>, Error> {
+        let processes = sysinfo::ProcessList::query()?;
+        let (_, threads) = processes
+            .find(self.pid)
+            .ok_or(Error::ProcessExited { pid: self.pid })?;
+        Ok(threads
+            .iter()
+            .map(|thread| thread.unique_thread as usize as Tid)
+            .collect())
     }
 
     pub fn child_processes(&self) -> Result<Vec<(Pid, Pid)>, Error> {
@@ -247,7 +522,11 @@ impl Process {
 
 impl super::ProcessMemory for Process {
     fn read(&self, addr: usize, buf: &mut [u8]) -> Result<(), Error> {
-        Ok(self.handle.copy_address(addr, buf)?)
+        self.read_options
+            .read_chunked(addr, buf, |addr, buf| {
+                Ok(self.handle.copy_address(addr, buf)?)
+            })
+            .map_err(|e| Error::from_read_error(self.pid, addr, buf.len(), e))
     }
 }
 
@@ -270,6 +549,14 @@ impl Thread {
             })
         }
     }
+
+    /// Returns a new handle to the same thread that can be kept independently of this one,
+    /// duplicating the underlying thread handle
+    pub fn try_clone(&self) -> Result<Thread, Error> {
+        Ok(Thread {
+            thread: duplicate_handle(*self.thread as HANDLE)?,
+        })
+    }
     pub fn lock(&self) -> Result<ThreadLock, Error> {
         ThreadLock::new(self.thread.clone())
     }
@@ -278,6 +565,12 @@ impl Thread {
         unsafe { Ok(GetThreadId(*self.thread as HANDLE)) }
     }
 
+    /// True if the thread has finished running
+    fn exited(&self) -> bool {
+        let mut code: DWORD = 0;
+        unsafe { GetExitCodeThread(*self.thread as HANDLE, &mut code) != 0 && code != STILL_ACTIVE }
+    }
+
     pub fn active(&self) -> Result<bool, Error> {
         // Getting whether a thread is active or not is surprisingly difficult on windows
         // we're getting the syscall the thread is doing here, and then checking against a list
@@ -303,8 +596,109 @@ impl Thread {
     }
 }
 
+/// Returns a unique value to stand in for a process handle, for dbghelp and StackWalk64 to
+/// key targets that aren't processes by. Kernel handles are always multiples of 4, so odd
+/// values can never collide with a real handle.
+#[cfg(feature = "unwind")]
+fn pseudo_handle() -> HANDLE {
+    static NEXT: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+    let id = NEXT.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    ((id << 2) | 1) as HANDLE
+}
+
+/// Closes a handle when dropped
+struct OwnedHandle(HANDLE);
+
+impl Drop for OwnedHandle {
+    fn drop(&mut self) {
+        unsafe { CloseHandle(self.0) };
+    }
+}
+
+/// Duplicates a handle in the current process, with the same access rights
+fn duplicate_handle(handle: HANDLE) -> Result<ProcessHandle, Error> {
+    let mut duplicate: HANDLE = std::ptr::null_mut();
+    unsafe {
+        if DuplicateHandle(
+            GetCurrentProcess(),
+            handle,
+            GetCurrentProcess(),
+            &mut duplicate,
+            0,
+            FALSE,
+            DUPLICATE_SAME_ACCESS,
+        ) == 0
+        {
+            return Err(Error::from(std::io::Error::last_os_error()));
+        }
+    }
+    Ok((duplicate as RawHandle).into())
+}
+
+type IsWow64Process2Fn = unsafe extern "system" fn(HANDLE, *mut USHORT, *mut USHORT) -> BOOL;
+
+/// Returns the IMAGE_FILE_MACHINE value of the process with IsWow64Process2, or None if this
+/// version of windows doesn't have it
+fn process_machine(process: HANDLE) -> Result<Option<USHORT>, Error> {
+    // IsWow64Process2 isn't in older versions of kernel32, so has to be looked up
+    let function = unsafe {
+        let kernel32: Vec<u16> = "kernel32.dll\0".encode_utf16().collect();
+        GetProcAddress(
+            GetModuleHandleW(kernel32.as_ptr()),
+            b"IsWow64Process2\0".as_ptr() as LPCSTR,
+        )
+    };
+    if function.is_null() {
+        return Ok(None);
+    }
+    let is_wow64_process2 = unsafe { std::mem::transmute::<LPVOID, IsWow64Process2Fn>(function) };
+
+    let mut process_machine: USHORT = 0;
+    let mut native_machine: USHORT = 0;
+    if unsafe { is_wow64_process2(process, &mut process_machine, &mut native_machine) } == 0 {
+        return Err(Error::from(std::io::Error::last_os_error()));
+    }
+    // the process machine is IMAGE_FILE_MACHINE_UNKNOWN for processes that aren't under WoW64
+    if process_machine == IMAGE_FILE_MACHINE_UNKNOWN {
+        Ok(Some(native_machine))
+    } else {
+        Ok(Some(process_machine))
+    }
+}
+
+fn is_wow64(process: HANDLE) -> Result<bool, Error> {
+    let mut wow64: BOOL = FALSE;
+    if unsafe { IsWow64Process(process, &mut wow64) } == 0 {
+        return Err(Error::from(std::io::Error::last_os_error()));
+    }
+    Ok(wow64 != FALSE)
+}
+
+fn get_threads(process: HANDLE) -> Result<Vec<Thread>, Error> {
+    let mut ret = Vec::new();
+    unsafe {
+        let mut thread: HANDLE = std::mem::zeroed();
+        while NtGetNextThread(
+            process,
+            thread,
+            MAXIMUM_ALLOWED,
+            0,
+            0,
+            &mut thread as *mut HANDLE,
+        ) == 0
+        {
+            ret.push(Thread {
+                thread: (thread as RawHandle).into(),
+            });
+        }
+    }
+    Ok(ret)
+}
+
 pub struct Lock {
     process: ProcessHandle,
+    threads: Vec<Thread>,
+    thread_locks: Vec<ThreadLock>,
 }
 
 impl Lock {
@@ -317,7 +711,27 @@ impl Lock {
                 )));
             }
         }
-        Ok(Lock { process })
+        // the lock resumes the process on drop if listing the threads fails
+        let mut lock = Lock {
+            process,
+            threads: Vec::new(),
+            thread_locks: Vec::new(),
+        };
+        lock.threads = get_threads(*lock.process as HANDLE)?;
+        Ok(lock)
+    }
+
+    /// The threads that were suspended by this lock
+    pub fn threads(&self) -> &[Thread] {
+        &self.threads
+    }
+
+    /// The thread ids of the threads that were suspended by this lock
+    pub fn tids(&self) -> Vec<Tid> {
+        self.threads
+            .iter()
+            .filter_map(|thread| thread.id().ok())
+            .collect()
     }
 }
 
@@ -383,4 +797,7 @@ struct PROCESS_BASIC_INFORMATION {
     inherited_from_unique_process_id: HANDLE,
 }
 
+// the handle is only closed on drop, and the calls made with it are safe to make from any
+// number of threads at once
 unsafe impl Send for Process {}
+unsafe impl Sync for Process {}
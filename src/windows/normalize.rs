@@ -0,0 +1,231 @@
+//! Maps addresses in a process to the module containing them and their RVA in it, without
+//! loading any symbols - so that stacks can be collected cheaply, and then symbolicated
+//! offline somewhere else. The module's size and PDB identity are read straight from the PE
+//! headers in the process, rather than through dbghelp.
+use std::collections::HashMap;
+
+use winapi::shared::minwindef::{DWORD, HMODULE, LPVOID};
+use winapi::shared::winerror::ERROR_INVALID_PARAMETER;
+use winapi::um::memoryapi::VirtualQueryEx;
+use winapi::um::winnt::{HANDLE, MEMORY_BASIC_INFORMATION, MEM_IMAGE};
+
+use super::Process;
+use crate::{Error, Module, ModuleId, NormalizedAddress, ProcessMemory};
+
+// IMAGE_DIRECTORY_ENTRY_DEBUG and IMAGE_DEBUG_TYPE_CODEVIEW
+const DEBUG_DIRECTORY: u64 = 6;
+const DEBUG_TYPE_CODEVIEW: u32 = 2;
+// the size of an IMAGE_DEBUG_DIRECTORY entry
+const DEBUG_ENTRY_SIZE: u64 = 28;
+// 'RSDS', the signature of a CodeView record with a PDB 7.0 GUID
+const CV_SIGNATURE_RSDS: u32 = 0x5344_5352;
+
+extern "system" {
+    fn K32GetModuleFileNameExW(
+        process: HANDLE,
+        module: HMODULE,
+        filename: *mut u16,
+        size: DWORD,
+    ) -> DWORD;
+}
+
+impl Process {
+    /// Returns the module that contains an address and the address's RVA in it, which is
+    /// what the module's PDB uses. Only the headers of the module are read. This is None for
+    /// addresses that aren't in an image, like JIT compiled code.
+    pub fn normalize_address(&self, addr: u64) -> Result<Option<NormalizedAddress>, Error> {
+        Ok(self.normalize_addresses(&[addr])?.pop().flatten())
+    }
+
+    /// Normalizes many addresses at once, reading the headers of each module only once
+    pub fn normalize_addresses(
+        &self,
+        addrs: &[u64],
+    ) -> Result<Vec<Option<NormalizedAddress>>, Error> {
+        let mut modules: HashMap<u64, Option<Module>> = HashMap::new();
+        let mut ret = Vec::with_capacity(addrs.len());
+        for &addr in addrs {
+            let base = match self.image_base(addr)? {
+                Some(base) => base,
+                None => {
+                    ret.push(None);
+                    continue;
+                }
+            };
+            let module = modules
+                .entry(base)
+                .or_insert_with(|| self.image_module(base));
+            ret.push(module.as_ref().map(|module| NormalizedAddress {
+                module: module.clone(),
+                offset: addr - base,
+            }));
+        }
+        Ok(ret)
+    }
+
+    /// Returns the address an image containing `addr` is loaded at
+    fn image_base(&self, addr: u64) -> Result<Option<u64>, Error> {
+        let mut info: MEMORY_BASIC_INFORMATION = unsafe { std::mem::zeroed() };
+        let size = std::mem::size_of::<MEMORY_BASIC_INFORMATION>();
+        let process = *self.handle as HANDLE;
+        // this fails with ERROR_INVALID_PARAMETER past the end of the address space
+        if unsafe { VirtualQueryEx(process, addr as LPVOID, &mut info, size) } != size {
+            let error = std::io::Error::last_os_error();
+            if error.raw_os_error() == Some(ERROR_INVALID_PARAMETER as i32) {
+                return Ok(None);
+            }
+            return Err(Error::from_os_error(self.pid, error));
+        }
+        if info.Type != MEM_IMAGE {
+            return Ok(None);
+        }
+        Ok(Some(info.AllocationBase as u64))
+    }
+
+    /// Reads the size and PDB identity of the image loaded at `base` from its headers
+    fn image_module(&self, base: u64) -> Option<Module> {
+        let (optional_header, _) = self.pe_headers(base).ok()??;
+        // SizeOfImage is at the same offset in PE32 and PE32+
+        let size: u32 = self.copy_struct(optional_header as usize + 56).ok()?;
+
+        let mut filename = vec![0u16; 32768];
+        let len = unsafe {
+            K32GetModuleFileNameExW(
+                *self.handle as HANDLE,
+                base as HMODULE,
+                filename.as_mut_ptr(),
+                filename.len() as DWORD,
+            )
+        } as usize;
+        if len == 0 {
+            return None;
+        }
+
+        Some(Module {
+            filename: String::from_utf16_lossy(&filename[..len]),
+            address: base,
+            size: size as u64,
+            // addresses in PE files are relative to where the image is loaded
+            bias: base,
+            id: self.module_id(base).ok().flatten(),
+        })
+    }
+
+    /// Reads the GUID, age and name of the PDB that the image loaded at `address` was
+    /// linked with, from the CodeView record in its debug directory. This is read from the
+    /// memory of the process, so still works if the file has been replaced since it was
+    /// loaded.
+    pub fn module_id(&self, address: u64) -> Result<Option<ModuleId>, Error> {
+        let data_directories = match self.pe_headers(address)? {
+            Some((_, data_directories)) => data_directories,
+            None => return Ok(None),
+        };
+        let directory = (data_directories + DEBUG_DIRECTORY * 8) as usize;
+        let rva: u32 = self.copy_struct(directory)?;
+        let size: u32 = self.copy_struct(directory + 4)?;
+        if rva == 0 {
+            return Ok(None);
+        }
+        for i in 0..size as u64 / DEBUG_ENTRY_SIZE {
+            let entry = (address + rva as u64 + i * DEBUG_ENTRY_SIZE) as usize;
+            let debug_type: u32 = self.copy_struct(entry + 12)?;
+            let data_size: u32 = self.copy_struct(entry + 16)?;
+            let data_rva: u32 = self.copy_struct(entry + 20)?;
+            if debug_type != DEBUG_TYPE_CODEVIEW || data_rva == 0 {
+                continue;
+            }
+            let data = self.copy(address as usize + data_rva as usize, data_size as usize)?;
+            if let Some(id) = parse_codeview(&data) {
+                return Ok(Some(id));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns the addresses of the optional header and data directories of the image
+    /// loaded at `base`, or None if it isn't a PE32 or PE32+ image
+    fn pe_headers(&self, base: u64) -> Result<Option<(u64, u64)>, Error> {
+        // IMAGE_DOS_HEADER.e_lfanew points at the PE signature, which is followed by the
+        // 20 byte file header and then the optional header
+        let pe_offset: u32 = self.copy_struct(base as usize + 0x3c)?;
+        let optional_header = base + pe_offset as u64 + 24;
+        let magic: u16 = self.copy_struct(optional_header as usize)?;
+        Ok(match magic {
+            0x20b => Some((optional_header, optional_header + 112)), // PE32+
+            0x10b => Some((optional_header, optional_header + 96)),  // PE32
+            _ => None,
+        })
+    }
+}
+
+/// Parses a CV_INFO_PDB70 record: the signature, the GUID, the age and then the path of the
+/// PDB as a nul terminated string
+fn parse_codeview(data: &[u8]) -> Option<ModuleId> {
+    if data.len() < 24 || u32::from_le_bytes(data[..4].try_into().ok()?) != CV_SIGNATURE_RSDS {
+        return None;
+    }
+    let path = &data[24..];
+    let path = &path[..path.iter().position(|&c| c == 0).unwrap_or(path.len())];
+    let path = String::from_utf8_lossy(path);
+    let name = path
+        .rsplit(['\\', '/'])
+        .next()
+        .filter(|name| !name.is_empty())?;
+    Some(ModuleId::Pdb {
+        guid: data[4..20].try_into().ok()?,
+        age: u32::from_le_bytes(data[20..24].try_into().ok()?),
+        name: name.to_owned(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_codeview() {
+        let mut data = b"RSDS".to_vec();
+        data.extend(0..16u8);
+        data.extend(3u32.to_le_bytes());
+        data.extend(b"C:\\build\\app.pdb\0");
+        assert_eq!(
+            parse_codeview(&data),
+            Some(ModuleId::Pdb {
+                guid: [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
+                age: 3,
+                name: "app.pdb".to_owned(),
+            })
+        );
+        assert_eq!(parse_codeview(b"NB10"), None);
+    }
+
+    #[test]
+    fn test_normalize_address() {
+        let process = Process::new(std::process::id()).unwrap();
+        let addr = test_normalize_address as *const () as usize as u64;
+        let normalized = process.normalize_address(addr).unwrap().unwrap();
+        assert!(normalized.module.address <= addr);
+        assert!(normalized.offset < normalized.module.size);
+
+        let value = Box::new(0u64);
+        let batch = process
+            .normalize_addresses(&[&*value as *const u64 as u64, addr])
+            .unwrap();
+        assert_eq!(batch[0], None);
+        assert_eq!(batch[1], Some(normalized));
+    }
+
+    #[test]
+    fn test_module_id() {
+        let process = Process::new(std::process::id()).unwrap();
+        let addr = test_module_id as *const () as usize as u64;
+        let base = process.image_base(addr).unwrap().unwrap();
+        // test binaries are built with a PDB on msvc
+        if cfg!(target_env = "msvc") {
+            match process.module_id(base).unwrap() {
+                Some(ModuleId::Pdb { name, .. }) => assert!(name.ends_with(".pdb")),
+                id => panic!("unexpected module id {:?}", id),
+            }
+        }
+    }
+}
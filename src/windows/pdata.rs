@@ -0,0 +1,225 @@
+//! Looks up the unwind info for functions in the target process from the .pdata section of
+//! the loaded modules. StackWalk64 needs this to unwind frames that don't use a frame pointer,
+//! but can only find it itself when dbghelp's symbol handler has been initialized for the
+//! process.
+//!
+//! Targets that aren't live processes (core dumps, remote agents) are registered here with
+//! a pseudo handle, so that the same lookups read their memory and modules instead.
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+use winapi::shared::basetsd::{DWORD64, SIZE_T};
+use winapi::shared::minwindef::{BOOL, DWORD, FALSE, LPDWORD, TRUE};
+use winapi::shared::ntdef::PVOID;
+use winapi::um::memoryapi::{ReadProcessMemory, VirtualQueryEx};
+use winapi::um::winnt::{HANDLE, MEMORY_BASIC_INFORMATION};
+
+use crate::{Module, ProcessMemory};
+
+/// An entry in the .pdata section. On x64 this is a RUNTIME_FUNCTION with the begin/end
+/// addresses and the unwind info, on ARM64 it's the begin address and either the packed
+/// unwind data or the address of the .xdata record
+#[cfg(target_arch = "aarch64")]
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct RuntimeFunction {
+    pub begin_address: u32,
+    pub unwind_data: u32,
+}
+
+#[cfg(not(target_arch = "aarch64"))]
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct RuntimeFunction {
+    pub begin_address: u32,
+    pub end_address: u32,
+    pub unwind_data: u32,
+}
+
+// IMAGE_DIRECTORY_ENTRY_EXCEPTION
+const EXCEPTION_DIRECTORY: usize = 3;
+
+lazy_static! {
+    // function tables, keyed by (process handle, module base). StackWalk64 is given raw
+    // pointers into these, so the tables are boxed to keep their addresses stable
+    static ref FUNCTION_TABLES: Mutex<HashMap<(usize, u64), Box<[RuntimeFunction]>>> =
+        Mutex::new(HashMap::new());
+
+    // targets that aren't processes, keyed by their pseudo handle
+    static ref TARGETS: Mutex<HashMap<usize, Target>> = Mutex::new(HashMap::new());
+}
+
+struct Target {
+    memory: Box<dyn ProcessMemory + Send>,
+    modules: Vec<Module>,
+}
+
+/// Registers a target that reads memory through `memory` and has the given modules loaded,
+/// returning the pseudo handle to look it up by
+pub fn register(memory: Box<dyn ProcessMemory + Send>, modules: Vec<Module>) -> HANDLE {
+    let handle = super::pseudo_handle();
+    TARGETS
+        .lock()
+        .unwrap()
+        .insert(handle as usize, Target { memory, modules });
+    handle
+}
+
+/// True if the handle was returned by `register`, rather than being a process handle
+pub fn is_registered(process: HANDLE) -> bool {
+    TARGETS.lock().unwrap().contains_key(&(process as usize))
+}
+
+/// Returns the base address of the module containing `addr`
+pub fn module_base(process: HANDLE, addr: u64) -> Option<u64> {
+    if let Some(target) = TARGETS.lock().unwrap().get(&(process as usize)) {
+        return target
+            .modules
+            .iter()
+            .find(|m| m.address <= addr && addr < m.address + m.size)
+            .map(|m| m.address);
+    }
+    unsafe {
+        let mut info: MEMORY_BASIC_INFORMATION = std::mem::zeroed();
+        let size = std::mem::size_of::<MEMORY_BASIC_INFORMATION>();
+        if VirtualQueryEx(process, addr as PVOID, &mut info, size) != size {
+            return None;
+        }
+        let base = info.AllocationBase as u64;
+        let magic: u16 = read(process, base)?;
+        if magic != 0x5a4d {
+            // not an 'MZ' image
+            return None;
+        }
+        Some(base)
+    }
+}
+
+/// Finds the .pdata entry for the function containing `addr`
+pub fn lookup_function_entry(process: HANDLE, addr: u64) -> Option<RuntimeFunction> {
+    with_function_entry(process, addr, |entry| *entry)
+}
+
+fn with_function_entry<R>(
+    process: HANDLE,
+    addr: u64,
+    callback: impl FnOnce(&RuntimeFunction) -> R,
+) -> Option<R> {
+    let base = module_base(process, addr)?;
+    let mut tables = FUNCTION_TABLES.lock().unwrap();
+    let table = match tables.entry((process as usize, base)) {
+        std::collections::hash_map::Entry::Occupied(entry) => entry.into_mut(),
+        std::collections::hash_map::Entry::Vacant(entry) => {
+            entry.insert(read_function_table(process, base)?.into_boxed_slice())
+        }
+    };
+    let rva = (addr - base) as u32;
+    let index = match table.binary_search_by(|f| f.begin_address.cmp(&rva)) {
+        Ok(i) => i,
+        Err(0) => return None,
+        Err(i) => i - 1,
+    };
+    Some(callback(&table[index]))
+}
+
+/// Drops the cached function tables for a process, and the target if it was registered
+pub fn clear(process: HANDLE) {
+    FUNCTION_TABLES
+        .lock()
+        .unwrap()
+        .retain(|(handle, _), _| *handle != process as usize);
+    TARGETS.lock().unwrap().remove(&(process as usize));
+}
+
+fn read_function_table(process: HANDLE, base: u64) -> Option<Vec<RuntimeFunction>> {
+    // IMAGE_DOS_HEADER.e_lfanew points at the PE signature, which is followed by the
+    // 20 byte file header and then the optional header
+    let pe_offset: u32 = read(process, base + 0x3c)?;
+    let optional_header = base + pe_offset as u64 + 24;
+    let magic: u16 = read(process, optional_header)?;
+    let data_directories = match magic {
+        0x20b => optional_header + 112, // PE32+
+        0x10b => optional_header + 96,  // PE32
+        _ => return None,
+    };
+    let directory = data_directories + (EXCEPTION_DIRECTORY * 8) as u64;
+    let rva: u32 = read(process, directory)?;
+    let size: u32 = read(process, directory + 4)?;
+    if rva == 0 || size == 0 {
+        return Some(Vec::new());
+    }
+
+    let count = size as usize / std::mem::size_of::<RuntimeFunction>();
+    let mut table: Vec<RuntimeFunction> = Vec::with_capacity(count);
+    unsafe {
+        let bytes_read = read_bytes(
+            process,
+            base + rva as u64,
+            table.as_mut_ptr() as PVOID,
+            count * std::mem::size_of::<RuntimeFunction>(),
+        )?;
+        table.set_len(bytes_read / std::mem::size_of::<RuntimeFunction>());
+    }
+    // the table is supposed to be sorted already, but we binary search it so make sure
+    table.sort_unstable_by_key(|f| f.begin_address);
+    Some(table)
+}
+
+fn read<T: Copy>(process: HANDLE, addr: u64) -> Option<T> {
+    unsafe {
+        let mut value: T = std::mem::zeroed();
+        let size = std::mem::size_of::<T>();
+        if read_bytes(process, addr, &mut value as *mut T as PVOID, size)? != size {
+            return None;
+        }
+        Some(value)
+    }
+}
+
+/// Reads `size` bytes into `buffer` from a process or registered target, returning how many
+/// bytes were read
+unsafe fn read_bytes(process: HANDLE, addr: u64, buffer: PVOID, size: usize) -> Option<usize> {
+    if let Some(target) = TARGETS.lock().unwrap().get(&(process as usize)) {
+        let buffer = std::slice::from_raw_parts_mut(buffer as *mut u8, size);
+        return target.memory.read(addr as usize, buffer).ok().map(|_| size);
+    }
+    let mut bytes_read: SIZE_T = 0;
+    if ReadProcessMemory(process, addr as PVOID, buffer, size, &mut bytes_read) == 0 {
+        return None;
+    }
+    Some(bytes_read)
+}
+
+/// ReadMemoryRoutine for StackWalk64, only needed for registered targets
+pub unsafe extern "system" fn read_memory(
+    process: HANDLE,
+    addr: DWORD64,
+    buffer: PVOID,
+    size: DWORD,
+    bytes_read: LPDWORD,
+) -> BOOL {
+    match read_bytes(process, addr, buffer, size as usize) {
+        Some(read) => {
+            if !bytes_read.is_null() {
+                *bytes_read = read as DWORD;
+            }
+            TRUE
+        }
+        None => FALSE,
+    }
+}
+
+/// FunctionTableAccessRoutine for StackWalk64
+pub unsafe extern "system" fn function_table_access(process: HANDLE, addr: DWORD64) -> PVOID {
+    // this points into the cached table, which stays valid until the table is cleared
+    with_function_entry(process, addr, |entry| {
+        entry as *const RuntimeFunction as PVOID
+    })
+    .unwrap_or(std::ptr::null_mut())
+}
+
+/// GetModuleBaseRoutine for StackWalk64
+pub unsafe extern "system" fn get_module_base(process: HANDLE, addr: DWORD64) -> DWORD64 {
+    module_base(process, addr).unwrap_or(0)
+}
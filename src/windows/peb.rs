@@ -0,0 +1,107 @@
+use winapi::shared::minwindef::ULONG;
+use winapi::um::winnt::HANDLE;
+
+use super::super::{Error, ProcessMemory};
+use super::{NtQueryInformationProcess, Process, RtlNtStatusToDosError, PROCESS_BASIC_INFORMATION};
+
+/// Offsets of the fields we need in the PEB and RTL_USER_PROCESS_PARAMETERS structures. These
+/// differ between 64-bit processes and 32-bit processes running under WoW64
+struct PebLayout {
+    pointer_size: usize,
+    process_parameters: usize,
+    current_directory: usize,
+    command_line: usize,
+}
+
+const PEB64: PebLayout = PebLayout {
+    pointer_size: 8,
+    process_parameters: 0x20,
+    current_directory: 0x38,
+    command_line: 0x70,
+};
+
+const PEB32: PebLayout = PebLayout {
+    pointer_size: 4,
+    process_parameters: 0x10,
+    current_directory: 0x24,
+    command_line: 0x40,
+};
+
+pub enum ProcessParameter {
+    CurrentDirectory,
+    CommandLine,
+}
+
+/// Reads a string from the RTL_USER_PROCESS_PARAMETERS of the process, by walking the
+/// (32-bit for WoW64 processes) PEB of the process
+pub fn read_process_parameter(
+    process: &Process,
+    wow64: bool,
+    parameter: ProcessParameter,
+) -> Result<String, Error> {
+    let handle = *process.handle as HANDLE;
+    let (peb, layout) = if wow64 {
+        (wow64_peb_address(handle)?, &PEB32)
+    } else {
+        (peb_address(handle)?, &PEB64)
+    };
+
+    let parameters = read_pointer(process, peb + layout.process_parameters, layout)?;
+    let offset = match parameter {
+        ProcessParameter::CurrentDirectory => layout.current_directory,
+        ProcessParameter::CommandLine => layout.command_line,
+    };
+
+    // this is a UNICODE_STRING, which has a u16 length (in bytes) followed by a pointer
+    // aligned buffer
+    let string = parameters + offset;
+    let length: u16 = process.copy_struct(string)?;
+    let buffer = read_pointer(process, string + layout.pointer_size, layout)?;
+    let chars: Vec<u16> = process.copy_vec(buffer, length as usize / 2)?;
+    Ok(String::from_utf16_lossy(&chars))
+}
+
+fn read_pointer(process: &Process, addr: usize, layout: &PebLayout) -> Result<usize, Error> {
+    Ok(match layout.pointer_size {
+        4 => process.copy_struct::<u32>(addr)? as usize,
+        _ => process.copy_struct::<u64>(addr)? as usize,
+    })
+}
+
+fn peb_address(handle: HANDLE) -> Result<usize, Error> {
+    unsafe {
+        let mut basic_info = std::mem::zeroed::<PROCESS_BASIC_INFORMATION>();
+        let ret = NtQueryInformationProcess(
+            handle,
+            0, // ProcessBasicInformation
+            &mut basic_info as *mut _ as *mut _,
+            std::mem::size_of_val(&basic_info) as ULONG,
+            std::ptr::null_mut(),
+        );
+        if ret != 0 {
+            return Err(Error::from(std::io::Error::from_raw_os_error(
+                RtlNtStatusToDosError(ret) as i32,
+            )));
+        }
+        Ok(basic_info.peb_base_address as usize)
+    }
+}
+
+fn wow64_peb_address(handle: HANDLE) -> Result<usize, Error> {
+    unsafe {
+        let mut peb: usize = 0;
+        let ret = NtQueryInformationProcess(
+            handle,
+            26, // ProcessWow64Information
+            &mut peb as *mut _ as *mut _,
+            std::mem::size_of_val(&peb) as ULONG,
+            std::ptr::null_mut(),
+        );
+        if ret != 0 {
+            return Err(Error::from(std::io::Error::from_raw_os_error(
+                RtlNtStatusToDosError(ret) as i32,
+            )));
+        }
+        Ok(peb)
+    }
+}
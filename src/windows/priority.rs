@@ -0,0 +1,61 @@
+use winapi::shared::minwindef::{DWORD, FALSE};
+use winapi::um::processthreadsapi::{GetPriorityClass, OpenProcess, SetPriorityClass};
+use winapi::um::winbase::{
+    ABOVE_NORMAL_PRIORITY_CLASS, BELOW_NORMAL_PRIORITY_CLASS, HIGH_PRIORITY_CLASS,
+    IDLE_PRIORITY_CLASS, NORMAL_PRIORITY_CLASS, REALTIME_PRIORITY_CLASS,
+};
+use winapi::um::winnt::{HANDLE, PROCESS_SET_INFORMATION};
+
+use super::{OwnedHandle, Process};
+use crate::{Error, Priority};
+
+impl Process {
+    /// Returns the priority class of the process
+    pub fn priority(&self) -> Result<Priority, Error> {
+        let class = unsafe { GetPriorityClass(*self.handle as HANDLE) };
+        Ok(match class {
+            IDLE_PRIORITY_CLASS => Priority::Idle,
+            BELOW_NORMAL_PRIORITY_CLASS => Priority::BelowNormal,
+            NORMAL_PRIORITY_CLASS => Priority::Normal,
+            ABOVE_NORMAL_PRIORITY_CLASS => Priority::AboveNormal,
+            HIGH_PRIORITY_CLASS => Priority::High,
+            REALTIME_PRIORITY_CLASS => Priority::Realtime,
+            0 => {
+                return Err(Error::from_os_error(
+                    self.pid,
+                    std::io::Error::last_os_error(),
+                ))
+            }
+            class => return Err(Error::Other(format!("unknown priority class {:#x}", class))),
+        })
+    }
+
+    /// Sets the priority class of the process. Setting `Priority::Realtime` without
+    /// SeIncreaseBasePriorityPrivilege sets the process to `Priority::High` instead.
+    pub fn set_priority(&self, priority: Priority) -> Result<(), Error> {
+        let class: DWORD = match priority {
+            Priority::Idle => IDLE_PRIORITY_CLASS,
+            Priority::BelowNormal => BELOW_NORMAL_PRIORITY_CLASS,
+            Priority::Normal => NORMAL_PRIORITY_CLASS,
+            Priority::AboveNormal => ABOVE_NORMAL_PRIORITY_CLASS,
+            Priority::High => HIGH_PRIORITY_CLASS,
+            Priority::Realtime => REALTIME_PRIORITY_CLASS,
+        };
+        // the handle the process was opened with can't change its priority
+        let process = unsafe { OpenProcess(PROCESS_SET_INFORMATION, FALSE, self.pid) };
+        if process.is_null() {
+            return Err(Error::from_os_error(
+                self.pid,
+                std::io::Error::last_os_error(),
+            ));
+        }
+        let process = OwnedHandle(process);
+        if unsafe { SetPriorityClass(process.0, class) } == 0 {
+            return Err(Error::from_os_error(
+                self.pid,
+                std::io::Error::last_os_error(),
+            ));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,75 @@
+use std::ffi::OsStr;
+use std::os::windows::ffi::OsStrExt;
+
+use winapi::shared::minwindef::FALSE;
+use winapi::shared::ntdef::LUID;
+use winapi::shared::winerror::ERROR_NOT_ALL_ASSIGNED;
+use winapi::um::errhandlingapi::GetLastError;
+use winapi::um::handleapi::CloseHandle;
+use winapi::um::processthreadsapi::{GetCurrentProcess, OpenProcessToken};
+use winapi::um::securitybaseapi::AdjustTokenPrivileges;
+use winapi::um::winbase::LookupPrivilegeValueW;
+use winapi::um::winnt::{
+    HANDLE, LUID_AND_ATTRIBUTES, SE_PRIVILEGE_ENABLED, TOKEN_ADJUST_PRIVILEGES, TOKEN_PRIVILEGES,
+    TOKEN_QUERY,
+};
+
+use super::super::Error;
+
+/// Enables SeDebugPrivilege on the token of the current process. This lets us open
+/// processes belonging to other users (including services), but only succeeds if the
+/// current user holds the privilege - which generally means running as an administrator.
+pub fn enable_debug_privilege() -> Result<(), Error> {
+    enable_privilege("SeDebugPrivilege")
+}
+
+/// Enables a privilege on the token of the current process, if the token holds it
+pub fn enable_privilege(name: &str) -> Result<(), Error> {
+    let name: Vec<u16> = OsStr::new(name)
+        .encode_wide()
+        .chain(std::iter::once(0))
+        .collect();
+
+    unsafe {
+        let mut token: HANDLE = std::ptr::null_mut();
+        if OpenProcessToken(
+            GetCurrentProcess(),
+            TOKEN_ADJUST_PRIVILEGES | TOKEN_QUERY,
+            &mut token,
+        ) == 0
+        {
+            return Err(Error::from(std::io::Error::last_os_error()));
+        }
+
+        let mut luid: LUID = std::mem::zeroed();
+        if LookupPrivilegeValueW(std::ptr::null(), name.as_ptr(), &mut luid) == 0 {
+            let err = std::io::Error::last_os_error();
+            CloseHandle(token);
+            return Err(Error::from(err));
+        }
+
+        let mut privileges = TOKEN_PRIVILEGES {
+            PrivilegeCount: 1,
+            Privileges: [LUID_AND_ATTRIBUTES {
+                Luid: luid,
+                Attributes: SE_PRIVILEGE_ENABLED,
+            }],
+        };
+        let ret = AdjustTokenPrivileges(
+            token,
+            FALSE,
+            &mut privileges,
+            0,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+        );
+        // AdjustTokenPrivileges succeeds even if the privilege isn't held by the token,
+        // setting the last error to ERROR_NOT_ALL_ASSIGNED instead
+        let err = GetLastError();
+        CloseHandle(token);
+        if ret == 0 || err == ERROR_NOT_ALL_ASSIGNED {
+            return Err(Error::from(std::io::Error::from_raw_os_error(err as i32)));
+        }
+    }
+    Ok(())
+}
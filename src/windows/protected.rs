@@ -0,0 +1,123 @@
+//! Detects protected processes (PP and PPL), which refuse PROCESS_VM_READ to everyone -
+//! including administrators with SeDebugPrivilege
+use winapi::shared::minwindef::FALSE;
+use winapi::shared::ntdef::PVOID;
+use winapi::um::processthreadsapi::OpenProcess;
+
+use super::sysinfo::{unicode_string, ProcessList};
+use super::{NtQueryInformationProcess, OwnedHandle, Pid};
+use crate::Error;
+
+const PROCESS_QUERY_LIMITED_INFORMATION: u32 = 0x1000;
+const PROCESS_PROTECTION_INFORMATION: u32 = 61;
+
+// PS_PROTECTED_TYPE values
+const PS_PROTECTED_LIGHT: u8 = 1;
+const PS_PROTECTED: u8 = 2;
+
+/// Who signed a protected process, which decides what other protected processes can open it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ProtectionSigner {
+    Authenticode,
+    CodeGen,
+    Antimalware,
+    Lsa,
+    Windows,
+    WinTcb,
+    WinSystem,
+    App,
+    Other(u8),
+}
+
+/// The protection level of a protected process
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ProcessProtection {
+    /// True for protected process light (PPL), false for a full protected process
+    pub light: bool,
+    pub signer: ProtectionSigner,
+}
+
+/// A protected process we can't attach to, along with what could be found out about it
+/// without reading its memory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProtectedProcess {
+    pub pid: Pid,
+    /// The file name of the process's executable, like `MsMpEng.exe`
+    pub name: Option<String>,
+    pub protection: ProcessProtection,
+}
+
+impl std::fmt::Display for ProtectedProcess {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let kind = if self.protection.light {
+            "a protected process light"
+        } else {
+            "a protected process"
+        };
+        match &self.name {
+            Some(name) => write!(f, "Process {} ({}) is {}", self.pid, name, kind)?,
+            None => write!(f, "Process {} is {}", self.pid, kind)?,
+        }
+        write!(
+            f,
+            " signed by {:?}, so its memory can't be read",
+            self.protection.signer
+        )
+    }
+}
+
+/// Returns the protection of a process, or None if it isn't protected
+pub fn process_protection(pid: Pid) -> Result<Option<ProcessProtection>, Error> {
+    // this is the most access protected processes allow
+    let process = unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, FALSE, pid) };
+    if process.is_null() {
+        return Err(Error::from_os_error(pid, std::io::Error::last_os_error()));
+    }
+    let process = OwnedHandle(process);
+
+    // PS_PROTECTION is a byte with the type in the low 3 bits and the signer in the top 4
+    let mut protection: u8 = 0;
+    let ret = unsafe {
+        NtQueryInformationProcess(
+            process.0,
+            PROCESS_PROTECTION_INFORMATION,
+            &mut protection as *mut u8 as PVOID,
+            1,
+            std::ptr::null_mut(),
+        )
+    };
+    if ret != 0 {
+        // versions of windows before 8.1 don't have this, or PPL
+        return Ok(None);
+    }
+    let light = match protection & 0x7 {
+        PS_PROTECTED_LIGHT => true,
+        PS_PROTECTED => false,
+        _ => return Ok(None),
+    };
+    let signer = match protection >> 4 {
+        1 => ProtectionSigner::Authenticode,
+        2 => ProtectionSigner::CodeGen,
+        3 => ProtectionSigner::Antimalware,
+        4 => ProtectionSigner::Lsa,
+        5 => ProtectionSigner::Windows,
+        6 => ProtectionSigner::WinTcb,
+        7 => ProtectionSigner::WinSystem,
+        8 => ProtectionSigner::App,
+        signer => ProtectionSigner::Other(signer),
+    };
+    Ok(Some(ProcessProtection { light, signer }))
+}
+
+/// Returns the image name of a process from the system process list, which works for any
+/// process regardless of the access we have to it
+pub fn process_name(pid: Pid) -> Option<String> {
+    let processes = ProcessList::query().ok()?;
+    let (entry, _) = processes.find(pid)?;
+    if entry.image_name.Buffer.is_null() {
+        return None;
+    }
+    Some(unicode_string(&entry.image_name))
+}
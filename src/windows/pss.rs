@@ -0,0 +1,240 @@
+//! Snapshots of processes taken with PssCaptureSnapshot.
+//!
+//! The snapshot clones the address space of the process copy-on-write, and captures the
+//! context of each thread, so that stacks can be read and unwound from the snapshot while
+//! the process carries on running. The process is only suspended while the clone is made,
+//! which is much quicker than unwinding every thread of a large process.
+use winapi::shared::minwindef::{DWORD, FALSE, FILETIME, WORD};
+use winapi::shared::ntdef::{HANDLE, PVOID};
+use winapi::um::memoryapi::ReadProcessMemory;
+use winapi::um::processthreadsapi::{GetCurrentProcess, OpenProcess};
+use winapi::um::winnt::{
+    CONTEXT, PROCESS_CREATE_PROCESS, PROCESS_DUP_HANDLE, PROCESS_QUERY_INFORMATION,
+    PROCESS_VM_OPERATION, PROCESS_VM_READ,
+};
+
+use super::{OwnedHandle, Pid, Process, Tid};
+use crate::{Error, ProcessMemory};
+
+type Hpss = HANDLE;
+type HpssWalk = HANDLE;
+
+const PSS_CAPTURE_VA_CLONE: DWORD = 0x1;
+const PSS_CAPTURE_THREADS: DWORD = 0x80;
+const PSS_CAPTURE_THREAD_CONTEXT: DWORD = 0x100;
+const PSS_QUERY_VA_CLONE_INFORMATION: u32 = 1;
+const PSS_WALK_THREADS: u32 = 3;
+const ERROR_NO_MORE_ITEMS: DWORD = 259;
+
+// CONTEXT_FULL differs between architectures
+#[cfg(target_arch = "aarch64")]
+const CONTEXT_FULL: DWORD = 0x00400007;
+#[cfg(not(target_arch = "aarch64"))]
+const CONTEXT_FULL: DWORD = 0x0010000B;
+
+extern "system" {
+    fn PssCaptureSnapshot(
+        process: HANDLE,
+        capture_flags: DWORD,
+        thread_context_flags: DWORD,
+        snapshot: *mut Hpss,
+    ) -> DWORD;
+    fn PssFreeSnapshot(process: HANDLE, snapshot: Hpss) -> DWORD;
+    fn PssQuerySnapshot(snapshot: Hpss, class: u32, buffer: PVOID, len: DWORD) -> DWORD;
+    fn PssWalkMarkerCreate(allocator: PVOID, marker: *mut HpssWalk) -> DWORD;
+    fn PssWalkMarkerFree(marker: HpssWalk) -> DWORD;
+    fn PssWalkSnapshot(
+        snapshot: Hpss,
+        class: u32,
+        marker: HpssWalk,
+        buffer: PVOID,
+        len: DWORD,
+    ) -> DWORD;
+}
+
+/// `PSS_THREAD_ENTRY`
+#[repr(C)]
+struct ThreadEntry {
+    _exit_status: DWORD,
+    _teb_base_address: PVOID,
+    _process_id: DWORD,
+    thread_id: DWORD,
+    _affinity_mask: usize,
+    _priority: i32,
+    _base_priority: i32,
+    _last_syscall_first_argument: PVOID,
+    _last_syscall_number: WORD,
+    _create_time: FILETIME,
+    _exit_time: FILETIME,
+    _kernel_time: FILETIME,
+    _user_time: FILETIME,
+    _win32_start_address: PVOID,
+    _capture_time: FILETIME,
+    _flags: u32,
+    _suspend_count: WORD,
+    _size_of_context_record: WORD,
+    context_record: *const CONTEXT,
+}
+
+/// A thread captured in a snapshot, with its registers at the time
+pub struct SnapshotThread {
+    pub tid: Tid,
+    pub context: Box<CONTEXT>,
+}
+
+/// A copy-on-write clone of a process, returned by `Process::capture_snapshot`
+pub struct ProcessSnapshot {
+    pub pid: Pid,
+    snapshot: Hpss,
+    /// a handle to the clone of the process, which memory is read from. This is owned by
+    /// the snapshot, and closed when it's freed
+    clone: HANDLE,
+    threads: Vec<SnapshotThread>,
+}
+
+impl Process {
+    /// Takes a snapshot of the process's memory and threads with PssCaptureSnapshot, which
+    /// can be read and unwound from without suspending the process. This needs windows 8.1
+    /// or later.
+    pub fn capture_snapshot(&self) -> Result<ProcessSnapshot, Error> {
+        let access = PROCESS_CREATE_PROCESS
+            | PROCESS_QUERY_INFORMATION
+            | PROCESS_VM_OPERATION
+            | PROCESS_VM_READ
+            | PROCESS_DUP_HANDLE;
+        let process = unsafe { OpenProcess(access, FALSE, self.pid) };
+        if process.is_null() {
+            return Err(Error::from_os_error(
+                self.pid,
+                std::io::Error::last_os_error(),
+            ));
+        }
+        let process = OwnedHandle(process);
+
+        let mut snapshot: Hpss = std::ptr::null_mut();
+        let ret = unsafe {
+            PssCaptureSnapshot(
+                process.0,
+                PSS_CAPTURE_VA_CLONE | PSS_CAPTURE_THREADS | PSS_CAPTURE_THREAD_CONTEXT,
+                CONTEXT_FULL,
+                &mut snapshot,
+            )
+        };
+        if ret != 0 {
+            return Err(Error::from_os_error(
+                self.pid,
+                std::io::Error::from_raw_os_error(ret as i32),
+            ));
+        }
+        // the snapshot is freed if anything fails from here on
+        let mut snapshot = ProcessSnapshot {
+            pid: self.pid,
+            snapshot,
+            clone: std::ptr::null_mut(),
+            threads: Vec::new(),
+        };
+
+        let mut clone: HANDLE = std::ptr::null_mut();
+        let ret = unsafe {
+            PssQuerySnapshot(
+                snapshot.snapshot,
+                PSS_QUERY_VA_CLONE_INFORMATION,
+                &mut clone as *mut HANDLE as PVOID,
+                std::mem::size_of::<HANDLE>() as DWORD,
+            )
+        };
+        if ret != 0 {
+            return Err(std::io::Error::from_raw_os_error(ret as i32).into());
+        }
+        snapshot.clone = clone;
+        snapshot.threads = snapshot.walk_threads()?;
+        Ok(snapshot)
+    }
+}
+
+impl ProcessSnapshot {
+    /// The threads of the process when the snapshot was taken
+    pub fn threads(&self) -> &[SnapshotThread] {
+        &self.threads
+    }
+
+    /// Returns a handle to the clone of the process, which can be passed to anything that
+    /// reads memory from a process handle. The handle is only valid for the lifetime of the
+    /// snapshot.
+    pub fn handle(&self) -> HANDLE {
+        self.clone
+    }
+
+    /// Returns an unwinder that reads stacks from the snapshot. Create cursors for the
+    /// threads with `cursor_from_context`, passing the context of each `SnapshotThread`.
+    #[cfg(feature = "unwind")]
+    pub fn unwinder(&self) -> Result<super::Unwinder, Error> {
+        super::Unwinder::new(self.clone)
+    }
+
+    fn walk_threads(&self) -> Result<Vec<SnapshotThread>, Error> {
+        let mut marker: HpssWalk = std::ptr::null_mut();
+        let ret = unsafe { PssWalkMarkerCreate(std::ptr::null_mut(), &mut marker) };
+        if ret != 0 {
+            return Err(std::io::Error::from_raw_os_error(ret as i32).into());
+        }
+
+        let mut threads = Vec::new();
+        let result = loop {
+            let mut entry: ThreadEntry = unsafe { std::mem::zeroed() };
+            let ret = unsafe {
+                PssWalkSnapshot(
+                    self.snapshot,
+                    PSS_WALK_THREADS,
+                    marker,
+                    &mut entry as *mut ThreadEntry as PVOID,
+                    std::mem::size_of::<ThreadEntry>() as DWORD,
+                )
+            };
+            if ret == ERROR_NO_MORE_ITEMS {
+                break Ok(threads);
+            }
+            if ret != 0 {
+                break Err(std::io::Error::from_raw_os_error(ret as i32).into());
+            }
+            // the context lives in the snapshot, so needs copying out before it's freed
+            if entry.context_record.is_null() {
+                continue;
+            }
+            threads.push(SnapshotThread {
+                tid: entry.thread_id,
+                context: Box::new(unsafe { *entry.context_record }),
+            });
+        };
+        unsafe { PssWalkMarkerFree(marker) };
+        result
+    }
+}
+
+impl ProcessMemory for ProcessSnapshot {
+    fn read(&self, addr: usize, buf: &mut [u8]) -> Result<(), Error> {
+        let ret = unsafe {
+            ReadProcessMemory(
+                self.clone,
+                addr as PVOID,
+                buf.as_mut_ptr() as PVOID,
+                buf.len(),
+                std::ptr::null_mut(),
+            )
+        };
+        if ret == 0 {
+            let error = std::io::Error::last_os_error().into();
+            return Err(Error::from_read_error(self.pid, addr, buf.len(), error));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ProcessSnapshot {
+    fn drop(&mut self) {
+        // this also gets rid of the clone of the process
+        unsafe { PssFreeSnapshot(GetCurrentProcess(), self.snapshot) };
+    }
+}
+
+unsafe impl Send for ProcessSnapshot {}
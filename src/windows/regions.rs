@@ -0,0 +1,216 @@
+//! Lists the memory regions of a process with VirtualQueryEx, the closest windows has to
+//! /proc/pid/maps
+use winapi::shared::minwindef::{DWORD, LPVOID};
+use winapi::shared::winerror::ERROR_INVALID_PARAMETER;
+use winapi::um::memoryapi::VirtualQueryEx;
+use winapi::um::winnt::{
+    HANDLE, MEMORY_BASIC_INFORMATION, MEM_COMMIT, MEM_IMAGE, MEM_MAPPED, MEM_PRIVATE, MEM_RESERVE,
+    PAGE_EXECUTE, PAGE_EXECUTE_READ, PAGE_EXECUTE_READWRITE, PAGE_EXECUTE_WRITECOPY, PAGE_GUARD,
+    PAGE_READONLY, PAGE_READWRITE, PAGE_WRITECOPY,
+};
+
+use super::Process;
+#[cfg(feature = "unwind")]
+use crate::AddressRegion;
+use crate::{Error, Protection};
+
+extern "system" {
+    fn K32GetMappedFileNameW(process: HANDLE, addr: LPVOID, name: *mut u16, size: DWORD) -> DWORD;
+}
+
+/// Whether the pages of a region are backed by memory, only reserved, or unallocated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RegionState {
+    Commit,
+    Reserve,
+    Free,
+}
+
+/// What the pages of a region are mapped from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RegionType {
+    /// An executable image, like an exe or dll
+    Image,
+    /// A mapped view of a file or section
+    Mapped,
+    /// Memory private to the process, like heaps and stacks
+    Private,
+}
+
+/// A range of pages in a process that share the same state, protection and type
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MemoryRegion {
+    pub base: u64,
+    pub size: u64,
+    /// The base of the allocation the region is part of, which is where images are loaded
+    pub allocation_base: u64,
+    pub state: RegionState,
+    /// The PAGE_* protection of the pages, which is 0 for reserved and free regions
+    pub protect: u32,
+    /// The type of the region, or None for free regions
+    pub region_type: Option<RegionType>,
+    /// The file that image and mapped regions are mapped from, as an NT path like
+    /// `\Device\HarddiskVolume3\Windows\System32\ntdll.dll`
+    pub filename: Option<String>,
+}
+
+impl MemoryRegion {
+    /// The access allowed to the region, ignoring PAGE_GUARD and the other modifiers
+    pub fn protection(&self) -> Protection {
+        protection(self.protect)
+    }
+
+    pub fn contains(&self, addr: u64) -> bool {
+        addr >= self.base && addr - self.base < self.size
+    }
+}
+
+impl Process {
+    /// Returns the memory regions of the process in order of address, covering its whole
+    /// address space including free regions
+    pub fn regions(&self) -> Result<Vec<MemoryRegion>, Error> {
+        let process = *self.handle as HANDLE;
+        let mut regions: Vec<MemoryRegion> = Vec::new();
+        let mut addr: u64 = 0;
+        loop {
+            let mut info: MEMORY_BASIC_INFORMATION = unsafe { std::mem::zeroed() };
+            let size = std::mem::size_of::<MEMORY_BASIC_INFORMATION>();
+            // this fails with ERROR_INVALID_PARAMETER past the end of the address space
+            if unsafe { VirtualQueryEx(process, addr as LPVOID, &mut info, size) } != size {
+                if regions.is_empty() {
+                    return Err(Error::from_os_error(
+                        self.pid,
+                        std::io::Error::last_os_error(),
+                    ));
+                }
+                break;
+            }
+
+            let state = match info.State {
+                MEM_COMMIT => RegionState::Commit,
+                MEM_RESERVE => RegionState::Reserve,
+                _ => RegionState::Free,
+            };
+            let region_type = match info.Type {
+                MEM_IMAGE => Some(RegionType::Image),
+                MEM_MAPPED => Some(RegionType::Mapped),
+                MEM_PRIVATE => Some(RegionType::Private),
+                _ => None,
+            };
+            let allocation_base = info.AllocationBase as u64;
+            let filename = match region_type {
+                Some(RegionType::Image) | Some(RegionType::Mapped) => {
+                    // the regions of an image are all mapped from the same file
+                    match regions.last() {
+                        Some(last) if last.allocation_base == allocation_base => {
+                            last.filename.clone()
+                        }
+                        _ => mapped_filename(process, info.BaseAddress),
+                    }
+                }
+                _ => None,
+            };
+
+            regions.push(MemoryRegion {
+                base: info.BaseAddress as u64,
+                size: info.RegionSize as u64,
+                allocation_base,
+                state,
+                protect: info.Protect,
+                region_type,
+                filename,
+            });
+
+            let next = info.BaseAddress as u64 + info.RegionSize as u64;
+            if next <= addr {
+                break;
+            }
+            addr = next;
+        }
+        Ok(regions)
+    }
+
+    /// Returns the protection of the pages at `addr`, or None if they aren't committed
+    pub fn query_address(&self, addr: u64) -> Result<Option<Protection>, Error> {
+        let mut info: MEMORY_BASIC_INFORMATION = unsafe { std::mem::zeroed() };
+        let size = std::mem::size_of::<MEMORY_BASIC_INFORMATION>();
+        let process = *self.handle as HANDLE;
+        // this fails with ERROR_INVALID_PARAMETER past the end of the address space
+        if unsafe { VirtualQueryEx(process, addr as LPVOID, &mut info, size) } != size {
+            let error = std::io::Error::last_os_error();
+            if error.raw_os_error() == Some(ERROR_INVALID_PARAMETER as i32) {
+                return Ok(None);
+            }
+            return Err(Error::from_os_error(self.pid, error));
+        }
+        if info.State != MEM_COMMIT {
+            return Ok(None);
+        }
+        // guard pages fault on the first access, rather than being readable
+        if info.Protect & PAGE_GUARD != 0 {
+            return Ok(Some(Protection::default()));
+        }
+        Ok(Some(protection(info.Protect)))
+    }
+}
+
+/// Returns the committed region containing an address, for the symbolicator's unknown
+/// address handler
+#[cfg(feature = "unwind")]
+pub(super) fn address_region(process: HANDLE, addr: u64) -> Option<AddressRegion> {
+    let mut info: MEMORY_BASIC_INFORMATION = unsafe { std::mem::zeroed() };
+    let size = std::mem::size_of::<MEMORY_BASIC_INFORMATION>();
+    if unsafe { VirtualQueryEx(process, addr as LPVOID, &mut info, size) } != size
+        || info.State != MEM_COMMIT
+    {
+        return None;
+    }
+    let filename = match info.Type {
+        MEM_IMAGE | MEM_MAPPED => mapped_filename(process, info.BaseAddress),
+        _ => None,
+    };
+    Some(AddressRegion {
+        start: info.BaseAddress as u64,
+        size: info.RegionSize as u64,
+        protection: protection(info.Protect),
+        filename,
+    })
+}
+
+/// Converts PAGE_* flags to the access they allow, ignoring the modifiers like PAGE_GUARD
+fn protection(protect: u32) -> Protection {
+    let (read, write, execute) = match protect & 0xff {
+        PAGE_READONLY => (true, false, false),
+        PAGE_READWRITE | PAGE_WRITECOPY => (true, true, false),
+        PAGE_EXECUTE => (false, false, true),
+        PAGE_EXECUTE_READ => (true, false, true),
+        PAGE_EXECUTE_READWRITE | PAGE_EXECUTE_WRITECOPY => (true, true, true),
+        _ => (false, false, false),
+    };
+    Protection {
+        read,
+        write,
+        execute,
+    }
+}
+
+fn mapped_filename(process: HANDLE, addr: LPVOID) -> Option<String> {
+    let mut name: Vec<u16> = vec![0; 1024];
+    loop {
+        let len =
+            unsafe { K32GetMappedFileNameW(process, addr, name.as_mut_ptr(), name.len() as DWORD) }
+                as usize;
+        if len == 0 {
+            return None;
+        }
+        // the name is truncated to fit when the buffer is too small
+        if len >= name.len() - 1 && name.len() < 32768 {
+            name.resize(name.len() * 2, 0);
+            continue;
+        }
+        return Some(String::from_utf16_lossy(&name[..len]));
+    }
+}
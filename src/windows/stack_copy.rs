@@ -0,0 +1,101 @@
+//! Copies the registers and stack of a suspended thread, so that it can be resumed right
+//! away and unwound later from the copy
+use winapi::um::processthreadsapi::{GetProcessIdOfThread, GetThreadContext};
+use winapi::um::winnt::{CONTEXT, HANDLE};
+
+use super::{Process, Thread, Tid};
+use crate::{Error, ProcessMemory, StackCopy};
+
+// the control and integer registers, which differ between architectures
+#[cfg(target_arch = "aarch64")]
+const CONTEXT_INTEGER: u32 = 0x00400003;
+#[cfg(target_arch = "x86_64")]
+const CONTEXT_INTEGER: u32 = 0x00100003;
+#[cfg(target_arch = "x86")]
+const CONTEXT_INTEGER: u32 = 0x00010007;
+
+// the NEON or x87 and SSE registers
+#[cfg(target_arch = "aarch64")]
+const CONTEXT_FLOATING_POINT: u32 = 0x00400004;
+#[cfg(target_arch = "x86_64")]
+const CONTEXT_FLOATING_POINT: u32 = 0x00100008;
+// CONTEXT_FLOATING_POINT | CONTEXT_EXTENDED_REGISTERS
+#[cfg(target_arch = "x86")]
+const CONTEXT_FLOATING_POINT: u32 = 0x00010028;
+
+/// GetThreadContext needs the CONTEXT to be 16 byte aligned
+#[repr(C, align(16))]
+struct Context(CONTEXT);
+
+impl Process {
+    /// Returns the registers of every thread in the process, including the floating point
+    /// registers. The process is suspended once, the contexts of its threads are read one
+    /// after another, and then it's resumed right away - so it's stopped for as short a time
+    /// as possible.
+    pub fn capture_all_registers(&self) -> Result<Vec<(Tid, CONTEXT)>, Error> {
+        let lock = self.lock_and_snapshot()?;
+        lock.threads()
+            .iter()
+            .map(|thread| Ok((thread.id()?, thread.context(true)?)))
+            .collect()
+    }
+}
+
+impl Thread {
+    /// Returns the registers of this thread with GetThreadContext. The floating point and
+    /// SSE (or NEON) registers are only filled in if `floating_point` is set, otherwise
+    /// they're left zeroed. The thread needs to be locked.
+    pub fn context(&self, floating_point: bool) -> Result<CONTEXT, Error> {
+        let mut context: Box<Context> = Box::new(unsafe { std::mem::zeroed() });
+        context.0.ContextFlags = if floating_point {
+            CONTEXT_INTEGER | CONTEXT_FLOATING_POINT
+        } else {
+            CONTEXT_INTEGER
+        };
+        if unsafe { GetThreadContext(*self.thread as HANDLE, &mut context.0) } == 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok(context.0)
+    }
+
+    /// Copies the registers of this thread, and its stack from the stack pointer up to the
+    /// stack base in its TEB - at most `max_bytes` of it. The thread needs to be locked, but
+    /// only for as long as this takes: the copy can be unwound after the lock has been
+    /// released, since it implements `ProcessMemory` itself.
+    ///
+    /// Threads of WOW64 processes aren't supported.
+    pub fn copy_stack(&self, max_bytes: usize) -> Result<StackCopy<CONTEXT>, Error> {
+        let thread = *self.thread as HANDLE;
+        let pid = unsafe { GetProcessIdOfThread(thread) };
+        if pid == 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        let process = Process::new(pid)?;
+        if cfg!(target_pointer_width = "64") && process.is_wow64()? {
+            return Err(Error::Other(format!(
+                "can't copy the stack of a thread of WOW64 process {}",
+                pid
+            )));
+        }
+
+        let context = self.context(true)?;
+        #[cfg(target_arch = "x86_64")]
+        let sp = context.Rsp;
+        #[cfg(target_arch = "aarch64")]
+        let sp = context.Sp;
+        #[cfg(target_arch = "x86")]
+        let sp = context.Esp as u64;
+
+        // the TEB starts with an NT_TIB, which has the stack base after the exception list
+        let teb = self.tls_base()?;
+        let base = process.copy_struct::<usize>(teb as usize + std::mem::size_of::<usize>())?;
+        let len = (base as u64).saturating_sub(sp).min(max_bytes as u64) as usize;
+        let stack = process.copy(sp as usize, len)?;
+        Ok(StackCopy {
+            tid: self.id()?,
+            registers: context,
+            sp,
+            stack,
+        })
+    }
+}
@@ -0,0 +1,170 @@
+//! Downloads PDBs from symbol servers listed in _NT_SYMBOL_PATH, without needing symsrv.dll
+//! from the Debugging Tools for Windows. PDBs are stored in the same layout symsrv uses
+//! (`<cache>/<name>/<guid><age>/<name>`), so caches can be shared with windbg and Visual
+//! Studio.
+use std::path::PathBuf;
+use std::time::Duration;
+
+use log::debug;
+
+use crate::download::download;
+
+/// A symbol server, along with the local directory it is cached in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SymbolServer {
+    pub url: String,
+    pub cache: PathBuf,
+}
+
+/// The symbol servers from a symbol search path like
+/// `srv*c:\symbols*https://msdl.microsoft.com/download/symbols`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SymbolPath {
+    servers: Vec<SymbolServer>,
+    timeout: Duration,
+}
+
+impl SymbolPath {
+    /// Parses the srv* and cache* elements of a symbol path. Plain directories in the path
+    /// are ignored, since dbghelp already searches those itself.
+    pub fn parse(path: &str) -> SymbolPath {
+        let mut servers = Vec::new();
+        let mut default_cache = None;
+        for element in path.split(';').map(str::trim) {
+            let mut parts: Vec<&str> = element.split('*').collect();
+            match parts[0].to_ascii_lowercase().as_str() {
+                "srv" => {
+                    parts.remove(0);
+                }
+                // symsrv*symsrv.dll*... is the long form of srv*...
+                "symsrv" if parts.len() > 2 => {
+                    parts.drain(..2);
+                }
+                "cache" => {
+                    default_cache = parts.get(1).map(PathBuf::from);
+                    continue;
+                }
+                _ => continue,
+            }
+
+            // the last entry is the server, and any before it are local stores - the first
+            // of which caches the rest
+            let url = match parts.pop() {
+                Some(url) if url.starts_with("http://") || url.starts_with("https://") => url,
+                _ => continue,
+            };
+            let cache = parts
+                .first()
+                .filter(|cache| !cache.is_empty())
+                .map(PathBuf::from)
+                .or_else(|| default_cache.clone())
+                .unwrap_or_else(default_cache_dir);
+            servers.push(SymbolServer {
+                url: url.trim_end_matches('/').to_owned(),
+                cache,
+            });
+        }
+        SymbolPath {
+            servers,
+            timeout: Duration::from_secs(60),
+        }
+    }
+
+    /// Reads the symbol path from _NT_SYMBOL_PATH and _NT_ALT_SYMBOL_PATH, returning None if
+    /// neither has any symbol servers
+    pub fn from_env() -> Option<SymbolPath> {
+        let path: Vec<String> = ["_NT_SYMBOL_PATH", "_NT_ALT_SYMBOL_PATH"]
+            .iter()
+            .filter_map(|name| std::env::var(name).ok())
+            .collect();
+        let path = SymbolPath::parse(&path.join(";"));
+        if path.servers.is_empty() {
+            None
+        } else {
+            Some(path)
+        }
+    }
+
+    pub fn servers(&self) -> &[SymbolServer] {
+        &self.servers
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Returns the path to a PDB identified by its name, GUID and age - downloading it from
+    /// the first symbol server that has it if it isn't already cached
+    pub fn find_pdb(&self, name: &str, guid: &[u8; 16], age: u32) -> Option<PathBuf> {
+        let key = pdb_key(guid, age);
+        for server in &self.servers {
+            let cached = server.cache.join(name).join(&key).join(name);
+            if cached.exists() {
+                return Some(cached);
+            }
+            let url = format!("{}/{}/{}/{}", server.url, name, key, name);
+            match download(&url, &cached, self.timeout) {
+                Ok(()) => return Some(cached),
+                Err(e) => debug!("failed to download {}: {:?}", url, e),
+            }
+        }
+        None
+    }
+}
+
+/// The directory a PDB is stored under in a symbol store: the GUID as it's printed by
+/// windbg without the dashes, followed by the age in hex. The first three fields of the
+/// GUID are little endian, so are byte swapped relative to how it's stored.
+fn pdb_key(guid: &[u8; 16], age: u32) -> String {
+    let data1 = u32::from_le_bytes([guid[0], guid[1], guid[2], guid[3]]);
+    let data2 = u16::from_le_bytes([guid[4], guid[5]]);
+    let data3 = u16::from_le_bytes([guid[6], guid[7]]);
+    let data4: String = guid[8..].iter().map(|b| format!("{:02X}", b)).collect();
+    format!("{:08X}{:04X}{:04X}{}{:X}", data1, data2, data3, data4, age)
+}
+
+fn default_cache_dir() -> PathBuf {
+    std::env::temp_dir().join("SymbolCache")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_symbol_path() {
+        let path = SymbolPath::parse(
+            "c:\\local;srv*c:\\symbols*https://msdl.microsoft.com/download/symbols;\
+             cache*d:\\cache;SRV*http://symbols.example.com/;symsrv*symsrv.dll*e:\\store*https://other/",
+        );
+        assert_eq!(
+            path.servers(),
+            &[
+                SymbolServer {
+                    url: "https://msdl.microsoft.com/download/symbols".to_owned(),
+                    cache: PathBuf::from("c:\\symbols"),
+                },
+                SymbolServer {
+                    url: "http://symbols.example.com".to_owned(),
+                    cache: PathBuf::from("d:\\cache"),
+                },
+                SymbolServer {
+                    url: "https://other".to_owned(),
+                    cache: PathBuf::from("e:\\store"),
+                },
+            ]
+        );
+        assert!(SymbolPath::parse("c:\\local").servers().is_empty());
+    }
+
+    #[test]
+    fn test_pdb_key() {
+        // {1B0F7C56-FD86-4D72-9AE4-B2A0C3E4F5A6}, age 1
+        let guid = [
+            0x56, 0x7c, 0x0f, 0x1b, 0x86, 0xfd, 0x72, 0x4d, 0x9a, 0xe4, 0xb2, 0xa0, 0xc3, 0xe4,
+            0xf5, 0xa6,
+        ];
+        assert_eq!(pdb_key(&guid, 1), "1B0F7C56FD864D729AE4B2A0C3E4F5A61");
+        assert_eq!(pdb_key(&guid, 0x1a), "1B0F7C56FD864D729AE4B2A0C3E4F5A61A");
+    }
+}
@@ -1,9 +1,11 @@
-use libc::wcslen;
-use log::info;
+use libc::{c_void, wcslen};
+use log::{info, warn};
+#[cfg(feature = "symbol-server")]
+use std::os::windows::ffi::OsStrExt;
 use std::os::windows::ffi::OsStringExt;
 use winapi::shared::basetsd::DWORD64;
 use winapi::shared::guiddef::GUID;
-use winapi::shared::minwindef::{BOOL, DWORD, MAX_PATH, TRUE};
+use winapi::shared::minwindef::{BOOL, DWORD, FALSE, MAX_PATH, TRUE};
 use winapi::um::dbghelp::{
     SymCleanup, SymFromAddrW, SymGetLineFromAddrW64, SymInitializeW, IMAGEHLP_LINEW64,
     MAX_SYM_NAME, SYMBOL_INFOW,
@@ -11,48 +13,347 @@ use winapi::um::dbghelp::{
 use winapi::um::errhandlingapi::GetLastError;
 use winapi::um::winnt::{HANDLE, WCHAR};
 
+use super::super::DemangleOptions;
 use super::super::Error;
+use super::super::Frames;
 use super::super::StackFrame;
+use super::super::{Module, ModuleId, UnknownAddressHandler};
+#[cfg(feature = "symbol-server")]
+use super::symbol_server::SymbolPath;
 
 pub struct Symbolicator {
     pub handle: HANDLE,
+    demangle: DemangleOptions,
+    #[cfg(feature = "symbol-server")]
+    symbol_path: Option<SymbolPath>,
+    unknown_address: Option<UnknownAddressHandler>,
 }
 
 impl Symbolicator {
     pub fn new(handle: HANDLE) -> Result<Symbolicator, Error> {
         unsafe {
+            // without this dbghelp ignores the modules of 32-bit WoW64 processes
+            SymSetOptions(SymGetOptions() | SYMOPT_INCLUDE_32BIT_MODULES);
             if SymInitializeW(handle, std::ptr::null_mut(), TRUE) == 0 {
                 return Err(Error::from(std::io::Error::last_os_error()));
             };
-            Ok(Symbolicator { handle })
+            let ret = Symbolicator {
+                handle,
+                demangle: DemangleOptions::default(),
+                #[cfg(feature = "symbol-server")]
+                symbol_path: SymbolPath::from_env(),
+                unknown_address: None,
+            };
+            #[cfg(feature = "symbol-server")]
+            ret.fetch_pdbs();
+            Ok(ret)
         }
     }
 
+    /// Creates a symbolicator for a target that isn't a local process, like a core dump or
+    /// a remote agent, from the list of modules it has loaded. The filename of each module
+    /// needs to be a path to a copy of the binary that can be opened locally.
+    pub fn with_modules(modules: &[Module]) -> Result<Symbolicator, Error> {
+        let handle = super::pseudo_handle();
+        unsafe {
+            SymSetOptions(SymGetOptions() | SYMOPT_INCLUDE_32BIT_MODULES);
+            // dbghelp accepts any unique value in place of a process handle, as long as it
+            // isn't asked to enumerate the modules of the process itself
+            if SymInitializeW(handle, std::ptr::null_mut(), FALSE) == 0 {
+                return Err(Error::from(std::io::Error::last_os_error()));
+            }
+            for module in modules {
+                let filename: Vec<WCHAR> = module.filename.encode_utf16().chain([0]).collect();
+                if SymLoadModuleExW(
+                    handle,
+                    std::ptr::null_mut(),
+                    filename.as_ptr(),
+                    std::ptr::null(),
+                    module.address,
+                    module.size as DWORD,
+                    std::ptr::null_mut(),
+                    0,
+                ) == 0
+                {
+                    warn!(
+                        "failed to load {}: {}",
+                        module.filename,
+                        std::io::Error::last_os_error()
+                    );
+                }
+            }
+        }
+        let ret = Symbolicator {
+            handle,
+            demangle: DemangleOptions::default(),
+            #[cfg(feature = "symbol-server")]
+            symbol_path: SymbolPath::from_env(),
+            unknown_address: None,
+        };
+        #[cfg(feature = "symbol-server")]
+        ret.fetch_pdbs();
+        Ok(ret)
+    }
+
     pub fn reload(&mut self) -> Result<(), Error> {
         info!("reloading symbol module list");
         unsafe {
             SymRefreshModuleList(self.handle);
         }
+        #[cfg(feature = "symbol-server")]
+        self.fetch_pdbs();
         Ok(())
     }
 
+    /// Sets the symbol servers to download missing PDBs from. This defaults to the servers
+    /// in _NT_SYMBOL_PATH, which dbghelp can't use itself without symsrv.dll.
+    #[cfg(feature = "symbol-server")]
+    pub fn set_symbol_path(&mut self, symbol_path: Option<SymbolPath>) {
+        self.symbol_path = symbol_path;
+        self.fetch_pdbs();
+    }
+
+    /// Downloads the PDBs for any modules that dbghelp couldn't find symbols for, and then
+    /// reloads those modules with the downloaded PDBs on the search path
+    #[cfg(feature = "symbol-server")]
+    fn fetch_pdbs(&self) {
+        let symbol_path = match self.symbol_path.as_ref() {
+            Some(symbol_path) => symbol_path,
+            None => return,
+        };
+
+        let mut directories = Vec::new();
+        let mut modules = Vec::new();
+        for info in self.module_infos() {
+            let has_symbols = matches!(
+                info.SymType,
+                SYM_TYPE::SymPdb | SYM_TYPE::SymCv | SYM_TYPE::SymDia
+            );
+            if has_symbols || info.CVSig != CV_SIGNATURE_RSDS {
+                continue;
+            }
+
+            let cv_data = wide_string(&info.CVData);
+            let name = match pdb_name(&cv_data) {
+                Some(name) => name,
+                None => continue,
+            };
+            let signature = guid_bytes(&info.PdbSig70);
+            if let Some(pdb) = symbol_path.find_pdb(name, &signature, info.PdbAge) {
+                info!(
+                    "using {} for {}",
+                    pdb.display(),
+                    wide_string(&info.ImageName)
+                );
+                if let Some(directory) = pdb.parent() {
+                    directories.push(directory.as_os_str().to_owned());
+                }
+                modules.push((info.BaseOfImage, info.ImageSize, info.ImageName));
+            }
+        }
+        if modules.is_empty() {
+            return;
+        }
+
+        unsafe {
+            let mut search_path = vec![0_u16; 32768];
+            if SymGetSearchPathW(
+                self.handle,
+                search_path.as_mut_ptr(),
+                search_path.len() as DWORD,
+            ) == TRUE
+            {
+                search_path.truncate(wcslen(search_path.as_ptr()));
+            } else {
+                search_path.clear();
+            }
+            for directory in directories {
+                search_path.push(b';' as u16);
+                search_path.extend(directory.encode_wide());
+            }
+            search_path.push(0);
+            SymSetSearchPathW(self.handle, search_path.as_ptr());
+
+            for (base, size, image_name) in modules {
+                SymUnloadModule64(self.handle, base);
+                SymLoadModuleExW(
+                    self.handle,
+                    std::ptr::null_mut(),
+                    image_name.as_ptr(),
+                    std::ptr::null(),
+                    base,
+                    size,
+                    std::ptr::null_mut(),
+                    0,
+                );
+            }
+        }
+    }
+
+    /// Returns the modules loaded by the process, along with the GUID and age of the PDB
+    /// each was linked with
+    pub fn modules(&self) -> Vec<Module> {
+        self.module_infos()
+            .iter()
+            .map(|info| {
+                let cv_data = wide_string(&info.CVData);
+                let id = match pdb_name(&cv_data) {
+                    Some(name) if info.CVSig == CV_SIGNATURE_RSDS => Some(ModuleId::Pdb {
+                        guid: guid_bytes(&info.PdbSig70),
+                        age: info.PdbAge,
+                        name: name.to_owned(),
+                    }),
+                    _ => None,
+                };
+                Module {
+                    filename: wide_string(&info.ImageName),
+                    address: info.BaseOfImage,
+                    size: info.ImageSize as u64,
+                    // addresses in PE files are relative to where the image is loaded
+                    bias: info.BaseOfImage,
+                    id,
+                }
+            })
+            .collect()
+    }
+
+    // returns the dbghelp module info for each module loaded by the process
+    fn module_infos(&self) -> Vec<IMAGEHLP_MODULEW64> {
+        let mut bases: Vec<DWORD64> = Vec::new();
+        unsafe {
+            SymEnumerateModulesW64(
+                self.handle,
+                enumerate_module,
+                &mut bases as *mut Vec<DWORD64> as *mut c_void,
+            );
+        }
+        bases
+            .into_iter()
+            .filter_map(|base| {
+                let mut info = unsafe { std::mem::zeroed::<IMAGEHLP_MODULEW64>() };
+                info.SizeOfStruct = std::mem::size_of_val(&info) as u32;
+                if unsafe { SymGetModuleInfoW64(self.handle, base, &mut info) } != TRUE {
+                    return None;
+                }
+                Some(info)
+            })
+            .collect()
+    }
+
+    /// Sets how function names are demangled in the frames returned by symbolicate. Note
+    /// that dbghelp's options are global, so undecorating MSVC names here also stops dbghelp
+    /// from undecorating them for any other symbolicator in this process.
+    pub fn set_demangle_options(&mut self, options: DemangleOptions) {
+        unsafe {
+            let current = SymGetOptions();
+            if options.msvc {
+                SymSetOptions(current & !SYMOPT_UNDNAME);
+            } else {
+                SymSetOptions(current | SYMOPT_UNDNAME);
+            }
+        }
+        self.demangle = options;
+    }
+
+    /// Sets a handler for the addresses that aren't in any module dbghelp knows about, which
+    /// can supply the frames for them instead of them being returned as bare addresses
+    pub fn set_unknown_address_handler(&mut self, handler: Option<UnknownAddressHandler>) {
+        self.unknown_address = handler;
+    }
+
     pub fn symbolicate(
         &self,
         addr: u64,
         line_info: bool,
         callback: &mut dyn FnMut(&StackFrame),
+    ) -> Result<(), Error> {
+        if !self.demangle.enabled() {
+            return self.lookup(addr, line_info, callback);
+        }
+        self.lookup(addr, line_info, &mut |frame| {
+            callback(&self.demangle.frame(frame))
+        })
+    }
+
+    /// Symbolicates many addresses at once, returning the frames for each in the same order
+    /// as the addresses. Addresses are resolved in sorted order, so that dbghelp looks up
+    /// each module's symbols in a single run, and repeated addresses are only resolved once.
+    pub fn symbolicate_many(&self, addrs: &[u64], line_info: bool) -> Vec<Frames> {
+        let mut order: Vec<usize> = (0..addrs.len()).collect();
+        order.sort_unstable_by_key(|&i| addrs[i]);
+
+        let mut results: Vec<Option<Frames>> = addrs.iter().map(|_| None).collect();
+        let mut previous: Option<usize> = None;
+        for i in order {
+            let addr = addrs[i];
+            if let Some(Some(Ok(frames))) =
+                previous.filter(|&p| addrs[p] == addr).map(|p| &results[p])
+            {
+                results[i] = Some(Ok(frames.clone()));
+                continue;
+            }
+            let mut frames = Vec::new();
+            let result = self.symbolicate(addr, line_info, &mut |frame| frames.push(frame.clone()));
+            results[i] = Some(result.map(|()| frames));
+            previous = Some(i);
+        }
+        results.into_iter().flatten().collect()
+    }
+
+    fn lookup(
+        &self,
+        addr: u64,
+        line_info: bool,
+        callback: &mut dyn FnMut(&StackFrame),
     ) -> Result<(), Error> {
         let function = unsafe { self.symbol_function(addr) };
 
-        let module = match unsafe { self.symbol_module(addr) } {
-            Ok(module) => module,
+        let (module, module_offset) = match unsafe { self.module_info(addr) } {
+            Ok((module, base)) => (module, Some(addr - base)),
             Err(Error::NoBinaryForAddress(_)) => unsafe {
                 SymRefreshModuleList(self.handle);
-                self.symbol_module(addr).unwrap_or_else(|_| "?".to_owned())
+                match self.module_info(addr) {
+                    Ok((module, base)) => (module, Some(addr - base)),
+                    Err(_) => ("?".to_owned(), None),
+                }
             },
-            Err(_) => "?".to_owned(),
+            Err(_) => ("?".to_owned(), None),
         };
 
+        if function.is_none() && module_offset.is_none() {
+            if let Some(handler) = self.unknown_address.as_ref() {
+                let region = super::regions::address_region(self.handle, addr);
+                if let Some(frames) = handler(addr, region.as_ref()) {
+                    for frame in frames.iter() {
+                        callback(frame);
+                    }
+                    return Ok(());
+                }
+            }
+        }
+
+        // functions inlined at this address are reported through inline contexts, innermost
+        // first, before the function that they were inlined into
+        for context in unsafe { self.inline_contexts(addr) } {
+            let function = unsafe { self.inline_function(addr, context) };
+            let (filename, line) = if line_info {
+                unsafe { self.inline_filename(addr, context) }.unzip()
+            } else {
+                (None, None)
+            };
+            callback(&StackFrame {
+                function,
+                filename,
+                line,
+                // PDB line records don't include columns
+                column: None,
+                module: module.clone(),
+                addr,
+                module_offset,
+                inlined: true,
+            });
+        }
+
         let mut line = None;
         let mut filename = None;
 
@@ -66,12 +367,78 @@ impl Symbolicator {
             function,
             filename,
             line,
+            column: None,
             module,
             addr,
+            module_offset,
+            inlined: false,
         });
         Ok(())
     }
 
+    // returns the inline contexts of the functions inlined at an address
+    unsafe fn inline_contexts(&self, addr: u64) -> std::ops::Range<DWORD> {
+        let count = SymAddrIncludeInlineTrace(self.handle, addr);
+        if count == 0 {
+            return 0..0;
+        }
+        let mut context = 0;
+        let mut frame_index = 0;
+        if SymQueryInlineTrace(
+            self.handle,
+            addr,
+            0,
+            addr,
+            addr,
+            &mut context,
+            &mut frame_index,
+        ) != TRUE
+        {
+            return 0..0;
+        }
+        context..context + count
+    }
+
+    // returns the name of the function inlined at an address for an inline context
+    unsafe fn inline_function(&self, addr: u64, context: DWORD) -> Option<String> {
+        let mut buffer = std::mem::zeroed::<SymbolBuffer>();
+        let symbol_info = &mut *(buffer.buffer.as_mut_ptr() as *mut SYMBOL_INFOW);
+        symbol_info.MaxNameLen = MAX_SYM_NAME as u32;
+        symbol_info.SizeOfStruct = 88;
+
+        let mut displacement = 0;
+        if SymFromInlineContextW(self.handle, addr, context, &mut displacement, symbol_info) != TRUE
+        {
+            return None;
+        }
+
+        let length = std::cmp::min(
+            symbol_info.NameLen as usize,
+            symbol_info.MaxNameLen as usize - 1,
+        );
+        let symbol = std::slice::from_raw_parts(symbol_info.Name.as_ptr() as *const u16, length);
+        let symbol = std::ffi::OsString::from_wide(symbol);
+        Some(symbol.to_string_lossy().to_string())
+    }
+
+    // get the filename/line of the function inlined at an address for an inline context
+    unsafe fn inline_filename(&self, addr: u64, context: DWORD) -> Option<(String, u64)> {
+        let mut displacement = 0;
+        let mut info = std::mem::zeroed::<IMAGEHLP_LINEW64>();
+        info.SizeOfStruct = std::mem::size_of_val(&info) as u32;
+        if SymGetLineFromInlineContextW(self.handle, addr, context, 0, &mut displacement, &mut info)
+            != TRUE
+        {
+            return None;
+        }
+        let filename = std::slice::from_raw_parts(info.FileName, wcslen(info.FileName));
+        let filename = std::ffi::OsString::from_wide(filename);
+        Some((
+            filename.to_string_lossy().to_string(),
+            info.LineNumber.into(),
+        ))
+    }
+
     // returns the corresponding function name for an address
     pub unsafe fn symbol_function(&self, addr: u64) -> Option<String> {
         let mut buffer = std::mem::zeroed::<SymbolBuffer>();
@@ -95,6 +462,25 @@ impl Symbolicator {
         Some(symbol.to_string_lossy().to_owned().to_string())
     }
 
+    /// Returns the address of a symbol in the process, from the exports or PDB public
+    /// symbols of the loaded modules. The name can be qualified with the module it is in,
+    /// like `python311!_PyRuntime`.
+    pub fn find_symbol(&self, name: &str) -> Option<u64> {
+        let name: Vec<u16> = name.encode_utf16().chain(std::iter::once(0)).collect();
+        unsafe {
+            let mut buffer = std::mem::zeroed::<SymbolBuffer>();
+            let symbol_info = &mut *(buffer.buffer.as_mut_ptr() as *mut SYMBOL_INFOW);
+            symbol_info.MaxNameLen = MAX_SYM_NAME as u32;
+            symbol_info.SizeOfStruct = 88;
+            if SymFromNameW(self.handle, name.as_ptr(), symbol_info) != TRUE {
+                return None;
+            }
+            // modules are loaded at the base address they have in the process, so this
+            // already includes the ASLR slide
+            Some(symbol_info.Address)
+        }
+    }
+
     // get the corresponding filename/link
     pub unsafe fn symbol_filename(&self, addr: u64) -> Option<(String, u64)> {
         let mut displacement = 0;
@@ -113,6 +499,11 @@ impl Symbolicator {
 
     // get the corresponding module name
     pub unsafe fn symbol_module(&self, addr: u64) -> Result<String, Error> {
+        Ok(self.module_info(addr)?.0)
+    }
+
+    /// Returns the filename and base address of the module containing an address
+    unsafe fn module_info(&self, addr: u64) -> Result<(String, u64), Error> {
         let mut info = std::mem::zeroed::<IMAGEHLP_MODULEW64>();
         info.SizeOfStruct = std::mem::size_of_val(&info) as u32;
         if SymGetModuleInfoW64(self.handle, addr, &mut info) != TRUE {
@@ -125,7 +516,66 @@ impl Symbolicator {
         let filename = std::slice::from_raw_parts(filename, wcslen(filename));
         let filename = std::ffi::OsString::from_wide(filename);
 
-        Ok(filename.to_string_lossy().to_owned().to_string())
+        Ok((
+            filename.to_string_lossy().to_owned().to_string(),
+            info.BaseOfImage,
+        ))
+    }
+}
+
+extern "system" fn enumerate_module(
+    _name: *const WCHAR,
+    base: DWORD64,
+    context: *mut c_void,
+) -> BOOL {
+    let bases = unsafe { &mut *(context as *mut Vec<DWORD64>) };
+    bases.push(base);
+    TRUE
+}
+
+/// Returns the file name of a PDB from the path recorded in a binary's debug directory,
+/// which is where it was written at build time
+fn pdb_name(path: &str) -> Option<&str> {
+    path.rsplit(['\\', '/'])
+        .next()
+        .filter(|name| !name.is_empty())
+}
+
+// the bytes of a GUID in the order it's stored in a PDB
+fn guid_bytes(guid: &GUID) -> [u8; 16] {
+    let mut bytes = [0_u8; 16];
+    bytes[..4].copy_from_slice(&guid.Data1.to_le_bytes());
+    bytes[4..6].copy_from_slice(&guid.Data2.to_le_bytes());
+    bytes[6..8].copy_from_slice(&guid.Data3.to_le_bytes());
+    bytes[8..].copy_from_slice(&guid.Data4);
+    bytes
+}
+
+fn wide_string(buffer: &[WCHAR]) -> String {
+    let len = buffer.iter().position(|c| *c == 0).unwrap_or(buffer.len());
+    String::from_utf16_lossy(&buffer[..len])
+}
+
+impl crate::SymbolResolver for Symbolicator {
+    fn reload(&mut self) -> Result<(), Error> {
+        Symbolicator::reload(self)
+    }
+
+    fn symbolicate(
+        &self,
+        addr: u64,
+        line_info: bool,
+        callback: &mut dyn FnMut(&StackFrame),
+    ) -> Result<(), Error> {
+        Symbolicator::symbolicate(self, addr, line_info, callback)
+    }
+
+    fn symbolicate_many(&self, addrs: &[u64], line_info: bool) -> Vec<Frames> {
+        Symbolicator::symbolicate_many(self, addrs, line_info)
+    }
+
+    fn modules(&self) -> Vec<Module> {
+        Symbolicator::modules(self)
     }
 }
 
@@ -188,8 +638,71 @@ pub struct IMAGEHLP_MODULEW64 {
     pub Reserved: DWORD,
 }
 
+const SYMOPT_UNDNAME: DWORD = 0x00000002;
+// the signature of a PDB 7.0 CodeView record
+const CV_SIGNATURE_RSDS: DWORD = 0x5344_5352;
+const SYMOPT_INCLUDE_32BIT_MODULES: DWORD = 0x00002000;
+
 #[link(name = "dbghelp")]
 extern "system" {
+    fn SymGetOptions() -> DWORD;
+    fn SymSetOptions(options: DWORD) -> DWORD;
     fn SymGetModuleInfoW64(process: HANDLE, addr: u64, info: *mut IMAGEHLP_MODULEW64) -> BOOL;
     fn SymRefreshModuleList(process: HANDLE) -> BOOL;
+    fn SymFromNameW(process: HANDLE, name: *const WCHAR, symbol: *mut SYMBOL_INFOW) -> BOOL;
+    fn SymEnumerateModulesW64(
+        process: HANDLE,
+        callback: extern "system" fn(*const WCHAR, DWORD64, *mut c_void) -> BOOL,
+        context: *mut c_void,
+    ) -> BOOL;
+    fn SymGetSearchPathW(process: HANDLE, path: *mut WCHAR, length: DWORD) -> BOOL;
+    fn SymSetSearchPathW(process: HANDLE, path: *const WCHAR) -> BOOL;
+    fn SymUnloadModule64(process: HANDLE, base: DWORD64) -> BOOL;
+    fn SymLoadModuleExW(
+        process: HANDLE,
+        file: HANDLE,
+        image_name: *const WCHAR,
+        module_name: *const WCHAR,
+        base: DWORD64,
+        size: DWORD,
+        data: *mut c_void,
+        flags: DWORD,
+    ) -> DWORD64;
+    fn SymAddrIncludeInlineTrace(process: HANDLE, addr: DWORD64) -> DWORD;
+    fn SymQueryInlineTrace(
+        process: HANDLE,
+        start_addr: DWORD64,
+        start_context: DWORD,
+        start_ret_addr: DWORD64,
+        cur_addr: DWORD64,
+        cur_context: *mut DWORD,
+        cur_frame_index: *mut DWORD,
+    ) -> BOOL;
+    fn SymFromInlineContextW(
+        process: HANDLE,
+        addr: DWORD64,
+        inline_context: DWORD,
+        displacement: *mut DWORD64,
+        symbol: *mut SYMBOL_INFOW,
+    ) -> BOOL;
+    fn SymGetLineFromInlineContextW(
+        process: HANDLE,
+        addr: DWORD64,
+        inline_context: DWORD,
+        module_base: DWORD64,
+        displacement: *mut DWORD,
+        line: *mut IMAGEHLP_LINEW64,
+    ) -> BOOL;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pdb_name() {
+        assert_eq!(pdb_name("d:\\build\\out\\foo.pdb"), Some("foo.pdb"));
+        assert_eq!(pdb_name("ntdll.pdb"), Some("ntdll.pdb"));
+        assert_eq!(pdb_name(""), None);
+    }
 }
@@ -0,0 +1,153 @@
+//! Reads the system process list with NtQuerySystemInformation, which has the threads and
+//! statistics of every process without needing to open handles to them
+use winapi::shared::minwindef::ULONG;
+use winapi::shared::ntdef::{HANDLE, NTSTATUS, PVOID, UNICODE_STRING};
+
+use super::{NtQuerySystemInformation, Pid, RtlNtStatusToDosError};
+use crate::Error;
+
+const SYSTEM_PROCESS_INFORMATION: u32 = 5;
+const STATUS_INFO_LENGTH_MISMATCH: NTSTATUS = 0xC0000004_u32 as NTSTATUS;
+
+/// `SYSTEM_PROCESS_INFORMATION`, which is followed by a `SYSTEM_THREAD_INFORMATION` for each
+/// thread of the process
+#[repr(C)]
+pub(super) struct SystemProcessInformation {
+    pub next_entry_offset: ULONG,
+    pub number_of_threads: ULONG,
+    pub working_set_private_size: i64,
+    pub hard_fault_count: ULONG,
+    pub number_of_threads_high_watermark: ULONG,
+    pub cycle_time: u64,
+    pub create_time: i64,
+    pub user_time: i64,
+    pub kernel_time: i64,
+    pub image_name: UNICODE_STRING,
+    pub base_priority: i32,
+    pub unique_process_id: HANDLE,
+    pub inherited_from_unique_process_id: HANDLE,
+    pub handle_count: ULONG,
+    pub session_id: ULONG,
+    pub unique_process_key: usize,
+    pub peak_virtual_size: usize,
+    pub virtual_size: usize,
+    pub page_fault_count: ULONG,
+    pub peak_working_set_size: usize,
+    pub working_set_size: usize,
+    pub quota_peak_paged_pool_usage: usize,
+    pub quota_paged_pool_usage: usize,
+    pub quota_peak_non_paged_pool_usage: usize,
+    pub quota_non_paged_pool_usage: usize,
+    pub pagefile_usage: usize,
+    pub peak_pagefile_usage: usize,
+    pub private_page_count: usize,
+    pub read_operation_count: i64,
+    pub write_operation_count: i64,
+    pub other_operation_count: i64,
+    pub read_transfer_count: i64,
+    pub write_transfer_count: i64,
+    pub other_transfer_count: i64,
+}
+
+/// `SYSTEM_THREAD_INFORMATION`
+#[repr(C)]
+pub(super) struct SystemThreadInformation {
+    pub kernel_time: i64,
+    pub user_time: i64,
+    pub create_time: i64,
+    pub wait_time: ULONG,
+    pub start_address: PVOID,
+    pub unique_process: HANDLE,
+    pub unique_thread: HANDLE,
+    pub priority: i32,
+    pub base_priority: i32,
+    pub context_switches: ULONG,
+    pub thread_state: ULONG,
+    pub wait_reason: ULONG,
+}
+
+/// A snapshot of every process on the system
+pub(super) struct ProcessList {
+    // u64s keep the buffer aligned for the entries
+    buffer: Vec<u64>,
+}
+
+impl ProcessList {
+    pub fn query() -> Result<ProcessList, Error> {
+        // processes can start between calls, so keep retrying with a bigger buffer
+        let mut buffer: Vec<u64> = vec![0; 1 << 15];
+        loop {
+            let len = buffer.len() * std::mem::size_of::<u64>();
+            let mut needed: ULONG = 0;
+            let ret = unsafe {
+                NtQuerySystemInformation(
+                    SYSTEM_PROCESS_INFORMATION,
+                    buffer.as_mut_ptr() as PVOID,
+                    len as ULONG,
+                    &mut needed,
+                )
+            };
+            if ret == STATUS_INFO_LENGTH_MISMATCH {
+                let needed = (needed as usize).max(len * 2);
+                buffer.resize(needed / std::mem::size_of::<u64>() + 1024, 0);
+                continue;
+            }
+            if ret != 0 {
+                return Err(Error::from(std::io::Error::from_raw_os_error(unsafe {
+                    RtlNtStatusToDosError(ret) as i32
+                })));
+            }
+            return Ok(ProcessList { buffer });
+        }
+    }
+
+    /// Returns the entry for a process, along with its threads
+    pub fn find(
+        &self,
+        pid: Pid,
+    ) -> Option<(&SystemProcessInformation, &[SystemThreadInformation])> {
+        self.offset(pid).map(|offset| self.entry(offset))
+    }
+
+    /// Returns the offset of the entry for a process in the buffer
+    pub fn offset(&self, pid: Pid) -> Option<usize> {
+        let mut offset = 0;
+        loop {
+            let (entry, _) = self.entry(offset);
+            if entry.unique_process_id as usize == pid as usize {
+                return Some(offset);
+            }
+            if entry.next_entry_offset == 0 {
+                return None;
+            }
+            offset += entry.next_entry_offset as usize;
+        }
+    }
+
+    /// Returns the entry at an offset returned by `offset`
+    pub fn entry(&self, offset: usize) -> (&SystemProcessInformation, &[SystemThreadInformation]) {
+        unsafe {
+            let entry =
+                (self.buffer.as_ptr() as *const u8).add(offset) as *const SystemProcessInformation;
+            let threads = std::slice::from_raw_parts(
+                entry.add(1) as *const SystemThreadInformation,
+                (*entry).number_of_threads as usize,
+            );
+            (&*entry, threads)
+        }
+    }
+}
+
+/// Converts a UNICODE_STRING pointing into memory in the current process
+pub(super) fn unicode_string(string: &UNICODE_STRING) -> String {
+    if string.Buffer.is_null() {
+        return String::new();
+    }
+    let chars = unsafe {
+        std::slice::from_raw_parts(
+            string.Buffer,
+            string.Length as usize / std::mem::size_of::<u16>(),
+        )
+    };
+    String::from_utf16_lossy(chars)
+}
@@ -0,0 +1,84 @@
+//! Stops a process by asking its windows to close, and terminating it if it doesn't exit
+use std::time::Duration;
+
+use winapi::shared::minwindef::{BOOL, DWORD, FALSE, LPARAM, TRUE, UINT, WPARAM};
+use winapi::shared::ntdef::PVOID;
+use winapi::um::processthreadsapi::{OpenProcess, TerminateProcess};
+use winapi::um::winnt::{HANDLE, PROCESS_TERMINATE, SYNCHRONIZE};
+
+use super::{OwnedHandle, Pid, Process};
+use crate::{Error, Termination};
+
+// a window handle, from windef
+type HWND = PVOID;
+
+const WAIT_OBJECT_0: DWORD = 0;
+const WAIT_TIMEOUT: DWORD = 258;
+const WM_CLOSE: UINT = 0x0010;
+
+type EnumWindowsProc = unsafe extern "system" fn(HWND, LPARAM) -> BOOL;
+
+extern "system" {
+    fn WaitForSingleObject(handle: HANDLE, milliseconds: DWORD) -> DWORD;
+}
+
+#[link(name = "user32")]
+extern "system" {
+    fn EnumWindows(callback: EnumWindowsProc, param: LPARAM) -> BOOL;
+    fn GetWindowThreadProcessId(window: HWND, pid: *mut DWORD) -> DWORD;
+    fn PostMessageW(window: HWND, message: UINT, wparam: WPARAM, lparam: LPARAM) -> BOOL;
+}
+
+impl Process {
+    /// Asks the process to exit by sending WM_CLOSE to each of its top level windows, and
+    /// calls TerminateProcess if it's still running after `grace`. Processes without a window
+    /// (like console programs and services) can't be asked, so are only killed once the grace
+    /// period is up.
+    pub fn terminate(&self, grace: Duration) -> Result<Termination, Error> {
+        // the handle the process was opened with can't wait on or terminate it
+        let process = unsafe { OpenProcess(PROCESS_TERMINATE | SYNCHRONIZE, FALSE, self.pid) };
+        if process.is_null() {
+            return Err(Error::from_os_error(
+                self.pid,
+                std::io::Error::last_os_error(),
+            ));
+        }
+        let process = OwnedHandle(process);
+
+        close_windows(self.pid);
+        let timeout = grace.as_millis().min(DWORD::MAX as u128 - 1) as DWORD;
+        match unsafe { WaitForSingleObject(process.0, timeout) } {
+            WAIT_OBJECT_0 => return Ok(Termination::Exited),
+            WAIT_TIMEOUT => {}
+            _ => {
+                return Err(Error::from_os_error(
+                    self.pid,
+                    std::io::Error::last_os_error(),
+                ))
+            }
+        }
+
+        if unsafe { TerminateProcess(process.0, 1) } == 0 {
+            let error = std::io::Error::last_os_error();
+            // terminating a process that is already exiting fails with access denied
+            if unsafe { WaitForSingleObject(process.0, 0) } == WAIT_OBJECT_0 {
+                return Ok(Termination::Exited);
+            }
+            return Err(Error::from_os_error(self.pid, error));
+        }
+        Ok(Termination::Killed)
+    }
+}
+
+/// Posts WM_CLOSE to every top level window owned by a process
+fn close_windows(pid: Pid) {
+    unsafe extern "system" fn close(window: HWND, pid: LPARAM) -> BOOL {
+        let mut owner: DWORD = 0;
+        GetWindowThreadProcessId(window, &mut owner);
+        if owner == pid as DWORD {
+            PostMessageW(window, WM_CLOSE, 0, 0);
+        }
+        TRUE
+    }
+    unsafe { EnumWindows(close, pid as LPARAM) };
+}
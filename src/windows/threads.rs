@@ -0,0 +1,189 @@
+//! Details of the threads of a process. `threads_iter` lists the threads lazily from the
+//! system process list, which has the state and CPU time of every thread - so only the names
+//! need a thread handle, and those are only opened as the iterator reaches each thread.
+use std::time::Duration;
+
+use winapi::shared::minwindef::{BOOL, DWORD, FALSE, LPVOID};
+use winapi::shared::ntdef::{HRESULT, LPCSTR, PWSTR};
+use winapi::um::processthreadsapi::OpenThread;
+use winapi::um::winbase::{GetActiveProcessorCount, GetProcessAffinityMask, LocalFree};
+use winapi::um::winnt::{GROUP_AFFINITY, HANDLE};
+
+use super::sysinfo::{ProcessList, SystemThreadInformation};
+use super::{GetModuleHandleW, GetProcAddress, OwnedHandle, Process, Thread, Tid};
+use crate::{Error, ThreadInfo, ThreadState};
+
+const THREAD_QUERY_LIMITED_INFORMATION: DWORD = 0x0800;
+
+// KTHREAD_STATE values
+const THREAD_STATE_READY: u32 = 1;
+const THREAD_STATE_RUNNING: u32 = 2;
+const THREAD_STATE_STANDBY: u32 = 3;
+const THREAD_STATE_TERMINATED: u32 = 4;
+const THREAD_STATE_WAITING: u32 = 5;
+const THREAD_STATE_DEFERRED_READY: u32 = 7;
+
+// KWAIT_REASON values for suspended threads
+const WAIT_REASON_SUSPENDED: u32 = 5;
+const WAIT_REASON_WR_SUSPENDED: u32 = 26;
+
+type GetThreadDescriptionFn = unsafe extern "system" fn(HANDLE, *mut PWSTR) -> HRESULT;
+
+extern "system" {
+    fn GetThreadGroupAffinity(thread: HANDLE, affinity: *mut GROUP_AFFINITY) -> BOOL;
+}
+
+/// An iterator over the threads of a process, returned by `Process::threads_iter`
+pub struct ThreadIter {
+    processes: ProcessList,
+    offset: usize,
+    index: usize,
+    get_description: Option<GetThreadDescriptionFn>,
+}
+
+impl Process {
+    /// Returns an iterator over the threads of the process, with the name, state and CPU
+    /// time of each. The threads are from a snapshot taken when this is called, and names
+    /// are only available on windows 10 1607 and later.
+    pub fn threads_iter(&self) -> Result<ThreadIter, Error> {
+        let processes = ProcessList::query()?;
+        let offset = processes
+            .offset(self.pid)
+            .ok_or(Error::ProcessExited { pid: self.pid })?;
+
+        // GetThreadDescription isn't in older versions of kernel32, so has to be looked up
+        let get_description = unsafe {
+            let kernel32: Vec<u16> = "kernel32.dll\0".encode_utf16().collect();
+            let function = GetProcAddress(
+                GetModuleHandleW(kernel32.as_ptr()),
+                b"GetThreadDescription\0".as_ptr() as LPCSTR,
+            );
+            if function.is_null() {
+                None
+            } else {
+                Some(std::mem::transmute::<LPVOID, GetThreadDescriptionFn>(
+                    function,
+                ))
+            }
+        };
+
+        Ok(ThreadIter {
+            processes,
+            offset,
+            index: 0,
+            get_description,
+        })
+    }
+}
+
+impl Iterator for ThreadIter {
+    type Item = Result<ThreadInfo, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (_, threads) = self.processes.entry(self.offset);
+        let thread = threads.get(self.index)?;
+        self.index += 1;
+
+        let tid = thread.unique_thread as usize as Tid;
+        let name = self
+            .get_description
+            .and_then(|get_description| description(get_description, tid));
+        let cpu_time = (thread.kernel_time + thread.user_time).max(0) as u64 * 100;
+        Some(Ok(ThreadInfo {
+            tid,
+            name,
+            state: state(thread),
+            cpu_time: Duration::from_nanos(cpu_time),
+        }))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (_, threads) = self.processes.entry(self.offset);
+        let remaining = threads.len() - self.index;
+        (remaining, Some(remaining))
+    }
+}
+
+impl Process {
+    /// Returns the CPUs the process is allowed to run on. Only processes in the first
+    /// processor group have an affinity that can be queried, so this is empty for processes
+    /// whose threads have been assigned to other groups.
+    pub fn affinity(&self) -> Result<Vec<usize>, Error> {
+        let mut process_mask: usize = 0;
+        let mut system_mask: usize = 0;
+        if unsafe {
+            GetProcessAffinityMask(*self.handle as HANDLE, &mut process_mask, &mut system_mask)
+        } == 0
+        {
+            return Err(Error::from_os_error(
+                self.pid,
+                std::io::Error::last_os_error(),
+            ));
+        }
+        Ok((0..usize::BITS as usize)
+            .filter(|bit| process_mask & (1 << bit) != 0)
+            .collect())
+    }
+}
+
+impl Thread {
+    /// Returns the CPUs the thread is allowed to run on. A thread only runs on the processors
+    /// of one processor group, and processors are numbered after those in earlier groups.
+    pub fn affinity(&self) -> Result<Vec<usize>, Error> {
+        let mut affinity: GROUP_AFFINITY = unsafe { std::mem::zeroed() };
+        if unsafe { GetThreadGroupAffinity(*self.thread as HANDLE, &mut affinity) } == 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        let first: usize = (0..affinity.Group)
+            .map(|group| unsafe { GetActiveProcessorCount(group) } as usize)
+            .sum();
+        Ok((0..usize::BITS as usize)
+            .filter(|bit| affinity.Mask & (1 << bit) != 0)
+            .map(|bit| first + bit)
+            .collect())
+    }
+
+    /// Windows doesn't report the processor another thread last ran on, so this is always
+    /// None
+    pub fn last_cpu(&self) -> Result<Option<usize>, Error> {
+        Ok(None)
+    }
+}
+
+fn state(thread: &SystemThreadInformation) -> ThreadState {
+    match thread.thread_state {
+        THREAD_STATE_READY
+        | THREAD_STATE_RUNNING
+        | THREAD_STATE_STANDBY
+        | THREAD_STATE_DEFERRED_READY => ThreadState::Running,
+        THREAD_STATE_WAITING => match thread.wait_reason {
+            WAIT_REASON_SUSPENDED | WAIT_REASON_WR_SUSPENDED => ThreadState::Stopped,
+            _ => ThreadState::Sleeping,
+        },
+        THREAD_STATE_TERMINATED => ThreadState::Zombie,
+        _ => ThreadState::Unknown,
+    }
+}
+
+/// Returns the name a thread was given with SetThreadDescription
+fn description(get_description: GetThreadDescriptionFn, tid: Tid) -> Option<String> {
+    let thread = unsafe { OpenThread(THREAD_QUERY_LIMITED_INFORMATION, FALSE, tid) };
+    if thread.is_null() {
+        return None;
+    }
+    let thread = OwnedHandle(thread);
+    let mut description: PWSTR = std::ptr::null_mut();
+    if unsafe { get_description(thread.0, &mut description) } < 0 || description.is_null() {
+        return None;
+    }
+    let name = unsafe {
+        let len = libc::wcslen(description);
+        String::from_utf16_lossy(std::slice::from_raw_parts(description, len))
+    };
+    unsafe { LocalFree(description as LPVOID) };
+    if name.is_empty() {
+        None
+    } else {
+        Some(name)
+    }
+}
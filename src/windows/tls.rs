@@ -0,0 +1,107 @@
+//! Finds the thread environment block (TEB) of a thread, and reads the TLS slots that
+//! TlsAlloc hands out from it
+use winapi::shared::minwindef::ULONG;
+use winapi::shared::ntdef::{HANDLE, NTSTATUS, PVOID};
+
+use super::{NtQueryInformationThread, Process, RtlNtStatusToDosError, Thread};
+use crate::{Error, ProcessMemory};
+
+const THREAD_BASIC_INFORMATION: u32 = 0;
+
+// TLS_MINIMUM_AVAILABLE and TLS_EXPANSION_SLOTS
+const TLS_SLOTS: u32 = 64;
+const TLS_EXPANSION_SLOTS: u32 = 1024;
+
+/// The offsets of TlsSlots and TlsExpansionSlots in the TEB
+const TEB64_TLS_SLOTS: u64 = 0x1480;
+const TEB64_TLS_EXPANSION_SLOTS: u64 = 0x1780;
+const TEB32_TLS_SLOTS: u64 = 0xe10;
+const TEB32_TLS_EXPANSION_SLOTS: u64 = 0xf94;
+
+/// The 32-bit TEB of a WOW64 thread follows its 64-bit TEB
+const WOW64_TEB32_OFFSET: u64 = 0x2000;
+
+/// `THREAD_BASIC_INFORMATION`
+#[repr(C)]
+struct ThreadBasicInformation {
+    _exit_status: NTSTATUS,
+    teb_base_address: PVOID,
+    _unique_process: HANDLE,
+    _unique_thread: HANDLE,
+    _affinity_mask: usize,
+    _priority: i32,
+    _base_priority: i32,
+}
+
+impl Thread {
+    /// Returns the address of the thread's TEB, which gs points to on x64. For threads of
+    /// WOW64 processes this is the 64-bit TEB, and the 32-bit TEB that fs points to follows
+    /// it.
+    pub fn tls_base(&self) -> Result<u64, Error> {
+        let mut info: ThreadBasicInformation = unsafe { std::mem::zeroed() };
+        let ret = unsafe {
+            NtQueryInformationThread(
+                *self.thread as HANDLE,
+                THREAD_BASIC_INFORMATION,
+                &mut info as *mut ThreadBasicInformation as PVOID,
+                std::mem::size_of::<ThreadBasicInformation>() as ULONG,
+                std::ptr::null_mut(),
+            )
+        };
+        if ret != 0 {
+            return Err(Error::from(std::io::Error::from_raw_os_error(unsafe {
+                RtlNtStatusToDosError(ret) as i32
+            })));
+        }
+        Ok(info.teb_base_address as u64)
+    }
+
+    /// Reads a value at an offset from the thread's TEB
+    pub fn read_tls<T: Copy, P: ProcessMemory>(
+        &self,
+        process: &P,
+        offset: i64,
+    ) -> Result<T, Error> {
+        let addr = self.tls_base()?.wrapping_add(offset as u64);
+        process.copy_struct(addr as usize)
+    }
+
+    /// Returns the value of a TLS slot of the thread, like TlsGetValue would in the thread
+    /// itself. `index` is a value returned by TlsAlloc in the process.
+    pub fn tls_slot(&self, process: &Process, index: u32) -> Result<u64, Error> {
+        if index >= TLS_SLOTS + TLS_EXPANSION_SLOTS {
+            return Err(Error::Other(format!("invalid TLS index {}", index)));
+        }
+        let teb = self.tls_base()?;
+        let is_64bit = cfg!(target_pointer_width = "64") && !process.is_wow64()?;
+        let read_pointer = |addr: u64| -> Result<u64, Error> {
+            if is_64bit {
+                process.copy_struct::<u64>(addr as usize)
+            } else {
+                Ok(process.copy_struct::<u32>(addr as usize)? as u64)
+            }
+        };
+        let (teb, slots, expansion_slots, pointer_size) = if is_64bit {
+            (teb, TEB64_TLS_SLOTS, TEB64_TLS_EXPANSION_SLOTS, 8)
+        } else if cfg!(target_pointer_width = "64") {
+            (
+                teb + WOW64_TEB32_OFFSET,
+                TEB32_TLS_SLOTS,
+                TEB32_TLS_EXPANSION_SLOTS,
+                4,
+            )
+        } else {
+            (teb, TEB32_TLS_SLOTS, TEB32_TLS_EXPANSION_SLOTS, 4)
+        };
+
+        if index < TLS_SLOTS {
+            return read_pointer(teb + slots + index as u64 * pointer_size);
+        }
+        // the expansion slots are allocated the first time one of them is set
+        let expansion = read_pointer(teb + expansion_slots)?;
+        if expansion == 0 {
+            return Ok(0);
+        }
+        read_pointer(expansion + (index - TLS_SLOTS) as u64 * pointer_size)
+    }
+}
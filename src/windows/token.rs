@@ -0,0 +1,215 @@
+//! Reads the user, groups and integrity level from the access token of a process
+use winapi::shared::minwindef::{DWORD, FALSE, LPVOID};
+use winapi::shared::winerror::ERROR_INSUFFICIENT_BUFFER;
+use winapi::um::errhandlingapi::GetLastError;
+use winapi::um::processthreadsapi::{GetCurrentProcess, OpenProcess, OpenProcessToken};
+use winapi::um::securitybaseapi::{
+    GetSidSubAuthority, GetSidSubAuthorityCount, GetTokenInformation,
+};
+use winapi::um::winbase::{LocalFree, LookupAccountSidW};
+use winapi::um::winnt::{
+    TokenElevation, TokenGroups, TokenIntegrityLevel, TokenUser, HANDLE, PSID, TOKEN_ELEVATION,
+    TOKEN_GROUPS, TOKEN_INFORMATION_CLASS, TOKEN_MANDATORY_LABEL, TOKEN_QUERY, TOKEN_USER,
+};
+
+use super::{OwnedHandle, Pid, Process};
+use crate::Error;
+
+const PROCESS_QUERY_LIMITED_INFORMATION: DWORD = 0x1000;
+
+extern "system" {
+    fn ConvertSidToStringSidW(sid: PSID, string: *mut *mut u16) -> i32;
+}
+
+/// The mandatory integrity level of a token, ordered from least to most trusted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum IntegrityLevel {
+    Untrusted,
+    Low,
+    Medium,
+    MediumPlus,
+    High,
+    System,
+    Protected,
+    /// A level between the well known ones, with its RID
+    Other(u32),
+}
+
+impl IntegrityLevel {
+    fn from_rid(rid: u32) -> IntegrityLevel {
+        match rid {
+            0x0000 => IntegrityLevel::Untrusted,
+            0x1000 => IntegrityLevel::Low,
+            0x2000 => IntegrityLevel::Medium,
+            0x2100 => IntegrityLevel::MediumPlus,
+            0x3000 => IntegrityLevel::High,
+            0x4000 => IntegrityLevel::System,
+            0x5000 => IntegrityLevel::Protected,
+            rid => IntegrityLevel::Other(rid),
+        }
+    }
+}
+
+/// A group in a token, along with its SE_GROUP_* attributes
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TokenGroup {
+    pub sid: String,
+    pub attributes: u32,
+}
+
+/// The security context a process runs in, from its access token
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TokenInfo {
+    /// The SID of the user, like `S-1-5-18`
+    pub user_sid: String,
+    /// The name of the user as `DOMAIN\name`, if the SID could be looked up
+    pub user_name: Option<String>,
+    pub groups: Vec<TokenGroup>,
+    pub integrity_level: IntegrityLevel,
+    /// True if the token is elevated by UAC, or is an administrator's with UAC turned off
+    pub elevated: bool,
+}
+
+impl TokenInfo {
+    /// Reads the token of a process without attaching to it. Unlike `Process::new` this
+    /// only needs PROCESS_QUERY_LIMITED_INFORMATION, which is usually granted for processes
+    /// at a higher integrity level - so it can be used to explain why attaching failed.
+    pub fn from_pid(pid: Pid) -> Result<TokenInfo, Error> {
+        let process = unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, FALSE, pid) };
+        if process.is_null() {
+            return Err(Error::from_os_error(pid, std::io::Error::last_os_error()));
+        }
+        let process = OwnedHandle(process);
+        token_info(process.0).map_err(|e| match e {
+            Error::IOError(e) => Error::from_os_error(pid, e),
+            e => e,
+        })
+    }
+
+    /// Reads the token of the current process
+    pub fn current() -> Result<TokenInfo, Error> {
+        token_info(unsafe { GetCurrentProcess() })
+    }
+}
+
+impl Process {
+    /// Returns the user, groups, integrity level and elevation of the process
+    pub fn token_info(&self) -> Result<TokenInfo, Error> {
+        token_info(*self.handle as HANDLE).map_err(|e| match e {
+            Error::IOError(e) => Error::from_os_error(self.pid, e),
+            e => e,
+        })
+    }
+}
+
+fn token_info(process: HANDLE) -> Result<TokenInfo, Error> {
+    let mut token: HANDLE = std::ptr::null_mut();
+    if unsafe { OpenProcessToken(process, TOKEN_QUERY, &mut token) } == 0 {
+        return Err(Error::from(std::io::Error::last_os_error()));
+    }
+    let token = OwnedHandle(token);
+
+    let user = information(token.0, TokenUser)?;
+    let user = unsafe { &*(user.as_ptr() as *const TOKEN_USER) };
+    let user_sid = sid_string(user.User.Sid)?;
+    let user_name = account_name(user.User.Sid);
+
+    let groups = information(token.0, TokenGroups)?;
+    let groups = unsafe {
+        let groups = &*(groups.as_ptr() as *const TOKEN_GROUPS);
+        std::slice::from_raw_parts(groups.Groups.as_ptr(), groups.GroupCount as usize)
+    };
+    let groups = groups
+        .iter()
+        .map(|group| {
+            Ok(TokenGroup {
+                sid: sid_string(group.Sid)?,
+                attributes: group.Attributes,
+            })
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    // the integrity level is the last sub authority of the label's SID
+    let label = information(token.0, TokenIntegrityLevel)?;
+    let integrity_level = unsafe {
+        let sid = (*(label.as_ptr() as *const TOKEN_MANDATORY_LABEL))
+            .Label
+            .Sid;
+        let count = *GetSidSubAuthorityCount(sid) as DWORD;
+        IntegrityLevel::from_rid(*GetSidSubAuthority(sid, count.saturating_sub(1)))
+    };
+
+    let elevation = information(token.0, TokenElevation)?;
+    let elevated = unsafe { (*(elevation.as_ptr() as *const TOKEN_ELEVATION)).TokenIsElevated };
+
+    Ok(TokenInfo {
+        user_sid,
+        user_name,
+        groups,
+        integrity_level,
+        elevated: elevated != 0,
+    })
+}
+
+/// Returns a class of information about a token. The buffer is u64s so that it's aligned for
+/// the structures returned, which hold pointers into the rest of the buffer.
+fn information(token: HANDLE, class: TOKEN_INFORMATION_CLASS) -> Result<Vec<u64>, Error> {
+    let mut size: DWORD = 0;
+    unsafe { GetTokenInformation(token, class, std::ptr::null_mut(), 0, &mut size) };
+    if unsafe { GetLastError() } != ERROR_INSUFFICIENT_BUFFER {
+        return Err(Error::from(std::io::Error::last_os_error()));
+    }
+    let mut buffer: Vec<u64> = vec![0; size as usize / std::mem::size_of::<u64>() + 1];
+    let len = (buffer.len() * std::mem::size_of::<u64>()) as DWORD;
+    let ret =
+        unsafe { GetTokenInformation(token, class, buffer.as_mut_ptr() as LPVOID, len, &mut size) };
+    if ret == 0 {
+        return Err(Error::from(std::io::Error::last_os_error()));
+    }
+    Ok(buffer)
+}
+
+fn sid_string(sid: PSID) -> Result<String, Error> {
+    let mut string: *mut u16 = std::ptr::null_mut();
+    if unsafe { ConvertSidToStringSidW(sid, &mut string) } == 0 {
+        return Err(Error::from(std::io::Error::last_os_error()));
+    }
+    let sid = unsafe {
+        let len = libc::wcslen(string);
+        String::from_utf16_lossy(std::slice::from_raw_parts(string, len))
+    };
+    unsafe { LocalFree(string as LPVOID) };
+    Ok(sid)
+}
+
+/// Looks up the `DOMAIN\name` of the account a SID belongs to
+fn account_name(sid: PSID) -> Option<String> {
+    let mut name = vec![0u16; 256];
+    let mut domain = vec![0u16; 256];
+    let mut name_len = name.len() as DWORD;
+    let mut domain_len = domain.len() as DWORD;
+    let mut kind = 0;
+    let ret = unsafe {
+        LookupAccountSidW(
+            std::ptr::null(),
+            sid,
+            name.as_mut_ptr(),
+            &mut name_len,
+            domain.as_mut_ptr(),
+            &mut domain_len,
+            &mut kind,
+        )
+    };
+    if ret == 0 {
+        return None;
+    }
+    let name = String::from_utf16_lossy(&name[..name_len as usize]);
+    if domain_len == 0 {
+        return Some(name);
+    }
+    let domain = String::from_utf16_lossy(&domain[..domain_len as usize]);
+    Some(format!("{}\\{}", domain, name))
+}
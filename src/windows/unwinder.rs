@@ -1,53 +1,120 @@
 use winapi::um::processthreadsapi::GetThreadContext;
-use winapi::um::winnt::{CONTEXT, HANDLE, IMAGE_FILE_MACHINE_AMD64};
+use winapi::um::winnt::{CONTEXT, HANDLE};
 
 use winapi::shared::minwindef::TRUE;
 use winapi::um::dbghelp::{AddrModeFlat, StackWalk64, ADDRESS64, STACKFRAME64};
 
-use super::super::Error;
-use super::Thread;
+#[cfg(target_arch = "x86_64")]
+use winapi::um::winbase::Wow64GetThreadContext;
+#[cfg(target_arch = "x86_64")]
+use winapi::um::winnt::{IMAGE_FILE_MACHINE_I386, WOW64_CONTEXT};
+
+use super::super::unwind::unavailable_register;
+use super::super::{Error, Module, ProcessMemory};
+use super::{pdata, Thread};
+
+#[cfg(not(target_arch = "aarch64"))]
+use winapi::um::winnt::IMAGE_FILE_MACHINE_AMD64 as IMAGE_FILE_MACHINE_NATIVE;
+#[cfg(target_arch = "aarch64")]
+use winapi::um::winnt::IMAGE_FILE_MACHINE_ARM64 as IMAGE_FILE_MACHINE_NATIVE;
+
+// CONTEXT_FULL differs between architectures
+#[cfg(target_arch = "aarch64")]
+const CONTEXT_FULL: u32 = 0x00400007;
+#[cfg(not(target_arch = "aarch64"))]
+const CONTEXT_FULL: u32 = 0x0010000B;
 
 pub struct Unwinder {
     pub handle: HANDLE,
+    wow64: bool,
 }
 
 pub struct Cursor {
-    ctx: Context,
+    ctx: ThreadContext,
     frame: STACKFRAME64,
     process: HANDLE,
     thread: HANDLE,
 }
 
+enum ThreadContext {
+    Native(Box<Context>),
+    // 32-bit threads in a WoW64 process
+    #[cfg(target_arch = "x86_64")]
+    Wow64(Box<WOW64_CONTEXT>),
+}
+
 impl Unwinder {
     pub fn new(handle: HANDLE) -> Result<Unwinder, Error> {
-        Ok(Unwinder { handle })
+        let wow64 = super::is_wow64(handle)?;
+        Ok(Unwinder { handle, wow64 })
+    }
+
+    /// Creates an unwinder for a target that isn't a local process, like a core dump or a
+    /// remote agent, that reads stacks from `memory` and function tables from the given
+    /// modules. Cursors for these need to be created with `cursor_from_context`.
+    pub fn with_modules<M: ProcessMemory + Send + 'static>(
+        memory: M,
+        modules: &[Module],
+    ) -> Unwinder {
+        let handle = pdata::register(Box::new(memory), modules.to_vec());
+        Unwinder {
+            handle,
+            wow64: false,
+        }
     }
 
     pub fn cursor(&self, thread: &Thread) -> Result<Cursor, Error> {
-        Cursor::new(*thread.thread as HANDLE, self.handle)
+        Cursor::create(*thread.thread as HANDLE, self.handle, self.wow64)
+    }
+
+    /// Returns a cursor that starts unwinding from the registers in a thread context,
+    /// instead of reading them from a live thread
+    pub fn cursor_from_context(&self, context: &CONTEXT) -> Cursor {
+        let mut ctx: Box<Context> = Box::new(unsafe { std::mem::zeroed() });
+        ctx.0 = *context;
+        Cursor::from_context(ctx, std::ptr::null_mut(), self.handle)
+    }
+}
+
+impl Drop for Unwinder {
+    fn drop(&mut self) {
+        pdata::clear(self.handle);
     }
 }
 
 impl Cursor {
     pub fn new(thread: HANDLE, process: HANDLE) -> Result<Cursor, Error> {
+        Cursor::create(thread, process, super::is_wow64(process)?)
+    }
+
+    fn create(thread: HANDLE, process: HANDLE, wow64: bool) -> Result<Cursor, Error> {
+        if wow64 {
+            return Cursor::new_wow64(thread, process);
+        }
         unsafe {
-            let mut ctx: Context = std::mem::zeroed();
-            ctx.0.ContextFlags = 1048587; // CONTEXT_FULL
+            let mut ctx: Box<Context> = Box::new(std::mem::zeroed());
+            ctx.0.ContextFlags = CONTEXT_FULL;
             if GetThreadContext(thread, &mut ctx.0 as *mut CONTEXT) == 0 {
-                return Err(Error::from(std::io::Error::last_os_error()));
+                return Err(Error::UnwindError {
+                    message: format!(
+                        "GetThreadContext failed: {}",
+                        std::io::Error::last_os_error()
+                    ),
+                });
             }
 
+            Ok(Cursor::from_context(ctx, thread, process))
+        }
+    }
+
+    fn from_context(ctx: Box<Context>, thread: HANDLE, process: HANDLE) -> Cursor {
+        unsafe {
             // translate context into stack frame.
-            // TODO: if we ever decide to support 32-bit windows this will need extended
             let mut frame: STACKFRAME64 = std::mem::zeroed();
-            fn set_flat_addr(addr: &mut ADDRESS64, offset: u64) {
-                addr.Offset = offset;
-                addr.Mode = AddrModeFlat;
-            }
             cfg_if::cfg_if! {
                 if #[cfg(target_arch = "aarch64")] {
                   set_flat_addr(&mut frame.AddrStack, ctx.0.Sp as u64);
-                  set_flat_addr(&mut frame.AddrFrame, ctx.0.u.s().Lr as u64);
+                  set_flat_addr(&mut frame.AddrFrame, ctx.0.u.s().Fp as u64);
                   set_flat_addr(&mut frame.AddrPC, ctx.0.Pc as u64);
                 } else {
                   set_flat_addr(&mut frame.AddrStack, ctx.0.Rsp as u64);
@@ -56,8 +123,36 @@ impl Cursor {
                 }
             }
 
+            Cursor {
+                ctx: ThreadContext::Native(ctx),
+                frame,
+                thread,
+                process,
+            }
+        }
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn new_wow64(thread: HANDLE, process: HANDLE) -> Result<Cursor, Error> {
+        unsafe {
+            let mut ctx: Box<WOW64_CONTEXT> = Box::new(std::mem::zeroed());
+            ctx.ContextFlags = 0x10007; // WOW64_CONTEXT_FULL
+            if Wow64GetThreadContext(thread, &mut *ctx) == 0 {
+                return Err(Error::UnwindError {
+                    message: format!(
+                        "Wow64GetThreadContext failed: {}",
+                        std::io::Error::last_os_error()
+                    ),
+                });
+            }
+
+            let mut frame: STACKFRAME64 = std::mem::zeroed();
+            set_flat_addr(&mut frame.AddrStack, ctx.Esp as u64);
+            set_flat_addr(&mut frame.AddrFrame, ctx.Ebp as u64);
+            set_flat_addr(&mut frame.AddrPC, ctx.Eip as u64);
+
             Ok(Cursor {
-                ctx,
+                ctx: ThreadContext::Wow64(ctx),
                 frame,
                 thread,
                 process,
@@ -65,17 +160,48 @@ impl Cursor {
         }
     }
 
+    #[cfg(not(target_arch = "x86_64"))]
+    fn new_wow64(_thread: HANDLE, _process: HANDLE) -> Result<Cursor, Error> {
+        Err(Error::UnwindError {
+            message: "unwinding WoW64 processes is only supported on x86_64".to_owned(),
+        })
+    }
+
     fn unwind(&mut self) -> Result<Option<u64>, Error> {
+        // 32-bit x86 code doesn't have .pdata function tables, so we only look them up
+        // for native threads
+        let (machine, ctx, function_table_access, get_module_base) = match self.ctx {
+            ThreadContext::Native(ref mut ctx) => (
+                IMAGE_FILE_MACHINE_NATIVE,
+                &mut ctx.0 as *mut CONTEXT as *mut _,
+                Some(pdata::function_table_access as _),
+                Some(pdata::get_module_base as _),
+            ),
+            #[cfg(target_arch = "x86_64")]
+            ThreadContext::Wow64(ref mut ctx) => (
+                IMAGE_FILE_MACHINE_I386,
+                &mut **ctx as *mut WOW64_CONTEXT as *mut _,
+                None,
+                None,
+            ),
+        };
+        // registered targets aren't processes, so their memory can't be read with
+        // ReadProcessMemory
+        let read_memory = if pdata::is_registered(self.process) {
+            Some(pdata::read_memory as _)
+        } else {
+            None
+        };
         unsafe {
             if StackWalk64(
-                IMAGE_FILE_MACHINE_AMD64.into(),
+                machine.into(),
                 self.process,
                 self.thread,
                 &mut self.frame,
-                &mut self.ctx.0 as *mut CONTEXT as *mut _,
-                None,
-                None,
-                None,
+                ctx,
+                read_memory,
+                function_table_access,
+                get_module_base,
                 None,
             ) != TRUE
             {
@@ -96,6 +222,47 @@ impl Cursor {
     }
 }
 
+impl crate::StackUnwinder for Unwinder {
+    type Cursor<'a> = Cursor;
+
+    fn cursor(&self, thread: &Thread) -> Result<Cursor, Error> {
+        Unwinder::cursor(self, thread)
+    }
+}
+
+/// StackWalk64 only tracks the instruction, stack and frame pointers of each frame
+impl crate::StackCursor for Cursor {
+    fn ip(&self) -> Result<u64, Error> {
+        Ok(Cursor::ip(self))
+    }
+
+    fn sp(&self) -> Result<u64, Error> {
+        Ok(Cursor::sp(self))
+    }
+
+    fn register(&self, register: u16) -> Result<u64, Error> {
+        // the DWARF numbers of the instruction, stack and frame pointers
+        let (ip, sp, bp) = match self.ctx {
+            #[cfg(target_arch = "x86_64")]
+            ThreadContext::Wow64(_) => (8, 4, 5),
+            _ if cfg!(target_arch = "x86_64") => (16, 7, 6),
+            _ if cfg!(target_arch = "aarch64") => (32, 31, 29),
+            _ => (8, 4, 5),
+        };
+        match register {
+            r if r == ip => Ok(Cursor::ip(self)),
+            r if r == sp => Ok(Cursor::sp(self)),
+            r if r == bp => Ok(Cursor::bp(self)),
+            _ => Err(unavailable_register(register)),
+        }
+    }
+}
+
+fn set_flat_addr(addr: &mut ADDRESS64, offset: u64) {
+    addr.Offset = offset;
+    addr.Mode = AddrModeFlat;
+}
+
 impl Iterator for Cursor {
     type Item = Result<u64, Error>;
 